@@ -0,0 +1,55 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use cryptoki::object::Attribute;
+use cryptoki::session::Session;
+use serde::{Deserialize, Serialize};
+use serde_annotate::Annotate;
+use std::any::Any;
+use std::path::PathBuf;
+
+use crate::commands::{BasicResult, Dispatch};
+use crate::error::HsmError;
+use crate::module::Module;
+use crate::util::attribute::{KeyType, ObjectClass};
+use crate::util::helper;
+use crate::util::wrap::{OaepHash, WrappingKey};
+
+#[derive(clap::Args, Debug, Serialize, Deserialize)]
+pub struct Export {
+    #[arg(long)]
+    id: Option<String>,
+    #[arg(short, long)]
+    label: Option<String>,
+    /// Wrap the exported key with the named wrapping key.
+    #[arg(long)]
+    wrap: String,
+    /// The RSA-OAEP hash used when the wrapping key is an RSA key.
+    #[arg(long, value_enum, default_value = "sha256")]
+    #[serde(default)]
+    oaep_hash: OaepHash,
+    filename: PathBuf,
+}
+
+#[typetag::serde(name = "aes-export")]
+impl Dispatch for Export {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        _hsm: &Module,
+        session: Option<&Session>,
+    ) -> Result<Box<dyn Annotate>> {
+        let session = session.ok_or(HsmError::SessionRequired)?;
+        let mut attrs = helper::search_spec(self.id.as_deref(), self.label.as_deref())?;
+        attrs.push(Attribute::KeyType(KeyType::Aes.try_into()?));
+        attrs.push(Attribute::Class(ObjectClass::SecretKey.try_into()?));
+        let object = helper::find_one_object(session, &attrs)?;
+
+        let wkey = WrappingKey::find(session, &self.wrap, false, self.oaep_hash)?;
+        let wrapped = wkey.wrap(session, object)?;
+        std::fs::write(&self.filename, wrapped)?;
+        Ok(Box::<BasicResult>::default())
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use cryptoki::mechanism::Mechanism;
+use cryptoki::session::Session;
+use serde::{Deserialize, Serialize};
+use serde_annotate::Annotate;
+use std::any::Any;
+use std::str::FromStr;
+
+use crate::commands::{BasicResult, Dispatch};
+use crate::error::HsmError;
+use crate::module::Module;
+use crate::util::attribute::{AttrData, AttributeMap, AttributeType};
+use crate::util::helper;
+
+#[derive(clap::Args, Debug, Serialize, Deserialize)]
+pub struct Generate {
+    #[arg(long)]
+    id: Option<String>,
+    #[arg(short, long)]
+    label: Option<String>,
+    /// Key length in bits: 128, 192 or 256.
+    #[arg(short = 'n', long, default_value = "256")]
+    key_length: u64,
+    /// Permit the generated key to be used for wrapping other keys.
+    #[arg(long)]
+    wrapping: bool,
+    /// Permit the generated key to be extractable.
+    #[arg(long)]
+    extractable: bool,
+    /// Template for creating the key.
+    #[arg(long)]
+    template: Option<AttributeMap>,
+}
+
+impl Generate {
+    const TEMPLATE: &str = r#"{
+        "CKA_CLASS": "CKO_SECRET_KEY",
+        "CKA_KEY_TYPE": "CKK_AES",
+        "CKA_TOKEN": true,
+        "CKA_PRIVATE": true,
+        "CKA_SENSITIVE": true,
+        "CKA_ENCRYPT": true,
+        "CKA_DECRYPT": true
+    }"#;
+}
+
+#[typetag::serde(name = "aes-generate")]
+impl Dispatch for Generate {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        _hsm: &Module,
+        session: Option<&Session>,
+    ) -> Result<Box<dyn Annotate>> {
+        let session = session.ok_or(HsmError::SessionRequired)?;
+        helper::no_object_exists(session, self.id.as_deref(), self.label.as_deref())?;
+        let id = AttrData::Str(self.id.as_ref().cloned().unwrap_or_else(helper::random_id));
+        let result = Box::new(BasicResult {
+            success: true,
+            id: id.clone(),
+            label: AttrData::Str(self.label.as_ref().cloned().unwrap_or_default()),
+            value: None,
+            error: None,
+        });
+
+        let mut template = AttributeMap::from_str(Self::TEMPLATE).expect("error in TEMPLATE");
+        template.insert(AttributeType::Id, id);
+        template.insert(AttributeType::Label, result.label.clone());
+        if !matches!(self.key_length, 128 | 192 | 256) {
+            return Err(
+                HsmError::Unsupported(format!("AES key length {}", self.key_length)).into(),
+            );
+        }
+        template.insert(AttributeType::ValueLen, AttrData::from(self.key_length / 8));
+        if let Some(tpl) = &self.template {
+            template.merge(tpl.clone());
+        }
+        if self.wrapping {
+            template.insert(AttributeType::Wrap, AttrData::from(true));
+            template.insert(AttributeType::Unwrap, AttrData::from(true));
+        }
+        if self.extractable {
+            template.insert(AttributeType::Extractable, AttrData::from(true));
+        }
+
+        log::info!("template = {}", serde_json::to_string_pretty(&template)?);
+        let _key = session.generate_key(&Mechanism::AesKeyGen, &template.to_vec()?)?;
+        Ok(result)
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use cryptoki::session::Session;
+use serde::{Deserialize, Serialize};
+use serde_annotate::Annotate;
+use std::any::Any;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::commands::{BasicResult, Dispatch};
+use crate::error::HsmError;
+use crate::module::Module;
+use crate::util::attribute::{AttrData, AttributeMap, AttributeType};
+use crate::util::helper;
+use crate::util::wrap::{OaepHash, WrappingKey};
+
+#[derive(clap::Args, Debug, Serialize, Deserialize)]
+pub struct Import {
+    #[arg(long)]
+    id: Option<String>,
+    #[arg(short, long)]
+    label: Option<String>,
+    /// Attributes to apply to the key.
+    #[arg(long)]
+    attrs: Option<AttributeMap>,
+    /// Unwrap the imported key with the named unwrapping key.
+    #[arg(long)]
+    unwrap: String,
+    /// The RSA-OAEP hash used when the unwrapping key is an RSA key.
+    #[arg(long, value_enum, default_value = "sha256")]
+    #[serde(default)]
+    oaep_hash: OaepHash,
+    filename: PathBuf,
+}
+
+impl Import {
+    const ATTRS: &str = r#"{
+        "CKA_CLASS": "CKO_SECRET_KEY",
+        "CKA_KEY_TYPE": "CKK_AES",
+        "CKA_TOKEN": true,
+        "CKA_PRIVATE": true,
+        "CKA_SENSITIVE": true,
+        "CKA_ENCRYPT": true,
+        "CKA_DECRYPT": true
+    }"#;
+}
+
+#[typetag::serde(name = "aes-import")]
+impl Dispatch for Import {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        _hsm: &Module,
+        session: Option<&Session>,
+    ) -> Result<Box<dyn Annotate>> {
+        let session = session.ok_or(HsmError::SessionRequired)?;
+        helper::no_object_exists(session, self.id.as_deref(), self.label.as_deref())?;
+        let mut attrs = AttributeMap::from_str(Self::ATTRS).expect("error in ATTRS");
+
+        let id = AttrData::Str(self.id.as_ref().cloned().unwrap_or_else(helper::random_id));
+        let result = Box::new(BasicResult {
+            success: true,
+            id: id.clone(),
+            label: AttrData::Str(self.label.as_ref().cloned().unwrap_or_default()),
+            value: None,
+            error: None,
+        });
+        attrs.insert(AttributeType::Id, id);
+        attrs.insert(AttributeType::Label, result.label.clone());
+        if let Some(tpl) = &self.attrs {
+            attrs.merge(tpl.clone());
+        }
+
+        let wkey = WrappingKey::find(session, &self.unwrap, true, self.oaep_hash)?;
+        let wrapped = std::fs::read(&self.filename)?;
+        let _key = wkey.unwrap(session, &wrapped, &attrs)?;
+        Ok(result)
+    }
+}
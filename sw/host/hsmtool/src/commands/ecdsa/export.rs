@@ -2,7 +2,7 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, Result};
 use cryptoki::object::{Attribute, ObjectHandle};
 use cryptoki::session::Session;
 use p256::ecdsa::{SigningKey, VerifyingKey};
@@ -18,6 +18,7 @@ use crate::util::attribute::{AttrData, AttributeMap, AttributeType, KeyType, Obj
 use crate::util::helper;
 use crate::util::key::ecdsa::{save_private_key, save_public_key};
 use crate::util::key::KeyEncoding;
+use crate::util::wrap::{OaepHash, WrappingKey};
 
 #[derive(clap::Args, Debug, Serialize, Deserialize)]
 pub struct Export {
@@ -28,9 +29,13 @@ pub struct Export {
     /// Export the private key.
     #[arg(long)]
     private: bool,
-    /// Wrap the exported key a wrapping key.
+    /// Export the private key wrapped by the named wrapping key.
     #[arg(long)]
     wrap: Option<String>,
+    /// The RSA-OAEP hash used when the wrapping key is an RSA key.
+    #[arg(long, value_enum, default_value = "sha256")]
+    #[serde(default)]
+    oaep_hash: OaepHash,
     #[arg(short, long, value_enum, default_value = "pem")]
     format: KeyEncoding,
     filename: PathBuf,
@@ -54,16 +59,11 @@ impl Export {
         Ok(())
     }
 
-    fn wrap_key(&self, session: &Session, _object: ObjectHandle) -> Result<()> {
-        let mut attrs = helper::search_spec(None, self.wrap.as_deref())?;
-        attrs.push(Attribute::Class(ObjectClass::SecretKey.try_into()?));
-        let _wkey = helper::find_one_object(session, &attrs).context("Find wrapping key")?;
-
-        bail!("ECDSA export by wrapping is not supported yet!");
-        // FIXME(cfrantz): Turn this back on when cryptoki includes the correct mechanisms.
-        //let wrapped = session.wrap_key(&Mechanism::EcdsaPkcs, wkey, object)?;
-        //std::fs::write(&self.filename, &wrapped)?;
-        //Ok(())
+    fn wrap_key(&self, session: &Session, object: ObjectHandle, wrap: &str) -> Result<()> {
+        let wkey = WrappingKey::find(session, wrap, false, self.oaep_hash)?;
+        let wrapped = wkey.wrap(session, object)?;
+        std::fs::write(&self.filename, wrapped)?;
+        Ok(())
     }
 }
 
@@ -78,15 +78,15 @@ impl Dispatch for Export {
         let session = session.ok_or(HsmError::SessionRequired)?;
         let mut attrs = helper::search_spec(self.id.as_deref(), self.label.as_deref())?;
         attrs.push(Attribute::KeyType(KeyType::Ec.try_into()?));
-        if self.private {
+        if self.private || self.wrap.is_some() {
             attrs.push(Attribute::Class(ObjectClass::PrivateKey.try_into()?));
         } else {
             attrs.push(Attribute::Class(ObjectClass::PublicKey.try_into()?));
         }
         let object = helper::find_one_object(session, &attrs)?;
 
-        if self.wrap.is_some() {
-            self.wrap_key(session, object)?;
+        if let Some(wrap) = &self.wrap {
+            self.wrap_key(session, object, wrap)?;
         } else {
             self.export(session, object)?;
         }
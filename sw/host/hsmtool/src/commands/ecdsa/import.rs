@@ -2,8 +2,7 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{bail, Context, Result};
-use cryptoki::object::Attribute;
+use anyhow::{anyhow, Result};
 use cryptoki::session::Session;
 use serde::{Deserialize, Serialize};
 use serde_annotate::Annotate;
@@ -14,9 +13,10 @@ use std::str::FromStr;
 use crate::commands::{BasicResult, Dispatch};
 use crate::error::HsmError;
 use crate::module::Module;
-use crate::util::attribute::{AttrData, AttributeMap, AttributeType, ObjectClass};
+use crate::util::attribute::{AttrData, AttributeMap, AttributeType};
 use crate::util::helper;
 use crate::util::key::ecdsa::{load_private_key, load_public_key};
+use crate::util::wrap::{OaepHash, WrappingKey};
 
 #[derive(clap::Args, Debug, Serialize, Deserialize)]
 pub struct Import {
@@ -33,9 +33,16 @@ pub struct Import {
     /// Attributes to apply to the private key.
     #[arg(long)]
     private_attrs: Option<AttributeMap>,
-    /// Unwrap the imported key with a wrapping key.
-    #[arg(long)]
+    /// Unwrap the imported private key with the named unwrapping key.
+    #[arg(long, requires = "public_key")]
     unwrap: Option<String>,
+    /// The RSA-OAEP hash used when the unwrapping key is an RSA key.
+    #[arg(long, value_enum, default_value = "sha256")]
+    #[serde(default)]
+    oaep_hash: OaepHash,
+    /// The public key to import alongside an unwrapped private key.
+    #[arg(long, requires = "unwrap")]
+    public_key: Option<PathBuf>,
     filename: PathBuf,
 }
 
@@ -56,16 +63,26 @@ impl Import {
         "CKA_SIGN": true
     }"#;
 
-    fn unwrap_key(&self, session: &Session, _template: &AttributeMap) -> Result<()> {
-        let mut attrs = helper::search_spec(None, self.unwrap.as_deref())?;
-        attrs.push(Attribute::Class(ObjectClass::SecretKey.try_into()?));
-        let _wkey = helper::find_one_object(session, &attrs).context("Find unwrapping key")?;
-
-        bail!("ECDSA import by unwrapping is not supported yet!");
-        // FIXME(cfrantz): Turn this back on when cryptoki includes the correct mechanisms.
-        //let key = std::fs::read(&self.filename)?;
-        //let k = session.unwrap_key(&Mechanism::EcdsaPkcs, wkey, &key, &template.to_vec()?)?;
-        //Ok(())
+    fn unwrap_key(
+        &self,
+        session: &Session,
+        unwrap: &str,
+        mut public_attrs: AttributeMap,
+        private_attrs: &AttributeMap,
+    ) -> Result<()> {
+        // The wrapped key does not carry the public point, so the public
+        // key must be supplied separately.
+        let public_key = self
+            .public_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("Unwrapping an ECDSA key requires a public key"))?;
+        let key = load_public_key(public_key)?;
+        public_attrs.merge(AttributeMap::try_from(&key)?);
+        let wkey = WrappingKey::find(session, unwrap, true, self.oaep_hash)?;
+        let wrapped = std::fs::read(&self.filename)?;
+        let _privkey = wkey.unwrap(session, &wrapped, private_attrs)?;
+        let _pubkey = session.create_object(&public_attrs.to_vec()?)?;
+        Ok(())
     }
 }
 
@@ -108,8 +125,8 @@ impl Dispatch for Import {
             let key = load_public_key(&self.filename)?;
             public_attrs.merge(AttributeMap::try_from(&key)?);
             let _pubkey = session.create_object(&public_attrs.to_vec()?)?;
-        } else if self.unwrap.is_some() {
-            self.unwrap_key(session, &private_attrs)?;
+        } else if let Some(unwrap) = &self.unwrap {
+            self.unwrap_key(session, unwrap, public_attrs, &private_attrs)?;
         } else {
             let key = load_private_key(&self.filename)?;
             public_attrs.merge(AttributeMap::try_from(key.verifying_key())?);
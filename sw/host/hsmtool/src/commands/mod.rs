@@ -12,6 +12,7 @@ use std::io::IsTerminal;
 use crate::module::Module;
 use crate::util::attribute::AttrData;
 
+mod aes;
 mod ecdsa;
 mod exec;
 mod object;
@@ -38,6 +39,8 @@ pub trait Dispatch {
 
 #[derive(clap::Subcommand, Debug, Serialize, Deserialize)]
 pub enum Commands {
+    #[command(subcommand)]
+    Aes(aes::Aes),
     #[command(subcommand)]
     Ecdsa(ecdsa::Ecdsa),
     Exec(exec::Exec),
@@ -60,6 +63,7 @@ impl Dispatch for Commands {
         session: Option<&Session>,
     ) -> Result<Box<dyn Annotate>> {
         match self {
+            Commands::Aes(x) => x.run(context, hsm, session),
             Commands::Ecdsa(x) => x.run(context, hsm, session),
             Commands::Exec(x) => x.run(context, hsm, session),
             Commands::Object(x) => x.run(context, hsm, session),
@@ -74,6 +78,7 @@ impl Dispatch for Commands {
         Self: Sized,
     {
         match self {
+            Commands::Aes(x) => x.leaf(),
             Commands::Ecdsa(x) => x.leaf(),
             Commands::Exec(x) => x.leaf(),
             Commands::Object(x) => x.leaf(),
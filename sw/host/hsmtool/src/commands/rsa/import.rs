@@ -2,9 +2,9 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{bail, Context, Result};
-use cryptoki::object::Attribute;
+use anyhow::Result;
 use cryptoki::session::Session;
+use rsa::RsaPublicKey;
 use serde::{Deserialize, Serialize};
 use serde_annotate::Annotate;
 use std::any::Any;
@@ -14,9 +14,10 @@ use std::str::FromStr;
 use crate::commands::{BasicResult, Dispatch};
 use crate::error::HsmError;
 use crate::module::Module;
-use crate::util::attribute::{AttrData, AttributeMap, AttributeType, ObjectClass};
+use crate::util::attribute::{AttrData, AttributeMap, AttributeType};
 use crate::util::helper;
 use crate::util::key::rsa::{load_private_key, load_public_key};
+use crate::util::wrap::{OaepHash, WrappingKey};
 
 #[derive(clap::Args, Debug, Serialize, Deserialize)]
 pub struct Import {
@@ -33,9 +34,13 @@ pub struct Import {
     /// Attributes to apply to the private key.
     #[arg(long)]
     private_attrs: Option<AttributeMap>,
-    /// Unwrap the imported key with a wrapping key.
+    /// Unwrap the imported private key with the named unwrapping key.
     #[arg(long)]
     unwrap: Option<String>,
+    /// The RSA-OAEP hash used when the unwrapping key is an RSA key.
+    #[arg(long, value_enum, default_value = "sha256")]
+    #[serde(default)]
+    oaep_hash: OaepHash,
     filename: PathBuf,
 }
 
@@ -58,16 +63,22 @@ impl Import {
         "CKA_SIGN": true
     }"#;
 
-    fn unwrap_key(&self, session: &Session, _template: &AttributeMap) -> Result<()> {
-        let mut attrs = helper::search_spec(None, self.unwrap.as_deref())?;
-        attrs.push(Attribute::Class(ObjectClass::SecretKey.try_into()?));
-        let _wkey = helper::find_one_object(session, &attrs).context("Find unwrapping key")?;
-
-        bail!("RSA import by unwrapping is not supported yet!");
-        // FIXME(cfrantz): Turn this back on when cryptoki includes the correct mechanisms.
-        //let key = std::fs::read(&self.filename)?;
-        //let k = session.unwrap_key(&Mechanism::RsaPkcs, wkey, &key, &template.to_vec()?)?;
-        //Ok(())
+    fn unwrap_key(
+        &self,
+        session: &Session,
+        unwrap: &str,
+        mut public_attrs: AttributeMap,
+        private_attrs: &AttributeMap,
+    ) -> Result<()> {
+        let wkey = WrappingKey::find(session, unwrap, true, self.oaep_hash)?;
+        let wrapped = std::fs::read(&self.filename)?;
+        let privkey = wkey.unwrap(session, &wrapped, private_attrs)?;
+        // The wrapped key carries the modulus and public exponent, so the
+        // public key can be recreated from the unwrapped private key.
+        let key = RsaPublicKey::try_from(&AttributeMap::from_object(session, privkey)?)?;
+        public_attrs.merge(AttributeMap::try_from(&key)?);
+        let _pubkey = session.create_object(&public_attrs.to_vec()?)?;
+        Ok(())
     }
 }
 
@@ -110,8 +121,8 @@ impl Dispatch for Import {
             let key = load_public_key(&self.filename)?;
             public_attrs.merge(AttributeMap::try_from(&key)?);
             let _pubkey = session.create_object(&public_attrs.to_vec()?)?;
-        } else if self.unwrap.is_some() {
-            self.unwrap_key(session, &private_attrs)?;
+        } else if let Some(unwrap) = &self.unwrap {
+            self.unwrap_key(session, unwrap, public_attrs, &private_attrs)?;
         } else {
             let key = load_private_key(&self.filename)?;
             public_attrs.merge(AttributeMap::try_from(&key.to_public_key())?);
@@ -8,6 +8,7 @@ pub mod escape;
 pub mod helper;
 pub mod key;
 pub mod signing;
+pub mod wrap;
 
 /// The `testdata` function can be used in tests to reference testdata directories.
 #[cfg(test)]
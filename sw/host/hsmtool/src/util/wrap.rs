@@ -0,0 +1,181 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Key wrapping helpers.
+//!
+//! Keys are wrapped with one of two schemes, selected by the type of the
+//! wrapping key:
+//!
+//! - AES wrapping keys use AES key wrap with padding (RFC 5649).
+//! - RSA wrapping keys use the `CKM_RSA_AES_KEY_WRAP` format: an ephemeral
+//!   AES-256 key is wrapped with RSA-OAEP and is followed by the target key
+//!   wrapped with the ephemeral key using AES key wrap with padding.  The
+//!   OAEP hash (also used for MGF1) is selected with `--oaep-hash` and
+//!   defaults to SHA-256; SoftHSM only supports SHA-1.
+//!
+//! The RSA scheme is composed from its primitive mechanisms because most
+//! tokens (including SoftHSM) do not implement `CKM_RSA_AES_KEY_WRAP`
+//! directly.
+
+use anyhow::{Context, Result};
+use cryptoki::mechanism::rsa::{PkcsMgfType, PkcsOaepParams, PkcsOaepSource};
+use cryptoki::mechanism::{Mechanism, MechanismType};
+use cryptoki::object::{Attribute, ObjectHandle};
+use cryptoki::session::Session;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::error::HsmError;
+use crate::util::attribute::{AttrData, AttributeMap, AttributeType, KeyType};
+use crate::util::helper;
+
+/// The hash function used by RSA-OAEP, for both the digest and MGF1.
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OaepHash {
+    #[serde(alias = "sha1")]
+    Sha1,
+    #[default]
+    #[serde(alias = "sha256")]
+    Sha256,
+    #[serde(alias = "sha384")]
+    Sha384,
+    #[serde(alias = "sha512")]
+    Sha512,
+}
+
+/// A key used to wrap or unwrap other keys.
+pub enum WrappingKey {
+    Aes(ObjectHandle),
+    Rsa(ObjectHandle, OaepHash),
+}
+
+impl WrappingKey {
+    /// Size of the ephemeral AES key used by the RSA scheme.
+    const EPHEMERAL_KEY_LEN: u64 = 32;
+
+    const EPHEMERAL_TEMPLATE: &str = r#"{
+        "CKA_CLASS": "CKO_SECRET_KEY",
+        "CKA_KEY_TYPE": "CKK_AES",
+        "CKA_TOKEN": false,
+        "CKA_SENSITIVE": true,
+        "CKA_WRAP": true,
+        "CKA_UNWRAP": true
+    }"#;
+
+    /// Finds the wrapping key with `label`.
+    ///
+    /// When `unwrap` is true, the key must permit unwrapping (for RSA, this
+    /// selects the private key); otherwise it must permit wrapping.  An RSA
+    /// key uses `oaep_hash` for RSA-OAEP.
+    pub fn find(session: &Session, label: &str, unwrap: bool, oaep_hash: OaepHash) -> Result<Self> {
+        let mut attrs = helper::search_spec(None, Some(label))?;
+        attrs.push(if unwrap {
+            Attribute::Unwrap(true)
+        } else {
+            Attribute::Wrap(true)
+        });
+        let object = helper::find_one_object(session, &attrs).context(if unwrap {
+            "Find unwrapping key"
+        } else {
+            "Find wrapping key"
+        })?;
+        let aes: cryptoki::object::KeyType = KeyType::Aes.try_into()?;
+        let rsa: cryptoki::object::KeyType = KeyType::Rsa.try_into()?;
+        let key_type =
+            session.get_attributes(object, &[cryptoki::object::AttributeType::KeyType])?;
+        match key_type.first() {
+            Some(Attribute::KeyType(kt)) if *kt == aes => Ok(WrappingKey::Aes(object)),
+            Some(Attribute::KeyType(kt)) if *kt == rsa => Ok(WrappingKey::Rsa(object, oaep_hash)),
+            _ => Err(HsmError::Unsupported(format!("wrapping key type of {label:?}")).into()),
+        }
+    }
+
+    fn oaep(hash: OaepHash) -> Mechanism<'static> {
+        let (hash, mgf) = match hash {
+            OaepHash::Sha1 => (MechanismType::SHA1, PkcsMgfType::MGF1_SHA1),
+            OaepHash::Sha256 => (MechanismType::SHA256, PkcsMgfType::MGF1_SHA256),
+            OaepHash::Sha384 => (MechanismType::SHA384, PkcsMgfType::MGF1_SHA384),
+            OaepHash::Sha512 => (MechanismType::SHA512, PkcsMgfType::MGF1_SHA512),
+        };
+        Mechanism::RsaPkcsOaep(PkcsOaepParams::new(hash, mgf, PkcsOaepSource::empty()))
+    }
+
+    fn ephemeral_template() -> AttributeMap {
+        AttributeMap::from_str(Self::EPHEMERAL_TEMPLATE).expect("error in EPHEMERAL_TEMPLATE")
+    }
+
+    /// Wraps `key`, returning the wrapped key material.
+    pub fn wrap(&self, session: &Session, key: ObjectHandle) -> Result<Vec<u8>> {
+        match self {
+            WrappingKey::Aes(wkey) => {
+                Ok(session.wrap_key(&Mechanism::AesKeyWrapPad, *wkey, key)?)
+            }
+            WrappingKey::Rsa(wkey, hash) => {
+                let mut template = Self::ephemeral_template();
+                template.insert(AttributeType::Extractable, AttrData::from(true));
+                template.insert(
+                    AttributeType::ValueLen,
+                    AttrData::from(Self::EPHEMERAL_KEY_LEN),
+                );
+                let template = template.to_vec()?;
+                let ephemeral = session.generate_key(&Mechanism::AesKeyGen, &template)?;
+                let result = Self::wrap_rsa_aes(session, *wkey, *hash, ephemeral, key);
+                session.destroy_object(ephemeral)?;
+                result
+            }
+        }
+    }
+
+    fn wrap_rsa_aes(
+        session: &Session,
+        wkey: ObjectHandle,
+        hash: OaepHash,
+        ephemeral: ObjectHandle,
+        key: ObjectHandle,
+    ) -> Result<Vec<u8>> {
+        let mut wrapped = session.wrap_key(&Self::oaep(hash), wkey, ephemeral)?;
+        wrapped.extend(session.wrap_key(&Mechanism::AesKeyWrapPad, ephemeral, key)?);
+        Ok(wrapped)
+    }
+
+    /// Unwraps `wrapped` into a new object described by `template`.
+    pub fn unwrap(
+        &self,
+        session: &Session,
+        wrapped: &[u8],
+        template: &AttributeMap,
+    ) -> Result<ObjectHandle> {
+        let template = template.to_vec()?;
+        match self {
+            WrappingKey::Aes(wkey) => {
+                Ok(session.unwrap_key(&Mechanism::AesKeyWrapPad, *wkey, wrapped, &template)?)
+            }
+            WrappingKey::Rsa(wkey, hash) => {
+                let modulus =
+                    session.get_attributes(*wkey, &[cryptoki::object::AttributeType::Modulus])?;
+                let Some(Attribute::Modulus(modulus)) = modulus.first() else {
+                    return Err(HsmError::KeyError("wrapping key has no modulus".into()).into());
+                };
+                if wrapped.len() <= modulus.len() {
+                    return Err(HsmError::KeyError(format!(
+                        "wrapped key too short: {} bytes",
+                        wrapped.len()
+                    ))
+                    .into());
+                }
+                let (ephemeral, wrapped) = wrapped.split_at(modulus.len());
+                let ephemeral = session.unwrap_key(
+                    &Self::oaep(*hash),
+                    *wkey,
+                    ephemeral,
+                    &Self::ephemeral_template().to_vec()?,
+                )?;
+                let result =
+                    session.unwrap_key(&Mechanism::AesKeyWrapPad, ephemeral, wrapped, &template);
+                session.destroy_object(ephemeral)?;
+                Ok(result?)
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+#!/bin/bash
+# Copyright lowRISC contributors (OpenTitan project).
+# Licensed under the Apache License, Version 2.0, see LICENSE for details.
+# SPDX-License-Identifier: Apache-2.0
+
+# Round-trips RSA, ECDSA and AES keys through `export --wrap` and
+# `import --unwrap` with both AES and RSA wrapping keys.  The original and
+# imported keys are compared by wrapping both with the same AES key: AES key
+# wrap is deterministic, so identical keys produce identical wrapped blobs.
+
+set -euo pipefail
+
+readonly HSMTOOL=sw/host/hsmtool/hsmtool
+
+# The token in //signing/softhsm is read-only in the runfiles tree, so work
+# on a copy.
+mkdir -p "${TEST_TMPDIR}/tokens"
+cp -r signing/softhsm/tokens/. "${TEST_TMPDIR}/tokens"
+chmod -R u+w "${TEST_TMPDIR}/tokens"
+cat > "${TEST_TMPDIR}/softhsm.conf" <<CONF
+directories.tokendir = ${TEST_TMPDIR}/tokens
+objectstore.backend = file
+log.level = WARNING
+slots.removable = false
+slots.mechanisms = ALL
+CONF
+export SOFTHSM2_CONF="${TEST_TMPDIR}/softhsm.conf"
+export HSMTOOL_TOKEN=fake_keys
+export HSMTOOL_USER=user
+export HSMTOOL_PIN=123456
+
+readonly EXTRACTABLE='{"CKA_EXTRACTABLE": true}'
+# SoftHSM only supports SHA-1 for RSA-OAEP key wrapping.  The option is
+# ignored when the wrapping key is an AES key.
+readonly OAEP=--oaep-hash=sha1
+readonly WORK="${TEST_TMPDIR}/keys"
+mkdir -p "${WORK}"
+
+${HSMTOOL} aes generate --label=wrap-aes --wrapping
+${HSMTOOL} rsa generate --label=wrap-rsa --wrapping
+${HSMTOOL} aes generate --label=key-aes --extractable
+${HSMTOOL} rsa generate --label=key-rsa --extractable
+${HSMTOOL} ecdsa generate --label=key-ecdsa --extractable
+${HSMTOOL} ecdsa export --label=key-ecdsa "${WORK}/key-ecdsa.pub.pem"
+
+for wrap in wrap-aes wrap-rsa; do
+    ${HSMTOOL} aes export --label=key-aes --wrap=${wrap} ${OAEP} "${WORK}/aes.${wrap}"
+    ${HSMTOOL} aes import --label=key-aes-${wrap} --unwrap=${wrap} ${OAEP} \
+        --attrs="${EXTRACTABLE}" "${WORK}/aes.${wrap}"
+
+    ${HSMTOOL} rsa export --label=key-rsa --wrap=${wrap} ${OAEP} "${WORK}/rsa.${wrap}"
+    ${HSMTOOL} rsa import --label=key-rsa-${wrap} --unwrap=${wrap} ${OAEP} \
+        --private-attrs="${EXTRACTABLE}" "${WORK}/rsa.${wrap}"
+
+    ${HSMTOOL} ecdsa export --label=key-ecdsa --wrap=${wrap} ${OAEP} "${WORK}/ecdsa.${wrap}"
+    ${HSMTOOL} ecdsa import --label=key-ecdsa-${wrap} --unwrap=${wrap} ${OAEP} \
+        --private-attrs="${EXTRACTABLE}" --public-key="${WORK}/key-ecdsa.pub.pem" \
+        "${WORK}/ecdsa.${wrap}"
+done
+
+for alg in aes rsa ecdsa; do
+    ${HSMTOOL} ${alg} export --label=key-${alg} --wrap=wrap-aes "${WORK}/${alg}.orig"
+    for wrap in wrap-aes wrap-rsa; do
+        ${HSMTOOL} ${alg} export --label=key-${alg}-${wrap} --wrap=wrap-aes \
+            "${WORK}/${alg}.copy.${wrap}"
+        cmp "${WORK}/${alg}.orig" "${WORK}/${alg}.copy.${wrap}"
+    done
+done
+
+# The imported ECDSA public key must match the original.
+${HSMTOOL} ecdsa export --label=key-ecdsa-wrap-rsa "${WORK}/copy.pub.pem"
+cmp "${WORK}/key-ecdsa.pub.pem" "${WORK}/copy.pub.pem"
+echo "PASS"
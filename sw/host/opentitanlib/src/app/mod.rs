@@ -5,6 +5,7 @@
 //! Useful modules for OpenTitanTool application development.
 pub mod command;
 pub mod config;
+pub mod parallel;
 
 mod gpio;
 mod i2c;
@@ -25,7 +26,7 @@ use crate::transport::{
 };
 
 use anyhow::{bail, ensure, Result};
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use serde_annotate::Annotate;
 use serialport::Parity;
 use std::any::Any;
@@ -49,6 +50,9 @@ impl ProgressIndicator for NoProgressBar {
 /// underway.
 pub struct StagedProgressBar {
     pub current_progress_bar: Rc<RefCell<Option<indicatif::ProgressBar>>>,
+    /// When operating on several devices at once, bars are drawn together, each line prefixed
+    /// with the name of the device.
+    multi: Option<(MultiProgress, String)>,
 }
 
 impl Default for StagedProgressBar {
@@ -65,6 +69,17 @@ impl StagedProgressBar {
     pub fn new() -> Self {
         Self {
             current_progress_bar: Rc::new(RefCell::new(None)),
+            multi: None,
+        }
+    }
+
+    /// Creates a progress bar which will be drawn as part of `multi`, with every line prefixed
+    /// by `prefix`.  Since `MultiProgress` is thread-safe, this allows displaying the progress of
+    /// operations running in parallel threads.
+    pub fn with_multi_progress(multi: MultiProgress, prefix: &str) -> Self {
+        Self {
+            current_progress_bar: Rc::new(RefCell::new(None)),
+            multi: Some((multi, prefix.to_string())),
         }
     }
 
@@ -85,19 +100,21 @@ impl StagedProgressBar {
 
 impl ProgressIndicator for StagedProgressBar {
     fn new_stage(&self, name: &str, total: usize) {
-        let progress = ProgressBar::new(total as u64);
-        if name.is_empty() {
-            progress.set_style(
-                ProgressStyle::default_bar()
-                    .template(Self::DEFAULT_TEMPLATE)
-                    .unwrap(),
-            );
+        let mut progress = ProgressBar::new(total as u64);
+        let template = if name.is_empty() {
+            Self::DEFAULT_TEMPLATE
         } else {
+            Self::STAGE_TEMPLATE
+        };
+        if let Some((multi, prefix)) = &self.multi {
+            progress = multi.add(progress).with_prefix(prefix.clone());
             progress.set_style(
                 ProgressStyle::default_bar()
-                    .template(Self::STAGE_TEMPLATE)
+                    .template(&format!("{{prefix}} {}", template))
                     .unwrap(),
             );
+        } else {
+            progress.set_style(ProgressStyle::default_bar().template(template).unwrap());
         }
         self.current_progress_bar
             .borrow_mut()
@@ -1116,6 +1133,38 @@ impl TransportWrapper {
     pub fn maintain_connection(&self) -> Result<Rc<dyn MaintainConnection>> {
         self.transport.maintain_connection()
     }
+
+    /// Returns the name of the physical pin, which the given name refers to.
+    pub fn resolve_pin_name(&self, name: &str) -> String {
+        map_name(&self.pin_map, name)
+    }
+
+    /// Returns the name of the physical UART, which the given name refers to.
+    pub fn resolve_uart_name(&self, name: &str) -> String {
+        let name = name.to_uppercase();
+        match self.uart_conf_map.get(&name) {
+            Some(conf) => conf.underlying_instance.clone(),
+            None => name,
+        }
+    }
+
+    /// Returns the name of the physical SPI bus, which the given name refers to.
+    pub fn resolve_spi_name(&self, name: &str) -> String {
+        let name = name.to_uppercase();
+        match self.spi_conf_map.get(&name) {
+            Some(conf) => conf.underlying_instance.clone(),
+            None => name,
+        }
+    }
+
+    /// Returns the name of the physical I2C bus, which the given name refers to.
+    pub fn resolve_i2c_name(&self, name: &str) -> String {
+        let name = name.to_uppercase();
+        match self.i2c_conf_map.get(&name) {
+            Some(conf) => conf.underlying_instance.clone(),
+            None => name,
+        }
+    }
 }
 
 /// Given an pin/uart/spi/i2c port name, if the name is a known alias, return the underlying
@@ -0,0 +1,229 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support for operating on several devices from one process.
+//!
+//! `TransportWrapper` and everything below it relies on `Rc` and is confined to the thread that
+//! created it.  Operations on multiple devices are therefore run by giving each device its own
+//! thread, in which a separate `TransportWrapper` is instantiated from a copy of the
+//! `BackendOpts`.  Only the options, the operation closure and the resulting reports cross
+//! thread boundaries.
+
+use anyhow::{bail, Result};
+use indicatif::MultiProgress;
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::Instant;
+
+use crate::app::{StagedProgressBar, TransportWrapper};
+use crate::backend::{self, BackendOpts};
+
+/// Identifies one of several devices, by debug interface name and USB serial number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceSpec {
+    /// Name of the debug interface, if absent, the `--interface` option applies.
+    pub interface: Option<String>,
+    /// USB serial number of the debugger.
+    pub usb_serial: String,
+}
+
+impl FromStr for DeviceSpec {
+    type Err = anyhow::Error;
+
+    /// Parses `[INTERFACE:]USB_SERIAL`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (interface, usb_serial) = match s.split_once(':') {
+            Some((interface, usb_serial)) => (Some(interface.to_string()), usb_serial),
+            None => (None, s),
+        };
+        if usb_serial.is_empty() {
+            bail!("Missing USB serial number in device specification {:?}", s);
+        }
+        Ok(Self {
+            interface,
+            usb_serial: usb_serial.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for DeviceSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.interface {
+            Some(interface) => write!(f, "{}:{}", interface, self.usb_serial),
+            None => write!(f, "{}", self.usb_serial),
+        }
+    }
+}
+
+impl DeviceSpec {
+    /// Returns a copy of `base`, modified to select this particular device.
+    pub fn backend_opts(&self, base: &BackendOpts) -> BackendOpts {
+        let mut opts = base.clone();
+        if let Some(interface) = &self.interface {
+            opts.interface = interface.clone();
+        }
+        opts.usb_serial = Some(self.usb_serial.clone());
+        opts
+    }
+}
+
+/// Outcome of an operation on one device.
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceReport {
+    pub device: String,
+    pub success: bool,
+    pub error: Option<String>,
+    /// Wall clock time spent, including instantiating the transport.
+    pub seconds: f64,
+}
+
+/// Outcome of an operation on a set of devices.
+#[derive(Clone, Debug, Serialize)]
+pub struct ParallelReport {
+    pub passed: usize,
+    pub failed: usize,
+    pub devices: Vec<DeviceReport>,
+}
+
+impl ParallelReport {
+    /// Converts the report into an error listing each failed device, if any failed.
+    pub fn check(&self) -> Result<()> {
+        if self.failed == 0 {
+            return Ok(());
+        }
+        let failures = self
+            .devices
+            .iter()
+            .filter(|d| !d.success)
+            .map(|d| format!("{}: {}", d.device, d.error.as_deref().unwrap_or("")))
+            .collect::<Vec<_>>();
+        bail!(
+            "{} of {} devices failed:\n{}",
+            self.failed,
+            self.devices.len(),
+            failures.join("\n")
+        );
+    }
+}
+
+/// Runs `operation` concurrently on each of the given devices, each in its own thread with its
+/// own `TransportWrapper` instantiated according to the accompanying `BackendOpts`.  Progress
+/// bars of all devices are drawn together.  A failure (or panic) on one device does not affect
+/// the others.
+pub fn run_on_devices<F>(devices: &[(String, BackendOpts)], operation: F) -> ParallelReport
+where
+    F: Fn(&TransportWrapper, &StagedProgressBar) -> Result<()> + Sync,
+{
+    let multi = MultiProgress::new();
+    let operation = &operation;
+    let reports: Vec<DeviceReport> = std::thread::scope(|scope| {
+        let handles: Vec<_> = devices
+            .iter()
+            .map(|(name, opts)| {
+                let progress_target = multi.clone();
+                let start = Instant::now();
+                let handle = scope.spawn(move || {
+                    let transport = backend::create(opts)?;
+                    let progress = StagedProgressBar::with_multi_progress(progress_target, name);
+                    operation(&transport, &progress)
+                });
+                (name, start, handle)
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|(name, start, handle)| {
+                let result = match handle.join() {
+                    Ok(result) => result,
+                    Err(_) => Err(anyhow::anyhow!("Thread panicked")),
+                };
+                if let Err(e) = &result {
+                    log::error!("{}: {:?}", name, e);
+                }
+                DeviceReport {
+                    device: name.clone(),
+                    success: result.is_ok(),
+                    error: result.err().map(|e| format!("{:#}", e)),
+                    seconds: start.elapsed().as_secs_f64(),
+                }
+            })
+            .collect()
+    });
+    let passed = reports.iter().filter(|r| r.success).count();
+    ParallelReport {
+        passed,
+        failed: reports.len() - passed,
+        devices: reports,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bootstrap::{Bootstrap, BootstrapOptions};
+    use crate::spiflash::SpiFlash;
+    use anyhow::ensure;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Opts {
+        #[command(flatten)]
+        backend: BackendOpts,
+        #[command(flatten)]
+        bootstrap: BootstrapOptions,
+    }
+
+    #[test]
+    fn test_device_spec() -> Result<()> {
+        let spec = DeviceSpec::from_str("hyper340:204A38")?;
+        assert_eq!(spec.interface.as_deref(), Some("hyper340"));
+        assert_eq!(spec.usb_serial, "204A38");
+        assert_eq!(spec.to_string(), "hyper340:204A38");
+
+        let spec = DeviceSpec::from_str("204A38")?;
+        assert_eq!(spec.interface, None);
+        let opts = Opts::try_parse_from(["test", "--interface", "hyperdebug"])?;
+        let backend = spec.backend_opts(&opts.backend);
+        assert_eq!(backend.interface, "hyperdebug");
+        assert_eq!(backend.usb_serial.as_deref(), Some("204A38"));
+
+        assert!(DeviceSpec::from_str("hyperdebug:").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_bootstrap() -> Result<()> {
+        let opts = Opts::try_parse_from(["test", "--interface", "sim", "--reset-delay", "1ms"])?;
+        let mut bogus = opts.backend.clone();
+        bogus.interface = "bogus".to_string();
+        let devices = vec![
+            ("sim0".to_string(), opts.backend.clone()),
+            ("sim1".to_string(), opts.backend.clone()),
+            ("bogus".to_string(), bogus),
+        ];
+        let payload = vec![0x5au8; 8192];
+        let report = run_on_devices(&devices, |transport, progress| {
+            transport.apply_default_configuration(None)?;
+            Bootstrap::update_with_progress(transport, &opts.bootstrap, &payload, progress)?;
+            // Each thread has a simulated device of its own, read back its flash.
+            let spi = transport.spi("BOOTSTRAP")?;
+            let flash = SpiFlash::from_spi(&*spi)?;
+            let mut buffer = vec![0u8; payload.len()];
+            flash.read(&*spi, 0, &mut buffer)?;
+            ensure!(buffer == payload, "flash contents mismatch");
+            Ok(())
+        });
+        assert_eq!(report.passed, 2);
+        assert_eq!(report.failed, 1);
+        assert!(report.devices[0].success && report.devices[1].success);
+        assert_eq!(report.devices[2].device, "bogus");
+        assert!(report.devices[2]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("Unknown interface"));
+        assert!(report.check().is_err());
+        Ok(())
+    }
+}
@@ -243,6 +243,18 @@ impl Target for LogicalSpiWrapper {
             .run_eeprom_transactions(transactions)
     }
 
+    fn eeprom_sha256(
+        &self,
+        address: u32,
+        length: u32,
+        address_mode: eeprom::AddressMode,
+    ) -> Result<Option<[u8; 32]>> {
+        self.apply_settings_to_underlying()?;
+        self.physical_wrapper
+            .underlying_target
+            .eeprom_sha256(address, length, address_mode)
+    }
+
     fn assert_cs(self: Rc<Self>) -> Result<spi::AssertChipSelect> {
         self.apply_settings_to_underlying()?;
         Rc::clone(&self.physical_wrapper.underlying_target).assert_cs()
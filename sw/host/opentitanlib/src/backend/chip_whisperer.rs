@@ -10,7 +10,7 @@ use crate::transport::chip_whisperer::board::Board;
 use crate::transport::chip_whisperer::ChipWhisperer;
 use crate::transport::Transport;
 
-#[derive(Debug, Args)]
+#[derive(Clone, Debug, Args)]
 pub struct ChipWhispererOpts {
     /// Comma-separated list of Chip Whisperer board UARTs for non-udev environments. List the console uart first.
     #[arg(long, alias = "cw310-uarts")]
@@ -15,6 +15,8 @@ use crate::transport::ftdi::chip::Ft4232hq;
 use crate::transport::hyperdebug::{
     C2d2Flavor, ChipWhispererFlavor, ServoMicroFlavor, StandardFlavor, Ti50Flavor,
 };
+use crate::transport::record::RecordingTransport;
+use crate::transport::sim::Sim;
 use crate::transport::{EmptyTransport, Transport};
 use crate::util::parse_int::ParseInt;
 
@@ -22,11 +24,12 @@ mod chip_whisperer;
 mod ftdi;
 mod hyperdebug;
 mod proxy;
+mod replay;
 mod ti50emulator;
 mod ultradebug;
 mod verilator;
 
-#[derive(Debug, Args)]
+#[derive(Clone, Debug, Args)]
 pub struct BackendOpts {
     /// Name of the debug interface.
     #[arg(long, default_value = "")]
@@ -58,6 +61,9 @@ pub struct BackendOpts {
     #[command(flatten)]
     pub proxy_opts: proxy::ProxyOpts,
 
+    #[command(flatten)]
+    pub replay_opts: replay::ReplayOpts,
+
     #[command(flatten)]
     pub ti50emulator_opts: ti50emulator::Ti50EmulatorOpts,
 
@@ -71,6 +77,11 @@ pub struct BackendOpts {
     /// this argument must be specified if using JTAG.)
     #[arg(long)]
     pub openocd_adapter_config: Option<PathBuf>,
+
+    /// Record all SPI, I2C, UART and GPIO traffic to the given file, for later use with
+    /// `--interface replay`.
+    #[arg(long)]
+    pub record: Option<PathBuf>,
 }
 
 #[derive(Error, Debug)]
@@ -90,6 +101,11 @@ pub fn create(args: &BackendOpts) -> Result<TransportWrapper> {
     let (backend, default_conf) = match env.get_interface() {
         "" => (create_empty_transport()?, None),
         "proxy" => (proxy::create(&args.proxy_opts)?, None),
+        "replay" => (replay::create(&args.replay_opts)?, None),
+        "sim" => {
+            let sim: Box<dyn Transport> = Box::new(Sim::new());
+            (sim, Some(Path::new("/__builtin__/opentitan_sim.json")))
+        }
         "verilator" => (
             verilator::create(&args.verilator_opts)?,
             Some(Path::new("/__builtin__/opentitan_verilator.json")),
@@ -152,6 +168,10 @@ pub fn create(args: &BackendOpts) -> Result<TransportWrapper> {
             process_config_file(&mut env, conf_file)?
         }
     }
+    let backend: Box<dyn Transport> = match &args.record {
+        Some(path) => Box::new(RecordingTransport::new(backend, path)?),
+        None => backend,
+    };
     env.set_openocd_adapter_config(&args.openocd_adapter_config);
     env.build(backend)
 }
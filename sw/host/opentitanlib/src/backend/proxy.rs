@@ -2,20 +2,96 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Args;
+use std::path::PathBuf;
+use std::time::Duration;
 
+use crate::proxy::lease::{self, LeaseResource};
+use crate::proxy::security::{read_certificates, TlsClientConfig, TlsIdentity};
 use crate::transport::proxy::Proxy;
 use crate::transport::Transport;
 
-#[derive(Debug, Args)]
+#[derive(Clone, Debug, Args)]
 pub struct ProxyOpts {
     #[arg(long)]
     proxy: Option<String>,
     #[arg(long, default_value = "9900")]
     port: u16,
+    /// PEM file with CA certificates for verifying the session server, enables TLS.
+    #[arg(long)]
+    proxy_ca: Option<PathBuf>,
+    /// PEM file with the certificate chain by which to authenticate to the session server.
+    #[arg(long, requires = "proxy_key")]
+    proxy_cert: Option<PathBuf>,
+    /// PEM file with the private key of `--proxy-cert`.
+    #[arg(long, requires = "proxy_cert")]
+    proxy_key: Option<PathBuf>,
+    /// Client name under which to authenticate to the session server by pre-shared key,
+    /// enables TLS.
+    #[arg(long, requires = "proxy_psk_file")]
+    proxy_psk_identity: Option<String>,
+    /// File containing the hex-encoded pre-shared key.
+    #[arg(long, requires = "proxy_psk_identity")]
+    proxy_psk_file: Option<PathBuf>,
+    /// Resources of the session to lease for exclusive use by this process, e.g. `device` or
+    /// `gpio:RESET,spi:BOOTSTRAP`.
+    #[arg(long, value_delimiter = ',')]
+    proxy_lease: Vec<LeaseResource>,
+    /// Maximum duration of the lease, which otherwise ends when this process exits.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1h")]
+    proxy_lease_duration: Duration,
+    /// How long to wait for resources leased by other clients to become available.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    proxy_lease_wait: Duration,
+}
+
+impl ProxyOpts {
+    fn tls_config(&self) -> Result<Option<TlsClientConfig>> {
+        if self.proxy_ca.is_none() && self.proxy_cert.is_none() && self.proxy_psk_identity.is_none()
+        {
+            return Ok(None);
+        }
+        let ca = match &self.proxy_ca {
+            Some(path) => read_certificates(path)?,
+            None => Vec::new(),
+        };
+        let identity = match (&self.proxy_cert, &self.proxy_key) {
+            (Some(cert), Some(key)) => Some(TlsIdentity::read_from_files(cert, key)?),
+            _ => None,
+        };
+        let psk = match (&self.proxy_psk_identity, &self.proxy_psk_file) {
+            (Some(name), Some(path)) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to open {path:?}"))?;
+                let key = hex::decode(text.trim())
+                    .with_context(|| format!("Invalid pre-shared key in {path:?}"))?;
+                Some((name.clone(), key))
+            }
+            _ => None,
+        };
+        Ok(Some(TlsClientConfig { ca, identity, psk }))
+    }
+
+    /// Connects to the session listening on `port` of the `--proxy` host, using TLS if any of
+    /// the TLS options were given.
+    pub fn open(&self, port: u16) -> Result<Proxy> {
+        match self.tls_config()? {
+            Some(tls) => Proxy::open_tls(self.proxy.as_deref(), port, &tls),
+            None => Proxy::open(self.proxy.as_deref(), port),
+        }
+    }
 }
 
 pub fn create(args: &ProxyOpts) -> Result<Box<dyn Transport>> {
-    Ok(Box::new(Proxy::open(args.proxy.as_deref(), args.port)?))
+    let proxy = args.open(args.port)?;
+    if !args.proxy_lease.is_empty() {
+        lease::acquire_waiting(
+            &*proxy.proxy_ops()?,
+            &args.proxy_lease,
+            args.proxy_lease_duration,
+            args.proxy_lease_wait,
+        )?;
+    }
+    Ok(Box::new(proxy))
 }
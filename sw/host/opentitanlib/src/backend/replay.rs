@@ -0,0 +1,25 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{Context, Result};
+use clap::Args;
+use std::path::PathBuf;
+
+use crate::transport::replay::Replay;
+use crate::transport::Transport;
+
+#[derive(Clone, Debug, Args)]
+pub struct ReplayOpts {
+    /// Recording made with `--record`, to be served by the `replay` interface.
+    #[arg(long)]
+    replay_file: Option<PathBuf>,
+}
+
+pub fn create(args: &ReplayOpts) -> Result<Box<dyn Transport>> {
+    let path = args
+        .replay_file
+        .as_ref()
+        .context("The replay interface requires --replay-file")?;
+    Ok(Box::new(Replay::open(path)?))
+}
@@ -9,7 +9,7 @@ use clap::Args;
 use std::path::PathBuf;
 use std::str::FromStr;
 
-#[derive(Debug, Args)]
+#[derive(Clone, Debug, Args)]
 pub struct Ti50EmulatorOpts {
     #[arg(long, default_value = "ti50")]
     instance_prefix: String,
@@ -10,7 +10,7 @@ use std::time::Duration;
 use crate::transport::verilator::{Options, Verilator};
 use crate::transport::Transport;
 
-#[derive(Debug, Args)]
+#[derive(Clone, Debug, Args)]
 pub struct VerilatorOpts {
     #[arg(long, default_value_t)]
     verilator_bin: String,
@@ -88,6 +88,16 @@ impl<T: Dmi> Dmi for &mut T {
     }
 }
 
+impl<T: Dmi + ?Sized> Dmi for Box<T> {
+    fn dmi_read(&mut self, addr: u32) -> Result<u32> {
+        T::dmi_read(self, addr)
+    }
+
+    fn dmi_write(&mut self, addr: u32, data: u32) -> Result<()> {
+        T::dmi_write(self, addr, data)
+    }
+}
+
 /// DMI interface via OpenOCD.
 pub struct OpenOcdDmi {
     openocd: OpenOcd,
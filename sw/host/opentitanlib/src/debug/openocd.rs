@@ -17,9 +17,10 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::debug::dmi::{Dmi, OpenOcdDmi};
 use crate::dif::lc_ctrl::LcCtrlReg;
 use crate::impl_serializable_error;
-use crate::io::jtag::{Jtag, JtagChain, JtagError, JtagParams, JtagTap, RiscvReg};
+use crate::io::jtag::{Jtag, JtagChain, JtagError, JtagParams, JtagTap, RiscvReg, TargetState};
 use crate::util::parse_int::ParseInt;
 use crate::util::printer;
 
@@ -253,6 +254,9 @@ pub enum OpenOcdError {
 impl_serializable_error!(OpenOcdError);
 
 impl OpenOcdJtagChain {
+    /// IDCODE of the RISC-V TAP, needs to match util/openocd/target.
+    const RISCV_TAP_IDCODE: u32 = 0x10001cdf;
+
     /// Start OpenOCD with given JTAG options but do not connect any TAP.
     pub fn new(adapter_command: &str, opts: &JtagParams) -> Result<OpenOcdJtagChain> {
         let mut openocd = OpenOcd::spawn(&opts.openocd, opts.log_stdio)?;
@@ -287,6 +291,46 @@ impl JtagChain for OpenOcdJtagChain {
         }))
     }
 
+    fn connect_dmi(mut self: Box<Self>, tap: JtagTap) -> Result<Box<dyn Dmi>> {
+        // Only the RISC-V TAP is a debug transport module.  Declare the TAP but no target, as
+        // OpenOCD would otherwise access the DMI registers on its own.
+        ensure!(matches!(tap, JtagTap::RiscvTap), JtagError::Tap(tap));
+        let resp = self.openocd.execute(&format!(
+            "jtag newtap riscv tap -irlen 5 -expected-id {:#x}",
+            Self::RISCV_TAP_IDCODE
+        ))?;
+        ensure!(resp.is_empty(), OpenOcdError::InitializeFailure(resp));
+        let resp = self.openocd.execute("capture init")?;
+        if resp.contains("JTAG scan chain interrogation failed") {
+            bail!(OpenOcdError::InitializeFailure(resp));
+        }
+        Ok(Box::new(OpenOcdDmi::new(self.openocd, "riscv.tap")?))
+    }
+
+    fn scan_idcodes(mut self: Box<Self>) -> Result<Vec<u32>> {
+        static IDCODE_REGEX: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"tap/device found: 0x([0-9A-Fa-f]+) \(").unwrap());
+        // Without any TAP declared, OpenOCD reports each device it finds during initialization.
+        let resp = self.openocd.execute("capture \"jtag init\"")?;
+        let idcodes = if resp.contains("JTAG scan chain interrogation failed") {
+            Vec::new()
+        } else {
+            let idcodes = IDCODE_REGEX
+                .captures_iter(&resp)
+                .map(|c| Ok(u32::from_str_radix(&c[1], 16)?))
+                .collect::<Result<Vec<u32>>>()?;
+            ensure!(
+                !idcodes.is_empty(),
+                OpenOcdError::InitializeFailure(format!(
+                    "Failed to parse IDCODE from OpenOCD output: {resp}"
+                ))
+            );
+            idcodes
+        };
+        self.openocd.shutdown()?;
+        Ok(idcodes)
+    }
+
     fn into_raw(self: Box<Self>) -> Result<OpenOcd> {
         Ok(self.openocd)
     }
@@ -431,6 +475,17 @@ impl Jtag for OpenOcdJtagTap {
         Ok(())
     }
 
+    fn target_state(&mut self) -> Result<TargetState> {
+        ensure!(
+            matches!(self.jtag_tap, JtagTap::RiscvTap),
+            JtagError::Tap(self.jtag_tap)
+        );
+        let response = self.send_tcl_cmd("$_TARGETNAME.0 curstate")?;
+        response
+            .parse()
+            .with_context(|| format!("unexpected target state: '{response}'"))
+    }
+
     fn read_memory(&mut self, addr: u32, buf: &mut [u8]) -> Result<usize> {
         ensure!(
             matches!(self.jtag_tap, JtagTap::RiscvTap),
@@ -2,7 +2,9 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{bail, Result};
+use std::str::FromStr;
+
+use anyhow::{bail, ensure, Error, Result};
 use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
 
@@ -37,6 +39,33 @@ with_unknown! {
     }
 }
 
+/// Kinds of tokens authorizing conditional life cycle transitions.
+#[derive(
+    clap::ValueEnum,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    strum::Display,
+    strum::EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum DifLcCtrlTokenKind {
+    /// Unlocks a device in the RAW state.
+    RawUnlock,
+    /// Unlocks a device in one of the TEST_LOCKED states.
+    TestUnlock,
+    /// Moves a device out of the TEST states into a mission mode state.
+    TestExit,
+    /// Moves a device in the DEV or PROD state into RMA.
+    Rma,
+}
+
 pub struct DifLcCtrlTransCheck {
     /// Whether the transition is valid.
     pub valid: bool,
@@ -277,6 +306,22 @@ impl DifLcCtrlState {
             },
         }
     }
+
+    /// Returns the kind of token required for the transition to `target`, or `None` if the
+    /// transition is invalid or unconditional.
+    pub fn transition_token(self, target: DifLcCtrlState) -> Option<DifLcCtrlTokenKind> {
+        if !self.check_transition(target).token {
+            return None;
+        }
+        Some(match (self, target) {
+            (DifLcCtrlState::Raw, _) => DifLcCtrlTokenKind::RawUnlock,
+            (_, DifLcCtrlState::Rma) => DifLcCtrlTokenKind::Rma,
+            (_, DifLcCtrlState::Dev | DifLcCtrlState::Prod | DifLcCtrlState::ProdEnd) => {
+                DifLcCtrlTokenKind::TestExit
+            }
+            _ => DifLcCtrlTokenKind::TestUnlock,
+        })
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -340,7 +385,32 @@ impl DifLcCtrlToken {
     }
 }
 
-#[derive(clap::ValueEnum, Clone, Copy, Debug, strum::EnumString)]
+impl FromStr for DifLcCtrlToken {
+    type Err = Error;
+
+    /// Parses a token from a hexstring, optionally prefixed with `0x` and separated with `_`.
+    /// Each group of eight digits is one of the [LcCtrlReg::TransitionToken0] to
+    /// [LcCtrlReg::TransitionToken3] register values, in that order.
+    fn from_str(token: &str) -> Result<Self> {
+        let hex_str_no_sep = token.replace('_', "");
+        let sanitized_hex_str = hex_str_no_sep
+            .strip_prefix("0x")
+            .unwrap_or(hex_str_no_sep.as_str());
+        let token_bytes = hex::decode(sanitized_hex_str)?;
+        let length = token_bytes.len();
+        ensure!(
+            length == 16,
+            "Expected a token of length 16-bytes but it was {length}-bytes."
+        );
+        let token_words = token_bytes
+            .chunks(4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<u32>>();
+        Ok(DifLcCtrlToken::from(token_words))
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, strum::EnumString, Serialize, Deserialize)]
 #[strum(serialize_all = "snake_case")]
 #[repr(u32)]
 pub enum LcCtrlReg {
@@ -483,6 +553,55 @@ mod tests {
         assert_eq!(words, [0x04030201, 0x14131211, 0x24232221, 0x34333231]);
     }
 
+    #[test]
+    fn lc_ctrl_token_from_str() -> Result<()> {
+        let token = DifLcCtrlToken::from_str("0x04030201_14131211_24232221_34333231")?;
+        assert_eq!(
+            token.into_register_values(),
+            [0x04030201, 0x14131211, 0x24232221, 0x34333231]
+        );
+        assert!(DifLcCtrlToken::from_str("00000000000000000000000000000000")?.is_zero());
+        assert!(DifLcCtrlToken::from_str("0x0102").is_err());
+        assert!(DifLcCtrlToken::from_str("0xnothex").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn lc_ctrl_transition_token() {
+        for (from, to, token) in [
+            (
+                DifLcCtrlState::Raw,
+                DifLcCtrlState::TestUnlocked0,
+                Some(DifLcCtrlTokenKind::RawUnlock),
+            ),
+            (DifLcCtrlState::Raw, DifLcCtrlState::Scrap, None),
+            (
+                DifLcCtrlState::TestLocked0,
+                DifLcCtrlState::TestUnlocked1,
+                Some(DifLcCtrlTokenKind::TestUnlock),
+            ),
+            (
+                DifLcCtrlState::TestUnlocked0,
+                DifLcCtrlState::TestLocked0,
+                None,
+            ),
+            (
+                DifLcCtrlState::TestUnlocked3,
+                DifLcCtrlState::Prod,
+                Some(DifLcCtrlTokenKind::TestExit),
+            ),
+            (DifLcCtrlState::TestUnlocked3, DifLcCtrlState::Rma, None),
+            (
+                DifLcCtrlState::Dev,
+                DifLcCtrlState::Rma,
+                Some(DifLcCtrlTokenKind::Rma),
+            ),
+            (DifLcCtrlState::Prod, DifLcCtrlState::Dev, None),
+        ] {
+            assert_eq!(from.transition_token(to), token, "{from} -> {to}");
+        }
+    }
+
     #[test]
     fn lc_ctrl_register_offsets() {
         let offset = bindgen::dif::LC_CTRL_LC_STATE_REG_OFFSET;
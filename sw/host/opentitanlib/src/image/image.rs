@@ -60,6 +60,12 @@ pub struct SpxSignatureParams {
     signature: [u8; 7856],
 }
 
+impl SpxSignatureParams {
+    pub fn key(&self) -> &SpxPublicKey {
+        &self.key
+    }
+}
+
 // Binary image is signed either RSA or ECDSA. SPX+ signature could be added as
 // an extension.
 pub struct SigverifyParams {
@@ -576,8 +582,15 @@ impl Image {
     where
         F: FnOnce(&[u8]) -> R,
     {
-        Ok(f(&self.data.bytes[offset_of!(Manifest, usage_constraints)
-            ..self.borrow_manifest()?.signed_region_end as usize]))
+        let region = self
+            .data
+            .bytes
+            .get(
+                offset_of!(Manifest, usage_constraints)
+                    ..self.borrow_manifest()?.signed_region_end as usize,
+            )
+            .ok_or(ImageError::Parse)?;
+        Ok(f(region))
     }
 
     /// Compute the SHA256 digest for the signed portion of the `Image`.
@@ -8,3 +8,5 @@ pub mod image;
 pub mod manifest;
 pub mod manifest_def;
 pub mod manifest_ext;
+pub mod policy;
+pub mod signing_bundle;
@@ -0,0 +1,398 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checking of image manifests against a fleet policy.
+//!
+//! `Image::manifest_sanity_check` only verifies that a manifest is self-consistent.  A policy
+//! expresses additional constraints on images which may be signed for a particular fleet, e.g.:
+//!
+//! ```hjson
+//! {
+//!   kinds: ["RomExt"],
+//!   security_version: { min: 1, max: 4 },
+//!   required_extensions: ["spx_key", "spx_signature"],
+//!   usage_constraints: {
+//!     allowed_selector_bits: "0x400",
+//!     required_selector_bits: "0x400",
+//!     life_cycle_states: ["0x3a4e5b1d"],
+//!   },
+//!   code_regions: [{ start: "0x400", end: "0x10000" }],
+//!   key_ids: ["0x7a1b99e8"],
+//! }
+//! ```
+//!
+//! Omitted fields impose no constraint.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_annotate::Annotate;
+use std::path::Path;
+
+use crate::image::image::Image;
+use crate::image::manifest::{
+    Manifest, ManifestKind, MANIFEST_USAGE_CONSTRAINT_UNSELECTED_WORD_VAL,
+};
+use crate::image::manifest_ext::ManifestExtId;
+use crate::util::num_de::HexEncoded;
+
+/// Selector bit enabling the life cycle state usage constraint.
+const SELECTOR_LIFE_CYCLE_STATE: u32 = 1 << 10;
+
+/// Inclusive range of permitted values.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VersionRange {
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+}
+
+/// Range of offsets within the image, `end` being exclusive.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AddressRange {
+    pub start: HexEncoded<u32>,
+    pub end: HexEncoded<u32>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsageConstraintsPolicy {
+    /// Selector bits which may be set, absent means any.
+    pub allowed_selector_bits: Option<HexEncoded<u32>>,
+    /// Selector bits which must be set.
+    pub required_selector_bits: Option<HexEncoded<u32>>,
+    /// Permitted values of the life cycle state constraint, when selected.
+    pub life_cycle_states: Vec<HexEncoded<u32>>,
+}
+
+/// Fleet policy for image manifests, usually read from an HJSON file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ManifestPolicy {
+    /// Permitted kinds of images.
+    pub kinds: Vec<ManifestKind>,
+    pub security_version: Option<VersionRange>,
+    /// Extensions which must be present in every manifest.
+    pub required_extensions: Vec<ManifestExtId>,
+    pub usage_constraints: Option<UsageConstraintsPolicy>,
+    /// The code region of each manifest must be contained within one of these.
+    pub code_regions: Vec<AddressRange>,
+    /// Permitted IDs of the key used to sign images.
+    pub key_ids: Vec<HexEncoded<u32>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Annotate, strum::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum PolicyRule {
+    Kind,
+    SecurityVersion,
+    RequiredExtension,
+    UsageConstraints,
+    CodeRegion,
+    EntryPoint,
+    KeyId,
+}
+
+/// A single way in which a manifest fails to comply with the policy.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct Violation {
+    /// Offset of the offending manifest within the image file.
+    #[annotate(format = hex)]
+    pub offset: usize,
+    #[annotate(format = hex)]
+    pub kind: ManifestKind,
+    pub rule: PolicyRule,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at {:#x}: {}: {}",
+            self.kind, self.offset, self.rule, self.message
+        )
+    }
+}
+
+/// Returns the ID of the key used to sign the image, which is the least significant word of the
+/// public key, as computed by the ROM.
+pub fn key_id(manifest: &Manifest) -> u32 {
+    manifest.pub_key.data[0]
+}
+
+impl ManifestPolicy {
+    pub fn read_from_file(path: &Path) -> Result<ManifestPolicy> {
+        Ok(deser_hjson::from_str(
+            &std::fs::read_to_string(path).with_context(|| format!("Failed to open {path:?}"))?,
+        )?)
+    }
+
+    /// Checks every manifest found in the image against the policy, returning the list of
+    /// violations.
+    pub fn check(&self, image: &Image) -> Result<Vec<Violation>> {
+        let subimages = image.subimages()?;
+        let mut violations = Vec::new();
+        if subimages.is_empty() {
+            violations.push(Violation {
+                offset: 0,
+                kind: ManifestKind(image.borrow_manifest()?.identifier),
+                rule: PolicyRule::Kind,
+                message: "No manifest of a known kind found".into(),
+            });
+        }
+        for subimage in subimages {
+            self.check_manifest(
+                subimage.manifest,
+                &mut |rule: PolicyRule, message: String| {
+                    violations.push(Violation {
+                        offset: subimage.offset,
+                        kind: subimage.kind,
+                        rule,
+                        message,
+                    })
+                },
+            );
+        }
+        Ok(violations)
+    }
+
+    fn check_manifest(&self, manifest: &Manifest, report: &mut dyn FnMut(PolicyRule, String)) {
+        let kind = ManifestKind(manifest.identifier);
+        if !self.kinds.is_empty() && !self.kinds.contains(&kind) {
+            report(
+                PolicyRule::Kind,
+                format!("Image kind {} not permitted", kind),
+            );
+        }
+
+        if let Some(range) = &self.security_version {
+            let version = manifest.security_version;
+            if range.min.is_some_and(|min| version < min)
+                || range.max.is_some_and(|max| version > max)
+            {
+                report(
+                    PolicyRule::SecurityVersion,
+                    format!(
+                        "Security version {} outside of permitted range {:?}..={:?}",
+                        version, range.min, range.max
+                    ),
+                );
+            }
+        }
+
+        for &id in &self.required_extensions {
+            let present = manifest
+                .extensions
+                .entries
+                .iter()
+                .any(|e| e.identifier == u32::from(id) && e.offset != 0);
+            if !present {
+                report(
+                    PolicyRule::RequiredExtension,
+                    format!("Missing extension {}", id),
+                );
+            }
+        }
+
+        if let Some(policy) = &self.usage_constraints {
+            self.check_usage_constraints(policy, manifest, report);
+        }
+
+        if !self.code_regions.is_empty()
+            && !self
+                .code_regions
+                .iter()
+                .any(|r| *r.start <= manifest.code_start && manifest.code_end <= *r.end)
+        {
+            report(
+                PolicyRule::CodeRegion,
+                format!(
+                    "Code region {:#x}..{:#x} outside of permitted ranges",
+                    manifest.code_start, manifest.code_end
+                ),
+            );
+        }
+        if !(manifest.code_start..manifest.code_end).contains(&manifest.entry_point) {
+            report(
+                PolicyRule::EntryPoint,
+                format!(
+                    "Entry point {:#x} outside of code region {:#x}..{:#x}",
+                    manifest.entry_point, manifest.code_start, manifest.code_end
+                ),
+            );
+        }
+
+        if !self.key_ids.is_empty() && !self.key_ids.iter().any(|id| **id == key_id(manifest)) {
+            report(
+                PolicyRule::KeyId,
+                format!("Key ID {:#010x} not permitted", key_id(manifest)),
+            );
+        }
+    }
+
+    fn check_usage_constraints(
+        &self,
+        policy: &UsageConstraintsPolicy,
+        manifest: &Manifest,
+        report: &mut dyn FnMut(PolicyRule, String),
+    ) {
+        let constraints = &manifest.usage_constraints;
+        let selector = constraints.selector_bits;
+        if let Some(allowed) = &policy.allowed_selector_bits {
+            if selector & !**allowed != 0 {
+                report(
+                    PolicyRule::UsageConstraints,
+                    format!("Selector bits {:#x} not permitted", selector & !**allowed),
+                );
+            }
+        }
+        if let Some(required) = &policy.required_selector_bits {
+            if !selector & **required != 0 {
+                report(
+                    PolicyRule::UsageConstraints,
+                    format!("Selector bits {:#x} required", !selector & **required),
+                );
+            }
+        }
+        if selector & SELECTOR_LIFE_CYCLE_STATE != 0
+            && !policy.life_cycle_states.is_empty()
+            && !policy
+                .life_cycle_states
+                .iter()
+                .any(|state| **state == constraints.life_cycle_state)
+        {
+            report(
+                PolicyRule::UsageConstraints,
+                format!(
+                    "Life cycle state {:#010x} not permitted",
+                    constraints.life_cycle_state
+                ),
+            );
+        }
+        // Unselected words are required to hold a fixed value, since they are still covered by
+        // the signature.
+        let unselected = constraints
+            .device_id
+            .device_id
+            .iter()
+            .chain([
+                &constraints.manuf_state_creator,
+                &constraints.manuf_state_owner,
+                &constraints.life_cycle_state,
+            ])
+            .enumerate()
+            .filter(|&(bit, &word)| {
+                selector & (1 << bit) == 0 && word != MANIFEST_USAGE_CONSTRAINT_UNSELECTED_WORD_VAL
+            })
+            .map(|(bit, _)| bit)
+            .collect::<Vec<_>>();
+        if !unselected.is_empty() {
+            report(
+                PolicyRule::UsageConstraints,
+                format!(
+                    "Unselected constraint words {:?} do not hold {:#x}",
+                    unselected, MANIFEST_USAGE_CONSTRAINT_UNSELECTED_WORD_VAL
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::manifest::{
+        ManifestUsageConstraints, CHIP_BL0_IDENTIFIER, CHIP_ROM_EXT_IDENTIFIER,
+        MANIFEST_EXT_ID_SPX_KEY,
+    };
+
+    const POLICY: &str = r#"{
+        kinds: ["RomExt"],
+        security_version: { min: 1, max: 4 },
+        required_extensions: ["spx_key"],
+        usage_constraints: {
+            allowed_selector_bits: "0x400",
+            life_cycle_states: ["0x1234"],
+        },
+        code_regions: [{ start: "0x400", end: "0x8000" }],
+        key_ids: ["0x7a1b99e8"],
+    }"#;
+
+    fn image(setup: impl FnOnce(&mut Manifest)) -> Result<Image> {
+        let mut image = Image::default();
+        image.size = 0x8000;
+        let manifest = image.borrow_manifest_mut()?;
+        *manifest = Manifest::default();
+        manifest.identifier = CHIP_ROM_EXT_IDENTIFIER;
+        manifest.length = 0x8000;
+        manifest.security_version = 2;
+        manifest.code_start = 0x400;
+        manifest.code_end = 0x4000;
+        manifest.entry_point = 0x480;
+        manifest.pub_key.data[0] = 0x7a1b99e8;
+        manifest.usage_constraints = ManifestUsageConstraints::default();
+        manifest.extensions.entries[0].identifier = MANIFEST_EXT_ID_SPX_KEY;
+        manifest.extensions.entries[0].offset = 0x7000;
+        setup(manifest);
+        Ok(image)
+    }
+
+    fn rules(violations: &[Violation]) -> Vec<PolicyRule> {
+        violations.iter().map(|v| v.rule).collect()
+    }
+
+    #[test]
+    fn test_compliant() -> Result<()> {
+        let policy: ManifestPolicy = deser_hjson::from_str(POLICY)?;
+        assert_eq!(policy.check(&image(|_| ())?)?, vec![]);
+        // An empty policy only checks the entry point.
+        let policy = ManifestPolicy::default();
+        assert_eq!(policy.check(&image(|_| ())?)?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_violations() -> Result<()> {
+        let policy: ManifestPolicy = deser_hjson::from_str(POLICY)?;
+        let violations = policy.check(&image(|m| {
+            m.security_version = 5;
+            m.extensions.entries[0].offset = 0;
+            m.code_end = 0x9000;
+            m.entry_point = 0x9000;
+            m.pub_key.data[0] = 0x1111;
+        })?)?;
+        assert_eq!(
+            rules(&violations),
+            vec![
+                PolicyRule::SecurityVersion,
+                PolicyRule::RequiredExtension,
+                PolicyRule::CodeRegion,
+                PolicyRule::EntryPoint,
+                PolicyRule::KeyId,
+            ]
+        );
+        assert_eq!(violations[0].offset, 0);
+        assert_eq!(violations[0].kind, ManifestKind::RomExt);
+
+        let violations = policy.check(&image(|m| {
+            m.identifier = CHIP_BL0_IDENTIFIER;
+            m.usage_constraints.selector_bits = SELECTOR_LIFE_CYCLE_STATE | 1;
+            m.usage_constraints.life_cycle_state = 0x5678;
+            m.usage_constraints.manuf_state_owner = 0;
+        })?)?;
+        assert_eq!(
+            rules(&violations),
+            vec![
+                PolicyRule::Kind,
+                PolicyRule::UsageConstraints,
+                PolicyRule::UsageConstraints,
+                PolicyRule::UsageConstraints,
+            ]
+        );
+        assert!(violations[3].message.contains("[9]"));
+        Ok(())
+    }
+}
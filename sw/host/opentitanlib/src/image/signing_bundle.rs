@@ -0,0 +1,222 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Detached signing of images.
+//!
+//! Signing ceremonies are often performed on air-gapped machines which should not need to handle
+//! the image itself.  Instead, a `SigningBundle` holding the data to be signed is exported from
+//! an image whose manifest is otherwise complete (public keys filled in, and space allocated for
+//! the SPX signature extension, as done by `opentitantool image manifest update`).  The signing
+//! ceremony fills in the signatures, after which they are verified and written into the image.
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_annotate::Annotate;
+use sphincsplus::SpxDomain;
+use std::path::Path;
+
+use crate::crypto::ecdsa::EcdsaRawSignature;
+use crate::crypto::rsa::Signature as RsaSignature;
+use crate::image::image::{Image, MainSignatureParams};
+use crate::image::manifest::ManifestKind;
+use crate::image::manifest_ext::ManifestExtEntry;
+use crate::image::policy::key_id;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+pub enum KeyType {
+    Rsa,
+    Ecdsa,
+}
+
+/// Data to be signed with the SPX key.
+#[derive(Clone, Debug, Serialize, Deserialize, Annotate)]
+pub struct SpxSigningRequest {
+    pub domain: SpxDomain,
+    /// SPX public key, as found in the `spx_key` manifest extension.
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub key: Vec<u8>,
+    /// Message to be signed: the signed region of the image, or its little-endian SHA256 digest
+    /// in the `PreHashedSha256` domain.
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub message: Vec<u8>,
+    /// The signature, as filled in by the signer.
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub signature: Vec<u8>,
+}
+
+/// The "to be signed" data of an image, and eventually its signatures.
+#[derive(Clone, Debug, Serialize, Deserialize, Annotate)]
+pub struct SigningBundle {
+    #[annotate(format = hex)]
+    pub kind: ManifestKind,
+    pub key_type: KeyType,
+    /// ID of the main signing key, i.e. the least significant word of the public key.
+    #[annotate(format = hex)]
+    pub key_id: u32,
+    /// Big-endian SHA256 digest of the signed region, to be signed with the main key.
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub digest: Vec<u8>,
+    /// Signature over `digest`, as filled in by the signer: a big-endian RSA signature, or a
+    /// raw ECDSA signature (r followed by s, 64 bytes).
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub signature: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spx: Option<SpxSigningRequest>,
+}
+
+impl SigningBundle {
+    /// Exports the data to be signed from `image`.  An SPX signing request is included if the
+    /// manifest has both the `spx_key` and `spx_signature` extensions.
+    pub fn from_image(image: &Image, domain: SpxDomain) -> Result<Self> {
+        let manifest = image.borrow_manifest()?;
+        let params = image
+            .get_sigverify_params_from_manifest()
+            .context("Failed to read public keys from the manifest")?;
+        let key_type = match params.main_sig_params {
+            MainSignatureParams::Rsa(..) => KeyType::Rsa,
+            MainSignatureParams::Ecdsa(..) => KeyType::Ecdsa,
+        };
+        let digest = image.compute_digest()?;
+        let spx = match &params.spx_sig_params {
+            Some(spx) => Some(SpxSigningRequest {
+                domain,
+                key: spx.key().as_bytes().to_vec(),
+                message: match domain {
+                    SpxDomain::PreHashedSha256 => digest.to_le_bytes(),
+                    SpxDomain::None | SpxDomain::Pure => image.map_signed_region(|b| b.to_vec())?,
+                },
+                signature: Vec::new(),
+            }),
+            None => None,
+        };
+        Ok(SigningBundle {
+            kind: ManifestKind(manifest.identifier),
+            key_type,
+            key_id: key_id(manifest),
+            digest: digest.to_be_bytes(),
+            signature: Vec::new(),
+            spx,
+        })
+    }
+
+    pub fn read_from_file(path: &Path) -> Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+        Ok(serde_annotate::from_str(&text)?)
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> Result<()> {
+        let text = serde_annotate::serialize(self)?.to_json().to_string();
+        std::fs::write(path, text).with_context(|| format!("Failed to write {path:?}"))
+    }
+
+    /// Writes the signatures of the bundle into `image`, and verifies them against the public
+    /// keys of its manifest.  On error, `image` may have been partially updated and should be
+    /// discarded.
+    pub fn apply(&self, image: &mut Image) -> Result<()> {
+        let digest = image.compute_digest()?;
+        ensure!(
+            digest.to_be_bytes() == self.digest,
+            "Signed region of the image does not match the signing bundle"
+        );
+        ensure!(
+            !self.signature.is_empty(),
+            "Signing bundle does not contain a signature"
+        );
+        match self.key_type {
+            KeyType::Rsa => {
+                image.update_rsa_signature(RsaSignature::from_be_bytes(&self.signature)?)?
+            }
+            KeyType::Ecdsa => image
+                .update_ecdsa_signature(EcdsaRawSignature::try_from(self.signature.as_slice())?)?,
+        }
+        if let Some(spx) = &self.spx {
+            ensure!(
+                !spx.signature.is_empty(),
+                "Signing bundle does not contain an SPX signature"
+            );
+            image.add_manifest_extension(ManifestExtEntry::new_spx_signature_entry(
+                &spx.signature,
+            )?)?;
+        }
+
+        let params = image.get_sigverify_params_from_manifest()?;
+        params
+            .verify(&digest)
+            .context("Verification of the main signature failed")?;
+        match (&self.spx, &params.spx_sig_params) {
+            (Some(spx), Some(_)) => image
+                .map_signed_region(|b| params.spx_verify(b, spx.domain))?
+                .context("Verification of the SPX signature failed")?,
+            (Some(_), None) => bail!("Image has no SPX key"),
+            (None, _) => (),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ecdsa::{EcdsaPrivateKey, EcdsaRawPublicKey};
+    use crate::crypto::sha256;
+    use crate::image::manifest::CHIP_MANIFEST_VERSION_MAJOR2;
+    use crate::util::testdata;
+    use std::collections::HashSet;
+
+    /// Returns the test image, prepared for ECDSA signing with a fresh key.
+    fn image(key: &EcdsaPrivateKey) -> Result<Image> {
+        let mut image = Image::read_from_file(&testdata("image/test_image.bin"))?;
+        image.borrow_manifest_mut()?.manifest_version.major = CHIP_MANIFEST_VERSION_MAJOR2;
+        image.update_ecdsa_public_key(EcdsaRawPublicKey::try_from(&key.public_key())?)?;
+        image.update_length()?;
+        image.update_signed_region(&HashSet::new())?;
+        Ok(image)
+    }
+
+    #[test]
+    fn test_detached_signing() -> Result<()> {
+        let key = EcdsaPrivateKey::new();
+        let mut image = image(&key)?;
+        let bundle = SigningBundle::from_image(&image, SpxDomain::default())?;
+        assert_eq!(bundle.key_type, KeyType::Ecdsa);
+        assert!(bundle.spx.is_none());
+
+        // The signer only sees the serialized bundle.
+        let text = serde_annotate::serialize(&bundle)?.to_json().to_string();
+        let mut signed: SigningBundle = serde_annotate::from_str(&text)?;
+        let digest = sha256::Sha256Digest::from_be_bytes(&signed.digest)?;
+        signed.signature = key.sign(&digest)?.to_vec()?;
+
+        // A bundle without signature is rejected.
+        assert!(bundle.apply(&mut image).is_err());
+        signed.apply(&mut image)?;
+        image
+            .get_sigverify_params_from_manifest()?
+            .verify(&image.compute_digest()?)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_signature() -> Result<()> {
+        let key = EcdsaPrivateKey::new();
+        let mut image = image(&key)?;
+        let mut bundle = SigningBundle::from_image(&image, SpxDomain::default())?;
+        let other = EcdsaPrivateKey::new();
+        let digest = sha256::Sha256Digest::from_be_bytes(&bundle.digest)?;
+        bundle.signature = other.sign(&digest)?.to_vec()?;
+        assert!(bundle.apply(&mut image).is_err());
+
+        // Signatures for another image are rejected before touching the image.
+        bundle.digest[0] ^= 1;
+        let err = bundle.apply(&mut image).unwrap_err();
+        assert!(err.to_string().contains("does not match"));
+        Ok(())
+    }
+}
@@ -182,6 +182,21 @@ pub const MODE_444: Mode = Mode {
     double_transfer_rate: false,
 };
 
+/// Single-wire address, octo-wire data
+pub const MODE_118: Mode = Mode {
+    dummy_cycles: 0,
+    switch: Switch::Mode11N,
+    width: DataWidth::Octo,
+    double_transfer_rate: false,
+};
+
+pub const MODE_188: Mode = Mode {
+    dummy_cycles: 0,
+    switch: Switch::Mode1NN,
+    width: DataWidth::Octo,
+    double_transfer_rate: false,
+};
+
 impl Cmd {
     /// Method use to get binary representation of the command for use on "plain" SPI.  Will be
     /// used in cases where the transport backend does not have specialied EEPROM/Flash
@@ -11,11 +11,12 @@ use std::path::PathBuf;
 use std::time::Duration;
 
 use crate::app::TransportWrapper;
+use crate::debug::dmi::Dmi;
 use crate::debug::openocd::OpenOcd;
 use crate::dif::lc_ctrl::LcCtrlReg;
 use crate::impl_serializable_error;
 
-#[derive(Debug, Args, Clone)]
+#[derive(Debug, Args, Clone, Serialize, Deserialize)]
 pub struct JtagParams {
     /// OpenOCD binary path.
     #[arg(long, default_value = "openocd")]
@@ -58,6 +59,15 @@ pub trait JtagChain {
     /// Connect to the given JTAG TAP on this chain.
     fn connect(self: Box<Self>, tap: JtagTap) -> Result<Box<dyn Jtag>>;
 
+    /// Connect to the debug module interface behind the given JTAG TAP, without setting up a
+    /// debug target, such that nothing but the caller accesses the DMI registers.
+    fn connect_dmi(self: Box<Self>, tap: JtagTap) -> Result<Box<dyn Dmi>>;
+
+    /// Scan the chain without declaring any TAP, returning the IDCODEs of the devices found.
+    /// The list is empty only if no device responds at all; output that reports devices but
+    /// cannot be parsed is an error.
+    fn scan_idcodes(self: Box<Self>) -> Result<Vec<u32>>;
+
     /// Stop further setup and returns raw OpenOCD instance.
     fn into_raw(self: Box<Self>) -> Result<OpenOcd>;
 }
@@ -81,6 +91,9 @@ pub trait Jtag {
     /// Write a value to a lifecycle controller register.
     fn write_lc_ctrl_reg(&mut self, reg: &LcCtrlReg, value: u32) -> Result<()>;
 
+    /// Get the execution state of the target, as last observed by the debugger.
+    fn target_state(&mut self) -> Result<TargetState>;
+
     /// Read bytes/words from memory into the provided buffer.
     /// When reading bytes, each memory access is 8 bits.
     /// When reading words, each memory access is 32 bit. If the hardware
@@ -134,6 +147,17 @@ pub enum JtagTap {
     LcTap,
 }
 
+/// Execution state of a debug target.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum TargetState {
+    Unknown,
+    Running,
+    Halted,
+    Reset,
+    DebugRunning,
+}
+
 /// List of RISC-V general purpose registers
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, strum::IntoStaticStr)]
 #[strum(serialize_all = "lowercase")]
@@ -243,6 +243,18 @@ pub trait Target {
         eeprom::default_run_eeprom_transactions(self, transactions)
     }
 
+    /// Computes the SHA-256 digest of `length` bytes of EEPROM/FLASH contents starting at
+    /// `address`, without transferring the data to the host.  Returns `None` if the target is
+    /// not able to do so, in which case callers should read back the data instead.
+    fn eeprom_sha256(
+        &self,
+        _address: u32,
+        _length: u32,
+        _address_mode: eeprom::AddressMode,
+    ) -> Result<Option<[u8; 32]>> {
+        Ok(None)
+    }
+
     /// Assert the CS signal.  Uses reference counting, will be deasserted when each and every
     /// returned `AssertChipSelect` object have gone out of scope.
     fn assert_cs(self: Rc<Self>) -> Result<AssertChipSelect>;
@@ -2,11 +2,30 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use num_bigint_dig::BigUint;
+use num_traits::ToPrimitive;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
+use crate::util::num_de::{DecEncoded, DeferredValue};
+use crate::util::secure_prng::SecurePrng;
+
+/// Seed diversification constant of `util/design/lib/LcStEnc.py`.
+const LC_SEED_DIVERSIFIER: u128 = 1939944205722120255;
+
+/// The state types of the life cycle encoding, in the order in which their words are generated,
+/// with the letters referring to the first and second word of each word pair.
+const LC_STATE_TYPES: [(&str, char, char); 5] = [
+    ("lc_state", 'A', 'B'),
+    ("lc_cnt", 'C', 'D'),
+    ("soc_dbg_state", 'E', 'F'),
+    ("ownership_state", 'G', 'H'),
+    ("auth_state", 'I', 'J'),
+];
+
 /// SECDED matrix used for ECC in OTP.
 #[derive(Deserialize, Debug)]
 pub struct LcSecded {
@@ -18,10 +37,59 @@ pub struct LcSecded {
     ecc_matrix: Vec<Vec<u8>>,
 }
 
+/// A token of the life cycle controller, such as the unlock token of the RAW state.
+#[derive(Deserialize, Debug)]
+pub struct LcToken {
+    pub name: String,
+    pub value: DeferredValue,
+}
+
+/// Assignment of words to each state of a state type, such as `DEV: ['B0', 'B1', 'A2']`.
+type LcStateTable = BTreeMap<String, Vec<String>>;
+
 /// The internal representation of lc_ctrl_state, used in OTP operations.
 #[derive(Deserialize, Debug)]
 pub struct LcState {
     secded: LcSecded,
+    #[serde(default)]
+    seed: Option<DecEncoded<BigUint>>,
+    #[serde(default)]
+    min_hw: u32,
+    #[serde(default)]
+    max_hw: u32,
+    #[serde(default)]
+    min_hd: u32,
+    #[serde(default = "default_token_size")]
+    token_size: usize,
+    #[serde(default)]
+    tokens: Vec<LcToken>,
+    #[serde(default)]
+    lc_state: LcStateTable,
+    #[serde(default)]
+    lc_cnt: LcStateTable,
+    #[serde(default)]
+    soc_dbg_state: LcStateTable,
+    #[serde(default)]
+    ownership_state: LcStateTable,
+    #[serde(default)]
+    auth_state: LcStateTable,
+}
+
+fn default_token_size() -> usize {
+    128
+}
+
+/// The life cycle encoding generated from an `LcState` definition.
+///
+/// This reproduces the netlist constants generated by `util/design/lib/LcStEnc.py` from the same
+/// seed: each word of a state type is a pair of ECC codewords, where the second codeword can be
+/// programmed on top of the first one.
+pub struct LcStateEncoding {
+    state: LcState,
+    /// Values of the tokens, with `<random>` tokens drawn from the seed.
+    tokens: Vec<(String, Vec<u8>)>,
+    /// Codeword pairs of each state type, in the order of `LC_STATE_TYPES`.
+    words: Vec<Vec<(u64, u64)>>,
 }
 
 #[repr(u32)]
@@ -36,12 +104,7 @@ pub enum LcStateVal {
 
 impl LcSecded {
     pub fn new(in_file: &Path) -> Result<LcSecded> {
-        let json_text = fs::read_to_string(in_file)?;
-        let res: LcState = deser_hjson::from_str(&json_text)?;
-        if res.secded.ecc_matrix.len() != res.secded.ecc_width {
-            bail!("Bad ecc matrix length {}", res.secded.ecc_matrix.len());
-        }
-        Ok(res.secded)
+        Ok(LcState::from_file(in_file)?.secded)
     }
 
     fn bit_index(data: &[u8], index: usize) -> bool {
@@ -78,6 +141,234 @@ impl LcSecded {
             (self.ecc_width - 1) / 8 + 1
         }
     }
+
+    /// The number of bits of data covered by ECC.
+    pub fn data_width(&self) -> usize {
+        self.data_width
+    }
+
+    /// The number of ECC bits.
+    pub fn ecc_width(&self) -> usize {
+        self.ecc_width
+    }
+
+    /// Computes the codeword of `data`, with the ECC bits above the data bits.
+    pub fn ecc_encode_word(&self, data: u64) -> Result<u64> {
+        ensure!(
+            self.data_width + self.ecc_width <= 64,
+            "Codewords of {} bits are not supported",
+            self.data_width + self.ecc_width
+        );
+        let data_len = self.data_width / 8;
+        let mut word = [0u8; 8];
+        let codeword = self.ecc_encode(data.to_le_bytes()[..data_len].to_vec())?;
+        word[..codeword.len()].copy_from_slice(&codeword);
+        Ok(u64::from_le_bytes(word))
+    }
+}
+
+impl LcState {
+    pub fn from_file(in_file: &Path) -> Result<LcState> {
+        let json_text = fs::read_to_string(in_file)
+            .with_context(|| format!("Failed to read {}", in_file.display()))?;
+        let res: LcState = deser_hjson::from_str(&json_text)?;
+        if res.secded.ecc_matrix.len() != res.secded.ecc_width {
+            bail!("Bad ecc matrix length {}", res.secded.ecc_matrix.len());
+        }
+        Ok(res)
+    }
+
+    pub fn secded(&self) -> &LcSecded {
+        &self.secded
+    }
+
+    fn table(&self, typ: &str) -> Option<&LcStateTable> {
+        match typ {
+            "lc_state" => Some(&self.lc_state),
+            "lc_cnt" => Some(&self.lc_cnt),
+            "soc_dbg_state" => Some(&self.soc_dbg_state),
+            "ownership_state" => Some(&self.ownership_state),
+            "auth_state" => Some(&self.auth_state),
+            _ => None,
+        }
+    }
+
+    /// Checks that all states of `typ` are made up of the same number of valid entries, and
+    /// returns that number.
+    fn num_words(&self, typ: &str, first: char, second: char) -> Result<usize> {
+        let table = self.table(typ).unwrap();
+        let num_words = table.values().next().map(Vec::len).unwrap_or(0);
+        for (state, entries) in table {
+            ensure!(
+                entries.len() == num_words,
+                "{typ} entry {state} has incorrect length {}",
+                entries.len()
+            );
+            for (j, entry) in entries.iter().enumerate() {
+                ensure!(
+                    entry == "0"
+                        || *entry == format!("{first}{j}")
+                        || *entry == format!("{second}{j}"),
+                    "Illegal entry \"{entry}\" found in {state} of {typ}"
+                );
+            }
+        }
+        Ok(num_words)
+    }
+}
+
+impl LcStateEncoding {
+    /// Generates the encoding, using `seed` instead of the seed of the definition if given.
+    pub fn generate(state: LcState, seed: Option<&BigUint>) -> Result<LcStateEncoding> {
+        let seed = match (seed, &state.seed) {
+            (Some(seed), _) => seed.clone(),
+            (None, Some(seed)) => (**seed).clone(),
+            (None, None) => bail!("Missing seed in life cycle definition"),
+        };
+        let total_width = (state.secded.data_width + state.secded.ecc_width) as u32;
+        ensure!(
+            state.secded.data_width % 8 == 0 && total_width <= 64,
+            "SECDED data width must be a multiple of 8, and codewords at most 64 bits"
+        );
+        ensure!(
+            state.min_hw < total_width
+                && state.max_hw <= total_width
+                && state.min_hw < state.max_hw,
+            "Hamming weight constraints are inconsistent"
+        );
+        ensure!(
+            state.max_hw - state.min_hw + 1 >= state.min_hd,
+            "Hamming distance constraint is inconsistent"
+        );
+        ensure!(
+            state.token_size % 8 == 0,
+            "Size of tokens {} must be byte aligned",
+            state.token_size
+        );
+
+        let mut prng = SecurePrng::new(&(BigUint::from(LC_SEED_DIVERSIFIER) + seed))?;
+        let mut tokens = Vec::with_capacity(state.tokens.len());
+        for token in &state.tokens {
+            let value = token.value.resolve(state.token_size / 8, &mut prng);
+            tokens.push((token.name.clone(), value));
+        }
+
+        let mut encoding = LcStateEncoding {
+            state,
+            tokens,
+            words: Vec::with_capacity(LC_STATE_TYPES.len()),
+        };
+        let mut existing = Vec::new();
+        for (typ, first, second) in LC_STATE_TYPES {
+            let num_words = encoding.state.num_words(typ, first, second)?;
+            let mut words = Vec::with_capacity(num_words);
+            for _ in 0..num_words {
+                words.push(encoding.new_word_pair(&mut prng, &mut existing)?);
+            }
+            encoding.words.push(words);
+        }
+        Ok(encoding)
+    }
+
+    pub fn secded(&self) -> &LcSecded {
+        &self.state.secded
+    }
+
+    /// Returns the value of the token `name`.
+    pub fn token(&self, name: &str) -> Option<&[u8]> {
+        self.tokens
+            .iter()
+            .find(|(token, _)| token == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Returns the little-endian encoding of `state` of the state type `typ`, without ECC.
+    ///
+    /// For example, `encode("lc_state", "DEV")` or `encode("lc_cnt", "5")`.
+    pub fn encode(&self, typ: &str, state: &str) -> Result<Vec<u8>> {
+        let index = LC_STATE_TYPES
+            .iter()
+            .position(|(t, _, _)| *t == typ)
+            .ok_or_else(|| anyhow!("Unknown state type {typ}"))?;
+        let (_, first, _) = LC_STATE_TYPES[index];
+        let entries = self
+            .state
+            .table(typ)
+            .unwrap()
+            .get(state)
+            .ok_or_else(|| anyhow!("Unknown state {state} of type {typ}"))?;
+        let data_width = self.state.secded.data_width;
+        let data_mask = u64::MAX >> (64 - data_width);
+        let mut bytes = Vec::with_capacity(entries.len() * data_width / 8);
+        for (j, entry) in entries.iter().enumerate() {
+            let (base, incr) = self.words[index][j];
+            let word = match entry.chars().next() {
+                _ if entry == "0" => 0,
+                Some(c) if c == first => base & data_mask,
+                _ => incr & data_mask,
+            };
+            bytes.extend_from_slice(&word.to_le_bytes()[..data_width / 8]);
+        }
+        Ok(bytes)
+    }
+
+    /// Randomly generates a new pair of codewords, where the second one can be programmed on top
+    /// of the first one, both satisfying the Hamming weight and distance constraints.
+    fn new_word_pair(&self, prng: &mut SecurePrng, existing: &mut Vec<u64>) -> Result<(u64, u64)> {
+        let secded = &self.state.secded;
+        let data_width = secded.data_width;
+        loop {
+            let data = prng.getrandbits(data_width).to_u64().unwrap();
+            let base = secded.ecc_encode_word(data)?;
+            let weight = base.count_ones();
+            if weight < self.state.min_hw || weight > self.state.max_hw {
+                continue;
+            }
+            if existing
+                .iter()
+                .any(|w| (w ^ base).count_ones() < self.state.min_hd)
+            {
+                continue;
+            }
+            let candidates = self.incremental_codewords(data, base, existing)?;
+            if candidates.is_empty() {
+                continue;
+            }
+            let incr = *prng.choice(&candidates);
+            existing.push(base);
+            existing.push(incr);
+            return Ok((base, incr));
+        }
+    }
+
+    /// Enumerates all codewords whose data and ECC bits only add to those of `base`.
+    fn incremental_codewords(&self, data: u64, base: u64, existing: &[u64]) -> Result<Vec<u64>> {
+        let secded = &self.state.secded;
+        let free: Vec<usize> = (0..secded.data_width)
+            .filter(|bit| data & (1 << bit) == 0)
+            .collect();
+        let mut candidates = Vec::new();
+        for k in 1u64..(1 << free.len()) {
+            // Scatter the bits of `k` into the unset data bits.
+            let mut incr_data = data;
+            for (i, bit) in free.iter().enumerate() {
+                if k & (1 << i) != 0 {
+                    incr_data |= 1 << bit;
+                }
+            }
+            let incr = secded.ecc_encode_word(incr_data)?;
+            if incr & base == base
+                && incr.count_ones() <= self.state.max_hw
+                && existing
+                    .iter()
+                    .chain(std::iter::once(&base))
+                    .all(|w| (w ^ incr).count_ones() >= self.state.min_hd)
+            {
+                candidates.push(incr);
+            }
+        }
+        Ok(candidates)
+    }
 }
 
 #[cfg(test)]
@@ -94,6 +385,34 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_lc_state_encoding() -> Result<()> {
+        let state = LcState::from_file(&testdata("otp/lc_ctrl_state.hjson"))?;
+        let encoding = LcStateEncoding::generate(state, None)?;
+
+        // Computed with `util/design/lib/LcStEnc.py` from the same definition.
+        assert_eq!(
+            encoding.token("RndCnstRawUnlockToken").map(hex::encode),
+            Some("f989c2c05b1422eafd301d89589680d2".to_owned())
+        );
+        assert_eq!(
+            hex::encode(encoding.encode("lc_state", "DEV")?),
+            "7ddbaef9edcdf69ffc7a276b5777fb5ceeeebf43b3bb28d35f9cfd6f9bfb7ebf51971a840da052bd"
+        );
+        assert_eq!(
+            hex::encode(encoding.encode("lc_state", "TEST_UNLOCKED0")?),
+            "7ddb28e8614db217a878272104173a48ae8a114333a220c25b04486a99585e8b51971a840da052bd"
+        );
+        assert_eq!(
+            hex::encode(encoding.encode("lc_cnt", "5")?),
+            "efd33fd7afaf759f97dfdac2945362026380f0be870348513585853427bc0cbe\
+             26848447b0555603342d4788702f50a4"
+        );
+        assert_eq!(encoding.encode("lc_state", "RAW")?, vec![0u8; 40]);
+        assert!(encoding.encode("lc_state", "FOO").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_ecc_encode() {
         let secded = LcSecded {
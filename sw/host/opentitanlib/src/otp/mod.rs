@@ -5,6 +5,9 @@
 pub mod alert_handler;
 pub mod alert_handler_regs;
 pub mod lc_state;
+pub mod otp_dump;
 // TODO(lowRISC/opentitan#15443): Fix this lint.
 #[allow(clippy::module_inception)]
 pub mod otp_img;
+pub mod otp_mem_img;
+pub mod otp_mmap;
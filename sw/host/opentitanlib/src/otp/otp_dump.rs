@@ -0,0 +1,403 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decoding of the OTP contents of a device, and comparison with an expected OTP image.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_annotate::Annotate;
+
+use crate::chip::boolean::{HardenedBool, MultiBitBool8};
+use crate::dif::otp_ctrl::{Granularity, OtpParamMmap};
+use crate::otp::otp_img::{OtpImg, OtpImgValue};
+use crate::otp::otp_mmap::{present_digest, OtpMap, OtpMapItem, OtpMapPartition};
+
+/// Interpretation of the contents of an item.
+#[derive(Annotate, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpDecoded {
+    /// A multi-bit boolean item.
+    MultiBitBool(bool),
+    /// A multi-bit boolean item holding neither true nor false.
+    InvalidMultiBitBool,
+    /// A word holding one of the hardened boolean values.
+    HardenedBool(bool),
+}
+
+impl OtpDecoded {
+    fn decode(item: &OtpMapItem, bytes: &[u8]) -> Option<OtpDecoded> {
+        if item.ismubi {
+            // Wider multi-bit booleans repeat the 8-bit encoding.
+            return Some(if bytes.iter().all(|&b| b == MultiBitBool8::True.0) {
+                OtpDecoded::MultiBitBool(true)
+            } else if bytes.iter().all(|&b| b == MultiBitBool8::False.0) {
+                OtpDecoded::MultiBitBool(false)
+            } else {
+                OtpDecoded::InvalidMultiBitBool
+            });
+        }
+        let word = u32::from_le_bytes(bytes.try_into().ok()?);
+        match HardenedBool(word) {
+            HardenedBool::True => Some(OtpDecoded::HardenedBool(true)),
+            HardenedBool::False => Some(OtpDecoded::HardenedBool(false)),
+            _ => None,
+        }
+    }
+}
+
+/// Contents of an OTP item.
+#[derive(Annotate, Serialize, Debug, PartialEq, Eq)]
+pub struct OtpItemDump {
+    pub name: String,
+    #[annotate(format = hex)]
+    pub offset: u32,
+    pub size: u32,
+    /// Value read from the device.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<OtpImgValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<OtpDecoded>,
+    /// Value of the item in the expected image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<OtpImgValue>,
+    /// Whether the value read matches the expected one, unless the latter is `<random>`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches: Option<bool>,
+    /// Reason the item could not be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl OtpItemDump {
+    /// Returns whether the item differs from the expected image, or could not be compared.
+    pub fn is_difference(&self) -> bool {
+        self.matches == Some(false) || (self.expected.is_some() && self.error.is_some())
+    }
+}
+
+/// Contents of an OTP partition.
+#[derive(Annotate, Serialize, Debug, PartialEq, Eq)]
+pub struct OtpPartitionDump {
+    pub name: String,
+    #[annotate(format = hex)]
+    pub offset: u32,
+    /// Whether a digest has been written, for partitions with a digest that could be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked: Option<bool>,
+    /// Whether the hardware digest matches the contents of the partition, if it could be
+    /// verified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest_ok: Option<bool>,
+    pub items: Vec<OtpItemDump>,
+}
+
+/// Contents of the OTP of a device.
+#[derive(Annotate, Serialize, Debug, PartialEq, Eq)]
+pub struct OtpDump {
+    #[annotate(format = hex)]
+    pub partitions: Vec<OtpPartitionDump>,
+}
+
+impl OtpDump {
+    /// Reads and decodes the OTP contents through `read`, which returns the bytes of a region
+    /// of OTP accessed with the given granularity.
+    ///
+    /// Without an `image`, all items of the memory map are read.  Otherwise, only the
+    /// partitions and items named in the image are read and compared to their expected values.
+    /// Digests are always read and, if the IV and finalization constant of the consistency
+    /// digest are given, verified for unscrambled partitions with a hardware digest.
+    pub fn read<F>(
+        map: &OtpMap,
+        image: Option<&OtpImg>,
+        digest_consts: Option<(u64, u128)>,
+        mut read: F,
+    ) -> Result<OtpDump>
+    where
+        F: FnMut(OtpParamMmap, Granularity) -> Result<Vec<u8>>,
+    {
+        // Select the partitions and items to read, along with their expected values.
+        let mut selection = Vec::new();
+        match image {
+            None => {
+                for part in &map.partitions {
+                    let items = part.items.iter().map(|item| (item, None)).collect();
+                    selection.push((part, items));
+                }
+            }
+            Some(image) => {
+                for img_part in &image.partitions {
+                    let part = map.partition(&img_part.name).ok_or_else(|| {
+                        anyhow!("Partition {} not in the memory map", img_part.name)
+                    })?;
+                    let mut items = Vec::new();
+                    for img_item in img_part.items.iter().flatten() {
+                        let item = part.item(&img_item.name).ok_or_else(|| {
+                            anyhow!("Item {} not in partition {}", img_item.name, part.name)
+                        })?;
+                        items.push((item, Some(&img_item.value)));
+                    }
+                    if let Some(digest) = part.digest() {
+                        if !items.iter().any(|(item, _)| item.isdigest) {
+                            items.push((digest, None));
+                        }
+                    }
+                    selection.push((part, items));
+                }
+            }
+        }
+
+        let mut partitions = Vec::new();
+        for (part, items) in selection {
+            let mut dump = OtpPartitionDump {
+                name: part.name.clone(),
+                offset: part.offset,
+                locked: None,
+                digest_ok: None,
+                items: Vec::new(),
+            };
+            for (item, expected) in items {
+                // Digests are always accessed with 64-bit granularity.
+                let granule = if item.isdigest {
+                    Granularity::B64
+                } else {
+                    part.granularity()
+                };
+                let item_dump = Self::read_item(item, expected, read(item.mmap(), granule))?;
+                if item.isdigest {
+                    if let Some(OtpImgValue::Word(digest)) = item_dump.value {
+                        dump.locked = Some(digest != 0);
+                        if digest != 0 {
+                            dump.digest_ok = digest_consts.and_then(|consts| {
+                                Self::verify_digest(part, digest, consts, &mut read)
+                            });
+                        }
+                    }
+                }
+                dump.items.push(item_dump);
+            }
+            partitions.push(dump);
+        }
+        Ok(OtpDump { partitions })
+    }
+
+    fn read_item(
+        item: &OtpMapItem,
+        expected: Option<&OtpImgValue>,
+        bytes: Result<Vec<u8>>,
+    ) -> Result<OtpItemDump> {
+        let mut dump = OtpItemDump {
+            name: item.name.clone(),
+            offset: item.offset,
+            size: item.size,
+            value: None,
+            decoded: None,
+            expected: expected.cloned(),
+            matches: None,
+            error: None,
+        };
+        match bytes {
+            Ok(bytes) => {
+                if let Some(encoded) = expected.map(|value| item.encode(value)).transpose()? {
+                    dump.matches = encoded.map(|encoded| encoded == bytes);
+                }
+                dump.decoded = OtpDecoded::decode(item, &bytes);
+                dump.value = Some(item.decode(&bytes));
+            }
+            Err(e) => dump.error = Some(format!("{:#}", e)),
+        }
+        Ok(dump)
+    }
+
+    /// Recomputes the hardware digest of the partition.  Returns `None`, if the partition has
+    /// no hardware digest, is scrambled or could not be read.
+    fn verify_digest<F>(
+        part: &OtpMapPartition,
+        digest: u64,
+        (iv, cnst): (u64, u128),
+        read: &mut F,
+    ) -> Option<bool>
+    where
+        F: FnMut(OtpParamMmap, Granularity) -> Result<Vec<u8>>,
+    {
+        // The digest of secret partitions is computed over the scrambled contents, which are
+        // not accessible.
+        if !part.hw_digest || part.secret {
+            return None;
+        }
+        let data = read(part.data_mmap(), part.granularity()).ok()?;
+        let blocks: Vec<u64> = data
+            .chunks_exact(8)
+            .map(|block| u64::from_le_bytes(block.try_into().unwrap()))
+            .collect();
+        Some(present_digest(&blocks, iv, cnst) == digest)
+    }
+
+    /// Returns the number of items differing from the expected image, and partitions whose
+    /// digest does not match.
+    pub fn differences(&self) -> usize {
+        self.partitions
+            .iter()
+            .map(|part| {
+                part.items
+                    .iter()
+                    .filter(|item| item.is_difference())
+                    .count()
+                    + (part.digest_ok == Some(false)) as usize
+            })
+            .sum()
+    }
+
+    /// Removes everything matching the expected image, leaving only the differences.
+    pub fn retain_differences(&mut self) {
+        for part in &mut self.partitions {
+            part.items.retain(OtpItemDump::is_difference);
+        }
+        self.partitions
+            .retain(|part| !part.items.is_empty() || part.digest_ok == Some(false));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    use crate::util::testdata;
+
+    const IV: u64 = 0x90fb9f42a0d1fa8e;
+    const CNST: u128 = 0x5a1a5b3e86e1e1ab2d4bd6d8a2f36c4f;
+
+    const TEST_IMG: &str = r#"
+        {
+            partitions: [
+                {
+                    name: "HW_CFG0",
+                    items: [
+                        {
+                            name: "DEVICE_ID",
+                            value: "<random>",
+                        },
+                        {
+                            name: "MANUF_STATE",
+                            value: ["0x1", "0x2"],
+                        },
+                        {
+                            name: "EN_SRAM_IFETCH",
+                            value: true,
+                        },
+                        {
+                            name: "EN_CSRNG_SW_APP_READ",
+                            value: true,
+                        },
+                    ]
+                },
+                {
+                    name: "SECRET0",
+                    items: [
+                        {
+                            name: "TEST_UNLOCK_TOKEN",
+                            value: "0x1234",
+                        },
+                    ]
+                }
+            ]
+        }"#;
+
+    /// Reads from an OTP image in memory, failing on secret partitions.
+    fn reader(
+        otp: &[u8],
+        secret: (u32, u32),
+    ) -> impl FnMut(OtpParamMmap, Granularity) -> Result<Vec<u8>> + '_ {
+        move |mmap, _| {
+            let range = mmap.byte_addr as usize..(mmap.byte_addr + mmap.size) as usize;
+            if (secret.0..secret.1).contains(&mmap.byte_addr) {
+                anyhow::bail!("read locked");
+            }
+            Ok(otp[range].to_vec())
+        }
+    }
+
+    #[test]
+    fn test_dump_and_diff() -> Result<()> {
+        let map = OtpMap::from_file(&testdata("otp/otp_ctrl_mmap.hjson"))?;
+        let hw_cfg = map.partition("HW_CFG0").unwrap();
+        let secret = map.partition("SECRET0").unwrap();
+        let mut otp = vec![0u8; map.size as usize];
+
+        // Program the hardware configuration and lock it.
+        let manuf_state = hw_cfg.item("MANUF_STATE").unwrap().offset as usize;
+        otp[manuf_state] = 1;
+        otp[manuf_state + 4] = 3;
+        otp[hw_cfg.item("EN_SRAM_IFETCH").unwrap().offset as usize] = MultiBitBool8::True.0;
+        otp[hw_cfg.item("EN_CSRNG_SW_APP_READ").unwrap().offset as usize] = 0x42;
+        let data = hw_cfg.data_mmap();
+        let blocks: Vec<u64> = otp[data.byte_addr as usize..(data.byte_addr + data.size) as usize]
+            .chunks(8)
+            .map(|block| u64::from_le_bytes(block.try_into().unwrap()))
+            .collect();
+        let digest = hw_cfg.digest().unwrap().offset as usize;
+        otp[digest..digest + 8].copy_from_slice(&present_digest(&blocks, IV, CNST).to_le_bytes());
+
+        let secret_range = (secret.offset, secret.offset + secret.size);
+        let dump = OtpDump::read(&map, None, Some((IV, CNST)), reader(&otp, secret_range))?;
+        assert_eq!(dump.partitions.len(), map.partitions.len());
+        assert_eq!(dump.differences(), 0);
+        let hw_dump = dump
+            .partitions
+            .iter()
+            .find(|p| p.name == "HW_CFG0")
+            .unwrap();
+        assert_eq!(hw_dump.locked, Some(true));
+        assert_eq!(hw_dump.digest_ok, Some(true));
+        assert_eq!(
+            hw_dump.items[2].decoded,
+            Some(OtpDecoded::MultiBitBool(true))
+        );
+        assert_eq!(
+            hw_dump.items[3].decoded,
+            Some(OtpDecoded::InvalidMultiBitBool)
+        );
+        let vendor_dump = &dump.partitions[0];
+        assert_eq!(vendor_dump.locked, Some(false));
+        assert_eq!(vendor_dump.digest_ok, None);
+
+        let image = OtpImg::from_str(TEST_IMG)?;
+        let mut diff = OtpDump::read(&map, Some(&image), None, reader(&otp, secret_range))?;
+        // The digests are read as well.
+        assert_eq!(diff.partitions[0].items.len(), 5);
+        assert_eq!(diff.partitions[0].items[0].matches, None);
+        // MANUF_STATE, EN_CSRNG_SW_APP_READ and the unreadable TEST_UNLOCK_TOKEN differ.
+        assert_eq!(diff.differences(), 3);
+        diff.retain_differences();
+        let names: Vec<&str> = diff
+            .partitions
+            .iter()
+            .flat_map(|p| p.items.iter().map(|item| item.name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            ["MANUF_STATE", "EN_CSRNG_SW_APP_READ", "TEST_UNLOCK_TOKEN"]
+        );
+        assert_eq!(
+            diff.partitions[0].items[0].value,
+            Some(OtpImgValue::Sequence(vec![1, 3, 0, 0, 0, 0, 0, 0]))
+        );
+
+        // Tampering with the contents invalidates the digest.
+        otp[manuf_state] = 2;
+        let dump = OtpDump::read(&map, None, Some((IV, CNST)), reader(&otp, secret_range))?;
+        assert_eq!(dump.differences(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_item() -> Result<()> {
+        let map = OtpMap::from_file(&testdata("otp/otp_ctrl_mmap.hjson"))?;
+        let image = OtpImg::from_str(
+            r#"{ partitions: [{ name: "HW_CFG0", items: [{ name: "NOPE", value: 0 }] }] }"#,
+        )?;
+        let otp = vec![0u8; map.size as usize];
+        assert!(OtpDump::read(&map, Some(&image), None, reader(&otp, (0, 0))).is_err());
+        Ok(())
+    }
+}
@@ -2,19 +2,21 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::util::num_de::DecEncoded;
 use crate::util::parse_int::ParseInt;
 
 use std::fmt;
 use std::path::Path;
 
 use anyhow::{anyhow, bail, Result};
+use num_bigint_dig::BigUint;
 
 use serde::de::{self, Unexpected};
 use serde::{Deserialize, Serialize};
 
 use serde_annotate::Annotate;
 
-#[derive(Annotate, Serialize, Debug, PartialEq, Eq)]
+#[derive(Annotate, Serialize, Debug, Clone, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum OtpImgValue {
     Word(u64),
@@ -102,12 +104,61 @@ pub struct OtpImgItem {
 pub struct OtpImgPartition {
     pub name: String,
     pub items: Option<Vec<OtpImgItem>>,
+    /// Whether to lock the partition by computing its hardware digest.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_bool",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub lock: bool,
+    /// Life cycle state, only for the `LIFE_CYCLE` partition.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    /// Life cycle transition count, only for the `LIFE_CYCLE` partition.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<DecEncoded<u32>>,
+}
+
+/// Deserializes booleans, which are often written as strings such as `"True"` in OTP images.
+fn deserialize_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct Visitor;
+
+    impl de::Visitor<'_> for Visitor {
+        type Value = bool;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a boolean")
+        }
+
+        fn visit_bool<E>(self, val: bool) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(val)
+        }
+
+        fn visit_str<E>(self, val: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            match val.to_lowercase().as_str() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(de::Error::invalid_value(Unexpected::Str(val), &self)),
+            }
+        }
+    }
+    deserializer.deserialize_any(Visitor)
 }
 
 #[derive(Annotate, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct OtpImg {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub seed: Option<u64>,
+    /// Seed from which the `<random>` values of the image are generated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<DecEncoded<BigUint>>,
     // FIXME: Needed to get `OtpImgValue` serailization to emit hex values.
     // See: https://github.com/cfrantz/serde-annotate/issues/5.
     #[annotate(format = hex)]
@@ -216,6 +267,9 @@ mod tests {
         seed: None,
         partitions: vec![OtpImgPartition {
             name: "CREATOR_SW_CFG".to_owned(),
+            lock: false,
+            state: None,
+            count: None,
             items: Some(vec![
                 OtpImgItem {
                     name: "CREATOR_SW_CFG_DIGEST".to_owned(),
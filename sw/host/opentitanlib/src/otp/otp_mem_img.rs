@@ -0,0 +1,386 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Generation of OTP memory images from an `OtpImg`.
+//!
+//! This reproduces `util/design/gen-otp-img.py`: the items of the image are laid out according
+//! to the memory map, `<random>` values are drawn from the seed of the image, secret partitions
+//! are scrambled, locked partitions get their hardware digest, and the resulting words are
+//! ECC-encoded into a `.vmem` file identical to the one generated by the Python tooling.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail, ensure, Result};
+use num_bigint_dig::BigUint;
+use rand::RngCore;
+
+use crate::otp::lc_state::LcStateEncoding;
+use crate::otp::otp_img::{OtpImg, OtpImgPartition, OtpImgValue};
+use crate::otp::otp_mmap::{present_digest, OtpMap};
+use crate::util::present::Present;
+use crate::util::secure_prng::SecurePrng;
+
+/// Seed diversification constant of `util/design/lib/OtpMemImg.py`.
+const OTP_IMG_SEED_DIVERSIFIER: u128 = 1941661965323525198146;
+
+/// Name of the partition holding the life cycle state.
+const LIFE_CYCLE: &str = "LIFE_CYCLE";
+
+/// The contents of the OTP described by one or more OTP images.
+pub struct OtpMemImg {
+    map: OtpMap,
+    lc_state: LcStateEncoding,
+    prng: SecurePrng,
+    /// Values of the items, indexed like the partitions and items of the memory map.
+    values: Vec<Vec<Option<Vec<u8>>>>,
+    /// Whether each partition of the memory map is locked.
+    locks: Vec<bool>,
+}
+
+impl OtpMemImg {
+    /// Creates the contents described by `image`.
+    ///
+    /// The scrambling keys and digest constants of `map` must be known, see
+    /// `OtpMap::resolve_scrambling`.  The `<random>` values of the image are drawn from `seed`, or
+    /// from the seed of the image.
+    pub fn new(
+        map: OtpMap,
+        lc_state: LcStateEncoding,
+        image: &OtpImg,
+        seed: Option<&BigUint>,
+    ) -> Result<OtpMemImg> {
+        ensure!(
+            map.width as usize * 8 == lc_state.secded().data_width(),
+            "OTP width and SECDED data width must be equal"
+        );
+        let seed = seed
+            .or(image.seed.as_deref())
+            .ok_or_else(|| anyhow!("Missing seed in OTP image"))?;
+        let prng = SecurePrng::new(&(BigUint::from(OTP_IMG_SEED_DIVERSIFIER) + seed))?;
+        let mut mem_img = OtpMemImg {
+            values: map
+                .partitions
+                .iter()
+                .map(|part| vec![None; part.items.len()])
+                .collect(),
+            locks: vec![false; map.partitions.len()],
+            map,
+            lc_state,
+            prng,
+        };
+        mem_img.merge(image)?;
+        Ok(mem_img)
+    }
+
+    /// Applies the partitions and items of `image` on top of the current contents.
+    ///
+    /// This is used for additional images that override parts of the main image.  Their seed is
+    /// ignored, `<random>` values continue to be drawn from the seed of the main image.
+    pub fn merge(&mut self, image: &OtpImg) -> Result<()> {
+        for part in &image.partitions {
+            self.merge_partition(part)?;
+        }
+        Ok(())
+    }
+
+    fn merge_partition(&mut self, part: &OtpImgPartition) -> Result<()> {
+        let index = self
+            .map
+            .partitions
+            .iter()
+            .position(|p| p.name == part.name)
+            .ok_or_else(|| anyhow!("Partition {} does not exist", part.name))?;
+        let mmap_part = &self.map.partitions[index];
+        ensure!(
+            !part.lock || mmap_part.hw_digest,
+            "Partition {} does not contain a hardware digest",
+            part.name
+        );
+        self.locks[index] = part.lock;
+
+        let items = part.items.as_deref().unwrap_or_default();
+        if part.name == LIFE_CYCLE {
+            ensure!(
+                items.is_empty(),
+                "Life cycle items cannot directly be overridden"
+            );
+            ensure!(!part.lock, "Life cycle partition cannot be locked");
+            let state = part.state.as_deref().unwrap_or("RAW");
+            let count = part.count.as_ref().map(|count| **count).unwrap_or(0);
+            ensure!(
+                count != 0 || state == "RAW",
+                "Life cycle transition counter can only be zero in the RAW state"
+            );
+            let state = self.lc_state.encode("lc_state", state)?;
+            let count = self.lc_state.encode("lc_cnt", &count.to_string())?;
+            self.set_item(index, "LC_STATE", state)?;
+            self.set_item(index, "LC_TRANSITION_CNT", count)?;
+            return Ok(());
+        }
+        ensure!(
+            part.state.is_none() && part.count.is_none(),
+            "Only the {LIFE_CYCLE} partition has a state and count"
+        );
+        if items.is_empty() {
+            log::warn!("Partition {} does not contain any items", part.name);
+        }
+
+        for item in items {
+            let mmap_item = self.map.partitions[index]
+                .item(&item.name)
+                .ok_or_else(|| anyhow!("Item {} does not exist", item.name))?;
+            let value = match (&item.value, mmap_item.ismubi) {
+                (OtpImgValue::Bool(_), _) | (_, false) => mmap_item.encode(&item.value)?,
+                (value, true) => bail!(
+                    "Multi-bit boolean item {} must be true or false, not {:?}",
+                    item.name,
+                    value
+                ),
+            };
+            let value = match value {
+                Some(value) => value,
+                None => {
+                    let mut value = vec![0u8; mmap_item.size as usize];
+                    self.prng.fill_bytes(&mut value);
+                    value
+                }
+            };
+            self.set_item(index, &item.name, value)?;
+        }
+        Ok(())
+    }
+
+    fn set_item(&mut self, part: usize, name: &str, value: Vec<u8>) -> Result<()> {
+        let items = &self.map.partitions[part].items;
+        let index = items
+            .iter()
+            .position(|item| item.name == name)
+            .ok_or_else(|| anyhow!("Item {name} does not exist"))?;
+        ensure!(
+            value.len() == items[index].size as usize,
+            "Value of {name} has {} bytes instead of {}",
+            value.len(),
+            items[index].size
+        );
+        self.values[part][index] = Some(value);
+        Ok(())
+    }
+
+    /// Returns the contents of the OTP, before ECC, along with the name of the item each byte
+    /// belongs to.
+    pub fn data(&self) -> Result<(Vec<u8>, Vec<String>)> {
+        let size = self.map.size as usize;
+        let mut data = vec![0u8; size];
+        let mut annotations = vec![String::new(); size];
+        let digest_consts = self.map.consistency_digest();
+
+        for ((part, values), &lock) in self
+            .map
+            .partitions
+            .iter()
+            .zip(&self.values)
+            .zip(&self.locks)
+        {
+            let offset = part.offset as usize;
+            let part_size = part.size as usize;
+            let mut bytes = vec![0u8; part_size];
+            let mut defined = vec![false; part_size];
+            annotations[offset..offset + part_size].fill("unallocated".to_owned());
+            for (item, value) in part.items.iter().zip(values) {
+                let start = item.offset as usize;
+                let end = start + item.size as usize;
+                annotations[start..end].fill(format!("{}: {}", part.name, item.name));
+                if let Some(value) = value {
+                    bytes[start - offset..end - offset].copy_from_slice(value);
+                    defined[start - offset..end - offset].fill(true);
+                }
+            }
+
+            // Undefined blocks are neither scrambled nor digested, they stay blank.
+            let mut blocks: Vec<u64> = bytes
+                .chunks(8)
+                .map(|block| u64::from_le_bytes(block.try_into().unwrap()))
+                .collect();
+            if part.secret {
+                let key_sel = part
+                    .key_sel
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("Partition {} has no scrambling key", part.name))?;
+                let cipher = Present::new_128(&self.map.scrambling.key(key_sel)?.to_le_bytes());
+                for (block, defined) in blocks.iter_mut().zip(defined.chunks(8)) {
+                    if defined.iter().any(|&d| d) {
+                        *block = cipher.encrypt_block(*block);
+                    }
+                }
+            }
+            if part.hw_digest {
+                let last = blocks.len() - 1;
+                ensure!(
+                    blocks[last] == 0,
+                    "Digest of partition {} cannot be overridden manually",
+                    part.name
+                );
+                if lock {
+                    let (iv, cnst) = digest_consts
+                        .ok_or_else(|| anyhow!("Consistency digest constants are not known"))?;
+                    blocks[last] = present_digest(&blocks[..last], iv, cnst);
+                }
+            }
+
+            for (dst, block) in data[offset..offset + part_size].chunks_mut(8).zip(blocks) {
+                dst.copy_from_slice(&block.to_le_bytes());
+            }
+        }
+        Ok((data, annotations))
+    }
+
+    /// Returns the ECC-encoded words of the OTP, each with the names of the items it holds.
+    pub fn words(&self) -> Result<Vec<(u64, BTreeSet<String>)>> {
+        let (data, annotations) = self.data()?;
+        let secded = self.lc_state.secded();
+        let word_size = secded.data_width() / 8;
+        data.chunks(word_size)
+            .zip(annotations.chunks(word_size))
+            .map(|(bytes, names)| {
+                let mut word = [0u8; 8];
+                word[..word_size].copy_from_slice(bytes);
+                let codeword = secded.ecc_encode_word(u64::from_le_bytes(word))?;
+                Ok((codeword, names.iter().cloned().collect()))
+            })
+            .collect()
+    }
+
+    /// Renders the contents of the OTP as a `.vmem` file for `$readmemh`.
+    ///
+    /// Each line holds one ECC-encoded word at its word address, annotated with the items it
+    /// holds.
+    pub fn to_vmem(&self) -> Result<String> {
+        let secded = self.lc_state.secded();
+        let bytes_per_word = (secded.data_width() + secded.ecc_width()).div_ceil(8);
+        let words = self.words()?;
+        let mut vmem = String::from("//\n");
+        write!(
+            &mut vmem,
+            "// OTP MEM file with {} x {}bit layout",
+            words.len(),
+            bytes_per_word * 8
+        )?;
+        for (address, (word, names)) in words.iter().enumerate() {
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+            write!(
+                &mut vmem,
+                "\n@{address:06x} {word:0width$x} // {}",
+                names.join(", "),
+                width = bytes_per_word * 2
+            )?;
+        }
+        Ok(vmem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::lc_state::LcState;
+    use crate::util::testdata;
+    use std::str::FromStr;
+
+    const TEST_IMG: &str = r#"
+    {
+        "seed": "01931961561863975174",
+        "partitions": [
+            {
+                "name": "HW_CFG0",
+                "lock": "True",
+                "items": [
+                    { "name": "DEVICE_ID", "value": "<random>" },
+                    { "name": "EN_SRAM_IFETCH", "value": true },
+                    { "name": "EN_CSRNG_SW_APP_READ", "value": false }
+                ]
+            },
+            {
+                "name": "SECRET0",
+                "lock": true,
+                "items": [
+                    { "name": "TEST_UNLOCK_TOKEN", "value": "<random>" },
+                    { "name": "TEST_EXIT_TOKEN", "value": "0x1122334455667788" }
+                ]
+            },
+            {
+                "name": "LIFE_CYCLE",
+                "state": "DEV",
+                "count": "5"
+            }
+        ]
+    }"#;
+
+    fn mem_img(image: &OtpImg) -> Result<OtpMemImg> {
+        let mut map = OtpMap::from_file(&testdata("otp/otp_ctrl_mmap.hjson"))?;
+        map.resolve_scrambling(None)?;
+        let lc_state = LcState::from_file(&testdata("otp/lc_ctrl_state.hjson"))?;
+        OtpMemImg::new(map, LcStateEncoding::generate(lc_state, None)?, image, None)
+    }
+
+    #[test]
+    fn test_vmem() -> Result<()> {
+        let vmem = mem_img(&OtpImg::from_str(TEST_IMG)?)?.to_vmem()?;
+        let lines: Vec<&str> = vmem.lines().collect();
+        assert_eq!(lines.len(), 1026);
+        assert_eq!(lines[1], "// OTP MEM file with 1024 x 24bit layout");
+
+        // Computed with `util/design/gen-otp-img.py` from the same definitions.
+        for line in [
+            "@000000 000000 // VENDOR_TEST: SCRATCH",
+            "@000340 38d4c1 // HW_CFG0: DEVICE_ID",
+            "@00034f 1cc99e // HW_CFG0: DEVICE_ID",
+            "@000350 000000 // HW_CFG0: MANUF_STATE",
+            "@000360 1d6996 // HW_CFG0: EN_CSRNG_SW_APP_READ, HW_CFG0: EN_SRAM_IFETCH",
+            "@000364 3aceb9 // HW_CFG0: HW_CFG0_DIGEST",
+            "@000367 10aadf // HW_CFG0: HW_CFG0_DIGEST",
+            "@000368 330d31 // SECRET0: TEST_UNLOCK_TOKEN",
+            "@000370 25a1bb // SECRET0: TEST_EXIT_TOKEN",
+            "@000377 22a39a // SECRET0: TEST_EXIT_TOKEN",
+            "@00037b 2330ae // SECRET0: SECRET0_DIGEST",
+            "@0003d4 3ad3ef // LIFE_CYCLE: LC_TRANSITION_CNT",
+            "@0003f9 1c6ffd // LIFE_CYCLE: LC_STATE",
+        ] {
+            let address = usize::from_str_radix(&line[1..7], 16)?;
+            assert_eq!(lines[address + 2], line);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let mut mem_img = mem_img(&OtpImg::from_str(TEST_IMG)?)?;
+        // Overriding a partition without locking it removes its digest.
+        mem_img.merge(&OtpImg::from_str(
+            r#"{ "partitions": [ { "name": "HW_CFG0", "items": [] } ] }"#,
+        )?)?;
+        let vmem = mem_img.to_vmem()?;
+        assert!(vmem.contains("\n@000364 000000 // HW_CFG0: HW_CFG0_DIGEST"));
+        assert!(vmem.contains("\n@000340 38d4c1 // HW_CFG0: DEVICE_ID"));
+
+        for (image, error) in [
+            (
+                r#"{ "partitions": [ { "name": "VENDOR_TEST", "lock": true } ] }"#,
+                "Partition VENDOR_TEST does not contain a hardware digest",
+            ),
+            (
+                r#"{ "partitions": [ { "name": "LIFE_CYCLE", "state": "DEV" } ] }"#,
+                "Life cycle transition counter can only be zero in the RAW state",
+            ),
+            (
+                r#"{ "partitions": [ { "name": "HW_CFG0", "items": [
+                    { "name": "EN_SRAM_IFETCH", "value": "0x0" } ] } ] }"#,
+                "Multi-bit boolean item EN_SRAM_IFETCH must be true or false, not Word(0)",
+            ),
+        ] {
+            let result = mem_img.merge(&OtpImg::from_str(image)?);
+            assert_eq!(result.unwrap_err().to_string(), error);
+        }
+        Ok(())
+    }
+}
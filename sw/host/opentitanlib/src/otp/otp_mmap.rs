@@ -0,0 +1,578 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Layout of the OTP as described by `otp_ctrl_mmap.hjson`.
+//!
+//! Partition and item offsets are not part of the HJSON file, they are computed the same way
+//! as by `util/design/lib/OtpMemMap.py`: partitions are laid out in order, partitions without an
+//! explicit size are sized to fit their items (plus the digest), and any space left over is
+//! handed out in 64-bit blocks to the partitions marked `absorb`.
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use num_bigint_dig::BigUint;
+use serde::Deserialize;
+
+use crate::chip::boolean::MultiBitBool8;
+use crate::dif::otp_ctrl::{Granularity, OtpParamMmap};
+use crate::otp::otp_img::OtpImgValue;
+use crate::util::num_de::{DecEncoded, DeferredValue};
+use crate::util::present::Present;
+use crate::util::secure_prng::SecurePrng;
+
+/// Seed diversification constant of `util/design/lib/OtpMemMap.py`.
+const OTP_SEED_DIVERSIFIER: u128 = 177149201092001677687;
+
+/// Size of the blocks in which partitions are scrambled and digested.
+const SCRAMBLE_BLOCK_WIDTH: u32 = 8;
+/// Size of partition digests.
+const DIGEST_SIZE: u32 = 8;
+/// Suffix of the name of the digest item automatically added to partitions with a digest.
+const DIGEST_SUFFIX: &str = "_DIGEST";
+
+#[derive(Deserialize, Debug)]
+struct MmapGeometry {
+    /// Width of an OTP word in bytes.
+    width: DecEncoded<u32>,
+    /// Number of OTP words.
+    depth: DecEncoded<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MmapItem {
+    name: String,
+    #[serde(default)]
+    size: Option<DecEncoded<u32>>,
+    #[serde(default)]
+    ismubi: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct MmapPartition {
+    name: String,
+    #[serde(default)]
+    size: Option<DecEncoded<u32>>,
+    #[serde(default)]
+    absorb: bool,
+    #[serde(default)]
+    secret: bool,
+    #[serde(default)]
+    sw_digest: bool,
+    #[serde(default)]
+    hw_digest: bool,
+    #[serde(default)]
+    key_sel: Option<String>,
+    #[serde(default)]
+    items: Vec<MmapItem>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MmapFile {
+    #[serde(default)]
+    seed: Option<DecEncoded<BigUint>>,
+    otp: MmapGeometry,
+    scrambling: OtpScrambling,
+    partitions: Vec<MmapPartition>,
+}
+
+/// A scrambling key of the secret partitions.
+#[derive(Deserialize, Debug)]
+pub struct OtpScrambleKey {
+    pub name: String,
+    pub value: DeferredValue,
+}
+
+/// IV and finalization constant of a digest computation.
+#[derive(Deserialize, Debug)]
+pub struct OtpDigestConsts {
+    pub name: String,
+    pub iv_value: DeferredValue,
+    pub cnst_value: DeferredValue,
+}
+
+/// Netlist constants used for scrambling and digests.
+///
+/// These are usually `<random>` in the memory map and only known to whoever generated the
+/// netlist, in which case they need to be supplied by other means.
+#[derive(Deserialize, Debug)]
+pub struct OtpScrambling {
+    /// Size of the scrambling keys in bytes.
+    #[serde(default = "default_key_size")]
+    pub key_size: DecEncoded<usize>,
+    /// Size of the digest IVs in bytes.
+    #[serde(default = "default_iv_size")]
+    pub iv_size: DecEncoded<usize>,
+    /// Size of the digest finalization constants in bytes.
+    #[serde(default = "default_cnst_size")]
+    pub cnst_size: DecEncoded<usize>,
+    #[serde(default)]
+    pub keys: Vec<OtpScrambleKey>,
+    #[serde(default)]
+    pub digests: Vec<OtpDigestConsts>,
+}
+
+/// An item of an OTP partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpMapItem {
+    pub name: String,
+    /// Byte offset of the item from the start of the OTP.
+    pub offset: u32,
+    /// Size of the item in bytes.
+    pub size: u32,
+    /// Whether the item holds a multi-bit boolean.
+    pub ismubi: bool,
+    /// Whether this is the digest item of the partition.
+    pub isdigest: bool,
+}
+
+/// A partition of the OTP, with all offsets resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpMapPartition {
+    pub name: String,
+    /// Byte offset of the partition from the start of the OTP.
+    pub offset: u32,
+    /// Size of the partition in bytes, including the digest.
+    pub size: u32,
+    /// Whether the partition is scrambled.
+    pub secret: bool,
+    pub sw_digest: bool,
+    pub hw_digest: bool,
+    /// Name of the scrambling key of secret partitions.
+    pub key_sel: Option<String>,
+    /// Items of the partition, followed by the digest item, if any.
+    pub items: Vec<OtpMapItem>,
+}
+
+fn default_key_size() -> DecEncoded<usize> {
+    DecEncoded(16)
+}
+
+fn default_iv_size() -> DecEncoded<usize> {
+    DecEncoded(8)
+}
+
+fn default_cnst_size() -> DecEncoded<usize> {
+    DecEncoded(16)
+}
+
+/// The OTP memory map.
+#[derive(Debug)]
+pub struct OtpMap {
+    /// Seed from which the `<random>` netlist constants are generated.
+    pub seed: Option<BigUint>,
+    /// Width of an OTP word in bytes.
+    pub width: u32,
+    /// Size of the OTP in bytes.
+    pub size: u32,
+    pub scrambling: OtpScrambling,
+    pub partitions: Vec<OtpMapPartition>,
+}
+
+impl OtpMapItem {
+    /// Location of the item, for use with the direct access interface.
+    pub fn mmap(&self) -> OtpParamMmap {
+        OtpParamMmap {
+            byte_addr: self.offset,
+            size: self.size,
+        }
+    }
+
+    /// Encodes a value from an OTP image as the little-endian contents of this item.
+    ///
+    /// Boolean values of multi-bit boolean items are encoded as such, other booleans as 0 or 1.
+    /// Returns `None` for `<random>` values, whose contents are unknown.
+    pub fn encode(&self, value: &OtpImgValue) -> Result<Option<Vec<u8>>> {
+        let size = self.size as usize;
+        let mut bytes = match value {
+            OtpImgValue::Random => return Ok(None),
+            OtpImgValue::Bool(value) if self.ismubi => {
+                let mubi = if *value {
+                    MultiBitBool8::True
+                } else {
+                    MultiBitBool8::False
+                };
+                return Ok(Some(vec![mubi.0; size]));
+            }
+            OtpImgValue::Bool(value) => vec![*value as u8],
+            OtpImgValue::Word(value) => value.to_le_bytes().to_vec(),
+            OtpImgValue::Sequence(words) => words.iter().flat_map(|w| w.to_le_bytes()).collect(),
+        };
+        if bytes.len() > size {
+            ensure!(
+                bytes[size..].iter().all(|&b| b == 0),
+                "Value {:?} does not fit into {} bytes of {}",
+                value,
+                size,
+                self.name
+            );
+        }
+        bytes.resize(size, 0);
+        Ok(Some(bytes))
+    }
+
+    /// Decodes the little-endian contents of this item into a value, the way it would be
+    /// written in an OTP image.
+    pub fn decode(&self, bytes: &[u8]) -> OtpImgValue {
+        if bytes.len() <= 8 {
+            let mut word = [0u8; 8];
+            word[..bytes.len()].copy_from_slice(bytes);
+            OtpImgValue::Word(u64::from_le_bytes(word))
+        } else {
+            OtpImgValue::Sequence(
+                bytes
+                    .chunks(4)
+                    .map(|chunk| {
+                        let mut word = [0u8; 4];
+                        word[..chunk.len()].copy_from_slice(chunk);
+                        u32::from_le_bytes(word)
+                    })
+                    .collect(),
+            )
+        }
+    }
+}
+
+impl OtpScrambling {
+    /// Returns the scrambling key `name` as used by the PRESENT cipher.
+    pub fn key(&self, name: &str) -> Result<u128> {
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.name == name)
+            .ok_or_else(|| anyhow!("Scrambling key {name} cannot be found"))?;
+        ensure!(
+            key.value.is_initialized(),
+            "Scrambling key {name} is not known"
+        );
+        let mut bytes = [0u8; 16];
+        let len = key.value.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&key.value[..len]);
+        Ok(u128::from_le_bytes(bytes))
+    }
+}
+
+impl OtpMapPartition {
+    /// Granularity with which the items of this partition are accessed.
+    ///
+    /// Digests are always accessed with 64-bit granularity.
+    pub fn granularity(&self) -> Granularity {
+        if self.secret {
+            Granularity::B64
+        } else {
+            Granularity::B32
+        }
+    }
+
+    pub fn item(&self, name: &str) -> Option<&OtpMapItem> {
+        self.items.iter().find(|item| item.name == name)
+    }
+
+    /// The digest item, if the partition has a software or hardware digest.
+    pub fn digest(&self) -> Option<&OtpMapItem> {
+        self.items.iter().find(|item| item.isdigest)
+    }
+
+    /// Location of the contents of the partition covered by the digest.
+    pub fn data_mmap(&self) -> OtpParamMmap {
+        OtpParamMmap {
+            byte_addr: self.offset,
+            size: self.size - self.digest().map(|item| item.size).unwrap_or(0),
+        }
+    }
+}
+
+impl OtpMap {
+    pub fn from_file(in_file: &Path) -> Result<OtpMap> {
+        use std::str::FromStr;
+        let text = std::fs::read_to_string(in_file)
+            .with_context(|| format!("Failed to read {}", in_file.display()))?;
+        Self::from_str(&text)
+    }
+
+    pub fn partition(&self, name: &str) -> Option<&OtpMapPartition> {
+        self.partitions.iter().find(|part| part.name == name)
+    }
+
+    /// Returns the IV and finalization constant of the consistency digest computed by the
+    /// hardware to lock partitions, if they are known.
+    pub fn consistency_digest(&self) -> Option<(u64, u128)> {
+        // The first digest configuration is the one used for partition digests.
+        let digest = self.scrambling.digests.first()?;
+        if !digest.iv_value.is_initialized() || !digest.cnst_value.is_initialized() {
+            return None;
+        }
+        let mut iv = [0u8; 8];
+        let mut cnst = [0u8; 16];
+        let iv_len = digest.iv_value.len().min(iv.len());
+        let cnst_len = digest.cnst_value.len().min(cnst.len());
+        iv[..iv_len].copy_from_slice(&digest.iv_value[..iv_len]);
+        cnst[..cnst_len].copy_from_slice(&digest.cnst_value[..cnst_len]);
+        Some((u64::from_le_bytes(iv), u128::from_le_bytes(cnst)))
+    }
+
+    /// Draws the `<random>` scrambling keys and digest constants from `seed`, or from the seed
+    /// of the memory map, the same way `OtpMemMap.py` generates them for the netlist.
+    pub fn resolve_scrambling(&mut self, seed: Option<&BigUint>) -> Result<()> {
+        let seed = seed
+            .or(self.seed.as_ref())
+            .ok_or_else(|| anyhow!("Missing seed in OTP memory map"))?;
+        let mut prng = SecurePrng::new(&(BigUint::from(OTP_SEED_DIVERSIFIER) + seed))?;
+        let scrambling = &mut self.scrambling;
+        for key in scrambling.keys.iter_mut() {
+            key.value = key.value.resolve(*scrambling.key_size, &mut prng).into();
+        }
+        for digest in scrambling.digests.iter_mut() {
+            digest.iv_value = digest
+                .iv_value
+                .resolve(*scrambling.iv_size, &mut prng)
+                .into();
+            digest.cnst_value = digest
+                .cnst_value
+                .resolve(*scrambling.cnst_size, &mut prng)
+                .into();
+        }
+        Ok(())
+    }
+
+    fn layout(mmap: MmapFile) -> Result<OtpMap> {
+        let size = *mmap.otp.width * *mmap.otp.depth;
+
+        // Determine the size of all partitions.
+        let mut sizes = Vec::with_capacity(mmap.partitions.len());
+        for part in &mmap.partitions {
+            let has_digest = part.sw_digest || part.hw_digest;
+            let size = match &part.size {
+                Some(size) => **size,
+                None => {
+                    let items: u32 = part.items.iter().map(item_size).sum();
+                    let digest = if has_digest { DIGEST_SIZE } else { 0 };
+                    items.next_multiple_of(SCRAMBLE_BLOCK_WIDTH) + digest
+                }
+            };
+            ensure!(
+                size % SCRAMBLE_BLOCK_WIDTH == 0,
+                "Size of partition {} must be a multiple of {} bytes",
+                part.name,
+                SCRAMBLE_BLOCK_WIDTH
+            );
+            sizes.push(size);
+        }
+
+        // Distribute unallocated blocks among the absorbing partitions.
+        let allocated: u32 = sizes.iter().sum();
+        let absorbing: Vec<usize> = (0..mmap.partitions.len())
+            .filter(|&i| mmap.partitions[i].absorb)
+            .collect();
+        if !absorbing.is_empty() {
+            let leftover = size.saturating_sub(allocated) / SCRAMBLE_BLOCK_WIDTH;
+            for block in 0..leftover as usize {
+                sizes[absorbing[block % absorbing.len()]] += SCRAMBLE_BLOCK_WIDTH;
+            }
+        }
+
+        let mut partitions = Vec::with_capacity(mmap.partitions.len());
+        let mut offset = 0;
+        for (part, part_size) in mmap.partitions.into_iter().zip(sizes) {
+            ensure!(
+                partitions
+                    .iter()
+                    .all(|p: &OtpMapPartition| p.name != part.name),
+                "Partition name {} is not unique",
+                part.name
+            );
+            let end = offset + part_size;
+            let mut items = Vec::with_capacity(part.items.len() + 1);
+            let mut item_offset = offset;
+            for item in part.items {
+                let item_size = item_size(&item);
+                items.push(OtpMapItem {
+                    name: item.name,
+                    offset: item_offset,
+                    size: item_size,
+                    ismubi: item.ismubi,
+                    isdigest: false,
+                });
+                item_offset += item_size;
+            }
+            if part.sw_digest || part.hw_digest {
+                let digest_offset = end - DIGEST_SIZE;
+                ensure!(
+                    item_offset <= digest_offset,
+                    "Items of partition {} overlap with its digest",
+                    part.name
+                );
+                items.push(OtpMapItem {
+                    name: format!("{}{}", part.name, DIGEST_SUFFIX),
+                    offset: digest_offset,
+                    size: DIGEST_SIZE,
+                    ismubi: false,
+                    isdigest: true,
+                });
+            } else if item_offset > end {
+                bail!("Items of partition {} exceed its size", part.name);
+            }
+            partitions.push(OtpMapPartition {
+                name: part.name,
+                offset,
+                size: part_size,
+                secret: part.secret,
+                sw_digest: part.sw_digest,
+                hw_digest: part.hw_digest,
+                key_sel: part.key_sel,
+                items,
+            });
+            offset = end;
+        }
+        if offset > size {
+            bail!("Partitions require {offset} bytes, but the OTP only has {size}");
+        }
+
+        Ok(OtpMap {
+            seed: mmap.seed.map(|seed| seed.0),
+            width: *mmap.otp.width,
+            size,
+            scrambling: mmap.scrambling,
+            partitions,
+        })
+    }
+}
+
+fn item_size(item: &MmapItem) -> u32 {
+    item.size.as_ref().map(|size| **size).unwrap_or(0)
+}
+
+impl std::str::FromStr for OtpMap {
+    type Err = anyhow::Error;
+
+    fn from_str(json_text: &str) -> Result<OtpMap> {
+        let mmap: MmapFile = deser_hjson::from_str(json_text)
+            .map_err(|e| anyhow!("Failed to parse OTP memory map: {e}"))?;
+        Self::layout(mmap)
+    }
+}
+
+/// Computes the digest of a sequence of 64-bit blocks the way the OTP controller does to lock
+/// a partition.
+///
+/// This is a Merkle-Damgard construction using PRESENT in a Davies-Meyer scheme, finalized with
+/// the 128-bit constant `cnst`.
+pub fn present_digest(blocks: &[u64], iv: u64, cnst: u128) -> u64 {
+    let mut blocks = blocks.to_vec();
+    // The blocks are consumed in pairs, so the last block is repeated if necessary.
+    if blocks.len() % 2 == 1 {
+        blocks.push(blocks[blocks.len() - 1]);
+    }
+    blocks.push(cnst as u64);
+    blocks.push((cnst >> 64) as u64);
+
+    let mut state = iv;
+    for pair in blocks.chunks(2) {
+        let key = (pair[0] as u128) | ((pair[1] as u128) << 64);
+        state ^= Present::new_128(&key.to_le_bytes()).encrypt_block(state);
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testdata;
+
+    fn test_map() -> Result<OtpMap> {
+        OtpMap::from_file(&testdata("otp/otp_ctrl_mmap.hjson"))
+    }
+
+    #[test]
+    fn test_layout() -> Result<()> {
+        let map = test_map()?;
+        assert_eq!(map.size, 2048);
+
+        let layout: Vec<(&str, u32, u32)> = map
+            .partitions
+            .iter()
+            .map(|p| (p.name.as_str(), p.offset, p.size))
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                ("VENDOR_TEST", 0, 64),
+                // The two absorbing partitions share the 64 unallocated bytes.
+                ("CREATOR_SW_CFG", 64, 800),
+                ("OWNER_SW_CFG", 864, 800),
+                ("HW_CFG0", 1664, 80),
+                ("SECRET0", 1744, 40),
+                ("SECRET1", 1784, 88),
+                ("SECRET2", 1872, 88),
+                ("LIFE_CYCLE", 1960, 88),
+            ]
+        );
+
+        let hw_cfg = map.partition("HW_CFG0").unwrap();
+        let item = hw_cfg.item("EN_CSRNG_SW_APP_READ").unwrap();
+        assert_eq!((item.offset, item.size, item.ismubi), (1729, 1, true));
+        let digest = hw_cfg.digest().unwrap();
+        assert_eq!(digest.name, "HW_CFG0_DIGEST");
+        assert_eq!(digest.offset, 1736);
+        assert_eq!(hw_cfg.data_mmap().size, 72);
+        assert_eq!(hw_cfg.granularity(), Granularity::B32);
+
+        let secret = map.partition("SECRET0").unwrap();
+        assert_eq!(secret.granularity(), Granularity::B64);
+        assert_eq!(secret.key_sel.as_deref(), Some("Secret0Key"));
+        assert!(map.partition("LIFE_CYCLE").unwrap().digest().is_none());
+        // The netlist constants are random in the test memory map.
+        assert!(map.consistency_digest().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_decode() -> Result<()> {
+        let map = test_map()?;
+        let hw_cfg = map.partition("HW_CFG0").unwrap();
+        let mubi = hw_cfg.item("EN_SRAM_IFETCH").unwrap();
+        assert_eq!(
+            mubi.encode(&OtpImgValue::Bool(true))?,
+            Some(vec![MultiBitBool8::True.0])
+        );
+        let device_id = hw_cfg.item("DEVICE_ID").unwrap();
+        let bytes = device_id
+            .encode(&OtpImgValue::Sequence(vec![1, 2, 3]))?
+            .unwrap();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(
+            device_id.decode(&bytes),
+            OtpImgValue::Sequence(vec![1, 2, 3, 0, 0, 0, 0, 0])
+        );
+        assert_eq!(device_id.encode(&OtpImgValue::Random)?, None);
+        assert_eq!(
+            mubi.decode(&[0x96]),
+            OtpImgValue::Word(MultiBitBool8::True.0 as u64)
+        );
+        assert!(mubi.encode(&OtpImgValue::Word(0x1234)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_present_digest() {
+        // Computed with `_present_64bit_digest` of `util/design/lib/OtpMemImg.py`.
+        assert_eq!(
+            present_digest(
+                &[0x0123456789abcdef, 0xfedcba9876543210, 0x1122334455667788],
+                0x90fb9f42a0d1fa8e,
+                0x5a1a5b3e86e1e1ab2d4bd6d8a2f36c4f
+            ),
+            0x001aad0c2010b0c9
+        );
+        // An odd number of blocks is padded by repeating the last one.
+        assert_eq!(
+            present_digest(&[1, 2, 3], 0x1234, 0x5678),
+            present_digest(&[1, 2, 3, 3], 0x1234, 0x5678)
+        );
+        assert_ne!(
+            present_digest(&[1, 2, 3], 0x1234, 0x5678),
+            present_digest(&[1, 2, 4], 0x1234, 0x5678)
+        );
+    }
+}
@@ -120,6 +120,8 @@ impl From<anyhow::Error> for SerializedError {
             crate::io::spi::SpiError,
             crate::io::uart::UartError,
             crate::transport::TransportError,
+            crate::proxy::lease::LeaseError,
+            crate::proxy::security::AccessError,
             crate::transport::proxy::ProxyError,
         );
     }
@@ -11,19 +11,24 @@ use std::rc::Rc;
 use std::time::Duration;
 
 use super::errors::SerializedError;
+use super::lease::{LeaseResource, LeaseTable};
 use super::protocol::{
     BitbangEntryRequest, BitbangEntryResponse, DacBangEntryRequest, EmuRequest, EmuResponse,
-    GpioBitRequest, GpioBitResponse, GpioDacRequest, GpioDacResponse, GpioMonRequest,
+    Encoding, GpioBitRequest, GpioBitResponse, GpioDacRequest, GpioDacResponse, GpioMonRequest,
     GpioMonResponse, GpioRequest, GpioResponse, I2cRequest, I2cResponse, I2cTransferRequest,
-    I2cTransferResponse, Message, ProxyRequest, ProxyResponse, Request, Response, SpiRequest,
-    SpiResponse, SpiTransferRequest, SpiTransferResponse, UartRequest, UartResponse,
+    I2cTransferResponse, JtagRequest, JtagResponse, Message, ProxyRequest, ProxyResponse, Request,
+    Response, SpiRequest, SpiResponse, SpiTransferRequest, SpiTransferResponse, UartRequest,
+    UartResponse, PROTOCOL_VERSION,
 };
+use super::security::{AccessError, AccessPolicy, ClientPolicy, RequestClass};
 use super::CommandHandler;
 use crate::app::TransportWrapper;
 use crate::bootstrap::Bootstrap;
+use crate::debug::dmi::Dmi;
 use crate::io::gpio::{
     BitbangEntry, DacBangEntry, GpioBitbangOperation, GpioDacBangOperation, GpioPin,
 };
+use crate::io::jtag::{Jtag, JtagError, JtagParams};
 use crate::io::{i2c, nonblocking_help, spi};
 use crate::proxy::nonblocking_uart::NonblockingUartRegistry;
 use crate::transport::TransportError;
@@ -36,10 +41,44 @@ pub struct TransportCommandHandler<'a> {
     spi_chip_select: HashMap<String, Vec<spi::AssertChipSelect>>,
     ongoing_bitbanging: Option<Box<dyn GpioBitbangOperation<'static, 'static>>>,
     ongoing_dacbanging: Option<Box<dyn GpioDacBangOperation>>,
+    /// JTAG connection made on behalf of the client identified by the token.
+    jtag: Option<(Token, JtagConnection)>,
+    /// OpenOCD and adapter settings used for JTAG connections, clients can only lower the speed.
+    jtag_params: JtagParams,
+    policy: AccessPolicy,
+    /// Identity and permissions of the client of each open connection.
+    clients: HashMap<Token, Client>,
+    leases: LeaseTable,
+}
+
+struct Client {
+    /// Authenticated identity, or a description of the connection.
+    name: String,
+    policy: ClientPolicy,
+}
+
+enum JtagConnection {
+    Tap(Box<dyn Jtag>),
+    /// Raw access to the debug module interface, without OpenOCD debug target.
+    Dmi(Box<dyn Dmi>),
+}
+
+impl JtagConnection {
+    fn disconnect(self) -> Result<()> {
+        match self {
+            Self::Tap(jtag) => jtag.disconnect(),
+            // Dropping the DMI terminates OpenOCD.
+            Self::Dmi(_) => Ok(()),
+        }
+    }
 }
 
 impl<'a> TransportCommandHandler<'a> {
-    pub fn new(transport: &'a TransportWrapper) -> Result<Self> {
+    pub fn new(
+        transport: &'a TransportWrapper,
+        policy: AccessPolicy,
+        jtag_params: JtagParams,
+    ) -> Result<Self> {
         let nonblocking_help = transport.nonblocking_help()?;
         Ok(Self {
             transport,
@@ -47,9 +86,50 @@ impl<'a> TransportCommandHandler<'a> {
             spi_chip_select: HashMap::new(),
             ongoing_bitbanging: None,
             ongoing_dacbanging: None,
+            jtag: None,
+            jtag_params,
+            policy,
+            clients: HashMap::new(),
+            leases: LeaseTable::default(),
         })
     }
 
+    /// Resolves aliases in the interface name of the resource.
+    fn canonical(&self, resource: LeaseResource) -> LeaseResource {
+        match resource {
+            LeaseResource::Gpio(name) => {
+                LeaseResource::Gpio(self.transport.resolve_pin_name(&name))
+            }
+            LeaseResource::Uart(name) => {
+                LeaseResource::Uart(self.transport.resolve_uart_name(&name))
+            }
+            LeaseResource::Spi(name) => LeaseResource::Spi(self.transport.resolve_spi_name(&name)),
+            LeaseResource::I2c(name) => LeaseResource::I2c(self.transport.resolve_i2c_name(&name)),
+            other => other,
+        }
+    }
+
+    /// Checks the request against the permissions of the client, and leases held by others.
+    fn check_access(&mut self, conn_token: Token, req: &Request) -> Result<()> {
+        let classes: Vec<RequestClass> = match req {
+            Request::Proxy(ProxyRequest::AcquireLease { resources, .. }) => {
+                resources.iter().map(LeaseResource::class).collect()
+            }
+            _ => RequestClass::of(req).into_iter().collect(),
+        };
+        for class in classes {
+            match self.clients.get(&conn_token) {
+                Some(client) => client.policy.check(class)?,
+                None => bail!(AccessError::Denied(class)),
+            }
+        }
+        for resource in LeaseResource::used_by(req) {
+            let resource = self.canonical(resource);
+            self.leases.check(conn_token, &resource)?;
+        }
+        Ok(())
+    }
+
     fn optional_pin(&self, pin: &Option<String>) -> Result<Option<Rc<dyn GpioPin>>> {
         if let Some(pin) = pin {
             Ok(Some(self.transport.gpio_pin(pin)?))
@@ -58,6 +138,188 @@ impl<'a> TransportCommandHandler<'a> {
         }
     }
 
+    /// Returns the JTAG connection previously made by the given client.
+    fn jtag_connection(&mut self, conn_token: Token) -> Result<&mut JtagConnection> {
+        match &mut self.jtag {
+            Some((owner, connection)) if *owner == conn_token => Ok(connection),
+            Some(_) => bail!(JtagError::Busy),
+            None => bail!(JtagError::Generic(
+                "Not connected to any JTAG TAP".to_string()
+            )),
+        }
+    }
+
+    /// Returns the JTAG TAP previously connected by the given client.
+    fn jtag(&mut self, conn_token: Token) -> Result<&mut dyn Jtag> {
+        match self.jtag_connection(conn_token)? {
+            JtagConnection::Tap(jtag) => Ok(jtag.as_mut()),
+            JtagConnection::Dmi(_) => bail!(JtagError::Generic(
+                "Connected to the DMI rather than a debug target".to_string()
+            )),
+        }
+    }
+
+    /// Returns the DMI previously connected by the given client.
+    fn dmi(&mut self, conn_token: Token) -> Result<&mut dyn Dmi> {
+        match self.jtag_connection(conn_token)? {
+            JtagConnection::Dmi(dmi) => Ok(dmi.as_mut()),
+            JtagConnection::Tap(_) => bail!(JtagError::Generic(
+                "Connected to a debug target rather than the DMI".to_string()
+            )),
+        }
+    }
+
+    /// Drops any JTAG connection previously made by the given client, failing if another client
+    /// is using JTAG.
+    fn release_jtag(&mut self, conn_token: Token) -> Result<()> {
+        match self.jtag.take() {
+            Some((owner, connection)) if owner == conn_token => connection.disconnect(),
+            Some(other) => {
+                self.jtag = Some(other);
+                bail!(JtagError::Busy);
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the JTAG parameters of the session, with the adapter speed lowered to the one
+    /// requested by the client.
+    fn jtag_params(&self, adapter_speed_khz: u64) -> JtagParams {
+        JtagParams {
+            adapter_speed_khz: adapter_speed_khz.min(self.jtag_params.adapter_speed_khz),
+            ..self.jtag_params.clone()
+        }
+    }
+
+    fn execute_jtag_cmd(
+        &mut self,
+        conn_token: Token,
+        command: &JtagRequest,
+    ) -> Result<JtagResponse> {
+        match command {
+            JtagRequest::Connect {
+                tap,
+                adapter_speed_khz,
+            } => {
+                self.release_jtag(conn_token)?;
+                let params = self.jtag_params(*adapter_speed_khz);
+                let jtag = self.transport.jtag(&params)?.connect(*tap)?;
+                self.jtag = Some((conn_token, JtagConnection::Tap(jtag)));
+                Ok(JtagResponse::Connect)
+            }
+            JtagRequest::ConnectDmi {
+                tap,
+                adapter_speed_khz,
+            } => {
+                self.release_jtag(conn_token)?;
+                let params = self.jtag_params(*adapter_speed_khz);
+                let dmi = self.transport.jtag(&params)?.connect_dmi(*tap)?;
+                self.jtag = Some((conn_token, JtagConnection::Dmi(dmi)));
+                Ok(JtagResponse::ConnectDmi)
+            }
+            JtagRequest::ScanIdcodes { adapter_speed_khz } => {
+                self.release_jtag(conn_token)?;
+                let params = self.jtag_params(*adapter_speed_khz);
+                let idcodes = self.transport.jtag(&params)?.scan_idcodes()?;
+                Ok(JtagResponse::ScanIdcodes { idcodes })
+            }
+            JtagRequest::Disconnect => {
+                self.jtag_connection(conn_token)?;
+                self.release_jtag(conn_token)?;
+                Ok(JtagResponse::Disconnect)
+            }
+            JtagRequest::DmiRead { addr } => {
+                let value = self.dmi(conn_token)?.dmi_read(*addr)?;
+                Ok(JtagResponse::DmiRead { value })
+            }
+            JtagRequest::DmiWrite { addr, value } => {
+                self.dmi(conn_token)?.dmi_write(*addr, *value)?;
+                Ok(JtagResponse::DmiWrite)
+            }
+            JtagRequest::TargetState => {
+                let state = self.jtag(conn_token)?.target_state()?;
+                Ok(JtagResponse::TargetState { state })
+            }
+            JtagRequest::ReadLcCtrlReg { reg } => {
+                let value = self.jtag(conn_token)?.read_lc_ctrl_reg(reg)?;
+                Ok(JtagResponse::ReadLcCtrlReg { value })
+            }
+            JtagRequest::WriteLcCtrlReg { reg, value } => {
+                self.jtag(conn_token)?.write_lc_ctrl_reg(reg, *value)?;
+                Ok(JtagResponse::WriteLcCtrlReg)
+            }
+            JtagRequest::ReadMemory { addr, len } => {
+                let mut data = vec![0u8; *len as usize];
+                let n = self.jtag(conn_token)?.read_memory(*addr, &mut data)?;
+                data.truncate(n);
+                Ok(JtagResponse::ReadMemory { data })
+            }
+            JtagRequest::ReadMemory32 { addr, len } => {
+                let mut data = vec![0u32; *len as usize];
+                let n = self.jtag(conn_token)?.read_memory32(*addr, &mut data)?;
+                data.truncate(n);
+                Ok(JtagResponse::ReadMemory32 { data })
+            }
+            JtagRequest::WriteMemory { addr, data } => {
+                self.jtag(conn_token)?.write_memory(*addr, data)?;
+                Ok(JtagResponse::WriteMemory)
+            }
+            JtagRequest::WriteMemory32 { addr, data } => {
+                self.jtag(conn_token)?.write_memory32(*addr, data)?;
+                Ok(JtagResponse::WriteMemory32)
+            }
+            JtagRequest::Halt => {
+                self.jtag(conn_token)?.halt()?;
+                Ok(JtagResponse::Halt)
+            }
+            JtagRequest::WaitHalt { timeout_millis } => {
+                self.jtag(conn_token)?
+                    .wait_halt(Duration::from_millis(*timeout_millis as u64))?;
+                Ok(JtagResponse::WaitHalt)
+            }
+            JtagRequest::Resume => {
+                self.jtag(conn_token)?.resume()?;
+                Ok(JtagResponse::Resume)
+            }
+            JtagRequest::ResumeAt { addr } => {
+                self.jtag(conn_token)?.resume_at(*addr)?;
+                Ok(JtagResponse::ResumeAt)
+            }
+            JtagRequest::Step => {
+                self.jtag(conn_token)?.step()?;
+                Ok(JtagResponse::Step)
+            }
+            JtagRequest::StepAt { addr } => {
+                self.jtag(conn_token)?.step_at(*addr)?;
+                Ok(JtagResponse::StepAt)
+            }
+            JtagRequest::Reset { run } => {
+                self.jtag(conn_token)?.reset(*run)?;
+                Ok(JtagResponse::Reset)
+            }
+            JtagRequest::ReadRiscvReg { reg } => {
+                let value = self.jtag(conn_token)?.read_riscv_reg(reg)?;
+                Ok(JtagResponse::ReadRiscvReg { value })
+            }
+            JtagRequest::WriteRiscvReg { reg, value } => {
+                self.jtag(conn_token)?.write_riscv_reg(reg, *value)?;
+                Ok(JtagResponse::WriteRiscvReg)
+            }
+            JtagRequest::SetBreakpoint { addr, hw } => {
+                self.jtag(conn_token)?.set_breakpoint(*addr, *hw)?;
+                Ok(JtagResponse::SetBreakpoint)
+            }
+            JtagRequest::RemoveBreakpoint { addr } => {
+                self.jtag(conn_token)?.remove_breakpoint(*addr)?;
+                Ok(JtagResponse::RemoveBreakpoint)
+            }
+            JtagRequest::RemoveAllBreakpoints => {
+                self.jtag(conn_token)?.remove_all_breakpoints()?;
+                Ok(JtagResponse::RemoveAllBreakpoints)
+            }
+        }
+    }
+
     /// This method will perform whatever action on the underlying `Transport` that is requested
     /// by the given `Request`, and return a response to be sent to the client.  Any `Err`
     /// return from this method will be propagated to the remote client, without any server-side
@@ -580,7 +842,21 @@ impl<'a> TransportCommandHandler<'a> {
                     }
                 }
             }
+            Request::Jtag { command } => {
+                Ok(Response::Jtag(self.execute_jtag_cmd(conn_token, command)?))
+            }
             Request::Proxy(command) => match command {
+                ProxyRequest::Hello { version, encodings } => {
+                    log::info!(
+                        "Connection {:#X} speaks protocol version {}",
+                        conn_token.0,
+                        version
+                    );
+                    Ok(Response::Proxy(ProxyResponse::Hello {
+                        version: PROTOCOL_VERSION,
+                        encoding: Encoding::select(encodings),
+                    }))
+                }
                 ProxyRequest::Provides {} => {
                     let provides_map = self.transport.provides_map()?.clone();
                     Ok(Response::Proxy(ProxyResponse::Provides { provides_map }))
@@ -604,6 +880,34 @@ impl<'a> TransportCommandHandler<'a> {
                         ProxyResponse::ApplyDefaultConfigurationWithStrapping,
                     ))
                 }
+                ProxyRequest::AcquireLease {
+                    resources,
+                    duration_millis,
+                } => {
+                    let resources = resources
+                        .iter()
+                        .map(|resource| self.canonical(resource.clone()))
+                        .collect();
+                    let holder = match self.clients.get(&conn_token) {
+                        Some(client) => client.name.clone(),
+                        None => format!("connection {:#X}", conn_token.0),
+                    };
+                    self.leases.acquire(
+                        conn_token,
+                        &holder,
+                        resources,
+                        Duration::from_millis(*duration_millis),
+                    )?;
+                    Ok(Response::Proxy(ProxyResponse::AcquireLease))
+                }
+                ProxyRequest::ReleaseLease => {
+                    self.leases.release(conn_token);
+                    Ok(Response::Proxy(ProxyResponse::ReleaseLease))
+                }
+                ProxyRequest::GetLeases => {
+                    let leases = self.leases.list();
+                    Ok(Response::Proxy(ProxyResponse::GetLeases { leases }))
+                }
             },
         }
     }
@@ -624,7 +928,8 @@ impl<'a> CommandHandler<Message, NonblockingUartRegistry> for TransportCommandHa
         if let Message::Req(req) = msg {
             // Package either `Ok()` or `Err()` into a `Message`, to be sent via network.
             return Ok(Message::Res(
-                self.do_execute_cmd(conn_token, registry, others, req)
+                self.check_access(conn_token, req)
+                    .and_then(|()| self.do_execute_cmd(conn_token, registry, others, req))
                     .map_err(SerializedError::from),
             ));
         }
@@ -639,4 +944,35 @@ impl<'a> CommandHandler<Message, NonblockingUartRegistry> for TransportCommandHa
     fn nonblocking_help(&self) -> Result<()> {
         self.nonblocking_help.nonblocking_help()
     }
+
+    fn connection_opened(&mut self, conn_token: Token, peer_identity: Option<&str>) {
+        let client = Client {
+            name: match peer_identity {
+                Some(name) => name.to_string(),
+                None => format!("connection {:#X}", conn_token.0),
+            },
+            policy: self.policy.client(peer_identity).clone(),
+        };
+        self.clients.insert(conn_token, client);
+    }
+
+    fn negotiated_encoding(&self, msg: &Message) -> Option<Encoding> {
+        match msg {
+            Message::Req(Request::Proxy(ProxyRequest::Hello { encodings, .. })) => {
+                Some(Encoding::select(encodings))
+            }
+            _ => None,
+        }
+    }
+
+    fn connection_closed(&mut self, conn_token: Token) {
+        self.clients.remove(&conn_token);
+        self.leases.release(conn_token);
+        // Release a JTAG TAP left connected by the client.
+        if matches!(&self.jtag, Some((owner, _)) if *owner == conn_token) {
+            if let Err(e) = self.release_jtag(conn_token) {
+                log::warn!("Failed to disconnect JTAG: {:?}", e);
+            }
+        }
+    }
 }
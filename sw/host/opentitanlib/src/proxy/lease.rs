@@ -0,0 +1,371 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Exclusive use of interfaces of a session by one client at a time.
+//!
+//! Without leases, the requests of all clients of a session are interleaved.  A client can
+//! lease named interfaces, or the entire device, for a bounded time.  Requests by other clients
+//! using leased interfaces then fail with `LeaseError::Busy`, except for requests only observing
+//! GPIO levels or UART output, which remain available to everyone.
+
+use anyhow::{bail, Result};
+use mio::Token;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::impl_serializable_error;
+use crate::proxy::protocol::{GpioBitRequest, GpioDacRequest, ProxyRequest, Request};
+use crate::proxy::security::RequestClass;
+use crate::transport::ProxyOps;
+
+/// Interval between attempts to acquire a lease, while waiting for it to become available.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Longest lease granted by a session, requests for longer leases are shortened to this.
+pub const MAX_LEASE_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Something which can be leased, written as e.g. `device`, `gpio:RESET` or `spi:BOOTSTRAP` on
+/// the command line.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LeaseResource {
+    /// The entire device, including operations affecting many pins, such as bootstrapping.
+    Device,
+    Gpio(String),
+    Uart(String),
+    Spi(String),
+    I2c(String),
+    Jtag,
+    Emu,
+}
+
+impl LeaseResource {
+    /// Returns whether use of one resource conflicts with a lease on the other.
+    fn conflicts_with(&self, other: &LeaseResource) -> bool {
+        *self == LeaseResource::Device || *other == LeaseResource::Device || self == other
+    }
+
+    /// Returns the class of requests, which a client must be permitted to issue in order to
+    /// lease this resource.
+    pub fn class(&self) -> RequestClass {
+        match self {
+            Self::Device => RequestClass::Configuration,
+            Self::Gpio(_) => RequestClass::GpioWrite,
+            Self::Uart(_) => RequestClass::UartWrite,
+            Self::Spi(_) => RequestClass::Spi,
+            Self::I2c(_) => RequestClass::I2c,
+            Self::Jtag => RequestClass::Jtag,
+            Self::Emu => RequestClass::Emu,
+        }
+    }
+
+    /// Returns the resources used by the request, which must not be leased by other clients.
+    /// Interface names are returned as given in the request, possibly being aliases.
+    pub fn used_by(req: &Request) -> Vec<LeaseResource> {
+        if matches!(
+            RequestClass::of(req),
+            None | Some(RequestClass::GpioRead | RequestClass::UartRead)
+        ) {
+            return Vec::new();
+        }
+        match req {
+            Request::Gpio { id, .. } => vec![Self::Gpio(id.clone())],
+            Request::GpioBitbanging {
+                command: GpioBitRequest::Start { pins, .. },
+            }
+            | Request::GpioDacBanging {
+                command: GpioDacRequest::Start { pins, .. },
+            } => pins.iter().map(|pin| Self::Gpio(pin.clone())).collect(),
+            Request::Uart { id, .. } => vec![Self::Uart(id.clone())],
+            Request::Spi { id, .. } => vec![Self::Spi(id.clone())],
+            Request::I2c { id, .. } => vec![Self::I2c(id.clone())],
+            Request::Jtag { .. } => vec![Self::Jtag],
+            Request::Emu { .. } => vec![Self::Emu],
+            Request::ApplyDefaultConfiguration
+            | Request::Proxy(
+                ProxyRequest::Bootstrap { .. }
+                | ProxyRequest::ApplyPinStrapping { .. }
+                | ProxyRequest::RemovePinStrapping { .. }
+                | ProxyRequest::ApplyDefaultConfigurationWithStrapping { .. },
+            ) => vec![Self::Device],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for LeaseResource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Device => write!(f, "device"),
+            Self::Gpio(name) => write!(f, "gpio:{}", name),
+            Self::Uart(name) => write!(f, "uart:{}", name),
+            Self::Spi(name) => write!(f, "spi:{}", name),
+            Self::I2c(name) => write!(f, "i2c:{}", name),
+            Self::Jtag => write!(f, "jtag"),
+            Self::Emu => write!(f, "emu"),
+        }
+    }
+}
+
+impl FromStr for LeaseResource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, name) = match s.split_once(':') {
+            Some((kind, name)) => (kind, Some(name.to_string())),
+            None => (s, None),
+        };
+        Ok(match (kind.to_lowercase().as_str(), name) {
+            ("device", None) => Self::Device,
+            ("jtag", None) => Self::Jtag,
+            ("emu", None) => Self::Emu,
+            ("gpio", Some(name)) => Self::Gpio(name),
+            ("uart", Some(name)) => Self::Uart(name),
+            ("spi", Some(name)) => Self::Spi(name),
+            ("i2c", Some(name)) => Self::I2c(name),
+            _ => bail!("Invalid lease resource {:?}", s),
+        })
+    }
+}
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum LeaseError {
+    #[error("{resource} is leased by {holder} for another {remaining_millis}ms")]
+    Busy {
+        resource: LeaseResource,
+        holder: String,
+        remaining_millis: u64,
+    },
+    #[error("Invalid lease duration of {duration_millis}ms")]
+    InvalidDuration { duration_millis: u64 },
+}
+impl_serializable_error!(LeaseError);
+
+/// Entry of the lease table, as reported to clients.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LeaseInfo {
+    /// Authenticated identity of the client holding the lease, or its connection number.
+    pub holder: String,
+    pub resources: Vec<LeaseResource>,
+    pub remaining_millis: u64,
+}
+
+struct Lease {
+    holder: String,
+    resources: Vec<LeaseResource>,
+    expiry: Instant,
+}
+
+/// Leases of a session, at most one per connection.
+#[derive(Default)]
+pub struct LeaseTable {
+    leases: HashMap<Token, Lease>,
+}
+
+impl LeaseTable {
+    fn expire(&mut self) {
+        let now = Instant::now();
+        self.leases.retain(|_, lease| lease.expiry > now);
+    }
+
+    /// Checks that no other connection holds a lease conflicting with use of `resource`.
+    pub fn check(&mut self, conn_token: Token, resource: &LeaseResource) -> Result<(), LeaseError> {
+        self.expire();
+        let now = Instant::now();
+        for (token, lease) in &self.leases {
+            if *token == conn_token {
+                continue;
+            }
+            if lease.resources.iter().any(|r| resource.conflicts_with(r)) {
+                return Err(LeaseError::Busy {
+                    resource: resource.clone(),
+                    holder: lease.holder.clone(),
+                    remaining_millis: (lease.expiry - now).as_millis() as u64,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Grants the resources to the connection for `duration`, at most `MAX_LEASE_DURATION`,
+    /// replacing any lease it held before.  Fails without effect, if any of the resources is
+    /// leased by another connection.
+    pub fn acquire(
+        &mut self,
+        conn_token: Token,
+        holder: &str,
+        resources: Vec<LeaseResource>,
+        duration: Duration,
+    ) -> Result<(), LeaseError> {
+        let expiry = Instant::now()
+            .checked_add(duration.min(MAX_LEASE_DURATION))
+            .ok_or(LeaseError::InvalidDuration {
+                duration_millis: duration.as_millis() as u64,
+            })?;
+        for resource in &resources {
+            self.check(conn_token, resource)?;
+        }
+        let lease = Lease {
+            holder: holder.to_string(),
+            resources,
+            expiry,
+        };
+        self.leases.insert(conn_token, lease);
+        Ok(())
+    }
+
+    pub fn release(&mut self, conn_token: Token) {
+        self.leases.remove(&conn_token);
+    }
+
+    pub fn list(&mut self) -> Vec<LeaseInfo> {
+        self.expire();
+        let now = Instant::now();
+        let mut leases: Vec<LeaseInfo> = self
+            .leases
+            .values()
+            .map(|lease| LeaseInfo {
+                holder: lease.holder.clone(),
+                resources: lease.resources.clone(),
+                remaining_millis: (lease.expiry - now).as_millis() as u64,
+            })
+            .collect();
+        leases.sort_by(|a, b| a.holder.cmp(&b.holder));
+        leases
+    }
+}
+
+/// Acquires a lease through `ops`, retrying for up to `wait` while the resources are leased by
+/// other clients.
+pub fn acquire_waiting(
+    ops: &dyn ProxyOps,
+    resources: &[LeaseResource],
+    duration: Duration,
+    wait: Duration,
+) -> Result<()> {
+    let deadline = Instant::now() + wait;
+    loop {
+        match ops.acquire_lease(resources, duration) {
+            Err(e) if e.downcast_ref::<LeaseError>().is_some() && Instant::now() < deadline => {
+                log::info!("Waiting for lease: {}", e.root_cause());
+                std::thread::sleep(RETRY_INTERVAL);
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::proxy::test::start_session;
+    use crate::transport::proxy::Proxy;
+    use crate::transport::Transport;
+
+    #[test]
+    fn test_lease_table() -> Result<()> {
+        let (a, b) = (Token(1), Token(2));
+        let mut table = LeaseTable::default();
+        let reset = LeaseResource::from_str("gpio:RESET")?;
+        table.acquire(a, "a", vec![reset.clone()], Duration::from_secs(60))?;
+        assert!(table.check(a, &reset).is_ok());
+        assert!(matches!(
+            table.check(b, &reset),
+            Err(LeaseError::Busy { holder, .. }) if holder == "a"
+        ));
+        assert!(table.check(b, &LeaseResource::Gpio("OTHER".into())).is_ok());
+        // The entire device conflicts with any lease.
+        assert!(table.check(b, &LeaseResource::Device).is_err());
+        assert!(table
+            .acquire(b, "b", vec![LeaseResource::Device], Duration::from_secs(1))
+            .is_err());
+
+        table.release(a);
+        table.acquire(b, "b", vec![LeaseResource::Device], Duration::ZERO)?;
+        // Expired leases vanish.
+        assert!(table.check(a, &reset).is_ok());
+        assert!(table.list().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_oversized_lease() -> Result<()> {
+        let mut table = LeaseTable::default();
+        table.acquire(
+            Token(1),
+            "a",
+            vec![LeaseResource::Jtag],
+            Duration::from_millis(u64::MAX),
+        )?;
+        let leases = table.list();
+        assert_eq!(leases.len(), 1);
+        assert!(leases[0].remaining_millis <= MAX_LEASE_DURATION.as_millis() as u64);
+
+        // Durations sent by clients cannot bring down the session either.
+        let port = start_session(Default::default())?;
+        let proxy = Proxy::open(None, port)?;
+        proxy
+            .proxy_ops()?
+            .acquire_lease(&[LeaseResource::Device], Duration::from_millis(u64::MAX))?;
+        let leases = proxy.proxy_ops()?.leases()?;
+        assert!(leases[0].remaining_millis <= MAX_LEASE_DURATION.as_millis() as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lease_resource() -> Result<()> {
+        for s in [
+            "device",
+            "gpio:RESET",
+            "spi:BOOTSTRAP",
+            "uart:CONSOLE",
+            "jtag",
+        ] {
+            assert_eq!(LeaseResource::from_str(s)?.to_string(), s);
+        }
+        assert!(LeaseResource::from_str("gpio").is_err());
+        assert!(LeaseResource::from_str("device:X").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_leases() -> Result<()> {
+        let port = start_session(Default::default())?;
+        let owner = Proxy::open(None, port)?;
+        let other = Proxy::open(None, port)?;
+        owner.proxy_ops()?.acquire_lease(
+            &[LeaseResource::Gpio("RESET".into())],
+            Duration::from_secs(60),
+        )?;
+        owner.gpio_pin("RESET")?.write(true)?;
+
+        // Others can observe, but not drive the pin.
+        let pin = other.gpio_pin("RESET")?;
+        pin.read()?;
+        let err = pin.write(false).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LeaseError>(),
+            Some(LeaseError::Busy { .. })
+        ));
+        let err = other.proxy_ops()?.apply_pin_strapping("X").unwrap_err();
+        assert!(err.downcast_ref::<LeaseError>().is_some());
+        other.gpio_pin("OTHER")?.write(false)?;
+        let leases = other.proxy_ops()?.leases()?;
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].resources, [LeaseResource::Gpio("RESET".into())]);
+
+        // The lease ends with the connection of its holder.
+        drop(owner);
+        acquire_waiting(
+            &*other.proxy_ops()?,
+            &[LeaseResource::Device],
+            Duration::from_secs(1),
+            Duration::from_secs(5),
+        )?;
+        pin.write(false)?;
+        Ok(())
+    }
+}
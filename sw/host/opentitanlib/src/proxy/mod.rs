@@ -8,17 +8,21 @@ use mio::event::Event;
 use mio::net::TcpListener;
 use mio::{Registry, Token};
 use nonblocking_uart::NonblockingUartRegistry;
-use protocol::Message;
+use protocol::{Encoding, Message};
+use security::SessionSecurity;
 use socket_server::{Connection, JsonSocketServer};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 
 use crate::app::TransportWrapper;
+use crate::io::jtag::JtagParams;
 
 pub mod errors;
 mod handler;
+pub mod lease;
 mod nonblocking_uart;
 pub mod protocol;
+pub mod security;
 mod socket_server;
 
 /// Interface for handlers of protocol messages, responding to each message with a single
@@ -43,6 +47,19 @@ pub trait CommandHandler<Msg, E: ExtraEventHandler> {
     fn nonblocking_help(&self) -> Result<()> {
         Ok(())
     }
+
+    /// Called when a new connection is ready to submit commands, after any TLS handshake.
+    /// `peer_identity` is the name under which the client authenticated, if any.
+    fn connection_opened(&mut self, _conn_token: Token, _peer_identity: Option<&str>) {}
+
+    /// Called after a connection has been closed, to release any resources held on its behalf.
+    fn connection_closed(&mut self, _conn_token: Token) {}
+
+    /// Returns the encoding to use on the connection after responding to `msg`, if `msg`
+    /// negotiated a change.
+    fn negotiated_encoding(&self, _msg: &Msg) -> Option<Encoding> {
+        None
+    }
 }
 
 pub trait ExtraEventHandler {
@@ -61,7 +78,12 @@ pub struct SessionHandler<'a> {
 }
 
 impl<'a> SessionHandler<'a> {
-    pub fn init(transport: &'a TransportWrapper, listen_port: Option<u16>) -> Result<Self> {
+    pub fn init(
+        transport: &'a TransportWrapper,
+        listen_port: Option<u16>,
+        security: SessionSecurity,
+        jtag_params: JtagParams,
+    ) -> Result<Self> {
         let mut port = listen_port.unwrap_or(9900);
         let limit = listen_port.unwrap_or(9999);
         // Find a suitable port to bind to.
@@ -74,9 +96,10 @@ impl<'a> SessionHandler<'a> {
             }
         };
         let socket_server = JsonSocketServer::new(
-            TransportCommandHandler::new(transport)?,
+            TransportCommandHandler::new(transport, security.policy, jtag_params)?,
             NonblockingUartRegistry::new(),
             socket,
+            security.tls,
         )?;
         Ok(Self {
             port,
@@ -92,3 +115,41 @@ impl<'a> SessionHandler<'a> {
         self.socket_server.run_loop()
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::backend::{self, BackendOpts};
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Opts {
+        #[command(flatten)]
+        backend: BackendOpts,
+        #[command(flatten)]
+        jtag: JtagParams,
+    }
+
+    /// Runs a session on a simulated transport in a background thread, returning its port.
+    pub fn start_session(security: SessionSecurity) -> Result<u16> {
+        let opts = Opts::try_parse_from(["test", "--interface", "sim"])?;
+        start_session_on(move || backend::create(&opts.backend), security, opts.jtag)
+    }
+
+    /// Runs a session on the transport made by `create` in a background thread, returning its
+    /// port.
+    pub fn start_session_on(
+        create: impl FnOnce() -> Result<TransportWrapper> + Send + 'static,
+        security: SessionSecurity,
+        jtag_params: JtagParams,
+    ) -> Result<u16> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || -> Result<()> {
+            let transport = create()?;
+            let mut session = SessionHandler::init(&transport, None, security, jtag_params)?;
+            tx.send(session.get_port())?;
+            session.run_loop()
+        });
+        Ok(rx.recv()?)
+    }
+}
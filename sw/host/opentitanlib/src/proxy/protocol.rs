@@ -2,18 +2,23 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
+use anyhow::{ensure, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::bootstrap::BootstrapOptions;
+use crate::dif::lc_ctrl::LcCtrlReg;
 use crate::io::emu::{EmuState, EmuValue};
 use crate::io::gpio::{
     ClockNature, MonitoringReadResponse, MonitoringStartResponse, PinMode, PullMode,
 };
 use crate::io::i2c::DeviceStatus;
+use crate::io::jtag::{JtagTap, RiscvReg, TargetState};
 use crate::io::spi::{MaxSizes, TransferMode};
 use crate::io::uart::{FlowControl, Parity};
 use crate::proxy::errors::SerializedError;
+use crate::proxy::lease::{LeaseInfo, LeaseResource};
 use crate::transport::Capabilities;
 use crate::util::voltage::Voltage;
 
@@ -28,9 +33,90 @@ pub enum Message {
     Async { channel: u32, msg: AsyncMessage },
 }
 
+/// Version of the protocol spoken by this implementation, exchanged by `ProxyRequest::Hello`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Upper limit on the size of a single binary encoded message, guarding against allocating
+/// memory for a garbled length field.
+const MAX_FRAME_SIZE: usize = 1 << 30;
+
+/// Encoding of `Message`s on the wire.  Connections start out using JSON, a client can then
+/// negotiate a more compact encoding by means of `ProxyRequest::Hello`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    /// One JSON document per line.  Byte arrays become arrays of decimal numbers.
+    #[default]
+    Json,
+    /// MessagePack documents, each preceded by its length as a big-endian 32-bit value.  Byte
+    /// arrays are sent as raw binary.
+    MessagePack,
+}
+
+impl Encoding {
+    /// Encodings supported by this implementation, in order of preference.
+    pub const SUPPORTED: [Encoding; 2] = [Encoding::MessagePack, Encoding::Json];
+
+    /// Chooses the first of the encodings offered by the peer which is also supported locally.
+    pub fn select(offered: &[Encoding]) -> Encoding {
+        offered
+            .iter()
+            .copied()
+            .find(|encoding| Self::SUPPORTED.contains(encoding))
+            .unwrap_or_default()
+    }
+
+    /// Appends one encoded message to `buf`.
+    pub fn encode<T: Serialize>(self, buf: &mut Vec<u8>, msg: &T) -> Result<()> {
+        match self {
+            Self::Json => {
+                serde_json::to_writer(&mut *buf, msg)?;
+                buf.push(b'\n');
+            }
+            Self::MessagePack => {
+                let frame = rmp_serde::to_vec_named(msg)?;
+                ensure!(
+                    frame.len() <= MAX_FRAME_SIZE,
+                    "Message of {} bytes too large",
+                    frame.len()
+                );
+                buf.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+                buf.extend_from_slice(&frame);
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the first message in `buf`, if it has been completely received, returning the
+    /// message along with the number of bytes it occupied.
+    pub fn decode<T: DeserializeOwned>(self, buf: &[u8]) -> Result<Option<(T, usize)>> {
+        match self {
+            Self::Json => {
+                let Some(n) = buf.iter().position(|c| *c == b'\n') else {
+                    return Ok(None);
+                };
+                Ok(Some((serde_json::from_slice(&buf[..n])?, n + 1)))
+            }
+            Self::MessagePack => {
+                let Some(len) = buf.first_chunk::<4>() else {
+                    return Ok(None);
+                };
+                let len = u32::from_be_bytes(*len) as usize;
+                ensure!(len <= MAX_FRAME_SIZE, "Message of {} bytes too large", len);
+                let Some(frame) = buf.get(4..4 + len) else {
+                    return Ok(None);
+                };
+                Ok(Some((rmp_serde::from_slice(frame)?, 4 + len)))
+            }
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum AsyncMessage {
-    UartData { data: Vec<u8> },
+    UartData {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -45,6 +131,7 @@ pub enum Request {
     Spi { id: String, command: SpiRequest },
     I2c { id: String, command: I2cRequest },
     Emu { command: EmuRequest },
+    Jtag { command: JtagRequest },
     Proxy(ProxyRequest),
 }
 
@@ -60,6 +147,7 @@ pub enum Response {
     Spi(SpiResponse),
     I2c(I2cResponse),
     Emu(EmuResponse),
+    Jtag(JtagResponse),
     Proxy(ProxyResponse),
 }
 
@@ -119,16 +207,30 @@ pub enum GpioMonResponse {
 
 #[derive(Serialize, Deserialize)]
 pub enum BitbangEntryRequest {
-    Write { data: Vec<u8> },
-    Both { data: Vec<u8> },
-    Delay { clock_ticks: u32 },
-    Await { mask: u8, pattern: u8 },
+    Write {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    Both {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    Delay {
+        clock_ticks: u32,
+    },
+    Await {
+        mask: u8,
+        pattern: u8,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum BitbangEntryResponse {
     Write,
-    Both { data: Vec<u8> },
+    Both {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     Delay,
     Await,
 }
@@ -193,6 +295,7 @@ pub enum UartRequest {
         len: u32,
     },
     Write {
+        #[serde(with = "serde_bytes")]
         data: Vec<u8>,
     },
     SupportsNonblockingRead,
@@ -201,34 +304,63 @@ pub enum UartRequest {
 
 #[derive(Serialize, Deserialize)]
 pub enum UartResponse {
-    GetBaudrate { rate: u32 },
+    GetBaudrate {
+        rate: u32,
+    },
     SetBaudrate,
     SetBreak,
-    GetParity { parity: Parity },
+    GetParity {
+        parity: Parity,
+    },
     SetParity,
-    GetFlowControl { flow_control: FlowControl },
+    GetFlowControl {
+        flow_control: FlowControl,
+    },
     SetFlowControl,
-    GetDevicePath { path: String },
-    Read { data: Vec<u8> },
+    GetDevicePath {
+        path: String,
+    },
+    Read {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     Write,
-    SupportsNonblockingRead { has_support: bool },
-    RegisterNonblockingRead { channel: u32 },
+    SupportsNonblockingRead {
+        has_support: bool,
+    },
+    RegisterNonblockingRead {
+        channel: u32,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum SpiTransferRequest {
-    Read { len: u32 },
-    Write { data: Vec<u8> },
-    Both { data: Vec<u8> },
+    Read {
+        len: u32,
+    },
+    Write {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    Both {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     TpmPoll,
     GscReady,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum SpiTransferResponse {
-    Read { data: Vec<u8> },
+    Read {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     Write,
-    Both { data: Vec<u8> },
+    Both {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     TpmPoll,
     GscReady,
 }
@@ -313,14 +445,22 @@ pub enum SpiResponse {
 
 #[derive(Serialize, Deserialize)]
 pub enum I2cTransferRequest {
-    Read { len: u32 },
-    Write { data: Vec<u8> },
+    Read {
+        len: u32,
+    },
+    Write {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     GscReady,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum I2cTransferResponse {
-    Read { data: Vec<u8> },
+    Read {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     Write,
     GscReady,
 }
@@ -348,6 +488,7 @@ pub enum I2cRequest {
         timeout_millis: u32,
     },
     PrepareReadData {
+        #[serde(with = "serde_bytes")]
         data: Vec<u8>,
         sticky: bool,
     },
@@ -388,11 +529,143 @@ pub enum EmuResponse {
     Stop,
 }
 
+// A single TAP can be connected at a time, by one client.  The OpenOCD binary and adapter are
+// configured on the server, clients only choose the TAP and may lower the adapter speed.
+#[derive(Serialize, Deserialize)]
+pub enum JtagRequest {
+    Connect {
+        tap: JtagTap,
+        adapter_speed_khz: u64,
+    },
+    ConnectDmi {
+        tap: JtagTap,
+        adapter_speed_khz: u64,
+    },
+    ScanIdcodes {
+        adapter_speed_khz: u64,
+    },
+    Disconnect,
+    DmiRead {
+        addr: u32,
+    },
+    DmiWrite {
+        addr: u32,
+        value: u32,
+    },
+    TargetState,
+    ReadLcCtrlReg {
+        reg: LcCtrlReg,
+    },
+    WriteLcCtrlReg {
+        reg: LcCtrlReg,
+        value: u32,
+    },
+    ReadMemory {
+        addr: u32,
+        len: u32,
+    },
+    ReadMemory32 {
+        addr: u32,
+        len: u32,
+    },
+    WriteMemory {
+        addr: u32,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    WriteMemory32 {
+        addr: u32,
+        data: Vec<u32>,
+    },
+    Halt,
+    WaitHalt {
+        timeout_millis: u32,
+    },
+    Resume,
+    ResumeAt {
+        addr: u32,
+    },
+    Step,
+    StepAt {
+        addr: u32,
+    },
+    Reset {
+        run: bool,
+    },
+    ReadRiscvReg {
+        reg: RiscvReg,
+    },
+    WriteRiscvReg {
+        reg: RiscvReg,
+        value: u32,
+    },
+    SetBreakpoint {
+        addr: u32,
+        hw: bool,
+    },
+    RemoveBreakpoint {
+        addr: u32,
+    },
+    RemoveAllBreakpoints,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum JtagResponse {
+    Connect,
+    ConnectDmi,
+    ScanIdcodes {
+        idcodes: Vec<u32>,
+    },
+    Disconnect,
+    DmiRead {
+        value: u32,
+    },
+    DmiWrite,
+    TargetState {
+        state: TargetState,
+    },
+    ReadLcCtrlReg {
+        value: u32,
+    },
+    WriteLcCtrlReg,
+    ReadMemory {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    ReadMemory32 {
+        data: Vec<u32>,
+    },
+    WriteMemory,
+    WriteMemory32,
+    Halt,
+    WaitHalt,
+    Resume,
+    ResumeAt,
+    Step,
+    StepAt,
+    Reset,
+    ReadRiscvReg {
+        value: u32,
+    },
+    WriteRiscvReg,
+    SetBreakpoint,
+    RemoveBreakpoint,
+    RemoveAllBreakpoints,
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum ProxyRequest {
+    /// Sent by clients as the first request, in order to learn the version of the session and
+    /// negotiate the encoding of subsequent messages.  Sessions predating this request close
+    /// the connection upon receiving it.
+    Hello {
+        version: u32,
+        encodings: Vec<Encoding>,
+    },
     Provides,
     Bootstrap {
         options: BootstrapOptions,
+        #[serde(with = "serde_bytes")]
         payload: Vec<u8>,
     },
     ApplyPinStrapping {
@@ -404,10 +677,21 @@ pub enum ProxyRequest {
     ApplyDefaultConfigurationWithStrapping {
         strapping_name: String,
     },
+    AcquireLease {
+        resources: Vec<LeaseResource>,
+        duration_millis: u64,
+    },
+    ReleaseLease,
+    GetLeases,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum ProxyResponse {
+    /// The session switches to `encoding` immediately after sending this response.
+    Hello {
+        version: u32,
+        encoding: Encoding,
+    },
     Provides {
         provides_map: HashMap<String, String>,
     },
@@ -415,4 +699,65 @@ pub enum ProxyResponse {
     ApplyPinStrapping,
     RemovePinStrapping,
     ApplyDefaultConfigurationWithStrapping,
+    AcquireLease,
+    ReleaseLease,
+    GetLeases {
+        leases: Vec<LeaseInfo>,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn uart_data(msg: Message) -> Vec<u8> {
+        match msg {
+            Message::Async {
+                msg: AsyncMessage::UartData { data },
+                ..
+            } => data,
+            _ => panic!("Unexpected message"),
+        }
+    }
+
+    #[test]
+    fn test_encodings() -> Result<()> {
+        let data: Vec<u8> = (0..=255).collect();
+        for encoding in Encoding::SUPPORTED {
+            let mut buf = Vec::new();
+            for _ in 0..2 {
+                let msg = Message::Async {
+                    channel: 1,
+                    msg: AsyncMessage::UartData { data: data.clone() },
+                };
+                encoding.encode(&mut buf, &msg)?;
+            }
+            let (msg, len) = encoding.decode::<Message>(&buf)?.unwrap();
+            assert_eq!(uart_data(msg), data);
+            assert_eq!(len * 2, buf.len());
+            // Incomplete messages are left for later.
+            assert!(encoding
+                .decode::<Message>(&buf[len..2 * len - 1])?
+                .is_none());
+            let (msg, _) = encoding.decode::<Message>(&buf[len..])?.unwrap();
+            assert_eq!(uart_data(msg), data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_compatibility() -> Result<()> {
+        // Byte arrays remain arrays of numbers in JSON, as understood by older peers.
+        let msg = AsyncMessage::UartData { data: vec![1, 2] };
+        assert_eq!(
+            serde_json::to_string(&msg)?,
+            r#"{"UartData":{"data":[1,2]}}"#
+        );
+        assert_eq!(
+            Encoding::select(&[Encoding::Json, Encoding::MessagePack]),
+            Encoding::Json
+        );
+        assert_eq!(Encoding::select(&[]), Encoding::Json);
+        Ok(())
+    }
 }
@@ -0,0 +1,562 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Authentication and authorization of session clients.
+//!
+//! By default, a session accepts plain TCP connections from anyone able to reach its port.
+//! Optionally, connections can be protected by TLS, with clients authenticating either by a
+//! certificate signed by a given CA, or by a pre-shared key (PSK).  An access policy can then
+//! deny particular classes of requests to each client, e.g. to allow only reading from UARTs.
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::Args;
+use openssl::ex_data::Index;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{
+    Ssl, SslAcceptor, SslConnector, SslContextBuilder, SslMethod, SslOptions, SslRef,
+    SslSessionCacheMode, SslStream, SslVerifyMode, SslVersion,
+};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::X509;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::impl_serializable_error;
+use crate::proxy::protocol::{GpioRequest, ProxyRequest, Request, UartRequest};
+
+/// Classes of requests, which can be individually denied by an `AccessPolicy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, strum::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum RequestClass {
+    /// Reading or monitoring GPIO levels.
+    GpioRead,
+    /// Changing GPIO levels or configuration, including bitbanging.
+    GpioWrite,
+    /// Receiving from UARTs, and querying their settings.
+    UartRead,
+    /// Transmitting on UARTs, and changing their settings.
+    UartWrite,
+    Spi,
+    I2c,
+    Jtag,
+    Emu,
+    /// Applying or removing named pin strapping.
+    PinStrapping,
+    Bootstrap,
+    /// Restoring the default configuration of all pins and buses.
+    Configuration,
+}
+
+impl RequestClass {
+    /// Returns the class of the given request, or `None` for the requests which merely query
+    /// the capabilities of the session, and are always permitted.
+    pub fn of(req: &Request) -> Option<Self> {
+        Some(match req {
+            // Permissions for leases are checked against `LeaseResource::class()`.
+            Request::GetCapabilities
+            | Request::Proxy(
+                ProxyRequest::Hello { .. }
+                | ProxyRequest::Provides
+                | ProxyRequest::AcquireLease { .. }
+                | ProxyRequest::ReleaseLease
+                | ProxyRequest::GetLeases,
+            ) => return None,
+            Request::ApplyDefaultConfiguration
+            | Request::Proxy(ProxyRequest::ApplyDefaultConfigurationWithStrapping { .. }) => {
+                Self::Configuration
+            }
+            Request::Gpio {
+                command: GpioRequest::Read | GpioRequest::AnalogRead,
+                ..
+            }
+            | Request::GpioMonitoring { .. } => Self::GpioRead,
+            Request::Gpio { .. }
+            | Request::GpioBitbanging { .. }
+            | Request::GpioDacBanging { .. } => Self::GpioWrite,
+            Request::Uart {
+                command:
+                    UartRequest::GetBaudrate
+                    | UartRequest::GetParity
+                    | UartRequest::GetFlowControl
+                    | UartRequest::GetDevicePath
+                    | UartRequest::Read { .. }
+                    | UartRequest::SupportsNonblockingRead
+                    | UartRequest::RegisterNonblockingRead,
+                ..
+            } => Self::UartRead,
+            Request::Uart { .. } => Self::UartWrite,
+            Request::Spi { .. } => Self::Spi,
+            Request::I2c { .. } => Self::I2c,
+            Request::Emu { .. } => Self::Emu,
+            Request::Jtag { .. } => Self::Jtag,
+            Request::Proxy(ProxyRequest::Bootstrap { .. }) => Self::Bootstrap,
+            Request::Proxy(
+                ProxyRequest::ApplyPinStrapping { .. } | ProxyRequest::RemovePinStrapping { .. },
+            ) => Self::PinStrapping,
+        })
+    }
+}
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum AccessError {
+    #[error("Access denied: {0} requests are not permitted for this client")]
+    Denied(RequestClass),
+}
+impl_serializable_error!(AccessError);
+
+/// Permissions of one client, or of unauthenticated clients.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientPolicy {
+    /// Hex-encoded pre-shared key, by which the client authenticates under its name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub psk: Option<String>,
+    /// Classes of requests which the client may not issue.
+    #[serde(default)]
+    pub deny: HashSet<RequestClass>,
+}
+
+impl ClientPolicy {
+    pub fn check(&self, class: RequestClass) -> Result<(), AccessError> {
+        if self.deny.contains(&class) {
+            return Err(AccessError::Denied(class));
+        }
+        Ok(())
+    }
+}
+
+/// Permissions of the clients of a session, read from an HJSON file such as:
+///
+/// ```hjson
+/// {
+///   // Applies to unauthenticated clients, and to authenticated clients not listed below.
+///   default: { deny: ["bootstrap", "pin_strapping", "configuration"] },
+///   clients: {
+///     // Authenticated by a certificate with this common name.
+///     "ci-runner": {},
+///     // Authenticated by pre-shared key, with read-only access to UARTs and GPIOs.
+///     "dashboard": {
+///       psk: "8d1fbd37a1c4a9b06cb8e3b4f0a2e6d5",
+///       deny: ["gpio_write", "uart_write", "spi", "i2c", "jtag", "emu", "bootstrap",
+///              "pin_strapping", "configuration"],
+///     },
+///   },
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessPolicy {
+    #[serde(default)]
+    pub default: ClientPolicy,
+    #[serde(default)]
+    pub clients: HashMap<String, ClientPolicy>,
+}
+
+impl AccessPolicy {
+    pub fn read_from_file(path: &Path) -> Result<Self> {
+        let policy: Self = deser_hjson::from_str(
+            &std::fs::read_to_string(path).with_context(|| format!("Failed to open {path:?}"))?,
+        )?;
+        ensure!(
+            policy.default.psk.is_none(),
+            "The default policy cannot have a pre-shared key"
+        );
+        policy.psks()?;
+        Ok(policy)
+    }
+
+    /// Returns the policy applying to a client with the given authenticated identity.
+    pub fn client(&self, identity: Option<&str>) -> &ClientPolicy {
+        identity
+            .and_then(|name| self.clients.get(name))
+            .unwrap_or(&self.default)
+    }
+
+    /// Returns the decoded pre-shared keys, by client name.
+    fn psks(&self) -> Result<HashMap<String, Vec<u8>>> {
+        self.clients
+            .iter()
+            .filter_map(|(name, client)| Some((name, client.psk.as_ref()?)))
+            .map(|(name, psk)| {
+                let key = hex::decode(psk)
+                    .with_context(|| format!("Invalid pre-shared key of client {name:?}"))?;
+                ensure!(!key.is_empty(), "Empty pre-shared key of client {name:?}");
+                Ok((name.clone(), key))
+            })
+            .collect()
+    }
+}
+
+/// Certificate chain and private key, by which either end of a connection authenticates.
+pub struct TlsIdentity {
+    pub chain: Vec<X509>,
+    pub key: PKey<Private>,
+}
+
+impl TlsIdentity {
+    pub fn read_from_files(cert: &Path, key: &Path) -> Result<Self> {
+        let key = std::fs::read(key).with_context(|| format!("Failed to open {key:?}"))?;
+        Ok(Self {
+            chain: read_certificates(cert)?,
+            key: PKey::private_key_from_pem(&key)?,
+        })
+    }
+
+    fn apply(&self, builder: &mut SslContextBuilder) -> Result<()> {
+        let (leaf, intermediates) = self
+            .chain
+            .split_first()
+            .context("Empty certificate chain")?;
+        builder.set_certificate(leaf)?;
+        for cert in intermediates {
+            builder.add_extra_chain_cert(cert.clone())?;
+        }
+        builder.set_private_key(&self.key)?;
+        builder.check_private_key()?;
+        Ok(())
+    }
+}
+
+/// Reads all PEM-encoded certificates from the given file.
+pub fn read_certificates(path: &Path) -> Result<Vec<X509>> {
+    let pem = std::fs::read(path).with_context(|| format!("Failed to open {path:?}"))?;
+    let certs = X509::stack_from_pem(&pem)?;
+    ensure!(!certs.is_empty(), "No certificates in {path:?}");
+    Ok(certs)
+}
+
+/// Server side TLS settings of a session.
+pub struct TlsServerConfig {
+    acceptor: SslAcceptor,
+    /// Client name stored by the PSK callback, upon successful lookup of its key.
+    psk_identity: Index<Ssl, String>,
+    /// Clients must authenticate, by either certificate or pre-shared key.
+    client_auth: bool,
+}
+
+impl TlsServerConfig {
+    /// The server certificate can be omitted if all clients authenticate by pre-shared keys
+    /// from the `policy`.  Clients without pre-shared key must present a certificate signed by
+    /// one of `client_ca`.
+    pub fn new(
+        identity: Option<&TlsIdentity>,
+        client_ca: &[X509],
+        policy: &AccessPolicy,
+    ) -> Result<Self> {
+        let psks = policy.psks()?;
+        ensure!(
+            identity.is_some() || !psks.is_empty(),
+            "TLS requires either a server certificate, or clients with pre-shared keys"
+        );
+        let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls_server())?;
+        // Without resumption, a reused session means that a pre-shared key was used.
+        builder.set_options(SslOptions::NO_TICKET);
+        builder.set_session_cache_mode(SslSessionCacheMode::OFF);
+        builder.set_session_id_context(b"opentitansession")?;
+        if let Some(identity) = identity {
+            identity.apply(&mut builder)?;
+        }
+        if !client_ca.is_empty() {
+            let mut store = X509StoreBuilder::new()?;
+            for ca in client_ca {
+                store.add_cert(ca.clone())?;
+                builder.add_client_ca(ca)?;
+            }
+            builder.set_verify_cert_store(store.build())?;
+            // Clients authenticating by pre-shared key present no certificate, hence absence of
+            // a certificate is rejected only after the handshake, by `authenticate()`.
+            builder.set_verify(SslVerifyMode::PEER);
+        }
+        let psk_identity = Ssl::new_ex_index()?;
+        if !psks.is_empty() {
+            builder.set_psk_server_callback(move |ssl, identity, psk| {
+                let Some((name, key)) = identity
+                    .and_then(|id| std::str::from_utf8(id).ok())
+                    .and_then(|id| psks.get_key_value(id))
+                else {
+                    // Unknown client, fail the handshake.
+                    return Ok(0);
+                };
+                if key.len() > psk.len() {
+                    return Ok(0);
+                }
+                psk[..key.len()].copy_from_slice(key);
+                ssl.set_ex_data(psk_identity, name.clone());
+                Ok(key.len())
+            });
+        }
+        Ok(Self {
+            acceptor: builder.build(),
+            psk_identity,
+            client_auth: !client_ca.is_empty(),
+        })
+    }
+
+    /// Prepares the server side of a TLS connection.  The handshake is performed by calling
+    /// `SslStream::accept()` until it succeeds, as the socket permits.
+    pub fn accept<S: Read + Write>(&self, stream: S) -> Result<SslStream<S>> {
+        let ssl = Ssl::new(self.acceptor.context())?;
+        Ok(SslStream::new(ssl, stream)?)
+    }
+
+    /// Returns the authenticated identity of the client after a completed handshake: the name
+    /// under which its pre-shared key is listed, or the common name of its certificate.  Fails
+    /// if client authentication is required, but the client presented neither.
+    pub fn authenticate(&self, ssl: &SslRef) -> Result<Option<String>> {
+        // The PSK callback may have been called for a key which was eventually not used.
+        if ssl.session_reused() {
+            let name = ssl.ex_data(self.psk_identity);
+            return Ok(Some(name.context("Unknown pre-shared key")?.clone()));
+        }
+        match ssl.peer_certificate() {
+            Some(cert) => Ok(cert
+                .subject_name()
+                .entries_by_nid(Nid::COMMONNAME)
+                .next()
+                .and_then(|entry| entry.data().as_utf8().ok())
+                .map(|name| name.to_string())),
+            None if self.client_auth => {
+                bail!("Client presented neither certificate nor pre-shared key")
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Client side TLS settings for connecting to a session.
+#[derive(Default)]
+pub struct TlsClientConfig {
+    /// Certificates trusted to sign the certificate of the server.  May be empty when
+    /// authenticating by pre-shared key, as that also authenticates the server.
+    pub ca: Vec<X509>,
+    /// Certificate chain by which this client authenticates.
+    pub identity: Option<TlsIdentity>,
+    /// Client name and pre-shared key by which this client authenticates.
+    pub psk: Option<(String, Vec<u8>)>,
+}
+
+impl TlsClientConfig {
+    /// Performs the TLS handshake with the server named `host` over `stream`.
+    pub fn connect<S: Read + Write + Debug>(&self, host: &str, stream: S) -> Result<SslStream<S>> {
+        ensure!(
+            !self.ca.is_empty() || self.psk.is_some(),
+            "TLS requires a CA certificate for verifying the server, or a pre-shared key"
+        );
+        let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+        builder.set_min_proto_version(Some(SslVersion::TLS1_3))?;
+        if self.ca.is_empty() {
+            builder.set_verify(SslVerifyMode::NONE);
+        } else {
+            let mut store = X509StoreBuilder::new()?;
+            for ca in &self.ca {
+                store.add_cert(ca.clone())?;
+            }
+            builder.set_cert_store(store.build());
+        }
+        if let Some(identity) = &self.identity {
+            identity.apply(&mut builder)?;
+        }
+        if let Some((name, key)) = &self.psk {
+            // OpenSSL uses external pre-shared keys in TLS 1.3 only with this cipher suite.
+            builder.set_ciphersuites("TLS_AES_128_GCM_SHA256")?;
+            let (name, key) = (name.clone(), key.clone());
+            builder.set_psk_client_callback(move |_ssl, _hint, identity, psk| {
+                // The identity is passed as a NUL-terminated string.
+                if name.len() >= identity.len() || key.len() > psk.len() {
+                    return Ok(0);
+                }
+                identity[..name.len()].copy_from_slice(name.as_bytes());
+                identity[name.len()] = 0;
+                psk[..key.len()].copy_from_slice(&key);
+                Ok(key.len())
+            });
+        }
+        builder
+            .build()
+            .connect(host, stream)
+            .map_err(|e| anyhow!("TLS handshake with {host} failed: {e}"))
+    }
+}
+
+/// Command line options of the session server concerning TLS and access control.  TLS is
+/// enabled by giving a server certificate, or an access policy with pre-shared keys.
+#[derive(Clone, Debug, Default, Args)]
+pub struct SessionSecurityOpts {
+    /// PEM file with the certificate chain of the session server.
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM file with the private key of the session server.
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// PEM file with CA certificates, clients without pre-shared key must present a
+    /// certificate signed by one of them.
+    #[arg(long, requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+    /// HJSON file with the permissions and pre-shared keys of clients.
+    #[arg(long)]
+    pub access_policy: Option<PathBuf>,
+}
+
+/// Security settings of a session.
+#[derive(Default)]
+pub struct SessionSecurity {
+    /// If present, connections must use TLS.
+    pub tls: Option<TlsServerConfig>,
+    pub policy: AccessPolicy,
+}
+
+impl SessionSecurityOpts {
+    pub fn load(&self) -> Result<SessionSecurity> {
+        let policy = match &self.access_policy {
+            Some(path) => AccessPolicy::read_from_file(path)?,
+            None => AccessPolicy::default(),
+        };
+        let identity = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some(TlsIdentity::read_from_files(cert, key)?),
+            (None, None) => None,
+            _ => bail!("--tls-cert and --tls-key must be given together"),
+        };
+        let client_ca = match &self.tls_client_ca {
+            Some(path) => read_certificates(path)?,
+            None => Vec::new(),
+        };
+        let tls = if identity.is_some() || !policy.psks()?.is_empty() {
+            Some(TlsServerConfig::new(
+                identity.as_ref(),
+                &client_ca,
+                &policy,
+            )?)
+        } else {
+            None
+        };
+        Ok(SessionSecurity { tls, policy })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::proxy::test::start_session;
+    use crate::transport::proxy::Proxy;
+    use crate::transport::Transport;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+
+    /// Generates a self-signed certificate for `name`, which serves as its own CA.
+    fn self_signed(name: &str) -> Result<TlsIdentity> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+        let mut subject = X509NameBuilder::new()?;
+        subject.append_entry_by_nid(Nid::COMMONNAME, name)?;
+        let subject = subject.build();
+        let mut builder = X509Builder::new()?;
+        builder.set_version(2)?;
+        let serial = BigNum::from_u32(1)?.to_asn1_integer()?;
+        builder.set_serial_number(&serial)?;
+        builder.set_subject_name(&subject)?;
+        builder.set_issuer_name(&subject)?;
+        builder.set_pubkey(&key)?;
+        let (not_before, not_after) = (Asn1Time::days_from_now(0)?, Asn1Time::days_from_now(1)?);
+        builder.set_not_before(&not_before)?;
+        builder.set_not_after(&not_after)?;
+        let san = SubjectAlternativeName::new()
+            .dns(name)
+            .build(&builder.x509v3_context(None, None))?;
+        builder.append_extension(san)?;
+        builder.sign(&key, MessageDigest::sha256())?;
+        Ok(TlsIdentity {
+            chain: vec![builder.build()],
+            key,
+        })
+    }
+
+    fn psk_client(name: &str, key: &[u8]) -> TlsClientConfig {
+        TlsClientConfig {
+            psk: Some((name.to_string(), key.to_vec())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_request_class() {
+        let read = Request::Uart {
+            id: "CONSOLE".to_string(),
+            command: UartRequest::GetBaudrate,
+        };
+        assert_eq!(RequestClass::of(&read), Some(RequestClass::UartRead));
+        let write = Request::Gpio {
+            id: "RESET".to_string(),
+            command: GpioRequest::AnalogWrite { value: 1.0 },
+        };
+        assert_eq!(RequestClass::of(&write), Some(RequestClass::GpioWrite));
+        assert_eq!(RequestClass::of(&Request::GetCapabilities), None);
+        assert_eq!(RequestClass::PinStrapping.to_string(), "pin_strapping");
+    }
+
+    #[test]
+    fn test_tls_session() -> Result<()> {
+        let server = self_signed("localhost")?;
+        let admin = self_signed("admin")?;
+        let psk: Vec<u8> = (0..16).collect();
+        let policy: AccessPolicy = deser_hjson::from_str(
+            r#"{
+                default: { deny: ["gpio_write", "bootstrap", "pin_strapping"] },
+                clients: {
+                    admin: {},
+                    viewer: { psk: "000102030405060708090a0b0c0d0e0f", deny: ["gpio_write"] },
+                },
+            }"#,
+        )?;
+        let tls = TlsServerConfig::new(Some(&server), &admin.chain, &policy)?;
+        let port = start_session(SessionSecurity {
+            tls: Some(tls),
+            policy,
+        })?;
+
+        // Authenticated by pre-shared key, with read-only access to GPIOs.
+        let viewer = Proxy::open_tls(None, port, &psk_client("viewer", &psk))?;
+        let pin = viewer.gpio_pin("RESET")?;
+        pin.read()?;
+        let err = pin.write(true).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AccessError>(),
+            Some(AccessError::Denied(RequestClass::GpioWrite))
+        ));
+
+        // Authenticated by certificate, with full access.
+        let ca = server.chain.clone();
+        let client = Proxy::open_tls(
+            None,
+            port,
+            &TlsClientConfig {
+                ca: ca.clone(),
+                identity: Some(admin),
+                psk: None,
+            },
+        )?;
+        client.gpio_pin("RESET")?.write(true)?;
+
+        // Wrong key, no client authentication, or no TLS at all.
+        let wrong = Proxy::open_tls(None, port, &psk_client("viewer", &[0; 16]));
+        assert!(wrong.and_then(|p| p.capabilities()).is_err());
+        let anonymous = TlsClientConfig {
+            ca,
+            ..Default::default()
+        };
+        let anonymous = Proxy::open_tls(None, port, &anonymous);
+        assert!(anonymous.and_then(|p| p.capabilities()).is_err());
+        assert!(Proxy::open(None, port)?.capabilities().is_err());
+        Ok(())
+    }
+}
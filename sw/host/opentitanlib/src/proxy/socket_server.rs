@@ -8,6 +8,7 @@ use mio::net::TcpListener;
 use mio::net::TcpStream;
 use mio::{Events, Interest, Poll, Registry, Token};
 use mio_signals::{Signal, SignalSet, Signals};
+use openssl::ssl::{ErrorCode, SslStream};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
@@ -16,11 +17,12 @@ use std::io::{ErrorKind, Read, Write};
 use std::marker::PhantomData;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use super::protocol::Encoding;
+use super::security::TlsServerConfig;
 use super::CommandHandler;
 use super::ExtraEventHandler;
 
 const BUFFER_SIZE: usize = 8192;
-const EOL_CODE: u8 = b'\n';
 
 pub fn get_next_token() -> Token {
     static TOCKEN_COUNTER: AtomicUsize = AtomicUsize::new(0);
@@ -28,9 +30,11 @@ pub fn get_next_token() -> Token {
 }
 
 /// This struct listens on a TCP socket, and maintains a number of concurrent connections,
-/// receiving serialized JSON representations of `Msg`, passing them to the given
-/// `CommandHandler` to obtain responses to be sent as socket flow contol permits.  Note that
-/// this implementaion is not specific to (and does not refer to) any particular protocol.
+/// receiving serialized representations of `Msg`, passing them to the given `CommandHandler` to
+/// obtain responses to be sent as socket flow contol permits.  Note that this implementaion is
+/// not specific to (and does not refer to) any particular protocol.  Messages are encoded as
+/// JSON, until the `CommandHandler` reports that another `Encoding` has been negotiated.
+/// Optionally, all connections are protected by TLS.
 pub struct JsonSocketServer<
     Msg: DeserializeOwned + Serialize,
     T: CommandHandler<Msg, E>,
@@ -41,6 +45,7 @@ pub struct JsonSocketServer<
     poll: Poll,
     socket: TcpListener,
     socket_token: Token,
+    tls: Option<TlsServerConfig>,
     signals: Signals,
     signal_token: Token,
     nonblocking_help_token: Token,
@@ -56,6 +61,7 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
         command_handler: T,
         extra_event_handler: E,
         mut socket: TcpListener,
+        tls: Option<TlsServerConfig>,
     ) -> Result<Self> {
         let poll = Poll::new()?;
         let socket_token = get_next_token();
@@ -76,6 +82,7 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
             poll,
             socket,
             socket_token,
+            tls,
             signals,
             signal_token,
             nonblocking_help_token,
@@ -138,7 +145,16 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
                                 token,
                                 Interest::READABLE | Interest::WRITABLE,
                             )?;
-                            entry.insert(Connection::new(conn_socket));
+                            let conn = match &self.tls {
+                                Some(tls) => {
+                                    Connection::new(Stream::Tls(Box::new(tls.accept(conn_socket)?)))
+                                }
+                                None => {
+                                    self.command_handler.connection_opened(token, None);
+                                    Connection::new(Stream::Plain(conn_socket))
+                                }
+                            };
+                            entry.insert(conn);
                         }
                         Occupied(_) => {
                             panic!("JsonSocketServer error: token colision");
@@ -177,10 +193,29 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
     fn process_connection(&mut self, event: &Event) -> Result<bool> {
         match self.connection_map.get_mut(&event.token()) {
             Some(conn) => {
+                let mut opened = false;
+                if conn.handshaking {
+                    if !conn.handshake()? {
+                        return Ok(false);
+                    }
+                    let identity = match (&conn.socket, &self.tls) {
+                        (Stream::Tls(stream), Some(tls)) => tls.authenticate(stream.ssl())?,
+                        _ => None,
+                    };
+                    log::info!(
+                        "Connection id:{:#X} authenticated as {:?}",
+                        event.token().0,
+                        identity
+                    );
+                    self.command_handler
+                        .connection_opened(event.token(), identity.as_deref());
+                    opened = true;
+                }
                 if event.is_writable() {
                     conn.write()?;
                 }
-                if event.is_readable() {
+                // Requests may have arrived along with the final handshake messages.
+                if event.is_readable() || opened {
                     conn.read()?;
                     Self::process_any_requests(
                         conn,
@@ -204,30 +239,16 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
             .connection_map
             .remove(&event.token())
             .expect("Missing connection this should never happend!!!");
-        self.poll.registry().deregister(&mut conn.socket)?;
+        self.poll.registry().deregister(conn.socket.tcp_mut())?;
+        self.command_handler.connection_closed(event.token());
         // As `conn` runs out of scope here, its `drop()` method will close the OS handle, which
         // in turn causes TCP/IP connection shutdown to be signalled to the remote end.
         Ok(())
     }
 
-    /// Check if the buffer contains at least one full JSON request.  If so, remove it from the
-    /// buffer, decode and return it.
-    fn get_complete_request(conn: &mut Connection) -> Result<Option<Msg>> {
-        if let Some(n) = conn.rx_buf.iter().position(|c| *c == EOL_CODE) {
-            let res = serde_json::from_slice::<Msg>(&conn.rx_buf[..n])?;
-            if n + 1 < conn.rx_buf.len() {
-                // Shuffling bytes around in a Vec is expensive, but realistically, as the
-                // clients would be waiting for response to each request before sending the next
-                // request, this code will rarely if ever execute.
-                conn.rx_buf.rotate_left(n + 1);
-            }
-            conn.rx_buf.resize(conn.rx_buf.len() - n - 1, 0);
-            return Ok(Some(res));
-        }
-        Ok(None)
-    }
-
     // Look for any completely received requests in the rx_buf, and handle them one by one.
+    // Clients may send several requests without waiting for responses, so the buffer is only
+    // compacted once all complete requests have been handled.
     fn process_any_requests(
         conn: &mut Connection,
         command_handler: &mut T,
@@ -235,19 +256,70 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
         registry: &Registry,
         extra_event_handler: &mut E,
     ) -> Result<()> {
-        while let Some(request) = Self::get_complete_request(conn)? {
+        let mut consumed = 0;
+        while let Some((request, len)) = conn.encoding.decode::<Msg>(&conn.rx_buf[consumed..])? {
+            consumed += len;
             // One complete request received, execute it.
             let resp =
                 command_handler.execute_cmd(conn_token, registry, extra_event_handler, &request)?;
             conn.transmit_outgoing_msg(resp)?;
+            // The response to a negotiation is sent in the old encoding.
+            if let Some(encoding) = command_handler.negotiated_encoding(&request) {
+                conn.encoding = encoding;
+            }
         }
+        conn.rx_buf.drain(..consumed);
         Ok(())
     }
 }
 
+/// Socket of a connection, possibly protected by TLS.
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<SslStream<TcpStream>>),
+}
+
+impl Stream {
+    fn tcp_mut(&mut self) -> &mut TcpStream {
+        match self {
+            Self::Plain(stream) => stream,
+            Self::Tls(stream) => stream.get_mut(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.read(buf),
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.write(buf),
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.flush(),
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
 /// Represents one connection with a remote OpenTitan tool invocation.
 pub struct Connection {
-    socket: TcpStream,
+    socket: Stream,
+    /// The TLS handshake has not yet completed, no requests can be processed.
+    handshaking: bool,
+    /// Encoding of messages in both directions.
+    encoding: Encoding,
     /// Outgoing data waiting to be written when the socket permits.
     tx_buf: Vec<u8>,
     /// Data received from the remote end, but not yet decoded into `Msg`.
@@ -261,9 +333,11 @@ pub struct Connection {
 }
 
 impl Connection {
-    fn new(soc: TcpStream) -> Self {
+    fn new(soc: Stream) -> Self {
         Self {
+            handshaking: matches!(soc, Stream::Tls(_)),
             socket: soc,
+            encoding: Encoding::Json,
             tx_buf: Vec::new(),
             rx_buf: Vec::new(),
             rx_eof: false,
@@ -273,14 +347,30 @@ impl Connection {
 
     pub fn transmit_outgoing_msg<T: Serialize>(&mut self, msg: T) -> Result<()> {
         // Encode response into tx_buf.
-        serde_json::to_writer(&mut self.tx_buf, &msg)?;
-        self.tx_buf.push(EOL_CODE);
+        self.encoding.encode(&mut self.tx_buf, &msg)?;
         // Transmit as much as possible without blocking, leaving any remnant in
         // tx_buf.  poll() will tell us when more can be written.
         self.write()?;
         Ok(())
     }
 
+    /// Advance the TLS handshake as far as the socket permits, returning whether it completed.
+    fn handshake(&mut self) -> Result<bool> {
+        let Stream::Tls(stream) = &mut self.socket else {
+            return Ok(true);
+        };
+        match stream.accept() {
+            Ok(()) => {
+                self.handshaking = false;
+                Ok(true)
+            }
+            Err(e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => {
+                Ok(false)
+            }
+            Err(e) => bail!("TLS handshake failed: {}", e),
+        }
+    }
+
     // Fill rx_buf with as much data as is available on the socket.
     fn read(&mut self) -> Result<()> {
         let mut rx_buf_len: usize = self.rx_buf.len();
@@ -311,14 +401,7 @@ impl Connection {
         while !self.tx_buf.is_empty() {
             match self.socket.write(&self.tx_buf) {
                 Ok(n) => {
-                    if n < self.tx_buf.len() {
-                        // Shuffling bytes around in a Vec is expensive, but realistically, as
-                        // the clients would be waiting for response to each request before
-                        // sending the next request, it is unlikely that the OS transmit buffer
-                        // would ever fill up and cause partial writes.
-                        self.tx_buf.rotate_left(n);
-                    }
-                    self.tx_buf.resize(self.tx_buf.len() - n, 0);
+                    self.tx_buf.drain(..n);
                 }
                 Err(err) => {
                     if err.kind() != ErrorKind::WouldBlock {
@@ -0,0 +1,668 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+// ROM_EXT rescue protocol carried over USB DFU.
+//
+// The ROM_EXT presents a single DFU interface while in rescue mode.  The rescue mode is
+// selected with the `SET_MODE` class request (an extension to DFU 1.1), carrying the same
+// four-character tag as the serial protocol (e.g. `RESQ`, `BLOG` or `OWNR`).  Data is then
+// moved with ordinary DFU `DNLOAD` and `UPLOAD` requests, and the outcome of each request is
+// read back with `GETSTATUS`.
+//
+// `SET_MODE` is provisional: no ROM_EXT in this tree implements the DFU transport yet, so the
+// request number and its payload may still change to match the device side once it lands.
+
+use anyhow::{bail, Result};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::app::TransportWrapper;
+use crate::io::uart::Uart;
+use crate::rescue::{Rescue, RescueError};
+use crate::util::usb::UsbBackend;
+
+const USB_CLASS_APP: u8 = 0xFE;
+const USB_SUBCLASS_DFU: u8 = 0x01;
+
+const USB_DFU_DNLOAD: u8 = 1;
+const USB_DFU_UPLOAD: u8 = 2;
+const USB_DFU_GETSTATUS: u8 = 3;
+const USB_DFU_CLRSTATUS: u8 = 4;
+const USB_DFU_ABORT: u8 = 6;
+// Vendor request, see the provisional note above.
+const USB_DFU_SET_MODE: u8 = 0x80;
+
+const DFU_STATUS_OK: u8 = 0x00;
+
+const DFU_STATE_DFU_IDLE: u8 = 0x02;
+const DFU_STATE_DOWNLOAD_SYNC: u8 = 0x03;
+const DFU_STATE_DOWNLOAD_BUSY: u8 = 0x04;
+const DFU_STATE_DOWNLOAD_IDLE: u8 = 0x05;
+const DFU_STATE_MANIFEST_SYNC: u8 = 0x06;
+const DFU_STATE_MANIFEST: u8 = 0x07;
+const DFU_STATE_UPLOAD_IDLE: u8 = 0x09;
+const DFU_STATE_ERROR: u8 = 0x0A;
+
+/// The default `wTransferSize` if the DFU functional descriptor cannot be found.
+const DEFAULT_TRANSFER_SIZE: usize = 2048;
+
+/// Control transfer access to a DFU interface.  This is implemented by `UsbBackend`, and can
+/// be implemented by an in-process model of the ROM_EXT for testing.
+pub trait DfuDevice {
+    /// Issue a USB control request with optional host-to-device data.
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+    ) -> Result<usize>;
+
+    /// Issue a USB control request with optional device-to-host data.
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+    ) -> Result<usize>;
+}
+
+impl DfuDevice for UsbBackend {
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+    ) -> Result<usize> {
+        UsbBackend::write_control(self, request_type, request, value, index, buf)
+    }
+
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        UsbBackend::read_control(self, request_type, request, value, index, buf)
+    }
+}
+
+/// The response to a DFU `GETSTATUS` request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DfuStatus {
+    status: u8,
+    poll_timeout: Duration,
+    state: u8,
+}
+
+/// An open DFU interface.
+struct DfuConnection {
+    device: Box<dyn DfuDevice>,
+    interface: u8,
+    transfer_size: usize,
+}
+
+impl DfuConnection {
+    fn request_out() -> u8 {
+        rusb::request_type(
+            rusb::Direction::Out,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        )
+    }
+
+    fn request_in() -> u8 {
+        rusb::request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        )
+    }
+
+    fn get_status(&self) -> Result<DfuStatus> {
+        let mut response = [0u8; 6];
+        let rc = self.device.read_control(
+            Self::request_in(),
+            USB_DFU_GETSTATUS,
+            0,
+            self.interface as u16,
+            &mut response,
+        )?;
+        if rc != response.len() {
+            bail!(RescueError::Dfu(format!("Short GETSTATUS response: {rc}")));
+        }
+        Ok(DfuStatus {
+            status: response[0],
+            poll_timeout: Duration::from_millis(u64::from_le_bytes([
+                response[1],
+                response[2],
+                response[3],
+                0,
+                0,
+                0,
+                0,
+                0,
+            ])),
+            state: response[4],
+        })
+    }
+
+    fn clear_status(&self) -> Result<()> {
+        self.device.write_control(
+            Self::request_out(),
+            USB_DFU_CLRSTATUS,
+            0,
+            self.interface as u16,
+            &[],
+        )?;
+        Ok(())
+    }
+
+    fn abort(&self) -> Result<()> {
+        self.device.write_control(
+            Self::request_out(),
+            USB_DFU_ABORT,
+            0,
+            self.interface as u16,
+            &[],
+        )?;
+        Ok(())
+    }
+
+    /// Brings the interface back to `dfuIDLE` after an error or an interrupted transfer.
+    fn recover(&self) -> Result<()> {
+        match self.get_status()?.state {
+            DFU_STATE_DFU_IDLE => Ok(()),
+            DFU_STATE_ERROR => self.clear_status(),
+            DFU_STATE_DOWNLOAD_IDLE | DFU_STATE_UPLOAD_IDLE => self.abort(),
+            s => bail!(RescueError::Dfu(format!("Unexpected DFU state {s}"))),
+        }
+    }
+
+    /// Polls `GETSTATUS` until the device leaves its busy states.  Returns an error if the
+    /// device reports a failure or settles in a state other than `expected`.
+    fn wait_for_state(&self, expected: u8) -> Result<()> {
+        loop {
+            let status = self.get_status()?;
+            if status.status != DFU_STATUS_OK {
+                let _ = self.clear_status();
+                bail!(RescueError::Dfu(format!(
+                    "Unexpected DFU status {:#x}",
+                    status.status
+                )));
+            }
+            match status.state {
+                s if s == expected => return Ok(()),
+                DFU_STATE_DOWNLOAD_SYNC
+                | DFU_STATE_DOWNLOAD_BUSY
+                | DFU_STATE_MANIFEST_SYNC
+                | DFU_STATE_MANIFEST => std::thread::sleep(status.poll_timeout),
+                s => bail!(RescueError::Dfu(format!("Unexpected DFU state {s}"))),
+            }
+        }
+    }
+
+    fn set_mode(&self, mode: [u8; 4]) -> Result<()> {
+        self.recover()?;
+        self.device.write_control(
+            Self::request_out(),
+            USB_DFU_SET_MODE,
+            0,
+            self.interface as u16,
+            &mode,
+        )?;
+        let status = self.get_status()?;
+        if status.status != DFU_STATUS_OK {
+            self.clear_status()?;
+            return Err(RescueError::BadMode(format!(
+                "{}: DFU status {:#x}",
+                String::from_utf8_lossy(&mode),
+                status.status
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    fn download(&self, data: &[u8]) -> Result<()> {
+        for (block, chunk) in data.chunks(self.transfer_size).enumerate() {
+            self.device.write_control(
+                Self::request_out(),
+                USB_DFU_DNLOAD,
+                block as u16,
+                self.interface as u16,
+                chunk,
+            )?;
+            self.wait_for_state(DFU_STATE_DOWNLOAD_IDLE)?;
+        }
+        // A zero-length download marks the end of the transfer and begins manifestation.
+        let block = data.len().div_ceil(self.transfer_size);
+        self.device.write_control(
+            Self::request_out(),
+            USB_DFU_DNLOAD,
+            block as u16,
+            self.interface as u16,
+            &[],
+        )?;
+        self.wait_for_state(DFU_STATE_DFU_IDLE)
+    }
+
+    fn upload(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut buf = vec![0u8; self.transfer_size];
+        // The block number is 16 bits wide and wraps around on long transfers.
+        let mut block = 0u16;
+        loop {
+            let n = self.device.read_control(
+                Self::request_in(),
+                USB_DFU_UPLOAD,
+                block,
+                self.interface as u16,
+                &mut buf,
+            )?;
+            data.extend_from_slice(&buf[..n]);
+            // A short packet terminates the upload.
+            if n < self.transfer_size {
+                break;
+            }
+            block = block.wrapping_add(1);
+        }
+        self.wait_for_state(DFU_STATE_DFU_IDLE)?;
+        Ok(data)
+    }
+}
+
+/// Rescue over the ROM_EXT's USB DFU interface.
+pub struct UsbDfu {
+    // UART used for triggering rescue with a serial break.
+    uart: Rc<dyn Uart>,
+    usb_vid: u16,
+    usb_pid: u16,
+    usb_serial: Option<String>,
+    connection: RefCell<Option<DfuConnection>>,
+    reset_delay: Duration,
+    enter_delay: Duration,
+}
+
+impl UsbDfu {
+    pub fn new(uart: Rc<dyn Uart>, usb_vid: u16, usb_pid: u16, usb_serial: Option<String>) -> Self {
+        UsbDfu {
+            uart,
+            usb_vid,
+            usb_pid,
+            usb_serial,
+            connection: RefCell::new(None),
+            reset_delay: Duration::from_millis(50),
+            enter_delay: Duration::from_secs(5),
+        }
+    }
+
+    /// Creates a `UsbDfu` speaking to an already opened DFU `device`.
+    pub fn with_device(
+        uart: Rc<dyn Uart>,
+        device: Box<dyn DfuDevice>,
+        interface: u8,
+        transfer_size: usize,
+    ) -> Self {
+        let dfu = Self::new(uart, 0, 0, None);
+        dfu.connection.replace(Some(DfuConnection {
+            device,
+            interface,
+            transfer_size,
+        }));
+        dfu
+    }
+
+    /// Opens the ROM_EXT DFU device, retrying until it appears on the bus or `enter_delay`
+    /// elapses.
+    fn open(&self) -> Result<DfuConnection> {
+        let deadline = Instant::now() + self.enter_delay;
+        let mut usb = loop {
+            match UsbBackend::new(self.usb_vid, self.usb_pid, self.usb_serial.as_deref()) {
+                Ok(usb) => break usb,
+                Err(e) if Instant::now() >= deadline => return Err(e),
+                Err(_) => std::thread::sleep(Duration::from_millis(100)),
+            }
+        };
+
+        let mut dfu_interface = None;
+        let mut transfer_size = DEFAULT_TRANSFER_SIZE;
+        let config_desc = usb.active_config_descriptor()?;
+        for interface in config_desc.interfaces() {
+            for interface_desc in interface.descriptors() {
+                if interface_desc.class_code() != USB_CLASS_APP
+                    || interface_desc.sub_class_code() != USB_SUBCLASS_DFU
+                {
+                    continue;
+                }
+                dfu_interface = Some(interface.number());
+                // Extra bytes contains the DFU functional descriptor.
+                let extra_bytes = interface_desc.extra();
+                if extra_bytes.len() >= 7 {
+                    transfer_size = u16::from_le_bytes([extra_bytes[5], extra_bytes[6]]) as usize;
+                }
+            }
+        }
+        let Some(interface) = dfu_interface else {
+            bail!(RescueError::Dfu("No DFU interface found".into()));
+        };
+        usb.claim_interface(interface)?;
+        Ok(DfuConnection {
+            device: Box::new(usb),
+            interface,
+            transfer_size,
+        })
+    }
+
+    fn with_connection<T>(&self, f: impl FnOnce(&DfuConnection) -> Result<T>) -> Result<T> {
+        match &*self.connection.borrow() {
+            Some(connection) => f(connection),
+            None => bail!(RescueError::Dfu("Not connected; enter rescue first".into())),
+        }
+    }
+}
+
+impl Rescue for UsbDfu {
+    fn enter(&self, transport: &TransportWrapper, reset_target: bool) -> Result<()> {
+        log::info!("Setting serial break to trigger rescue mode.");
+        self.uart.set_break(true)?;
+        if reset_target {
+            // The device will re-enumerate, so any existing handle becomes stale.
+            self.connection.replace(None);
+            transport.reset_target(self.reset_delay, /*clear_uart=*/ true)?;
+        }
+        let result = if self.connection.borrow().is_none() {
+            self.open().map(|c| {
+                self.connection.replace(Some(c));
+            })
+        } else {
+            Ok(())
+        };
+        log::info!("Clearing serial break.");
+        self.uart.set_break(false)?;
+        result
+    }
+
+    fn set_speed(&self, _speed: u32) -> Result<u32> {
+        bail!(RescueError::Unsupported(
+            "link speed cannot be changed over USB DFU".into()
+        ))
+    }
+
+    fn set_mode(&self, mode: [u8; 4]) -> Result<()> {
+        self.with_connection(|c| c.set_mode(mode))
+    }
+
+    fn send(&self, data: &[u8]) -> Result<()> {
+        self.with_connection(|c| c.download(data))
+    }
+
+    fn recv(&self) -> Result<Vec<u8>> {
+        self.with_connection(|c| c.upload())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chip::boot_svc::BootSlot;
+    use crate::rescue::RescueMode;
+    use std::collections::HashMap;
+
+    const TRANSFER_SIZE: usize = 64;
+
+    /// An in-process model of the ROM_EXT DFU state machine.
+    #[derive(Default)]
+    struct FakeRomExt {
+        state: RefCell<FakeState>,
+    }
+
+    #[derive(Default)]
+    struct FakeState {
+        dfu_state: u8,
+        dfu_status: u8,
+        mode: Option<[u8; 4]>,
+        pending: Vec<u8>,
+        uploaded: usize,
+        next_block: u16,
+        storage: HashMap<[u8; 4], Vec<u8>>,
+    }
+
+    impl FakeState {
+        fn fail(&mut self) {
+            self.dfu_status = 0x0F; // errSTALLEDPKT
+            self.dfu_state = DFU_STATE_ERROR;
+        }
+    }
+
+    impl FakeRomExt {
+        fn new() -> Rc<Self> {
+            let fake = Self::default();
+            fake.state.borrow_mut().dfu_state = DFU_STATE_DFU_IDLE;
+            Rc::new(fake)
+        }
+
+        fn stored(&self, mode: [u8; 4]) -> Option<Vec<u8>> {
+            self.state.borrow().storage.get(&mode).cloned()
+        }
+
+        fn store(&self, mode: [u8; 4], data: &[u8]) {
+            self.state.borrow_mut().storage.insert(mode, data.to_vec());
+        }
+    }
+
+    impl DfuDevice for Rc<FakeRomExt> {
+        fn write_control(
+            &self,
+            request_type: u8,
+            request: u8,
+            value: u16,
+            _index: u16,
+            buf: &[u8],
+        ) -> Result<usize> {
+            assert_eq!(request_type & 0x80, 0);
+            let mut st = self.state.borrow_mut();
+            match (request, st.dfu_state) {
+                (USB_DFU_SET_MODE, DFU_STATE_DFU_IDLE) => {
+                    let mode: [u8; 4] = buf.try_into()?;
+                    if mode == *b"NONE" {
+                        st.fail();
+                    } else {
+                        st.mode = Some(mode);
+                    }
+                }
+                (USB_DFU_DNLOAD, DFU_STATE_DFU_IDLE | DFU_STATE_DOWNLOAD_IDLE)
+                    if st.mode.is_some() && value == st.next_block =>
+                {
+                    if buf.is_empty() {
+                        let mode = st.mode.unwrap();
+                        let data = std::mem::take(&mut st.pending);
+                        st.storage.insert(mode, data);
+                        st.dfu_state = DFU_STATE_MANIFEST_SYNC;
+                        st.next_block = 0;
+                    } else {
+                        st.pending.extend_from_slice(buf);
+                        st.dfu_state = DFU_STATE_DOWNLOAD_BUSY;
+                        st.next_block = st.next_block.wrapping_add(1);
+                    }
+                }
+                (USB_DFU_CLRSTATUS, DFU_STATE_ERROR) => {
+                    st.dfu_status = DFU_STATUS_OK;
+                    st.dfu_state = DFU_STATE_DFU_IDLE;
+                }
+                (USB_DFU_ABORT, _) => {
+                    st.pending.clear();
+                    st.uploaded = 0;
+                    st.next_block = 0;
+                    st.dfu_state = DFU_STATE_DFU_IDLE;
+                }
+                _ => st.fail(),
+            }
+            Ok(buf.len())
+        }
+
+        fn read_control(
+            &self,
+            request_type: u8,
+            request: u8,
+            value: u16,
+            _index: u16,
+            buf: &mut [u8],
+        ) -> Result<usize> {
+            assert_eq!(request_type & 0x80, 0x80);
+            let mut st = self.state.borrow_mut();
+            match request {
+                USB_DFU_GETSTATUS => {
+                    buf[..6].copy_from_slice(&[st.dfu_status, 1, 0, 0, st.dfu_state, 0]);
+                    // Advance out of the transient states, as a real device would after the
+                    // poll timeout.
+                    st.dfu_state = match st.dfu_state {
+                        DFU_STATE_DOWNLOAD_BUSY => DFU_STATE_DOWNLOAD_IDLE,
+                        DFU_STATE_MANIFEST_SYNC => DFU_STATE_MANIFEST,
+                        DFU_STATE_MANIFEST => DFU_STATE_DFU_IDLE,
+                        s => s,
+                    };
+                    Ok(6)
+                }
+                USB_DFU_UPLOAD
+                    if matches!(st.dfu_state, DFU_STATE_DFU_IDLE | DFU_STATE_UPLOAD_IDLE)
+                        && value == st.next_block =>
+                {
+                    let st = &mut *st;
+                    let data = st
+                        .storage
+                        .get(&st.mode.unwrap())
+                        .map(Vec::as_slice)
+                        .unwrap_or_default();
+                    let offset = st.uploaded;
+                    let n = std::cmp::min(buf.len(), data.len() - offset);
+                    buf[..n].copy_from_slice(&data[offset..offset + n]);
+                    if n < buf.len() {
+                        st.dfu_state = DFU_STATE_DFU_IDLE;
+                        st.uploaded = 0;
+                        st.next_block = 0;
+                    } else {
+                        st.dfu_state = DFU_STATE_UPLOAD_IDLE;
+                        st.uploaded += n;
+                        st.next_block = st.next_block.wrapping_add(1);
+                    }
+                    Ok(n)
+                }
+                _ => {
+                    st.fail();
+                    Ok(0)
+                }
+            }
+        }
+    }
+
+    /// A UART which ignores everything; only used to toggle the rescue trigger.
+    struct NullUart;
+
+    impl Uart for NullUart {
+        fn get_baudrate(&self) -> Result<u32> {
+            Ok(0)
+        }
+        fn set_baudrate(&self, _baudrate: u32) -> Result<()> {
+            Ok(())
+        }
+        fn read(&self, _buf: &mut [u8]) -> Result<usize> {
+            Ok(0)
+        }
+        fn read_timeout(&self, _buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+            Ok(0)
+        }
+        fn write(&self, _buf: &[u8]) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn rescue(fake: &Rc<FakeRomExt>) -> UsbDfu {
+        UsbDfu::with_device(
+            Rc::new(NullUart),
+            Box::new(Rc::clone(fake)),
+            0,
+            TRANSFER_SIZE,
+        )
+    }
+
+    #[test]
+    fn test_dfu_update_firmware() -> Result<()> {
+        let fake = FakeRomExt::new();
+        let rescue = rescue(&fake);
+        let image = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        rescue.update_firmware(BootSlot::SlotB, &image)?;
+        assert_eq!(fake.stored(RescueMode::RESCUE_B), Some(image));
+        assert_eq!(fake.stored(RescueMode::RESCUE), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dfu_update_firmware_exact_blocks() -> Result<()> {
+        let fake = FakeRomExt::new();
+        let rescue = rescue(&fake);
+        let image = vec![0xa5u8; TRANSFER_SIZE * 4];
+        rescue.update_firmware(BootSlot::SlotA, &image)?;
+        assert_eq!(fake.stored(RescueMode::RESCUE), Some(image));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dfu_get_raw() -> Result<()> {
+        let fake = FakeRomExt::new();
+        let rescue = rescue(&fake);
+        let blog = (0..TRANSFER_SIZE * 2 + 5)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        fake.store(RescueMode::BOOT_LOG, &blog);
+        assert_eq!(rescue.get_raw(RescueMode::BOOT_LOG)?, blog);
+
+        // An upload of an exact multiple of the transfer size ends with an empty packet.
+        let page = vec![0x5au8; TRANSFER_SIZE];
+        fake.store(RescueMode::GET_OWNER_PAGE0, &page);
+        assert_eq!(rescue.get_raw(RescueMode::GET_OWNER_PAGE0)?, page);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dfu_get_raw_block_number_wraps() -> Result<()> {
+        let fake = FakeRomExt::new();
+        let rescue = rescue(&fake);
+        // More than 65536 blocks, so that the block number wraps around.
+        let blog = (0..TRANSFER_SIZE * 0x10001 + 5)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        fake.store(RescueMode::BOOT_LOG, &blog);
+        assert_eq!(rescue.get_raw(RescueMode::BOOT_LOG)?, blog);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dfu_bad_mode() -> Result<()> {
+        let fake = FakeRomExt::new();
+        let rescue = rescue(&fake);
+        let err = rescue.set_mode(*b"NONE").unwrap_err();
+        assert_eq!(err.to_string(), "bad mode: NONE: DFU status 0xf");
+
+        // The error should have been cleared, and the next request should succeed.
+        rescue.set_owner_config(&[1, 2, 3, 4])?;
+        assert_eq!(fake.stored(RescueMode::OWNER_BLOCK), Some(vec![1, 2, 3, 4]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dfu_set_speed_unsupported() {
+        let fake = FakeRomExt::new();
+        let rescue = rescue(&fake);
+        assert!(rescue.set_speed(1500000).is_err());
+    }
+}
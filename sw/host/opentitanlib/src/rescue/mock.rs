@@ -0,0 +1,249 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, Result};
+use std::cell::{Ref, RefCell, RefMut};
+
+use crate::app::TransportWrapper;
+use crate::rescue::{Rescue, RescueError, RescueMode};
+
+/// The simulated state of the ROM_EXT behind a `MockRescue`.
+///
+/// Tests populate the fields read by the host (boot log, device ID, owner pages, ...) before
+/// exercising the code under test, and inspect the fields written by the host afterwards.
+#[derive(Clone, Debug)]
+pub struct MockState {
+    /// Whether the simulated chip is currently in rescue mode.
+    pub in_rescue: bool,
+    /// The most recently selected mode.
+    pub mode: Option<[u8; 4]>,
+    /// The current link speed.
+    pub speed: u32,
+    /// The modes the simulated ROM_EXT permits.  All modes are permitted if `None`.
+    pub allowed: Option<Vec<[u8; 4]>>,
+    /// Every mode selected by the host, in order.
+    pub history: Vec<[u8; 4]>,
+    /// The number of times the host requested a reboot.
+    pub reboots: usize,
+    /// The contents of the firmware slots.
+    pub slot_a: Vec<u8>,
+    pub slot_b: Vec<u8>,
+    /// The raw boot log returned for `BLOG`.
+    pub boot_log: Vec<u8>,
+    /// The last boot services request sent by the host.
+    pub boot_svc_req: Vec<u8>,
+    /// The raw boot services response returned for `BRSP`.
+    pub boot_svc_rsp: Vec<u8>,
+    /// The raw device ID returned for `OTID`.
+    pub device_id: Vec<u8>,
+    /// The owner configuration pages returned for `OPG0` and `OPG1`.
+    pub owner_page: [Vec<u8>; 2],
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        MockState {
+            in_rescue: false,
+            mode: None,
+            speed: 115200,
+            allowed: None,
+            history: Vec::new(),
+            reboots: 0,
+            slot_a: Vec::new(),
+            slot_b: Vec::new(),
+            boot_log: Vec::new(),
+            boot_svc_req: Vec::new(),
+            boot_svc_rsp: Vec::new(),
+            device_id: Vec::new(),
+            owner_page: [Vec::new(), Vec::new()],
+        }
+    }
+}
+
+/// An in-memory stand-in for the ROM_EXT rescue module.
+///
+/// `MockRescue` follows the same sequence of mode selections and transfers as the ROM_EXT,
+/// so code written against the `Rescue` trait can be exercised without hardware.
+#[derive(Default)]
+pub struct MockRescue {
+    state: RefCell<MockState>,
+}
+
+impl MockRescue {
+    pub fn new(state: MockState) -> Self {
+        MockRescue {
+            state: RefCell::new(state),
+        }
+    }
+
+    pub fn state(&self) -> Ref<'_, MockState> {
+        self.state.borrow()
+    }
+
+    pub fn state_mut(&self) -> RefMut<'_, MockState> {
+        self.state.borrow_mut()
+    }
+
+    fn current_mode(&self) -> Result<[u8; 4]> {
+        let state = self.state.borrow();
+        if !state.in_rescue {
+            bail!(RescueError::BadMode("not in rescue mode".into()));
+        }
+        match state.mode {
+            Some(mode) => Ok(mode),
+            None => bail!(RescueError::BadMode("no mode selected".into())),
+        }
+    }
+}
+
+impl Rescue for MockRescue {
+    fn enter(&self, _transport: &TransportWrapper, _reset_target: bool) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        state.in_rescue = true;
+        state.mode = None;
+        Ok(())
+    }
+
+    fn set_speed(&self, speed: u32) -> Result<u32> {
+        Ok(std::mem::replace(&mut self.state.borrow_mut().speed, speed))
+    }
+
+    fn set_mode(&self, mode: [u8; 4]) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if !state.in_rescue {
+            bail!(RescueError::BadMode("not in rescue mode".into()));
+        }
+        state.history.push(mode);
+        if let Some(allowed) = &state.allowed {
+            if !allowed.contains(&mode) {
+                bail!(RescueError::BadMode(format!(
+                    "mode not allowed: {}",
+                    String::from_utf8_lossy(&mode)
+                )));
+            }
+        }
+        match mode {
+            RescueMode::REBOOT => {
+                state.reboots += 1;
+                state.in_rescue = false;
+                state.mode = None;
+            }
+            RescueMode::ERASE_OWNER => {
+                state.owner_page = [Vec::new(), Vec::new()];
+                state.mode = None;
+            }
+            _ => state.mode = Some(mode),
+        }
+        Ok(())
+    }
+
+    fn send(&self, data: &[u8]) -> Result<()> {
+        let mode = self.current_mode()?;
+        let mut state = self.state.borrow_mut();
+        let dest = match mode {
+            RescueMode::RESCUE => &mut state.slot_a,
+            RescueMode::RESCUE_B => &mut state.slot_b,
+            RescueMode::BOOT_SVC_REQ => &mut state.boot_svc_req,
+            // The ROM_EXT stages a new owner configuration in owner page 1.
+            RescueMode::OWNER_BLOCK => &mut state.owner_page[1],
+            _ => bail!(RescueError::BadMode(format!(
+                "cannot send in mode {}",
+                String::from_utf8_lossy(&mode)
+            ))),
+        };
+        *dest = data.to_vec();
+        Ok(())
+    }
+
+    fn recv(&self) -> Result<Vec<u8>> {
+        let mode = self.current_mode()?;
+        let state = self.state.borrow();
+        let src = match mode {
+            RescueMode::BOOT_LOG => &state.boot_log,
+            RescueMode::BOOT_SVC_RSP => &state.boot_svc_rsp,
+            RescueMode::OT_ID => &state.device_id,
+            RescueMode::GET_OWNER_PAGE0 => &state.owner_page[0],
+            RescueMode::GET_OWNER_PAGE1 => &state.owner_page[1],
+            _ => bail!(RescueError::BadMode(format!(
+                "cannot receive in mode {}",
+                String::from_utf8_lossy(&mode)
+            ))),
+        };
+        Ok(src.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::TransportWrapperBuilder;
+    use crate::chip::boot_svc::BootSlot;
+    use crate::chip::device_id::DeviceId;
+    use crate::transport::EmptyTransport;
+
+    fn transport() -> Result<TransportWrapper> {
+        TransportWrapperBuilder::new(String::new(), false).build(Box::new(EmptyTransport))
+    }
+
+    #[test]
+    fn test_mock_update_firmware() -> Result<()> {
+        let transport = transport()?;
+        let rescue = MockRescue::default();
+        rescue.enter(&transport, true)?;
+        rescue.update_firmware(BootSlot::SlotB, &[1, 2, 3])?;
+        rescue.reboot()?;
+        let state = rescue.state();
+        assert_eq!(state.slot_b, vec![1, 2, 3]);
+        assert!(state.slot_a.is_empty());
+        assert_eq!(
+            state.history,
+            vec![RescueMode::RESCUE_B, RescueMode::REBOOT]
+        );
+        assert_eq!(state.reboots, 1);
+        assert!(!state.in_rescue);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mock_device_id() -> Result<()> {
+        let transport = transport()?;
+        let id = DeviceId {
+            creator: 0x4001,
+            din: 0x1234_5678,
+            ..Default::default()
+        };
+        let mut raw = Vec::new();
+        id.write(&mut raw)?;
+        let rescue = MockRescue::new(MockState {
+            device_id: raw,
+            ..Default::default()
+        });
+        rescue.enter(&transport, true)?;
+        let devid = rescue.get_device_id()?;
+        assert_eq!(devid.creator, 0x4001);
+        assert_eq!(devid.din, 0x1234_5678);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mock_requires_enter() -> Result<()> {
+        let rescue = MockRescue::default();
+        let err = rescue.set_owner_config(&[0u8; 4]).unwrap_err();
+        assert_eq!(err.to_string(), "bad mode: not in rescue mode");
+        Ok(())
+    }
+
+    #[test]
+    fn test_mock_disallowed_mode() -> Result<()> {
+        let transport = transport()?;
+        let rescue = MockRescue::new(MockState {
+            allowed: Some(vec![RescueMode::BOOT_LOG]),
+            ..Default::default()
+        });
+        rescue.enter(&transport, true)?;
+        let err = rescue.erase_owner().unwrap_err();
+        assert_eq!(err.to_string(), "bad mode: mode not allowed: KLBR");
+        Ok(())
+    }
+}
@@ -2,13 +2,188 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::app::TransportWrapper;
+use crate::chip::boot_log::BootLog;
+use crate::chip::boot_svc::{BootSlot, BootSvc, OwnershipActivateRequest, OwnershipUnlockRequest};
+use crate::chip::device_id::DeviceId;
+use crate::io::uart::UartParams;
+use crate::util::parse_int::ParseInt;
+
+pub mod dfu;
+pub mod mock;
 pub mod serial;
 pub mod xmodem;
 
+pub use dfu::UsbDfu;
+pub use serial::RescueSerial;
+
 #[derive(Debug, Error)]
 pub enum RescueError {
     #[error("bad mode: {0}")]
     BadMode(String),
+    #[error("unsupported operation: {0}")]
+    Unsupported(String),
+    #[error("DFU error: {0}")]
+    Dfu(String),
+}
+
+/// The four-character mode tags understood by the ROM_EXT rescue protocol.
+/// The tags are the same regardless of the transport used to carry them.
+pub struct RescueMode;
+
+impl RescueMode {
+    pub const RESCUE: [u8; 4] = *b"RESQ";
+    pub const RESCUE_B: [u8; 4] = *b"RESB";
+    pub const REBOOT: [u8; 4] = *b"REBO";
+    pub const BAUD: [u8; 4] = *b"BAUD";
+    pub const BOOT_LOG: [u8; 4] = *b"BLOG";
+    pub const BOOT_SVC_REQ: [u8; 4] = *b"BREQ";
+    pub const BOOT_SVC_RSP: [u8; 4] = *b"BRSP";
+    pub const OWNER_BLOCK: [u8; 4] = *b"OWNR";
+    pub const GET_OWNER_PAGE0: [u8; 4] = *b"OPG0";
+    pub const GET_OWNER_PAGE1: [u8; 4] = *b"OPG1";
+    pub const OT_ID: [u8; 4] = *b"OTID";
+    pub const ERASE_OWNER: [u8; 4] = *b"KLBR";
+    pub const WAIT: [u8; 4] = *b"WAIT";
+}
+
+/// `Rescue` is implemented by each transport capable of speaking the ROM_EXT rescue protocol.
+///
+/// Implementations only need to provide the primitive operations (entering rescue, selecting
+/// a mode and moving data in either direction); the higher level operations are expressed in
+/// terms of those primitives.
+pub trait Rescue {
+    /// Triggers rescue mode, optionally resetting the target first.
+    fn enter(&self, transport: &TransportWrapper, reset_target: bool) -> Result<()>;
+
+    /// Negotiates a new link speed with the ROM_EXT, returning the previous speed.
+    fn set_speed(&self, speed: u32) -> Result<u32>;
+
+    /// Selects the rescue `mode` for the next data transfer.
+    fn set_mode(&self, mode: [u8; 4]) -> Result<()>;
+
+    /// Sends `data` to the ROM_EXT in the current mode.
+    fn send(&self, data: &[u8]) -> Result<()>;
+
+    /// Receives data from the ROM_EXT in the current mode.
+    fn recv(&self) -> Result<Vec<u8>>;
+
+    fn wait(&self) -> Result<()> {
+        self.set_mode(RescueMode::WAIT)
+    }
+
+    fn reboot(&self) -> Result<()> {
+        self.set_mode(RescueMode::REBOOT)
+    }
+
+    fn update_firmware(&self, slot: BootSlot, image: &[u8]) -> Result<()> {
+        self.set_mode(if slot == BootSlot::SlotB {
+            RescueMode::RESCUE_B
+        } else {
+            RescueMode::RESCUE
+        })?;
+        self.send(image)
+    }
+
+    fn get_raw(&self, mode: [u8; 4]) -> Result<Vec<u8>> {
+        self.set_mode(mode)?;
+        self.recv()
+    }
+
+    fn get_boot_log(&self) -> Result<BootLog> {
+        let blog = self.get_raw(RescueMode::BOOT_LOG)?;
+        Ok(BootLog::try_from(blog.as_slice())?)
+    }
+
+    fn get_boot_svc(&self) -> Result<BootSvc> {
+        let bsvc = self.get_raw(RescueMode::BOOT_SVC_RSP)?;
+        Ok(BootSvc::try_from(bsvc.as_slice())?)
+    }
+
+    fn get_device_id(&self) -> Result<DeviceId> {
+        let id = self.get_raw(RescueMode::OT_ID)?;
+        DeviceId::read(&mut std::io::Cursor::new(&id))
+    }
+
+    fn set_boot_svc_raw(&self, data: &[u8]) -> Result<()> {
+        self.set_mode(RescueMode::BOOT_SVC_REQ)?;
+        self.send(data)
+    }
+
+    fn set_next_bl0_slot(&self, primary: BootSlot, next: BootSlot) -> Result<()> {
+        let message = BootSvc::next_boot_bl0_slot(primary, next);
+        let data = message.to_bytes()?;
+        self.set_boot_svc_raw(&data)
+    }
+
+    fn ownership_unlock(&self, unlock: OwnershipUnlockRequest) -> Result<()> {
+        let message = BootSvc::ownership_unlock(unlock);
+        let data = message.to_bytes()?;
+        self.set_boot_svc_raw(&data)
+    }
+
+    fn ownership_activate(&self, activate: OwnershipActivateRequest) -> Result<()> {
+        let message = BootSvc::ownership_activate(activate);
+        let data = message.to_bytes()?;
+        self.set_boot_svc_raw(&data)
+    }
+
+    fn set_owner_config(&self, data: &[u8]) -> Result<()> {
+        self.set_mode(RescueMode::OWNER_BLOCK)?;
+        self.send(data)
+    }
+
+    fn erase_owner(&self) -> Result<()> {
+        self.set_mode(RescueMode::ERASE_OWNER)
+    }
+}
+
+/// `RescueProtocol` selects the transport used to talk to the ROM_EXT rescue module.
+/// The `Serial` protocol uses XMODEM over the console UART.
+/// The `UsbDfu` protocol uses DFU class requests on the ROM_EXT's USB device.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, ValueEnum)]
+pub enum RescueProtocol {
+    Serial,
+    UsbDfu,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct RescueParams {
+    /// Rescue protocol to use.
+    #[arg(long, value_enum, ignore_case = true, default_value = "serial")]
+    pub protocol: RescueProtocol,
+
+    /// UART used by the serial protocol, and to trigger rescue for all protocols.
+    #[command(flatten)]
+    pub uart: UartParams,
+
+    /// USB Vendor ID of the ROM_EXT DFU device.
+    #[arg(long, value_parser = u16::from_str, default_value = "0x18d1")]
+    pub dfu_vid: u16,
+    /// USB Product ID of the ROM_EXT DFU device.
+    #[arg(long, value_parser = u16::from_str, default_value = "0x023a")]
+    pub dfu_pid: u16,
+    /// USB serial number of the ROM_EXT DFU device.
+    #[arg(long)]
+    pub dfu_serial: Option<String>,
+}
+
+impl RescueParams {
+    pub fn create(&self, transport: &TransportWrapper) -> Result<Box<dyn Rescue>> {
+        let uart = self.uart.create(transport)?;
+        match self.protocol {
+            RescueProtocol::Serial => Ok(Box::new(RescueSerial::new(uart))),
+            RescueProtocol::UsbDfu => Ok(Box::new(UsbDfu::new(
+                uart,
+                self.dfu_vid,
+                self.dfu_pid,
+                self.dfu_serial.clone(),
+            ))),
+        }
+    }
 }
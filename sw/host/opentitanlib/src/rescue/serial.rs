@@ -7,12 +7,9 @@ use std::rc::Rc;
 use std::time::Duration;
 
 use crate::app::TransportWrapper;
-use crate::chip::boot_log::BootLog;
-use crate::chip::boot_svc::{BootSlot, BootSvc, OwnershipActivateRequest, OwnershipUnlockRequest};
-use crate::chip::device_id::DeviceId;
 use crate::io::uart::Uart;
 use crate::rescue::xmodem::Xmodem;
-use crate::rescue::RescueError;
+use crate::rescue::{Rescue, RescueError, RescueMode};
 use crate::uart::console::UartConsole;
 
 pub struct RescueSerial {
@@ -23,19 +20,6 @@ pub struct RescueSerial {
 
 impl RescueSerial {
     const ONE_SECOND: Duration = Duration::from_secs(1);
-    pub const RESCUE: [u8; 4] = *b"RESQ";
-    pub const RESCUE_B: [u8; 4] = *b"RESB";
-    pub const REBOOT: [u8; 4] = *b"REBO";
-    pub const BAUD: [u8; 4] = *b"BAUD";
-    pub const BOOT_LOG: [u8; 4] = *b"BLOG";
-    pub const BOOT_SVC_REQ: [u8; 4] = *b"BREQ";
-    pub const BOOT_SVC_RSP: [u8; 4] = *b"BRSP";
-    pub const OWNER_BLOCK: [u8; 4] = *b"OWNR";
-    pub const GET_OWNER_PAGE0: [u8; 4] = *b"OPG0";
-    pub const GET_OWNER_PAGE1: [u8; 4] = *b"OPG1";
-    pub const OT_ID: [u8; 4] = *b"OTID";
-    pub const ERASE_OWNER: [u8; 4] = *b"KLBR";
-    pub const WAIT: [u8; 4] = *b"WAIT";
 
     const BAUD_115K: [u8; 4] = *b"115K";
     const BAUD_230K: [u8; 4] = *b"230K";
@@ -52,21 +36,6 @@ impl RescueSerial {
         }
     }
 
-    pub fn enter(&self, transport: &TransportWrapper, reset_target: bool) -> Result<()> {
-        log::info!("Setting serial break to trigger rescue mode.");
-        self.uart.set_break(true)?;
-        if reset_target {
-            transport.reset_target(self.reset_delay, /*clear_uart=*/ true)?;
-        }
-        UartConsole::wait_for(&*self.uart, r"rescue:.*\r\n", self.enter_delay)?;
-        log::info!("Rescue triggered. clearing serial break.");
-        self.uart.set_break(false)?;
-        // Upon entry, rescue is going to tell us what mode it is.
-        // Consume and discard.
-        let _ = UartConsole::wait_for(&*self.uart, r"(ok|error):.*\r\n", Self::ONE_SECOND);
-        Ok(())
-    }
-
     pub fn set_baud(&self, baud: u32) -> Result<()> {
         // Make sure the requested rate is a known rate.
         let symbol = match baud {
@@ -81,7 +50,7 @@ impl RescueSerial {
 
         // Request to change rates.  We don't use `set_mode` here because changing
         // rates isn't a "mode" request and doesn't respond the same way.
-        self.uart.write(&Self::BAUD)?;
+        self.uart.write(&RescueMode::BAUD)?;
         self.uart.write(b"\r")?;
         let result = UartConsole::wait_for(&*self.uart, r"(ok|error):.*\r\n", Self::ONE_SECOND)?;
         if result[1] == "error" {
@@ -98,8 +67,31 @@ impl RescueSerial {
         self.uart.set_baudrate(baud)?;
         Ok(())
     }
+}
+
+impl Rescue for RescueSerial {
+    fn enter(&self, transport: &TransportWrapper, reset_target: bool) -> Result<()> {
+        log::info!("Setting serial break to trigger rescue mode.");
+        self.uart.set_break(true)?;
+        if reset_target {
+            transport.reset_target(self.reset_delay, /*clear_uart=*/ true)?;
+        }
+        UartConsole::wait_for(&*self.uart, r"rescue:.*\r\n", self.enter_delay)?;
+        log::info!("Rescue triggered. clearing serial break.");
+        self.uart.set_break(false)?;
+        // Upon entry, rescue is going to tell us what mode it is.
+        // Consume and discard.
+        let _ = UartConsole::wait_for(&*self.uart, r"(ok|error):.*\r\n", Self::ONE_SECOND);
+        Ok(())
+    }
 
-    pub fn set_mode(&self, mode: [u8; 4]) -> Result<()> {
+    fn set_speed(&self, speed: u32) -> Result<u32> {
+        let prev_baudrate = self.uart.get_baudrate()?;
+        self.set_baud(speed)?;
+        Ok(prev_baudrate)
+    }
+
+    fn set_mode(&self, mode: [u8; 4]) -> Result<()> {
         self.uart.write(&mode)?;
         let enter = b'\r';
         self.uart.write(std::slice::from_ref(&enter))?;
@@ -116,84 +108,83 @@ impl RescueSerial {
         Ok(())
     }
 
-    pub fn wait(&self) -> Result<()> {
-        self.set_mode(Self::WAIT)?;
-        Ok(())
-    }
-
-    pub fn reboot(&self) -> Result<()> {
-        self.set_mode(Self::REBOOT)?;
-        Ok(())
-    }
-
-    pub fn update_firmware(&self, slot: BootSlot, image: &[u8]) -> Result<()> {
-        self.set_mode(if slot == BootSlot::SlotB {
-            Self::RESCUE_B
-        } else {
-            Self::RESCUE
-        })?;
+    fn send(&self, data: &[u8]) -> Result<()> {
         let xm = Xmodem::new();
-        xm.send(&*self.uart, image)?;
+        xm.send(&*self.uart, data)?;
         Ok(())
     }
 
-    pub fn get_raw(&self, mode: [u8; 4]) -> Result<Vec<u8>> {
-        self.set_mode(mode)?;
+    fn recv(&self) -> Result<Vec<u8>> {
         let mut data = Vec::new();
         let xm = Xmodem::new();
         xm.receive(&*self.uart, &mut data)?;
         Ok(data)
     }
+}
 
-    pub fn get_boot_log(&self) -> Result<BootLog> {
-        let blog = self.get_raw(Self::BOOT_LOG)?;
-        Ok(BootLog::try_from(blog.as_slice())?)
-    }
-
-    pub fn get_boot_svc(&self) -> Result<BootSvc> {
-        let bsvc = self.get_raw(Self::BOOT_SVC_RSP)?;
-        Ok(BootSvc::try_from(bsvc.as_slice())?)
-    }
-
-    pub fn get_device_id(&self) -> Result<DeviceId> {
-        let id = self.get_raw(Self::OT_ID)?;
-        DeviceId::read(&mut std::io::Cursor::new(&id))
-    }
-
-    pub fn set_boot_svc_raw(&self, data: &[u8]) -> Result<()> {
-        self.set_mode(Self::BOOT_SVC_REQ)?;
-        let xm = Xmodem::new();
-        xm.send(&*self.uart, data)?;
-        Ok(())
-    }
-
-    pub fn set_next_bl0_slot(&self, primary: BootSlot, next: BootSlot) -> Result<()> {
-        let message = BootSvc::next_boot_bl0_slot(primary, next);
-        let data = message.to_bytes()?;
-        self.set_boot_svc_raw(&data)
-    }
-
-    pub fn ownership_unlock(&self, unlock: OwnershipUnlockRequest) -> Result<()> {
-        let message = BootSvc::ownership_unlock(unlock);
-        let data = message.to_bytes()?;
-        self.set_boot_svc_raw(&data)
-    }
-
-    pub fn ownership_activate(&self, activate: OwnershipActivateRequest) -> Result<()> {
-        let message = BootSvc::ownership_activate(activate);
-        let data = message.to_bytes()?;
-        self.set_boot_svc_raw(&data)
-    }
-
-    pub fn set_owner_config(&self, data: &[u8]) -> Result<()> {
-        self.set_mode(Self::OWNER_BLOCK)?;
-        let xm = Xmodem::new();
-        xm.send(&*self.uart, data)?;
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chip::boot_svc::BootSlot;
+    use crate::rescue::xmodem::XmodemError;
+    use crate::util::testing::MemoryUart;
+
+    const ACK: u8 = 0x06;
+    const CAN: u8 = 0x18;
+    // The length of an XMODEM-1K block: header, block number, payload and CRC.
+    const BLOCK_LEN: usize = 3 + 1024 + 2;
+
+    fn read_exact(uart: &dyn Uart, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let n = uart.read(buf)?;
+            buf = &mut buf[n..];
+        }
         Ok(())
     }
 
-    pub fn erase_owner(&self) -> Result<()> {
-        self.set_mode(Self::ERASE_OWNER)?;
+    #[test]
+    fn test_interrupted_upload() -> Result<()> {
+        let (host, device) = MemoryUart::pair();
+        // Act as a ROM_EXT which accepts the first block of the firmware and then aborts the
+        // transfer, returning everything the host sends after the abort.
+        let rom_ext = std::thread::spawn(move || -> Result<Vec<u8>> {
+            let mut mode = [0u8; 5];
+            read_exact(&device, &mut mode)?;
+            assert_eq!(&mode, b"RESQ\r");
+            device.write(b"mode: RESQ\r\nok: receive firmware via xmodem\r\n")?;
+            device.write(b"C")?;
+            let mut block = vec![0u8; BLOCK_LEN];
+            read_exact(&device, &mut block)?;
+            assert_eq!(block[1], 1);
+            device.write(&[ACK])?;
+            read_exact(&device, &mut block)?;
+            assert_eq!(block[1], 2);
+            device.write(&[CAN, CAN])?;
+
+            let mut rest = Vec::new();
+            let mut buf = [0u8; 256];
+            loop {
+                match device.read_timeout(&mut buf, Duration::from_millis(500))? {
+                    0 => break,
+                    n => rest.extend_from_slice(&buf[..n]),
+                }
+            }
+            Ok(rest)
+        });
+
+        let rescue = RescueSerial::new(Rc::new(host));
+        let image = vec![0x5a; 4 * 1024];
+        let err = rescue.update_firmware(BootSlot::SlotA, &image).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<XmodemError>(),
+            Some(XmodemError::Cancelled)
+        ));
+
+        // The ROM_EXT cannot resume a transfer, so apart from retransmitting the aborted block,
+        // the host must not select the firmware mode again or restart the transfer.
+        let rest = rom_ext.join().unwrap()?;
+        assert!(rest.len() <= BLOCK_LEN);
+        assert!(!rest.windows(4).any(|w| w == RescueMode::RESCUE));
         Ok(())
     }
 }
@@ -15,6 +15,8 @@ pub enum XmodemError {
     ExhaustedRetries(usize),
     #[error("Unsupported mode: {0}")]
     UnsupportedMode(String),
+    #[error("Bad YMODEM header: {0}")]
+    BadHeader(String),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -77,6 +79,12 @@ impl Xmodem {
         Ok(())
     }
 
+    /// Aborts a transfer in progress.
+    pub fn cancel(&self, uart: &dyn Uart) -> Result<()> {
+        uart.write(&[Self::CAN, Self::CAN])?;
+        Ok(())
+    }
+
     fn send_start(&self, uart: &dyn Uart) -> Result<()> {
         let mut ch = 0u8;
         let mut cancels = 0usize;
@@ -111,120 +119,132 @@ impl Xmodem {
         let mut errors = 0usize;
         loop {
             block += 1;
-            let mut buf = vec![self.pad_byte; self.block_len as usize + 3];
-            let n = data.read(&mut buf[3..])?;
+            let mut buf = vec![self.pad_byte; self.block_len as usize];
+            let n = data.read(&mut buf)?;
             if n == 0 {
                 break;
             }
-
-            buf[0] = match self.block_len {
-                XmodemBlock::Block128 => Self::SOH,
-                XmodemBlock::Block1k => Self::STX,
-            };
-            buf[1] = block as u8;
-            buf[2] = 255 - buf[1];
-            let crc = Self::crc16(&buf[3..]);
-            buf.push((crc >> 8) as u8);
-            buf.push((crc & 0xFF) as u8);
             log::info!("Sending block {block}");
+            self.send_block(uart, block as u8, &buf, &mut errors)?;
+        }
+        Ok(())
+    }
 
-            let mut cancels = 0usize;
-            loop {
-                uart.write(&buf)?;
-                let mut ch = 0u8;
-                uart.read(std::slice::from_mut(&mut ch))?;
-                match ch {
-                    Self::ACK => break,
-                    Self::NAK => {
-                        log::info!("XMODEM send got NAK.  Retrying.");
-                        errors += 1;
-                    }
-                    Self::CAN => {
-                        cancels += 1;
-                        if cancels >= 2 {
-                            return Err(XmodemError::Cancelled.into());
-                        }
-                    }
-                    _ => {
-                        log::info!("Expected ACK. Got {ch:#x}.");
-                        errors += 1;
+    // Sends a single block, retransmitting it until the receiver acknowledges it.
+    fn send_block(
+        &self,
+        uart: &dyn Uart,
+        block: u8,
+        payload: &[u8],
+        errors: &mut usize,
+    ) -> Result<()> {
+        let mut buf = Vec::with_capacity(payload.len() + 5);
+        buf.push(if payload.len() == XmodemBlock::Block128 as usize {
+            Self::SOH
+        } else {
+            Self::STX
+        });
+        buf.push(block);
+        buf.push(255 - block);
+        buf.extend_from_slice(payload);
+        buf.extend_from_slice(&Self::crc16(payload).to_be_bytes());
+
+        let mut cancels = 0usize;
+        loop {
+            uart.write(&buf)?;
+            let mut ch = 0u8;
+            uart.read(std::slice::from_mut(&mut ch))?;
+            match ch {
+                Self::ACK => return Ok(()),
+                Self::NAK => {
+                    log::info!("XMODEM send got NAK.  Retrying.");
+                    *errors += 1;
+                }
+                Self::CAN => {
+                    cancels += 1;
+                    if cancels >= 2 {
+                        return Err(XmodemError::Cancelled.into());
                     }
                 }
-                if errors >= self.max_errors {
-                    return Err(XmodemError::ExhaustedRetries(errors).into());
+                _ => {
+                    log::info!("Expected ACK. Got {ch:#x}.");
+                    *errors += 1;
                 }
             }
+            if *errors >= self.max_errors {
+                return Err(XmodemError::ExhaustedRetries(*errors).into());
+            }
         }
-        Ok(())
     }
 
     fn send_finish(&self, uart: &dyn Uart) -> Result<()> {
         uart.write(&[Self::EOF])?;
         let mut ch = 0u8;
         uart.read(std::slice::from_mut(&mut ch))?;
+        if ch == Self::NAK {
+            // YMODEM receivers NAK the first EOF to confirm the end of the file.
+            uart.write(&[Self::EOF])?;
+            uart.read(std::slice::from_mut(&mut ch))?;
+        }
         if ch != Self::ACK {
             log::info!("Expected ACK. Got {ch:#x}.");
         }
         Ok(())
     }
 
+    /// Sends `files` in a single YMODEM batch.
+    ///
+    /// Each file is preceded by a block 0 header carrying its name and size, and the batch is
+    /// terminated by an empty header.
+    pub fn send_batch(&self, uart: &dyn Uart, files: &[YmodemFile]) -> Result<()> {
+        for file in files {
+            log::info!("Sending {} ({} bytes)", file.info.name, file.data.len());
+            self.send_start(uart)?;
+            self.send_header(uart, Some(&file.info))?;
+            self.send_start(uart)?;
+            self.send_data(uart, file.data.as_slice())?;
+            self.send_finish(uart)?;
+        }
+        self.send_start(uart)?;
+        self.send_header(uart, None)
+    }
+
+    fn send_header(&self, uart: &dyn Uart, info: Option<&FileInfo>) -> Result<()> {
+        let mut header = info.map(FileInfo::to_bytes).unwrap_or_default();
+        let len = if header.len() <= XmodemBlock::Block128 as usize {
+            XmodemBlock::Block128 as usize
+        } else {
+            XmodemBlock::Block1k as usize
+        };
+        if header.len() > len {
+            return Err(XmodemError::BadHeader("file name too long".into()).into());
+        }
+        header.resize(len, 0);
+        let mut errors = 0usize;
+        self.send_block(uart, 0, &header, &mut errors)
+    }
+
     pub fn receive(&self, uart: &dyn Uart, data: &mut impl Write) -> Result<()> {
         // Send the byte indicating the protocol we want (Xmodem-CRC).
         uart.write(&[Self::CRC])?;
+        self.receive_data(uart, data)
+    }
 
+    fn receive_data(&self, uart: &dyn Uart, data: &mut impl Write) -> Result<()> {
         let mut block = 1u8;
         let mut errors = 0usize;
-        loop {
-            // The first byte of the packet is the packet type which indicates the block size.
-            let mut byte = 0u8;
-            uart.read(std::slice::from_mut(&mut byte))?;
-            let block_len = match byte {
-                Self::SOH => 128,
-                Self::STX => 1024,
-                Self::EOF => {
-                    // End of file.  Send an ACK.
-                    uart.write(&[Self::ACK])?;
-                    break;
-                }
-                _ => {
-                    return Err(XmodemError::UnsupportedMode(format!(
-                        "bad start of packet: {byte:?}"
-                    ))
-                    .into());
-                }
-            };
-
-            // The next two bytes are the block number and its complement.
-            let mut bnum = 0u8;
-            let mut bcom = 0u8;
-            uart.read(std::slice::from_mut(&mut bnum))?;
-            uart.read(std::slice::from_mut(&mut bcom))?;
-            let cancel = block != bnum || bnum != 255 - bcom;
-
-            // The next `block_len` bytes are the packet itself.
-            let mut buffer = vec![0; block_len];
-            let mut total = 0;
-            while total < block_len {
-                let n = uart.read(&mut buffer[total..])?;
-                total += n;
-            }
-
-            // The final two bytes are the CRC16.
-            let mut crc1 = 0u8;
-            let mut crc2 = 0u8;
-            uart.read(std::slice::from_mut(&mut crc1))?;
-            uart.read(std::slice::from_mut(&mut crc2))?;
-            let crc = u16::from_be_bytes([crc1, crc2]);
+        while let Some(packet) = self.receive_packet(uart)? {
+            let cancel = block != packet.block || packet.block != 255 - packet.complement;
 
             // If we should cancel, do it now.
             if cancel {
-                uart.write(&[Self::CAN, Self::CAN])?;
+                self.cancel(uart)?;
                 return Err(XmodemError::Cancelled.into());
             }
-            if Self::crc16(&buffer) == crc {
+            if packet.crc_ok() {
                 // CRC was good; send an ACK and keep the data.
                 uart.write(&[Self::ACK])?;
-                data.write_all(&buffer)?;
+                data.write_all(&packet.data)?;
                 block = block.wrapping_add(1);
             } else {
                 uart.write(&[Self::NAK])?;
@@ -234,17 +254,182 @@ impl Xmodem {
                 return Err(XmodemError::ExhaustedRetries(errors).into());
             }
         }
+        // End of file.  Send an ACK.
+        uart.write(&[Self::ACK])?;
         Ok(())
     }
+
+    // Reads a single packet.  Returns `None` if the sender signalled the end of the file.
+    fn receive_packet(&self, uart: &dyn Uart) -> Result<Option<Packet>> {
+        // The first byte of the packet is the packet type which indicates the block size.
+        let mut byte = 0u8;
+        uart.read(std::slice::from_mut(&mut byte))?;
+        let block_len = match byte {
+            Self::SOH => 128,
+            Self::STX => 1024,
+            Self::EOF => return Ok(None),
+            _ => {
+                return Err(
+                    XmodemError::UnsupportedMode(format!("bad start of packet: {byte:?}")).into(),
+                );
+            }
+        };
+
+        // The next two bytes are the block number and its complement.
+        let mut bnum = 0u8;
+        let mut bcom = 0u8;
+        uart.read(std::slice::from_mut(&mut bnum))?;
+        uart.read(std::slice::from_mut(&mut bcom))?;
+
+        // The next `block_len` bytes are the packet itself.
+        let mut buffer = vec![0; block_len];
+        let mut total = 0;
+        while total < block_len {
+            let n = uart.read(&mut buffer[total..])?;
+            total += n;
+        }
+
+        // The final two bytes are the CRC16.
+        let mut crc1 = 0u8;
+        let mut crc2 = 0u8;
+        uart.read(std::slice::from_mut(&mut crc1))?;
+        uart.read(std::slice::from_mut(&mut crc2))?;
+        Ok(Some(Packet {
+            block: bnum,
+            complement: bcom,
+            data: buffer,
+            crc: u16::from_be_bytes([crc1, crc2]),
+        }))
+    }
+
+    /// Receives a YMODEM batch, returning each file along with the metadata from its header.
+    ///
+    /// Files whose header carries a size are truncated to that size; otherwise the data
+    /// includes the padding of the final block.
+    pub fn receive_batch(&self, uart: &dyn Uart) -> Result<Vec<YmodemFile>> {
+        let mut files = Vec::new();
+        while let Some(info) = self.receive_header(uart)? {
+            log::info!("Receiving {} ({:?} bytes)", info.name, info.size);
+            let mut data = Vec::new();
+            uart.write(&[Self::CRC])?;
+            self.receive_data(uart, &mut data)?;
+            if let Some(size) = info.size {
+                data.truncate(size);
+            }
+            files.push(YmodemFile { info, data });
+        }
+        Ok(files)
+    }
+
+    // Receives a block 0 header.  Returns `None` for the empty header that ends a batch.
+    fn receive_header(&self, uart: &dyn Uart) -> Result<Option<FileInfo>> {
+        uart.write(&[Self::CRC])?;
+        let mut errors = 0usize;
+        loop {
+            let Some(packet) = self.receive_packet(uart)? else {
+                return Err(XmodemError::BadHeader("unexpected end of file".into()).into());
+            };
+            if packet.block != 0 || packet.complement != 255 {
+                self.cancel(uart)?;
+                return Err(XmodemError::Cancelled.into());
+            }
+            if packet.crc_ok() {
+                uart.write(&[Self::ACK])?;
+                return FileInfo::parse(&packet.data);
+            }
+            uart.write(&[Self::NAK])?;
+            errors += 1;
+            if errors >= self.max_errors {
+                return Err(XmodemError::ExhaustedRetries(errors).into());
+            }
+        }
+    }
+}
+
+struct Packet {
+    block: u8,
+    complement: u8,
+    data: Vec<u8>,
+    crc: u16,
+}
+
+impl Packet {
+    fn crc_ok(&self) -> bool {
+        Xmodem::crc16(&self.data) == self.crc
+    }
+}
+
+/// The metadata carried in a YMODEM block 0 header.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: Option<usize>,
+}
+
+impl FileInfo {
+    // The header is the NUL-terminated file name followed by the file size in decimal.
+    // Any further space-separated fields (modification time, mode, ...) are ignored.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut header = self.name.as_bytes().to_vec();
+        header.push(0);
+        if let Some(size) = self.size {
+            header.extend_from_slice(size.to_string().as_bytes());
+        }
+        header.push(0);
+        header
+    }
+
+    fn parse(header: &[u8]) -> Result<Option<Self>> {
+        let mut fields = header.split(|&b| b == 0);
+        let name = fields.next().unwrap_or_default();
+        if name.is_empty() {
+            return Ok(None);
+        }
+        let name = std::str::from_utf8(name)
+            .map_err(|_| XmodemError::BadHeader("file name is not UTF-8".into()))?;
+        let size = fields
+            .next()
+            .and_then(|f| std::str::from_utf8(f).ok())
+            .and_then(|f| f.split_whitespace().next())
+            .map(|f| {
+                f.parse::<usize>()
+                    .map_err(|_| XmodemError::BadHeader(format!("bad file size: {f:?}")))
+            })
+            .transpose()?;
+        Ok(Some(FileInfo {
+            name: name.into(),
+            size,
+        }))
+    }
+}
+
+/// A file transferred in a YMODEM batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct YmodemFile {
+    pub info: FileInfo,
+    pub data: Vec<u8>,
+}
+
+impl YmodemFile {
+    pub fn new(name: &str, data: Vec<u8>) -> Self {
+        YmodemFile {
+            info: FileInfo {
+                name: name.into(),
+                size: Some(data.len()),
+            },
+            data,
+        }
+    }
 }
 
-// The xmodem tests depend on the lrzsz package which contains the classic
-// XMODEM/YMODEM/ZMODEM file transfer programs dating back to the 1980s and
-// 1990s.
+// Most of the xmodem tests depend on the lrzsz package which contains the
+// classic XMODEM/YMODEM/ZMODEM file transfer programs dating back to the
+// 1980s and 1990s.  The loopback tests run both sides of the transfer
+// in-process over a pair of `MemoryUart`s.
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::util::testing::{ChildUart, TransferState};
+    use crate::util::testing::{ChildUart, MemoryUart, TransferState};
     use crate::util::tmpfilename;
 
     #[rustfmt::skip]
@@ -404,4 +589,79 @@ November 19, 1863
         assert_eq!(err.unwrap_err().to_string(), "Cancelled");
         Ok(())
     }
+
+    fn batch() -> Vec<YmodemFile> {
+        let gettysburg = GETTYSBURG.as_bytes();
+        vec![
+            YmodemFile::new("rom_ext.bin", gettysburg[..1500].to_vec()),
+            YmodemFile::new("bl0.bin", gettysburg.to_vec()),
+            YmodemFile::new("owner.bin", gettysburg[..128].to_vec()),
+        ]
+    }
+
+    #[test]
+    fn test_xmodem_loopback() -> Result<()> {
+        let (tx, rx) = MemoryUart::pair();
+        let sender = std::thread::spawn(move || Xmodem::new().send(&tx, GETTYSBURG.as_bytes()));
+        let mut result = Vec::new();
+        Xmodem::new().receive(&rx, &mut result)?;
+        sender.join().unwrap()?;
+        let gettysburg = GETTYSBURG.as_bytes();
+        assert_eq!(result.len() % 1024, 0);
+        assert_eq!(&result[..gettysburg.len()], gettysburg);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ymodem_batch_loopback() -> Result<()> {
+        let (tx, rx) = MemoryUart::pair();
+        let files = batch();
+        let sent = files.clone();
+        let sender = std::thread::spawn(move || Xmodem::new().send_batch(&tx, &sent));
+        let result = Xmodem::new().receive_batch(&rx)?;
+        sender.join().unwrap()?;
+        assert_eq!(result, files);
+        assert_eq!(result[0].info.name, "rom_ext.bin");
+        assert_eq!(result[0].info.size, Some(1500));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ymodem_batch_with_errors() -> Result<()> {
+        // Corrupt the first header and a data block of the first file.
+        let (tx, rx) =
+            MemoryUart::pair_corrupt(TransferState::new(&[10, 500]), TransferState::default());
+        let files = batch();
+        let sent = files.clone();
+        let sender = std::thread::spawn(move || Xmodem::new().send_batch(&tx, &sent));
+        let result = Xmodem::new().receive_batch(&rx)?;
+        sender.join().unwrap()?;
+        assert_eq!(result, files);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ymodem_empty_batch() -> Result<()> {
+        let (tx, rx) = MemoryUart::pair();
+        let sender = std::thread::spawn(move || Xmodem::new().send_batch(&tx, &[]));
+        let result = Xmodem::new().receive_batch(&rx)?;
+        sender.join().unwrap()?;
+        assert!(result.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ymodem_header() -> Result<()> {
+        let info = FileInfo::parse(b"owner.bin\x002048 14537321634 100644\x00\x00")?;
+        assert_eq!(
+            info,
+            Some(FileInfo {
+                name: "owner.bin".into(),
+                size: Some(2048),
+            })
+        );
+        assert_eq!(FileInfo::parse(&[0u8; 128])?, None);
+        assert!(FileInfo::parse(b"owner.bin\x00big\x00").is_err());
+        Ok(())
+    }
 }
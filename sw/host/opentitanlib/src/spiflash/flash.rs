@@ -3,14 +3,19 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::app::NoProgressBar;
-use crate::io::eeprom::{AddressMode, Mode, Transaction, MODE_111, MODE_112, MODE_114};
+use crate::io::eeprom::{
+    AddressMode, Mode, Transaction, MODE_111, MODE_112, MODE_114, MODE_118, MODE_144, MODE_188,
+};
 use crate::io::spi::Target;
 use crate::spiflash::sfdp::{
-    BlockEraseSize, FastReadParam, SectorErase, Sfdp, SupportedAddressModes,
+    BlockEraseSize, FastReadParam, JedecParams, SectorErase, SectorMapAddressLength,
+    SectorMapConfig, Sfdp, SupportedAddressModes,
 };
-use crate::transport::ProgressIndicator;
+use crate::transport::{Capabilities, Capability, ProgressIndicator};
 use anyhow::{ensure, Result};
 use clap::ValueEnum;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::convert::TryFrom;
 use thiserror::Error;
 
@@ -26,8 +31,20 @@ pub enum Error {
     BadSequenceLength(usize),
     #[error("unsupported mode: {0:?}")]
     UnsupportedMode(ReadMode),
+    #[error("unsupported program mode: {0:?}")]
+    UnsupportedProgramMode(ProgramMode),
     #[error("unsupported opcode: {0:x?}")]
     UnsupportedOpcode(u8),
+    #[error("unsupported quad enable requirement: {0}")]
+    UnsupportedQuadEnable(u8),
+    #[error("no erase operation at address {0}")]
+    NoEraseOperation(u32),
+    #[error("sector map has no configuration {0}")]
+    UnknownSectorMapConfig(u8),
+    #[error("verify failed at address {0:#x}")]
+    VerifyFailed(u32),
+    #[error("verify failed: digest mismatch over {0:#x}..{1:#x}")]
+    VerifyDigestFailed(u32, u32),
 }
 
 impl From<SupportedAddressModes> for AddressMode {
@@ -118,15 +135,61 @@ pub enum EraseMode {
     Block,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
+#[value(rename_all = "verbatim")]
+pub enum ProgramMode {
+    #[default]
+    Standard,
+    Quad,
+    Octal,
+}
+
+/// A contiguous range of the SPI flash supporting the same erase operations.
+#[derive(Clone, Debug)]
+pub struct EraseRegion {
+    pub start: u32,
+    pub size: u32,
+    /// Erase operations supported in the region, sorted largest to smallest.
+    pub erase: Vec<SectorErase>,
+}
+
+/// Statistics of a differential program operation.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ProgramStats {
+    /// Number of bytes erased.
+    pub erased: u32,
+    /// Number of bytes programmed.
+    pub programmed: u32,
+    /// Number of bytes in erase sectors which already held the requested contents.
+    pub unchanged: u32,
+}
+
+/// The status registers holding the quad enable bit, and how to write them.
+struct QuadEnable {
+    /// Opcode writing `status`.
+    opcode: u8,
+    /// The current values of the status registers written by `opcode`.
+    status: Vec<u8>,
+    /// Whether `status` was read from the device, rather than assumed.
+    known: bool,
+    /// The byte and bit of `status` holding the quad enable bit.
+    byte: usize,
+    bit: u8,
+}
+
 pub struct SpiFlash {
     pub size: u32,
     pub program_size: u32,
     pub address_mode: AddressMode,
     pub read_mode: ReadMode,
     pub erase_mode: EraseMode,
+    pub program_mode: ProgramMode,
     pub sfdp: Option<Sfdp>,
     pub read_type: ReadTypes,
     pub erase: Vec<SectorErase>,
+    /// The erase regions of a flash with non-uniform sectors.  When empty, the operations in
+    /// `erase` may be used anywhere.
+    pub regions: Vec<EraseRegion>,
 }
 
 impl Default for SpiFlash {
@@ -137,6 +200,7 @@ impl Default for SpiFlash {
             address_mode: Default::default(),
             read_mode: Default::default(),
             erase_mode: Default::default(),
+            program_mode: Default::default(),
             sfdp: None,
             read_type: Default::default(),
             erase: vec![SectorErase {
@@ -144,6 +208,7 @@ impl Default for SpiFlash {
                 opcode: SpiFlash::SECTOR_ERASE,
                 time: None,
             }],
+            regions: Vec::new(),
         }
     }
 }
@@ -159,6 +224,11 @@ impl SpiFlash {
     pub const FAST_DUAL_READ_4B: u8 = 0x3c;
     pub const FAST_QUAD_READ_4B: u8 = 0x6c;
     pub const PAGE_PROGRAM: u8 = 0x02;
+    pub const PAGE_PROGRAM_4B: u8 = 0x12;
+    pub const QUAD_PAGE_PROGRAM_4B: u8 = 0x34;
+    pub const QUAD_IO_PAGE_PROGRAM_4B: u8 = 0x3e;
+    pub const OCTAL_PAGE_PROGRAM_4B: u8 = 0x84;
+    pub const OCTAL_IO_PAGE_PROGRAM_4B: u8 = 0x8e;
     pub const SECTOR_ERASE: u8 = 0x20;
     pub const BLOCK_ERASE_32K: u8 = 0x52;
     pub const BLOCK_ERASE_64K: u8 = 0xD8;
@@ -176,6 +246,9 @@ impl SpiFlash {
     // Winbond parts use 0x31 and 0x11 for extended status writes.
     pub const WRITE_STATUS2: u8 = 0x31;
     pub const WRITE_STATUS3: u8 = 0x11;
+    // Some parts keep the quad enable bit in a status register accessed with 0x3f and 0x3e.
+    pub const READ_STATUS2_ALT: u8 = 0x3f;
+    pub const WRITE_STATUS2_ALT: u8 = 0x3e;
     pub const READ_ID: u8 = 0x9f;
     pub const ENTER_4B: u8 = 0xb7;
     pub const EXIT_4B: u8 = 0xe9;
@@ -307,22 +380,99 @@ impl SpiFlash {
         // Sort largest to smallest.
         erase.sort_by(|a, b| b.size.cmp(&a.size));
 
+        // A sector map without detection commands describes a single layout.
+        let regions = match &sfdp.sector_map {
+            Some(map) if map.detect.is_empty() => map
+                .config(0)
+                .map(|config| Self::erase_regions(&sfdp.jedec, config))
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
         SpiFlash {
             size: sfdp.jedec.density,
             program_size: SpiFlash::LEGACY_PAGE_SIZE,
             address_mode: AddressMode::from(sfdp.jedec.address_modes),
             read_mode: Default::default(),
             erase_mode: Default::default(),
+            program_mode: Default::default(),
             sfdp: Some(sfdp),
             read_type,
             erase,
+            regions,
         }
     }
 
     /// Create a new `SpiFlash` instance by reading an SFDP table from the `spi` Target.
     pub fn from_spi(spi: &dyn Target) -> Result<Self> {
         let sfdp = SpiFlash::read_sfdp(spi)?;
-        Ok(SpiFlash::from_sfdp(sfdp))
+        let mut flash = SpiFlash::from_sfdp(sfdp);
+        flash.detect_sector_map(spi)?;
+        Ok(flash)
+    }
+
+    fn erase_regions(jedec: &JedecParams, config: &SectorMapConfig) -> Vec<EraseRegion> {
+        let mut start = 0u32;
+        config
+            .regions
+            .iter()
+            .map(|region| {
+                let mut erase = jedec
+                    .erase
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, e)| region.erase_types & (1 << i) != 0 && e.size != 0)
+                    .map(|(_, e)| e.clone())
+                    .collect::<Vec<_>>();
+                erase.sort_by(|a, b| b.size.cmp(&a.size));
+                let erase_region = EraseRegion {
+                    start,
+                    size: region.size,
+                    erase,
+                };
+                start = start.saturating_add(region.size);
+                erase_region
+            })
+            .collect()
+    }
+
+    /// Run the sector map detection commands of the SFDP against the `spi` target and set up
+    /// the erase regions of the configuration it reports.
+    pub fn detect_sector_map(&mut self, spi: &dyn Target) -> Result<()> {
+        let Some(sfdp) = &self.sfdp else {
+            return Ok(());
+        };
+        let Some(map) = sfdp.sector_map.as_ref().filter(|m| !m.detect.is_empty()) else {
+            return Ok(());
+        };
+        // Each command contributes one bit to the configuration ID, most significant first.
+        let mut id = 0u8;
+        for command in map.detect.iter() {
+            let address_mode = match command.address_length {
+                SectorMapAddressLength::None => None,
+                SectorMapAddressLength::Mode3b => Some(AddressMode::Mode3b),
+                SectorMapAddressLength::Mode4b => Some(AddressMode::Mode4b),
+                _ => Some(self.address_mode),
+            };
+            let mode = MODE_111.dummy_cycles(
+                command
+                    .read_latency
+                    .unwrap_or(self.read_type.fast.wait_states),
+            );
+            let cmd = match address_mode {
+                Some(address_mode) => mode.cmd_addr(command.opcode, command.address, address_mode),
+                None => mode.cmd(command.opcode),
+            };
+            let mut data = 0u8;
+            spi.run_eeprom_transactions(&mut [Transaction::Read(
+                cmd,
+                std::slice::from_mut(&mut data),
+            )])?;
+            id = (id << 1) | (data & command.read_data_mask != 0) as u8;
+        }
+        let config = map.config(id).ok_or(Error::UnknownSectorMapConfig(id))?;
+        self.regions = Self::erase_regions(&sfdp.jedec, config);
+        Ok(())
     }
 
     /// Set the SPI flash addressing mode to either 3b or 4b mode.
@@ -348,6 +498,140 @@ impl SpiFlash {
         )
     }
 
+    /// Reads the status registers holding the quad enable bit of the `spi` target, according to
+    /// the quad enable requirements in the SFDP (JESD216B section 6.4.18).  Returns `None` if
+    /// the device has no quad enable bit.
+    fn read_quad_enable(&self, spi: &dyn Target) -> Result<Option<QuadEnable>> {
+        let Some(rev_b) = self.sfdp.as_ref().and_then(|s| s.jedec.rev_b.as_ref()) else {
+            return Ok(None);
+        };
+        let read = |opcode| -> Result<u8> {
+            let mut value = 0u8;
+            spi.run_eeprom_transactions(&mut [Transaction::Read(
+                MODE_111.cmd(opcode),
+                std::slice::from_mut(&mut value),
+            )])?;
+            Ok(value)
+        };
+        let qe = match rev_b.quad_enable_requirements {
+            // The device has no quad enable bit.
+            0 => return Ok(None),
+            // QE is bit 1 of status register 2, which is written along with status register 1.
+            1 | 5 => QuadEnable {
+                opcode: SpiFlash::WRITE_STATUS,
+                status: vec![read(SpiFlash::READ_STATUS)?, read(SpiFlash::READ_STATUS2)?],
+                known: true,
+                byte: 1,
+                bit: 0x02,
+            },
+            // QE is bit 1 of status register 2, which cannot be read and is written along with
+            // status register 1.  Its other bits are assumed to be clear.
+            4 => QuadEnable {
+                opcode: SpiFlash::WRITE_STATUS,
+                status: vec![read(SpiFlash::READ_STATUS)?, 0],
+                known: false,
+                byte: 1,
+                bit: 0x02,
+            },
+            // QE is bit 6 of status register 1.
+            2 => QuadEnable {
+                opcode: SpiFlash::WRITE_STATUS,
+                status: vec![read(SpiFlash::READ_STATUS)?],
+                known: true,
+                byte: 0,
+                bit: 0x40,
+            },
+            // QE is bit 7 of status register 2.
+            3 => QuadEnable {
+                opcode: SpiFlash::WRITE_STATUS2_ALT,
+                status: vec![read(SpiFlash::READ_STATUS2_ALT)?],
+                known: true,
+                byte: 0,
+                bit: 0x80,
+            },
+            // QE is bit 1 of status register 2, which is written on its own.
+            6 => QuadEnable {
+                opcode: SpiFlash::WRITE_STATUS2,
+                status: vec![read(SpiFlash::READ_STATUS2)?],
+                known: true,
+                byte: 0,
+                bit: 0x02,
+            },
+            n => return Err(Error::UnsupportedQuadEnable(n).into()),
+        };
+        Ok(Some(qe))
+    }
+
+    /// Whether quad mode can be used without changing the quad enable bit of the `spi` target.
+    /// Returns `None` if the bit cannot be read.
+    pub fn quad_enabled(&self, spi: &dyn Target) -> Result<Option<bool>> {
+        Ok(match self.read_quad_enable(spi)? {
+            None => Some(true),
+            Some(qe) if qe.known => Some(qe.status[qe.byte] & qe.bit != 0),
+            Some(_) => None,
+        })
+    }
+
+    /// Set or clear the quad enable bit of the `spi` target, according to the quad enable
+    /// requirements in the SFDP (JESD216B section 6.4.18).
+    pub fn set_quad_enable(&self, spi: &dyn Target, enable: bool) -> Result<()> {
+        let Some(qe) = self.read_quad_enable(spi)? else {
+            return Ok(());
+        };
+        let mut data = qe.status.clone();
+        if enable {
+            data[qe.byte] |= qe.bit;
+        } else {
+            data[qe.byte] &= !qe.bit;
+        }
+        // The status registers are non-volatile, avoid wearing them out by rewriting the same
+        // value every time the program mode is set.
+        if qe.known && data == qe.status {
+            return Ok(());
+        }
+        spi.run_eeprom_transactions(&mut [
+            Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+            Transaction::Write(MODE_111.cmd(qe.opcode), &data),
+            Transaction::WaitForBusyClear,
+        ])?;
+        Ok(())
+    }
+
+    /// Set the mode used by program operations, setting the quad enable bit of the `spi` target
+    /// if the mode requires it.
+    pub fn set_program_mode(&mut self, spi: &dyn Target, mode: ProgramMode) -> Result<()> {
+        self.select_program(mode)?;
+        if mode == ProgramMode::Quad {
+            self.set_quad_enable(spi, true)?;
+        }
+        self.program_mode = mode;
+        Ok(())
+    }
+
+    /// Automatically set the fastest program mode supported by both the SPI flash and the
+    /// transport `capabilities`.  Quad mode is only selected if the quad enable bit is known to
+    /// be set already, as setting it rewrites non-volatile status registers.
+    pub fn set_program_mode_auto(
+        &mut self,
+        spi: &dyn Target,
+        capabilities: &Capabilities,
+    ) -> Result<()> {
+        let mut modes = Vec::new();
+        if capabilities.request(Capability::SPI_OCTAL).ok().is_ok() {
+            modes.push(ProgramMode::Octal);
+        }
+        if capabilities.request(Capability::SPI_QUAD).ok().is_ok()
+            && self.quad_enabled(spi)? == Some(true)
+        {
+            modes.push(ProgramMode::Quad);
+        }
+        let mode = modes
+            .into_iter()
+            .find(|&mode| self.select_program(mode).is_ok())
+            .unwrap_or(ProgramMode::Standard);
+        self.set_program_mode(spi, mode)
+    }
+
     /// Read into `buffer` from the SPI flash starting at `address`.
     pub fn read(&self, spi: &dyn Target, address: u32, buffer: &mut [u8]) -> Result<&Self> {
         self.read_with_progress(spi, address, buffer, &NoProgressBar, false)
@@ -426,19 +710,67 @@ impl SpiFlash {
         self.erase_with_progress(spi, address, length, &NoProgressBar)
     }
 
+    /// Returns the erase operations usable at `address` and the end of the region containing it.
+    fn erase_region(&self, address: u32) -> Result<(&[SectorErase], u32)> {
+        if self.regions.is_empty() {
+            return Ok((&self.erase, u32::MAX));
+        }
+        self.regions
+            .iter()
+            .find(|r| address >= r.start && address - r.start < r.size)
+            .map(|r| (r.erase.as_slice(), r.start.saturating_add(r.size)))
+            .ok_or_else(|| Error::AddressOutOfBounds(address, self.size).into())
+    }
+
+    /// Returns the smallest erase operation usable at `address`.
+    fn min_erase_size(&self, address: u32) -> Result<u32> {
+        let (erase, _) = self.erase_region(address)?;
+        Ok(erase.last().ok_or(Error::NoEraseOperation(address))?.size)
+    }
+
     fn select_erase(&self, address: u32, length: u32) -> Result<&SectorErase> {
-        if self.erase_mode == EraseMode::Standard {
+        self.select_erase_with_mode(address, length, self.erase_mode)
+    }
+
+    fn select_erase_with_mode(
+        &self,
+        address: u32,
+        length: u32,
+        mode: EraseMode,
+    ) -> Result<&SectorErase> {
+        let (erase, region_end) = self.erase_region(address)?;
+        let smallest = erase.last().ok_or(Error::NoEraseOperation(address))?;
+        if mode == EraseMode::Standard {
             // We assume the last element of the `erase` list is the standard
             // SECTOR_ERASE.  So far, this has been true for all eeproms
             // encountered by the author.
-            return Ok(self.erase.last().unwrap());
+            return Ok(smallest);
         }
-        for e in self.erase.iter() {
+        // Don't let a large erase spill over into a region which doesn't support it.
+        let length = std::cmp::min(length, region_end - address);
+        for e in erase.iter() {
             if address % e.size == 0 && length >= e.size {
                 return Ok(e);
             }
         }
-        Err(Error::BadEraseAddress(address, self.erase.last().unwrap().size).into())
+        Err(Error::BadEraseAddress(address, smallest.size).into())
+    }
+
+    /// Returns the 4-byte address variant of the erase `opcode` from the SFDP.
+    fn erase_opcode_4b(&self, opcode: u8) -> Option<u8> {
+        let sfdp = self.sfdp.as_ref()?;
+        let four_byte = sfdp.four_byte.as_ref()?;
+        sfdp.jedec
+            .erase
+            .iter()
+            .zip(four_byte.erase_opcodes)
+            .find_map(|(e, opcode_4b)| {
+                if e.size != 0 && e.opcode == opcode {
+                    opcode_4b
+                } else {
+                    None
+                }
+            })
     }
 
     /// Erase a segment of the SPI flash starting at `address` for `length` bytes.
@@ -451,23 +783,22 @@ impl SpiFlash {
         length: u32,
         progress: &dyn ProgressIndicator,
     ) -> Result<&Self> {
-        let min_erase_size = self.erase.last().unwrap().size;
+        let min_erase_size = self.min_erase_size(address)?;
         if address % min_erase_size != 0 {
             return Err(Error::BadEraseAddress(address, min_erase_size).into());
         }
-        if length % min_erase_size != 0 {
-            return Err(Error::BadEraseLength(length, min_erase_size).into());
+        let end = address + length;
+        if length != 0 {
+            let min_erase_size = self.min_erase_size(end - 1)?;
+            if end % min_erase_size != 0 {
+                return Err(Error::BadEraseLength(length, min_erase_size).into());
+            }
         }
         progress.new_stage("", length as usize);
-        let end = address + length;
         let mut addr = address;
         while addr < end {
             let erase = self.select_erase(addr, end - addr)?;
-            spi.run_eeprom_transactions(&mut [
-                Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
-                Transaction::Command(MODE_111.cmd_addr(erase.opcode, addr, self.address_mode)),
-                Transaction::WaitForBusyClear,
-            ])?;
+            self.erase_block(spi, addr, erase)?;
             progress.progress((addr - address) as usize);
             addr += erase.size;
         }
@@ -475,6 +806,64 @@ impl SpiFlash {
         Ok(self)
     }
 
+    fn erase_block(&self, spi: &dyn Target, address: u32, erase: &SectorErase) -> Result<()> {
+        // Prefer the 4-byte address opcodes, which don't depend on the addressing mode
+        // of the device.
+        let opcode = match self.address_mode {
+            AddressMode::Mode4b => self.erase_opcode_4b(erase.opcode).unwrap_or(erase.opcode),
+            AddressMode::Mode3b => erase.opcode,
+        };
+        spi.run_eeprom_transactions(&mut [
+            Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+            Transaction::Command(MODE_111.cmd_addr(opcode, address, self.address_mode)),
+            Transaction::WaitForBusyClear,
+        ])?;
+        Ok(())
+    }
+
+    /// Returns the lane mode, opcode and address mode used to program in `mode`.  All but
+    /// single-lane programming rely on the 4-byte address instructions in the SFDP.
+    fn select_program(&self, mode: ProgramMode) -> Result<(Mode, u8, AddressMode)> {
+        let four_byte = self.sfdp.as_ref().and_then(|s| s.four_byte.as_ref());
+        // The eight-lane instructions were reserved bits of the 4-byte address instruction
+        // table before JESD216D, so only trust them on parts which describe eight-lane modes.
+        // Programming eight lanes requiring an octal enable bit is not supported.
+        let octal = self
+            .sfdp
+            .as_ref()
+            .and_then(|s| s.jedec.rev_d.as_ref())
+            .is_some_and(|d| d.octal_enable_requirements == 0);
+        match (mode, four_byte) {
+            (ProgramMode::Standard, Some(f))
+                if self.address_mode == AddressMode::Mode4b && f.support_page_program_111 =>
+            {
+                Ok((MODE_111, SpiFlash::PAGE_PROGRAM_4B, AddressMode::Mode4b))
+            }
+            (ProgramMode::Standard, _) => Ok((MODE_111, SpiFlash::PAGE_PROGRAM, self.address_mode)),
+            (ProgramMode::Quad, Some(f)) if f.support_page_program_114 => Ok((
+                MODE_114,
+                SpiFlash::QUAD_PAGE_PROGRAM_4B,
+                AddressMode::Mode4b,
+            )),
+            (ProgramMode::Quad, Some(f)) if f.support_page_program_144 => Ok((
+                MODE_144,
+                SpiFlash::QUAD_IO_PAGE_PROGRAM_4B,
+                AddressMode::Mode4b,
+            )),
+            (ProgramMode::Octal, Some(f)) if octal && f.support_page_program_118 => Ok((
+                MODE_118,
+                SpiFlash::OCTAL_PAGE_PROGRAM_4B,
+                AddressMode::Mode4b,
+            )),
+            (ProgramMode::Octal, Some(f)) if octal && f.support_page_program_188 => Ok((
+                MODE_188,
+                SpiFlash::OCTAL_IO_PAGE_PROGRAM_4B,
+                AddressMode::Mode4b,
+            )),
+            _ => Err(Error::UnsupportedProgramMode(mode).into()),
+        }
+    }
+
     /// Program a segment of the SPI flash starting at `address` with the contents of `buffer`.
     /// The address and buffer length may be arbitrary.  This function will not
     /// erase the segment first.
@@ -494,6 +883,7 @@ impl SpiFlash {
         progress: &dyn ProgressIndicator,
     ) -> Result<&Self> {
         progress.new_stage("", buffer.len());
+        self.select_program(self.program_mode)?;
         let mut remain = buffer.len();
         let mut chunk_start = 0usize;
         while remain != 0 {
@@ -508,14 +898,7 @@ impl SpiFlash {
             let chunk = &buffer[chunk_start..chunk_end];
             // Skip this chunk if all bytes are 0xff.
             if !chunk.iter().all(|&x| x == 0xff) {
-                spi.run_eeprom_transactions(&mut [
-                    Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
-                    Transaction::Write(
-                        MODE_111.cmd_addr(SpiFlash::PAGE_PROGRAM, address, self.address_mode),
-                        chunk,
-                    ),
-                    Transaction::WaitForBusyClear,
-                ])?;
+                self.program_page(spi, address, chunk)?;
             }
             address += chunk_size as u32;
             chunk_start += chunk_size;
@@ -525,6 +908,143 @@ impl SpiFlash {
         Ok(self)
     }
 
+    /// Program `chunk`, which must not cross a program page boundary, at `address`.
+    fn program_page(&self, spi: &dyn Target, address: u32, chunk: &[u8]) -> Result<()> {
+        let (mode, opcode, address_mode) = self.select_program(self.program_mode)?;
+        spi.run_eeprom_transactions(&mut [
+            Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+            Transaction::Write(mode.cmd_addr(opcode, address, address_mode), chunk),
+            Transaction::WaitForBusyClear,
+        ])?;
+        Ok(())
+    }
+
+    /// Program a segment of the SPI flash starting at `address` with the contents of `buffer`,
+    /// erasing and programming only what differs from the current contents of the flash.
+    /// The address and buffer length may be arbitrary; data sharing an erase sector with the
+    /// segment is preserved.  The result is verified by reading it back.
+    pub fn program_differential(
+        &self,
+        spi: &dyn Target,
+        address: u32,
+        buffer: &[u8],
+    ) -> Result<ProgramStats> {
+        self.program_differential_with_progress(spi, address, buffer, &NoProgressBar)
+    }
+
+    /// Program a segment of the SPI flash starting at `address` with the contents of `buffer`,
+    /// erasing and programming only what differs from the current contents of the flash.
+    /// The address and buffer length may be arbitrary; data sharing an erase sector with the
+    /// segment is preserved.  The result is verified by reading it back, or by comparing
+    /// digests if `spi` can compute them.
+    /// The `progress` callback will be invoked after each chunk of the read, erase, program
+    /// and verify stages.
+    pub fn program_differential_with_progress(
+        &self,
+        spi: &dyn Target,
+        address: u32,
+        buffer: &[u8],
+        progress: &dyn ProgressIndicator,
+    ) -> Result<ProgramStats> {
+        let mut stats = ProgramStats::default();
+        if buffer.is_empty() {
+            return Ok(stats);
+        }
+        self.select_program(self.program_mode)?;
+
+        // Operate on whole erase sectors, so that the data around the segment can be restored
+        // if its sectors need erasing.
+        let end = u32::try_from(buffer.len())
+            .ok()
+            .and_then(|len| address.checked_add(len))
+            .ok_or(Error::AddressOutOfBounds(address, self.size))?;
+        ensure!(end <= self.size, Error::AddressOutOfBounds(end, self.size));
+        let start = address - address % self.min_erase_size(address)?;
+        let end = end.next_multiple_of(self.min_erase_size(end - 1)?);
+        let mut current = vec![0u8; (end - start) as usize];
+        self.read_with_progress(spi, start, &mut current, progress, false)?;
+        let mut target = current.clone();
+        let offset = (address - start) as usize;
+        target[offset..offset + buffer.len()].copy_from_slice(buffer);
+
+        // Find the runs of sectors which need erasing, because programming can only clear bits.
+        let mut erase_runs: Vec<(u32, u32)> = Vec::new();
+        let mut sector = start;
+        while sector < end {
+            let size = self.min_erase_size(sector)?;
+            let range = (sector - start) as usize..(sector - start + size) as usize;
+            let (old, new) = (&current[range.clone()], &target[range]);
+            if old == new {
+                stats.unchanged += size;
+            } else if old.iter().zip(new).any(|(&o, &n)| o & n != n) {
+                match erase_runs.last_mut() {
+                    Some((_, run_end)) if *run_end == sector => *run_end += size,
+                    _ => erase_runs.push((sector, sector + size)),
+                }
+            }
+            sector += size;
+        }
+
+        stats.erased = erase_runs.iter().map(|(s, e)| e - s).sum();
+        progress.new_stage("", stats.erased as usize);
+        let mut erased = 0;
+        for &(run_start, run_end) in erase_runs.iter() {
+            let mut addr = run_start;
+            while addr < run_end {
+                let erase = self.select_erase_with_mode(addr, run_end - addr, EraseMode::Block)?;
+                self.erase_block(spi, addr, erase)?;
+                addr += erase.size;
+                erased += erase.size;
+                progress.progress(erased as usize);
+            }
+            current[(run_start - start) as usize..(run_end - start) as usize].fill(0xff);
+        }
+
+        // Program the pages which differ.  The erased sectors are aligned to the page size.
+        let pages = (start..end)
+            .step_by(self.program_size as usize)
+            .map(|page| {
+                let range = (page - start) as usize
+                    ..std::cmp::min(page + self.program_size, end) as usize - start as usize;
+                (page, range)
+            })
+            .filter(|(_, range)| current[range.clone()] != target[range.clone()])
+            .collect::<Vec<_>>();
+        progress.new_stage("", pages.len() * self.program_size as usize);
+        for (i, (page, range)) in pages.iter().enumerate() {
+            self.program_page(spi, *page, &target[range.clone()])?;
+            stats.programmed += range.len() as u32;
+            progress.progress((i + 1) * self.program_size as usize);
+        }
+
+        self.verify(spi, start, &target, progress)?;
+        Ok(stats)
+    }
+
+    /// Check that the SPI flash holds `expected` at `address`.
+    fn verify(
+        &self,
+        spi: &dyn Target,
+        address: u32,
+        expected: &[u8],
+        progress: &dyn ProgressIndicator,
+    ) -> Result<()> {
+        let length = expected.len() as u32;
+        if let Some(digest) = spi.eeprom_sha256(address, length, self.address_mode)? {
+            ensure!(
+                digest[..] == Sha256::digest(expected)[..],
+                Error::VerifyDigestFailed(address, address + length)
+            );
+            return Ok(());
+        }
+        let mut actual = vec![0u8; expected.len()];
+        self.read_with_progress(spi, address, &mut actual, progress, false)?;
+        match actual.iter().zip(expected).position(|(a, e)| a != e) {
+            Some(i) => Err(Error::VerifyFailed(address + i as u32).into()),
+            None => Ok(()),
+        }
+    }
+
     /// Send the software reset sequence to the `spi` target.
     pub fn chip_reset(spi: &dyn Target) -> Result<()> {
         spi.run_eeprom_transactions(&mut [
@@ -534,3 +1054,101 @@ impl SpiFlash {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spiflash::sfdp::{SectorMapConfig, SectorMapRegion};
+
+    const SFDP_MX66L1G: &[u8; 512] = include_bytes!("SFDP_MX66L1G.bin");
+
+    fn mx66l1g() -> Result<SpiFlash> {
+        Ok(SpiFlash::from_sfdp(Sfdp::try_from(&SFDP_MX66L1G[..])?))
+    }
+
+    fn erase_size(flash: &SpiFlash, address: u32, length: u32) -> Result<u32> {
+        Ok(flash.select_erase(address, length)?.size)
+    }
+
+    #[test]
+    fn test_select_erase() -> Result<()> {
+        let mut flash = mx66l1g()?;
+        assert_eq!(erase_size(&flash, 0x10000, 0x20000)?, 4096);
+
+        flash.erase_mode = EraseMode::Block;
+        assert_eq!(erase_size(&flash, 0x10000, 0x20000)?, 65536);
+        assert_eq!(erase_size(&flash, 0x18000, 0x20000)?, 32768);
+        assert_eq!(erase_size(&flash, 0x10000, 0x8000)?, 32768);
+        assert_eq!(erase_size(&flash, 0x11000, 0x20000)?, 4096);
+        assert!(flash.select_erase(0x10800, 0x1000).is_err());
+
+        assert_eq!(flash.erase_opcode_4b(SpiFlash::SECTOR_ERASE), Some(0x21));
+        assert_eq!(flash.erase_opcode_4b(SpiFlash::BLOCK_ERASE_64K), Some(0xdc));
+        assert_eq!(flash.erase_opcode_4b(SpiFlash::CHIP_ERASE), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_erase_sector_map() -> Result<()> {
+        let mut flash = mx66l1g()?;
+        // 64KiB of 4KiB-only parameter sectors followed by uniform 64KiB sectors.
+        let config = SectorMapConfig {
+            id: 0,
+            regions: vec![
+                SectorMapRegion {
+                    size: 0x10000,
+                    erase_types: 0b0001,
+                },
+                SectorMapRegion {
+                    size: flash.size - 0x10000,
+                    erase_types: 0b0100,
+                },
+            ],
+        };
+        flash.regions = SpiFlash::erase_regions(&flash.sfdp.as_ref().unwrap().jedec, &config);
+        flash.erase_mode = EraseMode::Block;
+        assert_eq!(erase_size(&flash, 0x0, 0x20000)?, 4096);
+        assert_eq!(erase_size(&flash, 0xf000, 0x20000)?, 4096);
+        assert_eq!(erase_size(&flash, 0x10000, 0x20000)?, 65536);
+        // Erases outside the 4KiB sectors must be 64KiB-aligned.
+        assert!(flash.select_erase(0x11000, 0x1000).is_err());
+
+        flash.erase_mode = EraseMode::Standard;
+        assert_eq!(erase_size(&flash, 0x10000, 0x20000)?, 65536);
+        assert_eq!(flash.min_erase_size(0x8000)?, 4096);
+        assert_eq!(flash.min_erase_size(0x18000)?, 65536);
+        assert!(flash.min_erase_size(flash.size).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_program() -> Result<()> {
+        let mut flash = mx66l1g()?;
+        let (mode, opcode, address_mode) = flash.select_program(ProgramMode::Standard)?;
+        assert_eq!(mode.width, MODE_111.width);
+        assert_eq!(opcode, SpiFlash::PAGE_PROGRAM);
+        assert_eq!(address_mode, AddressMode::Mode3b);
+
+        flash.address_mode = AddressMode::Mode4b;
+        let (_, opcode, address_mode) = flash.select_program(ProgramMode::Standard)?;
+        assert_eq!(opcode, SpiFlash::PAGE_PROGRAM_4B);
+        assert_eq!(address_mode, AddressMode::Mode4b);
+
+        // The MX66L1G only supports the 1-4-4 quad page program.
+        let (mode, opcode, address_mode) = flash.select_program(ProgramMode::Quad)?;
+        assert_eq!(mode.switch, MODE_144.switch);
+        assert_eq!(mode.width, MODE_144.width);
+        assert_eq!(opcode, SpiFlash::QUAD_IO_PAGE_PROGRAM_4B);
+        assert_eq!(address_mode, AddressMode::Mode4b);
+
+        // It has no JESD216D parameters, so its reserved eight-lane bits are ignored.
+        assert_eq!(
+            flash
+                .select_program(ProgramMode::Octal)
+                .unwrap_err()
+                .to_string(),
+            "unsupported program mode: Octal"
+        );
+        Ok(())
+    }
+}
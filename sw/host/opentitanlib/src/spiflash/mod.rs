@@ -3,7 +3,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod flash;
+pub mod protect;
 pub mod sfdp;
 
-pub use flash::{EraseMode, ReadMode, SpiFlash};
+pub use flash::{EraseMode, ProgramMode, ProgramStats, ReadMode, SpiFlash};
+pub use protect::{BlockProtect, Protection};
 pub use sfdp::{BlockEraseSize, Sfdp, SupportedAddressModes, WriteGranularity};
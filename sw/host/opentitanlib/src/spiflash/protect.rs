@@ -0,0 +1,659 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{ensure, Result};
+use serde::Serialize;
+use std::ops::{Range, RangeInclusive};
+use thiserror::Error;
+
+use crate::io::eeprom::{AddressMode, Transaction, MODE_111};
+use crate::io::spi::Target;
+use crate::spiflash::flash::SpiFlash;
+use crate::spiflash::sfdp::Sfdp;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no block protection layout is known for JEDEC ID {0:02x?}")]
+    UnknownPart(Vec<u8>),
+    #[error("the range {0:#x}..{1:#x} cannot be expressed by the block protection bits")]
+    UnsupportedRange(u32, u32),
+    #[error("the part has no security registers")]
+    NoSecurityRegisters,
+    #[error("security register {0} does not exist")]
+    BadSecurityRegister(u8),
+    #[error("access of {1} bytes at offset {0:#x} exceeds the security register size")]
+    SecurityRegisterBounds(u32, usize),
+    #[error("security register {0} is locked")]
+    SecurityRegisterLocked(u8),
+    #[error("the security registers of this part cannot be erased")]
+    SecurityRegisterNotErasable,
+    #[error("status register reads {actual:#x} after writing {expected:#x} (mask {mask:#x})")]
+    StatusMismatch {
+        expected: u32,
+        actual: u32,
+        mask: u32,
+    },
+}
+
+/// How the status registers of a part are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum StatusWrite {
+    /// The first `n` status registers are written together by `WRITE_STATUS`.
+    Combined(usize),
+    /// Each of the first `n` status registers is written by its own opcode
+    /// (`WRITE_STATUS`, `WRITE_STATUS2` and `WRITE_STATUS3`).
+    Separate(usize),
+    /// Derive the method from the quad enable requirements in the SFDP.
+    Sfdp,
+}
+
+impl StatusWrite {
+    /// Determines how to write the status registers from the quad enable requirements, which
+    /// describe how status register 2 is accessed (JESD216B section 6.4.18).
+    pub fn from_sfdp(sfdp: Option<&Sfdp>) -> Self {
+        match sfdp
+            .and_then(|s| s.jedec.rev_b.as_ref())
+            .map(|r| r.quad_enable_requirements)
+        {
+            Some(1 | 4 | 5) => StatusWrite::Combined(2),
+            Some(2 | 3) => StatusWrite::Combined(1),
+            _ => StatusWrite::Separate(2),
+        }
+    }
+}
+
+/// The size of the range protected by the lowest non-zero value of the BP bits.  Each
+/// increment of the BP bits doubles the size of the range.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub enum ProtectUnit {
+    /// A fraction of the array (e.g. 64 for 1/64th).
+    Fraction(u32),
+    /// A fixed number of bytes.
+    Bytes(u32),
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub enum SecurityRegisterKind {
+    /// Registers at address `(index + 1) << 12`, accessed with dedicated read, program and
+    /// erase opcodes.  Each register is locked by an OTP bit in the status registers, the first
+    /// of which is `lock_bit`.
+    Dedicated { lock_bit: u32 },
+    /// A single secured OTP area which replaces the array between the `ENSO` and `EXSO`
+    /// opcodes, locked by the `LDSO` bit of the security register.
+    SecuredOtp,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct SecurityRegisters {
+    pub kind: SecurityRegisterKind,
+    pub count: u8,
+    pub size: u32,
+}
+
+/// Describes the write protection features of a family of SPI flash parts.
+///
+/// The status register bits are given as masks of the combined status registers, as returned
+/// by `SpiFlash::read_status_ex`: status register 1 in bits 0-7, register 2 in bits 8-15 and
+/// register 3 in bits 16-23.
+#[derive(Clone, Debug, Serialize)]
+pub struct ProtectLayout {
+    pub name: &'static str,
+    /// The block protect (BP) bits.  The largest value protects the whole array.
+    pub bp: u32,
+    /// The top/bottom (TB) bit, which protects the bottom of the array rather than the top.
+    pub tb: Option<u32>,
+    /// The sector/block (SEC) bit, which protects 4KiB sectors rather than blocks.
+    pub sec: Option<u32>,
+    /// The complement (CMP) bit, which protects the rest of the array instead.
+    pub cmp: Option<u32>,
+    /// The status register protect (SRP) bit, which makes the status registers read-only while
+    /// the WP# pin is asserted.
+    pub srp: Option<u32>,
+    pub unit: ProtectUnit,
+    pub status_write: StatusWrite,
+    pub security: Option<SecurityRegisters>,
+}
+
+impl ProtectLayout {
+    /// The largest range protected by the SEC bit.
+    const SECTOR_LIMIT: u32 = 32 * 1024;
+
+    fn bits(&self) -> u32 {
+        self.bp
+            | self.tb.unwrap_or(0)
+            | self.sec.unwrap_or(0)
+            | self.cmp.unwrap_or(0)
+            | self.srp.unwrap_or(0)
+    }
+
+    /// The number of status registers holding the bits of this layout.
+    fn status_registers(&self) -> usize {
+        let mut bits = self.bits();
+        if let Some(SecurityRegisters {
+            kind: SecurityRegisterKind::Dedicated { lock_bit },
+            count,
+            ..
+        }) = self.security
+        {
+            bits |= ((1 << count) - 1) << lock_bit;
+        }
+        (31 - bits.leading_zeros()) as usize / 8 + 1
+    }
+
+    /// Decodes the range protected by `status` in an array of `size` bytes.
+    pub fn decode(&self, status: u32, size: u32) -> Range<u32> {
+        let bit = |mask: Option<u32>| mask.map(|m| status & m != 0).unwrap_or(false);
+        let shift = self.bp.trailing_zeros();
+        let bp = (status & self.bp) >> shift;
+        let length = if bp == 0 {
+            0
+        } else if bp == self.bp >> shift {
+            size
+        } else if bit(self.sec) {
+            std::cmp::min(4096u32 << (bp - 1), Self::SECTOR_LIMIT)
+        } else {
+            let unit = match self.unit {
+                ProtectUnit::Fraction(n) => size / n,
+                ProtectUnit::Bytes(n) => n,
+            };
+            unit.checked_shl(bp - 1)
+                .filter(|&l| l < size && l >> (bp - 1) == unit)
+                .unwrap_or(size)
+        };
+        let range = if bit(self.tb) {
+            0..length
+        } else {
+            size - length..size
+        };
+        let range = match bit(self.cmp) {
+            false => range,
+            true if range.start == 0 => range.end..size,
+            true => 0..range.start,
+        };
+        if range.is_empty() {
+            0..0
+        } else {
+            range
+        }
+    }
+
+    /// Finds the value of the protection bits (excluding SRP) which protects exactly `range` in
+    /// an array of `size` bytes.
+    pub fn encode(&self, range: Range<u32>, size: u32) -> Result<u32> {
+        let range = if range.is_empty() { 0..0 } else { range };
+        let options = |mask: Option<u32>| [Some(0), mask].into_iter().flatten();
+        let shift = self.bp.trailing_zeros();
+        for cmp in options(self.cmp) {
+            for sec in options(self.sec) {
+                for tb in options(self.tb) {
+                    for bp in 0..=self.bp >> shift {
+                        let value = (bp << shift) | tb | sec | cmp;
+                        if self.decode(value, size) == range {
+                            return Ok(value);
+                        }
+                    }
+                }
+            }
+        }
+        Err(Error::UnsupportedRange(range.start, range.end).into())
+    }
+}
+
+/// Parts whose protection bits are described by a `ProtectLayout`, matched by the JEDEC
+/// manufacturer ID, memory type and range of capacity codes.
+struct Part {
+    manufacturer: u8,
+    memory_type: &'static [u8],
+    capacity: RangeInclusive<u8>,
+    layout: ProtectLayout,
+}
+
+static PARTS: &[Part] = &[
+    // The 32Mbit to 128Mbit parts of the Winbond W25Q family.  Smaller parts protect a
+    // different fraction of the array, and larger parts have a fourth BP bit.
+    Part {
+        manufacturer: 0xef,
+        memory_type: &[0x40, 0x60, 0x70],
+        capacity: 0x16..=0x18,
+        layout: ProtectLayout {
+            name: "Winbond W25Q",
+            bp: 0x1c,
+            tb: Some(0x20),
+            sec: Some(0x40),
+            cmp: Some(0x4000),
+            srp: Some(0x80),
+            unit: ProtectUnit::Fraction(64),
+            status_write: StatusWrite::Separate(2),
+            security: Some(SecurityRegisters {
+                kind: SecurityRegisterKind::Dedicated { lock_bit: 11 },
+                count: 3,
+                size: 256,
+            }),
+        },
+    },
+    // The GigaDevice GD25Q parts name the TB and SEC bits BP3 and BP4, but they have the same
+    // meaning as on the Winbond parts.  Older parts lack the `WRITE_STATUS2` opcode.
+    Part {
+        manufacturer: 0xc8,
+        memory_type: &[0x40, 0x60],
+        capacity: 0x16..=0x18,
+        layout: ProtectLayout {
+            name: "GigaDevice GD25Q",
+            bp: 0x1c,
+            tb: Some(0x20),
+            sec: Some(0x40),
+            cmp: Some(0x4000),
+            srp: Some(0x80),
+            unit: ProtectUnit::Fraction(64),
+            status_write: StatusWrite::Sfdp,
+            security: None,
+        },
+    },
+    // The Macronix MX25L parts up to 256Mbit.  The TB bit lives in the OTP configuration
+    // register, so only the top of the array can be protected.
+    Part {
+        manufacturer: 0xc2,
+        memory_type: &[0x20],
+        capacity: 0x16..=0x19,
+        layout: ProtectLayout {
+            name: "Macronix MX25L",
+            bp: 0x3c,
+            tb: None,
+            sec: None,
+            cmp: None,
+            srp: Some(0x80),
+            unit: ProtectUnit::Bytes(64 * 1024),
+            status_write: StatusWrite::Combined(1),
+            security: Some(SecurityRegisters {
+                kind: SecurityRegisterKind::SecuredOtp,
+                count: 1,
+                size: 512,
+            }),
+        },
+    },
+];
+
+/// The protection state of a SPI flash.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Protection {
+    pub start: u32,
+    pub length: u32,
+    /// Whether the status register protect bit is set.
+    pub locked: bool,
+    pub status: u32,
+}
+
+/// Manages the block protection bits and security registers of a SPI flash.
+pub struct BlockProtect {
+    pub layout: &'static ProtectLayout,
+    pub size: u32,
+    status_write: StatusWrite,
+    address_mode: AddressMode,
+}
+
+impl BlockProtect {
+    pub const READ_SECURITY_REGISTER: u8 = 0x48;
+    pub const PROGRAM_SECURITY_REGISTER: u8 = 0x42;
+    pub const ERASE_SECURITY_REGISTER: u8 = 0x44;
+    pub const ENTER_SECURED_OTP: u8 = 0xb1;
+    pub const EXIT_SECURED_OTP: u8 = 0xc1;
+    pub const READ_SECURITY: u8 = 0x2b;
+    pub const WRITE_SECURITY: u8 = 0x2f;
+
+    /// The lock-down bit of the Macronix security register.
+    const SECURITY_LDSO: u8 = 0x02;
+
+    /// Looks up the protection layout of the part with the given JEDEC ID.
+    pub fn lookup(jedec_id: &[u8]) -> Option<&'static ProtectLayout> {
+        let [manufacturer, memory_type, capacity, ..] = *jedec_id else {
+            return None;
+        };
+        PARTS
+            .iter()
+            .find(|p| {
+                p.manufacturer == manufacturer
+                    && p.memory_type.contains(&memory_type)
+                    && p.capacity.contains(&capacity)
+            })
+            .map(|p| &p.layout)
+    }
+
+    /// Identifies the part behind `spi`, which has been probed as `flash`.
+    pub fn new(spi: &dyn Target, flash: &SpiFlash) -> Result<Self> {
+        let jedec_id = SpiFlash::read_jedec_id(spi, 3)?;
+        let layout = Self::lookup(&jedec_id).ok_or(Error::UnknownPart(jedec_id))?;
+        let status_write = match layout.status_write {
+            StatusWrite::Sfdp => StatusWrite::from_sfdp(flash.sfdp.as_ref()),
+            s => s,
+        };
+        Ok(Self {
+            layout,
+            size: flash.size,
+            status_write,
+            address_mode: flash.address_mode,
+        })
+    }
+
+    /// Reads the status registers holding the protection bits.
+    pub fn read_status(&self, spi: &dyn Target) -> Result<u32> {
+        let seq = [
+            SpiFlash::READ_STATUS,
+            SpiFlash::READ_STATUS2,
+            SpiFlash::READ_STATUS3,
+        ];
+        SpiFlash::read_status_ex(spi, Some(&seq[..self.layout.status_registers()]))
+    }
+
+    /// Sets the bits of the status registers selected by `mask` to `value`, leaving the others
+    /// unchanged, and checks that the new value took effect.
+    pub fn write_status(&self, spi: &dyn Target, value: u32, mask: u32) -> Result<()> {
+        let current = self.read_status(spi)?;
+        let new = (current & !mask) | (value & mask);
+        let bytes = new.to_le_bytes();
+        match self.status_write {
+            StatusWrite::Combined(n) => spi.run_eeprom_transactions(&mut [
+                Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+                Transaction::Write(MODE_111.cmd(SpiFlash::WRITE_STATUS), &bytes[..n]),
+                Transaction::WaitForBusyClear,
+            ])?,
+            StatusWrite::Separate(n) => {
+                let opcodes = [
+                    SpiFlash::WRITE_STATUS,
+                    SpiFlash::WRITE_STATUS2,
+                    SpiFlash::WRITE_STATUS3,
+                ];
+                for (i, &opcode) in opcodes.iter().enumerate().take(n) {
+                    if (mask >> (8 * i)) & 0xff == 0 {
+                        continue;
+                    }
+                    spi.run_eeprom_transactions(&mut [
+                        Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+                        Transaction::Write(MODE_111.cmd(opcode), &bytes[i..i + 1]),
+                        Transaction::WaitForBusyClear,
+                    ])?;
+                }
+            }
+            StatusWrite::Sfdp => unreachable!("resolved in BlockProtect::new"),
+        }
+        let actual = self.read_status(spi)?;
+        ensure!(
+            actual & mask == new & mask,
+            Error::StatusMismatch {
+                expected: new,
+                actual,
+                mask
+            }
+        );
+        Ok(())
+    }
+
+    /// Reads the current protection state.
+    pub fn protection(&self, spi: &dyn Target) -> Result<Protection> {
+        let status = self.read_status(spi)?;
+        let range = self.layout.decode(status, self.size);
+        Ok(Protection {
+            start: range.start,
+            length: range.len() as u32,
+            locked: self.layout.srp.map(|m| status & m != 0).unwrap_or(false),
+            status,
+        })
+    }
+
+    /// Protects exactly `range` against program and erase operations.  If `lock` is set, the
+    /// status registers are also protected while the WP# pin is asserted.
+    pub fn protect(&self, spi: &dyn Target, range: Range<u32>, lock: bool) -> Result<()> {
+        let mut value = self.layout.encode(range, self.size)?;
+        if lock {
+            value |= self.layout.srp.unwrap_or(0);
+        }
+        self.write_status(spi, value, self.layout.bits())
+    }
+
+    /// Removes all block protection and the status register protection.
+    pub fn unprotect(&self, spi: &dyn Target) -> Result<()> {
+        self.protect(spi, 0..0, false)
+    }
+
+    fn security(&self, index: u8, offset: u32, length: usize) -> Result<&SecurityRegisters> {
+        let security = self
+            .layout
+            .security
+            .as_ref()
+            .ok_or(Error::NoSecurityRegisters)?;
+        ensure!(index < security.count, Error::BadSecurityRegister(index));
+        ensure!(
+            offset as usize + length <= security.size as usize,
+            Error::SecurityRegisterBounds(offset, length)
+        );
+        Ok(security)
+    }
+
+    /// Reads `buffer.len()` bytes at `offset` of security register `index`.
+    pub fn read_security_register(
+        &self,
+        spi: &dyn Target,
+        index: u8,
+        offset: u32,
+        buffer: &mut [u8],
+    ) -> Result<()> {
+        match self.security(index, offset, buffer.len())?.kind {
+            SecurityRegisterKind::Dedicated { .. } => {
+                spi.run_eeprom_transactions(&mut [Transaction::Read(
+                    MODE_111.dummy_cycles(8).cmd_addr(
+                        Self::READ_SECURITY_REGISTER,
+                        ((index as u32 + 1) << 12) | offset,
+                        AddressMode::Mode3b,
+                    ),
+                    buffer,
+                )])?;
+            }
+            SecurityRegisterKind::SecuredOtp => {
+                spi.run_eeprom_transactions(&mut [
+                    Transaction::Command(MODE_111.cmd(Self::ENTER_SECURED_OTP)),
+                    Transaction::Read(
+                        MODE_111.cmd_addr(SpiFlash::READ, offset, self.address_mode),
+                        buffer,
+                    ),
+                    Transaction::Command(MODE_111.cmd(Self::EXIT_SECURED_OTP)),
+                ])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Programs `data` at `offset` of security register `index`.
+    pub fn program_security_register(
+        &self,
+        spi: &dyn Target,
+        index: u8,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<()> {
+        let security = self.security(index, offset, data.len())?;
+        // The parts silently ignore programming of locked registers.
+        ensure!(
+            !self.security_register_locked(spi, index)?,
+            Error::SecurityRegisterLocked(index)
+        );
+        let mut address = offset;
+        for chunk in data.chunks(SpiFlash::LEGACY_PAGE_SIZE as usize) {
+            let chunk = &chunk[..std::cmp::min(
+                chunk.len(),
+                (SpiFlash::LEGACY_PAGE_SIZE - address % SpiFlash::LEGACY_PAGE_SIZE) as usize,
+            )];
+            match security.kind {
+                SecurityRegisterKind::Dedicated { .. } => {
+                    spi.run_eeprom_transactions(&mut [
+                        Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+                        Transaction::Write(
+                            MODE_111.cmd_addr(
+                                Self::PROGRAM_SECURITY_REGISTER,
+                                ((index as u32 + 1) << 12) | address,
+                                AddressMode::Mode3b,
+                            ),
+                            chunk,
+                        ),
+                        Transaction::WaitForBusyClear,
+                    ])?;
+                }
+                SecurityRegisterKind::SecuredOtp => {
+                    spi.run_eeprom_transactions(&mut [
+                        Transaction::Command(MODE_111.cmd(Self::ENTER_SECURED_OTP)),
+                        Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+                        Transaction::Write(
+                            MODE_111.cmd_addr(SpiFlash::PAGE_PROGRAM, address, self.address_mode),
+                            chunk,
+                        ),
+                        Transaction::WaitForBusyClear,
+                        Transaction::Command(MODE_111.cmd(Self::EXIT_SECURED_OTP)),
+                    ])?;
+                }
+            }
+            address += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    /// Erases security register `index`.
+    pub fn erase_security_register(&self, spi: &dyn Target, index: u8) -> Result<()> {
+        match self.security(index, 0, 0)?.kind {
+            SecurityRegisterKind::Dedicated { .. } => {
+                ensure!(
+                    !self.security_register_locked(spi, index)?,
+                    Error::SecurityRegisterLocked(index)
+                );
+                spi.run_eeprom_transactions(&mut [
+                    Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+                    Transaction::Command(MODE_111.cmd_addr(
+                        Self::ERASE_SECURITY_REGISTER,
+                        (index as u32 + 1) << 12,
+                        AddressMode::Mode3b,
+                    )),
+                    Transaction::WaitForBusyClear,
+                ])?;
+                Ok(())
+            }
+            SecurityRegisterKind::SecuredOtp => Err(Error::SecurityRegisterNotErasable.into()),
+        }
+    }
+
+    /// Whether security register `index` has been permanently locked.
+    pub fn security_register_locked(&self, spi: &dyn Target, index: u8) -> Result<bool> {
+        match self.security(index, 0, 0)?.kind {
+            SecurityRegisterKind::Dedicated { lock_bit } => {
+                Ok(self.read_status(spi)? & (1 << (lock_bit + index as u32)) != 0)
+            }
+            SecurityRegisterKind::SecuredOtp => {
+                let mut security = 0u8;
+                spi.run_eeprom_transactions(&mut [Transaction::Read(
+                    MODE_111.cmd(Self::READ_SECURITY),
+                    std::slice::from_mut(&mut security),
+                )])?;
+                Ok(security & Self::SECURITY_LDSO != 0)
+            }
+        }
+    }
+
+    /// Permanently locks security register `index` against program and erase operations.
+    /// This cannot be undone.
+    pub fn lock_security_register(&self, spi: &dyn Target, index: u8) -> Result<()> {
+        match self.security(index, 0, 0)?.kind {
+            SecurityRegisterKind::Dedicated { lock_bit } => {
+                let bit = 1 << (lock_bit + index as u32);
+                self.write_status(spi, bit, bit)?;
+            }
+            SecurityRegisterKind::SecuredOtp => {
+                spi.run_eeprom_transactions(&mut [
+                    Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+                    Transaction::Command(MODE_111.cmd(Self::WRITE_SECURITY)),
+                    Transaction::WaitForBusyClear,
+                ])?;
+                ensure!(
+                    self.security_register_locked(spi, index)?,
+                    Error::SecurityRegisterLocked(index)
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u32 = 1024 * 1024;
+
+    #[test]
+    fn test_lookup() {
+        let w25q128 = BlockProtect::lookup(&[0xef, 0x40, 0x18]).unwrap();
+        assert_eq!(w25q128.name, "Winbond W25Q");
+        assert_eq!(w25q128.status_registers(), 2);
+        let mx25l256 = BlockProtect::lookup(&[0xc2, 0x20, 0x19]).unwrap();
+        assert_eq!(mx25l256.name, "Macronix MX25L");
+        assert_eq!(mx25l256.status_registers(), 1);
+        // The W25Q256 has a different layout.
+        assert!(BlockProtect::lookup(&[0xef, 0x40, 0x19]).is_none());
+        assert!(BlockProtect::lookup(&[0xef, 0x40]).is_none());
+    }
+
+    #[test]
+    fn test_decode_winbond() {
+        let layout = BlockProtect::lookup(&[0xef, 0x40, 0x18]).unwrap();
+        let size = 16 * MIB;
+        assert_eq!(layout.decode(0x00, size), 0..0);
+        // Upper 1/64th.
+        assert_eq!(layout.decode(0x04, size), 0xfc_0000..size);
+        // Lower 1/16th.
+        assert_eq!(layout.decode(0x2c, size), 0..0x10_0000);
+        // Upper 4KiB and 32KiB sectors.
+        assert_eq!(layout.decode(0x44, size), size - 0x1000..size);
+        assert_eq!(layout.decode(0x58, size), size - 0x8000..size);
+        assert_eq!(layout.decode(0x1c, size), 0..size);
+        // All but the lower 1/64th, and nothing.
+        assert_eq!(layout.decode(0x4024, size), 0x4_0000..size);
+        assert_eq!(layout.decode(0x401c, size), 0..0);
+        // The remaining bits are ignored.
+        assert_eq!(layout.decode(0x3b87, size), size - 0x4_0000..size);
+    }
+
+    #[test]
+    fn test_decode_macronix() {
+        let layout = BlockProtect::lookup(&[0xc2, 0x20, 0x18]).unwrap();
+        let size = 16 * MIB;
+        assert_eq!(layout.decode(0x04, size), size - 0x1_0000..size);
+        assert_eq!(layout.decode(0x20, size), size / 2..size);
+        // Values beyond the size of the array protect all of it.
+        assert_eq!(layout.decode(0x24, size), 0..size);
+        assert_eq!(layout.decode(0x3c, size), 0..size);
+    }
+
+    #[test]
+    fn test_encode() -> Result<()> {
+        let layout = BlockProtect::lookup(&[0xef, 0x40, 0x18]).unwrap();
+        let size = 16 * MIB;
+        assert_eq!(layout.encode(0..0, size)?, 0x00);
+        assert_eq!(layout.encode(0..0x4_0000, size)?, 0x24);
+        assert_eq!(layout.encode(0..0x2000, size)?, 0x68);
+        assert_eq!(layout.encode(0x4_0000..size, size)?, 0x4024);
+        assert_eq!(layout.encode(0..size, size)?, 0x1c);
+        assert!(layout.encode(0..0x3000, size).is_err());
+        assert!(layout.encode(0x1000..0x2000, size).is_err());
+
+        // Every value round trips, apart from the redundant encodings.
+        for value in 0..0x8000 {
+            let value = value & layout.bits() & !0x80;
+            let range = layout.decode(value, size);
+            assert_eq!(
+                layout.decode(layout.encode(range.clone(), size)?, size),
+                range
+            );
+        }
+
+        let layout = BlockProtect::lookup(&[0xc2, 0x20, 0x18]).unwrap();
+        assert_eq!(layout.encode(size - 0x4_0000..size, size)?, 0x0c);
+        assert!(layout.encode(0..0x1_0000, size).is_err());
+        Ok(())
+    }
+}
@@ -90,18 +90,26 @@ pub struct SfdpPhdr {
     pub major: u8,
     pub dwords: u8,
     pub offset: u32,
+    /// The MSB of the parameter ID; 0xFF for tables defined by JEDEC.
+    pub id_msb: u8,
 }
 
 impl TryFrom<&[u8]> for SfdpPhdr {
     type Error = Error;
     fn try_from(buf: &[u8]) -> Result<Self, Self::Error> {
         let mut reader = std::io::Cursor::new(buf);
+        let id = reader.read_u8()?;
+        let minor = reader.read_u8()?;
+        let major = reader.read_u8()?;
+        let dwords = reader.read_u8()?;
+        let pointer = reader.read_u32::<LittleEndian>()?;
         Ok(SfdpPhdr {
-            id: reader.read_u8()?,
-            minor: reader.read_u8()?,
-            major: reader.read_u8()?,
-            dwords: reader.read_u8()?,
-            offset: reader.read_u32::<LittleEndian>()? & 0x00FFFFFFu32,
+            id,
+            minor,
+            major,
+            dwords,
+            offset: pointer & 0x00FFFFFFu32,
+            id_msb: (pointer >> 24) as u8,
         })
     }
 }
@@ -333,6 +341,37 @@ impl InternalJedecParams {
     field!(read_opcode_4s4d4d -> u8, 23, 24, 8);
 }
 
+// The 4-byte address instruction table is documented in JESD216B section 6.6.
+struct InternalFourByteParams {
+    pub data: Vec<u32>,
+}
+
+impl InternalFourByteParams {
+    field!(support_read_111 -> bool, 1, 0, 1);
+    field!(support_fast_read_111 -> bool, 1, 1, 1);
+    field!(support_fast_read_112 -> bool, 1, 2, 1);
+    field!(support_fast_read_122 -> bool, 1, 3, 1);
+    field!(support_fast_read_114 -> bool, 1, 4, 1);
+    field!(support_fast_read_144 -> bool, 1, 5, 1);
+    field!(support_page_program_111 -> bool, 1, 6, 1);
+    field!(support_page_program_114 -> bool, 1, 7, 1);
+    field!(support_page_program_144 -> bool, 1, 8, 1);
+    field!(support_erase_type1 -> bool, 1, 9, 1);
+    field!(support_erase_type2 -> bool, 1, 10, 1);
+    field!(support_erase_type3 -> bool, 1, 11, 1);
+    field!(support_erase_type4 -> bool, 1, 12, 1);
+    // JESD216D.01 pg. 69.
+    field!(support_fast_read_118 -> bool, 1, 20, 1);
+    field!(support_fast_read_188 -> bool, 1, 21, 1);
+    field!(support_page_program_118 -> bool, 1, 23, 1);
+    field!(support_page_program_188 -> bool, 1, 24, 1);
+
+    field!(erase_type1_opcode -> u8, 2, 0, 8);
+    field!(erase_type2_opcode -> u8, 2, 8, 8);
+    field!(erase_type3_opcode -> u8, 2, 16, 8);
+    field!(erase_type4_opcode -> u8, 2, 24, 8);
+}
+
 /// `BlockEraseSize` represents whether or not the device can perform
 /// a 4KiB erase.
 #[derive(Default, Debug, Eq, PartialEq, strum::FromRepr, Clone, Copy, Serialize)]
@@ -892,6 +931,181 @@ impl TryFrom<&[u8]> for JedecParams {
     }
 }
 
+/// The 4-byte address instruction table lists the instructions taking a 4-byte address
+/// regardless of the addressing mode of the device.  It is documented in JESD216B section 6.6
+/// and extended with eight-lane instructions in JESD216D.
+#[derive(Default, Debug, Serialize, Annotate)]
+pub struct FourByteParams {
+    pub support_read_111: bool,
+    pub support_fast_read_111: bool,
+    pub support_fast_read_112: bool,
+    pub support_fast_read_122: bool,
+    pub support_fast_read_114: bool,
+    pub support_fast_read_144: bool,
+    pub support_fast_read_118: bool,
+    pub support_fast_read_188: bool,
+    pub support_page_program_111: bool,
+    pub support_page_program_114: bool,
+    pub support_page_program_144: bool,
+    pub support_page_program_118: bool,
+    pub support_page_program_188: bool,
+    /// The 4-byte address opcodes of the erase types in the JEDEC parameter table, or `None`
+    /// if there is no 4-byte address variant of the erase type.
+    pub erase_opcodes: [Option<u8>; 4],
+}
+
+impl TryFrom<&[u8]> for FourByteParams {
+    type Error = Error;
+    fn try_from(buf: &[u8]) -> Result<Self, Self::Error> {
+        let mut reader = std::io::Cursor::new(buf);
+        let mut data = vec![0u32; 2];
+        reader.read_u32_into::<LittleEndian>(&mut data)?;
+        let p = InternalFourByteParams { data };
+        let opcode = |supported: bool, opcode: u8| supported.then_some(opcode);
+        Ok(FourByteParams {
+            support_read_111: p.support_read_111()?,
+            support_fast_read_111: p.support_fast_read_111()?,
+            support_fast_read_112: p.support_fast_read_112()?,
+            support_fast_read_122: p.support_fast_read_122()?,
+            support_fast_read_114: p.support_fast_read_114()?,
+            support_fast_read_144: p.support_fast_read_144()?,
+            support_fast_read_118: p.support_fast_read_118()?,
+            support_fast_read_188: p.support_fast_read_188()?,
+            support_page_program_111: p.support_page_program_111()?,
+            support_page_program_114: p.support_page_program_114()?,
+            support_page_program_144: p.support_page_program_144()?,
+            support_page_program_118: p.support_page_program_118()?,
+            support_page_program_188: p.support_page_program_188()?,
+            erase_opcodes: [
+                opcode(p.support_erase_type1()?, p.erase_type1_opcode()?),
+                opcode(p.support_erase_type2()?, p.erase_type2_opcode()?),
+                opcode(p.support_erase_type3()?, p.erase_type3_opcode()?),
+                opcode(p.support_erase_type4()?, p.erase_type4_opcode()?),
+            ],
+        })
+    }
+}
+
+/// `SectorMapAddressLength` represents the address sent with a sector map detection command.
+#[derive(Default, Debug, Eq, PartialEq, strum::FromRepr, Clone, Copy, Serialize)]
+#[repr(u32)]
+pub enum SectorMapAddressLength {
+    None = 0,
+    Mode3b = 1,
+    Mode4b = 2,
+    /// The address length of the current addressing mode of the device.
+    Variable = 3,
+    #[default]
+    Invalid,
+}
+
+impl From<u32> for SectorMapAddressLength {
+    fn from(val: u32) -> Self {
+        Self::from_repr(val).unwrap_or(Self::Invalid)
+    }
+}
+
+/// A `SectorMapCommand` reads one bit of the ID of the sector map configuration the device
+/// is currently using.
+#[derive(Clone, Debug, Serialize, Annotate)]
+pub struct SectorMapCommand {
+    #[annotate(format=hex)]
+    pub opcode: u8,
+    /// Dummy cycles before the data byte, or `None` if the device's current read latency
+    /// applies.
+    pub read_latency: Option<u8>,
+    pub address_length: SectorMapAddressLength,
+    /// The bit of the data byte which contributes to the configuration ID.
+    #[annotate(format=hex)]
+    pub read_data_mask: u8,
+    #[annotate(format=hex)]
+    pub address: u32,
+}
+
+/// A `SectorMapRegion` is a contiguous range of the device supporting the same erase types.
+#[derive(Clone, Debug, Serialize, Annotate)]
+pub struct SectorMapRegion {
+    pub size: u32,
+    /// Bitmap of the erase types in the JEDEC parameter table (bit 0 being the first erase
+    /// type) which may be used in this region.
+    #[annotate(format=bin)]
+    pub erase_types: u8,
+}
+
+/// A `SectorMapConfig` is the layout of the regions of the device in one configuration.
+#[derive(Clone, Debug, Serialize)]
+pub struct SectorMapConfig {
+    pub id: u8,
+    pub regions: Vec<SectorMapRegion>,
+}
+
+/// The sector map parameter table describes devices with non-uniform erase granularity, and
+/// how to detect which layout a configurable device is using.  It is documented in JESD216B
+/// section 6.5.
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct SectorMap {
+    pub detect: Vec<SectorMapCommand>,
+    pub configs: Vec<SectorMapConfig>,
+}
+
+impl SectorMap {
+    /// Returns the layout of configuration `id`.  Tables without detection commands describe a
+    /// single layout, which is returned regardless of `id`.
+    pub fn config(&self, id: u8) -> Option<&SectorMapConfig> {
+        if self.detect.is_empty() {
+            self.configs.first()
+        } else {
+            self.configs.iter().find(|c| c.id == id)
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for SectorMap {
+    type Error = Error;
+    fn try_from(buf: &[u8]) -> Result<Self, Self::Error> {
+        const DESCRIPTOR_END: u32 = 1 << 0;
+        const DESCRIPTOR_MAP: u32 = 1 << 1;
+
+        let mut reader = std::io::Cursor::new(buf);
+        let mut map = SectorMap::default();
+        // The detection command descriptors come first, followed by the map descriptors.  The
+        // end bit of the last map descriptor terminates the table.
+        loop {
+            let header = reader.read_u32::<LittleEndian>()?;
+            if header & DESCRIPTOR_MAP == 0 {
+                map.detect.push(SectorMapCommand {
+                    opcode: BitField::new(8, 8).extract(header) as u8,
+                    read_latency: match BitField::new(16, 4).extract(header) {
+                        0xF => None,
+                        cycles => Some(cycles as u8),
+                    },
+                    address_length: BitField::new(22, 2).extract(header).into(),
+                    read_data_mask: BitField::new(24, 8).extract(header) as u8,
+                    address: reader.read_u32::<LittleEndian>()?,
+                });
+                continue;
+            }
+            let count = BitField::new(16, 8).extract(header) + 1;
+            let regions = (0..count)
+                .map(|_| {
+                    let region = reader.read_u32::<LittleEndian>()?;
+                    Ok(SectorMapRegion {
+                        size: (BitField::new(8, 24).extract(region) + 1).saturating_mul(256),
+                        erase_types: BitField::new(0, 4).extract(region) as u8,
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            map.configs.push(SectorMapConfig {
+                id: BitField::new(8, 8).extract(header) as u8,
+                regions,
+            });
+            if header & DESCRIPTOR_END != 0 {
+                return Ok(map);
+            }
+        }
+    }
+}
+
 /// An `UnknownParams` structure represents SFDP parameter tables for which
 /// we don't have a specialized parser.
 #[derive(Debug, Serialize)]
@@ -915,10 +1129,17 @@ pub struct Sfdp {
     pub header: SfdpHeader,
     pub phdr: Vec<SfdpPhdr>,
     pub jedec: JedecParams,
+    pub four_byte: Option<FourByteParams>,
+    pub sector_map: Option<SectorMap>,
+    /// The raw contents of the parameter tables following the JEDEC parameter table.
     pub params: Vec<UnknownParams>,
 }
 
 impl Sfdp {
+    // Parameter IDs of JEDEC-defined tables (JESD216B section 6.3).
+    const SECTOR_MAP_ID: (u8, u8) = (0xFF, 0x81);
+    const FOUR_BYTE_ID: (u8, u8) = (0xFF, 0x84);
+
     /// Given an initial SFDP buffer calculate the number of bytes needed for
     /// the entire SFDP.
     pub fn length_required(buf: &[u8]) -> Result<usize, Error> {
@@ -967,19 +1188,27 @@ impl TryFrom<&[u8]> for Sfdp {
         let jedec =
             JedecParams::try_from(buf.get(start..end).ok_or(Error::SliceRange(start, end))?)?;
 
+        let mut four_byte = None;
+        let mut sector_map = None;
         let mut params = Vec::new();
         for ph in phdr.iter().take((header.nph as usize) + 1).skip(1) {
             let start = ph.offset as usize;
             let end = start + ph.dwords as usize * 4;
-            params.push(UnknownParams::try_from(
-                buf.get(start..end).ok_or(Error::SliceRange(start, end))?,
-            )?);
+            let table = buf.get(start..end).ok_or(Error::SliceRange(start, end))?;
+            match (ph.id_msb, ph.id) {
+                Sfdp::FOUR_BYTE_ID => four_byte = Some(FourByteParams::try_from(table)?),
+                Sfdp::SECTOR_MAP_ID => sector_map = Some(SectorMap::try_from(table)?),
+                _ => {}
+            }
+            params.push(UnknownParams::try_from(table)?);
         }
 
         Ok(Sfdp {
             header,
             phdr,
             jedec,
+            four_byte,
+            sector_map,
             params,
         })
     }
@@ -1078,6 +1307,59 @@ mod test {
         // The particular MX66L1G sampled doesn't have a RevD or RevF table.
         assert!(sfdp.jedec.rev_d.is_none());
         assert!(sfdp.jedec.rev_f.is_none());
+
+        assert_eq!(rev_b.quad_enable_requirements, 2);
+        let four_byte = sfdp
+            .four_byte
+            .as_ref()
+            .expect("4-byte address instructions");
+        assert_eq!(four_byte.support_page_program_111, true);
+        assert_eq!(four_byte.support_page_program_114, false);
+        assert_eq!(four_byte.support_page_program_144, true);
+        assert_eq!(
+            four_byte.erase_opcodes,
+            [Some(0x21), Some(0x5c), Some(0xdc), None]
+        );
+        assert!(sfdp.sector_map.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_sector_map() -> Result<()> {
+        #[rustfmt::skip]
+        let words: [u32; 9] = [
+            // Read bit 3 of the byte at address 0x800004 with opcode 0x65 and 8 dummy cycles.
+            0x0848_6500, 0x0080_0004,
+            // Configuration 0: 32KiB of 4KiB sectors and the rest in 64KiB sectors.
+            0x0001_0002, 0x0000_7f01, 0x007f_7f04,
+            // Configuration 1 (the last): the whole device in 64KiB sectors.
+            0x0000_0103, 0x007f_ff04,
+            // Trailing data isn't part of the table.
+            0xffff_ffff, 0xffff_ffff,
+        ];
+        let buf = words
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let map = SectorMap::try_from(&buf[..])?;
+        assert_eq!(map.detect.len(), 1);
+        assert_eq!(map.detect[0].opcode, 0x65);
+        assert_eq!(map.detect[0].read_latency, Some(8));
+        assert_eq!(map.detect[0].address_length, SectorMapAddressLength::Mode3b);
+        assert_eq!(map.detect[0].read_data_mask, 0x08);
+        assert_eq!(map.detect[0].address, 0x800004);
+
+        assert_eq!(map.configs.len(), 2);
+        let config = map.config(0).unwrap();
+        assert_eq!(config.regions.len(), 2);
+        assert_eq!(config.regions[0].size, 0x8000);
+        assert_eq!(config.regions[0].erase_types, 0b0001);
+        assert_eq!(config.regions[1].size, 0x7f8000);
+        assert_eq!(config.regions[1].erase_types, 0b0100);
+        let config = map.config(1).unwrap();
+        assert_eq!(config.regions.len(), 1);
+        assert_eq!(config.regions[0].size, 0x800000);
+        assert!(map.config(2).is_none());
         Ok(())
     }
 
@@ -0,0 +1,433 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::app::TransportWrapper;
+use crate::dif::lc_ctrl::{
+    DifLcCtrlState, DifLcCtrlToken, DifLcCtrlTokenKind, LcCtrlReg, LcCtrlStatus,
+};
+use crate::impl_serializable_error;
+use crate::io::jtag::{Jtag, JtagParams, JtagTap};
+use crate::test_utils::lc_transition::{trigger_lc_transition, wait_for_status};
+
+/// Number of transitions and transition attempts after which the life cycle controller rejects
+/// any further transition.
+pub const LC_TRANSITION_COUNT_MAX: u32 = 24;
+
+/// The life cycle states a device can be transitioned into.
+const LC_STATES: [DifLcCtrlState; 21] = [
+    DifLcCtrlState::Raw,
+    DifLcCtrlState::TestUnlocked0,
+    DifLcCtrlState::TestLocked0,
+    DifLcCtrlState::TestUnlocked1,
+    DifLcCtrlState::TestLocked1,
+    DifLcCtrlState::TestUnlocked2,
+    DifLcCtrlState::TestLocked2,
+    DifLcCtrlState::TestUnlocked3,
+    DifLcCtrlState::TestLocked3,
+    DifLcCtrlState::TestUnlocked4,
+    DifLcCtrlState::TestLocked4,
+    DifLcCtrlState::TestUnlocked5,
+    DifLcCtrlState::TestLocked5,
+    DifLcCtrlState::TestUnlocked6,
+    DifLcCtrlState::TestLocked6,
+    DifLcCtrlState::TestUnlocked7,
+    DifLcCtrlState::Dev,
+    DifLcCtrlState::Prod,
+    DifLcCtrlState::ProdEnd,
+    DifLcCtrlState::Rma,
+    DifLcCtrlState::Scrap,
+];
+
+/// Errors related to planning and executing a sequence of LC transitions.
+#[derive(Error, Debug, Deserialize, Serialize)]
+pub enum LcPlanError {
+    #[error("Life cycle state {0} cannot be transitioned out of.")]
+    NotTransitionable(DifLcCtrlState),
+    #[error("There is no sequence of transitions from {0} to {1}.")]
+    Unreachable(DifLcCtrlState, DifLcCtrlState),
+    #[error("Reaching {target} requires {required} transitions but only {remaining} are left.")]
+    BudgetExceeded {
+        target: DifLcCtrlState,
+        required: u32,
+        remaining: u32,
+    },
+    #[error("No {0} token is available.")]
+    MissingToken(DifLcCtrlTokenKind),
+    #[error("Expected the device in {expected} with count {expected_count}, found {found} with count {found_count}.")]
+    UnexpectedState {
+        expected: DifLcCtrlState,
+        expected_count: u32,
+        found: DifLcCtrlState,
+        found_count: u32,
+    },
+}
+impl_serializable_error!(LcPlanError);
+
+/// Source of the tokens authorizing conditional transitions.
+pub trait LcTokenStore {
+    /// Returns the token of the given kind.
+    fn token(&self, kind: DifLcCtrlTokenKind) -> Result<DifLcCtrlToken>;
+}
+
+/// Tokens read from an HJSON file mapping each kind of token to its hexstring, e.g.
+/// `{ raw_unlock: "0x...", test_exit: "0x..." }`.
+#[derive(Default)]
+pub struct LcTokenFile {
+    tokens: HashMap<DifLcCtrlTokenKind, DifLcCtrlToken>,
+}
+
+impl LcTokenFile {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::from_str(&text)
+    }
+}
+
+impl FromStr for LcTokenFile {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let tokens: HashMap<String, String> = deser_hjson::from_str(text)?;
+        let tokens = tokens
+            .into_iter()
+            .map(|(kind, token)| {
+                let kind = DifLcCtrlTokenKind::from_str(&kind)
+                    .with_context(|| format!("Unknown token kind {kind:?}"))?;
+                let token = DifLcCtrlToken::from_str(&token)
+                    .with_context(|| format!("Bad {kind} token"))?;
+                Ok((kind, token))
+            })
+            .collect::<Result<_>>()?;
+        Ok(LcTokenFile { tokens })
+    }
+}
+
+impl LcTokenStore for LcTokenFile {
+    fn token(&self, kind: DifLcCtrlTokenKind) -> Result<DifLcCtrlToken> {
+        Ok(*self
+            .tokens
+            .get(&kind)
+            .ok_or(LcPlanError::MissingToken(kind))?)
+    }
+}
+
+/// A single transition of an [`LcTransitionPlan`].
+#[derive(Clone, Debug, Serialize)]
+pub struct LcTransitionStep {
+    pub from: DifLcCtrlState,
+    pub to: DifLcCtrlState,
+    /// Token authorizing the transition, if it is conditional.
+    pub token: Option<DifLcCtrlTokenKind>,
+    /// Value of the transition counter after the transition.
+    pub transition_count: u32,
+}
+
+/// The sequence of transitions bringing a device from its current life cycle state into a
+/// target state.
+#[derive(Clone, Debug, Serialize)]
+pub struct LcTransitionPlan {
+    pub current: DifLcCtrlState,
+    pub target: DifLcCtrlState,
+    /// Value of the transition counter before the first transition.
+    pub transition_count: u32,
+    pub steps: Vec<LcTransitionStep>,
+}
+
+impl LcTransitionPlan {
+    /// Plans the shortest sequence of transitions from `current` to `target`, preferring the
+    /// one requiring the fewest tokens.  Each transition increments the transition counter,
+    /// which must not exceed [`LC_TRANSITION_COUNT_MAX`].
+    pub fn new(
+        current: DifLcCtrlState,
+        transition_count: u32,
+        target: DifLcCtrlState,
+    ) -> Result<Self> {
+        let index = |state| LC_STATES.iter().position(|&s| s == state);
+        let start = index(current).ok_or(LcPlanError::NotTransitionable(current))?;
+        let end = index(target).ok_or(LcPlanError::Unreachable(current, target))?;
+
+        // The transition graph is small enough to relax all edges until the (transitions,
+        // tokens) cost of reaching each state is minimal.
+        let mut best: Vec<Option<((u32, u32), usize)>> = vec![None; LC_STATES.len()];
+        best[start] = Some(((0, 0), start));
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for from in 0..LC_STATES.len() {
+                let Some(((steps, tokens), _)) = best[from] else {
+                    continue;
+                };
+                for to in 0..LC_STATES.len() {
+                    let check = LC_STATES[from].check_transition(LC_STATES[to]);
+                    if !check.valid {
+                        continue;
+                    }
+                    let cost = (steps + 1, tokens + check.token as u32);
+                    if best[to].is_none_or(|(best_cost, _)| cost < best_cost) {
+                        best[to] = Some((cost, from));
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        let mut path = vec![end];
+        while path[path.len() - 1] != start {
+            let (_, prev) =
+                best[path[path.len() - 1]].ok_or(LcPlanError::Unreachable(current, target))?;
+            path.push(prev);
+        }
+        path.reverse();
+
+        let required = path.len() as u32 - 1;
+        let remaining = LC_TRANSITION_COUNT_MAX.saturating_sub(transition_count);
+        if required > remaining {
+            return Err(LcPlanError::BudgetExceeded {
+                target,
+                required,
+                remaining,
+            }
+            .into());
+        }
+
+        let steps = path
+            .windows(2)
+            .zip(transition_count + 1..)
+            .map(|(hop, count)| {
+                let (from, to) = (LC_STATES[hop[0]], LC_STATES[hop[1]]);
+                LcTransitionStep {
+                    from,
+                    to,
+                    token: from.transition_token(to),
+                    transition_count: count,
+                }
+            })
+            .collect();
+        Ok(LcTransitionPlan {
+            current,
+            target,
+            transition_count,
+            steps,
+        })
+    }
+
+    /// Reads the current life cycle state and transition count of the device and plans the
+    /// transitions into `target`.
+    pub fn for_device(
+        transport: &TransportWrapper,
+        jtag_params: &JtagParams,
+        reset_delay: Duration,
+        target: DifLcCtrlState,
+    ) -> Result<Self> {
+        transport.pin_strapping("PINMUX_TAP_LC")?.apply()?;
+        transport.reset_target(reset_delay, true)?;
+        let mut jtag = jtag_params.create(transport)?.connect(JtagTap::LcTap)?;
+        let (current, transition_count) = read_state(&mut *jtag)?;
+        jtag.disconnect()?;
+        Self::new(current, transition_count, target)
+    }
+
+    /// Returns the tokens required by the plan, in the order they are needed.
+    pub fn tokens(&self) -> Vec<DifLcCtrlTokenKind> {
+        let mut tokens = Vec::new();
+        for kind in self.steps.iter().filter_map(|step| step.token) {
+            if !tokens.contains(&kind) {
+                tokens.push(kind);
+            }
+        }
+        tokens
+    }
+
+    /// Performs the transitions of the plan, checking the life cycle state and transition count
+    /// of the device before and after each of them.
+    ///
+    /// All required tokens are retrieved from `store` before the device is touched.
+    pub fn execute(
+        &self,
+        transport: &TransportWrapper,
+        jtag_params: &JtagParams,
+        reset_delay: Duration,
+        store: &dyn LcTokenStore,
+    ) -> Result<()> {
+        let tokens = self
+            .tokens()
+            .into_iter()
+            .map(|kind| Ok((kind, store.token(kind)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        // Keep ROM from running, so that it cannot reset the chip while we reconnect to the
+        // LC TAP after each transition.
+        let rom_bootstrap = transport.pin_strapping("ROM_BOOTSTRAP")?;
+        rom_bootstrap.apply()?;
+        transport.pin_strapping("PINMUX_TAP_LC")?.apply()?;
+        transport.reset_target(reset_delay, true)?;
+
+        let mut expected = (self.current, self.transition_count);
+        for step in &self.steps {
+            log::info!("Transitioning from {} to {}", step.from, step.to);
+            let mut jtag = jtag_params.create(transport)?.connect(JtagTap::LcTap)?;
+            check_state(&mut *jtag, expected)?;
+
+            let token = step
+                .token
+                .map(|kind| tokens[&kind])
+                .unwrap_or(DifLcCtrlToken::from([0u8; 16]));
+            trigger_lc_transition(
+                transport,
+                jtag,
+                step.to,
+                Some(token.into_register_values()),
+                /*use_external_clk=*/ true,
+                reset_delay,
+                /*reset_tap_straps=*/ Some(JtagTap::LcTap),
+            )?;
+            expected = (step.to, step.transition_count);
+        }
+
+        let mut jtag = jtag_params.create(transport)?.connect(JtagTap::LcTap)?;
+        check_state(&mut *jtag, expected)?;
+        jtag.disconnect()?;
+        rom_bootstrap.remove()?;
+        Ok(())
+    }
+}
+
+/// Reads the life cycle state and transition count through the LC TAP.
+fn read_state(jtag: &mut dyn Jtag) -> Result<(DifLcCtrlState, u32)> {
+    // The state is only exposed once the lc_ctrl is initialized.
+    wait_for_status(jtag, Duration::from_secs(1), LcCtrlStatus::INITIALIZED)?;
+    let state =
+        DifLcCtrlState::from_redundant_encoding(jtag.read_lc_ctrl_reg(&LcCtrlReg::LcState)?)?;
+    let count = jtag.read_lc_ctrl_reg(&LcCtrlReg::LcTransitionCnt)?;
+    Ok((state, count))
+}
+
+fn check_state(jtag: &mut dyn Jtag, expected: (DifLcCtrlState, u32)) -> Result<()> {
+    let found = read_state(jtag)?;
+    if found != expected {
+        return Err(LcPlanError::UnexpectedState {
+            expected: expected.0,
+            expected_count: expected.1,
+            found: found.0,
+            found_count: found.1,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hops(plan: &LcTransitionPlan) -> Vec<(DifLcCtrlState, Option<DifLcCtrlTokenKind>)> {
+        plan.steps
+            .iter()
+            .map(|step| (step.to, step.token))
+            .collect()
+    }
+
+    #[test]
+    fn test_plan() -> Result<()> {
+        let plan = LcTransitionPlan::new(DifLcCtrlState::Raw, 0, DifLcCtrlState::Dev)?;
+        assert_eq!(
+            hops(&plan),
+            [
+                (
+                    DifLcCtrlState::TestUnlocked0,
+                    Some(DifLcCtrlTokenKind::RawUnlock)
+                ),
+                (DifLcCtrlState::Dev, Some(DifLcCtrlTokenKind::TestExit)),
+            ]
+        );
+        assert_eq!(plan.steps[1].transition_count, 2);
+        assert_eq!(
+            plan.tokens(),
+            [DifLcCtrlTokenKind::RawUnlock, DifLcCtrlTokenKind::TestExit]
+        );
+
+        // Going through TEST_UNLOCKED1 avoids the RMA token.
+        let plan = LcTransitionPlan::new(DifLcCtrlState::TestLocked0, 3, DifLcCtrlState::Rma)?;
+        assert_eq!(
+            hops(&plan),
+            [
+                (
+                    DifLcCtrlState::TestUnlocked1,
+                    Some(DifLcCtrlTokenKind::TestUnlock)
+                ),
+                (DifLcCtrlState::Rma, None),
+            ]
+        );
+
+        let plan = LcTransitionPlan::new(DifLcCtrlState::Dev, 5, DifLcCtrlState::Scrap)?;
+        assert_eq!(hops(&plan), [(DifLcCtrlState::Scrap, None)]);
+        assert_eq!(plan.steps[0].transition_count, 6);
+
+        let plan = LcTransitionPlan::new(DifLcCtrlState::Prod, 5, DifLcCtrlState::Prod)?;
+        assert!(plan.steps.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        for (current, count, target, error) in [
+            (
+                DifLcCtrlState::Prod,
+                5,
+                DifLcCtrlState::Dev,
+                "There is no sequence of transitions from Prod to Dev.",
+            ),
+            (
+                DifLcCtrlState::Raw,
+                23,
+                DifLcCtrlState::Dev,
+                "Reaching Dev requires 2 transitions but only 1 are left.",
+            ),
+            (
+                DifLcCtrlState::Escalate,
+                5,
+                DifLcCtrlState::Scrap,
+                "Life cycle state Escalate cannot be transitioned out of.",
+            ),
+        ] {
+            let result = LcTransitionPlan::new(current, count, target);
+            assert_eq!(result.unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn test_token_file() -> Result<()> {
+        let tokens = LcTokenFile::from_str(
+            r#"{
+                raw_unlock: "0x04030201_14131211_24232221_34333231",
+                test_exit: "0xffffffffffffffffffffffffffffffff",
+            }"#,
+        )?;
+        assert_eq!(
+            tokens
+                .token(DifLcCtrlTokenKind::RawUnlock)?
+                .into_register_values(),
+            [0x04030201, 0x14131211, 0x24232221, 0x34333231]
+        );
+        assert_eq!(
+            tokens
+                .token(DifLcCtrlTokenKind::Rma)
+                .err()
+                .map(|e| e.to_string()),
+            Some("No rma token is available.".to_owned())
+        );
+        assert!(LcTokenFile::from_str(r#"{ rma: "0x1234" }"#).is_err());
+        Ok(())
+    }
+}
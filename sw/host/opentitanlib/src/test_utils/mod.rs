@@ -15,6 +15,7 @@ pub mod gpio_monitor;
 pub mod i2c_target;
 pub mod init;
 pub mod lc;
+pub mod lc_plan;
 pub mod lc_transition;
 pub mod load_bitstream;
 pub mod load_sram_program;
@@ -67,6 +67,38 @@ impl OtpParam {
         Ok(())
     }
 
+    /// Read an arbitrary region of OTP as bytes.
+    ///
+    /// The region need not be aligned, the surrounding words are read with the given
+    /// granularity and trimmed.
+    pub fn read_bytes(
+        jtag: &mut dyn Jtag,
+        mmap: OtpParamMmap,
+        granule: Granularity,
+    ) -> OtpDaiResult<Vec<u8>> {
+        let OtpParamMmap { byte_addr, size } = mmap;
+        let step = match granule {
+            Granularity::B32 => mem::size_of::<u32>() as u32,
+            Granularity::B64 => mem::size_of::<u64>() as u32,
+        };
+
+        let start = byte_addr - byte_addr % step;
+        let end = (byte_addr + size).next_multiple_of(step);
+        let mut bytes = Vec::with_capacity((end - start) as usize);
+        for addr in (start..end).step_by(step as usize) {
+            let [lower, upper] = OtpDai::read(jtag, addr, granule)?;
+            bytes.extend_from_slice(&lower.to_le_bytes());
+            if granule == Granularity::B64 {
+                bytes.extend_from_slice(&upper.to_le_bytes());
+            }
+        }
+
+        let skip = (byte_addr - start) as usize;
+        bytes.drain(..skip);
+        bytes.truncate(size as usize);
+        Ok(bytes)
+    }
+
     /// Write a value from a buffer to an OTP parameter.
     pub fn write_param(jtag: &mut dyn Jtag, param: DaiParam, data: &[u32]) -> OtpDaiResult<()> {
         let OtpParamMmap { byte_addr, size } = param.mmap();
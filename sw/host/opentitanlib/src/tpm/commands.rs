@@ -0,0 +1,897 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Typed TPM 2.0 commands, executed through a `Driver`.
+
+use anyhow::{bail, ensure, Result};
+use serde::Serialize;
+use serde_annotate::Annotate;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::tpm::driver::Driver;
+use crate::tpm::marshal::{
+    marshal_list, marshal_tpm2b, unmarshal_bytes, unmarshal_list, unmarshal_tpm2b, Marshal,
+    Unmarshal,
+};
+use crate::tpm::session::{HmacSession, Session};
+use crate::tpm::types::{
+    AlgorithmId, Capability, CapabilityData, CommandCode, Handle, NvPublic, PcrSelection, Property,
+    Public, QuoteInfo, ResponseCode, Scheme, Signature, StartupType, SymmetricDefinition, Tag,
+    TaggedDigest,
+};
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("TPM command {0} failed: {1}")]
+    Failed(CommandCode, ResponseCode),
+    #[error("Unexpected response tag {1} to TPM command {0}")]
+    BadTag(CommandCode, Tag),
+    #[error("Response size {1} to TPM command {0} does not match {2} received bytes")]
+    BadSize(CommandCode, u32, usize),
+    #[error("The TPM returned no data for {0}")]
+    NoProgress(CommandCode),
+}
+
+impl CommandError {
+    /// The response code of the TPM, if the command failed on the TPM.
+    pub fn response_code(&self) -> Option<ResponseCode> {
+        match self {
+            CommandError::Failed(_, rc) => Some(*rc),
+            _ => None,
+        }
+    }
+}
+
+/// The parameters and handles of a successful response.
+struct Response {
+    handles: Vec<Handle>,
+    params: Vec<u8>,
+}
+
+/// The values of PCRs read by `Tpm::pcr_read`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct PcrValues {
+    pub update_counter: u32,
+    pub values: Vec<PcrValue>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct PcrValue {
+    pub hash: AlgorithmId,
+    pub pcr: u32,
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub digest: Vec<u8>,
+}
+
+/// The object created by `Tpm::create_primary`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct CreatedPrimary {
+    pub handle: Handle,
+    pub public: Public,
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub name: Vec<u8>,
+}
+
+/// The public area of an object, as returned by `Tpm::read_public`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct ObjectPublic {
+    pub public: Public,
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub name: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub qualified_name: Vec<u8>,
+}
+
+/// The public area of an NV index, as returned by `Tpm::nv_read_public`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct NvIndexPublic {
+    pub public: NvPublic,
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub name: Vec<u8>,
+}
+
+/// A quote of PCRs, as returned by `Tpm::quote`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct Quote {
+    /// The signed attestation structure.
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub attest: Vec<u8>,
+    pub info: QuoteInfo,
+    pub signature: Signature,
+}
+
+/// TPM 2.0 commands executed through a `Driver`.
+pub struct Tpm<'a> {
+    driver: &'a dyn Driver,
+}
+
+const SESSION_TYPE_HMAC: u8 = 0x00;
+
+impl<'a> Tpm<'a> {
+    pub fn new(driver: &'a dyn Driver) -> Self {
+        Tpm { driver }
+    }
+
+    /// Executes a command.  The first `sessions.len()` handles are the ones requiring
+    /// authorization, and the response is expected to contain `response_handles` handles.
+    fn execute(
+        &self,
+        cc: CommandCode,
+        handles: &[Handle],
+        sessions: &mut [&mut Session],
+        params: &[u8],
+        response_handles: usize,
+    ) -> Result<Response> {
+        let tag = if sessions.is_empty() {
+            Tag::NoSessions
+        } else {
+            Tag::Sessions
+        };
+        let mut cmd = Vec::new();
+        tag.marshal(&mut cmd)?;
+        // The size is filled in once the command is complete.
+        0u32.marshal(&mut cmd)?;
+        cc.marshal(&mut cmd)?;
+        for handle in handles {
+            handle.marshal(&mut cmd)?;
+        }
+        let cp_hash = if sessions.iter().any(|s| s.is_hmac()) {
+            let mut hasher = Sha256::new();
+            hasher.update(cc.to_tpm_bytes()?);
+            for handle in handles {
+                hasher.update(self.name_of(*handle)?);
+            }
+            hasher.update(params);
+            hasher.finalize().to_vec()
+        } else {
+            Vec::new()
+        };
+        if !sessions.is_empty() {
+            let mut auth = Vec::new();
+            for session in sessions.iter_mut() {
+                session.marshal_command(&cp_hash, &mut auth)?;
+            }
+            (auth.len() as u32).marshal(&mut cmd)?;
+            cmd.extend(auth);
+        }
+        cmd.extend_from_slice(params);
+        let size = (cmd.len() as u32).to_be_bytes();
+        cmd[2..6].copy_from_slice(&size);
+
+        let resp = self.driver.execute_command(&cmd)?;
+        let mut buf = resp.as_slice();
+        let resp_tag = Tag::unmarshal(&mut buf)?;
+        let resp_size = u32::unmarshal(&mut buf)?;
+        let rc = ResponseCode::unmarshal(&mut buf)?;
+        ensure!(
+            resp_size as usize == resp.len(),
+            CommandError::BadSize(cc, resp_size, resp.len())
+        );
+        ensure!(rc == ResponseCode::SUCCESS, CommandError::Failed(cc, rc));
+        ensure!(resp_tag == tag, CommandError::BadTag(cc, resp_tag));
+        let handles = (0..response_handles)
+            .map(|_| Handle::unmarshal(&mut buf))
+            .collect::<Result<Vec<_>>>()?;
+        let params = if sessions.is_empty() {
+            buf.to_vec()
+        } else {
+            let size = u32::unmarshal(&mut buf)? as usize;
+            let params = unmarshal_bytes(&mut buf, size)?;
+            let rp_hash = if sessions.iter().any(|s| s.is_hmac()) {
+                let mut hasher = Sha256::new();
+                hasher.update(rc.to_tpm_bytes()?);
+                hasher.update(cc.to_tpm_bytes()?);
+                hasher.update(&params);
+                hasher.finalize().to_vec()
+            } else {
+                Vec::new()
+            };
+            for session in sessions.iter_mut() {
+                session.unmarshal_response(&rp_hash, &mut buf)?;
+            }
+            params
+        };
+        Ok(Response { handles, params })
+    }
+
+    /// The name of the entity designated by `handle`, as used in HMAC computations.
+    pub fn name_of(&self, handle: Handle) -> Result<Vec<u8>> {
+        if handle.is_nv_index() {
+            Ok(self.nv_read_public(handle)?.name)
+        } else if handle.0 >= Handle::TransientFirst.0 {
+            Ok(self.read_public(handle)?.name)
+        } else {
+            handle.to_tpm_bytes()
+        }
+    }
+
+    /// TPM2_Startup.
+    pub fn startup(&self, startup_type: StartupType) -> Result<()> {
+        self.execute(
+            CommandCode::Startup,
+            &[],
+            &mut [],
+            &startup_type.to_tpm_bytes()?,
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// TPM2_Shutdown.
+    pub fn shutdown(&self, shutdown_type: StartupType) -> Result<()> {
+        self.execute(
+            CommandCode::Shutdown,
+            &[],
+            &mut [],
+            &shutdown_type.to_tpm_bytes()?,
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// TPM2_SelfTest, testing all algorithms if `full` or only the untested ones otherwise.
+    pub fn self_test(&self, full: bool) -> Result<()> {
+        self.execute(CommandCode::SelfTest, &[], &mut [], &[u8::from(full)], 0)?;
+        Ok(())
+    }
+
+    /// TPM2_GetCapability, returning whether more data is available and the data.
+    pub fn get_capability(
+        &self,
+        capability: Capability,
+        property: u32,
+        count: u32,
+    ) -> Result<(bool, CapabilityData)> {
+        let mut params = Vec::new();
+        capability.marshal(&mut params)?;
+        property.marshal(&mut params)?;
+        count.marshal(&mut params)?;
+        let resp = self.execute(CommandCode::GetCapability, &[], &mut [], &params, 0)?;
+        <(bool, CapabilityData)>::from_tpm_bytes(&resp.params)
+    }
+
+    /// Reads a single TPM property.
+    pub fn get_property(&self, property: Property) -> Result<Option<u32>> {
+        let (_, data) = self.get_capability(Capability::TpmProperties, property.0, 1)?;
+        Ok(match data {
+            CapabilityData::TpmProperties(props) => props
+                .into_iter()
+                .find(|(p, _)| *p == property)
+                .map(|(_, value)| value),
+            _ => None,
+        })
+    }
+
+    /// TPM2_GetRandom, repeated until `len` bytes are returned.
+    pub fn get_random(&self, len: usize) -> Result<Vec<u8>> {
+        let mut random = Vec::with_capacity(len);
+        while random.len() < len {
+            let want = (len - random.len()).min(u16::MAX as usize) as u16;
+            let resp = self.execute(
+                CommandCode::GetRandom,
+                &[],
+                &mut [],
+                &want.to_tpm_bytes()?,
+                0,
+            )?;
+            let bytes = unmarshal_tpm2b(&mut resp.params.as_slice())?;
+            ensure!(
+                !bytes.is_empty(),
+                CommandError::NoProgress(CommandCode::GetRandom)
+            );
+            random.extend(bytes);
+        }
+        random.truncate(len);
+        Ok(random)
+    }
+
+    /// TPM2_PCR_Read, repeated until all the selected PCRs are read.
+    pub fn pcr_read(&self, selection: &[PcrSelection]) -> Result<PcrValues> {
+        let mut remaining = selection.to_vec();
+        let mut values = PcrValues {
+            update_counter: 0,
+            values: Vec::new(),
+        };
+        while remaining.iter().any(|sel| !sel.pcrs.is_empty()) {
+            let mut params = Vec::new();
+            marshal_list(&remaining, &mut params)?;
+            let resp = self.execute(CommandCode::PcrRead, &[], &mut [], &params, 0)?;
+            let mut buf = resp.params.as_slice();
+            values.update_counter = u32::unmarshal(&mut buf)?;
+            let read = unmarshal_list::<PcrSelection>(&mut buf)?;
+            let count = u32::unmarshal(&mut buf)?;
+            let mut digests = (0..count)
+                .map(|_| unmarshal_tpm2b(&mut buf))
+                .collect::<Result<Vec<_>>>()?
+                .into_iter();
+            let mut progress = false;
+            for sel in read {
+                for pcr in sel.pcrs {
+                    let Some(digest) = digests.next() else {
+                        bail!(CommandError::NoProgress(CommandCode::PcrRead));
+                    };
+                    values.values.push(PcrValue {
+                        hash: sel.hash,
+                        pcr,
+                        digest,
+                    });
+                    for rem in remaining.iter_mut().filter(|r| r.hash == sel.hash) {
+                        rem.pcrs.retain(|p| *p != pcr);
+                    }
+                    progress = true;
+                }
+            }
+            // PCRs which are not implemented are silently dropped from the selection.
+            if !progress {
+                break;
+            }
+        }
+        Ok(values)
+    }
+
+    /// TPM2_PCR_Extend.
+    pub fn pcr_extend(
+        &self,
+        pcr: u32,
+        digests: &[TaggedDigest],
+        session: &mut Session,
+    ) -> Result<()> {
+        let mut params = Vec::new();
+        marshal_list(digests, &mut params)?;
+        self.execute(
+            CommandCode::PcrExtend,
+            &[Handle(Handle::Pcr0.0 + pcr)],
+            &mut [session],
+            &params,
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// TPM2_CreatePrimary, creating a key in `hierarchy` from `template`, with `auth_value` as
+    /// the authorization value of the new key.
+    pub fn create_primary(
+        &self,
+        hierarchy: Handle,
+        template: &Public,
+        auth_value: &[u8],
+        session: &mut Session,
+    ) -> Result<CreatedPrimary> {
+        let mut params = Vec::new();
+        // TPM2B_SENSITIVE_CREATE: the authorization value and no sensitive data.
+        let mut sensitive = Vec::new();
+        marshal_tpm2b(auth_value, &mut sensitive)?;
+        marshal_tpm2b(&[], &mut sensitive)?;
+        marshal_tpm2b(&sensitive, &mut params)?;
+        marshal_tpm2b(&template.to_tpm_bytes()?, &mut params)?;
+        // No outside info nor creation PCRs.
+        marshal_tpm2b(&[], &mut params)?;
+        marshal_list::<PcrSelection>(&[], &mut params)?;
+        let resp = self.execute(
+            CommandCode::CreatePrimary,
+            &[hierarchy],
+            &mut [session],
+            &params,
+            1,
+        )?;
+        let mut buf = resp.params.as_slice();
+        let public = Public::from_tpm_bytes(&unmarshal_tpm2b(&mut buf)?)?;
+        // Skip the creation data, hash and ticket.
+        let _creation_data = unmarshal_tpm2b(&mut buf)?;
+        let _creation_hash = unmarshal_tpm2b(&mut buf)?;
+        let _ticket_tag = Tag::unmarshal(&mut buf)?;
+        let _ticket_hierarchy = Handle::unmarshal(&mut buf)?;
+        let _ticket_digest = unmarshal_tpm2b(&mut buf)?;
+        let name = unmarshal_tpm2b(&mut buf)?;
+        Ok(CreatedPrimary {
+            handle: resp.handles[0],
+            public,
+            name,
+        })
+    }
+
+    /// TPM2_ReadPublic.
+    pub fn read_public(&self, handle: Handle) -> Result<ObjectPublic> {
+        let resp = self.execute(CommandCode::ReadPublic, &[handle], &mut [], &[], 0)?;
+        let mut buf = resp.params.as_slice();
+        Ok(ObjectPublic {
+            public: Public::from_tpm_bytes(&unmarshal_tpm2b(&mut buf)?)?,
+            name: unmarshal_tpm2b(&mut buf)?,
+            qualified_name: unmarshal_tpm2b(&mut buf)?,
+        })
+    }
+
+    /// TPM2_FlushContext, unloading a transient object or a session.
+    pub fn flush_context(&self, handle: Handle) -> Result<()> {
+        self.execute(
+            CommandCode::FlushContext,
+            &[],
+            &mut [],
+            &handle.to_tpm_bytes()?,
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// TPM2_StartAuthSession, starting an unbound and unsalted HMAC session which authorizes
+    /// entities whose authorization value is `auth_value`.
+    pub fn start_hmac_session(&self, auth_value: &[u8]) -> Result<Session> {
+        let nonce_caller = rand::random::<[u8; 32]>().to_vec();
+        let mut params = Vec::new();
+        marshal_tpm2b(&nonce_caller, &mut params)?;
+        // No encrypted salt.
+        marshal_tpm2b(&[], &mut params)?;
+        SESSION_TYPE_HMAC.marshal(&mut params)?;
+        SymmetricDefinition::default().marshal(&mut params)?;
+        AlgorithmId::Sha256.marshal(&mut params)?;
+        let resp = self.execute(
+            CommandCode::StartAuthSession,
+            &[Handle::Null, Handle::Null],
+            &mut [],
+            &params,
+            1,
+        )?;
+        Ok(Session::Hmac(HmacSession {
+            handle: resp.handles[0],
+            auth_value: auth_value.to_vec(),
+            nonce_caller,
+            nonce_tpm: unmarshal_tpm2b(&mut resp.params.as_slice())?,
+        }))
+    }
+
+    /// TPM2_NV_DefineSpace, with `auth_handle` the owner or platform hierarchy.
+    pub fn nv_define_space(
+        &self,
+        auth_handle: Handle,
+        auth_value: &[u8],
+        public: &NvPublic,
+        session: &mut Session,
+    ) -> Result<()> {
+        let mut params = Vec::new();
+        marshal_tpm2b(auth_value, &mut params)?;
+        marshal_tpm2b(&public.to_tpm_bytes()?, &mut params)?;
+        self.execute(
+            CommandCode::NvDefineSpace,
+            &[auth_handle],
+            &mut [session],
+            &params,
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// TPM2_NV_UndefineSpace.
+    pub fn nv_undefine_space(
+        &self,
+        auth_handle: Handle,
+        index: Handle,
+        session: &mut Session,
+    ) -> Result<()> {
+        self.execute(
+            CommandCode::NvUndefineSpace,
+            &[auth_handle, index],
+            &mut [session],
+            &[],
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// TPM2_NV_ReadPublic.
+    pub fn nv_read_public(&self, index: Handle) -> Result<NvIndexPublic> {
+        let resp = self.execute(CommandCode::NvReadPublic, &[index], &mut [], &[], 0)?;
+        let mut buf = resp.params.as_slice();
+        Ok(NvIndexPublic {
+            public: NvPublic::from_tpm_bytes(&unmarshal_tpm2b(&mut buf)?)?,
+            name: unmarshal_tpm2b(&mut buf)?,
+        })
+    }
+
+    /// The largest buffer accepted by `NV_Read` and `NV_Write`.
+    fn nv_buffer_max(&self) -> Result<usize> {
+        // The minimum required by the PC Client platform specification.
+        const DEFAULT_NV_BUFFER_MAX: usize = 512;
+        Ok(self
+            .get_property(Property::NvBufferMax)?
+            .map_or(DEFAULT_NV_BUFFER_MAX, |max| max as usize))
+    }
+
+    /// TPM2_NV_Write, split in as many commands as needed.  `auth_handle` is either the index
+    /// itself or the hierarchy owning it.
+    pub fn nv_write(
+        &self,
+        auth_handle: Handle,
+        index: Handle,
+        data: &[u8],
+        offset: u16,
+        session: &mut Session,
+    ) -> Result<()> {
+        let max = self.nv_buffer_max()?;
+        let mut offset = offset;
+        for chunk in data.chunks(max) {
+            let mut params = Vec::new();
+            marshal_tpm2b(chunk, &mut params)?;
+            offset.marshal(&mut params)?;
+            self.execute(
+                CommandCode::NvWrite,
+                &[auth_handle, index],
+                &mut [session],
+                &params,
+                0,
+            )?;
+            offset += chunk.len() as u16;
+        }
+        Ok(())
+    }
+
+    /// TPM2_NV_Read, split in as many commands as needed.  `auth_handle` is either the index
+    /// itself or the hierarchy owning it.
+    pub fn nv_read(
+        &self,
+        auth_handle: Handle,
+        index: Handle,
+        size: u16,
+        offset: u16,
+        session: &mut Session,
+    ) -> Result<Vec<u8>> {
+        let max = self.nv_buffer_max()?;
+        let mut data = Vec::with_capacity(size as usize);
+        while data.len() < size as usize {
+            let want = (size as usize - data.len()).min(max) as u16;
+            let mut params = Vec::new();
+            want.marshal(&mut params)?;
+            (offset + data.len() as u16).marshal(&mut params)?;
+            let resp = self.execute(
+                CommandCode::NvRead,
+                &[auth_handle, index],
+                &mut [session],
+                &params,
+                0,
+            )?;
+            let chunk = unmarshal_tpm2b(&mut resp.params.as_slice())?;
+            ensure!(
+                !chunk.is_empty(),
+                CommandError::NoProgress(CommandCode::NvRead)
+            );
+            data.extend(chunk);
+        }
+        Ok(data)
+    }
+
+    /// TPM2_Quote, signing the selected PCRs and `qualifying_data` with the scheme of the
+    /// restricted signing key `key`.
+    pub fn quote(
+        &self,
+        key: Handle,
+        qualifying_data: &[u8],
+        selection: &[PcrSelection],
+        session: &mut Session,
+    ) -> Result<Quote> {
+        let mut params = Vec::new();
+        marshal_tpm2b(qualifying_data, &mut params)?;
+        Scheme::NULL.marshal(&mut params)?;
+        marshal_list(selection, &mut params)?;
+        let resp = self.execute(CommandCode::Quote, &[key], &mut [session], &params, 0)?;
+        let mut buf = resp.params.as_slice();
+        let attest = unmarshal_tpm2b(&mut buf)?;
+        let signature = Signature::unmarshal(&mut buf)?;
+        Ok(Quote {
+            info: QuoteInfo::from_tpm_bytes(&attest)?,
+            attest,
+            signature,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tpm::driver::Register;
+    use crate::tpm::types::{EccCurve, NvAttributes, PublicParams};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    const HEADER_SIZE: usize = 10;
+
+    /// A driver returning scripted responses, and recording the commands.
+    #[derive(Default)]
+    struct FakeDriver {
+        commands: RefCell<Vec<Vec<u8>>>,
+        responses: RefCell<VecDeque<Vec<u8>>>,
+    }
+
+    impl FakeDriver {
+        fn respond(&self, tag: Tag, rc: u32, body: &[u8]) {
+            let mut resp = Vec::new();
+            tag.marshal(&mut resp).unwrap();
+            ((HEADER_SIZE + body.len()) as u32)
+                .marshal(&mut resp)
+                .unwrap();
+            rc.marshal(&mut resp).unwrap();
+            resp.extend_from_slice(body);
+            self.responses.borrow_mut().push_back(resp);
+        }
+
+        /// Responds to a command with sessions, with one password session.
+        fn respond_pw(&self, handles: &[u8], params: &[u8]) {
+            let mut body = handles.to_vec();
+            (params.len() as u32).marshal(&mut body).unwrap();
+            body.extend_from_slice(params);
+            body.extend([0x00, 0x00, 0x01, 0x00, 0x00]);
+            self.respond(Tag::Sessions, 0, &body);
+        }
+    }
+
+    impl Driver for FakeDriver {
+        fn read_register(&self, _: Register, _: &mut [u8]) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn write_register(&self, _: Register, _: &[u8]) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn execute_command(&self, cmd: &[u8]) -> Result<Vec<u8>> {
+            self.commands.borrow_mut().push(cmd.to_vec());
+            Ok(self.responses.borrow_mut().pop_front().unwrap())
+        }
+    }
+
+    #[test]
+    fn test_startup() -> Result<()> {
+        let driver = FakeDriver::default();
+        let tpm = Tpm::new(&driver);
+        driver.respond(Tag::NoSessions, 0, &[]);
+        tpm.startup(StartupType::Clear)?;
+        assert_eq!(
+            driver.commands.borrow()[0],
+            [0x80, 0x01, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x01, 0x44, 0x00, 0x00]
+        );
+
+        driver.respond(Tag::NoSessions, 0x100, &[]);
+        let err = tpm.startup(StartupType::Clear).unwrap_err();
+        let err = err.downcast_ref::<CommandError>().unwrap();
+        assert_eq!(err.response_code(), Some(ResponseCode::INITIALIZE));
+        assert_eq!(
+            err.to_string(),
+            "TPM command Startup failed: TPM_RC_INITIALIZE"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_random() -> Result<()> {
+        let driver = FakeDriver::default();
+        let tpm = Tpm::new(&driver);
+        // The TPM returns fewer bytes than requested.
+        driver.respond(Tag::NoSessions, 0, &[0x00, 0x02, 0xaa, 0xbb]);
+        driver.respond(Tag::NoSessions, 0, &[0x00, 0x02, 0xcc, 0xdd]);
+        assert_eq!(tpm.get_random(3)?, [0xaa, 0xbb, 0xcc]);
+        assert_eq!(driver.commands.borrow()[1][10..], [0x00, 0x01]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_capability() -> Result<()> {
+        let driver = FakeDriver::default();
+        let tpm = Tpm::new(&driver);
+        #[rustfmt::skip]
+        driver.respond(Tag::NoSessions, 0, &[
+            0x00, // moreData
+            0x00, 0x00, 0x00, 0x06, // TPM_CAP_TPM_PROPERTIES
+            0x00, 0x00, 0x00, 0x01,
+            0x00, 0x00, 0x01, 0x2c, 0x00, 0x00, 0x04, 0x00,
+        ]);
+        assert_eq!(tpm.get_property(Property::NvBufferMax)?, Some(1024));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pcr_read() -> Result<()> {
+        let driver = FakeDriver::default();
+        let tpm = Tpm::new(&driver);
+        let digest = |b| {
+            let mut v = vec![0x00, 0x20];
+            v.extend([b; 32]);
+            v
+        };
+        // The TPM only returns one PCR per response.
+        for (select, b) in [(0x01, 0x11), (0x02, 0x22)] {
+            let mut body = vec![0, 0, 0, 7];
+            body.extend([0, 0, 0, 1, 0x00, 0x0b, 0x03, select, 0x00, 0x00]);
+            body.extend([0, 0, 0, 1]);
+            body.extend(digest(b));
+            driver.respond(Tag::NoSessions, 0, &body);
+        }
+        let values = tpm.pcr_read(&[PcrSelection::new(AlgorithmId::Sha256, &[0, 1])])?;
+        assert_eq!(values.update_counter, 7);
+        assert_eq!(values.values.len(), 2);
+        assert_eq!(values.values[1].pcr, 1);
+        assert_eq!(values.values[1].digest, [0x22; 32]);
+        // The second command only selects the PCR which was not returned.
+        assert_eq!(
+            driver.commands.borrow()[1][10..],
+            [0, 0, 0, 1, 0x00, 0x0b, 0x03, 0x02, 0x00, 0x00]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_nv_write_read() -> Result<()> {
+        let driver = FakeDriver::default();
+        let tpm = Tpm::new(&driver);
+        let index = Handle(0x0150_0000);
+        let max_buffer = [
+            0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00,
+            0x00, 0x00, 0x04,
+        ];
+        driver.respond(Tag::NoSessions, 0, &max_buffer);
+        driver.respond_pw(&[], &[]);
+        driver.respond_pw(&[], &[]);
+        let mut session = Session::password(b"");
+        tpm.nv_write(index, index, b"abcdef", 2, &mut session)?;
+        {
+            let commands = driver.commands.borrow();
+            // handles, auth size, password session, data, offset
+            assert_eq!(commands[1][10..18], [0x01, 0x50, 0, 0, 0x01, 0x50, 0, 0]);
+            assert_eq!(
+                commands[1][31..],
+                [0x00, 0x04, b'a', b'b', b'c', b'd', 0x00, 0x02]
+            );
+            assert_eq!(commands[2][31..], [0x00, 0x02, b'e', b'f', 0x00, 0x06]);
+        }
+
+        driver.respond(Tag::NoSessions, 0, &max_buffer);
+        driver.respond_pw(&[], &[0x00, 0x04, 1, 2, 3, 4]);
+        driver.respond_pw(&[], &[0x00, 0x01, 5]);
+        assert_eq!(
+            tpm.nv_read(Handle::Owner, index, 5, 0, &mut session)?,
+            [1, 2, 3, 4, 5]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmac_session() -> Result<()> {
+        let driver = FakeDriver::default();
+        let tpm = Tpm::new(&driver);
+        let mut body = vec![0x02, 0x00, 0x00, 0x00];
+        marshal_tpm2b(&[0x55; 32], &mut body)?;
+        driver.respond(Tag::NoSessions, 0, &body);
+        let mut session = tpm.start_hmac_session(b"owner")?;
+        assert_eq!(session.handle(), Handle(0x0200_0000));
+
+        // NV_UndefineSpace needs the name of the NV index, read with NV_ReadPublic.
+        let public = NvPublic {
+            index: Handle(0x0150_0000),
+            name_alg: AlgorithmId::Sha256,
+            attributes: NvAttributes::OWNERWRITE | NvAttributes::OWNERREAD,
+            auth_policy: Vec::new(),
+            data_size: 8,
+        };
+        let mut body = Vec::new();
+        marshal_tpm2b(&public.to_tpm_bytes()?, &mut body)?;
+        marshal_tpm2b(&public.name()?, &mut body)?;
+        driver.respond(Tag::NoSessions, 0, &body);
+
+        // Build the response authorized by the TPM.
+        let nonce_tpm = [0x66; 32];
+        let rp_hash = Sha256::digest([0, 0, 0, 0, 0, 0, 0x01, 0x22]);
+        let respond = |nonce_caller: &[u8]| {
+            let mut body = vec![0, 0, 0, 0];
+            marshal_tpm2b(&nonce_tpm, &mut body).unwrap();
+            body.push(0x01);
+            let auth = crate::tpm::session::hmac_sha256(
+                b"owner",
+                &[&rp_hash, &nonce_tpm, nonce_caller, &[0x01]],
+            );
+            marshal_tpm2b(&auth, &mut body).unwrap();
+            driver.respond(Tag::Sessions, 0, &body);
+        };
+        // The caller nonce is not known before the command is sent, so use a driver wrapper
+        // which answers once it saw the command.
+        struct Answering<'a, F: Fn(&[u8])>(&'a FakeDriver, F);
+        impl<F: Fn(&[u8])> Driver for Answering<'_, F> {
+            fn read_register(&self, _: Register, _: &mut [u8]) -> Result<()> {
+                unimplemented!()
+            }
+            fn write_register(&self, _: Register, _: &[u8]) -> Result<()> {
+                unimplemented!()
+            }
+            fn execute_command(&self, cmd: &[u8]) -> Result<Vec<u8>> {
+                if cmd[6..10] == [0x00, 0x00, 0x01, 0x22] {
+                    // The caller nonce follows the handles, the auth size, the session handle
+                    // and the nonce size.
+                    (self.1)(&cmd[28..60]);
+                }
+                self.0.execute_command(cmd)
+            }
+        }
+        let answering = Answering(&driver, respond);
+        let tpm = Tpm::new(&answering);
+        tpm.nv_undefine_space(Handle::Owner, public.index, &mut session)?;
+
+        let commands = driver.commands.borrow();
+        let cmd = &commands[2];
+        let mut cp_hash = Sha256::new();
+        cp_hash.update([0x00, 0x00, 0x01, 0x22]);
+        cp_hash.update([0x40, 0x00, 0x00, 0x01]);
+        cp_hash.update(public.name()?);
+        let expected = crate::tpm::session::hmac_sha256(
+            b"owner",
+            &[&cp_hash.finalize(), &cmd[28..60], &[0x55; 32], &[0x01]],
+        );
+        assert_eq!(cmd[63..], expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_primary_and_quote() -> Result<()> {
+        let driver = FakeDriver::default();
+        let tpm = Tpm::new(&driver);
+        let mut public = Public::ecc_signing_template();
+        if let PublicParams::Ecc { x, y, .. } = &mut public.params {
+            *x = vec![0x01; 32];
+            *y = vec![0x02; 32];
+        }
+        let mut params = Vec::new();
+        marshal_tpm2b(&public.to_tpm_bytes()?, &mut params)?;
+        marshal_tpm2b(&[0xcc; 4], &mut params)?;
+        marshal_tpm2b(&[0xdd; 32], &mut params)?;
+        params.extend([0x80, 0x21, 0x40, 0x00, 0x00, 0x01, 0x00, 0x00]);
+        marshal_tpm2b(&public.name()?, &mut params)?;
+        driver.respond_pw(&[0x80, 0x00, 0x00, 0x01], &params);
+        let mut session = Session::password(b"");
+        let key = tpm.create_primary(
+            Handle::Owner,
+            &Public::ecc_signing_template(),
+            b"",
+            &mut session,
+        )?;
+        assert_eq!(key.handle, Handle(0x8000_0001));
+        assert_eq!(key.public, public);
+        assert_eq!(key.name, public.name()?);
+        assert!(matches!(
+            key.public.params,
+            PublicParams::Ecc {
+                curve: EccCurve::NistP256,
+                ..
+            }
+        ));
+
+        let mut attest = Vec::new();
+        attest.extend([0xff, 0x54, 0x43, 0x47, 0x80, 0x18]);
+        marshal_tpm2b(&key.name, &mut attest)?;
+        marshal_tpm2b(b"nonce", &mut attest)?;
+        attest.extend([0; 17]);
+        attest.extend([0, 0, 0, 0, 0, 0, 0, 9]);
+        attest.extend([0, 0, 0, 1, 0x00, 0x0b, 0x03, 0x01, 0x00, 0x00]);
+        marshal_tpm2b(&[0xee; 32], &mut attest)?;
+        let mut params = Vec::new();
+        marshal_tpm2b(&attest, &mut params)?;
+        params.extend([0x00, 0x18, 0x00, 0x0b]);
+        marshal_tpm2b(&[0x0a; 32], &mut params)?;
+        marshal_tpm2b(&[0x0b; 32], &mut params)?;
+        driver.respond_pw(&[], &params);
+        let quote = tpm.quote(
+            key.handle,
+            b"nonce",
+            &[PcrSelection::new(AlgorithmId::Sha256, &[0])],
+            &mut session,
+        )?;
+        assert_eq!(quote.attest, attest);
+        assert_eq!(quote.info.extra_data, b"nonce");
+        assert_eq!(quote.info.firmware_version, 9);
+        assert_eq!(quote.info.pcr_select[0].pcrs, [0]);
+        assert!(matches!(quote.signature, Signature::Ecdsa { .. }));
+        Ok(())
+    }
+}
@@ -0,0 +1,635 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conformance checks of the FIFO interface of the TCG PC Client Platform TPM Profile (PTP).
+//!
+//! Unlike `Driver::execute_command`, which tolerates misbehaving TPMs where it can, these checks
+//! drive the registers directly to exercise the edge cases of the protocol, and report any
+//! deviation.  Only the registers available over both SPI and I2C are used.
+
+use anyhow::{ensure, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_annotate::Annotate;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::tpm::access::TpmAccess;
+use crate::tpm::driver::{Driver, FlowControl, Register, TpmError, MAX_RESPONSE_SIZE};
+use crate::tpm::status::TpmStatus;
+
+/// TPM2_GetRandom of 8 bytes, which any TPM answers quickly, even before TPM2_Startup (with an
+/// error code).
+const GET_RANDOM: [u8; 12] = [
+    0x80, 0x01, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x01, 0x7b, 0x00, 0x08,
+];
+
+/// Size of the tag, size and code of TPM responses.
+const RESPONSE_HEADER_SIZE: usize = 10;
+
+/// Number of iterations of the back to back accesses.
+const BACK_TO_BACK_COUNT: usize = 64;
+
+/// Number of bytes of wait states after which a status read during command execution fails,
+/// far more than a TPM needs to answer it.
+const WAIT_STATE_POLLS: usize = 32;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum ConformanceError {
+    #[error("ACCESS register {0:#04x} is not valid")]
+    AccessNotValid(u8),
+    #[error("Locality not active after request, ACCESS register {0:#04x}")]
+    LocalityNotActive(u8),
+    #[error("Locality still active after relinquish, ACCESS register {0:#04x}")]
+    LocalityStillActive(u8),
+    #[error("Status {status:#010x}, expected {expected}")]
+    UnexpectedStatus { status: u32, expected: &'static str },
+    #[error("Corrupted status {0:#010x}")]
+    CorruptedStatus(u32),
+    #[error("Malformed response {0:02x?}")]
+    MalformedResponse(Vec<u8>),
+    #[error("Register {0:?} changed between back to back reads")]
+    UnstableRegister(Register),
+}
+
+/// The conformance checks, which can be selected on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Annotate, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConformanceCheck {
+    /// Locality 0 is granted and relinquished through the ACCESS register, also when a request
+    /// is immediately followed by a relinquish.
+    Locality,
+    /// The TPM enters the ready state with a non-zero burst count.
+    CommandReady,
+    /// A command written one byte at a time keeps `Expect` set until its last byte, with a
+    /// non-zero (possibly changing) burst count.
+    BurstCount,
+    /// Writing `commandReady` aborts a partially written command or a partially read response.
+    AbortCommand,
+    /// Bytes written past the end of a command are ignored.
+    OversizedWrite,
+    /// The status read while a command executes, without waiting for the `gsc_ready` signal,
+    /// is never corrupted by missed wait states and takes few wait states.
+    WaitStates,
+    /// Back to back register accesses return consistent values without waiting for the
+    /// `gsc_ready` signal of Google security chips in between, relying on wait states alone.
+    BackToBack,
+}
+
+/// The outcome of a conformance check.
+#[derive(Clone, Debug, Serialize, Annotate)]
+pub struct ConformanceResult {
+    pub check: ConformanceCheck,
+    pub passed: bool,
+    /// Observations of a passing check, or the reason of a failure.
+    pub details: String,
+}
+
+/// Runs `checks` on `driver`, returning a result for each check.  The TPM is returned to the
+/// ready state after each check, so that a failing check does not affect the following ones.
+pub fn run_conformance(driver: &dyn Driver, checks: &[ConformanceCheck]) -> Vec<ConformanceResult> {
+    checks
+        .iter()
+        .map(|&check| {
+            log::info!("Running conformance check {:?}", check);
+            let result = check.run(driver);
+            let _ = driver.write_register(Register::STS, &TpmStatus::CMD_READY.to_le_bytes());
+            match result {
+                Ok(details) => ConformanceResult {
+                    check,
+                    passed: true,
+                    details,
+                },
+                Err(e) => ConformanceResult {
+                    check,
+                    passed: false,
+                    details: format!("{:#}", e),
+                },
+            }
+        })
+        .collect()
+}
+
+impl ConformanceCheck {
+    /// Runs the check, returning a description of what was observed.
+    pub fn run(&self, driver: &dyn Driver) -> Result<String> {
+        match self {
+            Self::Locality => check_locality(driver),
+            Self::CommandReady => check_command_ready(driver),
+            Self::BurstCount => check_burst_count(driver),
+            Self::AbortCommand => check_abort_command(driver),
+            Self::OversizedWrite => check_oversized_write(driver),
+            Self::WaitStates => check_wait_states(driver),
+            Self::BackToBack => check_back_to_back(driver),
+        }
+    }
+}
+
+fn read_access(driver: &dyn Driver) -> Result<TpmAccess> {
+    let mut access = [0u8; 1];
+    driver.read_register(Register::ACCESS, &mut access)?;
+    let access = TpmAccess::from_bits_retain(access[0]);
+    ensure!(
+        access.contains(TpmAccess::VALID),
+        ConformanceError::AccessNotValid(access.bits())
+    );
+    Ok(access)
+}
+
+fn write_access(driver: &dyn Driver, access: TpmAccess) -> Result<()> {
+    driver.write_register(Register::ACCESS, &[access.bits()])
+}
+
+fn request_locality(driver: &dyn Driver) -> Result<()> {
+    write_access(driver, TpmAccess::REQUEST_USE)?;
+    let access = read_access(driver)?;
+    ensure!(
+        access.contains(TpmAccess::ACTIVE_LOCALITY),
+        ConformanceError::LocalityNotActive(access.bits())
+    );
+    Ok(())
+}
+
+fn expect_status(status: &TpmStatus, condition: bool, expected: &'static str) -> Result<()> {
+    ensure!(
+        condition,
+        ConformanceError::UnexpectedStatus {
+            status: status.raw_value(),
+            expected,
+        }
+    );
+    Ok(())
+}
+
+/// Status values which a TPM respecting the wait states of the bus never returns.
+fn is_corrupted(status: &TpmStatus) -> bool {
+    status.raw_value() == !0 || status.raw_value() & 0xFF == 0xFF
+}
+
+/// Runs `check` with the flow control of `driver` changed to `flow_control`, so that the TPM
+/// cannot rely on the flow control which the driver normally provides.
+fn with_flow_control<T>(
+    driver: &dyn Driver,
+    flow_control: FlowControl,
+    check: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let saved = driver.flow_control();
+    driver.set_flow_control(flow_control)?;
+    let result = check();
+    driver.set_flow_control(saved)?;
+    result
+}
+
+/// Aborts any command and waits for the ready state.
+fn command_ready(driver: &dyn Driver) -> Result<TpmStatus> {
+    driver.write_register(Register::STS, &TpmStatus::CMD_READY.to_le_bytes())?;
+    driver.poll_for_ready()
+}
+
+/// Waits for a non-zero burst count, while the TPM accepts or provides data.
+fn wait_for_burst(driver: &dyn Driver) -> Result<TpmStatus> {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let sts = driver.read_status()?;
+        if sts.is_valid() && sts.burst_count() > 0 {
+            return Ok(sts);
+        }
+        ensure!(Instant::now() <= deadline, TpmError::Timeout);
+    }
+}
+
+/// Writes `data` to the FIFO in chunks of at most `max_chunk` bytes, honoring the current burst
+/// count before each chunk.  Returns the burst counts observed.
+fn write_fifo(driver: &dyn Driver, data: &[u8], max_chunk: usize) -> Result<Vec<usize>> {
+    let mut bursts = Vec::new();
+    let mut written = 0;
+    while written < data.len() {
+        let sts = wait_for_burst(driver)?;
+        if written > 0 {
+            expect_status(&sts, sts.expect(), "Expect set within the command")?;
+        }
+        bursts.push(sts.burst_count());
+        let end = data.len().min(written + sts.burst_count().min(max_chunk));
+        driver.write_register(Register::DATA_FIFO, &data[written..end])?;
+        written = end;
+    }
+    Ok(bursts)
+}
+
+/// Starts the execution of the command written to the FIFO.
+fn go(driver: &dyn Driver) -> Result<()> {
+    driver.write_register(Register::STS, &TpmStatus::TPM_GO.to_le_bytes())
+}
+
+/// Reads a complete response from the FIFO, honoring the burst count before each chunk.
+fn read_response(driver: &dyn Driver) -> Result<Vec<u8>> {
+    driver.poll_for_data_available()?;
+    let mut response = Vec::new();
+    let mut size = RESPONSE_HEADER_SIZE;
+    while response.len() < size {
+        let sts = wait_for_burst(driver)?;
+        expect_status(
+            &sts,
+            sts.data_available(),
+            "data available within the response",
+        )?;
+        let mut chunk = vec![0u8; sts.burst_count().min(size - response.len())];
+        driver.read_register(Register::DATA_FIFO, &mut chunk)?;
+        response.extend(chunk);
+        if response.len() >= RESPONSE_HEADER_SIZE {
+            size = u32::from_be_bytes(response[2..6].try_into().unwrap()) as usize;
+            ensure!(
+                (RESPONSE_HEADER_SIZE..MAX_RESPONSE_SIZE).contains(&size) && size >= response.len(),
+                ConformanceError::MalformedResponse(response)
+            );
+        }
+    }
+    let sts = driver.read_status()?;
+    expect_status(
+        &sts,
+        sts.is_valid() && !sts.data_available(),
+        "no data available after the response",
+    )?;
+    check_response(&response)?;
+    Ok(response)
+}
+
+/// Checks the header of a response, which may carry any response code.
+fn check_response(response: &[u8]) -> Result<()> {
+    ensure!(
+        response.len() >= RESPONSE_HEADER_SIZE
+            && matches!(response[..2], [0x80, 0x01] | [0x80, 0x02])
+            && u32::from_be_bytes(response[2..6].try_into().unwrap()) as usize == response.len(),
+        ConformanceError::MalformedResponse(response.to_vec())
+    );
+    Ok(())
+}
+
+fn check_locality(driver: &dyn Driver) -> Result<String> {
+    write_access(driver, TpmAccess::ACTIVE_LOCALITY)?;
+    let access = read_access(driver)?;
+    ensure!(
+        !access.contains(TpmAccess::ACTIVE_LOCALITY),
+        ConformanceError::LocalityStillActive(access.bits())
+    );
+    request_locality(driver)?;
+
+    // Requesting the active locality again changes nothing, as no other locality is waiting.
+    write_access(driver, TpmAccess::REQUEST_USE)?;
+    let access = read_access(driver)?;
+    ensure!(
+        access.contains(TpmAccess::ACTIVE_LOCALITY) && !access.contains(TpmAccess::PENDING_REQUEST),
+        ConformanceError::LocalityNotActive(access.bits())
+    );
+
+    // Relinquish right after a request, without waiting for the TPM to grant it.
+    write_access(driver, TpmAccess::ACTIVE_LOCALITY)?;
+    write_access(driver, TpmAccess::REQUEST_USE)?;
+    write_access(driver, TpmAccess::ACTIVE_LOCALITY)?;
+    let access = read_access(driver)?;
+    ensure!(
+        !access.contains(TpmAccess::ACTIVE_LOCALITY),
+        ConformanceError::LocalityStillActive(access.bits())
+    );
+
+    request_locality(driver)?;
+    Ok("locality 0 granted and relinquished".into())
+}
+
+fn check_command_ready(driver: &dyn Driver) -> Result<String> {
+    let sts = command_ready(driver)?;
+    expect_status(
+        &sts,
+        !sts.expect() && !sts.data_available(),
+        "no command or response in progress",
+    )?;
+    expect_status(&sts, sts.burst_count() > 0, "a non-zero burst count")?;
+    Ok(format!("burst count {}", sts.burst_count()))
+}
+
+fn check_burst_count(driver: &dyn Driver) -> Result<String> {
+    command_ready(driver)?;
+    let mut bursts = write_fifo(driver, &GET_RANDOM, 1)?;
+    let sts = driver.read_status()?;
+    expect_status(&sts, !sts.expect(), "Expect cleared after the command")?;
+    go(driver)?;
+    read_response(driver)?;
+    bursts.dedup();
+    Ok(format!("burst counts {:?}", bursts))
+}
+
+fn check_abort_command(driver: &dyn Driver) -> Result<String> {
+    // Abort while the command is written.
+    command_ready(driver)?;
+    write_fifo(driver, &GET_RANDOM[..6], usize::MAX)?;
+    let sts = driver.read_status()?;
+    expect_status(&sts, sts.expect(), "Expect set within the command")?;
+    let sts = command_ready(driver)?;
+    expect_status(&sts, !sts.expect(), "Expect cleared after abort")?;
+
+    // Abort while the response is read.
+    write_fifo(driver, &GET_RANDOM, usize::MAX)?;
+    go(driver)?;
+    driver.poll_for_data_available()?;
+    let mut header = [0u8; 4];
+    driver.read_register(Register::DATA_FIFO, &mut header)?;
+    let sts = command_ready(driver)?;
+    expect_status(&sts, !sts.data_available(), "no data available after abort")?;
+
+    // The TPM still executes complete commands.
+    write_fifo(driver, &GET_RANDOM, usize::MAX)?;
+    go(driver)?;
+    read_response(driver)?;
+    Ok("aborted command and response".into())
+}
+
+fn check_oversized_write(driver: &dyn Driver) -> Result<String> {
+    command_ready(driver)?;
+    write_fifo(driver, &GET_RANDOM, usize::MAX)?;
+    // The TPM may as well refuse the extra bytes on the bus.
+    let refused = driver
+        .write_register(Register::DATA_FIFO, &[0u8; 16])
+        .is_err();
+    let sts = driver.read_status()?;
+    expect_status(&sts, !sts.expect(), "Expect cleared after extra bytes")?;
+    go(driver)?;
+    read_response(driver)?;
+    Ok(format!(
+        "extra bytes {}",
+        if refused { "refused" } else { "ignored" }
+    ))
+}
+
+fn check_wait_states(driver: &dyn Driver) -> Result<String> {
+    command_ready(driver)?;
+    write_fifo(driver, &GET_RANDOM, usize::MAX)?;
+    go(driver)?;
+    let flow_control = FlowControl {
+        wait_state_polls: Some(WAIT_STATE_POLLS),
+        gsc_ready: false,
+    };
+    let reads = with_flow_control(driver, flow_control, || {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut reads = 0;
+        loop {
+            let sts = driver.read_status()?;
+            reads += 1;
+            ensure!(
+                !is_corrupted(&sts),
+                ConformanceError::CorruptedStatus(sts.raw_value())
+            );
+            if sts.is_valid() && sts.data_available() {
+                return Ok(reads);
+            }
+            ensure!(Instant::now() <= deadline, TpmError::Timeout);
+        }
+    })?;
+    read_response(driver)?;
+    Ok(format!("{} status reads during execution", reads))
+}
+
+fn check_back_to_back(driver: &dyn Driver) -> Result<String> {
+    command_ready(driver)?;
+    let mut did_vid = [0u8; 4];
+    driver.read_register(Register::DID_VID, &mut did_vid)?;
+    let flow_control = FlowControl {
+        gsc_ready: false,
+        ..driver.flow_control()
+    };
+    with_flow_control(driver, flow_control, || {
+        for _ in 0..BACK_TO_BACK_COUNT {
+            let sts = driver.read_status()?;
+            ensure!(
+                !is_corrupted(&sts),
+                ConformanceError::CorruptedStatus(sts.raw_value())
+            );
+            expect_status(&sts, sts.is_valid() && sts.is_ready(), "ready")?;
+            let access = read_access(driver)?;
+            ensure!(
+                access.contains(TpmAccess::ACTIVE_LOCALITY),
+                ConformanceError::LocalityNotActive(access.bits())
+            );
+            let mut value = [0u8; 4];
+            driver.read_register(Register::DID_VID, &mut value)?;
+            ensure!(
+                value == did_vid,
+                ConformanceError::UnstableRegister(Register::DID_VID)
+            );
+            driver.write_register(Register::STS, &TpmStatus::CMD_READY.to_le_bytes())?;
+        }
+        Ok(())
+    })?;
+    Ok(format!(
+        "DID_VID {:#010x} stable over {} reads",
+        u32::from_le_bytes(did_vid),
+        BACK_TO_BACK_COUNT
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Default)]
+    struct Fifo {
+        active: bool,
+        ready: bool,
+        command: Vec<u8>,
+        executed: bool,
+        response: Vec<u8>,
+    }
+
+    /// A minimal model of the FIFO interface of a TPM answering every command with
+    /// TPM_RC_INITIALIZE, with `faulty_abort` ignoring aborts of partial commands,
+    /// `wait_states` inserted before every access, and `needs_gsc_ready` returning garbage
+    /// unless the host waits for `gsc_ready`.
+    #[derive(Default)]
+    struct FakeTpm {
+        fifo: RefCell<Fifo>,
+        faulty_abort: bool,
+        wait_states: usize,
+        needs_gsc_ready: bool,
+        flow_control: Cell<FlowControl>,
+    }
+
+    impl FakeTpm {
+        fn flow(&self) -> Result<FlowControl> {
+            let flow_control = self.flow_control.get();
+            if let Some(max) = flow_control.wait_state_polls {
+                ensure!(self.wait_states <= max, TpmError::WaitStatesExceeded(max));
+            }
+            Ok(flow_control)
+        }
+    }
+
+    const BURST: usize = 8;
+
+    impl Driver for FakeTpm {
+        fn read_register(&self, register: Register, data: &mut [u8]) -> Result<()> {
+            if self.needs_gsc_ready && !self.flow()?.gsc_ready {
+                data.fill(0xff);
+                return Ok(());
+            }
+            let mut fifo = self.fifo.borrow_mut();
+            match register {
+                Register::ACCESS => {
+                    let mut access = TpmAccess::VALID;
+                    access.set(TpmAccess::ACTIVE_LOCALITY, fifo.active);
+                    data[0] = access.bits();
+                }
+                Register::STS => {
+                    let expected = match fifo.command.get(2..6) {
+                        Some(size) => u32::from_be_bytes(size.try_into().unwrap()) as usize,
+                        None => usize::MAX,
+                    };
+                    let expect = !fifo.command.is_empty() && fifo.command.len() < expected;
+                    let burst = if fifo.executed {
+                        fifo.response.len().min(BURST)
+                    } else {
+                        BURST
+                    };
+                    let sts = (burst as u32) << 8
+                        | 1 << 7
+                        | u32::from(fifo.ready) << 6
+                        | u32::from(!fifo.response.is_empty()) << 4
+                        | u32::from(expect) << 3;
+                    data.copy_from_slice(&sts.to_le_bytes());
+                }
+                Register::DATA_FIFO => {
+                    let len = data.len().min(fifo.response.len());
+                    data[..len].copy_from_slice(&fifo.response[..len]);
+                    fifo.response.drain(..len);
+                }
+                Register::DID_VID => data.copy_from_slice(&0x0001_1ae0u32.to_le_bytes()),
+                _ => bail!("unsupported register {:?}", register),
+            }
+            Ok(())
+        }
+
+        fn write_register(&self, register: Register, data: &[u8]) -> Result<()> {
+            self.flow()?;
+            let mut fifo = self.fifo.borrow_mut();
+            match register {
+                Register::ACCESS => {
+                    let access = TpmAccess::from_bits_retain(data[0]);
+                    if access.contains(TpmAccess::ACTIVE_LOCALITY) {
+                        fifo.active = false;
+                    } else if access.contains(TpmAccess::REQUEST_USE) {
+                        fifo.active = true;
+                    }
+                }
+                Register::STS => {
+                    let sts = u32::from_le_bytes(data.try_into().unwrap());
+                    if sts & TpmStatus::CMD_READY != 0 {
+                        if !self.faulty_abort {
+                            fifo.command.clear();
+                        }
+                        fifo.response.clear();
+                        fifo.executed = false;
+                        fifo.ready = true;
+                    }
+                    if sts & TpmStatus::TPM_GO != 0 && !fifo.executed {
+                        fifo.command.clear();
+                        fifo.executed = true;
+                        fifo.ready = false;
+                        fifo.response = vec![0x80, 0x01, 0, 0, 0, 10, 0, 0, 1, 0];
+                    }
+                }
+                Register::DATA_FIFO => {
+                    // Bytes beyond the size in the header are dropped.
+                    let mut command = fifo.command.clone();
+                    command.extend_from_slice(data);
+                    if let Some(size) = command.get(2..6) {
+                        let size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+                        command.truncate(size);
+                    }
+                    fifo.command = command;
+                    fifo.ready = false;
+                }
+                _ => bail!("unsupported register {:?}", register),
+            }
+            Ok(())
+        }
+
+        fn flow_control(&self) -> FlowControl {
+            self.flow_control.get()
+        }
+
+        fn set_flow_control(&self, flow_control: FlowControl) -> Result<()> {
+            self.flow_control.set(flow_control);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_conforming_tpm() {
+        let tpm = FakeTpm::default();
+        tpm.init().unwrap();
+        let results = run_conformance(&tpm, ConformanceCheck::value_variants());
+        for result in &results {
+            assert!(result.passed, "{:?}", result);
+        }
+        assert_eq!(results.len(), 7);
+        assert_eq!(results[1].details, "burst count 8");
+        assert_eq!(results[2].details, "burst counts [8]");
+    }
+
+    #[test]
+    fn test_faulty_abort() {
+        let tpm = FakeTpm {
+            faulty_abort: true,
+            ..Default::default()
+        };
+        tpm.init().unwrap();
+        let results = run_conformance(
+            &tpm,
+            &[
+                ConformanceCheck::AbortCommand,
+                ConformanceCheck::CommandReady,
+            ],
+        );
+        assert!(!results[0].passed);
+        assert!(results[0].details.contains("Expect cleared after abort"));
+        // The leftover partial command also fails the following check.
+        assert!(!results[1].passed);
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let gsc_ready = FlowControl {
+            wait_state_polls: None,
+            gsc_ready: true,
+        };
+        let tpm = FakeTpm {
+            wait_states: 2 * WAIT_STATE_POLLS,
+            needs_gsc_ready: true,
+            flow_control: Cell::new(gsc_ready),
+            ..Default::default()
+        };
+        tpm.init().unwrap();
+        let results = run_conformance(
+            &tpm,
+            &[
+                ConformanceCheck::WaitStates,
+                ConformanceCheck::BackToBack,
+                ConformanceCheck::CommandReady,
+            ],
+        );
+        assert!(!results[0].passed);
+        assert!(results[0].details.contains("wait states"), "{:?}", results);
+        assert!(!results[1].passed);
+        assert!(
+            results[1].details.contains("Corrupted status"),
+            "{:?}",
+            results
+        );
+        // The flow control is restored after each check.
+        assert!(results[2].passed);
+        assert_eq!(tpm.flow_control(), gsc_ready);
+    }
+}
@@ -5,6 +5,7 @@
 use anyhow::{bail, ensure, Result};
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+use std::cell::Cell;
 use std::rc::Rc;
 use std::thread;
 use std::time::{Duration, Instant};
@@ -65,6 +66,21 @@ pub enum TpmError {
     ReadStatusFail,
     #[error("Timeout polling for response")]
     ResponseTimeout,
+    #[error("TPM still inserting wait states after {0} polls")]
+    WaitStatesExceeded(usize),
+    #[error("Flow control {0:?} not supported by the driver")]
+    UnsupportedFlowControl(FlowControl),
+}
+
+/// Flow control of the bus, which conformance checks restrict to find whether the TPM depends
+/// on it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlowControl {
+    /// Number of bytes polled for the end of the wait states of a SPI access before it fails,
+    /// `None` to poll until the driver's timeout.
+    pub wait_state_polls: Option<usize>,
+    /// Whether to wait for the ready signal of Google security chips after each access.
+    pub gsc_ready: bool,
 }
 
 /// Low level interface for accessing TPM.  Separate implementations exist for SPI and I2C.
@@ -88,6 +104,20 @@ pub trait Driver {
     /// Write to the given TPM register.
     fn write_register(&self, register: Register, data: &[u8]) -> Result<()>;
 
+    /// Returns the flow control used by register accesses.
+    fn flow_control(&self) -> FlowControl {
+        FlowControl::default()
+    }
+
+    /// Changes the flow control used by register accesses, see `FlowControl`.
+    fn set_flow_control(&self, flow_control: FlowControl) -> Result<()> {
+        ensure!(
+            flow_control == self.flow_control(),
+            TpmError::UnsupportedFlowControl(flow_control)
+        );
+        Ok(())
+    }
+
     /// Execute a TPM command and return the result as a `Vec<u8>` or time out.
     fn execute_command(&self, cmd: &[u8]) -> Result<Vec<u8>> {
         self.write_register(Register::STS, &TpmStatus::CMD_READY.to_le_bytes())?;
@@ -130,12 +160,13 @@ pub trait Driver {
     /// Fetches the current status.
     fn read_status(&self) -> Result<TpmStatus> {
         let mut out = [0u8; 4];
-        let res = self.read_register(Register::STS, &mut out);
-        if res.is_ok() {
-            Ok(TpmStatus::from_bytes(out))
-        } else {
-            log::error!("Failed to read status");
-            Err(TpmError::ReadStatusFail.into())
+        match self.read_register(Register::STS, &mut out) {
+            Ok(()) => Ok(TpmStatus::from_bytes(out)),
+            Err(e) => {
+                log::error!("Failed to read status");
+                // Keep the cause, such as wait states exceeding the limit of the flow control.
+                Err(e.context(TpmError::ReadStatusFail))
+            }
         }
     }
 
@@ -188,11 +219,19 @@ pub trait Driver {
 pub struct SpiDriver {
     spi: Rc<dyn spi::Target>,
     use_gsc_ready: bool,
+    flow_control: Cell<FlowControl>,
 }
 
 impl SpiDriver {
     pub fn new(spi: Rc<dyn spi::Target>, use_gsc_ready: bool) -> Result<Self> {
-        Ok(Self { spi, use_gsc_ready })
+        Ok(Self {
+            spi,
+            use_gsc_ready,
+            flow_control: Cell::new(FlowControl {
+                wait_state_polls: None,
+                gsc_ready: use_gsc_ready,
+            }),
+        })
     }
 
     /// Numerical TPM register address as used in SPI protocol.
@@ -235,10 +274,17 @@ impl SpiDriver {
         // back 0x01 on the other data line, data will come next.
         if buffer[3] & 1 == 0 {
             // The TPM was not immediately ready, keep polling, until we receive a byte of 0x01.
+            let max_polls = self.flow_control.get().wait_state_polls;
             let start_time = Instant::now();
+            let mut polls = 0;
             while {
+                ensure!(
+                    max_polls.map_or(true, |max| polls < max),
+                    TpmError::WaitStatesExceeded(polls)
+                );
                 self.spi
                     .run_transaction(&mut [spi::Transfer::Read(&mut buffer[0..1])])?;
+                polls += 1;
                 buffer[0] & 1 == 0
             } {
                 if Instant::now().duration_since(start_time) > TIMEOUT {
@@ -270,17 +316,18 @@ const SPI_TPM_WRITE: u32 = 0x40000000;
 const SPI_TPM_DATA_LEN_POS: u8 = 24;
 const SPI_TPM_ADDRESS_OFFSET: u32 = 0x00D40000;
 
-const MAX_RESPONSE_SIZE: usize = 4096;
+pub(crate) const MAX_RESPONSE_SIZE: usize = 4096;
 const TIMEOUT: Duration = Duration::from_millis(500);
 
 impl Driver for SpiDriver {
     fn read_register(&self, register: Register, data: &mut [u8]) -> Result<()> {
-        if !self.spi.supports_tpm_poll()? {
+        let flow_control = self.flow_control.get();
+        if flow_control.wait_state_polls.is_some() || !self.spi.supports_tpm_poll()? {
             // Fallback on polling TPM status from this Rust code.
             return self.do_read_register(register, data);
         }
         let req = self.compose_header(register, data.len(), true /* is_read */);
-        if self.use_gsc_ready {
+        if flow_control.gsc_ready {
             self.spi.run_transaction(&mut [
                 spi::Transfer::Write(&req),
                 spi::Transfer::TpmPoll,
@@ -297,12 +344,13 @@ impl Driver for SpiDriver {
     }
 
     fn write_register(&self, register: Register, data: &[u8]) -> Result<()> {
-        if !self.spi.supports_tpm_poll()? {
+        let flow_control = self.flow_control.get();
+        if flow_control.wait_state_polls.is_some() || !self.spi.supports_tpm_poll()? {
             // Fallback on polling TPM status from this Rust code.
             return self.do_write_register(register, data);
         }
         let req = self.compose_header(register, data.len(), false /* is_read */);
-        if self.use_gsc_ready {
+        if flow_control.gsc_ready {
             self.spi.run_transaction(&mut [
                 spi::Transfer::Write(&req),
                 spi::Transfer::TpmPoll,
@@ -317,17 +365,41 @@ impl Driver for SpiDriver {
             ])
         }
     }
+
+    fn flow_control(&self) -> FlowControl {
+        self.flow_control.get()
+    }
+
+    /// The wait states are only counted when polled from this Rust code, which does not
+    /// support the `gsc_ready` signal.
+    fn set_flow_control(&self, flow_control: FlowControl) -> Result<()> {
+        ensure!(
+            !flow_control.gsc_ready
+                || (self.use_gsc_ready && flow_control.wait_state_polls.is_none()),
+            TpmError::UnsupportedFlowControl(flow_control)
+        );
+        self.flow_control.set(flow_control);
+        Ok(())
+    }
 }
 
 /// Implementation of the low level interface via Google I2C protocol.
 pub struct I2cDriver {
     i2c: Rc<dyn i2c::Bus>,
     use_gsc_ready: bool,
+    flow_control: Cell<FlowControl>,
 }
 
 impl I2cDriver {
     pub fn new(i2c: Rc<dyn i2c::Bus>, use_gsc_ready: bool) -> Result<Self> {
-        Ok(Self { i2c, use_gsc_ready })
+        Ok(Self {
+            i2c,
+            use_gsc_ready,
+            flow_control: Cell::new(FlowControl {
+                wait_state_polls: None,
+                gsc_ready: use_gsc_ready,
+            }),
+        })
     }
 
     /// Numerical TPM register address as used in Google I2C protocol.
@@ -342,7 +414,7 @@ impl I2cDriver {
     }
 
     fn try_read_register(&self, register: Register, data: &mut [u8]) -> Result<()> {
-        if !self.use_gsc_ready {
+        if !self.flow_control.get().gsc_ready {
             // Do two I2C transfers in one call, for lowest latency.
             self.i2c.run_transaction(
                 None, /* default addr */
@@ -400,7 +472,7 @@ impl Driver for I2cDriver {
     fn write_register(&self, register: Register, data: &[u8]) -> Result<()> {
         let mut buffer = vec![Self::addr(register).unwrap()];
         buffer.extend_from_slice(data);
-        if !self.use_gsc_ready {
+        if !self.flow_control.get().gsc_ready {
             self.i2c.run_transaction(
                 None, /* default addr */
                 &mut [i2c::Transfer::Write(&buffer)],
@@ -412,4 +484,18 @@ impl Driver for I2cDriver {
             )
         }
     }
+
+    fn flow_control(&self) -> FlowControl {
+        self.flow_control.get()
+    }
+
+    /// I2C has no wait states, clock stretching is handled by the bus.
+    fn set_flow_control(&self, flow_control: FlowControl) -> Result<()> {
+        ensure!(
+            !flow_control.gsc_ready || self.use_gsc_ready,
+            TpmError::UnsupportedFlowControl(flow_control)
+        );
+        self.flow_control.set(flow_control);
+        Ok(())
+    }
 }
@@ -0,0 +1,188 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Marshalling of TPM 2.0 structures, as described in part 2 of the TPM 2.0 library
+//! specification.  All integers are big-endian, and sized buffers (`TPM2B_*`) are prefixed by
+//! a 16-bit length.
+
+use anyhow::{ensure, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MarshalError {
+    #[error("Buffer of {0} bytes is too large for a TPM2B")]
+    BufferTooLarge(usize),
+    #[error("Response has {0} trailing bytes")]
+    TrailingBytes(usize),
+    #[error("Expected a list of at most {max} elements, got {count}")]
+    ListTooLong { count: u32, max: u32 },
+}
+
+/// A TPM structure which can be serialized into a command.
+pub trait Marshal {
+    fn marshal(&self, buf: &mut Vec<u8>) -> Result<()>;
+
+    /// Returns the marshalled representation of `self`.
+    fn to_tpm_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.marshal(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// A TPM structure which can be deserialized from a response.
+pub trait Unmarshal: Sized {
+    /// Reads `Self` from the front of `buf`, advancing it.
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self>;
+
+    /// Reads `Self` from `buf`, which must not contain anything else.
+    fn from_tpm_bytes(mut buf: &[u8]) -> Result<Self> {
+        let value = Self::unmarshal(&mut buf)?;
+        ensure!(buf.is_empty(), MarshalError::TrailingBytes(buf.len()));
+        Ok(value)
+    }
+}
+
+macro_rules! impl_marshal_int {
+    ($($type:ty => $write:ident, $read:ident;)*) => {$(
+        impl Marshal for $type {
+            fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+                buf.$write::<BigEndian>(*self)?;
+                Ok(())
+            }
+        }
+
+        impl Unmarshal for $type {
+            fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+                Ok(buf.$read::<BigEndian>()?)
+            }
+        }
+    )*};
+}
+
+impl_marshal_int! {
+    u16 => write_u16, read_u16;
+    u32 => write_u32, read_u32;
+    u64 => write_u64, read_u64;
+}
+
+impl Marshal for u8 {
+    fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+        buf.push(*self);
+        Ok(())
+    }
+}
+
+impl Unmarshal for u8 {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        Ok(buf.read_u8()?)
+    }
+}
+
+impl Marshal for bool {
+    fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+        u8::from(*self).marshal(buf)
+    }
+}
+
+impl Unmarshal for bool {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        Ok(u8::unmarshal(buf)? != 0)
+    }
+}
+
+impl<A: Unmarshal, B: Unmarshal> Unmarshal for (A, B) {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        Ok((A::unmarshal(buf)?, B::unmarshal(buf)?))
+    }
+}
+
+/// Marshals `data` as a sized buffer (`TPM2B_*`).
+pub fn marshal_tpm2b(data: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+    let len = u16::try_from(data.len()).map_err(|_| MarshalError::BufferTooLarge(data.len()))?;
+    len.marshal(buf)?;
+    buf.extend_from_slice(data);
+    Ok(())
+}
+
+/// Unmarshals a sized buffer (`TPM2B_*`).
+pub fn unmarshal_tpm2b(buf: &mut &[u8]) -> Result<Vec<u8>> {
+    let len = u16::unmarshal(buf)? as usize;
+    unmarshal_bytes(buf, len)
+}
+
+/// Unmarshals exactly `len` raw bytes.
+pub fn unmarshal_bytes(buf: &mut &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut data = vec![0u8; len];
+    std::io::Read::read_exact(buf, &mut data)?;
+    Ok(data)
+}
+
+/// Marshals a list (`TPML_*`) of elements, prefixed by a 32-bit count.
+pub fn marshal_list<T: Marshal>(list: &[T], buf: &mut Vec<u8>) -> Result<()> {
+    (list.len() as u32).marshal(buf)?;
+    for item in list {
+        item.marshal(buf)?;
+    }
+    Ok(())
+}
+
+/// Unmarshals a list (`TPML_*`) of elements, prefixed by a 32-bit count.
+pub fn unmarshal_list<T: Unmarshal>(buf: &mut &[u8]) -> Result<Vec<T>> {
+    // Guard against allocating a huge vector for a corrupt count: each element takes at least
+    // one byte.
+    let count = u32::unmarshal(buf)?;
+    ensure!(
+        count as usize <= buf.len(),
+        MarshalError::ListTooLong {
+            count,
+            max: buf.len() as u32
+        }
+    );
+    (0..count).map(|_| T::unmarshal(buf)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ints() -> Result<()> {
+        let mut buf = Vec::new();
+        0x1234u16.marshal(&mut buf)?;
+        0x56789abcu32.marshal(&mut buf)?;
+        true.marshal(&mut buf)?;
+        assert_eq!(buf, [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0x01]);
+        let mut slice = buf.as_slice();
+        assert_eq!(u16::unmarshal(&mut slice)?, 0x1234);
+        assert_eq!(u32::unmarshal(&mut slice)?, 0x56789abc);
+        assert!(bool::unmarshal(&mut slice)?);
+        assert!(u8::unmarshal(&mut slice).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tpm2b() -> Result<()> {
+        let mut buf = Vec::new();
+        marshal_tpm2b(b"abc", &mut buf)?;
+        assert_eq!(buf, [0x00, 0x03, b'a', b'b', b'c']);
+        assert_eq!(unmarshal_tpm2b(&mut buf.as_slice())?, b"abc");
+        // The length exceeds the data.
+        assert!(unmarshal_tpm2b(&mut &buf[..4]).is_err());
+        assert!(marshal_tpm2b(&[0u8; 0x10000], &mut buf).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_list() -> Result<()> {
+        let mut buf = Vec::new();
+        marshal_list(&[1u32, 2, 3], &mut buf)?;
+        assert_eq!(&buf[..4], [0, 0, 0, 3]);
+        assert_eq!(unmarshal_list::<u32>(&mut buf.as_slice())?, [1, 2, 3]);
+        // A count larger than the remaining bytes is rejected before allocating.
+        assert!(unmarshal_list::<u32>(&mut [0xff, 0xff, 0xff, 0xff].as_slice()).is_err());
+        Ok(())
+    }
+}
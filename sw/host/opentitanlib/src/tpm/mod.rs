@@ -3,7 +3,19 @@
 // SPDX-License-Identifier: Apache-2.0
 
 mod access;
+mod commands;
+mod conformance;
 mod driver;
+pub mod marshal;
+mod session;
 mod status;
+mod tcp;
+pub mod types;
 
-pub use driver::{Driver, I2cDriver, Register, SpiDriver};
+pub use commands::{
+    CommandError, CreatedPrimary, NvIndexPublic, ObjectPublic, PcrValue, PcrValues, Quote, Tpm,
+};
+pub use conformance::{run_conformance, ConformanceCheck, ConformanceError, ConformanceResult};
+pub use driver::{Driver, FlowControl, I2cDriver, Register, SpiDriver};
+pub use session::{HmacSession, Session, SessionError};
+pub use tcp::{TcpDriver, TcpError, TcpTpmCommands, TpmEndpointInfo, TCP_PROTOCOL_VERSION};
@@ -0,0 +1,226 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Authorization sessions of TPM 2.0 commands.
+//!
+//! Only password sessions and unbound, unsalted HMAC sessions using SHA-256 are supported, and
+//! parameters are never encrypted.  For such an HMAC session, the key of the HMAC is the
+//! authorization value of the entity being authorized.
+
+use anyhow::{ensure, Result};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::tpm::marshal::{marshal_tpm2b, unmarshal_tpm2b, Marshal, Unmarshal};
+use crate::tpm::types::{Handle, SessionAttributes};
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("The response HMAC of session {0} does not match")]
+    BadResponseHmac(Handle),
+    #[error("Unexpected response nonce for password session")]
+    UnexpectedNonce,
+}
+
+/// An authorization session, used for the commands requiring authorization of a handle.
+#[derive(Clone, Debug)]
+pub enum Session {
+    /// Plain text authorization value.
+    Password(Vec<u8>),
+    /// HMAC session started with `Tpm::start_hmac_session`.
+    Hmac(HmacSession),
+}
+
+/// The state of an HMAC session.
+#[derive(Clone, Debug)]
+pub struct HmacSession {
+    pub(crate) handle: Handle,
+    pub(crate) auth_value: Vec<u8>,
+    pub(crate) nonce_caller: Vec<u8>,
+    pub(crate) nonce_tpm: Vec<u8>,
+}
+
+const SHA256_BLOCK_SIZE: usize = 64;
+
+/// Computes the HMAC-SHA256 of the concatenation of `parts`.
+pub(crate) fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut block = [0u8; SHA256_BLOCK_SIZE];
+    if key.len() > SHA256_BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    for part in parts {
+        inner.update(part);
+    }
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+impl Session {
+    /// A password session authorizing with `auth_value`.
+    pub fn password(auth_value: &[u8]) -> Self {
+        Session::Password(auth_value.to_vec())
+    }
+
+    /// The session handle used in the authorization area of commands.
+    pub fn handle(&self) -> Handle {
+        match self {
+            Session::Password(_) => Handle::Password,
+            Session::Hmac(hmac) => hmac.handle,
+        }
+    }
+
+    pub(crate) fn is_hmac(&self) -> bool {
+        matches!(self, Session::Hmac(_))
+    }
+
+    /// Marshals the authorization of a command (`TPMS_AUTH_COMMAND`).  `cp_hash` is required by
+    /// HMAC sessions.
+    pub(crate) fn marshal_command(&mut self, cp_hash: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+        let attributes = SessionAttributes::CONTINUE_SESSION;
+        self.handle().marshal(buf)?;
+        match self {
+            Session::Password(auth_value) => {
+                marshal_tpm2b(&[], buf)?;
+                attributes.marshal(buf)?;
+                marshal_tpm2b(auth_value, buf)?;
+            }
+            Session::Hmac(hmac) => {
+                // Roll the caller nonce for every command.
+                hmac.nonce_caller = rand::random::<[u8; 32]>().to_vec();
+                let auth = hmac_sha256(
+                    hmac.key(),
+                    &[
+                        cp_hash,
+                        &hmac.nonce_caller,
+                        &hmac.nonce_tpm,
+                        &[attributes.bits()],
+                    ],
+                );
+                marshal_tpm2b(&hmac.nonce_caller, buf)?;
+                attributes.marshal(buf)?;
+                marshal_tpm2b(&auth, buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Unmarshals and checks the authorization of a response (`TPMS_AUTH_RESPONSE`).
+    pub(crate) fn unmarshal_response(&mut self, rp_hash: &[u8], buf: &mut &[u8]) -> Result<()> {
+        let nonce = unmarshal_tpm2b(buf)?;
+        let attributes = SessionAttributes::unmarshal(buf)?;
+        let auth = unmarshal_tpm2b(buf)?;
+        match self {
+            Session::Password(_) => {
+                ensure!(nonce.is_empty(), SessionError::UnexpectedNonce);
+            }
+            Session::Hmac(hmac) => {
+                hmac.nonce_tpm = nonce;
+                let expected = hmac_sha256(
+                    hmac.key(),
+                    &[
+                        rp_hash,
+                        &hmac.nonce_tpm,
+                        &hmac.nonce_caller,
+                        &[attributes.bits()],
+                    ],
+                );
+                ensure!(auth == expected, SessionError::BadResponseHmac(hmac.handle));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HmacSession {
+    /// The session is neither bound nor salted, so the session key is empty and the HMAC key is
+    /// the authorization value with its trailing zeros removed.
+    fn key(&self) -> &[u8] {
+        let len = self
+            .auth_value
+            .iter()
+            .rposition(|b| *b != 0)
+            .map_or(0, |i| i + 1);
+        &self.auth_value[..len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231, test case 2.
+        let mac = hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+        assert_eq!(
+            hex::encode(mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // RFC 4231, test case 6, with a key longer than the block size.
+        let mac = hmac_sha256(
+            &[0xaa; 131],
+            &[b"Test Using Larger Than Block-Size Key - Hash Key First"],
+        );
+        assert_eq!(
+            hex::encode(mac),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_password_session() -> Result<()> {
+        let mut session = Session::password(b"pw");
+        let mut buf = Vec::new();
+        session.marshal_command(&[], &mut buf)?;
+        assert_eq!(
+            buf,
+            [0x40, 0x00, 0x00, 0x09, 0x00, 0x00, 0x01, 0x00, 0x02, b'p', b'w']
+        );
+        session.unmarshal_response(&[], &mut [0x00, 0x00, 0x01, 0x00, 0x00].as_slice())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmac_session() -> Result<()> {
+        let mut session = Session::Hmac(HmacSession {
+            handle: Handle(0x0200_0000),
+            auth_value: b"secret\0\0".to_vec(),
+            nonce_caller: Vec::new(),
+            nonce_tpm: vec![0x11; 32],
+        });
+        let mut buf = Vec::new();
+        session.marshal_command(&[0x22; 32], &mut buf)?;
+        let Session::Hmac(hmac) = &session else {
+            unreachable!()
+        };
+        let nonce_caller = hmac.nonce_caller.clone();
+        assert_eq!(&buf[6..38], nonce_caller);
+        let expected = hmac_sha256(b"secret", &[&[0x22; 32], &nonce_caller, &[0x11; 32], &[1]]);
+        assert_eq!(&buf[41..], expected);
+
+        // The response is authorized with the new TPM nonce.
+        let rp_hash = [0x33; 32];
+        let nonce_tpm = [0x44; 32];
+        let mut response = Vec::new();
+        marshal_tpm2b(&nonce_tpm, &mut response)?;
+        response.push(1);
+        let auth = hmac_sha256(b"secret", &[&rp_hash, &nonce_tpm, &nonce_caller, &[1]]);
+        marshal_tpm2b(&auth, &mut response)?;
+        session.unmarshal_response(&rp_hash, &mut response.as_slice())?;
+
+        // A corrupted HMAC is rejected.
+        let len = response.len();
+        response[len - 1] ^= 1;
+        assert!(session
+            .unmarshal_response(&rp_hash, &mut response.as_slice())
+            .is_err());
+        Ok(())
+    }
+}
@@ -22,7 +22,7 @@ impl TpmStatus {
         ((self.0 >> 8) & 0xFFFF) as usize
     }
 
-    pub fn _expect(&self) -> bool {
+    pub fn expect(&self) -> bool {
         (self.0 >> 3) & 1 == 1
     }
 
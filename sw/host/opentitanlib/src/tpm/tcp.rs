@@ -0,0 +1,333 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Client side of the TCP protocol of the Microsoft TPM 2.0 reference simulator, as also served
+//! by `tpm2_test_server`.
+//!
+//! The simulator listens on two ports: TPM commands are sent to the command port, and platform
+//! signals (e.g. power on/off) to the platform port, conventionally the command port plus one.
+//! All integers are big-endian, and every exchange is terminated by a 32-bit zero from the
+//! server.
+
+use anyhow::{bail, ensure, Result};
+use bitflags::bitflags;
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::tpm::driver::{Driver, Register, MAX_RESPONSE_SIZE};
+
+/// Version of the protocol spoken by both the client and the server.
+pub const TCP_PROTOCOL_VERSION: u32 = 1;
+
+/// Commands of the TCP protocol.  `SendCommand` and `RemoteHandshake` are sent to the command
+/// port, the signals to the platform port.
+#[derive(strum::FromRepr, Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TcpTpmCommands {
+    SignalPowerOn = 1,
+    SignalPowerOff = 2,
+    SignalPPOn = 3,
+    SignalPPOff = 4,
+    SignalHashStart = 5,
+    SignalHashData = 6,
+    SignalHashEnd = 7,
+    SendCommand = 8,
+    SignalCancelOn = 9,
+    SignalCancelOff = 10,
+    SignalNvOn = 11,
+    SignalNvOff = 12,
+    SignalKeyCacheOn = 13,
+    SignalKeyCacheOff = 14,
+    RemoteHandshake = 15,
+    //SetAlternativeResult = 16,    // Not used since 1.38h
+    SessionEnd = 20,
+    Stop = 21,
+    ActGetSignaled = 26,
+    TestFailureMode = 30,
+}
+
+bitflags! {
+    /// Capabilities of the server, returned by `RemoteHandshake`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct TpmEndpointInfo: u32 {
+        /// Platform hierarchy is enabled, and hardware platform functionality (such as
+        /// SignalHashStart/Data/End) is available.
+        const PLATFORM_AVAILABLE = 0x01;
+        /// The device is TPM Resource Manager (TRM), rather than a raw TPM.  This means context
+        /// management commands are unavailable, and the handle values returned to the client are
+        /// virtualized.
+        const USES_TBS = 0x02;
+        /// The TRM is in raw mode (i.e. no actual resourse virtualization is performed).
+        const IN_RAW_MODE = 0x04;
+        /// Physical presence signals (SignalPPOn/Off) are supported.
+        const SUPPORTS_PP = 0x08;
+        /// Valid only with PLATFORM_AVAILABLE set.  System and TPM power control signals
+        /// (SignalPowerOn/Off) are not supported.
+        const NO_POWER_CTL = 0x10;
+        /// Valid only with PLATFORM_AVAILABLE set.  TPM locality cannot be changed.
+        const NO_LOCALITY_CTL = 0x20;
+        /// Valid only with PLATFORM_AVAILABLE set.  NV control signals (SignalNvOn/Off) are not
+        /// supported.
+        const NO_NV_CTL = 0x40;
+    }
+}
+
+/// Errors relating to the TCP simulator protocol.
+#[derive(Error, Debug)]
+pub enum TcpError {
+    #[error("Unsupported simulator protocol version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Unexpected acknowledgement {0:#010x} of {1:?}")]
+    BadAcknowledgement(u32, TcpTpmCommands),
+    #[error("Server failed to execute the command")]
+    CommandFailed,
+    #[error("Unexpected response size {0}")]
+    UnexpectedResponseSize(usize),
+    #[error("Register access is not supported by the simulator protocol")]
+    RegisterAccess,
+}
+
+/// Some TPM operations, such as generating RSA keys can take several minutes.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Implementation of the low level interface via the TCP protocol of the TPM simulator.
+pub struct TcpDriver {
+    command: RefCell<TcpStream>,
+    platform: RefCell<TcpStream>,
+    endpoint_info: TpmEndpointInfo,
+    locality: u8,
+}
+
+impl TcpDriver {
+    /// Connects to the simulator at the given command and platform ports, and performs the
+    /// handshake.
+    pub fn connect(command: impl ToSocketAddrs, platform: impl ToSocketAddrs) -> Result<Self> {
+        let command = TcpStream::connect(command)?;
+        let platform = TcpStream::connect(platform)?;
+        for stream in [&command, &platform] {
+            stream.set_nodelay(true)?;
+            stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        }
+        let mut driver = Self {
+            command: RefCell::new(command),
+            platform: RefCell::new(platform),
+            endpoint_info: TpmEndpointInfo::empty(),
+            locality: 0,
+        };
+        driver.endpoint_info = driver.handshake()?;
+        Ok(driver)
+    }
+
+    /// Capabilities advertised by the server.
+    pub fn endpoint_info(&self) -> TpmEndpointInfo {
+        self.endpoint_info
+    }
+
+    /// Sets the locality of the subsequent TPM commands.
+    pub fn set_locality(&mut self, locality: u8) {
+        self.locality = locality;
+    }
+
+    /// Sends a signal to the platform port.
+    pub fn signal(&self, signal: TcpTpmCommands) -> Result<()> {
+        let mut stream = self.platform.borrow_mut();
+        stream.write_all(&(signal as u32).to_be_bytes())?;
+        read_ack(&mut stream, signal)
+    }
+
+    /// Powers the TPM on and enables its NV memory, as required before `TPM2_Startup`.
+    pub fn power_on(&self) -> Result<()> {
+        self.signal(TcpTpmCommands::SignalPowerOn)?;
+        self.signal(TcpTpmCommands::SignalNvOn)
+    }
+
+    /// Powers the TPM off.
+    pub fn power_off(&self) -> Result<()> {
+        self.signal(TcpTpmCommands::SignalPowerOff)
+    }
+
+    fn handshake(&self) -> Result<TpmEndpointInfo> {
+        let mut stream = self.command.borrow_mut();
+        let mut request = Vec::with_capacity(8);
+        request.extend_from_slice(&(TcpTpmCommands::RemoteHandshake as u32).to_be_bytes());
+        request.extend_from_slice(&TCP_PROTOCOL_VERSION.to_be_bytes());
+        stream.write_all(&request)?;
+        let version = read_u32(&mut stream)?;
+        ensure!(
+            version == TCP_PROTOCOL_VERSION,
+            TcpError::UnsupportedVersion(version)
+        );
+        let endpoint_info = TpmEndpointInfo::from_bits_retain(read_u32(&mut stream)?);
+        read_ack(&mut stream, TcpTpmCommands::RemoteHandshake)?;
+        Ok(endpoint_info)
+    }
+}
+
+impl Drop for TcpDriver {
+    fn drop(&mut self) {
+        // Let the server close the connections, errors are irrelevant at this point.
+        for stream in [&self.command, &self.platform] {
+            let mut stream = stream.borrow_mut();
+            let _ = stream.write_all(&(TcpTpmCommands::SessionEnd as u32).to_be_bytes());
+            let _ = stream.shutdown(Shutdown::Write);
+        }
+    }
+}
+
+fn read_u32(stream: &mut TcpStream) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_ack(stream: &mut TcpStream, cmd: TcpTpmCommands) -> Result<()> {
+    let ack = read_u32(stream)?;
+    ensure!(ack == 0, TcpError::BadAcknowledgement(ack, cmd));
+    Ok(())
+}
+
+impl Driver for TcpDriver {
+    /// The TPM is initialized by the server, see `power_on` to initialize a simulator.
+    fn init(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_register(&self, _register: Register, _data: &mut [u8]) -> Result<()> {
+        bail!(TcpError::RegisterAccess)
+    }
+
+    fn write_register(&self, _register: Register, _data: &[u8]) -> Result<()> {
+        bail!(TcpError::RegisterAccess)
+    }
+
+    fn execute_command(&self, cmd: &[u8]) -> Result<Vec<u8>> {
+        let mut stream = self.command.borrow_mut();
+        // Send the whole request at once, as some servers expect the command in a single read.
+        let mut request = Vec::with_capacity(cmd.len() + 9);
+        request.extend_from_slice(&(TcpTpmCommands::SendCommand as u32).to_be_bytes());
+        request.push(self.locality);
+        request.extend_from_slice(&(cmd.len() as u32).to_be_bytes());
+        request.extend_from_slice(cmd);
+        log::debug!("RUN({}) {:02X?}", cmd.len(), cmd);
+        stream.write_all(&request)?;
+
+        let size = read_u32(&mut stream)? as usize;
+        ensure!(
+            size < MAX_RESPONSE_SIZE,
+            TcpError::UnexpectedResponseSize(size)
+        );
+        let mut result = vec![0u8; size];
+        stream.read_exact(&mut result)?;
+        read_ack(&mut stream, TcpTpmCommands::SendCommand)?;
+        // `tpm2_test_server` reports failures to reach the TPM with an empty response.
+        ensure!(size != 0, TcpError::CommandFailed);
+        log::debug!("RES({}) {:02X?}", result.len(), result.as_slice());
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Runs a fake simulator answering `handshake`, then `requests` on the command port and
+    /// `signals` on the platform port.
+    fn fake_simulator(
+        handshake: [u32; 2],
+        requests: Vec<(Vec<u8>, Vec<u8>)>,
+        signals: Vec<TcpTpmCommands>,
+    ) -> Result<(TcpDriver, thread::JoinHandle<()>)> {
+        let command = TcpListener::bind("127.0.0.1:0")?;
+        let platform = TcpListener::bind("127.0.0.1:0")?;
+        let addrs = (command.local_addr()?, platform.local_addr()?);
+        let server = thread::spawn(move || {
+            let mut cmd = command.accept().unwrap().0;
+            let mut plat = platform.accept().unwrap().0;
+            let mut buf = [0u8; 8];
+            cmd.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [0, 0, 0, 15, 0, 0, 0, 1]);
+            for word in handshake.into_iter().chain([0]) {
+                cmd.write_all(&word.to_be_bytes()).unwrap();
+            }
+            for signal in signals {
+                assert_eq!(read_u32(&mut plat).unwrap(), signal as u32);
+                plat.write_all(&[0u8; 4]).unwrap();
+            }
+            for (request, response) in requests {
+                let mut header = [0u8; 9];
+                cmd.read_exact(&mut header).unwrap();
+                assert_eq!(header[..5], [0, 0, 0, 8, 0]);
+                let mut received = vec![0u8; request.len()];
+                cmd.read_exact(&mut received).unwrap();
+                assert_eq!(header[5..], (request.len() as u32).to_be_bytes());
+                assert_eq!(received, request);
+                cmd.write_all(&(response.len() as u32).to_be_bytes())
+                    .unwrap();
+                cmd.write_all(&response).unwrap();
+                cmd.write_all(&[0u8; 4]).unwrap();
+            }
+            assert_eq!(
+                read_u32(&mut cmd).unwrap(),
+                TcpTpmCommands::SessionEnd as u32
+            );
+        });
+        Ok((TcpDriver::connect(addrs.0, addrs.1)?, server))
+    }
+
+    #[test]
+    fn test_execute_command() -> Result<()> {
+        let (driver, server) = fake_simulator(
+            [1, 0x71],
+            vec![
+                (
+                    vec![0x80, 0x01, 0, 0, 0, 0x0a],
+                    vec![0x80, 0x01, 0, 0, 0, 0x0a, 0, 0, 0, 0],
+                ),
+                (vec![0x12], vec![]),
+            ],
+            vec![TcpTpmCommands::SignalPowerOn, TcpTpmCommands::SignalNvOn],
+        )?;
+        assert_eq!(
+            driver.endpoint_info(),
+            TpmEndpointInfo::PLATFORM_AVAILABLE
+                | TpmEndpointInfo::NO_POWER_CTL
+                | TpmEndpointInfo::NO_LOCALITY_CTL
+                | TpmEndpointInfo::NO_NV_CTL
+        );
+        driver.power_on()?;
+        assert_eq!(
+            driver.execute_command(&[0x80, 0x01, 0, 0, 0, 0x0a])?,
+            [0x80, 0x01, 0, 0, 0, 0x0a, 0, 0, 0, 0]
+        );
+        // An empty response reports a failure of the server.
+        assert!(driver.execute_command(&[0x12]).is_err());
+        assert!(driver.read_register(Register::STS, &mut [0u8; 4]).is_err());
+        drop(driver);
+        server.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_version() -> Result<()> {
+        let command = TcpListener::bind("127.0.0.1:0")?;
+        let platform = TcpListener::bind("127.0.0.1:0")?;
+        let addrs = (command.local_addr()?, platform.local_addr()?);
+        let server = thread::spawn(move || {
+            let mut cmd = command.accept().unwrap().0;
+            let _plat = platform.accept().unwrap().0;
+            let mut buf = [0u8; 8];
+            cmd.read_exact(&mut buf).unwrap();
+            cmd.write_all(&[0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+        });
+        assert!(TcpDriver::connect(addrs.0, addrs.1).is_err());
+        server.join().unwrap();
+        Ok(())
+    }
+}
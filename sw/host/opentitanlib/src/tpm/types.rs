@@ -0,0 +1,1100 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! TPM 2.0 constants and structures used by the commands of `crate::tpm::Tpm`.
+
+use anyhow::{bail, ensure, Result};
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+use serde_annotate::Annotate;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+use crate::tpm::marshal::{
+    marshal_tpm2b, unmarshal_bytes, unmarshal_list, unmarshal_tpm2b, Marshal, Unmarshal,
+};
+use crate::with_unknown;
+
+with_unknown! {
+    /// Command codes (`TPM_CC`).
+    pub enum CommandCode: u32 [default = Self::Unknown] {
+        Unknown = 0,
+        NvUndefineSpace = 0x122,
+        NvDefineSpace = 0x12a,
+        CreatePrimary = 0x131,
+        NvWrite = 0x137,
+        SelfTest = 0x143,
+        Startup = 0x144,
+        Shutdown = 0x145,
+        NvRead = 0x14e,
+        Quote = 0x158,
+        FlushContext = 0x165,
+        NvReadPublic = 0x169,
+        ReadPublic = 0x173,
+        StartAuthSession = 0x176,
+        GetCapability = 0x17a,
+        GetRandom = 0x17b,
+        PcrRead = 0x17e,
+        PcrExtend = 0x182,
+    }
+
+    /// Structure tags (`TPM_ST`).
+    pub enum Tag: u16 [default = Self::Null] {
+        Null = 0x8000,
+        NoSessions = 0x8001,
+        Sessions = 0x8002,
+        AttestQuote = 0x8018,
+    }
+
+    /// The type of `Startup` and `Shutdown` (`TPM_SU`).
+    pub enum StartupType: u16 [default = Self::Clear] {
+        Clear = 0,
+        State = 1,
+    }
+
+    /// Algorithm identifiers (`TPM_ALG_ID`).
+    pub enum AlgorithmId: u16 [default = Self::Null] {
+        Error = 0x00,
+        Rsa = 0x01,
+        Sha1 = 0x04,
+        Hmac = 0x05,
+        Aes = 0x06,
+        KeyedHash = 0x08,
+        Sha256 = 0x0b,
+        Sha384 = 0x0c,
+        Sha512 = 0x0d,
+        Null = 0x10,
+        RsaSsa = 0x14,
+        RsaPss = 0x16,
+        Ecdsa = 0x18,
+        Ecdh = 0x19,
+        Ecc = 0x23,
+        Cfb = 0x43,
+    }
+
+    /// Elliptic curves (`TPM_ECC_CURVE`).
+    pub enum EccCurve: u16 [default = Self::None] {
+        None = 0,
+        NistP256 = 3,
+        NistP384 = 4,
+    }
+
+    /// Capability groups queried by `GetCapability` (`TPM_CAP`).
+    pub enum Capability: u32 [default = Self::Algs] {
+        Algs = 0,
+        Handles = 1,
+        Commands = 2,
+        PpCommands = 3,
+        AuditCommands = 4,
+        Pcrs = 5,
+        TpmProperties = 6,
+        PcrProperties = 7,
+        EccCurves = 8,
+    }
+
+    /// Permanent handles (`TPM_RH`) and the first handles of each handle range (`TPM_HT`).
+    pub enum Handle: u32 [default = Self::Null] {
+        Pcr0 = 0x0000_0000,
+        NvIndexFirst = 0x0100_0000,
+        HmacSessionFirst = 0x0200_0000,
+        PolicySessionFirst = 0x0300_0000,
+        Owner = 0x4000_0001,
+        Null = 0x4000_0007,
+        Password = 0x4000_0009,
+        Lockout = 0x4000_000a,
+        Endorsement = 0x4000_000b,
+        Platform = 0x4000_000c,
+        TransientFirst = 0x8000_0000,
+        PersistentFirst = 0x8100_0000,
+    }
+
+    /// Fixed TPM properties (`TPM_PT`) returned with `Capability::TpmProperties`.
+    pub enum Property: u32 [default = Self::None] {
+        None = 0,
+        FamilyIndicator = 0x100,
+        Level = 0x101,
+        Revision = 0x102,
+        DayOfYear = 0x103,
+        Year = 0x104,
+        Manufacturer = 0x105,
+        VendorString1 = 0x106,
+        VendorString2 = 0x107,
+        VendorString3 = 0x108,
+        VendorString4 = 0x109,
+        VendorTpmType = 0x10a,
+        FirmwareVersion1 = 0x10b,
+        FirmwareVersion2 = 0x10c,
+        InputBuffer = 0x10d,
+        HrTransientMin = 0x10e,
+        HrPersistentMin = 0x10f,
+        HrLoadedMin = 0x110,
+        ActiveSessionsMax = 0x111,
+        PcrCount = 0x112,
+        PcrSelectMin = 0x113,
+        ContextGapMax = 0x114,
+        NvCountersMax = 0x116,
+        NvIndexMax = 0x117,
+        Memory = 0x118,
+        ClockUpdate = 0x119,
+        ContextHash = 0x11a,
+        ContextSym = 0x11b,
+        ContextSymSize = 0x11c,
+        OrderlyCount = 0x11d,
+        MaxCommandSize = 0x11e,
+        MaxResponseSize = 0x11f,
+        MaxDigest = 0x120,
+        MaxObjectContext = 0x121,
+        MaxSessionContext = 0x122,
+        PsFamilyIndicator = 0x123,
+        PsLevel = 0x124,
+        PsRevision = 0x125,
+        PsDayOfYear = 0x126,
+        PsYear = 0x127,
+        SplitMax = 0x128,
+        TotalCommands = 0x129,
+        LibraryCommands = 0x12a,
+        VendorCommands = 0x12b,
+        NvBufferMax = 0x12c,
+        Modes = 0x12d,
+        MaxCapBuffer = 0x12e,
+        Permanent = 0x200,
+        StartupClear = 0x201,
+        HrNvIndex = 0x202,
+        HrLoaded = 0x203,
+        HrLoadedAvail = 0x204,
+        HrActive = 0x205,
+        HrActiveAvail = 0x206,
+        HrTransientAvail = 0x207,
+        HrPersistent = 0x208,
+        HrPersistentAvail = 0x209,
+        NvCounters = 0x20a,
+        NvCountersAvail = 0x20b,
+        AlgorithmSet = 0x20c,
+        LoadedCurves = 0x20d,
+        LockoutCounter = 0x20e,
+        MaxAuthFail = 0x20f,
+        LockoutInterval = 0x210,
+        LockoutRecovery = 0x211,
+        NvWriteRecovery = 0x212,
+        AuditCounter0 = 0x213,
+        AuditCounter1 = 0x214,
+    }
+}
+
+macro_rules! impl_marshal_newtype {
+    ($($type:ty: $inner:ty,)*) => {$(
+        impl Marshal for $type {
+            fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+                self.0.marshal(buf)
+            }
+        }
+
+        impl Unmarshal for $type {
+            fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+                Ok(Self(<$inner>::unmarshal(buf)?))
+            }
+        }
+    )*};
+}
+
+impl_marshal_newtype! {
+    CommandCode: u32,
+    Tag: u16,
+    StartupType: u16,
+    AlgorithmId: u16,
+    EccCurve: u16,
+    Capability: u32,
+    Handle: u32,
+    Property: u32,
+}
+
+macro_rules! impl_marshal_bitflags {
+    ($($type:ty: $inner:ty,)*) => {$(
+        impl Marshal for $type {
+            fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+                self.bits().marshal(buf)
+            }
+        }
+
+        impl Unmarshal for $type {
+            fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+                Ok(Self::from_bits_retain(<$inner>::unmarshal(buf)?))
+            }
+        }
+    )*};
+}
+
+bitflags! {
+    /// Object attributes (`TPMA_OBJECT`).
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub struct ObjectAttributes: u32 {
+        const FIXED_TPM = 1 << 1;
+        const ST_CLEAR = 1 << 2;
+        const FIXED_PARENT = 1 << 4;
+        const SENSITIVE_DATA_ORIGIN = 1 << 5;
+        const USER_WITH_AUTH = 1 << 6;
+        const ADMIN_WITH_POLICY = 1 << 7;
+        const NO_DA = 1 << 10;
+        const ENCRYPTED_DUPLICATION = 1 << 11;
+        const RESTRICTED = 1 << 16;
+        const DECRYPT = 1 << 17;
+        const SIGN_ENCRYPT = 1 << 18;
+    }
+
+    /// NV index attributes (`TPMA_NV`).  Bits 4 to 7 hold the index type, which is left to zero
+    /// for ordinary indices.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub struct NvAttributes: u32 {
+        const PPWRITE = 1 << 0;
+        const OWNERWRITE = 1 << 1;
+        const AUTHWRITE = 1 << 2;
+        const POLICYWRITE = 1 << 3;
+        const POLICY_DELETE = 1 << 10;
+        const WRITELOCKED = 1 << 11;
+        const WRITEALL = 1 << 12;
+        const WRITEDEFINE = 1 << 13;
+        const WRITE_STCLEAR = 1 << 14;
+        const GLOBALLOCK = 1 << 15;
+        const PPREAD = 1 << 16;
+        const OWNERREAD = 1 << 17;
+        const AUTHREAD = 1 << 18;
+        const POLICYREAD = 1 << 19;
+        const NO_DA = 1 << 25;
+        const ORDERLY = 1 << 26;
+        const CLEAR_STCLEAR = 1 << 27;
+        const READLOCKED = 1 << 28;
+        const WRITTEN = 1 << 29;
+        const PLATFORMCREATE = 1 << 30;
+        const READ_STCLEAR = 1 << 31;
+    }
+
+    /// Session attributes (`TPMA_SESSION`).
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub struct SessionAttributes: u8 {
+        const CONTINUE_SESSION = 1 << 0;
+        const AUDIT_EXCLUSIVE = 1 << 1;
+        const AUDIT_RESET = 1 << 2;
+        const DECRYPT = 1 << 5;
+        const ENCRYPT = 1 << 6;
+        const AUDIT = 1 << 7;
+    }
+}
+
+impl_marshal_bitflags! {
+    ObjectAttributes: u32,
+    NvAttributes: u32,
+    SessionAttributes: u8,
+}
+
+impl AlgorithmId {
+    /// The size of the digests produced by a hash algorithm.
+    pub fn digest_size(self) -> Result<usize> {
+        Ok(match self {
+            AlgorithmId::Sha1 => 20,
+            AlgorithmId::Sha256 => 32,
+            AlgorithmId::Sha384 => 48,
+            AlgorithmId::Sha512 => 64,
+            _ => bail!(TypeError::NotAHash(self)),
+        })
+    }
+}
+
+impl Handle {
+    /// Whether the handle designates a permanent entity, whose name is its handle.
+    pub fn is_permanent(self) -> bool {
+        self.0 >> 24 == 0x40
+    }
+
+    /// Whether the handle designates an NV index.
+    pub fn is_nv_index(self) -> bool {
+        self.0 >> 24 == 0x01
+    }
+
+    /// Whether the handle designates a PCR.
+    pub fn is_pcr(self) -> bool {
+        self.0 >> 24 == 0x00
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TypeError {
+    #[error("{0} is not a supported hash algorithm")]
+    NotAHash(AlgorithmId),
+    #[error("Digest of {1} bytes does not match {0}")]
+    BadDigestSize(AlgorithmId, usize),
+    #[error("Unsupported public key type {0}")]
+    UnsupportedKeyType(AlgorithmId),
+    #[error("Unsupported signature algorithm {0}")]
+    UnsupportedSignature(AlgorithmId),
+    #[error("Unsupported capability {0}")]
+    UnsupportedCapability(Capability),
+    #[error("Invalid PCR selection of {0} bytes")]
+    BadPcrSelection(usize),
+    #[error("Bad attestation magic {0:#x}")]
+    BadAttestMagic(u32),
+    #[error("Unexpected attestation type {0}")]
+    UnexpectedAttestType(Tag),
+}
+
+/// Response codes (`TPM_RC`).  Format-one codes carry the index of the handle, session or
+/// parameter that caused the error, which is decoded by the `Display` implementation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseCode(pub u32);
+
+impl ResponseCode {
+    pub const SUCCESS: ResponseCode = ResponseCode(0);
+    pub const INITIALIZE: ResponseCode = ResponseCode(RC_VER1);
+    pub const FAILURE: ResponseCode = ResponseCode(RC_VER1 + 0x01);
+    pub const NV_DEFINED: ResponseCode = ResponseCode(RC_VER1 + 0x4c);
+    pub const RETRY: ResponseCode = ResponseCode(RC_WARN + 0x22);
+    pub const TESTING: ResponseCode = ResponseCode(RC_WARN + 0x0a);
+
+    /// The error code with the handle, session or parameter number stripped.
+    pub fn base(self) -> ResponseCode {
+        if self.0 & RC_FMT1 != 0 {
+            ResponseCode(RC_FMT1 | (self.0 & 0x3f))
+        } else {
+            self
+        }
+    }
+
+    /// Whether the command may succeed if retried later.
+    pub fn is_warning(self) -> bool {
+        self.0 & RC_FMT1 == 0 && self.0 & 0xf00 == RC_WARN
+    }
+
+    fn name(self) -> Option<&'static str> {
+        let code = self.base().0;
+        let table: &[(u32, &str)] = if code & RC_FMT1 != 0 {
+            RC_FMT1_NAMES
+        } else if code & 0xf00 == RC_WARN {
+            RC_WARN_NAMES
+        } else if code & 0xf00 == RC_VER1 {
+            RC_VER1_NAMES
+        } else {
+            return None;
+        };
+        table
+            .iter()
+            .find(|(value, _)| *value == code & 0x7f)
+            .map(|(_, name)| *name)
+    }
+}
+
+const RC_VER1: u32 = 0x100;
+const RC_FMT1: u32 = 0x080;
+const RC_WARN: u32 = 0x900;
+
+const RC_FMT1_NAMES: &[(u32, &str)] = &[
+    (0x01, "TPM_RC_ASYMMETRIC"),
+    (0x02, "TPM_RC_ATTRIBUTES"),
+    (0x03, "TPM_RC_HASH"),
+    (0x04, "TPM_RC_VALUE"),
+    (0x05, "TPM_RC_HIERARCHY"),
+    (0x07, "TPM_RC_KEY_SIZE"),
+    (0x08, "TPM_RC_MGF"),
+    (0x09, "TPM_RC_MODE"),
+    (0x0a, "TPM_RC_TYPE"),
+    (0x0b, "TPM_RC_HANDLE"),
+    (0x0c, "TPM_RC_KDF"),
+    (0x0d, "TPM_RC_RANGE"),
+    (0x0e, "TPM_RC_AUTH_FAIL"),
+    (0x0f, "TPM_RC_NONCE"),
+    (0x10, "TPM_RC_PP"),
+    (0x12, "TPM_RC_SCHEME"),
+    (0x15, "TPM_RC_SIZE"),
+    (0x16, "TPM_RC_SYMMETRIC"),
+    (0x17, "TPM_RC_TAG"),
+    (0x18, "TPM_RC_SELECTOR"),
+    (0x1a, "TPM_RC_INSUFFICIENT"),
+    (0x1b, "TPM_RC_SIGNATURE"),
+    (0x1c, "TPM_RC_KEY"),
+    (0x1d, "TPM_RC_POLICY_FAIL"),
+    (0x1f, "TPM_RC_INTEGRITY"),
+    (0x20, "TPM_RC_TICKET"),
+    (0x21, "TPM_RC_RESERVED_BITS"),
+    (0x22, "TPM_RC_BAD_AUTH"),
+    (0x23, "TPM_RC_EXPIRED"),
+    (0x24, "TPM_RC_POLICY_CC"),
+    (0x25, "TPM_RC_BINDING"),
+    (0x26, "TPM_RC_CURVE"),
+    (0x27, "TPM_RC_ECC_POINT"),
+];
+
+const RC_VER1_NAMES: &[(u32, &str)] = &[
+    (0x00, "TPM_RC_INITIALIZE"),
+    (0x01, "TPM_RC_FAILURE"),
+    (0x03, "TPM_RC_SEQUENCE"),
+    (0x0b, "TPM_RC_PRIVATE"),
+    (0x19, "TPM_RC_HMAC"),
+    (0x20, "TPM_RC_DISABLED"),
+    (0x21, "TPM_RC_EXCLUSIVE"),
+    (0x24, "TPM_RC_AUTH_TYPE"),
+    (0x25, "TPM_RC_AUTH_MISSING"),
+    (0x26, "TPM_RC_POLICY"),
+    (0x27, "TPM_RC_PCR"),
+    (0x28, "TPM_RC_PCR_CHANGED"),
+    (0x2d, "TPM_RC_UPGRADE"),
+    (0x2e, "TPM_RC_TOO_MANY_CONTEXTS"),
+    (0x2f, "TPM_RC_AUTH_UNAVAILABLE"),
+    (0x30, "TPM_RC_REBOOT"),
+    (0x31, "TPM_RC_UNBALANCED"),
+    (0x42, "TPM_RC_COMMAND_SIZE"),
+    (0x43, "TPM_RC_COMMAND_CODE"),
+    (0x44, "TPM_RC_AUTHSIZE"),
+    (0x45, "TPM_RC_AUTH_CONTEXT"),
+    (0x46, "TPM_RC_NV_RANGE"),
+    (0x47, "TPM_RC_NV_SIZE"),
+    (0x48, "TPM_RC_NV_LOCKED"),
+    (0x49, "TPM_RC_NV_AUTHORIZATION"),
+    (0x4a, "TPM_RC_NV_UNINITIALIZED"),
+    (0x4b, "TPM_RC_NV_SPACE"),
+    (0x4c, "TPM_RC_NV_DEFINED"),
+    (0x50, "TPM_RC_BAD_CONTEXT"),
+    (0x51, "TPM_RC_CPHASH"),
+    (0x52, "TPM_RC_PARENT"),
+    (0x53, "TPM_RC_NEEDS_TEST"),
+    (0x54, "TPM_RC_NO_RESULT"),
+    (0x55, "TPM_RC_SENSITIVE"),
+];
+
+const RC_WARN_NAMES: &[(u32, &str)] = &[
+    (0x01, "TPM_RC_CONTEXT_GAP"),
+    (0x02, "TPM_RC_OBJECT_MEMORY"),
+    (0x03, "TPM_RC_SESSION_MEMORY"),
+    (0x04, "TPM_RC_MEMORY"),
+    (0x05, "TPM_RC_SESSION_HANDLES"),
+    (0x06, "TPM_RC_OBJECT_HANDLES"),
+    (0x07, "TPM_RC_LOCALITY"),
+    (0x08, "TPM_RC_YIELDED"),
+    (0x09, "TPM_RC_CANCELED"),
+    (0x0a, "TPM_RC_TESTING"),
+    (0x20, "TPM_RC_NV_RATE"),
+    (0x21, "TPM_RC_LOCKOUT"),
+    (0x22, "TPM_RC_RETRY"),
+    (0x23, "TPM_RC_NV_UNAVAILABLE"),
+];
+
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{name}")?,
+            None => write!(f, "{:#x}", self.0)?,
+        }
+        if self.0 & RC_FMT1 != 0 {
+            let n = (self.0 >> 8) & 0x7;
+            match (self.0 & 0x40 != 0, self.0 & 0x800 != 0) {
+                (true, _) => write!(f, " (parameter {})", (self.0 >> 8) & 0xf)?,
+                (false, false) if n != 0 => write!(f, " (handle {n})")?,
+                (false, true) => write!(f, " (session {n})")?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl_marshal_newtype! {
+    ResponseCode: u32,
+}
+
+/// Selection of PCRs of one bank (`TPMS_PCR_SELECTION`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct PcrSelection {
+    pub hash: AlgorithmId,
+    pub pcrs: Vec<u32>,
+}
+
+impl PcrSelection {
+    /// The minimum number of bytes of a PCR bitmap.
+    const SELECT_MIN: usize = 3;
+
+    pub fn new(hash: AlgorithmId, pcrs: &[u32]) -> Self {
+        let mut pcrs = pcrs.to_vec();
+        pcrs.sort_unstable();
+        pcrs.dedup();
+        PcrSelection { hash, pcrs }
+    }
+}
+
+impl Marshal for PcrSelection {
+    fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+        let size = self
+            .pcrs
+            .iter()
+            .map(|pcr| *pcr as usize / 8 + 1)
+            .max()
+            .unwrap_or(0)
+            .max(Self::SELECT_MIN);
+        ensure!(size <= u8::MAX as usize, TypeError::BadPcrSelection(size));
+        let mut select = vec![0u8; size];
+        for pcr in self.pcrs.iter() {
+            select[*pcr as usize / 8] |= 1 << (pcr % 8);
+        }
+        self.hash.marshal(buf)?;
+        (size as u8).marshal(buf)?;
+        buf.extend_from_slice(&select);
+        Ok(())
+    }
+}
+
+impl Unmarshal for PcrSelection {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        let hash = AlgorithmId::unmarshal(buf)?;
+        let size = u8::unmarshal(buf)? as usize;
+        let select = unmarshal_bytes(buf, size)?;
+        let pcrs = (0..size as u32 * 8)
+            .filter(|pcr| select[*pcr as usize / 8] & (1 << (pcr % 8)) != 0)
+            .collect();
+        Ok(PcrSelection { hash, pcrs })
+    }
+}
+
+/// A symmetric algorithm used by a storage key (`TPMT_SYM_DEF_OBJECT`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Annotate)]
+pub struct SymmetricDefinition {
+    pub algorithm: AlgorithmId,
+    pub key_bits: u16,
+    pub mode: AlgorithmId,
+}
+
+impl SymmetricDefinition {
+    pub const AES_128_CFB: SymmetricDefinition = SymmetricDefinition {
+        algorithm: AlgorithmId::Aes,
+        key_bits: 128,
+        mode: AlgorithmId::Cfb,
+    };
+}
+
+impl Marshal for SymmetricDefinition {
+    fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+        self.algorithm.marshal(buf)?;
+        if self.algorithm != AlgorithmId::Null {
+            self.key_bits.marshal(buf)?;
+            self.mode.marshal(buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl Unmarshal for SymmetricDefinition {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        let algorithm = AlgorithmId::unmarshal(buf)?;
+        if algorithm == AlgorithmId::Null {
+            return Ok(SymmetricDefinition::default());
+        }
+        Ok(SymmetricDefinition {
+            algorithm,
+            key_bits: u16::unmarshal(buf)?,
+            mode: AlgorithmId::unmarshal(buf)?,
+        })
+    }
+}
+
+/// A signing or key derivation scheme with its hash algorithm (`TPMT_*_SCHEME`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Annotate)]
+pub struct Scheme {
+    pub scheme: AlgorithmId,
+    pub hash: AlgorithmId,
+}
+
+impl Scheme {
+    pub const NULL: Scheme = Scheme {
+        scheme: AlgorithmId::Null,
+        hash: AlgorithmId::Null,
+    };
+}
+
+impl Marshal for Scheme {
+    fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+        self.scheme.marshal(buf)?;
+        if self.scheme != AlgorithmId::Null {
+            self.hash.marshal(buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl Unmarshal for Scheme {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        let scheme = AlgorithmId::unmarshal(buf)?;
+        if scheme == AlgorithmId::Null {
+            return Ok(Scheme::NULL);
+        }
+        Ok(Scheme {
+            scheme,
+            hash: AlgorithmId::unmarshal(buf)?,
+        })
+    }
+}
+
+/// The type specific parameters and public key of an object.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+#[serde(rename_all = "lowercase")]
+pub enum PublicParams {
+    Ecc {
+        symmetric: SymmetricDefinition,
+        scheme: Scheme,
+        curve: EccCurve,
+        kdf: Scheme,
+        #[serde(with = "serde_bytes")]
+        #[annotate(format = hexstr)]
+        x: Vec<u8>,
+        #[serde(with = "serde_bytes")]
+        #[annotate(format = hexstr)]
+        y: Vec<u8>,
+    },
+    Rsa {
+        symmetric: SymmetricDefinition,
+        scheme: Scheme,
+        key_bits: u16,
+        exponent: u32,
+        #[serde(with = "serde_bytes")]
+        #[annotate(format = hexstr)]
+        modulus: Vec<u8>,
+    },
+}
+
+/// The public area of an object (`TPMT_PUBLIC`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct Public {
+    pub name_alg: AlgorithmId,
+    pub attributes: ObjectAttributes,
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub auth_policy: Vec<u8>,
+    pub params: PublicParams,
+}
+
+impl Public {
+    const KEY_ATTRIBUTES: ObjectAttributes = ObjectAttributes::FIXED_TPM
+        .union(ObjectAttributes::FIXED_PARENT)
+        .union(ObjectAttributes::SENSITIVE_DATA_ORIGIN)
+        .union(ObjectAttributes::USER_WITH_AUTH)
+        .union(ObjectAttributes::RESTRICTED);
+
+    /// Template of a restricted ECDSA P-256 signing key, suitable for `Quote`.
+    pub fn ecc_signing_template() -> Self {
+        Public {
+            name_alg: AlgorithmId::Sha256,
+            attributes: Self::KEY_ATTRIBUTES | ObjectAttributes::SIGN_ENCRYPT,
+            auth_policy: Vec::new(),
+            params: PublicParams::Ecc {
+                symmetric: SymmetricDefinition::default(),
+                scheme: Scheme {
+                    scheme: AlgorithmId::Ecdsa,
+                    hash: AlgorithmId::Sha256,
+                },
+                curve: EccCurve::NistP256,
+                kdf: Scheme::NULL,
+                x: Vec::new(),
+                y: Vec::new(),
+            },
+        }
+    }
+
+    /// Template of an ECC P-256 storage key, as used for the storage root key.
+    pub fn ecc_storage_template() -> Self {
+        Public {
+            name_alg: AlgorithmId::Sha256,
+            attributes: Self::KEY_ATTRIBUTES | ObjectAttributes::DECRYPT,
+            auth_policy: Vec::new(),
+            params: PublicParams::Ecc {
+                symmetric: SymmetricDefinition::AES_128_CFB,
+                scheme: Scheme::NULL,
+                curve: EccCurve::NistP256,
+                kdf: Scheme::NULL,
+                x: Vec::new(),
+                y: Vec::new(),
+            },
+        }
+    }
+
+    /// Template of an RSA 2048 storage key, as used for the storage root key.
+    pub fn rsa_storage_template() -> Self {
+        Public {
+            name_alg: AlgorithmId::Sha256,
+            attributes: Self::KEY_ATTRIBUTES | ObjectAttributes::DECRYPT,
+            auth_policy: Vec::new(),
+            params: PublicParams::Rsa {
+                symmetric: SymmetricDefinition::AES_128_CFB,
+                scheme: Scheme::NULL,
+                key_bits: 2048,
+                exponent: 0,
+                modulus: Vec::new(),
+            },
+        }
+    }
+
+    /// Computes the name of the object, the hash of its public area prefixed with the hash
+    /// algorithm.
+    pub fn name(&self) -> Result<Vec<u8>> {
+        compute_name(self.name_alg, &self.to_tpm_bytes()?)
+    }
+}
+
+impl Marshal for Public {
+    fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+        match &self.params {
+            PublicParams::Ecc { .. } => AlgorithmId::Ecc,
+            PublicParams::Rsa { .. } => AlgorithmId::Rsa,
+        }
+        .marshal(buf)?;
+        self.name_alg.marshal(buf)?;
+        self.attributes.marshal(buf)?;
+        marshal_tpm2b(&self.auth_policy, buf)?;
+        match &self.params {
+            PublicParams::Ecc {
+                symmetric,
+                scheme,
+                curve,
+                kdf,
+                x,
+                y,
+            } => {
+                symmetric.marshal(buf)?;
+                scheme.marshal(buf)?;
+                curve.marshal(buf)?;
+                kdf.marshal(buf)?;
+                marshal_tpm2b(x, buf)?;
+                marshal_tpm2b(y, buf)?;
+            }
+            PublicParams::Rsa {
+                symmetric,
+                scheme,
+                key_bits,
+                exponent,
+                modulus,
+            } => {
+                symmetric.marshal(buf)?;
+                scheme.marshal(buf)?;
+                key_bits.marshal(buf)?;
+                exponent.marshal(buf)?;
+                marshal_tpm2b(modulus, buf)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Unmarshal for Public {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        let key_type = AlgorithmId::unmarshal(buf)?;
+        let name_alg = AlgorithmId::unmarshal(buf)?;
+        let attributes = ObjectAttributes::unmarshal(buf)?;
+        let auth_policy = unmarshal_tpm2b(buf)?;
+        let params = match key_type {
+            AlgorithmId::Ecc => PublicParams::Ecc {
+                symmetric: SymmetricDefinition::unmarshal(buf)?,
+                scheme: Scheme::unmarshal(buf)?,
+                curve: EccCurve::unmarshal(buf)?,
+                kdf: Scheme::unmarshal(buf)?,
+                x: unmarshal_tpm2b(buf)?,
+                y: unmarshal_tpm2b(buf)?,
+            },
+            AlgorithmId::Rsa => PublicParams::Rsa {
+                symmetric: SymmetricDefinition::unmarshal(buf)?,
+                scheme: Scheme::unmarshal(buf)?,
+                key_bits: u16::unmarshal(buf)?,
+                exponent: u32::unmarshal(buf)?,
+                modulus: unmarshal_tpm2b(buf)?,
+            },
+            _ => bail!(TypeError::UnsupportedKeyType(key_type)),
+        };
+        Ok(Public {
+            name_alg,
+            attributes,
+            auth_policy,
+            params,
+        })
+    }
+}
+
+/// The public area of an NV index (`TPMS_NV_PUBLIC`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct NvPublic {
+    pub index: Handle,
+    pub name_alg: AlgorithmId,
+    pub attributes: NvAttributes,
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub auth_policy: Vec<u8>,
+    pub data_size: u16,
+}
+
+impl NvPublic {
+    /// Computes the name of the NV index, the hash of its public area prefixed with the hash
+    /// algorithm.
+    pub fn name(&self) -> Result<Vec<u8>> {
+        compute_name(self.name_alg, &self.to_tpm_bytes()?)
+    }
+}
+
+impl Marshal for NvPublic {
+    fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+        self.index.marshal(buf)?;
+        self.name_alg.marshal(buf)?;
+        self.attributes.marshal(buf)?;
+        marshal_tpm2b(&self.auth_policy, buf)?;
+        self.data_size.marshal(buf)
+    }
+}
+
+impl Unmarshal for NvPublic {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        Ok(NvPublic {
+            index: Handle::unmarshal(buf)?,
+            name_alg: AlgorithmId::unmarshal(buf)?,
+            attributes: NvAttributes::unmarshal(buf)?,
+            auth_policy: unmarshal_tpm2b(buf)?,
+            data_size: u16::unmarshal(buf)?,
+        })
+    }
+}
+
+fn compute_name(name_alg: AlgorithmId, public: &[u8]) -> Result<Vec<u8>> {
+    let digest = match name_alg {
+        AlgorithmId::Sha256 => Sha256::digest(public).to_vec(),
+        _ => bail!(TypeError::NotAHash(name_alg)),
+    };
+    let mut name = name_alg.to_tpm_bytes()?;
+    name.extend(digest);
+    Ok(name)
+}
+
+/// A signature produced by the TPM (`TPMT_SIGNATURE`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+#[serde(rename_all = "lowercase")]
+pub enum Signature {
+    Null,
+    Ecdsa {
+        hash: AlgorithmId,
+        #[serde(with = "serde_bytes")]
+        #[annotate(format = hexstr)]
+        r: Vec<u8>,
+        #[serde(with = "serde_bytes")]
+        #[annotate(format = hexstr)]
+        s: Vec<u8>,
+    },
+    RsaSsa {
+        hash: AlgorithmId,
+        #[serde(with = "serde_bytes")]
+        #[annotate(format = hexstr)]
+        signature: Vec<u8>,
+    },
+}
+
+impl Unmarshal for Signature {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        let algorithm = AlgorithmId::unmarshal(buf)?;
+        Ok(match algorithm {
+            AlgorithmId::Null => Signature::Null,
+            AlgorithmId::Ecdsa => Signature::Ecdsa {
+                hash: AlgorithmId::unmarshal(buf)?,
+                r: unmarshal_tpm2b(buf)?,
+                s: unmarshal_tpm2b(buf)?,
+            },
+            AlgorithmId::RsaSsa => Signature::RsaSsa {
+                hash: AlgorithmId::unmarshal(buf)?,
+                signature: unmarshal_tpm2b(buf)?,
+            },
+            _ => bail!(TypeError::UnsupportedSignature(algorithm)),
+        })
+    }
+}
+
+/// The clock state included in attestations (`TPMS_CLOCK_INFO`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct ClockInfo {
+    pub clock: u64,
+    pub reset_count: u32,
+    pub restart_count: u32,
+    pub safe: bool,
+}
+
+impl Unmarshal for ClockInfo {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        Ok(ClockInfo {
+            clock: u64::unmarshal(buf)?,
+            reset_count: u32::unmarshal(buf)?,
+            restart_count: u32::unmarshal(buf)?,
+            safe: bool::unmarshal(buf)?,
+        })
+    }
+}
+
+/// The attestation structure signed by `Quote` (`TPMS_ATTEST` with `TPMS_QUOTE_INFO`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct QuoteInfo {
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub qualified_signer: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub extra_data: Vec<u8>,
+    pub clock_info: ClockInfo,
+    pub firmware_version: u64,
+    pub pcr_select: Vec<PcrSelection>,
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub pcr_digest: Vec<u8>,
+}
+
+impl QuoteInfo {
+    /// The magic value starting all attestations produced by a TPM (`TPM_GENERATED_VALUE`).
+    const GENERATED_VALUE: u32 = 0xff54_4347;
+}
+
+impl Unmarshal for QuoteInfo {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        let magic = u32::unmarshal(buf)?;
+        ensure!(
+            magic == Self::GENERATED_VALUE,
+            TypeError::BadAttestMagic(magic)
+        );
+        let tag = Tag::unmarshal(buf)?;
+        ensure!(
+            tag == Tag::AttestQuote,
+            TypeError::UnexpectedAttestType(tag)
+        );
+        Ok(QuoteInfo {
+            qualified_signer: unmarshal_tpm2b(buf)?,
+            extra_data: unmarshal_tpm2b(buf)?,
+            clock_info: ClockInfo::unmarshal(buf)?,
+            firmware_version: u64::unmarshal(buf)?,
+            pcr_select: unmarshal_list(buf)?,
+            pcr_digest: unmarshal_tpm2b(buf)?,
+        })
+    }
+}
+
+/// Data returned by `GetCapability` (`TPMS_CAPABILITY_DATA`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+#[serde(rename_all = "snake_case")]
+pub enum CapabilityData {
+    /// Algorithms and their `TPMA_ALGORITHM` attributes.
+    Algorithms(Vec<(AlgorithmId, u32)>),
+    Handles(Vec<Handle>),
+    /// Command codes with their `TPMA_CC` attributes.
+    Commands(Vec<u32>),
+    Pcrs(Vec<PcrSelection>),
+    TpmProperties(Vec<(Property, u32)>),
+    EccCurves(Vec<EccCurve>),
+}
+
+impl Unmarshal for CapabilityData {
+    fn unmarshal(buf: &mut &[u8]) -> Result<Self> {
+        let capability = Capability::unmarshal(buf)?;
+        Ok(match capability {
+            Capability::Algs => CapabilityData::Algorithms(unmarshal_list(buf)?),
+            Capability::Handles => CapabilityData::Handles(unmarshal_list(buf)?),
+            Capability::Commands => CapabilityData::Commands(unmarshal_list(buf)?),
+            Capability::Pcrs => CapabilityData::Pcrs(unmarshal_list(buf)?),
+            Capability::TpmProperties => CapabilityData::TpmProperties(unmarshal_list(buf)?),
+            Capability::EccCurves => CapabilityData::EccCurves(unmarshal_list(buf)?),
+            _ => bail!(TypeError::UnsupportedCapability(capability)),
+        })
+    }
+}
+
+/// A digest tagged with its hash algorithm (`TPMT_HA`), as used by `PCR_Extend`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Annotate)]
+pub struct TaggedDigest {
+    pub hash: AlgorithmId,
+    #[serde(with = "serde_bytes")]
+    #[annotate(format = hexstr)]
+    pub digest: Vec<u8>,
+}
+
+impl Marshal for TaggedDigest {
+    fn marshal(&self, buf: &mut Vec<u8>) -> Result<()> {
+        self.hash.marshal(buf)?;
+        ensure!(
+            self.digest.len() == self.hash.digest_size()?,
+            TypeError::BadDigestSize(self.hash, self.digest.len())
+        );
+        buf.extend_from_slice(&self.digest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_code() {
+        assert_eq!(ResponseCode(0x100).to_string(), "TPM_RC_INITIALIZE");
+        assert_eq!(ResponseCode(0x922).to_string(), "TPM_RC_RETRY");
+        assert!(ResponseCode(0x922).is_warning());
+        // TPM_RC_VALUE on the first parameter.
+        assert_eq!(
+            ResponseCode(0x1c4).to_string(),
+            "TPM_RC_VALUE (parameter 1)"
+        );
+        // TPM_RC_HANDLE on the second handle.
+        assert_eq!(ResponseCode(0x28b).to_string(), "TPM_RC_HANDLE (handle 2)");
+        // TPM_RC_BAD_AUTH on the first session.
+        assert_eq!(
+            ResponseCode(0x9a2).to_string(),
+            "TPM_RC_BAD_AUTH (session 1)"
+        );
+        assert!(!ResponseCode(0x9a2).is_warning());
+        assert_eq!(ResponseCode(0x9a2).base(), ResponseCode(0xa2));
+        assert_eq!(ResponseCode(0x1234).to_string(), "0x1234");
+    }
+
+    #[test]
+    fn test_pcr_selection() -> Result<()> {
+        let sel = PcrSelection::new(AlgorithmId::Sha256, &[16, 0, 7, 0]);
+        let bytes = sel.to_tpm_bytes()?;
+        assert_eq!(bytes, [0x00, 0x0b, 0x03, 0x81, 0x00, 0x01]);
+        assert_eq!(PcrSelection::from_tpm_bytes(&bytes)?, sel);
+        let sel = PcrSelection::new(AlgorithmId::Sha1, &[]);
+        assert_eq!(sel.to_tpm_bytes()?, [0x00, 0x04, 0x03, 0x00, 0x00, 0x00]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_public_roundtrip() -> Result<()> {
+        let template = Public::ecc_signing_template();
+        let bytes = template.to_tpm_bytes()?;
+        #[rustfmt::skip]
+        let expected = [
+            0x00, 0x23, // TPM_ALG_ECC
+            0x00, 0x0b, // nameAlg = SHA256
+            0x00, 0x05, 0x00, 0x72, // attributes
+            0x00, 0x00, // authPolicy
+            0x00, 0x10, // symmetric = NULL
+            0x00, 0x18, 0x00, 0x0b, // scheme = ECDSA-SHA256
+            0x00, 0x03, // curve = NIST P256
+            0x00, 0x10, // kdf = NULL
+            0x00, 0x00, 0x00, 0x00, // unique
+        ];
+        assert_eq!(bytes, expected);
+        assert_eq!(Public::from_tpm_bytes(&bytes)?, template);
+
+        let template = Public::rsa_storage_template();
+        let bytes = template.to_tpm_bytes()?;
+        assert_eq!(Public::from_tpm_bytes(&bytes)?, template);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nv_public_name() -> Result<()> {
+        let public = NvPublic {
+            index: Handle(0x0150_0000),
+            name_alg: AlgorithmId::Sha256,
+            attributes: NvAttributes::OWNERWRITE | NvAttributes::OWNERREAD,
+            auth_policy: Vec::new(),
+            data_size: 32,
+        };
+        let name = public.name()?;
+        assert_eq!(&name[..2], [0x00, 0x0b]);
+        assert_eq!(name[2..], *Sha256::digest(public.to_tpm_bytes()?));
+        assert!(public.index.is_nv_index());
+        assert!(Handle::Owner.is_permanent());
+        Ok(())
+    }
+}
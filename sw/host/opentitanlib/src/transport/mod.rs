@@ -9,6 +9,7 @@ use std::any::Any;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::Duration;
 
 use crate::bootstrap::BootstrapOptions;
 use crate::io::emu::Emulator;
@@ -18,6 +19,7 @@ use crate::io::jtag::{JtagChain, JtagParams};
 use crate::io::nonblocking_help::{NoNonblockingHelp, NonblockingHelp};
 use crate::io::spi::Target;
 use crate::io::uart::Uart;
+use crate::proxy::lease::{LeaseInfo, LeaseResource};
 
 pub mod chip_whisperer;
 pub mod common;
@@ -26,6 +28,9 @@ pub mod ftdi;
 pub mod hyperdebug;
 pub mod ioexpander;
 pub mod proxy;
+pub mod record;
+pub mod replay;
+pub mod sim;
 pub mod ti50emulator;
 pub mod ultradebug;
 pub mod verilator;
@@ -52,6 +57,7 @@ bitflags! {
         const SPI_DUAL = 0x01 << 9;
         const SPI_QUAD = 0x01 << 10;
         const GPIO_BITBANGING = 0x01 << 11;
+        const SPI_OCTAL = 0x01 << 12;
     }
 }
 
@@ -196,6 +202,13 @@ pub trait ProxyOps {
 
     /// Applies the default transport init configuration expect with the specify strap applied.
     fn apply_default_configuration_with_strap(&self, strapping_name: &str) -> Result<()>;
+
+    /// Acquires exclusive use of the given resources of the session for `duration`, or until
+    /// this client disconnects.  Replaces any lease previously held by this client.
+    fn acquire_lease(&self, resources: &[LeaseResource], duration: Duration) -> Result<()>;
+    fn release_lease(&self) -> Result<()>;
+    /// Returns the leases currently held by any client of the session.
+    fn leases(&self) -> Result<Vec<LeaseInfo>>;
 }
 
 /// Used by Transport implementations dealing with emulated OpenTitan
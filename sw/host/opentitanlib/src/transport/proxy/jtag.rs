@@ -0,0 +1,559 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, ensure, Result};
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::ProxyError;
+use crate::debug::dmi::Dmi;
+use crate::debug::openocd::OpenOcd;
+use crate::dif::lc_ctrl::LcCtrlReg;
+use crate::io::jtag::{Jtag, JtagChain, JtagParams, JtagTap, RiscvReg, TargetState};
+use crate::proxy::protocol::{JtagRequest, JtagResponse, Request, Response};
+use crate::transport::proxy::{Inner, Proxy};
+use crate::transport::TransportError;
+
+fn execute_command(inner: &Inner, command: JtagRequest) -> Result<JtagResponse> {
+    match inner.execute_command(Request::Jtag { command })? {
+        Response::Jtag(resp) => Ok(resp),
+        _ => bail!(ProxyError::UnexpectedReply()),
+    }
+}
+
+/// A JTAG chain of the session server.  OpenOCD and the adapter are configured on the server,
+/// only the adapter speed is passed along, as an upper bound.
+pub struct ProxyJtagChain {
+    inner: Rc<Inner>,
+    adapter_speed_khz: u64,
+}
+
+impl ProxyJtagChain {
+    pub fn new(proxy: &Proxy, params: &JtagParams) -> Self {
+        Self {
+            inner: Rc::clone(&proxy.inner),
+            adapter_speed_khz: params.adapter_speed_khz,
+        }
+    }
+}
+
+impl JtagChain for ProxyJtagChain {
+    fn connect(self: Box<Self>, tap: JtagTap) -> Result<Box<dyn Jtag>> {
+        match execute_command(
+            &self.inner,
+            JtagRequest::Connect {
+                tap,
+                adapter_speed_khz: self.adapter_speed_khz,
+            },
+        )? {
+            JtagResponse::Connect => Ok(Box::new(ProxyJtag {
+                inner: self.inner,
+                tap,
+            })),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn connect_dmi(self: Box<Self>, tap: JtagTap) -> Result<Box<dyn Dmi>> {
+        match execute_command(
+            &self.inner,
+            JtagRequest::ConnectDmi {
+                tap,
+                adapter_speed_khz: self.adapter_speed_khz,
+            },
+        )? {
+            JtagResponse::ConnectDmi => Ok(Box::new(ProxyDmi { inner: self.inner })),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn scan_idcodes(self: Box<Self>) -> Result<Vec<u32>> {
+        match execute_command(
+            &self.inner,
+            JtagRequest::ScanIdcodes {
+                adapter_speed_khz: self.adapter_speed_khz,
+            },
+        )? {
+            JtagResponse::ScanIdcodes { idcodes } => Ok(idcodes),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn into_raw(self: Box<Self>) -> Result<OpenOcd> {
+        // OpenOCD runs on the remote server.
+        Err(TransportError::UnsupportedOperation.into())
+    }
+}
+
+/// The debug module interface, connected by the session server on behalf of this client.  The
+/// server keeps it until the client connects again or closes the session connection.
+pub struct ProxyDmi {
+    inner: Rc<Inner>,
+}
+
+impl Dmi for ProxyDmi {
+    fn dmi_read(&mut self, addr: u32) -> Result<u32> {
+        match execute_command(&self.inner, JtagRequest::DmiRead { addr })? {
+            JtagResponse::DmiRead { value } => Ok(value),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn dmi_write(&mut self, addr: u32, value: u32) -> Result<()> {
+        match execute_command(&self.inner, JtagRequest::DmiWrite { addr, value })? {
+            JtagResponse::DmiWrite => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+}
+
+/// A JTAG TAP connected by the session server on behalf of this client.
+pub struct ProxyJtag {
+    inner: Rc<Inner>,
+    tap: JtagTap,
+}
+
+impl ProxyJtag {
+    fn execute_command(&self, command: JtagRequest) -> Result<JtagResponse> {
+        execute_command(&self.inner, command)
+    }
+}
+
+impl Jtag for ProxyJtag {
+    fn into_raw(self: Box<Self>) -> Result<OpenOcd> {
+        Err(TransportError::UnsupportedOperation.into())
+    }
+
+    fn as_raw(&mut self) -> Result<&mut OpenOcd> {
+        Err(TransportError::UnsupportedOperation.into())
+    }
+
+    fn disconnect(self: Box<Self>) -> Result<()> {
+        match self.execute_command(JtagRequest::Disconnect)? {
+            JtagResponse::Disconnect => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn tap(&self) -> JtagTap {
+        self.tap
+    }
+
+    fn read_lc_ctrl_reg(&mut self, reg: &LcCtrlReg) -> Result<u32> {
+        match self.execute_command(JtagRequest::ReadLcCtrlReg { reg: *reg })? {
+            JtagResponse::ReadLcCtrlReg { value } => Ok(value),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn write_lc_ctrl_reg(&mut self, reg: &LcCtrlReg, value: u32) -> Result<()> {
+        match self.execute_command(JtagRequest::WriteLcCtrlReg { reg: *reg, value })? {
+            JtagResponse::WriteLcCtrlReg => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn target_state(&mut self) -> Result<TargetState> {
+        match self.execute_command(JtagRequest::TargetState)? {
+            JtagResponse::TargetState { state } => Ok(state),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn read_memory(&mut self, addr: u32, buf: &mut [u8]) -> Result<usize> {
+        match self.execute_command(JtagRequest::ReadMemory {
+            addr,
+            len: buf.len() as u32,
+        })? {
+            JtagResponse::ReadMemory { data } => {
+                ensure!(data.len() <= buf.len(), ProxyError::UnexpectedReply());
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn read_memory32(&mut self, addr: u32, buf: &mut [u32]) -> Result<usize> {
+        match self.execute_command(JtagRequest::ReadMemory32 {
+            addr,
+            len: buf.len() as u32,
+        })? {
+            JtagResponse::ReadMemory32 { data } => {
+                ensure!(data.len() <= buf.len(), ProxyError::UnexpectedReply());
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn write_memory(&mut self, addr: u32, buf: &[u8]) -> Result<()> {
+        match self.execute_command(JtagRequest::WriteMemory {
+            addr,
+            data: buf.to_vec(),
+        })? {
+            JtagResponse::WriteMemory => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn write_memory32(&mut self, addr: u32, buf: &[u32]) -> Result<()> {
+        match self.execute_command(JtagRequest::WriteMemory32 {
+            addr,
+            data: buf.to_vec(),
+        })? {
+            JtagResponse::WriteMemory32 => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn halt(&mut self) -> Result<()> {
+        match self.execute_command(JtagRequest::Halt)? {
+            JtagResponse::Halt => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn wait_halt(&mut self, timeout: Duration) -> Result<()> {
+        match self.execute_command(JtagRequest::WaitHalt {
+            timeout_millis: timeout.as_millis() as u32,
+        })? {
+            JtagResponse::WaitHalt => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        match self.execute_command(JtagRequest::Resume)? {
+            JtagResponse::Resume => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn resume_at(&mut self, addr: u32) -> Result<()> {
+        match self.execute_command(JtagRequest::ResumeAt { addr })? {
+            JtagResponse::ResumeAt => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn step(&mut self) -> Result<()> {
+        match self.execute_command(JtagRequest::Step)? {
+            JtagResponse::Step => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn step_at(&mut self, addr: u32) -> Result<()> {
+        match self.execute_command(JtagRequest::StepAt { addr })? {
+            JtagResponse::StepAt => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn reset(&mut self, run: bool) -> Result<()> {
+        match self.execute_command(JtagRequest::Reset { run })? {
+            JtagResponse::Reset => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn read_riscv_reg(&mut self, reg: &RiscvReg) -> Result<u32> {
+        match self.execute_command(JtagRequest::ReadRiscvReg { reg: *reg })? {
+            JtagResponse::ReadRiscvReg { value } => Ok(value),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn write_riscv_reg(&mut self, reg: &RiscvReg, value: u32) -> Result<()> {
+        match self.execute_command(JtagRequest::WriteRiscvReg { reg: *reg, value })? {
+            JtagResponse::WriteRiscvReg => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn set_breakpoint(&mut self, addr: u32, hw: bool) -> Result<()> {
+        match self.execute_command(JtagRequest::SetBreakpoint { addr, hw })? {
+            JtagResponse::SetBreakpoint => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn remove_breakpoint(&mut self, addr: u32) -> Result<()> {
+        match self.execute_command(JtagRequest::RemoveBreakpoint { addr })? {
+            JtagResponse::RemoveBreakpoint => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn remove_all_breakpoints(&mut self) -> Result<()> {
+        match self.execute_command(JtagRequest::RemoveAllBreakpoints)? {
+            JtagResponse::RemoveAllBreakpoints => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::TransportWrapperBuilder;
+    use crate::io::jtag::JtagError;
+    use crate::proxy::test::start_session_on;
+    use crate::transport::{Capabilities, Capability, Transport};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    /// OpenOCD binary and adapter speed of each JTAG chain set up by the session.
+    type ChainLog = Arc<Mutex<Vec<(PathBuf, u64)>>>;
+
+    struct FakeTransport {
+        log: ChainLog,
+    }
+
+    impl Transport for FakeTransport {
+        fn capabilities(&self) -> Result<Capabilities> {
+            Ok(Capabilities::new(Capability::JTAG))
+        }
+
+        fn jtag(&self, opts: &JtagParams) -> Result<Box<dyn JtagChain + '_>> {
+            self.log
+                .lock()
+                .unwrap()
+                .push((opts.openocd.clone(), opts.adapter_speed_khz));
+            Ok(Box::new(FakeChain))
+        }
+    }
+
+    struct FakeChain;
+
+    impl JtagChain for FakeChain {
+        fn connect(self: Box<Self>, tap: JtagTap) -> Result<Box<dyn Jtag>> {
+            Ok(Box::new(FakeTap {
+                tap,
+                memory: vec![0; 256],
+                halted: false,
+            }))
+        }
+
+        fn connect_dmi(self: Box<Self>, _tap: JtagTap) -> Result<Box<dyn Dmi>> {
+            Ok(Box::new(FakeDmi(HashMap::new())))
+        }
+
+        fn scan_idcodes(self: Box<Self>) -> Result<Vec<u32>> {
+            Ok(vec![0x10001cdf])
+        }
+
+        fn into_raw(self: Box<Self>) -> Result<OpenOcd> {
+            Err(TransportError::UnsupportedOperation.into())
+        }
+    }
+
+    struct FakeDmi(HashMap<u32, u32>);
+
+    impl Dmi for FakeDmi {
+        fn dmi_read(&mut self, addr: u32) -> Result<u32> {
+            Ok(self.0.get(&addr).copied().unwrap_or_default())
+        }
+
+        fn dmi_write(&mut self, addr: u32, value: u32) -> Result<()> {
+            self.0.insert(addr, value);
+            Ok(())
+        }
+    }
+
+    struct FakeTap {
+        tap: JtagTap,
+        memory: Vec<u8>,
+        halted: bool,
+    }
+
+    impl Jtag for FakeTap {
+        fn into_raw(self: Box<Self>) -> Result<OpenOcd> {
+            Err(TransportError::UnsupportedOperation.into())
+        }
+
+        fn as_raw(&mut self) -> Result<&mut OpenOcd> {
+            Err(TransportError::UnsupportedOperation.into())
+        }
+
+        fn disconnect(self: Box<Self>) -> Result<()> {
+            Ok(())
+        }
+
+        fn tap(&self) -> JtagTap {
+            self.tap
+        }
+
+        fn read_lc_ctrl_reg(&mut self, _reg: &LcCtrlReg) -> Result<u32> {
+            Err(TransportError::UnsupportedOperation.into())
+        }
+
+        fn write_lc_ctrl_reg(&mut self, _reg: &LcCtrlReg, _value: u32) -> Result<()> {
+            Err(TransportError::UnsupportedOperation.into())
+        }
+
+        fn target_state(&mut self) -> Result<TargetState> {
+            Ok(if self.halted {
+                TargetState::Halted
+            } else {
+                TargetState::Running
+            })
+        }
+
+        fn read_memory(&mut self, addr: u32, buf: &mut [u8]) -> Result<usize> {
+            let addr = addr as usize;
+            buf.copy_from_slice(&self.memory[addr..addr + buf.len()]);
+            Ok(buf.len())
+        }
+
+        fn read_memory32(&mut self, _addr: u32, _buf: &mut [u32]) -> Result<usize> {
+            Err(TransportError::UnsupportedOperation.into())
+        }
+
+        fn write_memory(&mut self, addr: u32, buf: &[u8]) -> Result<()> {
+            let addr = addr as usize;
+            self.memory[addr..addr + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn write_memory32(&mut self, _addr: u32, _buf: &[u32]) -> Result<()> {
+            Err(TransportError::UnsupportedOperation.into())
+        }
+
+        fn halt(&mut self) -> Result<()> {
+            self.halted = true;
+            Ok(())
+        }
+
+        fn wait_halt(&mut self, _timeout: Duration) -> Result<()> {
+            ensure!(self.halted, JtagError::Timeout);
+            Ok(())
+        }
+
+        fn resume(&mut self) -> Result<()> {
+            self.halted = false;
+            Ok(())
+        }
+
+        fn resume_at(&mut self, _addr: u32) -> Result<()> {
+            self.resume()
+        }
+
+        fn step(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn step_at(&mut self, _addr: u32) -> Result<()> {
+            Ok(())
+        }
+
+        fn reset(&mut self, run: bool) -> Result<()> {
+            self.halted = !run;
+            Ok(())
+        }
+
+        fn read_riscv_reg(&mut self, _reg: &RiscvReg) -> Result<u32> {
+            Err(TransportError::UnsupportedOperation.into())
+        }
+
+        fn write_riscv_reg(&mut self, _reg: &RiscvReg, _val: u32) -> Result<()> {
+            Err(TransportError::UnsupportedOperation.into())
+        }
+
+        fn set_breakpoint(&mut self, _addr: u32, _hw: bool) -> Result<()> {
+            Ok(())
+        }
+
+        fn remove_breakpoint(&mut self, _addr: u32) -> Result<()> {
+            Ok(())
+        }
+
+        fn remove_all_breakpoints(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn params(openocd: &str, adapter_speed_khz: u64) -> JtagParams {
+        JtagParams {
+            openocd: PathBuf::from(openocd),
+            adapter_speed_khz,
+            log_stdio: false,
+        }
+    }
+
+    #[test]
+    fn test_jtag_loopback() -> Result<()> {
+        let log = ChainLog::default();
+        let transport_log = Arc::clone(&log);
+        let port = start_session_on(
+            move || {
+                TransportWrapperBuilder::new("fake".to_string(), false)
+                    .build(Box::new(FakeTransport { log: transport_log }))
+            },
+            Default::default(),
+            params("/session/openocd", 500),
+        )?;
+        let client = Proxy::open(None, port)?;
+        let other = Proxy::open(None, port)?;
+
+        // The session uses its own OpenOCD, and at most its own adapter speed.
+        let mut jtag = client
+            .jtag(&params("/client/openocd", 1000))?
+            .connect(JtagTap::RiscvTap)?;
+        assert_eq!(
+            *log.lock().unwrap(),
+            [(PathBuf::from("/session/openocd"), 500)]
+        );
+        assert_eq!(jtag.tap(), JtagTap::RiscvTap);
+
+        jtag.write_memory(0x10, &[1, 2, 3, 4])?;
+        let mut buf = [0u8; 4];
+        assert_eq!(jtag.read_memory(0x10, &mut buf)?, 4);
+        assert_eq!(buf, [1, 2, 3, 4]);
+        assert_eq!(jtag.target_state()?, TargetState::Running);
+        jtag.halt()?;
+        jtag.wait_halt(Duration::from_secs(1))?;
+        assert_eq!(jtag.target_state()?, TargetState::Halted);
+        assert!(jtag.as_raw().is_err());
+
+        // Only one client can use JTAG at a time.
+        let err = other
+            .jtag(&params("openocd", 100))?
+            .scan_idcodes()
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JtagError>(),
+            Some(JtagError::Busy)
+        ));
+        jtag.disconnect()?;
+
+        assert_eq!(
+            other.jtag(&params("openocd", 100))?.scan_idcodes()?,
+            [0x10001cdf]
+        );
+        let mut dmi = other
+            .jtag(&params("openocd", 100))?
+            .connect_dmi(JtagTap::RiscvTap)?;
+        dmi.dmi_write(0x39, 0xdeadbeef)?;
+        assert_eq!(dmi.dmi_read(0x39)?, 0xdeadbeef);
+        assert_eq!(
+            log.lock().unwrap()[2],
+            (PathBuf::from("/session/openocd"), 100)
+        );
+
+        // A client replaces its own connection.
+        other
+            .jtag(&params("openocd", 100))?
+            .connect(JtagTap::LcTap)?
+            .disconnect()?;
+        client
+            .jtag(&params("openocd", 1000))?
+            .connect(JtagTap::LcTap)?
+            .disconnect()?;
+        Ok(())
+    }
+}
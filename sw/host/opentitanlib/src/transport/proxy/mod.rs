@@ -3,11 +3,12 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::{bail, Context, Result};
+use openssl::ssl::SslStream;
 use serde::{Deserialize, Serialize};
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::io;
-use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::net::{TcpStream, ToSocketAddrs};
 use std::os::unix::io::AsRawFd;
 use std::rc::Rc;
@@ -19,20 +20,33 @@ use crate::impl_serializable_error;
 use crate::io::emu::Emulator;
 use crate::io::gpio::{GpioBitbanging, GpioMonitoring, GpioPin};
 use crate::io::i2c::Bus;
+use crate::io::jtag::{JtagChain, JtagParams};
 use crate::io::nonblocking_help::NonblockingHelp;
 use crate::io::spi::Target;
 use crate::io::uart::Uart;
+use crate::proxy::errors::SerializedError;
+use crate::proxy::lease::{LeaseInfo, LeaseResource};
 use crate::proxy::protocol::{
-    AsyncMessage, Message, ProxyRequest, ProxyResponse, Request, Response,
+    AsyncMessage, Encoding, Message, ProxyRequest, ProxyResponse, Request, Response,
+    PROTOCOL_VERSION,
 };
+use crate::proxy::security::TlsClientConfig;
 use crate::transport::{Capabilities, Capability, ProxyOps, Transport, TransportError};
 
 mod emu;
 mod gpio;
 mod i2c;
+mod jtag;
 mod spi;
 mod uart;
 
+/// Maximum number of pipelined requests awaiting their response, before sending another
+/// request waits for the oldest one to complete.
+const MAX_PIPELINED: usize = 64;
+
+/// Amount by which to grow the receive buffer for each read from the socket.
+const RECV_CHUNK_SIZE: usize = 65536;
+
 #[derive(Debug, Error, Serialize, Deserialize)]
 pub enum ProxyError {
     #[error("Unexpected reply")]
@@ -54,21 +68,106 @@ impl Proxy {
     /// Establish connection with a running session process.
     pub fn open(host: Option<&str>, port: u16) -> Result<Self> {
         let host = host.unwrap_or("localhost");
+        Self::establish(|| Ok(ProxyStream::Plain(Self::connect(host, port)?)))
+    }
+
+    /// Establish TLS protected connection with a running session process.
+    pub fn open_tls(host: Option<&str>, port: u16, tls: &TlsClientConfig) -> Result<Self> {
+        let host = host.unwrap_or("localhost");
+        Self::establish(|| {
+            let conn = tls.connect(host, Self::connect(host, port)?)?;
+            Ok(ProxyStream::Tls(Box::new(conn)))
+        })
+    }
+
+    /// Connects by means of `connect`, and negotiates the most compact encoding supported by
+    /// the session.  Sessions predating the negotiation close the connection in response to
+    /// the unknown request, in which case a new connection is made, speaking JSON throughout.
+    fn establish(connect: impl Fn() -> Result<ProxyStream>) -> Result<Self> {
+        let proxy = Self::new(connect()?);
+        match proxy.inner.negotiate() {
+            Ok(()) => Ok(proxy),
+            Err(e) if is_connection_closed(&e) => {
+                log::info!("Session does not support negotiation, using JSON encoding");
+                Ok(Self::new(connect()?))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn connect(host: &str, port: u16) -> Result<TcpStream> {
         let addr = ToSocketAddrs::to_socket_addrs(&(host, port))
             .map_err(|e| TransportError::ProxyLookupError(host.to_string(), e.to_string()))?
             .next()
             .unwrap();
-        let conn = TcpStream::connect(addr)
-            .map_err(|e| TransportError::ProxyConnectError(addr.to_string(), e.to_string()))?;
-        Ok(Self {
+        Ok(TcpStream::connect(addr)
+            .map_err(|e| TransportError::ProxyConnectError(addr.to_string(), e.to_string()))?)
+    }
+
+    fn new(conn: ProxyStream) -> Self {
+        Self {
             inner: Rc::new(Inner {
                 conn: RefCell::new(conn),
                 uarts: RefCell::new(HashMap::new()),
                 uart_channel_map: RefCell::new(HashMap::new()),
                 recv_buf: RefCell::new(Vec::new()),
+                encoding: Cell::new(Encoding::Json),
+                pipelined: Cell::new(0),
+                pipeline_error: RefCell::new(None),
                 nonblocking_help_enabled: Cell::new(false),
             }),
+        }
+    }
+}
+
+fn is_connection_closed(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|e| e.downcast_ref::<io::Error>())
+        .any(|e| {
+            matches!(
+                e.kind(),
+                ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::BrokenPipe
+            )
         })
+}
+
+/// Connection to the session process, possibly protected by TLS.
+enum ProxyStream {
+    Plain(TcpStream),
+    Tls(Box<SslStream<TcpStream>>),
+}
+
+impl ProxyStream {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Self::Plain(stream) => stream,
+            Self::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl Read for ProxyStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.read(buf),
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ProxyStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.write(buf),
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.flush(),
+            Self::Tls(stream) => stream.flush(),
+        }
     }
 }
 
@@ -79,38 +178,97 @@ struct UartRecord {
 }
 
 struct Inner {
-    conn: RefCell<TcpStream>,
+    conn: RefCell<ProxyStream>,
     pub uarts: RefCell<HashMap<String, UartRecord>>,
     uart_channel_map: RefCell<HashMap<u32, String>>,
     recv_buf: RefCell<Vec<u8>>,
+    /// Encoding of messages in both directions, as negotiated with the session.
+    encoding: Cell<Encoding>,
+    /// Number of requests sent by `pipeline_command()`, whose responses have yet to arrive.
+    pipelined: Cell<usize>,
+    /// First failure among the pipelined requests, to be reported by the next command.
+    pipeline_error: RefCell<Option<anyhow::Error>>,
     nonblocking_help_enabled: Cell<bool>,
 }
 
 impl Inner {
-    /// Helper method for sending one JSON request and receiving the response.  Called as part
-    /// of the implementation of every method of the sub-traits (gpio, uart, spi, i2c).
+    /// Helper method for sending one request and receiving the response.  Called as part
+    /// of the implementation of every method of the sub-traits (gpio, uart, spi, i2c).  Failure
+    /// of any previously pipelined request is reported in place of the response.
     fn execute_command(&self, req: Request) -> Result<Response> {
-        self.send_json_request(req).context("json encoding")?;
+        self.send_request(req).context("encoding request")?;
         loop {
-            match self.recv_json_response().context("json decoding")? {
-                Message::Res(res) => match res {
-                    Ok(value) => return Ok(value),
-                    Err(e) => return Err(anyhow::Error::from(e)),
-                },
+            match self.recv_message().context("decoding response")? {
+                Message::Res(res) if self.pipelined.get() > 0 => self.complete_pipelined(res),
+                Message::Res(res) => {
+                    if let Some(e) = self.pipeline_error.take() {
+                        return Err(e);
+                    }
+                    match res {
+                        Ok(value) => return Ok(value),
+                        Err(e) => return Err(anyhow::Error::from(e)),
+                    }
+                }
                 Message::Async { channel, msg } => self.process_async_data(channel, msg)?,
                 _ => bail!(ProxyError::UnexpectedReply()),
             }
         }
     }
 
+    /// Sends a request without waiting for its response, for requests whose response carries
+    /// no information beyond success.  Any failure is reported by a later command.
+    fn pipeline_command(&self, req: Request) -> Result<()> {
+        self.send_request(req).context("encoding request")?;
+        self.pipelined.set(self.pipelined.get() + 1);
+        while self.pipelined.get() > MAX_PIPELINED {
+            match self.recv_message().context("decoding response")? {
+                Message::Res(res) => self.complete_pipelined(res),
+                Message::Async { channel, msg } => self.process_async_data(channel, msg)?,
+                _ => bail!(ProxyError::UnexpectedReply()),
+            }
+        }
+        match self.pipeline_error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Accounts for the response to the oldest pipelined request.
+    fn complete_pipelined(&self, res: Result<Response, SerializedError>) {
+        self.pipelined.set(self.pipelined.get() - 1);
+        if let Err(e) = res {
+            self.pipeline_error
+                .borrow_mut()
+                .get_or_insert_with(|| anyhow::Error::from(e).context("Pipelined request failed"));
+        }
+    }
+
+    /// Exchanges `ProxyRequest::Hello` with the session, and switches to the encoding chosen
+    /// by the session.
+    fn negotiate(&self) -> Result<()> {
+        let req = Request::Proxy(ProxyRequest::Hello {
+            version: PROTOCOL_VERSION,
+            encodings: Encoding::SUPPORTED.to_vec(),
+        });
+        match self.execute_command(req)? {
+            Response::Proxy(ProxyResponse::Hello { version, encoding }) => {
+                log::debug!("Session protocol version {}, using {:?}", version, encoding);
+                self.encoding.set(encoding);
+                Ok(())
+            }
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
     fn poll_for_async_data(&self, timeout: Option<Duration>) -> Result<()> {
         if timeout == Some(Duration::from_millis(0)) {
             self.recv_nonblocking()?;
         } else {
             self.recv_with_timeout(timeout)?;
         }
-        while let Some(msg) = self.dequeue_json_response()? {
+        while let Some(msg) = self.dequeue_message()? {
             match msg {
+                Message::Res(res) if self.pipelined.get() > 0 => self.complete_pipelined(res),
                 Message::Async { channel, msg } => self.process_async_data(channel, msg)?,
                 _ => bail!(ProxyError::UnexpectedReply()),
             }
@@ -131,47 +289,40 @@ impl Inner {
         Ok(())
     }
 
-    /// Send a one-line JSON encoded requests, terminated with one newline.
-    fn send_json_request(&self, req: Request) -> Result<()> {
-        let conn: &mut std::net::TcpStream = &mut self.conn.borrow_mut();
-        let mut writer = BufWriter::new(conn);
-        serde_json::to_writer(&mut writer, &Message::Req(req))?;
-        writer.write_all(b"\n")?;
-        writer.flush()?;
+    /// Send one request, encoded as negotiated.
+    fn send_request(&self, req: Request) -> Result<()> {
+        let mut buf = Vec::new();
+        self.encoding.get().encode(&mut buf, &Message::Req(req))?;
+        let mut conn = self.conn.borrow_mut();
+        conn.write_all(&buf)?;
+        conn.flush()?;
         Ok(())
     }
 
-    /// Decode one JSON response, possibly waiting for more network data.
-    fn recv_json_response(&self) -> Result<Message> {
-        if let Some(msg) = self.dequeue_json_response()? {
-            return Ok(msg);
-        }
-        let mut conn = self.conn.borrow_mut();
-        let mut buf = self.recv_buf.borrow_mut();
-        let mut idx: usize = buf.len();
+    /// Decode one message, possibly waiting for more network data.
+    fn recv_message(&self) -> Result<Message> {
         loop {
-            buf.resize(idx + 2048, 0);
-            let rc = conn.read(&mut buf[idx..])?;
-            if rc == 0 {
+            if let Some(msg) = self.dequeue_message()? {
+                return Ok(msg);
+            }
+            let mut conn = self.conn.borrow_mut();
+            let mut buf = self.recv_buf.borrow_mut();
+            let idx: usize = buf.len();
+            buf.resize(idx + RECV_CHUNK_SIZE, 0);
+            let rc = conn.read(&mut buf[idx..]);
+            buf.truncate(idx + rc.as_ref().map_or(0, |n| *n));
+            if rc? == 0 {
                 anyhow::bail!(io::Error::new(
                     ErrorKind::UnexpectedEof,
                     "Server unexpectedly closed connection"
                 ))
             }
-            idx += rc;
-            let Some(newline_pos) = buf[idx - rc..idx].iter().position(|b| *b == b'\n') else {
-                continue;
-            };
-            let result = serde_json::from_slice::<Message>(&buf[..idx - rc + newline_pos])?;
-            buf.resize(idx, 0u8);
-            buf.drain(..idx - rc + newline_pos + 1);
-            return Ok(result);
         }
     }
 
     fn recv_with_timeout(&self, timeout: Option<Duration>) -> Result<()> {
         let mut conn = self.conn.borrow_mut();
-        conn.set_read_timeout(timeout)?;
+        conn.tcp().set_read_timeout(timeout)?;
         let mut buf = self.recv_buf.borrow_mut();
         let mut idx: usize = buf.len();
         buf.resize(idx + 2048, 0);
@@ -187,13 +338,13 @@ impl Inner {
             Err(e) => anyhow::bail!(e),
         }
         buf.resize(idx, 0);
-        conn.set_read_timeout(None)?;
+        conn.tcp().set_read_timeout(None)?;
         Ok(())
     }
 
     fn recv_nonblocking(&self) -> Result<()> {
         let mut conn = self.conn.borrow_mut();
-        conn.set_nonblocking(true)?;
+        conn.tcp().set_nonblocking(true)?;
         let mut buf = self.recv_buf.borrow_mut();
         let mut idx: usize = buf.len();
         loop {
@@ -211,18 +362,36 @@ impl Inner {
             }
         }
         buf.resize(idx, 0);
-        conn.set_nonblocking(false)?;
+        conn.tcp().set_nonblocking(false)?;
         Ok(())
     }
 
-    fn dequeue_json_response(&self) -> Result<Option<Message>> {
+    fn dequeue_message(&self) -> Result<Option<Message>> {
         let mut buf = self.recv_buf.borrow_mut();
-        let Some(newline_pos) = buf.iter().position(|b| *b == b'\n') else {
+        let Some((msg, len)) = self.encoding.get().decode::<Message>(&buf)? else {
             return Ok(None);
         };
-        let result = serde_json::from_slice::<Message>(&buf[..newline_pos])?;
-        buf.drain(..newline_pos + 1);
-        Ok(Some(result))
+        buf.drain(..len);
+        Ok(Some(msg))
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // Make sure that the failure of any final pipelined request does not go unnoticed.
+        while self.pipelined.get() > 0 {
+            match self.recv_message() {
+                Ok(Message::Res(res)) => self.complete_pipelined(res),
+                Ok(_) => (),
+                Err(e) => {
+                    log::error!("Awaiting pipelined requests: {:?}", e);
+                    return;
+                }
+            }
+        }
+        if let Some(e) = self.pipeline_error.take() {
+            log::error!("{:?}", e);
+        }
     }
 }
 
@@ -290,6 +459,30 @@ impl ProxyOps for ProxyOpsImpl {
             _ => bail!(ProxyError::UnexpectedReply()),
         }
     }
+
+    fn acquire_lease(&self, resources: &[LeaseResource], duration: Duration) -> Result<()> {
+        match self.execute_command(ProxyRequest::AcquireLease {
+            resources: resources.to_vec(),
+            duration_millis: duration.as_millis() as u64,
+        })? {
+            ProxyResponse::AcquireLease => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn release_lease(&self) -> Result<()> {
+        match self.execute_command(ProxyRequest::ReleaseLease)? {
+            ProxyResponse::ReleaseLease => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn leases(&self) -> Result<Vec<LeaseInfo>> {
+        match self.execute_command(ProxyRequest::GetLeases)? {
+            ProxyResponse::GetLeases { leases } => Ok(leases),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
 }
 
 impl Transport for Proxy {
@@ -353,6 +546,11 @@ impl Transport for Proxy {
         Ok(Rc::new(gpio::GpioBitbangingImpl::new(self)?))
     }
 
+    // Create JtagChain instance, the TAP will be connected by the remote session process.
+    fn jtag(&self, opts: &JtagParams) -> Result<Box<dyn JtagChain + '_>> {
+        Ok(Box::new(jtag::ProxyJtagChain::new(self, opts)))
+    }
+
     // Create Emulator instance, or return one from a cache of previously created instances.
     fn emulator(&self) -> Result<Rc<dyn Emulator>> {
         Ok(Rc::new(emu::ProxyEmu::open(self)?))
@@ -376,9 +574,9 @@ pub struct ProxyNonblockingHelp {
 
 impl NonblockingHelp for ProxyNonblockingHelp {
     fn register_nonblocking_help(&self, registry: &mio::Registry, token: mio::Token) -> Result<()> {
-        let conn: &mut std::net::TcpStream = &mut self.inner.conn.borrow_mut();
+        let conn = self.inner.conn.borrow();
         registry.register(
-            &mut mio::unix::SourceFd(&conn.as_raw_fd()),
+            &mut mio::unix::SourceFd(&conn.tcp().as_raw_fd()),
             token,
             mio::Interest::READABLE,
         )?;
@@ -390,3 +588,89 @@ impl NonblockingHelp for ProxyNonblockingHelp {
             .poll_for_async_data(Some(Duration::from_millis(0)))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::spi::Transfer;
+    use crate::proxy::test::start_session;
+    use crate::spiflash::SpiFlash;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_pipelined_spi() -> Result<()> {
+        let port = start_session(Default::default())?;
+        let proxy = Proxy::open(None, port)?;
+        assert_eq!(proxy.inner.encoding.get(), Encoding::MessagePack);
+        let spi = proxy.spi("BOOTSTRAP")?;
+        let data: Vec<u8> = (0..=255).collect();
+        let mut program = vec![SpiFlash::PAGE_PROGRAM, 0, 1, 0];
+        program.extend_from_slice(&data);
+        spi.run_transaction(&mut [Transfer::Write(&[SpiFlash::WRITE_ENABLE])])?;
+        spi.run_transaction(&mut [Transfer::Write(&program)])?;
+        assert_eq!(proxy.inner.pipelined.get(), 2);
+
+        // The failure of a pipelined request is reported by the next request awaiting its
+        // response, in this case the flash is still busy programming.
+        spi.run_transaction(&mut [Transfer::Write(&[SpiFlash::WRITE_ENABLE])])?;
+        let mut status = [SpiFlash::STATUS_WIP];
+        let err = spi
+            .run_transaction(&mut [
+                Transfer::Write(&[SpiFlash::READ_STATUS]),
+                Transfer::Read(&mut status),
+            ])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Pipelined request failed");
+        assert_eq!(proxy.inner.pipelined.get(), 0);
+
+        while status[0] & SpiFlash::STATUS_WIP != 0 {
+            spi.run_transaction(&mut [
+                Transfer::Write(&[SpiFlash::READ_STATUS]),
+                Transfer::Read(&mut status),
+            ])?;
+        }
+        let mut buf = vec![0u8; data.len()];
+        spi.run_transaction(&mut [
+            Transfer::Write(&[SpiFlash::READ, 0, 1, 0]),
+            Transfer::Read(&mut buf),
+        ])?;
+        assert_eq!(buf, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_fallback() -> Result<()> {
+        // Pretend to be a session predating the negotiation, which drops the connection upon
+        // receiving an unknown request.
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server = std::thread::spawn(move || -> Result<()> {
+            let mut line = String::new();
+            let (conn, _) = listener.accept()?;
+            BufReader::new(conn).read_line(&mut line)?;
+            assert!(line.contains("Hello"));
+
+            let (mut conn, _) = listener.accept()?;
+            line.clear();
+            BufReader::new(&conn).read_line(&mut line)?;
+            let msg: Message = serde_json::from_str(&line)?;
+            assert!(matches!(
+                msg,
+                Message::Req(Request::Proxy(ProxyRequest::Provides))
+            ));
+            let provides_map = HashMap::from([("x".to_string(), "y".to_string())]);
+            let resp = Message::Res(Ok(Response::Proxy(ProxyResponse::Provides {
+                provides_map,
+            })));
+            serde_json::to_writer(&mut conn, &resp)?;
+            conn.write_all(b"\n")?;
+            Ok(())
+        });
+        let proxy = Proxy::open(Some("127.0.0.1"), port)?;
+        assert_eq!(proxy.inner.encoding.get(), Encoding::Json);
+        let provides_map = proxy.proxy_ops()?.provides_map()?;
+        assert_eq!(provides_map["x"], "y");
+        server.join().unwrap()
+    }
+}
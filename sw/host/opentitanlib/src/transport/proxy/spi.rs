@@ -186,6 +186,13 @@ impl Target for ProxySpi {
                 Transfer::GscReady => req.push(SpiTransferRequest::GscReady),
             }
         }
+        if transaction.iter().all(|t| matches!(t, Transfer::Write(_))) {
+            // Nothing to return to the caller, so do not wait for the session to respond.
+            return self.inner.pipeline_command(Request::Spi {
+                id: self.instance.clone(),
+                command: SpiRequest::RunTransaction { transaction: req },
+            });
+        }
         match self.execute_command(SpiRequest::RunTransaction { transaction: req })? {
             SpiResponse::RunTransaction { transaction: resp } => {
                 ensure!(
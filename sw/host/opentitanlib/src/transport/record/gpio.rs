@@ -0,0 +1,108 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::rc::Rc;
+
+use super::Recorder;
+use crate::io::gpio::{GpioPin, PinMode, PullMode};
+use crate::proxy::protocol::{GpioRequest, GpioResponse, Request, Response};
+
+pub struct RecordingGpioPin {
+    inner: Rc<dyn GpioPin>,
+    instance: String,
+    recorder: Rc<Recorder>,
+}
+
+impl RecordingGpioPin {
+    pub fn new(inner: Rc<dyn GpioPin>, instance: &str, recorder: &Rc<Recorder>) -> Self {
+        Self {
+            inner,
+            instance: instance.to_string(),
+            recorder: Rc::clone(recorder),
+        }
+    }
+
+    fn call<T>(
+        &self,
+        command: GpioRequest,
+        result: Result<T>,
+        response: impl FnOnce(&T) -> GpioResponse,
+    ) -> Result<T> {
+        let request = Request::Gpio {
+            id: self.instance.clone(),
+            command,
+        };
+        self.recorder
+            .call(request, result, |v| Response::Gpio(response(v)))
+    }
+}
+
+impl GpioPin for RecordingGpioPin {
+    fn read(&self) -> Result<bool> {
+        let result = self.inner.read();
+        self.call(GpioRequest::Read, result, |&value| GpioResponse::Read {
+            value,
+        })
+    }
+
+    fn write(&self, logic: bool) -> Result<()> {
+        let result = self.inner.write(logic);
+        self.call(GpioRequest::Write { logic }, result, |_| {
+            GpioResponse::Write
+        })
+    }
+
+    fn set_mode(&self, mode: PinMode) -> Result<()> {
+        let result = self.inner.set_mode(mode);
+        self.call(GpioRequest::SetMode { mode }, result, |_| {
+            GpioResponse::SetMode
+        })
+    }
+
+    fn set_pull_mode(&self, pull: PullMode) -> Result<()> {
+        let result = self.inner.set_pull_mode(pull);
+        self.call(GpioRequest::SetPullMode { pull }, result, |_| {
+            GpioResponse::SetPullMode
+        })
+    }
+
+    fn analog_read(&self) -> Result<f32> {
+        let result = self.inner.analog_read();
+        self.call(GpioRequest::AnalogRead, result, |&value| {
+            GpioResponse::AnalogRead { value }
+        })
+    }
+
+    fn analog_write(&self, value: f32) -> Result<()> {
+        let result = self.inner.analog_write(value);
+        self.call(GpioRequest::AnalogWrite { value }, result, |_| {
+            GpioResponse::AnalogWrite
+        })
+    }
+
+    fn set(
+        &self,
+        mode: Option<PinMode>,
+        value: Option<bool>,
+        pull: Option<PullMode>,
+        analog_value: Option<f32>,
+    ) -> Result<()> {
+        let result = self.inner.set(mode, value, pull, analog_value);
+        self.call(
+            GpioRequest::MultiSet {
+                mode,
+                value,
+                pull,
+                analog_value,
+            },
+            result,
+            |_| GpioResponse::MultiSet,
+        )
+    }
+
+    fn get_internal_pin_name(&self) -> Option<&str> {
+        self.inner.get_internal_pin_name()
+    }
+}
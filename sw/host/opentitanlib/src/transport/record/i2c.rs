@@ -0,0 +1,164 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::Recorder;
+use crate::io::gpio::GpioPin;
+use crate::io::i2c::{Bus, DeviceStatus, Mode, Transfer};
+use crate::proxy::protocol::{
+    I2cRequest, I2cResponse, I2cTransferRequest, I2cTransferResponse, Request, Response,
+};
+
+pub struct RecordingI2c {
+    inner: Rc<dyn Bus>,
+    instance: String,
+    recorder: Rc<Recorder>,
+}
+
+impl RecordingI2c {
+    pub fn new(inner: Rc<dyn Bus>, instance: &str, recorder: &Rc<Recorder>) -> Self {
+        Self {
+            inner,
+            instance: instance.to_string(),
+            recorder: Rc::clone(recorder),
+        }
+    }
+
+    fn call<T>(
+        &self,
+        command: I2cRequest,
+        result: Result<T>,
+        response: impl FnOnce(&T) -> I2cResponse,
+    ) -> Result<T> {
+        let request = Request::I2c {
+            id: self.instance.clone(),
+            command,
+        };
+        self.recorder
+            .call(request, result, |v| Response::I2c(response(v)))
+    }
+}
+
+fn pin_name(pin: Option<&Rc<dyn GpioPin>>) -> Option<String> {
+    pin.and_then(|p| p.get_internal_pin_name())
+        .map(str::to_string)
+}
+
+impl Bus for RecordingI2c {
+    fn set_mode(&self, mode: Mode) -> Result<()> {
+        match mode {
+            Mode::Host => {
+                let result = self.inner.set_mode(mode);
+                self.call(I2cRequest::SetModeHost, result, |_| {
+                    I2cResponse::SetModeHost
+                })
+            }
+            Mode::Device(addr) => {
+                let result = self.inner.set_mode(mode);
+                self.call(I2cRequest::SetModeDevice { addr }, result, |_| {
+                    I2cResponse::SetModeDevice
+                })
+            }
+        }
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        let result = self.inner.get_max_speed();
+        self.call(I2cRequest::GetMaxSpeed, result, |&speed| {
+            I2cResponse::GetMaxSpeed { speed }
+        })
+    }
+
+    fn set_max_speed(&self, value: u32) -> Result<()> {
+        let result = self.inner.set_max_speed(value);
+        self.call(I2cRequest::SetMaxSpeed { value }, result, |_| {
+            I2cResponse::SetMaxSpeed
+        })
+    }
+
+    fn set_pins(
+        &self,
+        serial_clock: Option<&Rc<dyn GpioPin>>,
+        serial_data: Option<&Rc<dyn GpioPin>>,
+        gsc_ready: Option<&Rc<dyn GpioPin>>,
+    ) -> Result<()> {
+        let request = I2cRequest::SetPins {
+            serial_clock: pin_name(serial_clock),
+            serial_data: pin_name(serial_data),
+            gsc_ready: pin_name(gsc_ready),
+        };
+        let result = self.inner.set_pins(serial_clock, serial_data, gsc_ready);
+        self.call(request, result, |_| I2cResponse::SetPins)
+    }
+
+    // The proxy protocol has no request for setting the default address.  Transactions are
+    // recorded with the address given by the caller, so replay does not depend on it.
+    fn set_default_address(&self, addr: u8) -> Result<()> {
+        self.inner.set_default_address(addr)
+    }
+
+    fn run_transaction(&self, address: Option<u8>, transaction: &mut [Transfer]) -> Result<()> {
+        let request = transaction
+            .iter()
+            .map(|transfer| match transfer {
+                Transfer::Read(rbuf) => I2cTransferRequest::Read {
+                    len: rbuf.len() as u32,
+                },
+                Transfer::Write(wbuf) => I2cTransferRequest::Write {
+                    data: wbuf.to_vec(),
+                },
+                Transfer::GscReady => I2cTransferRequest::GscReady,
+            })
+            .collect();
+        let result = self.inner.run_transaction(address, transaction);
+        let response = transaction
+            .iter()
+            .map(|transfer| match transfer {
+                Transfer::Read(rbuf) => I2cTransferResponse::Read {
+                    data: rbuf.to_vec(),
+                },
+                Transfer::Write(_) => I2cTransferResponse::Write,
+                Transfer::GscReady => I2cTransferResponse::GscReady,
+            })
+            .collect();
+        self.call(
+            I2cRequest::RunTransaction {
+                address,
+                transaction: request,
+            },
+            result,
+            |_| I2cResponse::RunTransaction {
+                transaction: response,
+            },
+        )
+    }
+
+    fn get_device_status(&self, timeout: Duration) -> Result<DeviceStatus> {
+        let result = self.inner.get_device_status(timeout);
+        self.call(
+            I2cRequest::GetDeviceStatus {
+                timeout_millis: timeout.as_millis() as u32,
+            },
+            result,
+            |status| I2cResponse::GetDeviceStatus {
+                status: status.clone(),
+            },
+        )
+    }
+
+    fn prepare_read_data(&self, data: &[u8], sticky: bool) -> Result<()> {
+        let result = self.inner.prepare_read_data(data, sticky);
+        self.call(
+            I2cRequest::PrepareReadData {
+                data: data.to_vec(),
+                sticky,
+            },
+            result,
+            |_| I2cResponse::PrepareReadData,
+        )
+    }
+}
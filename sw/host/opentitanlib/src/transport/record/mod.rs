@@ -0,0 +1,204 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::io::emu::Emulator;
+use crate::io::gpio::{GpioBitbanging, GpioMonitoring, GpioPin};
+use crate::io::i2c::Bus;
+use crate::io::jtag::{JtagChain, JtagParams};
+use crate::io::nonblocking_help::NonblockingHelp;
+use crate::io::spi::Target;
+use crate::io::uart::Uart;
+use crate::proxy::errors::SerializedError;
+use crate::proxy::protocol::{Request, Response};
+use crate::transport::{Capabilities, MaintainConnection, ProxyOps, Transport};
+
+mod gpio;
+mod i2c;
+mod spi;
+mod uart;
+
+/// One entry of a recording: a single call on a transport interface, and its outcome.
+///
+/// Calls are described using the request and response messages of the session proxy protocol,
+/// which identify the interface instance and carry the arguments and results of the call.
+#[derive(Deserialize)]
+pub struct Record {
+    /// Seconds since the start of the recording at which the call completed.
+    pub timestamp: f64,
+    pub request: Request,
+    pub response: Result<Response, SerializedError>,
+}
+
+// Borrowed form of `Record`, used when writing.
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    timestamp: f64,
+    request: &'a Request,
+    response: Result<&'a Response, &'a SerializedError>,
+}
+
+/// Writes `Record`s to a file, one JSON object per line.
+pub struct Recorder {
+    start: Instant,
+    writer: RefCell<LineWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        Ok(Self {
+            start: Instant::now(),
+            writer: RefCell::new(LineWriter::new(file)),
+        })
+    }
+
+    fn write(&self, request: &Request, response: Result<&Response, &SerializedError>) {
+        let record = RecordRef {
+            timestamp: self.start.elapsed().as_secs_f64(),
+            request,
+            response,
+        };
+        let mut writer = self.writer.borrow_mut();
+        // A failure to record must not change the outcome of the call being recorded.
+        let result = serde_json::to_writer(&mut *writer, &record)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(writer.write_all(b"\n")?));
+        if let Err(e) = result {
+            log::warn!("Failed to record transport call: {e}");
+        }
+    }
+
+    /// Records `request` along with the outcome of performing it, and passes on the outcome.
+    /// `response` describes a successful result in terms of the proxy protocol.
+    pub fn call<T>(
+        &self,
+        request: Request,
+        result: Result<T>,
+        response: impl FnOnce(&T) -> Response,
+    ) -> Result<T> {
+        match result {
+            Ok(value) => {
+                self.write(&request, Ok(&response(&value)));
+                Ok(value)
+            }
+            Err(e) => {
+                let mut error = SerializedError::from(e);
+                // The backtrace would otherwise be attached as context when converting back.
+                error.backtrace = "<disabled>".to_string();
+                self.write(&request, Err(&error));
+                Err(error.into())
+            }
+        }
+    }
+}
+
+/// A `Transport` decorator which records every SPI, I2C, UART and GPIO call made through the
+/// wrapped transport.  The resulting file can be served by the `Replay` transport.
+///
+/// Recorded UARTs do not support nonblocking reads, so that consoles poll using `read_timeout`
+/// and every byte received is captured in the recording.
+pub struct RecordingTransport {
+    inner: Box<dyn Transport>,
+    recorder: Rc<Recorder>,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Box<dyn Transport>, path: &Path) -> Result<Self> {
+        Ok(Self {
+            inner,
+            recorder: Rc::new(Recorder::create(path)?),
+        })
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn capabilities(&self) -> Result<Capabilities> {
+        let result = self.inner.capabilities();
+        self.recorder.call(Request::GetCapabilities, result, |c| {
+            Response::GetCapabilities(Capabilities::new(c.capabilities))
+        })
+    }
+
+    fn apply_default_configuration(&self) -> Result<()> {
+        let result = self.inner.apply_default_configuration();
+        self.recorder
+            .call(Request::ApplyDefaultConfiguration, result, |_| {
+                Response::ApplyDefaultConfiguration
+            })
+    }
+
+    fn jtag(&self, opts: &JtagParams) -> Result<Box<dyn JtagChain + '_>> {
+        self.inner.jtag(opts)
+    }
+
+    fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
+        Ok(Rc::new(spi::RecordingSpi::new(
+            self.inner.spi(instance)?,
+            instance,
+            &self.recorder,
+        )))
+    }
+
+    fn i2c(&self, instance: &str) -> Result<Rc<dyn Bus>> {
+        Ok(Rc::new(i2c::RecordingI2c::new(
+            self.inner.i2c(instance)?,
+            instance,
+            &self.recorder,
+        )))
+    }
+
+    fn uart(&self, instance: &str) -> Result<Rc<dyn Uart>> {
+        Ok(Rc::new(uart::RecordingUart::new(
+            self.inner.uart(instance)?,
+            instance,
+            &self.recorder,
+        )))
+    }
+
+    fn gpio_pin(&self, instance: &str) -> Result<Rc<dyn GpioPin>> {
+        Ok(Rc::new(gpio::RecordingGpioPin::new(
+            self.inner.gpio_pin(instance)?,
+            instance,
+            &self.recorder,
+        )))
+    }
+
+    fn gpio_monitoring(&self) -> Result<Rc<dyn GpioMonitoring>> {
+        self.inner.gpio_monitoring()
+    }
+
+    fn gpio_bitbanging(&self) -> Result<Rc<dyn GpioBitbanging>> {
+        self.inner.gpio_bitbanging()
+    }
+
+    fn emulator(&self) -> Result<Rc<dyn Emulator>> {
+        self.inner.emulator()
+    }
+
+    fn proxy_ops(&self) -> Result<Rc<dyn ProxyOps>> {
+        self.inner.proxy_ops()
+    }
+
+    fn dispatch(&self, action: &dyn Any) -> Result<Option<Box<dyn serde_annotate::Annotate>>> {
+        self.inner.dispatch(action)
+    }
+
+    fn maintain_connection(&self) -> Result<Rc<dyn MaintainConnection>> {
+        self.inner.maintain_connection()
+    }
+
+    fn nonblocking_help(&self) -> Result<Rc<dyn NonblockingHelp>> {
+        self.inner.nonblocking_help()
+    }
+}
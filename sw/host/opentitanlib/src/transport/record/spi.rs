@@ -0,0 +1,242 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::Recorder;
+use crate::io::gpio::GpioPin;
+use crate::io::spi::{
+    AssertChipSelect, MaxSizes, Target, TargetChipDeassert, Transfer, TransferMode,
+};
+use crate::proxy::protocol::{
+    Request, Response, SpiRequest, SpiResponse, SpiTransferRequest, SpiTransferResponse,
+};
+use crate::util::voltage::Voltage;
+
+pub struct RecordingSpi {
+    inner: Rc<dyn Target>,
+    instance: String,
+    recorder: Rc<Recorder>,
+    // Chip select assertions of the underlying target, released in `deassert_cs()`.
+    asserted: RefCell<Vec<AssertChipSelect>>,
+}
+
+impl RecordingSpi {
+    pub fn new(inner: Rc<dyn Target>, instance: &str, recorder: &Rc<Recorder>) -> Self {
+        Self {
+            inner,
+            instance: instance.to_string(),
+            recorder: Rc::clone(recorder),
+            asserted: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn call<T>(
+        &self,
+        command: SpiRequest,
+        result: Result<T>,
+        response: impl FnOnce(&T) -> SpiResponse,
+    ) -> Result<T> {
+        let request = Request::Spi {
+            id: self.instance.clone(),
+            command,
+        };
+        self.recorder
+            .call(request, result, |v| Response::Spi(response(v)))
+    }
+}
+
+fn pin_name(pin: Option<&Rc<dyn GpioPin>>) -> Option<String> {
+    pin.and_then(|p| p.get_internal_pin_name())
+        .map(str::to_string)
+}
+
+impl Target for RecordingSpi {
+    fn get_transfer_mode(&self) -> Result<TransferMode> {
+        let result = self.inner.get_transfer_mode();
+        self.call(SpiRequest::GetTransferMode, result, |&mode| {
+            SpiResponse::GetTransferMode { mode }
+        })
+    }
+
+    fn set_transfer_mode(&self, mode: TransferMode) -> Result<()> {
+        let result = self.inner.set_transfer_mode(mode);
+        self.call(SpiRequest::SetTransferMode { mode }, result, |_| {
+            SpiResponse::SetTransferMode
+        })
+    }
+
+    fn get_bits_per_word(&self) -> Result<u32> {
+        let result = self.inner.get_bits_per_word();
+        self.call(SpiRequest::GetBitsPerWord, result, |&bits_per_word| {
+            SpiResponse::GetBitsPerWord { bits_per_word }
+        })
+    }
+
+    fn set_bits_per_word(&self, bits_per_word: u32) -> Result<()> {
+        let result = self.inner.set_bits_per_word(bits_per_word);
+        self.call(SpiRequest::SetBitsPerWord { bits_per_word }, result, |_| {
+            SpiResponse::SetBitsPerWord
+        })
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        let result = self.inner.get_max_speed();
+        self.call(SpiRequest::GetMaxSpeed, result, |&speed| {
+            SpiResponse::GetMaxSpeed { speed }
+        })
+    }
+
+    fn set_max_speed(&self, value: u32) -> Result<()> {
+        let result = self.inner.set_max_speed(value);
+        self.call(SpiRequest::SetMaxSpeed { value }, result, |_| {
+            SpiResponse::SetMaxSpeed
+        })
+    }
+
+    fn supports_bidirectional_transfer(&self) -> Result<bool> {
+        let result = self.inner.supports_bidirectional_transfer();
+        self.call(
+            SpiRequest::SupportsBidirectionalTransfer,
+            result,
+            |&has_support| SpiResponse::SupportsBidirectionalTransfer { has_support },
+        )
+    }
+
+    fn supports_tpm_poll(&self) -> Result<bool> {
+        let result = self.inner.supports_tpm_poll();
+        self.call(SpiRequest::SupportsTpmPoll, result, |&has_support| {
+            SpiResponse::SupportsTpmPoll { has_support }
+        })
+    }
+
+    fn set_pins(
+        &self,
+        serial_clock: Option<&Rc<dyn GpioPin>>,
+        host_out_device_in: Option<&Rc<dyn GpioPin>>,
+        host_in_device_out: Option<&Rc<dyn GpioPin>>,
+        chip_select: Option<&Rc<dyn GpioPin>>,
+        gsc_ready: Option<&Rc<dyn GpioPin>>,
+    ) -> Result<()> {
+        let request = SpiRequest::SetPins {
+            serial_clock: pin_name(serial_clock),
+            host_out_device_in: pin_name(host_out_device_in),
+            host_in_device_out: pin_name(host_in_device_out),
+            chip_select: pin_name(chip_select),
+            gsc_ready: pin_name(gsc_ready),
+        };
+        let result = self.inner.set_pins(
+            serial_clock,
+            host_out_device_in,
+            host_in_device_out,
+            chip_select,
+            gsc_ready,
+        );
+        self.call(request, result, |_| SpiResponse::SetPins)
+    }
+
+    fn get_max_transfer_count(&self) -> Result<usize> {
+        let result = self.inner.get_max_transfer_count();
+        self.call(SpiRequest::GetMaxTransferCount, result, |&number| {
+            SpiResponse::GetMaxTransferCount { number }
+        })
+    }
+
+    fn get_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        let result = self.inner.get_max_transfer_sizes();
+        self.call(SpiRequest::GetMaxTransferSizes, result, |&sizes| {
+            SpiResponse::GetMaxTransferSizes { sizes }
+        })
+    }
+
+    fn get_eeprom_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        let result = self.inner.get_eeprom_max_transfer_sizes();
+        self.call(SpiRequest::GetEepromMaxTransferSizes, result, |&sizes| {
+            SpiResponse::GetEepromMaxTransferSizes { sizes }
+        })
+    }
+
+    fn set_voltage(&self, voltage: Voltage) -> Result<()> {
+        let result = self.inner.set_voltage(voltage);
+        self.call(SpiRequest::SetVoltage { voltage }, result, |_| {
+            SpiResponse::SetVoltage
+        })
+    }
+
+    fn get_flashrom_programmer(&self) -> Result<String> {
+        let result = self.inner.get_flashrom_programmer();
+        self.call(SpiRequest::GetFlashromArgs, result, |programmer| {
+            SpiResponse::GetFlashromArgs {
+                programmer: programmer.clone(),
+            }
+        })
+    }
+
+    // EEPROM transactions are not overridden, so that they are recorded as the individual SPI
+    // transactions of the default implementation.
+    fn run_transaction(&self, transaction: &mut [Transfer]) -> Result<()> {
+        let request = transaction
+            .iter()
+            .map(|transfer| match transfer {
+                Transfer::Read(rbuf) => SpiTransferRequest::Read {
+                    len: rbuf.len() as u32,
+                },
+                Transfer::Write(wbuf) => SpiTransferRequest::Write {
+                    data: wbuf.to_vec(),
+                },
+                Transfer::Both(wbuf, _) => SpiTransferRequest::Both {
+                    data: wbuf.to_vec(),
+                },
+                Transfer::TpmPoll => SpiTransferRequest::TpmPoll,
+                Transfer::GscReady => SpiTransferRequest::GscReady,
+            })
+            .collect();
+        let result = self.inner.run_transaction(transaction);
+        let response = transaction
+            .iter()
+            .map(|transfer| match transfer {
+                Transfer::Read(rbuf) => SpiTransferResponse::Read {
+                    data: rbuf.to_vec(),
+                },
+                Transfer::Write(_) => SpiTransferResponse::Write,
+                Transfer::Both(_, rbuf) => SpiTransferResponse::Both {
+                    data: rbuf.to_vec(),
+                },
+                Transfer::TpmPoll => SpiTransferResponse::TpmPoll,
+                Transfer::GscReady => SpiTransferResponse::GscReady,
+            })
+            .collect();
+        self.call(
+            SpiRequest::RunTransaction {
+                transaction: request,
+            },
+            result,
+            |_| SpiResponse::RunTransaction {
+                transaction: response,
+            },
+        )
+    }
+
+    fn assert_cs(self: Rc<Self>) -> Result<AssertChipSelect> {
+        let result = Rc::clone(&self.inner).assert_cs();
+        let assertion = self.call(SpiRequest::AssertChipSelect, result, |_| {
+            SpiResponse::AssertChipSelect
+        })?;
+        self.asserted.borrow_mut().push(assertion);
+        Ok(AssertChipSelect::new(self))
+    }
+}
+
+impl TargetChipDeassert for RecordingSpi {
+    fn deassert_cs(&self) {
+        // Dropping the underlying assertion deasserts chip select on the underlying target.
+        let assertion = self.asserted.borrow_mut().pop();
+        drop(assertion);
+        let _ = self.call(SpiRequest::DeassertChipSelect, Ok(()), |_| {
+            SpiResponse::DeassertChipSelect
+        });
+    }
+}
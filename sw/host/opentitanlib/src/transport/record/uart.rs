@@ -0,0 +1,134 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::Recorder;
+use crate::io::uart::{FlowControl, Parity, Uart};
+use crate::proxy::protocol::{Request, Response, UartRequest, UartResponse};
+
+pub struct RecordingUart {
+    inner: Rc<dyn Uart>,
+    instance: String,
+    recorder: Rc<Recorder>,
+}
+
+impl RecordingUart {
+    pub fn new(inner: Rc<dyn Uart>, instance: &str, recorder: &Rc<Recorder>) -> Self {
+        Self {
+            inner,
+            instance: instance.to_string(),
+            recorder: Rc::clone(recorder),
+        }
+    }
+
+    fn call<T>(
+        &self,
+        command: UartRequest,
+        result: Result<T>,
+        response: impl FnOnce(&T) -> UartResponse,
+    ) -> Result<T> {
+        let request = Request::Uart {
+            id: self.instance.clone(),
+            command,
+        };
+        self.recorder
+            .call(request, result, |v| Response::Uart(response(v)))
+    }
+}
+
+impl Uart for RecordingUart {
+    fn get_baudrate(&self) -> Result<u32> {
+        let result = self.inner.get_baudrate();
+        self.call(UartRequest::GetBaudrate, result, |&rate| {
+            UartResponse::GetBaudrate { rate }
+        })
+    }
+
+    fn set_baudrate(&self, rate: u32) -> Result<()> {
+        let result = self.inner.set_baudrate(rate);
+        self.call(UartRequest::SetBaudrate { rate }, result, |_| {
+            UartResponse::SetBaudrate
+        })
+    }
+
+    fn get_flow_control(&self) -> Result<FlowControl> {
+        let result = self.inner.get_flow_control();
+        self.call(UartRequest::GetFlowControl, result, |&flow_control| {
+            UartResponse::GetFlowControl { flow_control }
+        })
+    }
+
+    fn set_flow_control(&self, flow_control: bool) -> Result<()> {
+        let result = self.inner.set_flow_control(flow_control);
+        self.call(UartRequest::SetFlowControl(flow_control), result, |_| {
+            UartResponse::SetFlowControl
+        })
+    }
+
+    fn get_device_path(&self) -> Result<String> {
+        let result = self.inner.get_device_path();
+        self.call(UartRequest::GetDevicePath, result, |path| {
+            UartResponse::GetDevicePath { path: path.clone() }
+        })
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let result = self.inner.read(buf);
+        self.call(
+            UartRequest::Read {
+                timeout_millis: None,
+                len: buf.len() as u32,
+            },
+            result,
+            |&n| UartResponse::Read {
+                data: buf[..n].to_vec(),
+            },
+        )
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let result = self.inner.read_timeout(buf, timeout);
+        self.call(
+            UartRequest::Read {
+                timeout_millis: Some(timeout.as_millis() as u32),
+                len: buf.len() as u32,
+            },
+            result,
+            |&n| UartResponse::Read {
+                data: buf[..n].to_vec(),
+            },
+        )
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        let result = self.inner.write(buf);
+        self.call(UartRequest::Write { data: buf.to_vec() }, result, |_| {
+            UartResponse::Write
+        })
+    }
+
+    fn set_break(&self, enable: bool) -> Result<()> {
+        let result = self.inner.set_break(enable);
+        self.call(UartRequest::SetBreak(enable), result, |_| {
+            UartResponse::SetBreak
+        })
+    }
+
+    fn set_parity(&self, parity: Parity) -> Result<()> {
+        let result = self.inner.set_parity(parity);
+        self.call(UartRequest::SetParity(parity), result, |_| {
+            UartResponse::SetParity
+        })
+    }
+
+    fn get_parity(&self) -> Result<Parity> {
+        let result = self.inner.get_parity();
+        self.call(UartRequest::GetParity, result, |&parity| {
+            UartResponse::GetParity { parity }
+        })
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, Result};
+use std::rc::Rc;
+
+use super::{Inner, Replay, ReplayError};
+use crate::io::gpio::{GpioPin, PinMode, PullMode};
+use crate::proxy::protocol::{GpioRequest, GpioResponse, Request, Response};
+
+pub struct ReplayGpioPin {
+    inner: Rc<Inner>,
+    pinname: String,
+}
+
+impl ReplayGpioPin {
+    pub fn open(replay: &Replay, pinname: &str) -> Self {
+        Self {
+            inner: Rc::clone(&replay.inner),
+            pinname: pinname.to_string(),
+        }
+    }
+
+    fn execute_command(&self, command: GpioRequest) -> Result<GpioResponse> {
+        match self.inner.execute_command(Request::Gpio {
+            id: self.pinname.clone(),
+            command,
+        })? {
+            Response::Gpio(resp) => Ok(resp),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+}
+
+impl GpioPin for ReplayGpioPin {
+    fn read(&self) -> Result<bool> {
+        match self.execute_command(GpioRequest::Read)? {
+            GpioResponse::Read { value } => Ok(value),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn write(&self, logic: bool) -> Result<()> {
+        match self.execute_command(GpioRequest::Write { logic })? {
+            GpioResponse::Write => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_mode(&self, mode: PinMode) -> Result<()> {
+        match self.execute_command(GpioRequest::SetMode { mode })? {
+            GpioResponse::SetMode => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_pull_mode(&self, pull: PullMode) -> Result<()> {
+        match self.execute_command(GpioRequest::SetPullMode { pull })? {
+            GpioResponse::SetPullMode => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn analog_read(&self) -> Result<f32> {
+        match self.execute_command(GpioRequest::AnalogRead)? {
+            GpioResponse::AnalogRead { value } => Ok(value),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn analog_write(&self, value: f32) -> Result<()> {
+        match self.execute_command(GpioRequest::AnalogWrite { value })? {
+            GpioResponse::AnalogWrite => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set(
+        &self,
+        mode: Option<PinMode>,
+        value: Option<bool>,
+        pull: Option<PullMode>,
+        analog_value: Option<f32>,
+    ) -> Result<()> {
+        match self.execute_command(GpioRequest::MultiSet {
+            mode,
+            value,
+            pull,
+            analog_value,
+        })? {
+            GpioResponse::MultiSet => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_internal_pin_name(&self) -> Option<&str> {
+        Some(&self.pinname)
+    }
+}
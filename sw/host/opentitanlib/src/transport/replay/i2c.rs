@@ -0,0 +1,153 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, ensure, Result};
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{Inner, Replay, ReplayError};
+use crate::io::gpio::GpioPin;
+use crate::io::i2c::{Bus, DeviceStatus, Mode, Transfer};
+use crate::proxy::protocol::{
+    I2cRequest, I2cResponse, I2cTransferRequest, I2cTransferResponse, Request, Response,
+};
+
+pub struct ReplayI2c {
+    inner: Rc<Inner>,
+    instance: String,
+}
+
+impl ReplayI2c {
+    pub fn open(replay: &Replay, instance: &str) -> Self {
+        Self {
+            inner: Rc::clone(&replay.inner),
+            instance: instance.to_string(),
+        }
+    }
+
+    fn execute_command(&self, command: I2cRequest) -> Result<I2cResponse> {
+        match self.inner.execute_command(Request::I2c {
+            id: self.instance.clone(),
+            command,
+        })? {
+            Response::I2c(resp) => Ok(resp),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+}
+
+fn pin_name(pin: Option<&Rc<dyn GpioPin>>) -> Option<String> {
+    pin.and_then(|p| p.get_internal_pin_name())
+        .map(str::to_string)
+}
+
+impl Bus for ReplayI2c {
+    fn set_mode(&self, mode: Mode) -> Result<()> {
+        match mode {
+            Mode::Host => match self.execute_command(I2cRequest::SetModeHost)? {
+                I2cResponse::SetModeHost => Ok(()),
+                _ => bail!(ReplayError::UnexpectedReply()),
+            },
+            Mode::Device(addr) => {
+                match self.execute_command(I2cRequest::SetModeDevice { addr })? {
+                    I2cResponse::SetModeDevice => Ok(()),
+                    _ => bail!(ReplayError::UnexpectedReply()),
+                }
+            }
+        }
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        match self.execute_command(I2cRequest::GetMaxSpeed)? {
+            I2cResponse::GetMaxSpeed { speed } => Ok(speed),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_max_speed(&self, value: u32) -> Result<()> {
+        match self.execute_command(I2cRequest::SetMaxSpeed { value })? {
+            I2cResponse::SetMaxSpeed => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_pins(
+        &self,
+        serial_clock: Option<&Rc<dyn GpioPin>>,
+        serial_data: Option<&Rc<dyn GpioPin>>,
+        gsc_ready: Option<&Rc<dyn GpioPin>>,
+    ) -> Result<()> {
+        match self.execute_command(I2cRequest::SetPins {
+            serial_clock: pin_name(serial_clock),
+            serial_data: pin_name(serial_data),
+            gsc_ready: pin_name(gsc_ready),
+        })? {
+            I2cResponse::SetPins => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    // Not part of the recording, see `RecordingI2c`.
+    fn set_default_address(&self, _addr: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn run_transaction(&self, address: Option<u8>, transaction: &mut [Transfer]) -> Result<()> {
+        let mut req: Vec<I2cTransferRequest> = Vec::new();
+        for transfer in transaction.iter() {
+            match transfer {
+                Transfer::Read(rbuf) => req.push(I2cTransferRequest::Read {
+                    len: rbuf.len() as u32,
+                }),
+                Transfer::Write(wbuf) => req.push(I2cTransferRequest::Write {
+                    data: wbuf.to_vec(),
+                }),
+                Transfer::GscReady => req.push(I2cTransferRequest::GscReady),
+            }
+        }
+        match self.execute_command(I2cRequest::RunTransaction {
+            address,
+            transaction: req,
+        })? {
+            I2cResponse::RunTransaction { transaction: resp } => {
+                ensure!(
+                    resp.len() == transaction.len(),
+                    ReplayError::UnexpectedReply()
+                );
+                for pair in resp.iter().zip(transaction.iter_mut()) {
+                    match pair {
+                        (I2cTransferResponse::Read { data }, Transfer::Read(rbuf)) => {
+                            ensure!(data.len() == rbuf.len(), ReplayError::UnexpectedReply());
+                            rbuf.clone_from_slice(data);
+                        }
+                        (I2cTransferResponse::Write, Transfer::Write(_)) => (),
+                        (I2cTransferResponse::GscReady, Transfer::GscReady) => (),
+                        _ => bail!(ReplayError::UnexpectedReply()),
+                    }
+                }
+                Ok(())
+            }
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_device_status(&self, timeout: Duration) -> Result<DeviceStatus> {
+        match self.execute_command(I2cRequest::GetDeviceStatus {
+            timeout_millis: timeout.as_millis() as u32,
+        })? {
+            I2cResponse::GetDeviceStatus { status } => Ok(status),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn prepare_read_data(&self, data: &[u8], sticky: bool) -> Result<()> {
+        match self.execute_command(I2cRequest::PrepareReadData {
+            data: data.to_vec(),
+            sticky,
+        })? {
+            I2cResponse::PrepareReadData => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+}
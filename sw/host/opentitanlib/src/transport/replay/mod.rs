@@ -0,0 +1,297 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::rc::Rc;
+use thiserror::Error;
+
+use crate::impl_serializable_error;
+use crate::io::gpio::GpioPin;
+use crate::io::i2c::Bus;
+use crate::io::spi::Target;
+use crate::io::uart::Uart;
+use crate::proxy::protocol::{Request, Response};
+use crate::transport::record::Record;
+use crate::transport::{Capabilities, Transport};
+
+mod gpio;
+mod i2c;
+mod spi;
+mod uart;
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum ReplayError {
+    #[error("Recording has no further calls on {0}")]
+    Exhausted(String),
+    #[error("Call on {0} does not match recording, expected {1}, got {2}")]
+    Mismatch(String, String, String),
+    #[error("Unexpected reply")]
+    UnexpectedReply(),
+}
+impl_serializable_error!(ReplayError);
+
+/// Implementation of the Transport trait which serves the responses from a recording made by
+/// `RecordingTransport`, without any hardware.
+///
+/// Calls are matched against the recording separately for each interface instance, in the
+/// order they were recorded, and any deviation from the recorded arguments is reported as an
+/// error.  The recording should be replayed using the same configuration files as were used
+/// when making it.
+pub struct Replay {
+    inner: Rc<Inner>,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let mut records: HashMap<String, VecDeque<Record>> = HashMap::new();
+        for (lineno, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Record = serde_json::from_str(&line)
+                .with_context(|| format!("{}:{}", path.display(), lineno + 1))?;
+            records
+                .entry(channel(&record.request))
+                .or_default()
+                .push_back(record);
+        }
+        Ok(Self {
+            inner: Rc::new(Inner {
+                records: RefCell::new(records),
+            }),
+        })
+    }
+}
+
+// Calls on different interface instances are matched independently.
+fn channel(request: &Request) -> String {
+    match request {
+        Request::Gpio { id, .. } => format!("gpio {id}"),
+        Request::Uart { id, .. } => format!("uart {id}"),
+        Request::Spi { id, .. } => format!("spi {id}"),
+        Request::I2c { id, .. } => format!("i2c {id}"),
+        _ => "transport".to_string(),
+    }
+}
+
+struct Inner {
+    records: RefCell<HashMap<String, VecDeque<Record>>>,
+}
+
+impl Inner {
+    /// Consumes the next recorded call on the interface targeted by `request`, verifying that
+    /// it was made with the same arguments, and returns its recorded outcome.
+    fn execute_command(&self, request: Request) -> Result<Response> {
+        let channel = channel(&request);
+        let Some(record) = self
+            .records
+            .borrow_mut()
+            .get_mut(&channel)
+            .and_then(VecDeque::pop_front)
+        else {
+            bail!(ReplayError::Exhausted(channel));
+        };
+        let expected = serde_json::to_string(&record.request)?;
+        let actual = serde_json::to_string(&request)?;
+        if expected != actual {
+            bail!(ReplayError::Mismatch(channel, expected, actual));
+        }
+        Ok(record.response?)
+    }
+
+    /// Consumes the next recorded call on the interface targeted by `request`, only if `pred`
+    /// accepts the recorded request in place of `request`.
+    fn execute_if(
+        &self,
+        request: &Request,
+        pred: impl FnOnce(&Request) -> bool,
+    ) -> Option<Result<Response>> {
+        let mut records = self.records.borrow_mut();
+        let queue = records.get_mut(&channel(request))?;
+        if !pred(&queue.front()?.request) {
+            return None;
+        }
+        let record = queue.pop_front()?;
+        Some(record.response.map_err(anyhow::Error::from))
+    }
+}
+
+impl Transport for Replay {
+    fn capabilities(&self) -> Result<Capabilities> {
+        match self.inner.execute_command(Request::GetCapabilities)? {
+            Response::GetCapabilities(capabilities) => Ok(capabilities),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn apply_default_configuration(&self) -> Result<()> {
+        match self
+            .inner
+            .execute_command(Request::ApplyDefaultConfiguration)?
+        {
+            Response::ApplyDefaultConfiguration => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
+        Ok(Rc::new(spi::ReplaySpi::open(self, instance)))
+    }
+
+    fn i2c(&self, instance: &str) -> Result<Rc<dyn Bus>> {
+        Ok(Rc::new(i2c::ReplayI2c::open(self, instance)))
+    }
+
+    fn uart(&self, instance: &str) -> Result<Rc<dyn Uart>> {
+        Ok(Rc::new(uart::ReplayUart::open(self, instance)))
+    }
+
+    fn gpio_pin(&self, instance: &str) -> Result<Rc<dyn GpioPin>> {
+        Ok(Rc::new(gpio::ReplayGpioPin::open(self, instance)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::spi::{AssertChipSelect, MaxSizes, Transfer, TransferMode};
+    use crate::transport::record::RecordingTransport;
+    use crate::transport::Capability;
+    use crate::util::tmpfilename;
+
+    // A minimal transport with one SPI target, which echoes inverted data back.
+    struct Echo;
+
+    struct EchoSpi;
+
+    impl Target for EchoSpi {
+        fn get_transfer_mode(&self) -> Result<TransferMode> {
+            Ok(TransferMode::Mode0)
+        }
+        fn set_transfer_mode(&self, _mode: TransferMode) -> Result<()> {
+            Ok(())
+        }
+        fn get_bits_per_word(&self) -> Result<u32> {
+            Ok(8)
+        }
+        fn set_bits_per_word(&self, _bits_per_word: u32) -> Result<()> {
+            Ok(())
+        }
+        fn get_max_speed(&self) -> Result<u32> {
+            Ok(1_000_000)
+        }
+        fn set_max_speed(&self, _max_speed: u32) -> Result<()> {
+            Ok(())
+        }
+        fn supports_bidirectional_transfer(&self) -> Result<bool> {
+            Ok(false)
+        }
+        fn supports_tpm_poll(&self) -> Result<bool> {
+            Ok(false)
+        }
+        fn get_max_transfer_count(&self) -> Result<usize> {
+            Ok(2)
+        }
+        fn get_max_transfer_sizes(&self) -> Result<MaxSizes> {
+            Ok(MaxSizes {
+                read: 256,
+                write: 256,
+            })
+        }
+        fn run_transaction(&self, transaction: &mut [Transfer]) -> Result<()> {
+            let mut last = Vec::new();
+            for transfer in transaction.iter_mut() {
+                match transfer {
+                    Transfer::Write(wbuf) => last = wbuf.to_vec(),
+                    Transfer::Read(rbuf) => {
+                        for (r, w) in rbuf.iter_mut().zip(last.iter()) {
+                            *r = !w;
+                        }
+                    }
+                    _ => bail!("unsupported"),
+                }
+            }
+            Ok(())
+        }
+        fn assert_cs(self: Rc<Self>) -> Result<AssertChipSelect> {
+            bail!("unsupported")
+        }
+    }
+
+    impl Transport for Echo {
+        fn capabilities(&self) -> Result<Capabilities> {
+            Ok(Capabilities::new(Capability::SPI))
+        }
+        fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
+            match instance {
+                "0" => Ok(Rc::new(EchoSpi)),
+                _ => bail!("no such instance {instance}"),
+            }
+        }
+    }
+
+    fn transact(transport: &dyn Transport, data: &[u8]) -> Result<Vec<u8>> {
+        let spi = transport.spi("0")?;
+        let mut rbuf = vec![0u8; data.len()];
+        spi.run_transaction(&mut [Transfer::Write(data), Transfer::Read(&mut rbuf)])?;
+        Ok(rbuf)
+    }
+
+    #[test]
+    fn test_record_replay() -> Result<()> {
+        let path = tmpfilename("replay.jsonl");
+        {
+            let recording = RecordingTransport::new(Box::new(Echo), Path::new(&path))?;
+            recording.capabilities()?.request(Capability::SPI).ok()?;
+            assert_eq!(transact(&recording, &[1, 2, 3])?, [0xfe, 0xfd, 0xfc]);
+            assert_eq!(recording.spi("0")?.get_max_speed()?, 1_000_000);
+            // Failures are recorded and replayed as well.
+            assert!(recording.spi("0")?.assert_cs().is_err());
+        }
+
+        let replay = Replay::open(Path::new(&path))?;
+        replay.capabilities()?.request(Capability::SPI).ok()?;
+        assert_eq!(transact(&replay, &[1, 2, 3])?, [0xfe, 0xfd, 0xfc]);
+        assert_eq!(replay.spi("0")?.get_max_speed()?, 1_000_000);
+        let err = replay.spi("0")?.assert_cs().err().unwrap();
+        assert_eq!(err.to_string(), "unsupported");
+        // The recording is exhausted.
+        let err = replay.spi("0")?.get_max_speed().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ReplayError>(),
+            Some(ReplayError::Exhausted(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_mismatch() -> Result<()> {
+        let path = tmpfilename("replay_mismatch.jsonl");
+        {
+            let recording = RecordingTransport::new(Box::new(Echo), Path::new(&path))?;
+            transact(&recording, &[1, 2, 3])?;
+        }
+        let replay = Replay::open(Path::new(&path))?;
+        let err = transact(&replay, &[1, 2, 4]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ReplayError>(),
+            Some(ReplayError::Mismatch(..))
+        ));
+        // Instances which were never used are reported as exhausted.
+        let err = replay.uart("console")?.write(b"x").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ReplayError>(),
+            Some(ReplayError::Exhausted(_))
+        ));
+        Ok(())
+    }
+}
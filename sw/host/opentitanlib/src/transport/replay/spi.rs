@@ -0,0 +1,225 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, ensure, Result};
+use std::rc::Rc;
+
+use super::{Inner, Replay, ReplayError};
+use crate::io::gpio::GpioPin;
+use crate::io::spi::{
+    AssertChipSelect, MaxSizes, SpiError, Target, TargetChipDeassert, Transfer, TransferMode,
+};
+use crate::proxy::protocol::{
+    Request, Response, SpiRequest, SpiResponse, SpiTransferRequest, SpiTransferResponse,
+};
+use crate::util::voltage::Voltage;
+
+pub struct ReplaySpi {
+    inner: Rc<Inner>,
+    instance: String,
+}
+
+impl ReplaySpi {
+    pub fn open(replay: &Replay, instance: &str) -> Self {
+        Self {
+            inner: Rc::clone(&replay.inner),
+            instance: instance.to_string(),
+        }
+    }
+
+    fn execute_command(&self, command: SpiRequest) -> Result<SpiResponse> {
+        match self.inner.execute_command(Request::Spi {
+            id: self.instance.clone(),
+            command,
+        })? {
+            Response::Spi(resp) => Ok(resp),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+}
+
+fn pin_name(pin: Option<&Rc<dyn GpioPin>>) -> Option<String> {
+    pin.and_then(|p| p.get_internal_pin_name())
+        .map(str::to_string)
+}
+
+impl Target for ReplaySpi {
+    fn get_transfer_mode(&self) -> Result<TransferMode> {
+        match self.execute_command(SpiRequest::GetTransferMode)? {
+            SpiResponse::GetTransferMode { mode } => Ok(mode),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_transfer_mode(&self, mode: TransferMode) -> Result<()> {
+        match self.execute_command(SpiRequest::SetTransferMode { mode })? {
+            SpiResponse::SetTransferMode => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_bits_per_word(&self) -> Result<u32> {
+        match self.execute_command(SpiRequest::GetBitsPerWord)? {
+            SpiResponse::GetBitsPerWord { bits_per_word } => Ok(bits_per_word),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_bits_per_word(&self, bits_per_word: u32) -> Result<()> {
+        match self.execute_command(SpiRequest::SetBitsPerWord { bits_per_word })? {
+            SpiResponse::SetBitsPerWord => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        match self.execute_command(SpiRequest::GetMaxSpeed)? {
+            SpiResponse::GetMaxSpeed { speed } => Ok(speed),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_max_speed(&self, value: u32) -> Result<()> {
+        match self.execute_command(SpiRequest::SetMaxSpeed { value })? {
+            SpiResponse::SetMaxSpeed => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn supports_bidirectional_transfer(&self) -> Result<bool> {
+        match self.execute_command(SpiRequest::SupportsBidirectionalTransfer)? {
+            SpiResponse::SupportsBidirectionalTransfer { has_support } => Ok(has_support),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn supports_tpm_poll(&self) -> Result<bool> {
+        match self.execute_command(SpiRequest::SupportsTpmPoll)? {
+            SpiResponse::SupportsTpmPoll { has_support } => Ok(has_support),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_pins(
+        &self,
+        serial_clock: Option<&Rc<dyn GpioPin>>,
+        host_out_device_in: Option<&Rc<dyn GpioPin>>,
+        host_in_device_out: Option<&Rc<dyn GpioPin>>,
+        chip_select: Option<&Rc<dyn GpioPin>>,
+        gsc_ready: Option<&Rc<dyn GpioPin>>,
+    ) -> Result<()> {
+        match self.execute_command(SpiRequest::SetPins {
+            serial_clock: pin_name(serial_clock),
+            host_out_device_in: pin_name(host_out_device_in),
+            host_in_device_out: pin_name(host_in_device_out),
+            chip_select: pin_name(chip_select),
+            gsc_ready: pin_name(gsc_ready),
+        })? {
+            SpiResponse::SetPins => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_max_transfer_count(&self) -> Result<usize> {
+        match self.execute_command(SpiRequest::GetMaxTransferCount)? {
+            SpiResponse::GetMaxTransferCount { number } => Ok(number),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        match self.execute_command(SpiRequest::GetMaxTransferSizes)? {
+            SpiResponse::GetMaxTransferSizes { sizes } => Ok(sizes),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_eeprom_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        match self.execute_command(SpiRequest::GetEepromMaxTransferSizes)? {
+            SpiResponse::GetEepromMaxTransferSizes { sizes } => Ok(sizes),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_voltage(&self, voltage: Voltage) -> Result<()> {
+        match self.execute_command(SpiRequest::SetVoltage { voltage })? {
+            SpiResponse::SetVoltage => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_flashrom_programmer(&self) -> Result<String> {
+        match self.execute_command(SpiRequest::GetFlashromArgs)? {
+            SpiResponse::GetFlashromArgs { programmer } => Ok(programmer),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn run_transaction(&self, transaction: &mut [Transfer]) -> Result<()> {
+        let mut req: Vec<SpiTransferRequest> = Vec::new();
+        for transfer in transaction.iter() {
+            match transfer {
+                Transfer::Read(rbuf) => req.push(SpiTransferRequest::Read {
+                    len: rbuf.len() as u32,
+                }),
+                Transfer::Write(wbuf) => req.push(SpiTransferRequest::Write {
+                    data: wbuf.to_vec(),
+                }),
+                Transfer::Both(wbuf, rbuf) => {
+                    ensure!(
+                        rbuf.len() == wbuf.len(),
+                        SpiError::MismatchedDataLength(wbuf.len(), rbuf.len())
+                    );
+                    req.push(SpiTransferRequest::Both {
+                        data: wbuf.to_vec(),
+                    })
+                }
+                Transfer::TpmPoll => req.push(SpiTransferRequest::TpmPoll),
+                Transfer::GscReady => req.push(SpiTransferRequest::GscReady),
+            }
+        }
+        match self.execute_command(SpiRequest::RunTransaction { transaction: req })? {
+            SpiResponse::RunTransaction { transaction: resp } => {
+                ensure!(
+                    resp.len() == transaction.len(),
+                    ReplayError::UnexpectedReply()
+                );
+                for pair in resp.iter().zip(transaction.iter_mut()) {
+                    match pair {
+                        (SpiTransferResponse::Read { data }, Transfer::Read(rbuf))
+                        | (SpiTransferResponse::Both { data }, Transfer::Both(_, rbuf)) => {
+                            ensure!(data.len() == rbuf.len(), ReplayError::UnexpectedReply());
+                            rbuf.clone_from_slice(data);
+                        }
+                        (SpiTransferResponse::Write, Transfer::Write(_)) => (),
+                        (SpiTransferResponse::TpmPoll, Transfer::TpmPoll) => (),
+                        (SpiTransferResponse::GscReady, Transfer::GscReady) => (),
+                        _ => bail!(ReplayError::UnexpectedReply()),
+                    }
+                }
+                Ok(())
+            }
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn assert_cs(self: Rc<Self>) -> Result<AssertChipSelect> {
+        match self.execute_command(SpiRequest::AssertChipSelect)? {
+            SpiResponse::AssertChipSelect => Ok(AssertChipSelect::new(self)),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+}
+
+impl TargetChipDeassert for ReplaySpi {
+    fn deassert_cs(&self) {
+        match self
+            .execute_command(SpiRequest::DeassertChipSelect)
+            .expect("Error deactivating chip select")
+        {
+            SpiResponse::DeassertChipSelect => (),
+            _ => panic!("Error deactivating chip select"),
+        }
+    }
+}
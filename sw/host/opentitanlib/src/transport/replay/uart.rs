@@ -0,0 +1,152 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, ensure, Result};
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{Inner, Replay, ReplayError};
+use crate::io::uart::{FlowControl, Parity, Uart};
+use crate::proxy::protocol::{Request, Response, UartRequest, UartResponse};
+
+pub struct ReplayUart {
+    inner: Rc<Inner>,
+    instance: String,
+}
+
+impl ReplayUart {
+    pub fn open(replay: &Replay, instance: &str) -> Self {
+        Self {
+            inner: Rc::clone(&replay.inner),
+            instance: instance.to_string(),
+        }
+    }
+
+    fn execute_command(&self, command: UartRequest) -> Result<UartResponse> {
+        match self.inner.execute_command(Request::Uart {
+            id: self.instance.clone(),
+            command,
+        })? {
+            Response::Uart(resp) => Ok(resp),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+}
+
+fn copy_data(resp: UartResponse, buf: &mut [u8]) -> Result<usize> {
+    match resp {
+        UartResponse::Read { data } => {
+            ensure!(data.len() <= buf.len(), ReplayError::UnexpectedReply());
+            buf[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+        }
+        _ => bail!(ReplayError::UnexpectedReply()),
+    }
+}
+
+impl Uart for ReplayUart {
+    fn get_baudrate(&self) -> Result<u32> {
+        match self.execute_command(UartRequest::GetBaudrate)? {
+            UartResponse::GetBaudrate { rate } => Ok(rate),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_baudrate(&self, rate: u32) -> Result<()> {
+        match self.execute_command(UartRequest::SetBaudrate { rate })? {
+            UartResponse::SetBaudrate => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_flow_control(&self) -> Result<FlowControl> {
+        match self.execute_command(UartRequest::GetFlowControl)? {
+            UartResponse::GetFlowControl { flow_control } => Ok(flow_control),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_flow_control(&self, flow_control: bool) -> Result<()> {
+        match self.execute_command(UartRequest::SetFlowControl(flow_control))? {
+            UartResponse::SetFlowControl => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_device_path(&self) -> Result<String> {
+        match self.execute_command(UartRequest::GetDevicePath)? {
+            UartResponse::GetDevicePath { path } => Ok(path),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let resp = self.execute_command(UartRequest::Read {
+            timeout_millis: None,
+            len: buf.len() as u32,
+        })?;
+        copy_data(resp, buf)
+    }
+
+    /// Timed reads are polled by console code until some deadline, so their number and
+    /// timeouts depend on the timing of the original run.  Any recorded timed read of the same
+    /// length satisfies the call, and if the next recorded call is something else, the read
+    /// times out without data.
+    fn read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let len = buf.len() as u32;
+        let request = Request::Uart {
+            id: self.instance.clone(),
+            command: UartRequest::Read {
+                timeout_millis: Some(timeout.as_millis() as u32),
+                len,
+            },
+        };
+        let next = self.inner.execute_if(&request, |recorded| {
+            matches!(
+                recorded,
+                Request::Uart {
+                    command: UartRequest::Read {
+                        timeout_millis: Some(_),
+                        len: l,
+                    },
+                    ..
+                } if *l == len
+            )
+        });
+        match next {
+            Some(Ok(Response::Uart(resp))) => copy_data(resp, buf),
+            Some(Ok(_)) => bail!(ReplayError::UnexpectedReply()),
+            Some(Err(e)) => Err(e),
+            None => Ok(0),
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        match self.execute_command(UartRequest::Write { data: buf.to_vec() })? {
+            UartResponse::Write => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_break(&self, enable: bool) -> Result<()> {
+        match self.execute_command(UartRequest::SetBreak(enable))? {
+            UartResponse::SetBreak => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn set_parity(&self, parity: Parity) -> Result<()> {
+        match self.execute_command(UartRequest::SetParity(parity))? {
+            UartResponse::SetParity => Ok(()),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+
+    fn get_parity(&self) -> Result<Parity> {
+        match self.execute_command(UartRequest::GetParity)? {
+            UartResponse::GetParity { parity } => Ok(parity),
+            _ => bail!(ReplayError::UnexpectedReply()),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::rc::Rc;
+
+use super::{Inner, RESET_PIN};
+use crate::io::gpio::{GpioError, GpioPin, PinMode, PullMode};
+use crate::transport::TransportError;
+
+/// Host side configuration of a simulated pin.
+#[derive(Clone, Copy)]
+pub(super) struct PinState {
+    mode: PinMode,
+    pull: PullMode,
+    value: bool,
+}
+
+impl Default for PinState {
+    fn default() -> Self {
+        Self {
+            mode: PinMode::Input,
+            pull: PullMode::None,
+            value: false,
+        }
+    }
+}
+
+impl PinState {
+    /// Logic level on the pin, taking into account weak pulls when the host is not driving it.
+    /// The reset pin has a pull-up on the simulated board, other pins float low.
+    pub(super) fn level(&self, name: &str) -> bool {
+        let released = match self.pull {
+            PullMode::PullUp => true,
+            PullMode::PullDown => false,
+            PullMode::None => name == RESET_PIN,
+        };
+        match self.mode {
+            PinMode::PushPull | PinMode::Alternate => self.value,
+            PinMode::OpenDrain => self.value && released,
+            _ => released,
+        }
+    }
+}
+
+pub struct SimGpioPin {
+    inner: Rc<Inner>,
+    pinname: String,
+}
+
+impl SimGpioPin {
+    pub(super) fn new(inner: &Rc<Inner>, pinname: &str) -> Self {
+        Self {
+            inner: Rc::clone(inner),
+            pinname: pinname.to_string(),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut PinState)) {
+        let mut pins = self.inner.pins.borrow_mut();
+        let state = pins.entry(self.pinname.clone()).or_default();
+        let before = state.level(&self.pinname);
+        f(state);
+        let after = state.level(&self.pinname);
+        drop(pins);
+        if self.pinname == RESET_PIN && !before && after {
+            self.inner.boot();
+        }
+    }
+}
+
+impl GpioPin for SimGpioPin {
+    fn read(&self) -> Result<bool> {
+        Ok(self
+            .inner
+            .pins
+            .borrow()
+            .get(&self.pinname)
+            .copied()
+            .unwrap_or_default()
+            .level(&self.pinname))
+    }
+
+    fn write(&self, value: bool) -> Result<()> {
+        self.update(|state| state.value = value);
+        Ok(())
+    }
+
+    fn set_mode(&self, mode: PinMode) -> Result<()> {
+        self.set(Some(mode), None, None, None)
+    }
+
+    fn set_pull_mode(&self, pull: PullMode) -> Result<()> {
+        self.update(|state| state.pull = pull);
+        Ok(())
+    }
+
+    /// Atomically sets mode, value, and weak pull, such that the reset pin sees at most one
+    /// edge.
+    fn set(
+        &self,
+        mode: Option<PinMode>,
+        value: Option<bool>,
+        pull: Option<PullMode>,
+        analog_value: Option<f32>,
+    ) -> Result<()> {
+        if analog_value.is_some() {
+            return Err(TransportError::UnsupportedOperation.into());
+        }
+        match mode {
+            None
+            | Some(PinMode::Input)
+            | Some(PinMode::PushPull)
+            | Some(PinMode::OpenDrain)
+            | Some(PinMode::Alternate) => (),
+            Some(mode) => return Err(GpioError::UnsupportedPinMode(mode).into()),
+        }
+        self.update(|state| {
+            state.mode = mode.unwrap_or(state.mode);
+            state.value = value.unwrap_or(state.value);
+            state.pull = pull.unwrap_or(state.pull);
+        });
+        Ok(())
+    }
+
+    fn get_internal_pin_name(&self) -> Option<&str> {
+        Some(&self.pinname)
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, ensure, Result};
+use std::cell::{Cell, RefCell};
+
+use super::SimError;
+use crate::io::i2c::{Bus, I2cError, Transfer};
+use crate::transport::TransportError;
+
+/// A simulated I2C bus with a single target device: a 256-byte register file in the style of a
+/// small EEPROM.  The first byte of a write selects the register, further bytes are written to
+/// consecutive registers, and reads continue from the currently selected register.
+pub struct SimI2c {
+    registers: RefCell<[u8; 256]>,
+    pointer: Cell<u8>,
+    default_address: Cell<Option<u8>>,
+    max_speed: Cell<u32>,
+}
+
+impl SimI2c {
+    /// 7-bit address of the target device.
+    pub const ADDRESS: u8 = 0x50;
+
+    pub(super) fn new() -> Self {
+        Self {
+            registers: RefCell::new([0u8; 256]),
+            pointer: Cell::new(0),
+            default_address: Cell::new(None),
+            max_speed: Cell::new(100_000),
+        }
+    }
+
+    /// Returns the contents of the register file.
+    pub fn registers(&self) -> [u8; 256] {
+        *self.registers.borrow()
+    }
+
+    /// Overwrites registers starting at `offset`, bypassing the bus.
+    pub fn load(&self, offset: u8, data: &[u8]) {
+        let mut registers = self.registers.borrow_mut();
+        for (i, &byte) in data.iter().enumerate() {
+            registers[offset.wrapping_add(i as u8) as usize] = byte;
+        }
+    }
+
+    fn next_pointer(&self) -> usize {
+        let pointer = self.pointer.get();
+        self.pointer.set(pointer.wrapping_add(1));
+        pointer as usize
+    }
+}
+
+impl Bus for SimI2c {
+    fn get_max_speed(&self) -> Result<u32> {
+        Ok(self.max_speed.get())
+    }
+
+    fn set_max_speed(&self, max_speed: u32) -> Result<()> {
+        self.max_speed.set(max_speed);
+        Ok(())
+    }
+
+    fn set_default_address(&self, addr: u8) -> Result<()> {
+        self.default_address.set(Some(addr));
+        Ok(())
+    }
+
+    fn run_transaction(&self, addr: Option<u8>, transaction: &mut [Transfer]) -> Result<()> {
+        let addr = addr
+            .or(self.default_address.get())
+            .ok_or(I2cError::MissingAddress)?;
+        ensure!(addr == Self::ADDRESS, SimError::I2cNack(addr));
+        for transfer in transaction.iter_mut() {
+            match transfer {
+                Transfer::Write(wbuf) => {
+                    if let Some((&pointer, data)) = wbuf.split_first() {
+                        self.pointer.set(pointer);
+                        let mut registers = self.registers.borrow_mut();
+                        for &byte in data {
+                            registers[self.next_pointer()] = byte;
+                        }
+                    }
+                }
+                Transfer::Read(rbuf) => {
+                    let registers = self.registers.borrow();
+                    for byte in rbuf.iter_mut() {
+                        *byte = registers[self.next_pointer()];
+                    }
+                }
+                Transfer::GscReady => bail!(TransportError::UnsupportedOperation),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,455 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! In-process simulation of a minimal OpenTitan test bench, for exercising host-side code paths
+//! such as bootstrap, console interaction and SPI flash programming in unit tests.
+//!
+//! The simulated bench consists of:
+//! - GPIO pins, created on first use.  Deasserting the active-low `RESET` pin "boots" the chip,
+//!   which samples the `IOC0`..`IOC2` software straps and reports on the console.
+//! - SPI target `0`, a serial NOR flash speaking the JEDEC command set and serving an SFDP table.
+//! - UART `0`, a console which can loop back data or answer scripted prompts.
+//! - I2C bus `0`, with a register-file target device.
+//!
+//! The builtin `opentitan_sim.json` configuration maps the usual OpenTitan names onto these.
+
+use anyhow::Result;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use thiserror::Error;
+
+use crate::io::gpio::GpioPin;
+use crate::io::i2c::Bus;
+use crate::io::spi::Target;
+use crate::io::uart::Uart;
+use crate::transport::{
+    Capabilities, Capability, Transport, TransportError, TransportInterfaceType,
+};
+
+mod gpio;
+mod i2c;
+mod spi;
+mod uart;
+
+pub use gpio::SimGpioPin;
+pub use i2c::SimI2c;
+pub use spi::SimFlash;
+pub use uart::SimUart;
+
+/// Name of the active-low reset pin.
+pub const RESET_PIN: &str = "RESET";
+/// Names of the software strap pins sampled when coming out of reset.
+pub const SW_STRAP_PINS: [&str; 3] = ["IOC0", "IOC1", "IOC2"];
+
+/// Console output of the simulated ROM when the bootstrap strapping is applied during reset.
+pub const BOOT_MESSAGE_BOOTSTRAP: &str = "ROM: bootstrap\r\n";
+/// Console output of the simulated ROM when booting normally.
+pub const BOOT_MESSAGE_FLASH: &str = "ROM: boot\r\n";
+
+/// Misuse of the simulated devices, which real hardware would typically silently ignore.
+#[derive(Debug, Error)]
+pub enum SimError {
+    #[error("SPI flash opcode {0:#04x} not supported")]
+    UnsupportedOpcode(u8),
+    #[error("SPI flash opcode {0:#04x} issued while busy")]
+    FlashBusy(u8),
+    #[error("SPI flash opcode {0:#04x} expects {1} address bytes, got {2}")]
+    AddressLength(u8, usize, usize),
+    #[error("No I2C device at address {0:#04x}")]
+    I2cNack(u8),
+}
+
+/// Implementation of the Transport trait backed by an in-process simulated test bench.
+///
+/// `Sim` is a cheap handle, clones of which share the simulated state.  A test can keep a
+/// clone for inspecting and scripting the bench, after handing another one to
+/// `TransportWrapperBuilder::build()`.
+#[derive(Clone)]
+pub struct Sim {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    pins: RefCell<HashMap<String, gpio::PinState>>,
+    flash: Rc<SimFlash>,
+    console: Rc<SimUart>,
+    i2c: Rc<SimI2c>,
+    boot_count: Cell<usize>,
+    bootstrap: Cell<bool>,
+}
+
+impl Inner {
+    /// Called whenever the reset pin is released.  Samples the straps, like the ROM would.
+    fn boot(&self) {
+        let pins = self.pins.borrow();
+        let bootstrap = SW_STRAP_PINS
+            .iter()
+            .all(|&name| pins.get(name).map(|p| p.level(name)).unwrap_or(false));
+        log::debug!("Simulated chip out of reset, bootstrap: {bootstrap}");
+        self.boot_count.set(self.boot_count.get() + 1);
+        self.bootstrap.set(bootstrap);
+        self.flash.reset();
+        self.console.inject(if bootstrap {
+            BOOT_MESSAGE_BOOTSTRAP.as_bytes()
+        } else {
+            BOOT_MESSAGE_FLASH.as_bytes()
+        });
+    }
+}
+
+impl Sim {
+    pub fn new() -> Self {
+        Self {
+            inner: Rc::new(Inner {
+                pins: RefCell::new(HashMap::new()),
+                flash: Rc::new(SimFlash::new()),
+                console: Rc::new(SimUart::new()),
+                i2c: Rc::new(SimI2c::new()),
+                boot_count: Cell::new(0),
+                bootstrap: Cell::new(false),
+            }),
+        }
+    }
+
+    /// The simulated SPI flash.
+    pub fn flash(&self) -> Rc<SimFlash> {
+        Rc::clone(&self.inner.flash)
+    }
+
+    /// The simulated console UART.
+    pub fn console(&self) -> Rc<SimUart> {
+        Rc::clone(&self.inner.console)
+    }
+
+    /// The simulated I2C bus.
+    pub fn i2c(&self) -> Rc<SimI2c> {
+        Rc::clone(&self.inner.i2c)
+    }
+
+    /// Current logic level of the named pin, as seen by the simulated chip.
+    pub fn pin_level(&self, name: &str) -> bool {
+        self.inner
+            .pins
+            .borrow()
+            .get(name)
+            .map(|p| p.level(name))
+            .unwrap_or_else(|| gpio::PinState::default().level(name))
+    }
+
+    /// Number of times the simulated chip has come out of reset.
+    pub fn boot_count(&self) -> usize {
+        self.inner.boot_count.get()
+    }
+
+    /// Whether the bootstrap strapping was applied the last time the chip came out of reset.
+    pub fn in_bootstrap(&self) -> bool {
+        self.inner.bootstrap.get()
+    }
+}
+
+impl Default for Sim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for Sim {
+    fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities::new(
+            Capability::GPIO
+                | Capability::UART
+                | Capability::SPI
+                | Capability::SPI_DUAL
+                | Capability::SPI_QUAD
+                | Capability::I2C,
+        ))
+    }
+
+    fn gpio_pin(&self, instance: &str) -> Result<Rc<dyn GpioPin>> {
+        Ok(Rc::new(SimGpioPin::new(&self.inner, instance)))
+    }
+
+    fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
+        match instance {
+            "0" => Ok(self.flash()),
+            _ => Err(TransportError::InvalidInstance(
+                TransportInterfaceType::Spi,
+                instance.to_string(),
+            )
+            .into()),
+        }
+    }
+
+    fn uart(&self, instance: &str) -> Result<Rc<dyn Uart>> {
+        match instance {
+            "0" => Ok(self.console()),
+            _ => Err(TransportError::InvalidInstance(
+                TransportInterfaceType::Uart,
+                instance.to_string(),
+            )
+            .into()),
+        }
+    }
+
+    fn i2c(&self, instance: &str) -> Result<Rc<dyn Bus>> {
+        match instance {
+            "0" => Ok(self.i2c()),
+            _ => Err(TransportError::InvalidInstance(
+                TransportInterfaceType::I2c,
+                instance.to_string(),
+            )
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use crate::app::config::process_config_file;
+    use crate::app::{TransportWrapper, TransportWrapperBuilder};
+    use crate::bootstrap::{Bootstrap, BootstrapOptions};
+    use crate::io::eeprom::AddressMode;
+    use crate::io::i2c::Transfer;
+    use crate::spiflash::{ProgramMode, ProgramStats, ReadMode, SpiFlash};
+    use crate::uart::console::UartConsole;
+
+    fn transport(sim: &Sim) -> Result<TransportWrapper> {
+        let mut builder = TransportWrapperBuilder::new("sim".to_string(), false);
+        process_config_file(&mut builder, Path::new("/__builtin__/opentitan_sim.json"))?;
+        let transport = builder.build(Box::new(sim.clone()))?;
+        transport.apply_default_configuration(None)?;
+        Ok(transport)
+    }
+
+    #[test]
+    fn test_spiflash() -> Result<()> {
+        let sim = Sim::new();
+        let transport = transport(&sim)?;
+        let spi = transport.spi("BOOTSTRAP")?;
+
+        assert_eq!(SpiFlash::read_jedec_id(&*spi, 3)?, SimFlash::JEDEC_ID);
+        let mut flash = SpiFlash::from_spi(&*spi)?;
+        assert_eq!(flash.size, SimFlash::SIZE);
+
+        let data = (0..10000).map(|i| i as u8).collect::<Vec<u8>>();
+        flash.erase(&*spi, 0x1000, 0x3000)?;
+        flash.program(&*spi, 0x1010, &data)?;
+        assert_eq!(sim.flash().contents(0x1010, data.len()), data);
+        let mut buf = vec![0u8; data.len()];
+        flash.read_mode = ReadMode::Quad;
+        flash.read(&*spi, 0x1010, &mut buf)?;
+        assert_eq!(buf, data);
+
+        // Addresses beyond 16MiB require 4-byte addressing.
+        flash.set_address_mode(&*spi, AddressMode::Mode4b)?;
+        assert!(sim.flash().is_4b_mode());
+        flash.program(&*spi, 0x0200_0000, b"hello")?;
+        flash.read_mode = ReadMode::Fast;
+        flash.read(&*spi, 0x0200_0000, &mut buf[..5])?;
+        assert_eq!(&buf[..5], b"hello");
+
+        // Commands with the wrong address length are rejected.
+        flash.address_mode = AddressMode::Mode3b;
+        assert!(flash.read(&*spi, 0, &mut buf[..5]).is_err());
+
+        // The quad page program takes a 4-byte address in either addressing mode.
+        flash.set_address_mode(&*spi, AddressMode::Mode3b)?;
+        flash.set_program_mode(&*spi, ProgramMode::Quad)?;
+        flash.program(&*spi, 0x0300_0000, b"quad")?;
+        assert_eq!(sim.flash().contents(0x0300_0000, 4), b"quad");
+        Ok(())
+    }
+
+    #[test]
+    fn test_spiflash_quad_enable() -> Result<()> {
+        let sim = Sim::new();
+        let transport = transport(&sim)?;
+        let spi = transport.spi("BOOTSTRAP")?;
+        let capabilities = transport.capabilities()?;
+        let mut flash = SpiFlash::from_spi(&*spi)?;
+
+        // Quad mode is only selected automatically once the quad enable bit is set, which is
+        // only written when it changes.
+        flash.set_program_mode_auto(&*spi, &capabilities)?;
+        assert_eq!(flash.program_mode, ProgramMode::Standard);
+        assert_eq!(sim.flash().status_writes(), 0);
+        flash.set_program_mode(&*spi, ProgramMode::Quad)?;
+        assert_eq!(sim.flash().status(), [0x40, 0]);
+        assert_eq!(sim.flash().status_writes(), 1);
+        flash.set_program_mode_auto(&*spi, &capabilities)?;
+        assert_eq!(flash.program_mode, ProgramMode::Quad);
+        assert_eq!(sim.flash().status_writes(), 1);
+        flash.set_quad_enable(&*spi, false)?;
+        assert_eq!(sim.flash().status(), [0, 0]);
+        assert_eq!(sim.flash().status_writes(), 2);
+
+        // With the quad enable bit in status register 2, its other bits are preserved.
+        let rev_b = flash.sfdp.as_mut().unwrap().jedec.rev_b.as_mut().unwrap();
+        rev_b.quad_enable_requirements = 1;
+        sim.flash().load_status([0x1c, 0x40]);
+        assert_eq!(flash.quad_enabled(&*spi)?, Some(false));
+        flash.set_program_mode(&*spi, ProgramMode::Quad)?;
+        assert_eq!(sim.flash().status(), [0x1c, 0x42]);
+        assert_eq!(sim.flash().status_writes(), 3);
+        flash.set_quad_enable(&*spi, true)?;
+        assert_eq!(sim.flash().status_writes(), 3);
+
+        // Without a way to read status register 2, quad mode must be requested explicitly.
+        let rev_b = flash.sfdp.as_mut().unwrap().jedec.rev_b.as_mut().unwrap();
+        rev_b.quad_enable_requirements = 4;
+        assert_eq!(flash.quad_enabled(&*spi)?, None);
+        flash.set_program_mode_auto(&*spi, &capabilities)?;
+        assert_eq!(flash.program_mode, ProgramMode::Standard);
+        assert_eq!(sim.flash().status_writes(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spiflash_differential() -> Result<()> {
+        let sim = Sim::new();
+        let transport = transport(&sim)?;
+        let spi = transport.spi("BOOTSTRAP")?;
+        let mut flash = SpiFlash::from_spi(&*spi)?;
+
+        // The first sector already holds the data, the second is blank and the third needs
+        // erasing, which must preserve the data beyond the end of the buffer.
+        sim.flash().load(0x10000, &[0x5a; 0x1000]);
+        sim.flash().load(0x12000, &[0x00; 0x800]);
+        sim.flash().load(0x12800, &[0xa5; 0x800]);
+        let data = [[0x5a; 0x800], [0x33; 0x800], [0x33; 0x800], [0x11; 0x800]].concat();
+        let stats = flash.program_differential(&*spi, 0x10800, &data)?;
+        assert_eq!(
+            stats,
+            ProgramStats {
+                erased: 0x1000,
+                programmed: 0x2000,
+                unchanged: 0x1000,
+            }
+        );
+        assert_eq!(sim.flash().contents(0x10000, 0x800), [0x5a; 0x800]);
+        assert_eq!(sim.flash().contents(0x10800, data.len()), data);
+        assert_eq!(sim.flash().contents(0x12800, 0x800), [0xa5; 0x800]);
+        // The result is verified by digest rather than by reading it back.
+        assert_eq!(sim.flash().digests(), 1);
+
+        // Nothing is left to do on a second run.
+        let stats = flash.program_differential(&*spi, 0x10800, &data)?;
+        assert_eq!(stats.unchanged, 0x3000);
+        assert_eq!((stats.erased, stats.programmed), (0, 0));
+        assert_eq!(sim.flash().digests(), 2);
+
+        // A buffer running past the end of the flash is rejected before touching it.
+        assert!(flash
+            .program_differential(&*spi, flash.size - 0x800, &data)
+            .is_err());
+        assert!(flash.program_differential(&*spi, u32::MAX, &data).is_err());
+        assert_eq!(sim.flash().digests(), 2);
+
+        // A digest requested with the wrong addressing mode is an error.
+        assert!(spi.eeprom_sha256(0, 16, AddressMode::Mode4b)?.is_some());
+        flash.set_address_mode(&*spi, AddressMode::Mode4b)?;
+        assert!(spi.eeprom_sha256(0, 16, AddressMode::Mode3b).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bootstrap() -> Result<()> {
+        #[derive(Parser)]
+        struct Opts {
+            #[command(flatten)]
+            bootstrap: BootstrapOptions,
+        }
+
+        let sim = Sim::new();
+        let transport = transport(&sim)?;
+        assert_eq!(sim.boot_count(), 0);
+        sim.flash().load(0x4000, &[0u8; 16]);
+
+        let opts = Opts::try_parse_from(["test", "--reset-delay", "1ms"])?;
+        let payload = (0..5000).map(|i| (i * 7) as u8).collect::<Vec<u8>>();
+        Bootstrap::update(&transport, &opts.bootstrap, &payload)?;
+
+        // Reset once into bootstrap, and once more to boot the new image.
+        assert_eq!(sim.boot_count(), 2);
+        assert!(!sim.in_bootstrap());
+        assert_eq!(sim.flash().contents(0, payload.len()), payload);
+        assert_eq!(sim.flash().contents(0x4000, 16), [0xff; 16]);
+        let console = transport.uart("console")?;
+        let mut buf = [0u8; 64];
+        let len = console.read(&mut buf)?;
+        assert_eq!(
+            &buf[..len],
+            [BOOT_MESSAGE_BOOTSTRAP, BOOT_MESSAGE_FLASH]
+                .concat()
+                .as_bytes()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_console() -> Result<()> {
+        let sim = Sim::new();
+        let transport = transport(&sim)?;
+        let uart = transport.uart("console")?;
+        sim.console().respond(b"ping\r", b"pong 42\r\n");
+
+        transport.reset_target(Duration::from_millis(1), true)?;
+        UartConsole::wait_for(&*uart, r"ROM: boot", Duration::from_secs(1))?;
+        uart.write(b"ping\r")?;
+        let captures = UartConsole::wait_for(&*uart, r"pong (\d+)", Duration::from_secs(1))?;
+        assert_eq!(captures[1], "42");
+        assert_eq!(sim.console().take_written(), b"ping\r");
+
+        sim.console().set_loopback(true);
+        uart.write(b"echo")?;
+        UartConsole::wait_for(&*uart, r"echo", Duration::from_secs(1))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_straps() -> Result<()> {
+        let sim = Sim::new();
+        let transport = transport(&sim)?;
+        assert!(sim.pin_level(RESET_PIN));
+        let strapping = transport.pin_strapping("ROM_BOOTSTRAP")?;
+        strapping.apply()?;
+        assert!(sim.pin_level("IOC0"));
+        transport.reset_target(Duration::from_millis(1), false)?;
+        assert!(sim.in_bootstrap());
+        strapping.remove()?;
+        assert!(!sim.pin_level("IOC0"));
+        transport.reset_target(Duration::from_millis(1), false)?;
+        assert!(!sim.in_bootstrap());
+        assert_eq!(sim.boot_count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_i2c() -> Result<()> {
+        let sim = Sim::new();
+        let transport = transport(&sim)?;
+        let i2c = transport.i2c("0")?;
+        i2c.run_transaction(
+            Some(SimI2c::ADDRESS),
+            &mut [Transfer::Write(&[0x10, 1, 2, 3])],
+        )?;
+        let mut buf = [0u8; 2];
+        i2c.run_transaction(
+            Some(SimI2c::ADDRESS),
+            &mut [Transfer::Write(&[0x11]), Transfer::Read(&mut buf)],
+        )?;
+        assert_eq!(buf, [2, 3]);
+        assert_eq!(sim.i2c().registers()[0x10..0x13], [1, 2, 3]);
+        assert!(i2c
+            .run_transaction(Some(0x51), &mut [Transfer::Read(&mut buf)])
+            .is_err());
+        Ok(())
+    }
+}
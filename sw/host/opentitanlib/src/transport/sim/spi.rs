@@ -0,0 +1,481 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, ensure, Result};
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::SimError;
+use crate::io::eeprom::{AddressMode, Cmd, Transaction};
+use crate::io::spi::{AssertChipSelect, MaxSizes, Target, Transfer, TransferMode};
+use crate::spiflash::SpiFlash;
+use crate::transport::TransportError;
+
+const SFDP: &[u8] = include_bytes!("../../spiflash/SFDP_MX66L1G.bin");
+
+const FAST_DUAL_IO_READ: u8 = 0xbb;
+const FAST_QUAD_IO_READ: u8 = 0xeb;
+const FAST_DUAL_IO_READ_4B: u8 = 0xbc;
+const FAST_QUAD_IO_READ_4B: u8 = 0xec;
+const CHIP_ERASE_ALT: u8 = 0x60;
+
+const SECTOR_SIZE: u32 = 4096;
+const PAGE_SIZE: u32 = SpiFlash::LEGACY_PAGE_SIZE;
+
+/// Number of status register reads for which a program or erase operation stays busy.
+const BUSY_POLLS: u32 = 2;
+
+struct FlashState {
+    // Contents of programmed 4 KiB sectors, keyed by sector index.  Absent sectors are erased.
+    sectors: HashMap<u32, Vec<u8>>,
+    write_enable: bool,
+    busy: u32,
+    // Non-volatile bits of status registers 1 and 2, and the number of times they were written.
+    status: [u8; 2],
+    status_writes: usize,
+    // Number of digests computed by `eeprom_sha256()`.
+    digests: usize,
+    addr4: bool,
+    reset_enabled: bool,
+}
+
+/// A simulated serial NOR flash, modelled after the Macronix MX66L1G whose SFDP table it serves.
+///
+/// Each call to `run_transaction()` or each element of `run_eeprom_transactions()` is treated
+/// as one chip select frame.  Program and erase operations keep the busy bit set for a few
+/// status reads, and other commands issued while busy are reported as errors, as would be
+/// commands using an address length which does not match the current addressing mode.
+pub struct SimFlash {
+    state: RefCell<FlashState>,
+    transfer_mode: Cell<TransferMode>,
+    bits_per_word: Cell<u32>,
+    max_speed: Cell<u32>,
+}
+
+impl SimFlash {
+    /// Size of the flash in bytes.
+    pub const SIZE: u32 = 128 * 1024 * 1024;
+    /// Response to the `READ_ID` command.
+    pub const JEDEC_ID: [u8; 3] = [0xc2, 0x20, 0x1b];
+
+    pub(super) fn new() -> Self {
+        Self {
+            state: RefCell::new(FlashState {
+                sectors: HashMap::new(),
+                write_enable: false,
+                busy: 0,
+                status: [0; 2],
+                status_writes: 0,
+                digests: 0,
+                addr4: false,
+                reset_enabled: false,
+            }),
+            transfer_mode: Cell::new(TransferMode::Mode0),
+            bits_per_word: Cell::new(8),
+            max_speed: Cell::new(10_000_000),
+        }
+    }
+
+    /// Returns `len` bytes of the flash contents starting at `address`.
+    pub fn contents(&self, address: u32, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        self.state.borrow().read(address, &mut buf);
+        buf
+    }
+
+    /// Overwrites the flash contents starting at `address`, bypassing the command interface.
+    pub fn load(&self, address: u32, data: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        for (i, &byte) in data.iter().enumerate() {
+            *state.byte_mut(address.wrapping_add(i as u32)) = byte;
+        }
+    }
+
+    /// Returns the non-volatile bits of status registers 1 and 2.
+    pub fn status(&self) -> [u8; 2] {
+        self.state.borrow().status
+    }
+
+    /// Overwrites status registers 1 and 2, bypassing the command interface.
+    pub fn load_status(&self, status: [u8; 2]) {
+        self.state.borrow_mut().status = status;
+    }
+
+    /// Number of writes to status registers 1 and 2 since the flash was created.
+    pub fn status_writes(&self) -> usize {
+        self.state.borrow().status_writes
+    }
+
+    /// Number of digests of the flash contents computed on behalf of the host.
+    pub fn digests(&self) -> usize {
+        self.state.borrow().digests
+    }
+
+    /// Whether the flash is in 4-byte addressing mode.
+    pub fn is_4b_mode(&self) -> bool {
+        self.state.borrow().addr4
+    }
+
+    /// Returns the volatile state of the flash to its power-on defaults.
+    pub(super) fn reset(&self) {
+        let mut state = self.state.borrow_mut();
+        state.write_enable = false;
+        state.busy = 0;
+        state.addr4 = false;
+        state.reset_enabled = false;
+    }
+
+    fn address_len(&self, opcode: u8) -> usize {
+        match opcode {
+            SpiFlash::READ_4B
+            | SpiFlash::FAST_READ_4B
+            | SpiFlash::FAST_DUAL_READ_4B
+            | SpiFlash::FAST_QUAD_READ_4B
+            | FAST_DUAL_IO_READ_4B
+            | FAST_QUAD_IO_READ_4B
+            | SpiFlash::PAGE_PROGRAM_4B
+            | SpiFlash::QUAD_IO_PAGE_PROGRAM_4B
+            | SpiFlash::SECTOR_ERASE_4B
+            | SpiFlash::BLOCK_ERASE_32K_4B
+            | SpiFlash::BLOCK_ERASE_64K_4B => 4,
+            SpiFlash::READ
+            | SpiFlash::FAST_READ
+            | SpiFlash::FAST_DUAL_READ
+            | SpiFlash::FAST_QUAD_READ
+            | FAST_DUAL_IO_READ
+            | FAST_QUAD_IO_READ
+            | SpiFlash::PAGE_PROGRAM
+            | SpiFlash::SECTOR_ERASE
+            | SpiFlash::BLOCK_ERASE_32K
+            | SpiFlash::BLOCK_ERASE_64K => {
+                if self.state.borrow().addr4 {
+                    4
+                } else {
+                    3
+                }
+            }
+            SpiFlash::READ_SFDP => 3,
+            _ => 0,
+        }
+    }
+
+    // Number of dummy bytes following the address, when clocked in single-wire mode.
+    fn dummy_len(opcode: u8) -> usize {
+        match opcode {
+            SpiFlash::READ | SpiFlash::READ_4B => 0,
+            _ => 1,
+        }
+    }
+
+    /// Performs a command which returns data.
+    fn read(&self, opcode: u8, address: u32, buf: &mut [u8]) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        state.reset_enabled = false;
+        match opcode {
+            SpiFlash::READ_STATUS => {
+                let mut status = state.status[0];
+                if state.write_enable {
+                    status |= SpiFlash::STATUS_WEL;
+                }
+                if state.busy > 0 {
+                    status |= SpiFlash::STATUS_WIP;
+                    state.busy -= 1;
+                }
+                buf.fill(status);
+            }
+            SpiFlash::READ_STATUS2 => buf.fill(state.status[1]),
+            SpiFlash::READ_STATUS3 => buf.fill(0),
+            _ if state.busy > 0 => bail!(SimError::FlashBusy(opcode)),
+            SpiFlash::READ_ID => {
+                buf.fill(0);
+                let n = std::cmp::min(buf.len(), Self::JEDEC_ID.len());
+                buf[..n].copy_from_slice(&Self::JEDEC_ID[..n]);
+            }
+            SpiFlash::READ_SFDP => {
+                for (i, byte) in buf.iter_mut().enumerate() {
+                    *byte = *SFDP.get(address as usize + i).unwrap_or(&0xff);
+                }
+            }
+            SpiFlash::READ
+            | SpiFlash::FAST_READ
+            | SpiFlash::FAST_DUAL_READ
+            | SpiFlash::FAST_QUAD_READ
+            | FAST_DUAL_IO_READ
+            | FAST_QUAD_IO_READ
+            | SpiFlash::READ_4B
+            | SpiFlash::FAST_READ_4B
+            | SpiFlash::FAST_DUAL_READ_4B
+            | SpiFlash::FAST_QUAD_READ_4B
+            | FAST_DUAL_IO_READ_4B
+            | FAST_QUAD_IO_READ_4B => state.read(address, buf),
+            _ => bail!(SimError::UnsupportedOpcode(opcode)),
+        }
+        Ok(())
+    }
+
+    /// Performs a command which takes optional data, upon chip select being deasserted.
+    fn execute(&self, opcode: u8, address: u32, data: &[u8]) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        let reset_enabled = std::mem::take(&mut state.reset_enabled);
+        ensure!(state.busy == 0, SimError::FlashBusy(opcode));
+        let erase_size = match opcode {
+            SpiFlash::WRITE_ENABLE => {
+                state.write_enable = true;
+                return Ok(());
+            }
+            SpiFlash::WRITE_DISABLE => {
+                state.write_enable = false;
+                return Ok(());
+            }
+            SpiFlash::ENTER_4B => {
+                state.addr4 = true;
+                return Ok(());
+            }
+            SpiFlash::EXIT_4B => {
+                state.addr4 = false;
+                return Ok(());
+            }
+            SpiFlash::RESET_ENABLE => {
+                state.reset_enabled = true;
+                return Ok(());
+            }
+            SpiFlash::RESET => {
+                drop(state);
+                if reset_enabled {
+                    self.reset();
+                }
+                return Ok(());
+            }
+            SpiFlash::NOP => return Ok(()),
+            SpiFlash::WRITE_STATUS | SpiFlash::WRITE_STATUS2 => {
+                // Status registers 1 and 2 are modelled, writes take effect immediately.
+                let first = usize::from(opcode == SpiFlash::WRITE_STATUS2);
+                if std::mem::take(&mut state.write_enable) && !data.is_empty() {
+                    for (i, &value) in data.iter().take(2 - first).enumerate() {
+                        state.status[first + i] = value;
+                    }
+                    state.status[0] &= !(SpiFlash::STATUS_WIP | SpiFlash::STATUS_WEL);
+                    state.status_writes += 1;
+                }
+                return Ok(());
+            }
+            SpiFlash::WRITE_STATUS3 => {
+                state.write_enable = false;
+                return Ok(());
+            }
+            SpiFlash::PAGE_PROGRAM
+            | SpiFlash::PAGE_PROGRAM_4B
+            | SpiFlash::QUAD_IO_PAGE_PROGRAM_4B => None,
+            SpiFlash::SECTOR_ERASE | SpiFlash::SECTOR_ERASE_4B => Some(SECTOR_SIZE),
+            SpiFlash::BLOCK_ERASE_32K | SpiFlash::BLOCK_ERASE_32K_4B => Some(32 * 1024),
+            SpiFlash::BLOCK_ERASE_64K | SpiFlash::BLOCK_ERASE_64K_4B => Some(64 * 1024),
+            SpiFlash::CHIP_ERASE | CHIP_ERASE_ALT => Some(Self::SIZE),
+            _ => bail!(SimError::UnsupportedOpcode(opcode)),
+        };
+        if !std::mem::take(&mut state.write_enable) {
+            log::warn!("Simulated flash ignoring opcode {opcode:#04x} without write enable");
+            return Ok(());
+        }
+        match erase_size {
+            Some(size) => {
+                let start = address % Self::SIZE / size * size;
+                for sector in (start..start + size).step_by(SECTOR_SIZE as usize) {
+                    state.sectors.remove(&(sector / SECTOR_SIZE));
+                }
+            }
+            None => {
+                // Programming wraps around within the page, and can only clear bits.
+                let page = address / PAGE_SIZE * PAGE_SIZE;
+                for (i, &byte) in data.iter().enumerate() {
+                    let offset = (address - page + i as u32) % PAGE_SIZE;
+                    *state.byte_mut(page + offset) &= byte;
+                }
+            }
+        }
+        state.busy = BUSY_POLLS;
+        Ok(())
+    }
+
+    fn check_cmd(&self, cmd: &Cmd) -> Result<u8> {
+        ensure!(
+            cmd.get_opcode_len() == 1,
+            SimError::UnsupportedOpcode(cmd.get_opcode()[0])
+        );
+        let opcode = cmd.get_opcode()[0];
+        let expected = self.address_len(opcode);
+        ensure!(
+            cmd.get_address_len() as usize == expected,
+            SimError::AddressLength(opcode, expected, cmd.get_address_len() as usize)
+        );
+        Ok(opcode)
+    }
+
+    // Splits the bytes clocked out by the host into opcode, address and length of the header.
+    fn decode(&self, written: &[u8], with_dummy: bool) -> Result<(u8, u32, usize)> {
+        let Some(&opcode) = written.first() else {
+            bail!(SimError::UnsupportedOpcode(SpiFlash::NOP));
+        };
+        let addr_len = self.address_len(opcode);
+        let mut header = 1 + addr_len;
+        if with_dummy && addr_len != 0 {
+            header += Self::dummy_len(opcode);
+        }
+        ensure!(
+            written.len() >= header,
+            SimError::AddressLength(opcode, addr_len, written.len() - 1)
+        );
+        let address = written[1..1 + addr_len]
+            .iter()
+            .fold(0u32, |acc, &b| (acc << 8) | b as u32);
+        Ok((opcode, address, header))
+    }
+}
+
+impl FlashState {
+    fn read(&self, address: u32, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let address = address.wrapping_add(i as u32) % SimFlash::SIZE;
+            *byte = self
+                .sectors
+                .get(&(address / SECTOR_SIZE))
+                .map(|s| s[(address % SECTOR_SIZE) as usize])
+                .unwrap_or(0xff);
+        }
+    }
+
+    fn byte_mut(&mut self, address: u32) -> &mut u8 {
+        let address = address % SimFlash::SIZE;
+        let sector = self
+            .sectors
+            .entry(address / SECTOR_SIZE)
+            .or_insert_with(|| vec![0xff; SECTOR_SIZE as usize]);
+        &mut sector[(address % SECTOR_SIZE) as usize]
+    }
+}
+
+impl Target for SimFlash {
+    fn get_transfer_mode(&self) -> Result<TransferMode> {
+        Ok(self.transfer_mode.get())
+    }
+    fn set_transfer_mode(&self, mode: TransferMode) -> Result<()> {
+        self.transfer_mode.set(mode);
+        Ok(())
+    }
+
+    fn get_bits_per_word(&self) -> Result<u32> {
+        Ok(self.bits_per_word.get())
+    }
+    fn set_bits_per_word(&self, bits_per_word: u32) -> Result<()> {
+        self.bits_per_word.set(bits_per_word);
+        Ok(())
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        Ok(self.max_speed.get())
+    }
+    fn set_max_speed(&self, max_speed: u32) -> Result<()> {
+        self.max_speed.set(max_speed);
+        Ok(())
+    }
+
+    fn supports_bidirectional_transfer(&self) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn supports_tpm_poll(&self) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn get_max_transfer_count(&self) -> Result<usize> {
+        Ok(usize::MAX)
+    }
+
+    fn get_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        Ok(MaxSizes {
+            read: 2048,
+            write: 2048,
+        })
+    }
+
+    fn run_transaction(&self, transaction: &mut [Transfer]) -> Result<()> {
+        let mut written = Vec::new();
+        let mut offset = None;
+        for transfer in transaction.iter_mut() {
+            match transfer {
+                Transfer::Write(wbuf) => written.extend_from_slice(wbuf),
+                Transfer::Read(rbuf) => {
+                    let (opcode, address, header) = self.decode(&written, true)?;
+                    // Bytes written beyond the header, and previous reads, advance the address.
+                    let skip = offset.unwrap_or(written.len() - header);
+                    let mut buf = vec![0u8; skip + rbuf.len()];
+                    self.read(opcode, address, &mut buf)?;
+                    rbuf.copy_from_slice(&buf[skip..]);
+                    offset = Some(skip + rbuf.len());
+                }
+                _ => bail!(TransportError::UnsupportedOperation),
+            }
+        }
+        if offset.is_none() && !written.is_empty() {
+            let (opcode, address, header) = self.decode(&written, false)?;
+            self.execute(opcode, address, &written[header..])?;
+        }
+        Ok(())
+    }
+
+    fn run_eeprom_transactions(&self, transactions: &mut [Transaction]) -> Result<()> {
+        for transaction in transactions {
+            match transaction {
+                Transaction::Command(cmd) => {
+                    let opcode = self.check_cmd(cmd)?;
+                    self.execute(opcode, cmd.get_address(), &[])?;
+                }
+                Transaction::Read(cmd, rbuf) => {
+                    let opcode = self.check_cmd(cmd)?;
+                    self.read(opcode, cmd.get_address(), rbuf)?;
+                }
+                Transaction::Write(cmd, wbuf) => {
+                    let opcode = self.check_cmd(cmd)?;
+                    self.execute(opcode, cmd.get_address(), wbuf)?;
+                }
+                Transaction::WaitForBusyClear => {
+                    let mut status = SpiFlash::STATUS_WIP;
+                    while status & SpiFlash::STATUS_WIP != 0 {
+                        self.read(SpiFlash::READ_STATUS, 0, std::slice::from_mut(&mut status))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn eeprom_sha256(
+        &self,
+        address: u32,
+        length: u32,
+        address_mode: AddressMode,
+    ) -> Result<Option<[u8; 32]>> {
+        // The digest is computed as if by a read command, which must match the addressing mode.
+        let opcode = match address_mode {
+            AddressMode::Mode3b => SpiFlash::READ,
+            AddressMode::Mode4b => SpiFlash::READ_4B,
+        };
+        let expected = self.address_len(opcode);
+        ensure!(
+            address_mode as usize == expected,
+            SimError::AddressLength(opcode, expected, address_mode as usize)
+        );
+        let mut state = self.state.borrow_mut();
+        ensure!(state.busy == 0, SimError::FlashBusy(opcode));
+        let mut buf = vec![0u8; length as usize];
+        state.read(address, &mut buf);
+        state.digests += 1;
+        Ok(Some(Sha256::digest(&buf).into()))
+    }
+
+    fn assert_cs(self: Rc<Self>) -> Result<AssertChipSelect> {
+        Err(TransportError::UnsupportedOperation.into())
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::io::uart::{FlowControl, Parity, Uart};
+
+/// A simulated console UART.
+///
+/// Data written by the host is recorded, and is matched against scripted prompts which make
+/// the simulated device respond.  In loopback mode, written data is also echoed back.  Reads
+/// return whatever the device has output, and there is nothing else producing data while the
+/// host waits, so a read with an empty buffer simply waits out its timeout.
+pub struct SimUart {
+    rx: RefCell<VecDeque<u8>>,
+    tx: RefCell<Vec<u8>>,
+    pending: RefCell<Vec<u8>>,
+    script: RefCell<Vec<(Vec<u8>, Vec<u8>)>>,
+    loopback: Cell<bool>,
+    baudrate: Cell<u32>,
+    parity: Cell<Parity>,
+    flow_control: Cell<FlowControl>,
+    break_enabled: Cell<bool>,
+}
+
+impl SimUart {
+    pub(super) fn new() -> Self {
+        Self {
+            rx: RefCell::new(VecDeque::new()),
+            tx: RefCell::new(Vec::new()),
+            pending: RefCell::new(Vec::new()),
+            script: RefCell::new(Vec::new()),
+            loopback: Cell::new(false),
+            baudrate: Cell::new(115200),
+            parity: Cell::new(Parity::None),
+            flow_control: Cell::new(FlowControl::None),
+            break_enabled: Cell::new(false),
+        }
+    }
+
+    /// Makes the simulated device output `data`, to be read by the host.
+    pub fn inject(&self, data: &[u8]) {
+        self.rx.borrow_mut().extend(data);
+    }
+
+    /// Makes the simulated device output `response` whenever the host has written `prompt`.
+    pub fn respond(&self, prompt: &[u8], response: &[u8]) {
+        self.script
+            .borrow_mut()
+            .push((prompt.to_vec(), response.to_vec()));
+    }
+
+    /// Enables or disables echoing of data written by the host.
+    pub fn set_loopback(&self, loopback: bool) {
+        self.loopback.set(loopback);
+    }
+
+    /// Returns and clears everything written by the host so far.
+    pub fn take_written(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.tx.borrow_mut())
+    }
+
+    /// Whether the host is currently asserting a break condition.
+    pub fn break_enabled(&self) -> bool {
+        self.break_enabled.get()
+    }
+
+    // Answers scripted prompts, each occurrence in the written data is answered once.
+    fn run_script(&self) {
+        let script = self.script.borrow();
+        let mut pending = self.pending.borrow_mut();
+        loop {
+            let found = script
+                .iter()
+                .filter(|(prompt, _)| !prompt.is_empty())
+                .filter_map(|(prompt, response)| {
+                    pending
+                        .windows(prompt.len())
+                        .position(|w| w == prompt.as_slice())
+                        .map(|pos| (pos + prompt.len(), response))
+                })
+                .min_by_key(|(end, _)| *end);
+            let Some((end, response)) = found else {
+                break;
+            };
+            self.rx.borrow_mut().extend(response);
+            pending.drain(..end);
+        }
+    }
+}
+
+impl Uart for SimUart {
+    fn get_baudrate(&self) -> Result<u32> {
+        Ok(self.baudrate.get())
+    }
+
+    fn set_baudrate(&self, baudrate: u32) -> Result<()> {
+        self.baudrate.set(baudrate);
+        Ok(())
+    }
+
+    fn get_flow_control(&self) -> Result<FlowControl> {
+        Ok(self.flow_control.get())
+    }
+
+    fn set_flow_control(&self, flow_control: bool) -> Result<()> {
+        self.flow_control.set(match flow_control {
+            false => FlowControl::None,
+            true => FlowControl::Resume,
+        });
+        Ok(())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut rx = self.rx.borrow_mut();
+        let len = std::cmp::min(buf.len(), rx.len());
+        for (dst, src) in buf.iter_mut().zip(rx.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        if self.rx.borrow().is_empty() {
+            std::thread::sleep(timeout);
+        }
+        self.read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        self.tx.borrow_mut().extend_from_slice(buf);
+        if self.loopback.get() {
+            self.inject(buf);
+        }
+        self.pending.borrow_mut().extend_from_slice(buf);
+        self.run_script();
+        Ok(())
+    }
+
+    fn clear_rx_buffer(&self) -> Result<()> {
+        self.rx.borrow_mut().clear();
+        Ok(())
+    }
+
+    fn set_break(&self, enable: bool) -> Result<()> {
+        self.break_enabled.set(enable);
+        Ok(())
+    }
+
+    fn set_parity(&self, parity: Parity) -> Result<()> {
+        self.parity.set(parity);
+        Ok(())
+    }
+
+    fn get_parity(&self) -> Result<Parity> {
+        Ok(self.parity.get())
+    }
+}
@@ -105,11 +105,20 @@ impl<const BIT_LEN: usize, const EXACT_LEN: bool> ParseInt for FixedSizeBigInt<B
 
     fn from_str_radix(src: &str, radix: u32) -> Result<Self, Self::FromStrRadixErr> {
         Self::new_from_biguint(
-            BigUint::from_str_radix(src, radix).map_err(ParseBigIntError::ParseBigIntError)?,
+            <BigUint as Num>::from_str_radix(src, radix)
+                .map_err(ParseBigIntError::ParseBigIntError)?,
         )
     }
 }
 
+impl ParseInt for BigUint {
+    type FromStrRadixErr = ParseBigIntError;
+
+    fn from_str_radix(src: &str, radix: u32) -> Result<Self, Self::FromStrRadixErr> {
+        <BigUint as Num>::from_str_radix(src, radix).map_err(ParseBigIntError::ParseBigIntError)
+    }
+}
+
 impl<const BIT_LEN: usize, const EXACT_LEN: bool> fmt::Display
     for FixedSizeBigInt<BIT_LEN, EXACT_LEN>
 {
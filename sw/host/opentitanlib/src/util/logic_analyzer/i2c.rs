@@ -0,0 +1,193 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_annotate::Annotate;
+
+use super::{Capture, Step};
+use crate::io::gpio::Edge;
+
+/// Decodes I2C traffic, reporting one `I2cTransfer` for each START (or repeated START)
+/// condition.
+#[derive(Clone, Debug)]
+pub struct I2cDecoder {
+    /// Index of the clock signal in the capture.
+    pub scl: usize,
+    /// Index of the data signal in the capture.
+    pub sda: usize,
+}
+
+/// A transfer addressed to a single device, from START to STOP or repeated START.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Annotate)]
+pub struct I2cTransfer {
+    /// Timestamp of the START condition.
+    pub start: u64,
+    /// Timestamp of the STOP or repeated START condition (or end of capture).
+    pub end: u64,
+    /// 7-bit device address.
+    #[annotate(format = hex)]
+    pub address: u8,
+    pub read: bool,
+    /// Whether any device acknowledged the address.
+    pub address_ack: bool,
+    /// Data bytes following the address.
+    #[annotate(format = hex)]
+    pub data: Vec<u8>,
+    /// Whether the final data byte was not acknowledged (which is the norm for the last byte of
+    /// a read).
+    pub last_nack: bool,
+    /// Whether the transfer ended in the middle of a byte, or without a complete address.
+    pub truncated: bool,
+}
+
+#[derive(Default)]
+struct State {
+    transfer: I2cTransfer,
+    have_address: bool,
+    shift: u16,
+    bits: u8,
+}
+
+impl I2cDecoder {
+    pub fn decode(&self, capture: &Capture) -> Result<Vec<I2cTransfer>> {
+        capture.check_signals(&[self.scl, self.sda])?;
+        let mut transfers = Vec::new();
+        let mut current: Option<State> = None;
+        capture.for_each_step(|step: &Step| {
+            let scl_high = step.before[self.scl] && step.after[self.scl];
+            match (scl_high, step.edge(self.sda), step.edge(self.scl)) {
+                (true, Some(Edge::Falling), _) => {
+                    // START or repeated START.
+                    if let Some(state) = current.take() {
+                        transfers.push(Self::finish(state, step.timestamp));
+                    }
+                    current = Some(State {
+                        transfer: I2cTransfer {
+                            start: step.timestamp,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    });
+                }
+                (true, Some(Edge::Rising), _) => {
+                    // STOP.
+                    if let Some(state) = current.take() {
+                        transfers.push(Self::finish(state, step.timestamp));
+                    }
+                }
+                (_, _, Some(Edge::Rising)) => {
+                    // Data is set up while SCL is low, possibly at the same time as SCL is
+                    // released by a bitbanging host, so use the level after the edge.
+                    if let Some(state) = &mut current {
+                        state.shift = state.shift << 1 | step.after[self.sda] as u16;
+                        state.bits += 1;
+                        if state.bits == 9 {
+                            Self::complete_byte(state);
+                        }
+                    }
+                }
+                _ => (),
+            }
+            Ok(())
+        })?;
+        if let Some(state) = current.take() {
+            transfers.push(Self::finish(state, capture.end_timestamp));
+        }
+        Ok(transfers)
+    }
+
+    fn complete_byte(state: &mut State) {
+        let byte = (state.shift >> 1) as u8;
+        let nack = state.shift & 1 != 0;
+        if state.have_address {
+            state.transfer.data.push(byte);
+            state.transfer.last_nack = nack;
+        } else {
+            state.transfer.address = byte >> 1;
+            state.transfer.read = byte & 1 != 0;
+            state.transfer.address_ack = !nack;
+            state.have_address = true;
+        }
+        state.shift = 0;
+        state.bits = 0;
+    }
+
+    fn finish(mut state: State, timestamp: u64) -> I2cTransfer {
+        state.transfer.end = timestamp;
+        // A STOP or repeated START is always preceded by one rising edge of SCL, which does not
+        // carry any data bit.
+        state.transfer.truncated = state.bits > 1 || !state.have_address;
+        state.transfer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::bitbanging::i2c::encoder::{Encoder, Transfer};
+    use crate::util::logic_analyzer::test::capture_from_levels;
+
+    /// Turns the bitbanging samples produced by the I2C encoder into per-signal waveforms.
+    fn capture(transfers: &[Transfer]) -> Capture {
+        let samples = Encoder::<0, 1> {}.run(transfers);
+        let sda: Vec<bool> = std::iter::once(true)
+            .chain(samples.iter().map(|s| s & 1 != 0))
+            .chain(std::iter::once(true))
+            .collect();
+        let scl: Vec<bool> = std::iter::once(true)
+            .chain(samples.iter().map(|s| s & 2 != 0))
+            .chain(std::iter::once(true))
+            .collect();
+        capture_from_levels(&[&scl, &sda])
+    }
+
+    #[test]
+    fn test_i2c_write_read() -> Result<()> {
+        let capture = capture(&[
+            Transfer::Start,
+            Transfer::Addr {
+                addr: 0x50,
+                read: false,
+                nack: false,
+            },
+            Transfer::Write(&[0x12, 0x34]),
+            Transfer::Start,
+            Transfer::Addr {
+                addr: 0x50,
+                read: true,
+                nack: false,
+            },
+            Transfer::Write(&[0xab]),
+            Transfer::Stop,
+            Transfer::Start,
+            Transfer::Addr {
+                addr: 0x21,
+                read: false,
+                nack: true,
+            },
+            Transfer::Stop,
+        ]);
+        let decoder = I2cDecoder { scl: 0, sda: 1 };
+        let transfers = decoder.decode(&capture)?;
+        assert_eq!(transfers.len(), 3);
+
+        assert_eq!(transfers[0].address, 0x50);
+        assert!(!transfers[0].read);
+        assert!(transfers[0].address_ack);
+        assert_eq!(transfers[0].data, vec![0x12, 0x34]);
+        assert!(!transfers[0].truncated);
+        // Repeated start.
+        assert_eq!(transfers[0].end, transfers[1].start);
+
+        assert_eq!(transfers[1].address, 0x50);
+        assert!(transfers[1].read);
+        assert_eq!(transfers[1].data, vec![0xab]);
+
+        assert_eq!(transfers[2].address, 0x21);
+        assert!(!transfers[2].address_ack);
+        assert!(transfers[2].data.is_empty());
+        Ok(())
+    }
+}
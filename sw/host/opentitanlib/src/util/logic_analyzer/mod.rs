@@ -0,0 +1,187 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Protocol decoders operating on edge captures from `GpioMonitoring`.
+//!
+//! Unlike the decoders in `test_utils::bitbanging`, which operate on buffers of samples taken at
+//! a fixed rate, these decoders consume the list of timestamped edges returned by
+//! `monitoring_read()`, allowing e.g. HyperDebug to be used as a protocol analyzer on a live bus.
+
+use anyhow::{ensure, Result};
+
+use crate::io::gpio::{
+    ClockNature, Edge, MonitoringEvent, MonitoringReadResponse, MonitoringStartResponse,
+};
+
+pub mod i2c;
+pub mod spi;
+pub mod uart;
+
+pub use i2c::{I2cDecoder, I2cTransfer};
+pub use spi::{SpiDecoder, SpiTransaction};
+pub use uart::{UartDecoder, UartDecoding, UartFrame};
+
+/// Edges observed on a set of monitored signals, accumulated from one `monitoring_start()` and
+/// any number of subsequent `monitoring_read()` calls.
+#[derive(Clone, Debug)]
+pub struct Capture {
+    pub clock_nature: ClockNature,
+    /// Timestamp at which monitoring started.
+    pub start_timestamp: u64,
+    /// Timestamp up to which the capture is known to be complete.
+    pub end_timestamp: u64,
+    /// Level of each signal at `start_timestamp`.
+    pub initial_levels: Vec<bool>,
+    /// Edges, in the order reported by the transport.
+    pub events: Vec<MonitoringEvent>,
+}
+
+/// The levels of all signals immediately before and after the edges occurring at a particular
+/// timestamp.
+pub(crate) struct Step<'a> {
+    pub timestamp: u64,
+    pub before: &'a [bool],
+    pub after: &'a [bool],
+}
+
+impl Step<'_> {
+    /// Returns the edge (if any) of the given signal at this step.
+    pub fn edge(&self, signal: usize) -> Option<Edge> {
+        match (self.before[signal], self.after[signal]) {
+            (false, true) => Some(Edge::Rising),
+            (true, false) => Some(Edge::Falling),
+            _ => None,
+        }
+    }
+}
+
+impl Capture {
+    pub fn new(clock_nature: ClockNature, start: MonitoringStartResponse) -> Self {
+        Self {
+            clock_nature,
+            start_timestamp: start.timestamp,
+            end_timestamp: start.timestamp,
+            initial_levels: start.initial_levels,
+            events: Vec::new(),
+        }
+    }
+
+    /// Appends the result of a `monitoring_read()` to the capture.
+    pub fn extend(&mut self, read: MonitoringReadResponse) {
+        self.events.extend(read.events);
+        self.end_timestamp = std::cmp::max(self.end_timestamp, read.timestamp);
+    }
+
+    /// Number of timestamp units per second, if the transport clock is related to wall clock
+    /// time.
+    pub fn resolution(&self) -> Option<u64> {
+        match self.clock_nature {
+            ClockNature::Wallclock { resolution, .. } => Some(resolution),
+            ClockNature::Unspecified => None,
+        }
+    }
+
+    /// Verifies that the given signal indices refer to monitored signals.
+    pub(crate) fn check_signals(&self, signals: &[usize]) -> Result<()> {
+        for &signal in signals {
+            ensure!(
+                signal < self.initial_levels.len(),
+                "Signal index {} out of range, capture has {} signals",
+                signal,
+                self.initial_levels.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Edges of a single signal, in chronological order.
+    pub(crate) fn edges(&self, signal: usize) -> Vec<(u64, Edge)> {
+        let mut edges: Vec<(u64, Edge)> = self
+            .events
+            .iter()
+            .filter(|e| e.signal_index as usize == signal)
+            .map(|e| (e.timestamp, e.edge))
+            .collect();
+        edges.sort_by_key(|(timestamp, _)| *timestamp);
+        edges
+    }
+
+    /// Invokes `f` once for each distinct timestamp having edges, in chronological order.  Edges
+    /// sharing a timestamp are considered simultaneous, leaving it to each decoder to decide
+    /// whether the levels before or after the step are relevant.
+    pub(crate) fn for_each_step(&self, mut f: impl FnMut(&Step) -> Result<()>) -> Result<()> {
+        let mut events: Vec<&MonitoringEvent> = self.events.iter().collect();
+        events.sort_by_key(|e| e.timestamp);
+        let mut before = self.initial_levels.clone();
+        let mut after = self.initial_levels.clone();
+        let mut i = 0;
+        while i < events.len() {
+            let timestamp = events[i].timestamp;
+            while i < events.len() && events[i].timestamp == timestamp {
+                after[events[i].signal_index as usize] = events[i].edge == Edge::Rising;
+                i += 1;
+            }
+            f(&Step {
+                timestamp,
+                before: &before,
+                after: &after,
+            })?;
+            before.copy_from_slice(&after);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Builds a capture from a per-signal list of levels, each entry lasting one time unit.
+    pub fn capture_from_levels(signals: &[&[bool]]) -> Capture {
+        let initial_levels: Vec<bool> = signals.iter().map(|s| s[0]).collect();
+        let mut events = Vec::new();
+        let len = signals.iter().map(|s| s.len()).max().unwrap();
+        for t in 1..len {
+            for (n, s) in signals.iter().enumerate() {
+                if t < s.len() && s[t] != s[t - 1] {
+                    events.push(MonitoringEvent {
+                        signal_index: n as u8,
+                        edge: if s[t] { Edge::Rising } else { Edge::Falling },
+                        timestamp: 1000 + t as u64,
+                    });
+                }
+            }
+        }
+        Capture {
+            clock_nature: ClockNature::Wallclock {
+                resolution: 1_000_000,
+                offset: None,
+            },
+            start_timestamp: 1000,
+            end_timestamp: 1000 + len as u64,
+            initial_levels,
+            events,
+        }
+    }
+
+    #[test]
+    fn test_steps() -> Result<()> {
+        let capture = capture_from_levels(&[&[false, true, true, false], &[true, false, false]]);
+        let mut steps = Vec::new();
+        capture.for_each_step(|step| {
+            steps.push((step.timestamp, step.edge(0), step.edge(1)));
+            Ok(())
+        })?;
+        assert_eq!(
+            steps,
+            vec![
+                (1001, Some(Edge::Rising), Some(Edge::Falling)),
+                (1003, Some(Edge::Falling), None),
+            ]
+        );
+        assert!(capture.check_signals(&[0, 1]).is_ok());
+        assert!(capture.check_signals(&[2]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_annotate::Annotate;
+
+use super::{Capture, Step};
+use crate::io::gpio::Edge;
+
+/// Decodes single-lane SPI traffic.  Data lines are sampled on the leading clock edge following
+/// chip select assertion if `cpha` is false, or on the trailing edge if `cpha` is true.
+#[derive(Clone, Debug)]
+pub struct SpiDecoder {
+    /// Index of the clock signal in the capture.
+    pub sck: usize,
+    /// Index of the active-low chip select signal.  If absent, the entire capture is decoded as
+    /// a single transaction.
+    pub cs: Option<usize>,
+    /// Index of the host to device data signal.
+    pub mosi: Option<usize>,
+    /// Index of the device to host data signal.
+    pub miso: Option<usize>,
+    /// Clock polarity, true if the clock idles high.
+    pub cpol: bool,
+    /// Clock phase.
+    pub cpha: bool,
+}
+
+/// Data exchanged while chip select was asserted.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Annotate)]
+pub struct SpiTransaction {
+    /// Timestamp of chip select assertion.
+    pub start: u64,
+    /// Timestamp of chip select deassertion (or end of capture).
+    pub end: u64,
+    #[annotate(format = hex)]
+    pub mosi: Vec<u8>,
+    #[annotate(format = hex)]
+    pub miso: Vec<u8>,
+    /// Number of clock cycles in excess of a whole number of bytes.
+    pub extra_bits: u8,
+}
+
+struct Shifter {
+    mosi: u8,
+    miso: u8,
+    bits: u8,
+}
+
+impl SpiDecoder {
+    /// Returns the SPI decoder corresponding to one of the four standard modes.
+    pub fn with_mode(
+        sck: usize,
+        cs: Option<usize>,
+        mosi: Option<usize>,
+        miso: Option<usize>,
+        mode: u8,
+    ) -> Self {
+        Self {
+            sck,
+            cs,
+            mosi,
+            miso,
+            cpol: mode & 2 != 0,
+            cpha: mode & 1 != 0,
+        }
+    }
+
+    fn sample_edge(&self) -> Edge {
+        if self.cpol == self.cpha {
+            Edge::Rising
+        } else {
+            Edge::Falling
+        }
+    }
+
+    pub fn decode(&self, capture: &Capture) -> Result<Vec<SpiTransaction>> {
+        let signals: Vec<usize> = [Some(self.sck), self.cs, self.mosi, self.miso]
+            .into_iter()
+            .flatten()
+            .collect();
+        capture.check_signals(&signals)?;
+
+        let mut transactions = Vec::new();
+        let mut current: Option<(SpiTransaction, Shifter)> = match self.cs {
+            Some(cs) if capture.initial_levels[cs] => None,
+            _ => Some(self.begin(capture.start_timestamp)),
+        };
+        let sample_edge = self.sample_edge();
+        capture.for_each_step(|step: &Step| {
+            // Data lines are sampled as they were before the clock edge, any simultaneous change
+            // belongs to the next bit.
+            if let Some((transaction, shifter)) = &mut current {
+                if step.edge(self.sck) == Some(sample_edge) {
+                    shifter.mosi =
+                        shifter.mosi << 1 | self.mosi.map_or(0, |n| step.before[n] as u8);
+                    shifter.miso =
+                        shifter.miso << 1 | self.miso.map_or(0, |n| step.before[n] as u8);
+                    shifter.bits += 1;
+                    if shifter.bits == 8 {
+                        if self.mosi.is_some() {
+                            transaction.mosi.push(shifter.mosi);
+                        }
+                        if self.miso.is_some() {
+                            transaction.miso.push(shifter.miso);
+                        }
+                        shifter.bits = 0;
+                    }
+                }
+            }
+            if let Some(cs) = self.cs {
+                match step.edge(cs) {
+                    Some(Edge::Falling) => current = Some(self.begin(step.timestamp)),
+                    Some(Edge::Rising) => {
+                        if let Some((transaction, shifter)) = current.take() {
+                            transactions.push(Self::finish(transaction, shifter, step.timestamp));
+                        }
+                    }
+                    None => (),
+                }
+            }
+            Ok(())
+        })?;
+        if let Some((transaction, shifter)) = current.take() {
+            transactions.push(Self::finish(transaction, shifter, capture.end_timestamp));
+        }
+        Ok(transactions)
+    }
+
+    fn begin(&self, timestamp: u64) -> (SpiTransaction, Shifter) {
+        (
+            SpiTransaction {
+                start: timestamp,
+                ..Default::default()
+            },
+            Shifter {
+                mosi: 0,
+                miso: 0,
+                bits: 0,
+            },
+        )
+    }
+
+    fn finish(mut transaction: SpiTransaction, shifter: Shifter, timestamp: u64) -> SpiTransaction {
+        transaction.end = timestamp;
+        transaction.extra_bits = shifter.bits;
+        transaction
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::logic_analyzer::test::capture_from_levels;
+
+    /// Produces CS, SCK, MOSI and MISO waveforms for the given byte exchange, in the given SPI
+    /// mode, using two time units per clock cycle.
+    fn waveforms(mode: u8, mosi: &[u8], miso: &[u8]) -> [Vec<bool>; 4] {
+        let cpol = mode & 2 != 0;
+        let cpha = mode & 1 != 0;
+        let mut cs = vec![true, false];
+        let mut sck = vec![cpol, cpol];
+        let mut dout = vec![false, false];
+        let mut din = vec![false, false];
+        for (&o, &i) in mosi.iter().zip(miso) {
+            for bit in (0..8).rev() {
+                let (o, i) = ((o >> bit) & 1 != 0, (i >> bit) & 1 != 0);
+                if cpha {
+                    // Data changes on the leading edge, sampled on the trailing edge.
+                    cs.extend([false, false]);
+                    sck.extend([!cpol, cpol]);
+                    dout.extend([o, o]);
+                    din.extend([i, i]);
+                } else {
+                    // Data set up before the leading edge, sampled on it.
+                    cs.extend([false, false, false]);
+                    sck.extend([cpol, !cpol, cpol]);
+                    dout.extend([o, o, o]);
+                    din.extend([i, i, i]);
+                }
+            }
+        }
+        cs.extend([false, true, true]);
+        sck.extend([cpol, cpol, cpol]);
+        dout.extend([false, false, false]);
+        din.extend([false, false, false]);
+        [cs, sck, dout, din]
+    }
+
+    #[test]
+    fn test_spi_modes() -> Result<()> {
+        for mode in 0..4 {
+            let [cs, sck, mosi, miso] = waveforms(mode, &[0x9f, 0x00, 0x5a], &[0xff, 0xc2, 0x20]);
+            let capture = capture_from_levels(&[&cs, &sck, &mosi, &miso]);
+            let decoder = SpiDecoder::with_mode(1, Some(0), Some(2), Some(3), mode);
+            let transactions = decoder.decode(&capture)?;
+            assert_eq!(transactions.len(), 1, "mode {}", mode);
+            assert_eq!(
+                transactions[0].mosi,
+                vec![0x9f, 0x00, 0x5a],
+                "mode {}",
+                mode
+            );
+            assert_eq!(
+                transactions[0].miso,
+                vec![0xff, 0xc2, 0x20],
+                "mode {}",
+                mode
+            );
+            assert_eq!(transactions[0].extra_bits, 0);
+            assert_eq!(transactions[0].start, 1001);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_spi_multiple_transactions() -> Result<()> {
+        let [mut cs, mut sck, mut mosi, mut miso] = waveforms(0, &[0x06], &[0x00]);
+        let [cs2, sck2, mosi2, miso2] = waveforms(0, &[0x05, 0x00], &[0x00, 0x03]);
+        cs.extend(cs2);
+        sck.extend(sck2);
+        mosi.extend(mosi2);
+        miso.extend(miso2);
+        let capture = capture_from_levels(&[&cs, &sck, &mosi, &miso]);
+
+        // Without MISO, only the host data is reported.
+        let decoder = SpiDecoder::with_mode(1, Some(0), Some(2), None, 0);
+        let transactions = decoder.decode(&capture)?;
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].mosi, vec![0x06]);
+        assert_eq!(transactions[1].mosi, vec![0x05, 0x00]);
+        assert!(transactions[1].miso.is_empty());
+        assert!(transactions[0].end < transactions[1].start);
+        Ok(())
+    }
+}
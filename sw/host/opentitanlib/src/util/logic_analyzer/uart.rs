@@ -0,0 +1,246 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::Serialize;
+use serde_annotate::Annotate;
+
+use super::Capture;
+use crate::io::gpio::Edge;
+use crate::io::uart::Parity;
+
+/// Decodes asynchronous serial data, LSB first, with a single start bit and at least one stop
+/// bit.
+#[derive(Clone, Debug)]
+pub struct UartDecoder {
+    /// Index of the data signal in the capture.
+    pub rx: usize,
+    /// Baud rate, or `None` to estimate it from the shortest pulses in the capture.
+    pub baud_rate: Option<u32>,
+    /// Number of data bits, between 5 and 8.
+    pub data_bits: u8,
+    pub parity: Parity,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Annotate)]
+pub struct UartFrame {
+    /// Timestamp of the falling edge of the start bit.
+    pub timestamp: u64,
+    #[annotate(format = hex)]
+    pub data: u8,
+    pub parity_error: bool,
+    /// The stop bit was not high.
+    pub framing_error: bool,
+    /// The line was held low for the duration of an entire frame.
+    pub is_break: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Annotate)]
+pub struct UartDecoding {
+    /// Baud rate used for decoding, if the transport clock is related to wall clock time.
+    pub baud_rate: Option<u32>,
+    /// Duration of a single bit, in transport timestamp units.
+    pub bit_time: f64,
+    pub frames: Vec<UartFrame>,
+}
+
+/// Intervals longer than this many times the shortest one are not considered when estimating
+/// the bit time, as they likely span idle periods.
+const MAX_AUTOBAUD_RATIO: f64 = 12.0;
+
+impl UartDecoder {
+    fn frame_bits(&self) -> u32 {
+        1 + self.data_bits as u32 + (self.parity != Parity::None) as u32
+    }
+
+    /// Estimates the duration of a bit, from intervals between edges.  Each interval is assumed
+    /// to be a whole number of bits, with the shortest being a single bit.
+    fn estimate_bit_time(edges: &[(u64, Edge)]) -> Result<f64> {
+        let intervals: Vec<u64> = edges
+            .windows(2)
+            .map(|w| w[1].0 - w[0].0)
+            .filter(|&d| d > 0)
+            .collect();
+        let shortest = *intervals
+            .iter()
+            .min()
+            .context("Too few edges to estimate the baud rate")? as f64;
+        let (mut total, mut bits) = (0.0, 0.0);
+        for &interval in &intervals {
+            let interval = interval as f64;
+            if interval <= shortest * MAX_AUTOBAUD_RATIO {
+                total += interval;
+                bits += (interval / shortest).round();
+            }
+        }
+        Ok(total / bits)
+    }
+
+    pub fn decode(&self, capture: &Capture) -> Result<UartDecoding> {
+        capture.check_signals(&[self.rx])?;
+        ensure!(
+            (5..=8).contains(&self.data_bits),
+            "UART decoding only supports between 5 and 8 data bits"
+        );
+        let edges = capture.edges(self.rx);
+        let bit_time = match (self.baud_rate, capture.resolution()) {
+            (Some(baud_rate), Some(resolution)) => resolution as f64 / baud_rate as f64,
+            (Some(_), None) => {
+                bail!("Transport clock is unrelated to wall clock time, cannot use fixed baud rate")
+            }
+            (None, _) => Self::estimate_bit_time(&edges)?,
+        };
+        let baud_rate = capture
+            .resolution()
+            .map(|resolution| (resolution as f64 / bit_time).round() as u32);
+
+        // Level of the line at a given point in time.
+        let level_at = |time: f64| -> bool {
+            let n = edges.partition_point(|(timestamp, _)| (*timestamp as f64) <= time);
+            match n {
+                0 => capture.initial_levels[self.rx],
+                _ => edges[n - 1].1 == Edge::Rising,
+            }
+        };
+
+        let mut frames = Vec::new();
+        let mut i = 0;
+        while i < edges.len() {
+            let (timestamp, edge) = edges[i];
+            if edge != Edge::Falling {
+                i += 1;
+                continue;
+            }
+            let sample_time = |bit: u32| timestamp as f64 + bit_time * (bit as f64 + 0.5);
+            let stop_time = sample_time(self.frame_bits());
+            if stop_time > capture.end_timestamp as f64 {
+                // Incomplete frame at the end of the capture.
+                break;
+            }
+            let mut frame = UartFrame {
+                timestamp,
+                ..Default::default()
+            };
+            let mut ones = 0;
+            for bit in 0..self.data_bits as u32 {
+                if level_at(sample_time(bit + 1)) {
+                    frame.data |= 1 << bit;
+                    ones += 1;
+                }
+            }
+            if self.parity != Parity::None {
+                let parity = level_at(sample_time(self.data_bits as u32 + 1));
+                let odd = (ones + parity as u32) & 1 != 0;
+                frame.parity_error = odd != (self.parity == Parity::Odd);
+            }
+            frame.framing_error = !level_at(stop_time);
+            frame.is_break = frame.framing_error
+                && frame.data == 0
+                && (1..self.frame_bits()).all(|bit| !level_at(sample_time(bit)));
+            if frame.is_break {
+                frame.parity_error = false;
+            }
+            frames.push(frame);
+
+            // Look for the next start bit after the stop bit, or, after the line is released in
+            // case of break or framing error.
+            i = edges.partition_point(|(timestamp, _)| (*timestamp as f64) <= stop_time);
+        }
+        Ok(UartDecoding {
+            baud_rate,
+            bit_time,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::bitbanging::uart::{
+        UartBitbangConfig, UartBitbangEncoder, UartStopBits,
+    };
+    use crate::util::logic_analyzer::test::capture_from_levels;
+
+    /// Produces a waveform with the given number of time units per bit.
+    fn waveform(
+        encoder: &UartBitbangEncoder<0>,
+        ticks_per_bit: usize,
+        data: &[u8],
+        brk: bool,
+    ) -> Vec<bool> {
+        let mut samples = Vec::new();
+        encoder.encode_characters(data, &mut samples);
+        if brk {
+            encoder.encode_break(&mut samples);
+        }
+        std::iter::repeat_n(true, 3 * ticks_per_bit)
+            .chain(
+                samples
+                    .into_iter()
+                    .flat_map(|s| std::iter::repeat_n(s & 1 != 0, ticks_per_bit)),
+            )
+            .chain(std::iter::repeat_n(true, ticks_per_bit))
+            .collect()
+    }
+
+    #[test]
+    fn test_uart_autobaud() -> Result<()> {
+        let config = UartBitbangConfig::new(8, UartStopBits::Stop1, 2, Parity::None)?;
+        let encoder = UartBitbangEncoder::<0>::new(config);
+        // 8 time units per bit, with a resolution of 1us gives 125000 baud.
+        let rx = waveform(&encoder, 8, b"OT\x00\xff", true);
+        let capture = capture_from_levels(&[&rx]);
+
+        let decoder = UartDecoder {
+            rx: 0,
+            baud_rate: None,
+            data_bits: 8,
+            parity: Parity::None,
+        };
+        let decoding = decoder.decode(&capture)?;
+        assert_eq!(decoding.baud_rate, Some(125000));
+        let data: Vec<u8> = decoding.frames.iter().map(|f| f.data).collect();
+        assert_eq!(data, b"OT\x00\xff\x00");
+        assert!(decoding.frames[..4]
+            .iter()
+            .all(|f| !f.framing_error && !f.is_break));
+        assert!(decoding.frames[4].is_break);
+
+        // Explicitly given baud rate.
+        let decoder = UartDecoder {
+            baud_rate: Some(125000),
+            ..decoder
+        };
+        assert_eq!(decoder.decode(&capture)?.frames.len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_uart_parity() -> Result<()> {
+        let config = UartBitbangConfig::new(7, UartStopBits::Stop2, 2, Parity::Even)?;
+        let encoder = UartBitbangEncoder::<0>::new(config);
+        let rx = waveform(&encoder, 4, b"\x11\x13", false);
+        let capture = capture_from_levels(&[&rx]);
+
+        let even = UartDecoder {
+            rx: 0,
+            baud_rate: Some(250000),
+            data_bits: 7,
+            parity: Parity::Even,
+        };
+        let decoding = even.decode(&capture)?;
+        assert_eq!(decoding.frames.len(), 2);
+        assert_eq!(decoding.frames[0].data, 0x11);
+        assert_eq!(decoding.frames[1].data, 0x13);
+        assert!(decoding.frames.iter().all(|f| !f.parity_error));
+
+        let odd = UartDecoder {
+            parity: Parity::Odd,
+            ..even
+        };
+        assert!(odd.decode(&capture)?.frames.iter().all(|f| f.parity_error));
+        Ok(())
+    }
+}
@@ -7,12 +7,14 @@ pub mod bitbang;
 pub mod bitfield;
 pub mod file;
 pub mod hexdump;
+pub mod logic_analyzer;
 pub mod num_de;
 pub mod parse_int;
 pub mod present;
 pub mod printer;
 pub mod raw_tty;
 pub mod rom_detect;
+pub mod secure_prng;
 pub mod serde;
 pub mod status;
 pub mod testing;
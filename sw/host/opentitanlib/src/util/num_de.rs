@@ -135,6 +135,12 @@ impl ParseInt for DeferredInit {
     }
 }
 
+impl From<Vec<u8>> for DeferredValue {
+    fn from(value: Vec<u8>) -> Self {
+        DeferredValue(DeferredInit::Initialized(value))
+    }
+}
+
 impl Deref for DeferredValue {
     type Target = [u8];
 
@@ -147,19 +153,19 @@ impl Deref for DeferredValue {
 }
 
 /// Wrapper type to force deserialization assuming octal encoding.
-#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
 pub struct OctEncoded<T>(#[serde(deserialize_with = "deserialize")] pub T)
 where
     T: ParseInt + fmt::Octal;
 
 /// Wrapper type to force deserialization assuming decimal encoding.
-#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
 pub struct DecEncoded<T>(#[serde(deserialize_with = "deserialize")] pub T)
 where
     T: ParseInt + fmt::Display;
 
 /// Wrapper type to force deserialization assuming hexadecimal encoding.
-#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
 pub struct HexEncoded<T>(#[serde(deserialize_with = "deserialize")] pub T)
 where
     T: ParseInt + fmt::LowerHex;
@@ -0,0 +1,181 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Deterministic random number generator used to derive netlist constants and OTP image values.
+//!
+//! This is a port of `util/topgen/secure_prng.py`, a CTR_DRBG based on AES-128 as described in
+//! NIST SP 800-90A, section 10.2.1, without derivation function.  The byte stream and the way it
+//! is turned into integers must match the Python implementation bit for bit, so that values
+//! drawn from the same seed agree with those of the hardware generators.
+
+use std::collections::VecDeque;
+
+use anyhow::{ensure, Result};
+use num_bigint_dig::BigUint;
+use num_traits::{ToPrimitive, Zero};
+use openssl::symm::{encrypt, Cipher};
+use rand::RngCore;
+
+/// Number of bytes produced by each invocation of the generate function.
+const GENERATE_BYTES: usize = 64;
+
+pub struct SecurePrng {
+    key: u128,
+    v: u128,
+    returned_bytes: VecDeque<u8>,
+}
+
+impl SecurePrng {
+    /// Instantiates the generator with `seed`, which is truncated to 256 bits.
+    pub fn new(seed: &BigUint) -> Result<SecurePrng> {
+        ensure!(!seed.is_zero(), "PRNG seeded with 0");
+        let seed = seed % (BigUint::from(1u8) << 256);
+        let mut entropy = seed.to_bytes_be();
+        entropy.splice(0..0, std::iter::repeat(0).take(32 - entropy.len()));
+
+        let mut prng = SecurePrng {
+            key: 0,
+            v: 0,
+            returned_bytes: VecDeque::new(),
+        };
+        prng.update(
+            u128::from_be_bytes(entropy[..16].try_into().unwrap()),
+            u128::from_be_bytes(entropy[16..].try_into().unwrap()),
+        );
+        Ok(prng)
+    }
+
+    fn encrypt(&self, blocks: &[u128]) -> Vec<u8> {
+        let input: Vec<u8> = blocks.iter().flat_map(|b| b.to_be_bytes()).collect();
+        let mut output = encrypt(Cipher::aes_128_ecb(), &self.key.to_be_bytes(), None, &input)
+            .expect("AES-128 encryption failed");
+        // Only full blocks are encrypted, drop the padding block.
+        output.truncate(input.len());
+        output
+    }
+
+    /// The CTR_DRBG update function, with the provided data split into two 128-bit halves.
+    fn update(&mut self, data0: u128, data1: u128) {
+        let ct = self.encrypt(&[self.v.wrapping_add(1), self.v.wrapping_add(2)]);
+        self.key = u128::from_be_bytes(ct[..16].try_into().unwrap()) ^ data0;
+        self.v = u128::from_be_bytes(ct[16..].try_into().unwrap()) ^ data1;
+    }
+
+    /// The CTR_DRBG generate function, producing 512 bits.
+    fn generate(&mut self) {
+        let blocks: Vec<u128> = (1..=(GENERATE_BYTES / 16) as u128)
+            .map(|i| self.v.wrapping_add(i))
+            .collect();
+        self.v = blocks[blocks.len() - 1];
+        let bytes = self.encrypt(&blocks);
+        self.returned_bytes.extend(bytes);
+        self.update(0, 0);
+    }
+
+    /// Returns the next byte of the random stream.
+    pub fn fetch_byte(&mut self) -> u8 {
+        if self.returned_bytes.is_empty() {
+            self.generate();
+        }
+        self.returned_bytes.pop_front().unwrap()
+    }
+
+    /// Draws a random integer of `bits` bits.
+    ///
+    /// Whole bytes of the stream are consumed most significant byte first, a partial last byte
+    /// contributes its most significant bits.
+    pub fn getrandbits(&mut self, bits: usize) -> BigUint {
+        let mut value = BigUint::zero();
+        let mut left = bits;
+        while left > 0 {
+            let byte = self.fetch_byte();
+            if left > 8 {
+                value = (value << 8) | BigUint::from(byte);
+                left -= 8;
+            } else {
+                value = (value << left) | BigUint::from(byte >> (8 - left));
+                left = 0;
+            }
+        }
+        value
+    }
+
+    /// Draws a random integer smaller than `n` by rejection sampling.
+    pub fn randbelow(&mut self, n: usize) -> usize {
+        assert!(n > 0, "empty range");
+        // Same as Python's `ceil(log(n, 2))`, which does not consume any bytes for `n == 1`.
+        let bits = ((n as f64).ln() / 2f64.ln()).ceil() as usize;
+        loop {
+            let value = self.getrandbits(bits).to_usize().unwrap();
+            if value < n {
+                return value;
+            }
+        }
+    }
+
+    /// Picks a random element of `items`.
+    pub fn choice<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.randbelow(items.len())]
+    }
+}
+
+/// Fills buffers with random integers of matching size in little-endian byte order, which is how
+/// values of `<random>` OTP items and netlist constants are drawn.
+impl RngCore for SecurePrng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for byte in dest.iter_mut().rev() {
+            *byte = self.fetch_byte();
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream() -> Result<()> {
+        // Computed with `util/topgen/secure_prng.py`.
+        let mut prng = SecurePrng::new(&BigUint::from(0x0123456789abcdefu64))?;
+        assert_eq!(
+            prng.getrandbits(128),
+            BigUint::from(0xf5be2aa6a2a2a0ef4c5ac43ba02035e8u128)
+        );
+        assert_eq!(prng.getrandbits(13), BigUint::from(0x11a3u32));
+        assert_eq!(prng.randbelow(1000), 17);
+        assert_eq!(prng.randbelow(1), 0);
+        // Crosses into the next generated block.
+        let mut bytes = [0u8; 64];
+        prng.fill_bytes(&mut bytes);
+        assert_eq!(
+            bytes.to_vec(),
+            hex::decode(
+                "31ef0f49b3024bee0c39fb839e92c44d31a4d1edddcfedee83043353e6d8ddd4\
+                 dcdb3cbf5260644bc13065a32aadb0b56c13fae3766c375505d1ee09998c893d"
+            )?
+            .into_iter()
+            .rev()
+            .collect::<Vec<u8>>()
+        );
+        assert!(SecurePrng::new(&BigUint::zero()).is_err());
+        Ok(())
+    }
+}
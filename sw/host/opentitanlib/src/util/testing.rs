@@ -5,8 +5,10 @@
 use crate::io::uart::Uart;
 use anyhow::{anyhow, Result};
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::ffi::OsStr;
 use std::io::{Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
 use std::time::Duration;
 
 // The transfer state allows us to intentionally inject errors into
@@ -112,3 +114,81 @@ impl Uart for ChildUart {
         }
     }
 }
+
+// One direction of a `MemoryUart` connection.
+#[derive(Default)]
+struct Pipe {
+    data: Mutex<VecDeque<u8>>,
+    ready: Condvar,
+}
+
+// An in-memory UART endpoint.  `MemoryUart::pair` creates two connected
+// endpoints which can be handed to separate threads to test both sides of
+// a protocol without an external program.
+pub struct MemoryUart {
+    rx: Arc<Pipe>,
+    tx: Arc<Pipe>,
+    wr: Mutex<TransferState>,
+}
+
+impl MemoryUart {
+    // How long `read` waits for data before assuming the peer has stalled.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn pair_corrupt(a: TransferState, b: TransferState) -> (Self, Self) {
+        let ab = Arc::new(Pipe::default());
+        let ba = Arc::new(Pipe::default());
+        (
+            MemoryUart {
+                rx: Arc::clone(&ba),
+                tx: Arc::clone(&ab),
+                wr: Mutex::new(a),
+            },
+            MemoryUart {
+                rx: ab,
+                tx: ba,
+                wr: Mutex::new(b),
+            },
+        )
+    }
+
+    pub fn pair() -> (Self, Self) {
+        Self::pair_corrupt(Default::default(), Default::default())
+    }
+}
+
+impl Uart for MemoryUart {
+    fn get_baudrate(&self) -> Result<u32> {
+        Ok(0)
+    }
+    fn set_baudrate(&self, _baudrate: u32) -> Result<()> {
+        Ok(())
+    }
+    fn read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let data = self.rx.data.lock().unwrap();
+        let (mut data, _) = self
+            .rx
+            .ready
+            .wait_timeout_while(data, timeout, |d| d.is_empty())
+            .unwrap();
+        let n = std::cmp::min(buf.len(), data.len());
+        for (dst, src) in buf.iter_mut().zip(data.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        match self.read_timeout(buf, Self::TIMEOUT)? {
+            0 => Err(anyhow!("timed out waiting for peer")),
+            n => Ok(n),
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        let mut data = buf.to_vec();
+        self.wr.lock().unwrap().maybe_corrupt(&mut data);
+        self.tx.data.lock().unwrap().extend(data);
+        self.tx.ready.notify_all();
+        Ok(())
+    }
+}
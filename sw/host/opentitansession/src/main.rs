@@ -19,7 +19,10 @@ use std::str::FromStr;
 use std::time::Duration;
 
 use opentitanlib::backend;
+use opentitanlib::io::jtag::JtagParams;
+use opentitanlib::proxy::security::SessionSecurityOpts;
 use opentitanlib::proxy::SessionHandler;
+use opentitanlib::transport::Transport;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -37,10 +40,22 @@ struct Opts {
     #[command(flatten)]
     backend_opts: backend::BackendOpts,
 
+    #[command(flatten)]
+    security_opts: SessionSecurityOpts,
+
+    /// OpenOCD and adapter settings for JTAG connections requested by clients.
+    #[command(flatten)]
+    jtag_params: JtagParams,
+
     /// Stop a running session, optionally combine with --listen_port for disambiguation.
     #[arg(long)]
     stop: bool,
 
+    /// Print the leases held by clients of a running session, optionally combine with
+    /// --listen_port for disambiguation.
+    #[arg(long)]
+    leases: bool,
+
     /// Optional, defaults to 9900 or nearest higher available port.
     #[arg(long)]
     listen_port: Option<u16>,
@@ -145,7 +160,12 @@ fn start_session(run_file_fn: impl FnOnce(u16) -> PathBuf) -> Result<Box<dyn Ser
 // socket, then report the chosen port number to the parent process by means of a serialized
 // `SessionStartResult` sent through the stdout anonymous pipe, and finally enter an infnite
 // loop, processing connections on that socket
-fn session_child(listen_port: Option<u16>, backend_opts: &backend::BackendOpts) -> Result<()> {
+fn session_child(
+    listen_port: Option<u16>,
+    backend_opts: &backend::BackendOpts,
+    security_opts: &SessionSecurityOpts,
+    jtag_params: &JtagParams,
+) -> Result<()> {
     // Open connection to transport backend (HyperDebug or other debugger device) based on
     // command line arguments.
     let transport = backend::create(backend_opts)?;
@@ -158,7 +178,12 @@ fn session_child(listen_port: Option<u16>, backend_opts: &backend::BackendOpts)
     let _maintain_connection = transport.maintain_connection()?;
 
     // Bind to TCP socket, in preparation for servicing requests from network.
-    let mut session = SessionHandler::init(&transport, listen_port)?;
+    let mut session = SessionHandler::init(
+        &transport,
+        listen_port,
+        security_opts.load()?,
+        jtag_params.clone(),
+    )?;
 
     // Instantiation of Transport backend, and binding to a socket was successful, now go
     // through the process of making this process a daemon, disconnected from the
@@ -239,7 +264,12 @@ fn main() -> Result<()> {
         rustix::process::set_parent_process_death_signal(Some(Signal::TERM))?;
 
         let transport = backend::create(&opts.backend_opts)?;
-        let mut session = SessionHandler::init(&transport, opts.listen_port)?;
+        let mut session = SessionHandler::init(
+            &transport,
+            opts.listen_port,
+            opts.security_opts.load()?,
+            opts.jtag_params.clone(),
+        )?;
         println!("Listening on port {}", session.get_port());
         session.run_loop()?;
         return Ok(());
@@ -247,7 +277,12 @@ fn main() -> Result<()> {
 
     if opts.child {
         // This process is a child, which is supposed to stay running as a daemon.
-        match session_child(opts.listen_port, &opts.backend_opts) {
+        match session_child(
+            opts.listen_port,
+            &opts.backend_opts,
+            &opts.security_opts,
+            &opts.jtag_params,
+        ) {
             Ok(()) => process::exit(0),
             Err(e) => {
                 // Report any error to parent process though stdout pipe.
@@ -271,7 +306,14 @@ fn main() -> Result<()> {
         p
     };
 
-    let value = if opts.stop {
+    let value: Box<dyn Serialize> = if opts.leases {
+        // Query the lease table of the daemon, connecting as any other client would.
+        let proxy = opts
+            .backend_opts
+            .proxy_opts
+            .open(opts.listen_port.unwrap_or(9900))?;
+        Box::new(proxy.proxy_ops()?.leases()?)
+    } else if opts.stop {
         // Send signal to daemon process to stop
         stop_session(run_file_fn, opts.listen_port.unwrap_or(9900))?
     } else {
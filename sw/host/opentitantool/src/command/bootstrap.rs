@@ -9,7 +9,9 @@ use std::any::Any;
 use std::path::PathBuf;
 
 use opentitanlib::app::command::CommandDispatch;
+use opentitanlib::app::parallel::{self, DeviceSpec};
 use opentitanlib::app::{StagedProgressBar, TransportWrapper};
+use opentitanlib::backend::BackendOpts;
 use opentitanlib::bootstrap::{Bootstrap, BootstrapOptions, BootstrapProtocol};
 use opentitanlib::image::image::ImageAssembler;
 use opentitanlib::transport;
@@ -29,6 +31,10 @@ pub struct BootstrapCommand {
     /// An image to bootstrap or multiple filename@offset specifiers to assemble into a bootstrap image.
     #[arg(value_name = "FILE", required = true, num_args = 1..)]
     filename: Vec<String>,
+    /// Bootstrap several devices in parallel, each given as `[INTERFACE:]USB_SERIAL`, with the
+    /// interface defaulting to `--interface`.  Other backend options apply to every device.
+    #[arg(long = "device", value_name = "DEVICE")]
+    devices: Vec<DeviceSpec>,
 }
 
 impl BootstrapCommand {
@@ -55,6 +61,42 @@ impl BootstrapCommand {
                 .with_context(|| format!("Failed to read {}", self.filename[0]))?)
         }
     }
+
+    /// Whether several devices were given with `--device`.
+    pub fn is_multi_device(&self) -> bool {
+        !self.devices.is_empty()
+    }
+
+    /// Bootstraps each of the `--device` targets in parallel.
+    ///
+    /// Each device gets a transport of its own, instantiated from `backend_opts`.  Its default
+    /// configuration is applied and `init` is run on it before the bootstrap.
+    pub fn bootstrap_devices<F>(
+        &self,
+        backend_opts: &BackendOpts,
+        init: F,
+    ) -> Result<Option<Box<dyn Annotate>>>
+    where
+        F: Fn(&TransportWrapper) -> Result<()> + Sync,
+    {
+        ensure!(
+            self.bootstrap_options.protocol != BootstrapProtocol::Emulator,
+            "The `emulator` protocol does not support multiple devices"
+        );
+        let payload = self.payload()?;
+        let devices = self
+            .devices
+            .iter()
+            .map(|device| (device.to_string(), device.backend_opts(backend_opts)))
+            .collect::<Vec<_>>();
+        let report = parallel::run_on_devices(&devices, |transport, progress| {
+            transport.apply_default_configuration(None)?;
+            init(transport)?;
+            Bootstrap::update_with_progress(transport, &self.bootstrap_options, &payload, progress)
+        });
+        report.check()?;
+        Ok(Some(Box::new(report)))
+    }
 }
 
 impl CommandDispatch for BootstrapCommand {
@@ -63,6 +105,11 @@ impl CommandDispatch for BootstrapCommand {
         _context: &dyn Any,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
+        // Multiple devices are handled before any transport is created, see `main`.
+        ensure!(
+            !self.is_multi_device(),
+            "`--device` is only supported by a top-level `bootstrap` command"
+        );
         if self.bootstrap_options.protocol == BootstrapProtocol::Emulator {
             return self.bootstrap_using_direct_emulator_integration(transport);
         }
@@ -73,3 +120,60 @@ impl CommandDispatch for BootstrapCommand {
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use opentitanlib::util::tmpfilename;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Parser)]
+    struct TestBackend {
+        #[command(flatten)]
+        backend_opts: BackendOpts,
+    }
+
+    #[derive(Debug, Parser)]
+    struct TestCommand {
+        #[command(flatten)]
+        bootstrap: BootstrapCommand,
+    }
+
+    #[test]
+    fn test_bootstrap_devices() -> Result<()> {
+        let filename = tmpfilename("test_bootstrap_devices.bin");
+        std::fs::write(&filename, vec![0x5au8; 8192])?;
+        let backend = TestBackend::try_parse_from(["test", "--interface", "sim"])?;
+        let cmd = TestCommand::try_parse_from([
+            "bootstrap",
+            "--reset-delay=1ms",
+            "--device=sim0",
+            "--device=sim:sim1",
+            &filename,
+        ])?;
+        assert!(cmd.bootstrap.is_multi_device());
+
+        // The simulated devices can only be bootstrapped once their default configuration has
+        // been applied.
+        let initialized = AtomicUsize::new(0);
+        let report = cmd
+            .bootstrap
+            .bootstrap_devices(&backend.backend_opts, |_transport| {
+                initialized.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })?;
+        assert!(report.is_some());
+        assert_eq!(initialized.load(Ordering::Relaxed), 2);
+
+        // A failing `init` fails the device it ran on.
+        let err = cmd
+            .bootstrap
+            .bootstrap_devices(&backend.backend_opts, |_transport| {
+                anyhow::bail!("init failed")
+            })
+            .unwrap_err();
+        assert!(err.to_string().starts_with("2 of 2 devices failed"));
+        Ok(())
+    }
+}
@@ -2,8 +2,8 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::Result;
-use clap::{Args, Subcommand};
+use anyhow::{ensure, Result};
+use clap::{Args, Subcommand, ValueEnum};
 use serde_annotate::Annotate;
 use std::any::Any;
 use std::borrow::Borrow;
@@ -12,15 +12,19 @@ use std::fs::File;
 use std::io::{Read, Write};
 use std::rc::Rc;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::TransportWrapper;
 use opentitanlib::io::gpio::{
     ClockNature, Edge, GpioPin, MonitoringReadResponse, MonitoringStartResponse, PinMode, PullMode,
 };
+use opentitanlib::io::uart::Parity;
 use opentitanlib::transport::Capability;
 use opentitanlib::util::file;
+use opentitanlib::util::logic_analyzer::{
+    Capture, I2cDecoder, I2cTransfer, SpiDecoder, SpiTransaction, UartDecoder,
+};
 use opentitanlib::util::raw_tty::RawTty;
 use opentitanlib::util::voltage::Voltage;
 
@@ -237,6 +241,7 @@ pub enum GpioMonitoringCommand {
     Start(GpioMonitoringStart),
     Read(GpioMonitoringRead),
     Vcd(GpioMonitoringVcd),
+    Decode(GpioMonitoringDecode),
 }
 
 #[derive(Debug, Args)]
@@ -541,6 +546,204 @@ impl CommandDispatch for GpioMonitoringVcd {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DecodeProtocol {
+    /// Pins are SCK and CS, optionally followed by MOSI and MISO.
+    Spi,
+    /// Pins are SCL and SDA.
+    I2c,
+    /// Single pin carrying serial data.
+    Uart,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DecodeParity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Debug, Args)]
+/// Capture edges on a set of pins, and decode them according to a serial protocol, turning the
+/// transport into a protocol analyzer.  Capturing runs for the given duration, or until the user
+/// presses Ctrl-C.
+pub struct GpioMonitoringDecode {
+    #[arg(long, value_enum)]
+    pub protocol: DecodeProtocol,
+
+    /// The list of GPIO pins to monitor (space separated), order depends on the protocol.
+    pub pins: Vec<String>,
+
+    /// How long to capture, if not given, capture until Ctrl-C is pressed.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub duration: Option<Duration>,
+
+    /// SPI mode (0-3), determining clock polarity and phase.
+    #[arg(long, default_value = "0", value_parser = clap::value_parser!(u8).range(0..=3))]
+    pub spi_mode: u8,
+
+    /// UART baud rate, if not given, it will be estimated from the capture.
+    #[arg(long)]
+    pub baud_rate: Option<u32>,
+
+    /// UART data bits per frame.
+    #[arg(long, default_value = "8")]
+    pub data_bits: u8,
+
+    /// UART parity.
+    #[arg(long, value_enum, default_value = "none")]
+    pub parity: DecodeParity,
+
+    /// Optional file to record the raw capture in VCD format.
+    #[arg(short, long)]
+    outfile: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct GpioMonitoringSpiResult {
+    pub transactions: Vec<SpiTransaction>,
+}
+
+#[derive(serde::Serialize)]
+pub struct GpioMonitoringI2cResult {
+    pub transfers: Vec<I2cTransfer>,
+}
+
+impl GpioMonitoringDecode {
+    fn check_pins(&self) -> Result<()> {
+        let (min, max) = match self.protocol {
+            DecodeProtocol::Spi => (2, 4),
+            DecodeProtocol::I2c => (2, 2),
+            DecodeProtocol::Uart => (1, 1),
+        };
+        ensure!(
+            (min..=max).contains(&self.pins.len()),
+            "Protocol {:?} requires between {} and {} pins",
+            self.protocol,
+            min,
+            max
+        );
+        Ok(())
+    }
+
+    /// Collects events until the requested duration has elapsed, or the user presses Ctrl-C.
+    fn capture(&self, transport: &TransportWrapper) -> Result<Capture> {
+        let gpio_monitoring = transport.gpio_monitoring()?;
+        let gpio_pins = transport.gpio_pins(&self.pins)?;
+        let pins = gpio_pins
+            .iter()
+            .map(Rc::borrow)
+            .collect::<Vec<&dyn GpioPin>>();
+
+        let mut stdin = match self.duration {
+            Some(_) => None,
+            None => {
+                eprint!("[CTRL+C] to stop capturing  ");
+                Some(RawTty::new(std::io::stdin())?)
+            }
+        };
+        let deadline = self.duration.map(|duration| Instant::now() + duration);
+
+        let clock_nature = gpio_monitoring.get_clock_nature()?;
+        let initial = gpio_monitoring.monitoring_start(&pins)?;
+        if let Some(file) = &self.outfile {
+            write_vcd_header(file, &self.pins, clock_nature, &initial)?;
+        }
+        let mut capture = Capture::new(clock_nature, initial);
+
+        // Read periodically, to prevent overflow of the event buffer in the transport.
+        loop {
+            let resp = gpio_monitoring.monitoring_read(&pins, true)?;
+            if let Some(file) = &self.outfile {
+                append_vcd_data(file, &resp, false)?;
+            }
+            let delay = if resp.events.is_empty() {
+                Duration::from_millis(10)
+            } else {
+                Duration::from_millis(0)
+            };
+            capture.extend(resp);
+            match (deadline, stdin.as_mut()) {
+                (Some(deadline), _) => {
+                    if Instant::now() >= deadline {
+                        break;
+                    }
+                    std::thread::sleep(delay);
+                }
+                (None, Some(stdin)) => {
+                    if file::wait_read_timeout(&*stdin, delay).is_ok() {
+                        let mut buf = [0u8; 1];
+                        let len = stdin.read(&mut buf)?;
+                        if len == 1 && buf[0] == 3 {
+                            // CtrlC
+                            break;
+                        }
+                    }
+                }
+                (None, None) => unreachable!(),
+            }
+        }
+
+        let resp = gpio_monitoring.monitoring_read(&pins, false)?;
+        if let Some(file) = &self.outfile {
+            append_vcd_data(file, &resp, true)?;
+        }
+        capture.extend(resp);
+        if stdin.is_some() {
+            eprintln!("\r");
+        }
+        Ok(capture)
+    }
+}
+
+impl CommandDispatch for GpioMonitoringDecode {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        transport
+            .capabilities()?
+            .request(Capability::GPIO | Capability::GPIO_MONITORING)
+            .ok()?;
+        self.check_pins()?;
+        let capture = self.capture(transport)?;
+        match self.protocol {
+            DecodeProtocol::Spi => {
+                let decoder = SpiDecoder::with_mode(
+                    0,
+                    Some(1),
+                    (self.pins.len() > 2).then_some(2),
+                    (self.pins.len() > 3).then_some(3),
+                    self.spi_mode,
+                );
+                Ok(Some(Box::new(GpioMonitoringSpiResult {
+                    transactions: decoder.decode(&capture)?,
+                })))
+            }
+            DecodeProtocol::I2c => {
+                let decoder = I2cDecoder { scl: 0, sda: 1 };
+                Ok(Some(Box::new(GpioMonitoringI2cResult {
+                    transfers: decoder.decode(&capture)?,
+                })))
+            }
+            DecodeProtocol::Uart => {
+                let decoder = UartDecoder {
+                    rx: 0,
+                    baud_rate: self.baud_rate,
+                    data_bits: self.data_bits,
+                    parity: match self.parity {
+                        DecodeParity::None => Parity::None,
+                        DecodeParity::Even => Parity::Even,
+                        DecodeParity::Odd => Parity::Odd,
+                    },
+                };
+                Ok(Some(Box::new(decoder.decode(&capture)?)))
+            }
+        }
+    }
+}
+
 #[derive(Debug, Args)]
 /// Remove a configuration-named pin strapping
 pub struct GpioRemoveStrapping {
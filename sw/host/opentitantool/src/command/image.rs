@@ -4,7 +4,7 @@
 
 use anyhow::{bail, ensure, Context, Result};
 use clap::{Args, Subcommand};
-use serde_annotate::Annotate;
+use serde_annotate::{serialize, Annotate};
 use std::any::Any;
 use std::collections::HashSet;
 use std::convert::TryInto;
@@ -24,6 +24,8 @@ use opentitanlib::image::image::{self, ImageAssembler};
 use opentitanlib::image::manifest::{ManifestExtSpxSignature, ManifestKind};
 use opentitanlib::image::manifest_def::ManifestSpec;
 use opentitanlib::image::manifest_ext::{ManifestExtEntry, ManifestExtId, ManifestExtSpec};
+use opentitanlib::image::policy::{ManifestPolicy, Violation};
+use opentitanlib::image::signing_bundle::SigningBundle;
 use opentitanlib::util::file::{FromReader, ToWriter};
 use opentitanlib::util::parse_int::ParseInt;
 use sphincsplus::{DecodeKey, SpxDomain, SpxError, SpxPublicKey, SpxSecretKey};
@@ -386,6 +388,112 @@ impl CommandDispatch for ManifestVerifyCommand {
     }
 }
 
+/// Manifest lint command.
+#[derive(Debug, Args)]
+pub struct ManifestLintCommand {
+    /// Filename for the image to check.
+    image: PathBuf,
+    /// Filename for the HJSON policy to check the image against.
+    #[arg(long)]
+    policy: PathBuf,
+    /// Filename for a JSON report of the violations, written even if the check fails.
+    #[arg(long)]
+    report: Option<PathBuf>,
+}
+
+#[derive(Debug, serde::Serialize, Annotate)]
+pub struct ManifestLintResult {
+    violations: Vec<Violation>,
+}
+
+impl CommandDispatch for ManifestLintCommand {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let image = image::Image::read_from_file(&self.image)?;
+        let policy = ManifestPolicy::read_from_file(&self.policy)?;
+        let result = ManifestLintResult {
+            violations: policy.check(&image)?,
+        };
+        if let Some(report) = &self.report {
+            let mut file = File::create(report)?;
+            file.write_all(serialize(&result)?.to_json().to_string().as_bytes())?;
+        }
+        if !result.violations.is_empty() {
+            let lines = result
+                .violations
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>();
+            bail!(
+                "{} policy violation(s) in {:?}:\n{}",
+                lines.len(),
+                self.image,
+                lines.join("\n")
+            );
+        }
+        Ok(Some(Box::new(result)))
+    }
+}
+
+/// Export the data to be signed for detached signing.
+#[derive(Debug, Args)]
+pub struct ManifestExportTbsCommand {
+    /// Filename for the image to be signed.
+    image: PathBuf,
+    /// The SPX signature domain (None, Pure, PreHashedSha256)
+    #[arg(long, default_value_t = SpxDomain::default())]
+    domain: SpxDomain,
+    /// Filename for the JSON signing bundle.
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+impl CommandDispatch for ManifestExportTbsCommand {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let image = image::Image::read_from_file(&self.image)?;
+        image
+            .manifest_sanity_check()
+            .context("Image doesn't appear to contain a manifest, or the manifest is corrupted")?;
+        let bundle = SigningBundle::from_image(&image, self.domain)?;
+        bundle.write_to_file(&self.output)?;
+        Ok(None)
+    }
+}
+
+/// Import verified signatures from a signing bundle.
+#[derive(Debug, Args)]
+pub struct ManifestImportSignaturesCommand {
+    /// Filename for the image to update.
+    image: PathBuf,
+    /// Filename for the JSON signing bundle, with signatures filled in.
+    #[arg(short, long)]
+    bundle: PathBuf,
+    /// Filename to write the output to instead of updating the input file.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+impl CommandDispatch for ManifestImportSignaturesCommand {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let mut image = image::Image::read_from_file(&self.image)?;
+        let bundle = SigningBundle::read_from_file(&self.bundle)?;
+        bundle.apply(&mut image)?;
+        image.write_to_file(self.output.as_ref().unwrap_or(&self.image))?;
+        Ok(None)
+    }
+}
+
 /// Compute digest command.
 #[derive(Debug, Args)]
 pub struct DigestCommand {
@@ -454,6 +562,9 @@ pub enum ManifestCommand {
     Show(ManifestShowCommand),
     Update(ManifestUpdateCommand),
     Verify(ManifestVerifyCommand),
+    Lint(ManifestLintCommand),
+    ExportTbs(ManifestExportTbsCommand),
+    ImportSignatures(ManifestImportSignaturesCommand),
 }
 
 #[derive(Debug, Subcommand, CommandDispatch)]
@@ -3,18 +3,26 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::any::Any;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
 
-use anyhow::{ensure, Result};
+use anyhow::{anyhow, ensure, Result};
 use clap::{Args, Subcommand};
-use hex::decode;
 use humantime::parse_duration;
 use serde_annotate::Annotate;
 
+use hsmtool::error::HsmError;
+use hsmtool::module::{self, Module};
+use hsmtool::util::attribute::{AttributeError, AttributeMap, AttributeType};
+use hsmtool::util::helper;
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::TransportWrapper;
-use opentitanlib::dif::lc_ctrl::{DifLcCtrlState, DifLcCtrlToken, LcCtrlReg, LcCtrlStatus};
+use opentitanlib::dif::lc_ctrl::{
+    DifLcCtrlState, DifLcCtrlToken, DifLcCtrlTokenKind, LcCtrlReg, LcCtrlStatus,
+};
 use opentitanlib::io::jtag::{Jtag, JtagParams, JtagTap};
+use opentitanlib::test_utils::lc_plan::{LcTokenFile, LcTokenStore, LcTransitionPlan};
 use opentitanlib::test_utils::lc_transition::{trigger_lc_transition, trigger_volatile_raw_unlock};
 
 #[derive(serde::Serialize)]
@@ -49,27 +57,6 @@ fn check_lc_transition(
     Ok(())
 }
 
-/// Parses an unlock token string.
-fn parse_token_str(token: &str) -> Result<DifLcCtrlToken> {
-    let hex_str_no_sep = token.replace('_', "");
-    let hex_str_prefix = "0x";
-    let sanitized_hex_str = if token.starts_with(hex_str_prefix) {
-        hex_str_no_sep.strip_prefix(hex_str_prefix).unwrap()
-    } else {
-        hex_str_no_sep.as_str()
-    };
-    let token_bytes_vec = decode(sanitized_hex_str)?
-        .chunks(4)
-        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
-        .collect::<Vec<u32>>();
-    let length = token_bytes_vec.len();
-    ensure!(
-        length == 4,
-        "Expected a token of length 16-bytes but it was {length}-bytes."
-    );
-    Ok(DifLcCtrlToken::from(token_bytes_vec))
-}
-
 #[derive(Debug, Args)]
 /// Reads the device life cycle state over JTAG.
 pub struct LcStateRead {
@@ -232,7 +219,7 @@ impl CommandDispatch for RawUnlock {
             .create(transport)?
             .connect(JtagTap::LcTap)?;
 
-        let token = parse_token_str(self.token.as_str())?;
+        let token = DifLcCtrlToken::from_str(&self.token)?;
         check_lc_transition(&mut *jtag, DifLcCtrlState::TestUnlocked0, token)?;
 
         // ROM execution is not enabled in the OTP so we can safely reconnect to
@@ -306,7 +293,7 @@ impl CommandDispatch for Transition {
         std::thread::sleep(Duration::from_millis(50));
 
         // Check whether this is a valid transition.
-        let token = parse_token_str(self.token.as_str())?;
+        let token = DifLcCtrlToken::from_str(&self.token)?;
         check_lc_transition(&mut *jtag, self.target_lc_state, token)?;
 
         trigger_lc_transition(
@@ -465,7 +452,7 @@ impl CommandDispatch for VolatileRawUnlock {
             .create(transport)?
             .connect(JtagTap::LcTap)?;
 
-        let token = parse_token_str(self.token.as_str())?;
+        let token = DifLcCtrlToken::from_str(&self.token)?;
         check_lc_transition(&mut *jtag, DifLcCtrlState::TestUnlocked0, token)?;
 
         // ROM execution is not enabled in the OTP so we can safely reconnect to
@@ -497,6 +484,147 @@ impl CommandDispatch for VolatileRawUnlock {
     }
 }
 
+#[derive(Debug, Args)]
+/// Plans the sequence of transitions into the target state and performs them.
+pub struct Plan {
+    /// The target life cycle state.
+    #[arg(value_parser = DifLcCtrlState::parse_lc_state_str)]
+    pub target_lc_state: DifLcCtrlState,
+
+    /// Plan from this life cycle state instead of reading it from the device.
+    #[arg(long, value_parser = DifLcCtrlState::parse_lc_state_str, requires = "transition_count")]
+    pub current: Option<DifLcCtrlState>,
+
+    /// Plan with this transition count instead of reading it from the device.
+    #[arg(long, requires = "current")]
+    pub transition_count: Option<u32>,
+
+    /// Print the plan without performing any transitions.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// HJSON file mapping token kinds (e.g. `test_exit`) to token hexstrings.  Takes precedence
+    /// over the HSM.
+    #[arg(long)]
+    pub tokens: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub hsm: HsmTokenParams,
+
+    /// Reset duration when switching the LC TAP straps.
+    #[arg(long, value_parser = parse_duration, default_value = "100ms")]
+    pub reset_delay: Duration,
+
+    #[command(flatten)]
+    pub jtag_params: JtagParams,
+}
+
+#[derive(Debug, Args)]
+pub struct HsmTokenParams {
+    /// Path to a PKCS11 shared library holding the tokens as data objects.
+    #[arg(long, env = "HSMTOOL_MODULE")]
+    pub hsm_module: Option<String>,
+
+    /// HSM token to use.
+    #[arg(long, env = "HSMTOOL_TOKEN")]
+    pub hsm_token: Option<String>,
+
+    /// HSM user type ('so' or 'user').
+    #[arg(long, env = "HSMTOOL_USER")]
+    pub hsm_user: Option<String>,
+
+    /// HSM pin.
+    #[arg(long, env = "HSMTOOL_PIN")]
+    pub hsm_pin: Option<String>,
+
+    /// Prefix of the HSM object labels; the token kind is appended (e.g. `lc_test_exit`).
+    #[arg(long, default_value = "lc_")]
+    pub hsm_label_prefix: String,
+}
+
+/// Life cycle tokens stored as PKCS#11 objects whose value is either the 16 raw token bytes or
+/// the token hexstring.
+struct HsmTokenStore {
+    hsm: Module,
+    label_prefix: String,
+}
+
+impl HsmTokenStore {
+    fn open(params: &HsmTokenParams) -> Result<Option<Self>> {
+        let Some(hsm_module) = &params.hsm_module else {
+            return Ok(None);
+        };
+        let token = params
+            .hsm_token
+            .as_deref()
+            .ok_or_else(|| anyhow!("An HSM token is required to read LC tokens"))?;
+        let user = params
+            .hsm_user
+            .as_deref()
+            .map(module::parse_user_type)
+            .transpose()?;
+        let mut hsm = Module::initialize(hsm_module)?;
+        hsm.connect(token, user, params.hsm_pin.as_deref())?;
+        Ok(Some(HsmTokenStore {
+            hsm,
+            label_prefix: params.hsm_label_prefix.clone(),
+        }))
+    }
+}
+
+impl LcTokenStore for HsmTokenStore {
+    fn token(&self, kind: DifLcCtrlTokenKind) -> Result<DifLcCtrlToken> {
+        let session = self.hsm.get_session().ok_or(HsmError::SessionRequired)?;
+        let label = format!("{}{kind}", self.label_prefix);
+        let attr = helper::search_spec(None, Some(&label))?;
+        let object = helper::find_one_object(session, &attr)?;
+        let map = AttributeMap::from_object(session, object)?;
+        let value = map
+            .get(&AttributeType::Value)
+            .ok_or(AttributeError::AttributeNotFound(AttributeType::Value))?;
+        let value = Vec::<u8>::try_from(value)?;
+        match <[u8; 16]>::try_from(value.as_slice()) {
+            Ok(bytes) => Ok(DifLcCtrlToken::from(bytes)),
+            Err(_) => DifLcCtrlToken::from_str(std::str::from_utf8(&value)?.trim()),
+        }
+    }
+}
+
+impl CommandDispatch for Plan {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let plan = match (self.current, self.transition_count) {
+            (Some(current), Some(transition_count)) => {
+                LcTransitionPlan::new(current, transition_count, self.target_lc_state)?
+            }
+            _ => LcTransitionPlan::for_device(
+                transport,
+                &self.jtag_params,
+                self.reset_delay,
+                self.target_lc_state,
+            )?,
+        };
+        if self.dry_run {
+            return Ok(Some(Box::new(plan)));
+        }
+
+        let store: Box<dyn LcTokenStore> = if let Some(tokens) = &self.tokens {
+            Box::new(LcTokenFile::from_file(tokens)?)
+        } else if let Some(hsm) = HsmTokenStore::open(&self.hsm)? {
+            Box::new(hsm)
+        } else {
+            Box::new(LcTokenFile::default())
+        };
+        plan.execute(transport, &self.jtag_params, self.reset_delay, &*store)?;
+        Ok(Some(Box::new(LcStateReadResult {
+            lc_state: plan.target,
+        })))
+    }
+}
+
 #[derive(Debug, Subcommand, CommandDispatch)]
 /// Commands for performing various device life cycle operations.
 pub enum LcCommand {
@@ -508,4 +636,5 @@ pub enum LcCommand {
     Status(Status),
     TransitionCount(TransitionCount),
     VolatileRawUnlock(VolatileRawUnlock),
+    Plan(Plan),
 }
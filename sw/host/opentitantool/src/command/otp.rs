@@ -6,18 +6,28 @@ use std::any::Any;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Result;
+use humantime::parse_duration;
 
 use serde_annotate::{serialize, Annotate, Base};
 
 use clap::{Args, Subcommand};
+use num_bigint_dig::BigUint;
 
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::TransportWrapper;
+use opentitanlib::io::jtag::{JtagParams, JtagTap};
 use opentitanlib::otp::alert_handler::AlertRegs;
-use opentitanlib::otp::lc_state::LcStateVal;
+use opentitanlib::otp::lc_state::{LcState, LcStateEncoding, LcStateVal};
+use opentitanlib::otp::otp_dump::OtpDump;
 use opentitanlib::otp::otp_img::{OtpImg, OtpImgItem, OtpImgPartition, OtpImgValue};
+use opentitanlib::otp::otp_mem_img::OtpMemImg;
+use opentitanlib::otp::otp_mmap::OtpMap;
+use opentitanlib::test_utils::otp_ctrl::OtpParam;
+use opentitanlib::util::num_de::DecEncoded;
+use opentitanlib::util::parse_int::ParseInt;
 
 /// Generate CRC magic value for alert_handler configuration.
 #[derive(Debug, Args)]
@@ -79,6 +89,9 @@ impl CommandDispatch for AlertDigest {
             partitions: vec![OtpImgPartition {
                 name: self.partition.clone(),
                 items: Some(items),
+                lock: false,
+                state: None,
+                count: None,
             }],
         };
 
@@ -98,8 +111,166 @@ impl CommandDispatch for AlertDigest {
     }
 }
 
+/// Options for reading the OTP of a device over JTAG.
+#[derive(Debug, Args)]
+pub struct OtpReadParams {
+    /// OTP memory map in HJSON format.
+    #[arg(long)]
+    mmap: PathBuf,
+    /// IV of the consistency digest, for verifying hardware digests, if not in the memory map.
+    #[arg(long, value_parser = u64::from_str, requires = "digest_const")]
+    digest_iv: Option<u64>,
+    /// Finalization constant of the consistency digest.
+    #[arg(long, value_parser = u128::from_str, requires = "digest_iv")]
+    digest_const: Option<u128>,
+    /// Reset duration when switching the RISC-V TAP straps.
+    #[arg(long, value_parser = parse_duration, default_value = "100ms")]
+    reset_delay: Duration,
+
+    #[command(flatten)]
+    jtag_params: JtagParams,
+}
+
+impl OtpReadParams {
+    /// Reads the partitions and items of `image`, or the entire memory map, over the DAI.
+    fn read(&self, transport: &TransportWrapper, image: Option<&OtpImg>) -> Result<OtpDump> {
+        let map = OtpMap::from_file(&self.mmap)?;
+        let digest_consts = match (self.digest_iv, self.digest_const) {
+            (Some(iv), Some(cnst)) => Some((iv, cnst)),
+            _ => map.consistency_digest(),
+        };
+
+        // Set the TAP straps for the CPU and reset.
+        transport.pin_strapping("PINMUX_TAP_RISCV")?.apply()?;
+        transport.reset_target(self.reset_delay, true)?;
+
+        let mut jtag = self
+            .jtag_params
+            .create(transport)?
+            .connect(JtagTap::RiscvTap)?;
+        let dump = OtpDump::read(&map, image, digest_consts, |mmap, granule| {
+            Ok(OtpParam::read_bytes(&mut *jtag, mmap, granule)?)
+        });
+        jtag.disconnect()?;
+        dump
+    }
+}
+
+/// Read and decode the OTP contents of the device over JTAG.
+#[derive(Debug, Args)]
+pub struct Dump {
+    /// OTP image in HJSON format, restricting the dump to its items and comparing them.
+    #[arg(long)]
+    image: Option<PathBuf>,
+
+    #[command(flatten)]
+    params: OtpReadParams,
+}
+
+impl CommandDispatch for Dump {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let image = self.image.as_deref().map(OtpImg::from_file).transpose()?;
+        let dump = self.params.read(transport, image.as_ref())?;
+        Ok(Some(Box::new(dump)))
+    }
+}
+
+/// Report how the OTP contents of the device differ from an OTP image.
+#[derive(Debug, Args)]
+pub struct Diff {
+    /// Expected OTP image in HJSON format.
+    image: PathBuf,
+
+    #[command(flatten)]
+    params: OtpReadParams,
+}
+
+impl CommandDispatch for Diff {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let image = OtpImg::from_file(&self.image)?;
+        let mut dump = self.params.read(transport, Some(&image))?;
+        log::info!("{} differences found", dump.differences());
+        dump.retain_differences();
+        Ok(Some(Box::new(dump)))
+    }
+}
+
+/// Generate an OTP memory image in vmem format from an OTP image description.
+#[derive(Debug, Args)]
+pub struct ImageGenerate {
+    /// Life cycle state encoding definition in HJSON format.
+    #[arg(long)]
+    lc_state_def: PathBuf,
+    /// OTP memory map definition in HJSON format.
+    #[arg(long)]
+    mmap_def: PathBuf,
+    /// OTP image description in HJSON format.
+    image: PathBuf,
+    /// Additional OTP image descriptions overriding parts of the main image, applied in order.
+    #[arg(long)]
+    add_cfg: Vec<PathBuf>,
+    /// Seed for the `<random>` values of the image, instead of the seed of the image.
+    #[arg(long, value_parser = DecEncoded::<BigUint>::from_str)]
+    img_seed: Option<DecEncoded<BigUint>>,
+    /// Seed for the life cycle tokens and encoding, instead of the seed of the definition.
+    #[arg(long, value_parser = DecEncoded::<BigUint>::from_str)]
+    lc_seed: Option<DecEncoded<BigUint>>,
+    /// Seed for the scrambling keys and digest constants, instead of the seed of the memory map.
+    #[arg(long, value_parser = DecEncoded::<BigUint>::from_str)]
+    otp_seed: Option<DecEncoded<BigUint>>,
+    /// Output file to write the vmem image to instead of printing.
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+impl CommandDispatch for ImageGenerate {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let lc_state = LcState::from_file(&self.lc_state_def)?;
+        let lc_state = LcStateEncoding::generate(lc_state, self.lc_seed.as_deref())?;
+        let mut map = OtpMap::from_file(&self.mmap_def)?;
+        map.resolve_scrambling(self.otp_seed.as_deref())?;
+
+        let image = OtpImg::from_file(&self.image)?;
+        let mut mem_img = OtpMemImg::new(map, lc_state, &image, self.img_seed.as_deref())?;
+        for add_cfg in &self.add_cfg {
+            mem_img.merge(&OtpImg::from_file(add_cfg)?)?;
+        }
+
+        let vmem = mem_img.to_vmem()?;
+        if let Some(output) = &self.output {
+            let mut file = File::create(output)?;
+            file.write_all(vmem.as_bytes())?;
+        } else {
+            println!("{vmem}");
+        }
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Subcommand, CommandDispatch)]
+/// OTP image related commands.
+pub enum Image {
+    Generate(ImageGenerate),
+}
+
 #[derive(Debug, Subcommand, CommandDispatch)]
 /// OTP related commands.
 pub enum Otp {
     AlertDigest(AlertDigest),
+    Diff(Diff),
+    Dump(Dump),
+    #[command(subcommand)]
+    Image(Image),
 }
@@ -4,22 +4,20 @@
 
 use anyhow::{anyhow, Result};
 use clap::{Args, Subcommand};
-use opentitanlib::io::uart::UartParams;
 use serde_annotate::Annotate;
 use std::any::Any;
 use std::fs::File;
 use std::path::PathBuf;
-use std::rc::Rc;
 use std::time::Duration;
 
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::TransportWrapper;
-use opentitanlib::chip::boot_svc::BootSlot;
+use opentitanlib::chip::boot_svc::{BootSlot, OwnershipActivateRequest, OwnershipUnlockRequest};
 use opentitanlib::chip::helper::{OwnershipActivateParams, OwnershipUnlockParams};
 use opentitanlib::image::image::Image;
 use opentitanlib::image::manifest::ManifestKind;
 use opentitanlib::ownership::{OwnerBlock, TlvHeader};
-use opentitanlib::rescue::serial::RescueSerial;
+use opentitanlib::rescue::{Rescue, RescueMode, RescueParams};
 use opentitanlib::util::file::FromReader;
 use opentitanlib::util::parse_int::ParseInt;
 
@@ -33,7 +31,7 @@ pub struct RawBytes(
 #[derive(Debug, Args)]
 pub struct Firmware {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(long, help = "After connecting to rescue, negotiate faster baudrate")]
     rate: Option<u32>,
     #[arg(long, default_value = "SlotA", help = "Which flash slot to rescue")]
@@ -66,6 +64,38 @@ pub struct Firmware {
     filename: PathBuf,
 }
 
+impl Firmware {
+    fn execute(
+        &self,
+        rescue: &dyn Rescue,
+        transport: &TransportWrapper,
+        payload: &[u8],
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let mut prev_baudrate = 0u32;
+        rescue.enter(transport, self.reset_target)?;
+        if let Some(rate) = self.rate {
+            prev_baudrate = rescue.set_speed(rate)?;
+        }
+        rescue.wait()?;
+        if self.erase_other_slot {
+            // Invalidate the other slot by overwriting its header.
+            if self.slot == BootSlot::SlotB {
+                rescue.update_firmware(BootSlot::SlotA, &vec![0xFF; 2048])?;
+            } else {
+                rescue.update_firmware(BootSlot::SlotB, &vec![0xFF; 2048])?;
+            }
+        }
+        rescue.update_firmware(self.slot, payload)?;
+        if self.rate.is_some() {
+            rescue.set_speed(prev_baudrate)?;
+        }
+        if !self.wait {
+            transport.reset_target(Duration::from_millis(50), false)?;
+        }
+        Ok(None)
+    }
+}
+
 impl CommandDispatch for Firmware {
     fn run(
         &self,
@@ -90,38 +120,15 @@ impl CommandDispatch for Firmware {
             }
             subimage.data
         };
-        let uart = self.params.create(transport)?;
-        let mut prev_baudrate = 0u32;
-        let rescue = RescueSerial::new(Rc::clone(&uart));
-        rescue.enter(transport, self.reset_target)?;
-        if let Some(rate) = self.rate {
-            prev_baudrate = uart.get_baudrate()?;
-            rescue.set_baud(rate)?;
-        }
-        rescue.wait()?;
-        if self.erase_other_slot {
-            // Invalidate the other slot by overwriting its header.
-            if self.slot == BootSlot::SlotB {
-                rescue.update_firmware(BootSlot::SlotA, &vec![0xFF; 2048])?;
-            } else {
-                rescue.update_firmware(BootSlot::SlotB, &vec![0xFF; 2048])?;
-            }
-        }
-        rescue.update_firmware(self.slot, payload)?;
-        if self.rate.is_some() {
-            rescue.set_baud(prev_baudrate)?;
-        }
-        if !self.wait {
-            transport.reset_target(Duration::from_millis(50), false)?;
-        }
-        Ok(None)
+        let rescue = self.params.create(transport)?;
+        self.execute(&*rescue, transport, payload)
     }
 }
 
 #[derive(Debug, Args)]
 pub struct GetBootLog {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(
         long,
         default_value_t = true,
@@ -133,17 +140,15 @@ pub struct GetBootLog {
     raw: bool,
 }
 
-impl CommandDispatch for GetBootLog {
-    fn run(
+impl GetBootLog {
+    fn execute(
         &self,
-        _context: &dyn Any,
+        rescue: &dyn Rescue,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
         rescue.enter(transport, self.reset_target)?;
         if self.raw {
-            let data = rescue.get_raw(RescueSerial::BOOT_LOG)?;
+            let data = rescue.get_raw(RescueMode::BOOT_LOG)?;
             Ok(Some(Box::new(RawBytes(data))))
         } else {
             let data = rescue.get_boot_log()?;
@@ -152,10 +157,21 @@ impl CommandDispatch for GetBootLog {
     }
 }
 
+impl CommandDispatch for GetBootLog {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let rescue = self.params.create(transport)?;
+        self.execute(&*rescue, transport)
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct GetBootSvc {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(
         long,
         default_value_t = true,
@@ -167,17 +183,15 @@ pub struct GetBootSvc {
     raw: bool,
 }
 
-impl CommandDispatch for GetBootSvc {
-    fn run(
+impl GetBootSvc {
+    fn execute(
         &self,
-        _context: &dyn Any,
+        rescue: &dyn Rescue,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
         rescue.enter(transport, self.reset_target)?;
         if self.raw {
-            let data = rescue.get_raw(RescueSerial::BOOT_SVC_RSP)?;
+            let data = rescue.get_raw(RescueMode::BOOT_SVC_RSP)?;
             Ok(Some(Box::new(RawBytes(data))))
         } else {
             let data = rescue.get_boot_svc()?;
@@ -186,10 +200,21 @@ impl CommandDispatch for GetBootSvc {
     }
 }
 
+impl CommandDispatch for GetBootSvc {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let rescue = self.params.create(transport)?;
+        self.execute(&*rescue, transport)
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct GetDeviceId {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(
         long,
         default_value_t = true,
@@ -201,17 +226,15 @@ pub struct GetDeviceId {
     raw: bool,
 }
 
-impl CommandDispatch for GetDeviceId {
-    fn run(
+impl GetDeviceId {
+    fn execute(
         &self,
-        _context: &dyn Any,
+        rescue: &dyn Rescue,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
         rescue.enter(transport, self.reset_target)?;
         if self.raw {
-            let data = rescue.get_raw(RescueSerial::OT_ID)?;
+            let data = rescue.get_raw(RescueMode::OT_ID)?;
             Ok(Some(Box::new(RawBytes(data))))
         } else {
             let data = rescue.get_device_id()?;
@@ -220,10 +243,21 @@ impl CommandDispatch for GetDeviceId {
     }
 }
 
+impl CommandDispatch for GetDeviceId {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let rescue = self.params.create(transport)?;
+        self.execute(&*rescue, transport)
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct SetNextBl0Slot {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(
         long,
         short,
@@ -254,14 +288,12 @@ pub struct SetNextBl0Slot {
     get_response: bool,
 }
 
-impl CommandDispatch for SetNextBl0Slot {
-    fn run(
+impl SetNextBl0Slot {
+    fn execute(
         &self,
-        _context: &dyn Any,
+        rescue: &dyn Rescue,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
         rescue.enter(transport, self.reset_target)?;
         rescue.set_next_bl0_slot(self.primary, self.next)?;
         if self.get_response {
@@ -275,10 +307,21 @@ impl CommandDispatch for SetNextBl0Slot {
     }
 }
 
+impl CommandDispatch for SetNextBl0Slot {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let rescue = self.params.create(transport)?;
+        self.execute(&*rescue, transport)
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct OwnershipUnlock {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(
         long,
         default_value_t = true,
@@ -299,18 +342,13 @@ pub struct OwnershipUnlock {
     input: Option<PathBuf>,
 }
 
-impl CommandDispatch for OwnershipUnlock {
-    fn run(
+impl OwnershipUnlock {
+    fn execute(
         &self,
-        _context: &dyn Any,
+        rescue: &dyn Rescue,
         transport: &TransportWrapper,
+        unlock: OwnershipUnlockRequest,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let unlock = self
-            .unlock
-            .apply_to(self.input.as_ref().map(File::open).transpose()?.as_mut())?;
-
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
         rescue.enter(transport, self.reset_target)?;
         rescue.ownership_unlock(unlock)?;
         if self.get_response {
@@ -324,10 +362,25 @@ impl CommandDispatch for OwnershipUnlock {
     }
 }
 
+impl CommandDispatch for OwnershipUnlock {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let unlock = self
+            .unlock
+            .apply_to(self.input.as_ref().map(File::open).transpose()?.as_mut())?;
+
+        let rescue = self.params.create(transport)?;
+        self.execute(&*rescue, transport, unlock)
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct OwnershipActivate {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(
         long,
         default_value_t = true,
@@ -348,18 +401,13 @@ pub struct OwnershipActivate {
     input: Option<PathBuf>,
 }
 
-impl CommandDispatch for OwnershipActivate {
-    fn run(
+impl OwnershipActivate {
+    fn execute(
         &self,
-        _context: &dyn Any,
+        rescue: &dyn Rescue,
         transport: &TransportWrapper,
+        activate: OwnershipActivateRequest,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let activate = self
-            .activate
-            .apply_to(self.input.as_ref().map(File::open).transpose()?.as_mut())?;
-
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
         rescue.enter(transport, self.reset_target)?;
         rescue.ownership_activate(activate)?;
         if self.get_response {
@@ -373,10 +421,25 @@ impl CommandDispatch for OwnershipActivate {
     }
 }
 
+impl CommandDispatch for OwnershipActivate {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let activate = self
+            .activate
+            .apply_to(self.input.as_ref().map(File::open).transpose()?.as_mut())?;
+
+        let rescue = self.params.create(transport)?;
+        self.execute(&*rescue, transport, activate)
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct SetOwnerConfig {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(
         long,
         default_value_t = true,
@@ -388,6 +451,19 @@ pub struct SetOwnerConfig {
     input: PathBuf,
 }
 
+impl SetOwnerConfig {
+    fn execute(
+        &self,
+        rescue: &dyn Rescue,
+        transport: &TransportWrapper,
+        data: &[u8],
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        rescue.enter(transport, self.reset_target)?;
+        rescue.set_owner_config(data)?;
+        Ok(None)
+    }
+}
+
 impl CommandDispatch for SetOwnerConfig {
     fn run(
         &self,
@@ -395,18 +471,15 @@ impl CommandDispatch for SetOwnerConfig {
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
         let data = std::fs::read(&self.input)?;
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
-        rescue.enter(transport, self.reset_target)?;
-        rescue.set_owner_config(&data)?;
-        Ok(None)
+        let rescue = self.params.create(transport)?;
+        self.execute(&*rescue, transport, &data)
     }
 }
 
 #[derive(Debug, Args)]
 pub struct GetOwnerConfig {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(
         long,
         default_value_t = true,
@@ -427,19 +500,13 @@ pub struct GetOwnerConfig {
     output: Option<PathBuf>,
 }
 
-impl CommandDispatch for GetOwnerConfig {
-    fn run(
+impl GetOwnerConfig {
+    fn execute(
         &self,
-        _context: &dyn Any,
+        rescue: &dyn Rescue,
         transport: &TransportWrapper,
+        page: [u8; 4],
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let page = match self.page {
-            0 => RescueSerial::GET_OWNER_PAGE0,
-            1 => RescueSerial::GET_OWNER_PAGE1,
-            _ => return Err(anyhow!("Unsupported page {}", self.page)),
-        };
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
         rescue.enter(transport, self.reset_target)?;
         let data = rescue.get_raw(page)?;
         if let Some(output) = &self.output {
@@ -455,10 +522,26 @@ impl CommandDispatch for GetOwnerConfig {
     }
 }
 
+impl CommandDispatch for GetOwnerConfig {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let page = match self.page {
+            0 => RescueMode::GET_OWNER_PAGE0,
+            1 => RescueMode::GET_OWNER_PAGE1,
+            _ => return Err(anyhow!("Unsupported page {}", self.page)),
+        };
+        let rescue = self.params.create(transport)?;
+        self.execute(&*rescue, transport, page)
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct EraseOwner {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(
         long,
         default_value_t = true,
@@ -470,6 +553,18 @@ pub struct EraseOwner {
     really: bool,
 }
 
+impl EraseOwner {
+    fn execute(
+        &self,
+        rescue: &dyn Rescue,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        rescue.enter(transport, self.reset_target)?;
+        rescue.erase_owner()?;
+        Ok(None)
+    }
+}
+
 impl CommandDispatch for EraseOwner {
     fn run(
         &self,
@@ -477,11 +572,8 @@ impl CommandDispatch for EraseOwner {
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
         if self.really {
-            let uart = self.params.create(transport)?;
-            let rescue = RescueSerial::new(uart);
-            rescue.enter(transport, self.reset_target)?;
-            rescue.erase_owner()?;
-            Ok(None)
+            let rescue = self.params.create(transport)?;
+            self.execute(&*rescue, transport)
         } else {
             Err(anyhow!("The owner may only be erased on DEV lifecycle-state chips with a ROM_EXT configured to permit owner erasing.\n\nUse the `--really` flag to send the command."))
         }
@@ -507,3 +599,127 @@ pub enum RescueCommand {
     SetOwnerConfig(SetOwnerConfig),
     GetOwnerConfig(GetOwnerConfig),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use opentitanlib::app::TransportWrapperBuilder;
+    use opentitanlib::chip::boot_svc::BootSvc;
+    use opentitanlib::rescue::mock::{MockRescue, MockState};
+    use opentitanlib::transport::EmptyTransport;
+
+    #[derive(Debug, Parser)]
+    struct TestCommand {
+        #[command(subcommand)]
+        command: RescueCommand,
+    }
+
+    fn parse(args: &[&str]) -> Result<RescueCommand> {
+        let args = std::iter::once("rescue").chain(args.iter().copied());
+        Ok(TestCommand::try_parse_from(args)?.command)
+    }
+
+    fn transport() -> Result<TransportWrapper> {
+        TransportWrapperBuilder::new(String::new(), false).build(Box::new(EmptyTransport))
+    }
+
+    #[test]
+    fn test_firmware_erases_other_slot() -> Result<()> {
+        let transport = transport()?;
+        let RescueCommand::Firmware(cmd) = parse(&["firmware", "--wait", "image.bin"])? else {
+            panic!("wrong command");
+        };
+        let rescue = MockRescue::default();
+        let payload = vec![0x5au8; 4096];
+        cmd.execute(&rescue, &transport, &payload)?;
+        let state = rescue.state();
+        assert_eq!(state.slot_a, payload);
+        assert_eq!(state.slot_b, vec![0xFFu8; 2048]);
+        assert_eq!(
+            state.history,
+            vec![RescueMode::WAIT, RescueMode::RESCUE_B, RescueMode::RESCUE]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_firmware_restores_rate() -> Result<()> {
+        let transport = transport()?;
+        let RescueCommand::Firmware(cmd) = parse(&[
+            "firmware",
+            "--wait",
+            "--rate=1500000",
+            "--slot=SlotB",
+            "--erase-other-slot=false",
+            "image.bin",
+        ])?
+        else {
+            panic!("wrong command");
+        };
+        let rescue = MockRescue::default();
+        cmd.execute(&rescue, &transport, &[1, 2, 3, 4])?;
+        let state = rescue.state();
+        assert_eq!(state.slot_b, vec![1, 2, 3, 4]);
+        assert!(state.slot_a.is_empty());
+        assert_eq!(state.speed, 115200);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_next_bl0_slot() -> Result<()> {
+        let transport = transport()?;
+        let RescueCommand::BootSvc(BootSvcCommand::SetNextBl0Slot(cmd)) = parse(&[
+            "boot-svc",
+            "set-next-bl0-slot",
+            "--primary=SlotB",
+            "--get-response=false",
+        ])?
+        else {
+            panic!("wrong command");
+        };
+        let rescue = MockRescue::default();
+        cmd.execute(&rescue, &transport)?;
+        let expected = BootSvc::next_boot_bl0_slot(BootSlot::SlotB, BootSlot::Unspecified);
+        assert_eq!(rescue.state().boot_svc_req, expected.to_bytes()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_owner_config_to_file() -> Result<()> {
+        let transport = transport()?;
+        let output = std::env::temp_dir().join("test_get_owner_config_to_file.bin");
+        let RescueCommand::GetOwnerConfig(cmd) =
+            parse(&["get-owner-config", "--output", output.to_str().unwrap()])?
+        else {
+            panic!("wrong command");
+        };
+        let rescue = MockRescue::new(MockState {
+            owner_page: [vec![1, 2, 3], vec![4, 5, 6]],
+            ..Default::default()
+        });
+        assert!(cmd
+            .execute(&rescue, &transport, RescueMode::GET_OWNER_PAGE0)?
+            .is_none());
+        assert_eq!(std::fs::read(&output)?, vec![1, 2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_erase_owner_requires_really() -> Result<()> {
+        let transport = transport()?;
+        let cmd = parse(&["erase-owner"])?;
+        assert!(cmd.run(&(), &transport).is_err());
+
+        let RescueCommand::EraseOwner(cmd) = parse(&["erase-owner", "--really"])? else {
+            panic!("wrong command");
+        };
+        let rescue = MockRescue::new(MockState {
+            owner_page: [vec![1, 2, 3], vec![4, 5, 6]],
+            ..Default::default()
+        });
+        cmd.execute(&rescue, &transport)?;
+        assert_eq!(rescue.state().owner_page, [vec![], vec![]]);
+        Ok(())
+    }
+}
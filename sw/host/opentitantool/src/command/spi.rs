@@ -2,20 +2,23 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Args, Subcommand};
 use serde_annotate::Annotate;
 use std::any::Any;
 use std::fs::{self, File};
 use std::io::{self, Write};
 use std::path::PathBuf;
+use std::rc::Rc;
 use std::time::Duration;
 
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::{StagedProgressBar, TransportWrapper};
 use opentitanlib::io::eeprom::{AddressMode, Transaction, MODE_111};
-use opentitanlib::io::spi::{SpiParams, Transfer};
-use opentitanlib::spiflash::{EraseMode, ReadMode, SpiFlash};
+use opentitanlib::io::spi::{SpiParams, Target, Transfer};
+use opentitanlib::spiflash::{
+    BlockProtect, EraseMode, ProgramMode, ProgramStats, ReadMode, SpiFlash,
+};
 use opentitanlib::tpm;
 use opentitanlib::transport::Capability;
 use opentitanlib::transport::ProgressIndicator;
@@ -244,6 +247,14 @@ pub struct SpiProgram {
     /// Start offset.
     #[arg(short, long, default_value = "0")]
     start: u32,
+    /// Program mode.  Defaults to the fastest mode supported by both the flash and the
+    /// transport, using quad mode only if the flash already has it enabled.
+    #[arg(short, long, value_enum, ignore_case = true)]
+    pub mode: Option<ProgramMode>,
+    /// Only erase and program the sectors whose contents differ from the file, then verify
+    /// the result.  Data sharing an erase sector with the file is preserved.
+    #[arg(long)]
+    differential: bool,
     #[arg(value_name = "FILE")]
     filename: PathBuf,
 }
@@ -252,6 +263,8 @@ pub struct SpiProgram {
 pub struct SpiProgramResponse {
     length: usize,
     bytes_per_second: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<ProgramStats>,
 }
 
 impl CommandDispatch for SpiProgram {
@@ -265,14 +278,24 @@ impl CommandDispatch for SpiProgram {
         let spi = context.params.create(transport, "BOOTSTRAP")?;
         let mut flash = SpiFlash::from_spi(&*spi)?;
         flash.set_address_mode_auto(&*spi)?;
+        match self.mode {
+            Some(mode) => flash.set_program_mode(&*spi, mode)?,
+            None => flash.set_program_mode_auto(&*spi, &transport.capabilities()?)?,
+        }
 
         let buffer = fs::read(&self.filename)?;
         let progress = StagedProgressBar::new();
-        flash.program_with_progress(&*spi, self.start, &buffer, &progress)?;
+        let stats = if self.differential {
+            Some(flash.program_differential_with_progress(&*spi, self.start, &buffer, &progress)?)
+        } else {
+            flash.program_with_progress(&*spi, self.start, &buffer, &progress)?;
+            None
+        };
 
         Ok(Some(Box::new(SpiProgramResponse {
             length: buffer.len(),
             bytes_per_second: progress.bytes_per_second(),
+            stats,
         })))
     }
 }
@@ -439,6 +462,191 @@ impl CommandDispatch for SpiFlashromArgs {
     }
 }
 
+// Identifies the write protection features of the SPI flash on the `BOOTSTRAP` bus.
+fn block_protect(
+    context: &dyn Any,
+    transport: &TransportWrapper,
+) -> Result<(Rc<dyn Target>, BlockProtect)> {
+    transport.capabilities()?.request(Capability::SPI).ok()?;
+    let context = context.downcast_ref::<SpiCommand>().unwrap();
+    let spi = context.params.create(transport, "BOOTSTRAP")?;
+    let mut flash = SpiFlash::from_spi(&*spi)?;
+    flash.set_address_mode_auto(&*spi)?;
+    let protect = BlockProtect::new(&*spi, &flash)?;
+    Ok((spi, protect))
+}
+
+#[derive(Debug, serde::Serialize, Annotate)]
+pub struct SpiProtectResponse {
+    part: &'static str,
+    #[annotate(format = hex)]
+    start: u32,
+    #[annotate(format = hex)]
+    length: u32,
+    locked: bool,
+    #[annotate(format = hex)]
+    status: u32,
+}
+
+impl SpiProtectResponse {
+    fn new(spi: &dyn Target, protect: &BlockProtect) -> Result<Self> {
+        let protection = protect.protection(spi)?;
+        Ok(Self {
+            part: protect.layout.name,
+            start: protection.start,
+            length: protection.length,
+            locked: protection.locked,
+            status: protection.status,
+        })
+    }
+}
+
+/// Protect a range of a SPI flash against program and erase operations, or show the current
+/// protection if no range is given.
+#[derive(Debug, Args)]
+pub struct SpiProtect {
+    /// Start offset.
+    #[arg(short, long, default_value = "0")]
+    start: u32,
+    /// Number of bytes to protect.
+    #[arg(short = 'n', long)]
+    length: Option<u32>,
+    /// Also protect the status registers while the WP# pin is asserted.
+    #[arg(long, requires = "length")]
+    lock: bool,
+}
+
+impl CommandDispatch for SpiProtect {
+    fn run(
+        &self,
+        context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let (spi, protect) = block_protect(context, transport)?;
+        if let Some(length) = self.length {
+            let end = self
+                .start
+                .checked_add(length)
+                .context("protected range exceeds the 32-bit address space")?;
+            protect.protect(&*spi, self.start..end, self.lock)?;
+        }
+        Ok(Some(Box::new(SpiProtectResponse::new(&*spi, &protect)?)))
+    }
+}
+
+/// Remove the block protection and status register protection of a SPI flash.
+#[derive(Debug, Args)]
+pub struct SpiUnprotect {}
+
+impl CommandDispatch for SpiUnprotect {
+    fn run(
+        &self,
+        context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let (spi, protect) = block_protect(context, transport)?;
+        protect.unprotect(&*spi)?;
+        Ok(Some(Box::new(SpiProtectResponse::new(&*spi, &protect)?)))
+    }
+}
+
+/// Read a security register of a SPI flash.
+#[derive(Debug, Args)]
+pub struct SpiSecurityRegisterRead {
+    /// Index of the security register.
+    #[arg(short, long, default_value = "0")]
+    index: u8,
+    /// Offset within the security register.
+    #[arg(short, long, default_value = "0")]
+    offset: u32,
+    /// Number of bytes to read.
+    #[arg(short = 'n', long)]
+    length: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SpiSecurityRegisterReadResponse {
+    hexdata: String,
+    locked: bool,
+}
+
+impl CommandDispatch for SpiSecurityRegisterRead {
+    fn run(
+        &self,
+        context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let (spi, protect) = block_protect(context, transport)?;
+        let mut buffer = vec![0u8; self.length];
+        protect.read_security_register(&*spi, self.index, self.offset, &mut buffer)?;
+        Ok(Some(Box::new(SpiSecurityRegisterReadResponse {
+            hexdata: hex::encode(buffer),
+            locked: protect.security_register_locked(&*spi, self.index)?,
+        })))
+    }
+}
+
+/// Program a security register of a SPI flash.
+#[derive(Debug, Args)]
+pub struct SpiSecurityRegisterWrite {
+    /// Index of the security register.
+    #[arg(short, long, default_value = "0")]
+    index: u8,
+    /// Offset within the security register.
+    #[arg(short, long, default_value = "0")]
+    offset: u32,
+    /// Erase the security register before programming it.
+    #[arg(long)]
+    erase: bool,
+    /// Hex data to program.
+    #[arg(short = 'd', long)]
+    hexdata: String,
+}
+
+impl CommandDispatch for SpiSecurityRegisterWrite {
+    fn run(
+        &self,
+        context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let (spi, protect) = block_protect(context, transport)?;
+        if self.erase {
+            protect.erase_security_register(&*spi, self.index)?;
+        }
+        let data = hex::decode(&self.hexdata)?;
+        protect.program_security_register(&*spi, self.index, self.offset, &data)?;
+        Ok(None)
+    }
+}
+
+/// Permanently lock a security register of a SPI flash.
+#[derive(Debug, Args)]
+pub struct SpiSecurityRegisterLock {
+    /// Index of the security register.
+    #[arg(short, long, default_value = "0")]
+    index: u8,
+}
+
+impl CommandDispatch for SpiSecurityRegisterLock {
+    fn run(
+        &self,
+        context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let (spi, protect) = block_protect(context, transport)?;
+        protect.lock_security_register(&*spi, self.index)?;
+        Ok(None)
+    }
+}
+
+/// Commands for the security (OTP) registers of a SPI flash.
+#[derive(Debug, Subcommand, CommandDispatch)]
+pub enum SpiSecurityRegister {
+    Read(SpiSecurityRegisterRead),
+    Write(SpiSecurityRegisterWrite),
+    Lock(SpiSecurityRegisterLock),
+}
+
 /// Commands for interacting with a SPI EEPROM.
 #[derive(Debug, Subcommand, CommandDispatch)]
 pub enum InternalSpiCommand {
@@ -447,6 +655,10 @@ pub enum InternalSpiCommand {
     Read(SpiRead),
     Erase(SpiErase),
     Program(SpiProgram),
+    Protect(SpiProtect),
+    Unprotect(SpiUnprotect),
+    #[command(subcommand)]
+    SecurityRegister(SpiSecurityRegister),
     RawRead(SpiRawRead),
     RawWrite(SpiRawWrite),
     RawWriteRead(SpiRawWriteRead),
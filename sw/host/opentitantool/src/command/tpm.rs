@@ -3,14 +3,21 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::{anyhow, Result};
-use clap::{Args, Subcommand};
+use clap::{Args, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
 use serde_annotate::Annotate;
 use std::any::Any;
+use std::net::ToSocketAddrs;
 
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::TransportWrapper;
 use opentitanlib::tpm;
+use opentitanlib::tpm::types::{
+    AlgorithmId, Capability, Handle, NvAttributes, NvPublic, PcrSelection, Public, StartupType,
+    TaggedDigest,
+};
+use opentitanlib::tpm::{Session, Tpm};
+use opentitanlib::util::parse_int::ParseInt;
 
 /// Read the value of a given TPM register.
 #[derive(Debug, Args)]
@@ -142,11 +149,673 @@ impl CommandDispatch for TpmExecuteCommand {
     }
 }
 
-/// Commands for interacting with a TPM.  These appear as subcommands of both `opentitantool i2c
-/// tpm` and `opentitantool spi tpm`.
+/// Parses a handle given either by name (e.g. `Owner`) or by value.
+fn parse_handle(s: &str) -> Result<Handle> {
+    match s.parse::<Handle>() {
+        Ok(handle) => Ok(handle),
+        Err(_) => Ok(Handle(<u32 as ParseInt>::from_str(s)?)),
+    }
+}
+
+/// Authorization of the commands operating on protected entities.
+#[derive(Debug, Args)]
+pub struct TpmAuth {
+    /// Authorization value of the entity (e.g. hierarchy, key or NV index).
+    #[arg(long, default_value = "")]
+    auth: String,
+
+    /// Authorize with an HMAC session instead of a plain text password.
+    #[arg(long)]
+    hmac: bool,
+}
+
+impl TpmAuth {
+    /// Runs `f` with a session authorizing the entity, flushing HMAC sessions afterwards.
+    fn with_session<T>(&self, tpm: &Tpm, f: impl FnOnce(&mut Session) -> Result<T>) -> Result<T> {
+        if !self.hmac {
+            return f(&mut Session::password(self.auth.as_bytes()));
+        }
+        let mut session = tpm.start_hmac_session(self.auth.as_bytes())?;
+        let result = f(&mut session);
+        tpm.flush_context(session.handle())?;
+        result
+    }
+}
+
+/// Selection of PCRs of one bank.
+#[derive(Debug, Args)]
+pub struct TpmPcrSelection {
+    /// Hash algorithm of the PCR bank.
+    #[arg(long, value_enum, ignore_case = true, default_value = "Sha256")]
+    bank: AlgorithmId,
+
+    /// Comma separated list of PCRs.
+    #[arg(long, value_delimiter = ',', required = true)]
+    pcrs: Vec<u32>,
+}
+
+impl TpmPcrSelection {
+    fn selection(&self) -> Vec<PcrSelection> {
+        vec![PcrSelection::new(self.bank, &self.pcrs)]
+    }
+}
+
+#[derive(Annotate, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TpmEmptyResponse {}
+
+/// Send TPM2_Startup.
+#[derive(Debug, Args)]
+pub struct TpmStartup {
+    /// Startup type.
+    #[arg(value_enum, ignore_case = true, default_value = "Clear")]
+    startup_type: StartupType,
+}
+
+impl CommandDispatch for TpmStartup {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        Tpm::new(&**tpm).startup(self.startup_type)?;
+        Ok(Some(Box::new(TpmEmptyResponse {})))
+    }
+}
+
+/// Send TPM2_Shutdown.
+#[derive(Debug, Args)]
+pub struct TpmShutdown {
+    /// Shutdown type.
+    #[arg(value_enum, ignore_case = true, default_value = "Clear")]
+    shutdown_type: StartupType,
+}
+
+impl CommandDispatch for TpmShutdown {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        Tpm::new(&**tpm).shutdown(self.shutdown_type)?;
+        Ok(Some(Box::new(TpmEmptyResponse {})))
+    }
+}
+
+/// Send TPM2_SelfTest.
+#[derive(Debug, Args)]
+pub struct TpmSelfTest {
+    /// Test all algorithms, not only the untested ones.
+    #[arg(long)]
+    full: bool,
+}
+
+impl CommandDispatch for TpmSelfTest {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        Tpm::new(&**tpm).self_test(self.full)?;
+        Ok(Some(Box::new(TpmEmptyResponse {})))
+    }
+}
+
+/// Query TPM capabilities with TPM2_GetCapability.
+#[derive(Debug, Args)]
+pub struct TpmGetCapability {
+    /// Capability group.
+    #[arg(value_enum, ignore_case = true)]
+    capability: Capability,
+
+    /// First property (or handle, algorithm, command...) to return.
+    #[arg(long, value_parser = u32::from_str, default_value = "0")]
+    property: u32,
+
+    /// Maximum number of values to return.
+    #[arg(long, value_parser = u32::from_str, default_value = "256")]
+    count: u32,
+}
+
+#[derive(Annotate, Serialize, Debug)]
+pub struct TpmGetCapabilityResponse {
+    more_data: bool,
+    data: tpm::types::CapabilityData,
+}
+
+impl CommandDispatch for TpmGetCapability {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let (more_data, data) =
+            Tpm::new(&**tpm).get_capability(self.capability, self.property, self.count)?;
+        Ok(Some(Box::new(TpmGetCapabilityResponse { more_data, data })))
+    }
+}
+
+/// Get random bytes with TPM2_GetRandom.
+#[derive(Debug, Args)]
+pub struct TpmGetRandom {
+    /// Number of bytes.
+    #[arg(short = 'n', long, value_parser = usize::from_str, default_value = "32")]
+    length: usize,
+}
+
+#[derive(Annotate, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TpmHexdataResponse {
+    hexdata: String,
+}
+
+impl CommandDispatch for TpmGetRandom {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let random = Tpm::new(&**tpm).get_random(self.length)?;
+        Ok(Some(Box::new(TpmHexdataResponse {
+            hexdata: hex::encode(random),
+        })))
+    }
+}
+
+/// Read PCRs with TPM2_PCR_Read.
+#[derive(Debug, Args)]
+pub struct TpmPcrRead {
+    #[command(flatten)]
+    selection: TpmPcrSelection,
+}
+
+impl CommandDispatch for TpmPcrRead {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let values = Tpm::new(&**tpm).pcr_read(&self.selection.selection())?;
+        Ok(Some(Box::new(values)))
+    }
+}
+
+/// Extend a PCR with TPM2_PCR_Extend.
+#[derive(Debug, Args)]
+pub struct TpmPcrExtend {
+    /// The PCR to extend.
+    #[arg(value_parser = u32::from_str)]
+    pcr: u32,
+
+    /// Hash algorithm of the digest.
+    #[arg(long, value_enum, ignore_case = true, default_value = "Sha256")]
+    bank: AlgorithmId,
+
+    /// Hex encoded digest to extend the PCR with.
+    #[arg(short = 'd', long)]
+    digest: String,
+
+    #[command(flatten)]
+    auth: TpmAuth,
+}
+
+impl CommandDispatch for TpmPcrExtend {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let tpm = Tpm::new(&**tpm);
+        let digest = TaggedDigest {
+            hash: self.bank,
+            digest: hex::decode(&self.digest)?,
+        };
+        self.auth
+            .with_session(&tpm, |session| tpm.pcr_extend(self.pcr, &[digest], session))?;
+        Ok(Some(Box::new(TpmEmptyResponse {})))
+    }
+}
+
+/// Templates of primary keys.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TpmKeyTemplate {
+    /// Restricted ECDSA P-256 signing key, suitable for quotes.
+    EccSigning,
+    /// ECC P-256 storage key.
+    EccStorage,
+    /// RSA 2048 storage key.
+    RsaStorage,
+}
+
+/// Create a primary key with TPM2_CreatePrimary.
+#[derive(Debug, Args)]
+pub struct TpmCreatePrimary {
+    /// Hierarchy of the key.
+    #[arg(long, value_parser = parse_handle, default_value = "Owner")]
+    hierarchy: Handle,
+
+    /// Template of the key.
+    #[arg(long, value_enum, default_value = "ecc-signing")]
+    template: TpmKeyTemplate,
+
+    /// Authorization value of the new key.
+    #[arg(long, default_value = "")]
+    key_auth: String,
+
+    #[command(flatten)]
+    auth: TpmAuth,
+}
+
+impl CommandDispatch for TpmCreatePrimary {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let tpm = Tpm::new(&**tpm);
+        let template = match self.template {
+            TpmKeyTemplate::EccSigning => Public::ecc_signing_template(),
+            TpmKeyTemplate::EccStorage => Public::ecc_storage_template(),
+            TpmKeyTemplate::RsaStorage => Public::rsa_storage_template(),
+        };
+        let key = self.auth.with_session(&tpm, |session| {
+            tpm.create_primary(self.hierarchy, &template, self.key_auth.as_bytes(), session)
+        })?;
+        Ok(Some(Box::new(key)))
+    }
+}
+
+/// Read the public area of an object with TPM2_ReadPublic.
+#[derive(Debug, Args)]
+pub struct TpmReadPublic {
+    /// Handle of the object.
+    #[arg(value_parser = parse_handle)]
+    handle: Handle,
+}
+
+impl CommandDispatch for TpmReadPublic {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let public = Tpm::new(&**tpm).read_public(self.handle)?;
+        Ok(Some(Box::new(public)))
+    }
+}
+
+/// Unload a transient object or a session with TPM2_FlushContext.
+#[derive(Debug, Args)]
+pub struct TpmFlushContext {
+    /// Handle of the object or session.
+    #[arg(value_parser = parse_handle)]
+    handle: Handle,
+}
+
+impl CommandDispatch for TpmFlushContext {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        Tpm::new(&**tpm).flush_context(self.handle)?;
+        Ok(Some(Box::new(TpmEmptyResponse {})))
+    }
+}
+
+/// Define an NV index with TPM2_NV_DefineSpace.
+#[derive(Debug, Args)]
+pub struct TpmNvDefine {
+    /// Handle of the NV index.
+    #[arg(value_parser = parse_handle)]
+    index: Handle,
+
+    /// Size of the NV index in bytes.
+    #[arg(short = 'n', long, value_parser = u16::from_str)]
+    size: u16,
+
+    /// Attributes of the NV index (`TPMA_NV`), by default read and write with the owner or
+    /// index authorization.
+    #[arg(long, value_parser = u32::from_str, default_value = "0x60006")]
+    attributes: u32,
+
+    /// Authorization value of the new NV index.
+    #[arg(long, default_value = "")]
+    index_auth: String,
+
+    /// Hierarchy owning the NV index.
+    #[arg(long, value_parser = parse_handle, default_value = "Owner")]
+    hierarchy: Handle,
+
+    #[command(flatten)]
+    auth: TpmAuth,
+}
+
+impl CommandDispatch for TpmNvDefine {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let tpm = Tpm::new(&**tpm);
+        let public = NvPublic {
+            index: self.index,
+            name_alg: AlgorithmId::Sha256,
+            attributes: NvAttributes::from_bits_retain(self.attributes),
+            auth_policy: Vec::new(),
+            data_size: self.size,
+        };
+        self.auth.with_session(&tpm, |session| {
+            tpm.nv_define_space(self.hierarchy, self.index_auth.as_bytes(), &public, session)
+        })?;
+        Ok(Some(Box::new(TpmEmptyResponse {})))
+    }
+}
+
+/// Delete an NV index with TPM2_NV_UndefineSpace.
+#[derive(Debug, Args)]
+pub struct TpmNvUndefine {
+    /// Handle of the NV index.
+    #[arg(value_parser = parse_handle)]
+    index: Handle,
+
+    /// Hierarchy owning the NV index.
+    #[arg(long, value_parser = parse_handle, default_value = "Owner")]
+    hierarchy: Handle,
+
+    #[command(flatten)]
+    auth: TpmAuth,
+}
+
+impl CommandDispatch for TpmNvUndefine {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let tpm = Tpm::new(&**tpm);
+        self.auth.with_session(&tpm, |session| {
+            tpm.nv_undefine_space(self.hierarchy, self.index, session)
+        })?;
+        Ok(Some(Box::new(TpmEmptyResponse {})))
+    }
+}
+
+/// Read the public area of an NV index with TPM2_NV_ReadPublic.
+#[derive(Debug, Args)]
+pub struct TpmNvReadPublic {
+    /// Handle of the NV index.
+    #[arg(value_parser = parse_handle)]
+    index: Handle,
+}
+
+impl CommandDispatch for TpmNvReadPublic {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let public = Tpm::new(&**tpm).nv_read_public(self.index)?;
+        Ok(Some(Box::new(public)))
+    }
+}
+
+/// Read an NV index with TPM2_NV_Read.
+#[derive(Debug, Args)]
+pub struct TpmNvRead {
+    /// Handle of the NV index.
+    #[arg(value_parser = parse_handle)]
+    index: Handle,
+
+    /// Entity authorizing the read, the NV index itself by default.
+    #[arg(long, value_parser = parse_handle)]
+    auth_handle: Option<Handle>,
+
+    /// Offset of the first byte to read.
+    #[arg(short = 'o', long, value_parser = u16::from_str, default_value = "0")]
+    offset: u16,
+
+    /// Number of bytes to read, up to the end of the index by default.
+    #[arg(short = 'n', long, value_parser = u16::from_str)]
+    length: Option<u16>,
+
+    #[command(flatten)]
+    auth: TpmAuth,
+}
+
+impl CommandDispatch for TpmNvRead {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let tpm = Tpm::new(&**tpm);
+        let length = match self.length {
+            Some(length) => length,
+            None => tpm
+                .nv_read_public(self.index)?
+                .public
+                .data_size
+                .saturating_sub(self.offset),
+        };
+        let data = self.auth.with_session(&tpm, |session| {
+            tpm.nv_read(
+                self.auth_handle.unwrap_or(self.index),
+                self.index,
+                length,
+                self.offset,
+                session,
+            )
+        })?;
+        Ok(Some(Box::new(TpmHexdataResponse {
+            hexdata: hex::encode(data),
+        })))
+    }
+}
+
+/// Write an NV index with TPM2_NV_Write.
+#[derive(Debug, Args)]
+pub struct TpmNvWrite {
+    /// Handle of the NV index.
+    #[arg(value_parser = parse_handle)]
+    index: Handle,
+
+    /// Entity authorizing the write, the NV index itself by default.
+    #[arg(long, value_parser = parse_handle)]
+    auth_handle: Option<Handle>,
+
+    /// Offset of the first byte to write.
+    #[arg(short = 'o', long, value_parser = u16::from_str, default_value = "0")]
+    offset: u16,
+
+    /// Hex encoded data to write.
+    #[arg(short = 'd', long)]
+    hexdata: String,
+
+    #[command(flatten)]
+    auth: TpmAuth,
+}
+
+impl CommandDispatch for TpmNvWrite {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let tpm = Tpm::new(&**tpm);
+        let data = hex::decode(&self.hexdata)?;
+        self.auth.with_session(&tpm, |session| {
+            tpm.nv_write(
+                self.auth_handle.unwrap_or(self.index),
+                self.index,
+                &data,
+                self.offset,
+                session,
+            )
+        })?;
+        Ok(Some(Box::new(TpmEmptyResponse {})))
+    }
+}
+
+/// Sign PCR values with TPM2_Quote.
+#[derive(Debug, Args)]
+pub struct TpmQuote {
+    /// Handle of the restricted signing key.
+    #[arg(value_parser = parse_handle)]
+    key: Handle,
+
+    /// Hex encoded data (e.g. a nonce) included in the quote.
+    #[arg(short = 'q', long, default_value = "")]
+    qualifying_data: String,
+
+    #[command(flatten)]
+    selection: TpmPcrSelection,
+
+    #[command(flatten)]
+    auth: TpmAuth,
+}
+
+impl CommandDispatch for TpmQuote {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let tpm = Tpm::new(&**tpm);
+        let qualifying_data = hex::decode(&self.qualifying_data)?;
+        let quote = self.auth.with_session(&tpm, |session| {
+            tpm.quote(
+                self.key,
+                &qualifying_data,
+                &self.selection.selection(),
+                session,
+            )
+        })?;
+        Ok(Some(Box::new(quote)))
+    }
+}
+
+/// Check the conformance of the TPM to the FIFO interface of the TCG PC Client Platform TPM
+/// Profile, exercising the edge cases of the register protocol.
+#[derive(Debug, Args)]
+pub struct TpmConformance {
+    /// Comma separated list of checks to run, all by default.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    check: Vec<tpm::ConformanceCheck>,
+}
+
+#[derive(Annotate, Serialize, Debug)]
+pub struct TpmConformanceResponse {
+    passed: usize,
+    failed: usize,
+    results: Vec<tpm::ConformanceResult>,
+}
+
+impl CommandDispatch for TpmConformance {
+    fn run(
+        &self,
+        context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let tpm = context.downcast_ref::<Box<dyn tpm::Driver>>().unwrap();
+        let checks = if self.check.is_empty() {
+            tpm::ConformanceCheck::value_variants()
+        } else {
+            &self.check
+        };
+        let results = tpm::run_conformance(&**tpm, checks);
+        let passed = results.iter().filter(|r| r.passed).count();
+        Ok(Some(Box::new(TpmConformanceResponse {
+            passed,
+            failed: results.len() - passed,
+            results,
+        })))
+    }
+}
+
+/// Commands for interacting with a TPM.  These appear as subcommands of `opentitantool i2c tpm`,
+/// `opentitantool spi tpm` and `opentitantool tpm`.
 #[derive(Debug, Subcommand, CommandDispatch)]
 pub enum TpmSubCommand {
     ReadRegister(TpmReadRegister),
     WriteRegister(TpmWriteRegister),
     ExecuteCommand(TpmExecuteCommand),
+    Startup(TpmStartup),
+    Shutdown(TpmShutdown),
+    SelfTest(TpmSelfTest),
+    GetCapability(TpmGetCapability),
+    GetRandom(TpmGetRandom),
+    PcrRead(TpmPcrRead),
+    PcrExtend(TpmPcrExtend),
+    CreatePrimary(TpmCreatePrimary),
+    ReadPublic(TpmReadPublic),
+    FlushContext(TpmFlushContext),
+    NvDefine(TpmNvDefine),
+    NvUndefine(TpmNvUndefine),
+    NvReadPublic(TpmNvReadPublic),
+    NvRead(TpmNvRead),
+    NvWrite(TpmNvWrite),
+    Quote(TpmQuote),
+    Conformance(TpmConformance),
+}
+
+/// Interact with a TPM simulator through its TCP protocol.
+#[derive(Debug, Args)]
+pub struct TpmCommand {
+    #[command(subcommand)]
+    command: TpmSubCommand,
+
+    /// Host and command port of the simulator, e.g. `localhost:2321`.
+    #[arg(long)]
+    tcp: String,
+
+    /// Platform port of the simulator, by default the one following the command port.
+    #[arg(long)]
+    platform_port: Option<u16>,
+
+    /// Power on the simulator before running the command, as needed after starting it.
+    #[arg(long)]
+    power_on: bool,
+}
+
+impl CommandDispatch for TpmCommand {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let command = self
+            .tcp
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("No address for {}", self.tcp))?;
+        let mut platform = command;
+        platform.set_port(
+            match self.platform_port {
+                Some(port) => Some(port),
+                None => command.port().checked_add(1),
+            }
+            .ok_or_else(|| anyhow!("Must specify --platform-port"))?,
+        );
+        let driver = tpm::TcpDriver::connect(command, platform)?;
+        if self.power_on {
+            driver.power_on()?;
+        }
+        let tpm_driver: Box<dyn tpm::Driver> = Box::new(driver);
+        self.command.run(&tpm_driver, transport)
+    }
 }
@@ -59,6 +59,7 @@ enum RootCommandHierarchy {
     Spi(command::spi::SpiCommand),
     #[command(subcommand)]
     Spx(command::spx::Spx),
+    Tpm(command::tpm::TpmCommand),
     #[command(subcommand)]
     Transport(command::transport::TransportCommand),
     Uart(command::uart::UartCommand),
@@ -241,9 +242,34 @@ where
     Ok(())
 }
 
+// Runs an `--exec` command on the transport of one of several devices.  The command result is
+// not printed, as the output of the devices would be interleaved.
+fn execute_on_device(command: &str, transport: &TransportWrapper) -> Result<()> {
+    let command = RootCommandHierarchy::try_parse_from(
+        std::iter::once(String::from("opentitantool")).chain(shellwords::split(command)?),
+    )?;
+    command.run(&(), transport)?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let opts = parse_command_line(Opts::parse(), args_os())?;
 
+    if let RootCommandHierarchy::Bootstrap(bootstrap) = &opts.command {
+        if bootstrap.is_multi_device() {
+            // Every device gets a transport of its own, so none is created here: it could not
+            // choose between several debuggers, and would hold one of them open otherwise.
+            let exec = &opts.exec;
+            let result = bootstrap.bootstrap_devices(&opts.backend_opts, |transport| {
+                for command in exec {
+                    execute_on_device(command, transport)?;
+                }
+                Ok(())
+            });
+            return print_command_result(&opts, result);
+        }
+    }
+
     let transport = backend::create(&opts.backend_opts)?;
 
     let mut _maintain_connection = None;
@@ -2,6 +2,7 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
+use anyhow::{bail, ensure, Context, Result};
 use serde::{Deserialize, Serialize};
 
 // For CBOR specification, it's defined in Concise Binary Object Representation (CBOR)
@@ -81,3 +82,164 @@ pub fn array_header(len: u64) -> Vec<u8> {
 pub fn map_header(len: u64) -> Vec<u8> {
     header(MajorType::Map, len)
 }
+
+/// A decoded CBOR data item.  Only the definite-length encodings of the major types produced
+/// by this module are supported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Int(i128),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    pub fn as_int(&self) -> Option<i128> {
+        match self {
+            Value::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Looks up the entry with integer label `label` in a map.
+    pub fn get(&self, label: i64) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| k.as_int() == Some(label.into()))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes a single data item which spans all of `bytes`.
+pub fn decode(bytes: &[u8]) -> Result<Value> {
+    let (value, size) = decode_prefix(bytes)?;
+    ensure!(
+        size == bytes.len(),
+        "{} trailing bytes after CBOR data item",
+        bytes.len() - size
+    );
+    Ok(value)
+}
+
+/// Decodes the data item at the start of `bytes`, returning it and its encoded size.
+pub fn decode_prefix(bytes: &[u8]) -> Result<(Value, usize)> {
+    let initial = *bytes.first().context("truncated CBOR data item")?;
+    let (kind, info) = (initial >> 5, initial & 0x1f);
+    let (arg, mut pos) = match info {
+        0..=23 => (info as u64, 1),
+        24..=27 => {
+            let size = 1 << (info - ArgType::U8 as u8);
+            let arg = bytes
+                .get(1..1 + size)
+                .context("truncated CBOR argument")?
+                .iter()
+                .fold(0u64, |acc, &b| (acc << 8) | b as u64);
+            (arg, 1 + size)
+        }
+        _ => bail!("unsupported CBOR additional information {info}"),
+    };
+    let mut take = |len: u64| -> Result<&[u8]> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| pos.checked_add(len))
+            .filter(|&end| end <= bytes.len())
+            .context("truncated CBOR string")?;
+        let data = &bytes[pos..end];
+        pos = end;
+        Ok(data)
+    };
+    let value = match kind {
+        k if k == MajorType::Uint as u8 => Value::Int(arg.into()),
+        k if k == MajorType::Sint as u8 => Value::Int(-1 - i128::from(arg)),
+        k if k == MajorType::Bstr as u8 => Value::Bytes(take(arg)?.to_vec()),
+        k if k == MajorType::Tstr as u8 => Value::Text(
+            std::str::from_utf8(take(arg)?)
+                .context("invalid UTF-8 in CBOR text string")?
+                .to_string(),
+        ),
+        k if k == MajorType::Array as u8 => {
+            let mut items = Vec::new();
+            for _ in 0..arg {
+                let (item, size) = decode_prefix(&bytes[pos..])?;
+                items.push(item);
+                pos += size;
+            }
+            Value::Array(items)
+        }
+        k if k == MajorType::Map as u8 => {
+            let mut entries = Vec::new();
+            for _ in 0..arg {
+                let (key, size) = decode_prefix(&bytes[pos..])?;
+                pos += size;
+                let (value, size) = decode_prefix(&bytes[pos..])?;
+                pos += size;
+                entries.push((key, value));
+            }
+            Value::Map(entries)
+        }
+        _ => bail!("unsupported CBOR major type {kind}"),
+    };
+    Ok((value, pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() -> Result<()> {
+        let mut bytes = map_header(3);
+        bytes.extend(int(1));
+        bytes.extend(int(-7));
+        bytes.extend(int(-4670552));
+        bytes.extend(byte_array_header(2));
+        bytes.extend([0xaa, 0xbb]);
+        bytes.extend(int(300));
+        bytes.extend(array_header(2));
+        bytes.extend(string_header(2));
+        bytes.extend(b"hi");
+        bytes.extend(int(u32::MAX as i64 + 1));
+
+        let value = decode(&bytes)?;
+        assert_eq!(value.get(1), Some(&Value::Int(-7)));
+        assert_eq!(
+            value.get(-4670552).and_then(Value::as_bytes),
+            Some(&[0xaa, 0xbb][..])
+        );
+        assert_eq!(
+            value.get(300).and_then(Value::as_array),
+            Some(&[Value::Text("hi".into()), Value::Int(0x1_0000_0000)][..])
+        );
+        assert!(value.get(2).is_none());
+
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        bytes.push(0);
+        assert!(decode(&bytes).is_err());
+        Ok(())
+    }
+}
@@ -273,10 +273,16 @@ impl<'a> asn1::SimpleAsn1Readable<'a> for KeyUsage {
 //       authorityCertSerialNumber [2] CertificateSerialNumber OPTIONAL  }
 //
 // KeyIdentifier ::= OCTET STRING
+//
+// Only the key identifier is used, the issuer and serial number are accepted but ignored.
 #[derive(asn1::Asn1Read)]
 struct AuthorityKeyIdentifier<'a> {
     #[implicit(0)]
     pub key_id: Option<&'a [u8]>,
+    #[implicit(1)]
+    _cert_issuer: Option<asn1::Sequence<'a>>,
+    #[implicit(2)]
+    _cert_serial_number: Option<asn1::BigInt<'a>>,
 }
 
 /// Try to parse an X509 extension as a DICE TCB info extension.
@@ -290,15 +296,22 @@ pub fn parse_dice_tcb_info_extension(ext: &[u8]) -> Result<DiceTcbInfoExtension>
 // crate. We cannot use the `BasicConstraints` in `template` since we
 // need to use specific annotations and types so that the `asn` library can
 // derive an ASN1 parser.
+//
+// BasicConstraints ::= SEQUENCE {
+//   cA                      BOOLEAN DEFAULT FALSE,
+//   pathLenConstraint       INTEGER (0..MAX) OPTIONAL }
+//
+// DER omits `cA` when false, but our own certificates always encode it.
 #[derive(asn1::Asn1Read)]
 struct BasicConstraintsInternal {
-    ca: bool,
+    ca: Option<bool>,
+    _path_len: Option<u64>,
 }
 
 impl BasicConstraintsInternal {
     fn to_basic_constraints(&self) -> Result<BasicConstraints> {
         Ok(BasicConstraints {
-            ca: Value::Literal(self.ca),
+            ca: Value::Literal(self.ca.unwrap_or(false)),
         })
     }
 }
@@ -11,6 +11,7 @@ use anyhow::{bail, Context, Result};
 use elliptic_curve::SecretKey;
 use num_bigint_dig::BigUint;
 use openssl::ecdsa::EcdsaSig;
+use openssl::x509::X509;
 use p256::ecdsa::SigningKey;
 use p256::NistP256;
 use serde::Deserialize;
@@ -21,6 +22,9 @@ use ot_certs::template::{EcdsaSignature, Signature, Value};
 use ot_certs::x509::generate_certificate_from_tbs;
 use ot_certs::CertFormat;
 
+mod verify;
+pub use verify::{verify_cert_chain, CertIssue, CertReport, ChainReport};
+
 /// Certificate Authority key type.
 #[derive(Debug, Clone, Deserialize)]
 pub enum CaKeyType {
@@ -176,54 +180,11 @@ fn parse_and_endorse_x509_cert_token(tbs: Vec<u8>, key_id: &str) -> Result<Vec<u
     generate_certificate_from_tbs(tbs, &signature)
 }
 
-fn write_cert_to_temp_pem_file(der_cert_bytes: &[u8], base_filename: &str) -> Result<String> {
-    // Build temp file names for DER and PEM cert files.
-    let base_name = tmpfilename(base_filename);
-    let binding_der = base_name.to_owned() + ".der";
-    let binding_pem = base_name.to_owned() + ".pem";
-    let der_filename = binding_der.as_str();
-    let pem_filename = binding_pem.as_str();
-
-    // Write DER bytes to the tmp file.
-    let size = get_cert_size(der_cert_bytes)?;
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(der_filename)
-        .context(format!(
-            "failed to open temporary DER file: {:?}",
-            der_filename
-        ))?;
-    file.write_all(&der_cert_bytes[0..size])?;
-    drop(file);
-
-    // Convert the DER cert file to a PEM cert file.
-    openssl_command(&[
-        "x509",
-        "-out",
-        pem_filename,
-        "-in",
-        der_filename,
-        "-inform",
-        "der",
-    ])
-    .context(format!(
-        "failed to covert DER file ({:?}) to PEM",
-        der_filename
-    ))?;
-
-    // Cleanup the intermediate DER file.
-    fs::remove_file(der_filename).context("failed to remove der file")?;
-
-    Ok(binding_pem)
-}
-
 /// Container for an endorsed certificate.
 ///
 /// This is used to pass a collection of endorsed certificates, along with metadata,
-/// to various functions that check the certificates validate properly with third-party
-/// tools.
+/// to the functions that validate certificate chains.  `ignore_critical` tolerates
+/// critical extensions which the validator does not understand.
 #[derive(Clone, Debug)]
 pub struct EndorsedCert {
     pub format: CertFormat,
@@ -232,59 +193,22 @@ pub struct EndorsedCert {
     pub ignore_critical: bool,
 }
 
-/// Validate a chain of X.509 certificates against a provided CA certificate.
+/// Validate a chain of certificates against a provided CA certificate.
 ///
-/// A chain of X.509 certificates are validated against the CA using the 'openssl verify ...' command.
+/// The X.509 and CWT certificates of the chain are validated in-process by
+/// `verify_cert_chain`, and the problems found with each certificate are reported in the
+/// error.
 ///
 /// Arguments:
 /// * ca_pem - The file name of the CA certificate saved in PEM format.
 /// * cert_chain - A slice of EndorsedCert objects representing a chain ordered from root to leaf.
 pub fn validate_cert_chain(ca_pem: &str, cert_chain: &[EndorsedCert]) -> Result<()> {
-    let mut ignore_critical = false;
-
-    // Create temp CA PEM file.
-    let tmp_ca_pem_filename_binding = tmpfilename("tmp_ca_chain.pem");
-    let tmp_ca_pem_filename = tmp_ca_pem_filename_binding.as_str();
-    fs::copy(ca_pem, tmp_ca_pem_filename)?;
-
-    // Iterate over leaf certs.
-    let mut tmp_leaf_cert_pem_filename: String = "".to_string();
-    for cert in cert_chain.iter() {
-        // Overwrite the current leaf cert PEM file.
-        tmp_leaf_cert_pem_filename = write_cert_to_temp_pem_file(&cert.bytes, "leaf")?;
-
-        // If a cert in the chain has a critical custom extension, we need to
-        // tell OpenSSL to ignore it from here out. The `-ignore_critical` flag
-        // is required to verify DICE certificates that use the DiceTcbInfo
-        // custom extension that is a TCG standard (critical) extension that is
-        // not recognized by OpenSSL.
-        if cert.ignore_critical {
-            ignore_critical = true;
-        }
-
-        // Verify the cert chain up to the current leaf cert.
-        let mut args = vec!["verify", "-CAfile", tmp_ca_pem_filename];
-        if ignore_critical {
-            args.push("-ignore_critical");
-        }
-        args.push(tmp_leaf_cert_pem_filename.as_str());
-        openssl_command(&args).context(format!(
-            "failed to verify a certificate chain at {:?} cert",
-            cert.name.as_str()
-        ))?;
-
-        // Append the current leaf cert to the CA PEM file.
-        let mut tmp_ca_file = OpenOptions::new().append(true).open(tmp_ca_pem_filename)?;
-        let leaf_cert_pem_contents = fs::read(tmp_leaf_cert_pem_filename.as_str())?;
-        tmp_ca_file.write_all(&leaf_cert_pem_contents)?;
-        drop(tmp_ca_file);
+    let ca = X509::from_pem(&fs::read(ca_pem)?)
+        .with_context(|| format!("failed to parse CA certificate {ca_pem:?}"))?;
+    let report = verify_cert_chain(&ca, cert_chain)?;
+    if !report.is_valid() {
+        bail!("failed to verify the certificate chain:\n{report}");
     }
-
-    // Cleanup the temp PEM cert files.
-    fs::remove_file(tmp_ca_pem_filename).context("failed to remove temp CA PEM file")?;
-    fs::remove_file(tmp_leaf_cert_pem_filename.as_str())
-        .context("failed to remove temp leaf PEM file")?;
-
     Ok(())
 }
 
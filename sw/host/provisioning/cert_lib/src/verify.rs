@@ -0,0 +1,827 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use anyhow::{bail, ensure, Context, Result};
+use num_bigint_dig::BigUint;
+use openssl::asn1::{Asn1Object, Asn1Time};
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::x509::{X509NameRef, X509};
+use serde::Serialize;
+use thiserror::Error;
+
+use opentitanlib::crypto::sha256::sha256;
+use ot_certs::asn1::Oid;
+use ot_certs::cbor;
+use ot_certs::template::{DiceTcbInfoExtension, HashAlgorithm, Value};
+use ot_certs::x509::extension::{self, X509ExtensionRef};
+use ot_certs::CertFormat;
+
+use crate::EndorsedCert;
+
+/// A problem found while validating a certificate of a chain.
+#[derive(Clone, Debug, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum CertIssue {
+    #[error("cannot parse the certificate: {0}")]
+    Parse(String),
+    #[error("the signature does not verify with the issuer's public key")]
+    BadSignature,
+    #[error("the issuer {issuer:?} does not match the issuer's subject {expected:?}")]
+    IssuerMismatch { issuer: String, expected: String },
+    #[error("the authority key identifier {aki} does not match the issuer's subject key identifier {ski}")]
+    KeyIdMismatch { aki: String, ski: String },
+    #[error("the issuer is not a CA")]
+    IssuerNotCa,
+    #[error("the issuer's key usage does not allow certificate signing")]
+    IssuerCannotSign,
+    #[error("not valid before {0}")]
+    NotYetValid(String),
+    #[error("expired on {0}")]
+    Expired(String),
+    #[error("unhandled critical extension {0}")]
+    UnhandledCriticalExtension(String),
+    #[error("invalid DICE TCB info: {0}")]
+    DiceTcbInfo(String),
+    #[error("the issuer could not be parsed")]
+    UnverifiedIssuer,
+}
+
+/// The validation result of one certificate of a chain.
+#[derive(Clone, Debug, Serialize)]
+pub struct CertReport {
+    pub name: String,
+    pub subject: String,
+    pub issues: Vec<CertIssue>,
+}
+
+/// The validation result of a certificate chain, with one report per certificate.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChainReport {
+    pub certs: Vec<CertReport>,
+}
+
+impl ChainReport {
+    /// Whether all certificates of the chain are valid.
+    pub fn is_valid(&self) -> bool {
+        self.certs.iter().all(|c| c.issues.is_empty())
+    }
+}
+
+impl fmt::Display for ChainReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for cert in self.certs.iter() {
+            match cert.issues.as_slice() {
+                [] => writeln!(f, "{} ({}): ok", cert.name, cert.subject)?,
+                issues => {
+                    for issue in issues {
+                        writeln!(f, "{} ({}): {issue}", cert.name, cert.subject)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The labels of the CWT claims and the DICE profile claims.
+const CWT_ISSUER: i64 = 1;
+const CWT_SUBJECT: i64 = 2;
+const DICE_MODE: i64 = -4670551;
+const DICE_SUBJECT_PK: i64 = -4670552;
+const DICE_KEY_USAGE: i64 = -4670553;
+// The labels and values of the COSE headers and keys.
+const COSE_ALG: i64 = 1;
+const COSE_ALG_ES256: i128 = -7;
+const COSE_KEY_KTY: i64 = 1;
+const COSE_KEY_KTY_EC2: i128 = 2;
+const COSE_KEY_CRV: i64 = -1;
+const COSE_KEY_CRV_P256: i128 = 1;
+const COSE_KEY_X: i64 = -2;
+const COSE_KEY_Y: i64 = -3;
+// The keyCertSign bit of the key usage.
+const KEY_USAGE_CERT_SIGN: usize = 5;
+
+/// The properties of a certificate which are checked against its issuer.
+struct ParsedCert {
+    subject: String,
+    issuer: String,
+    /// The identifier used as issuer by CWT certificates issued by this certificate.
+    dice_id: Option<String>,
+    subject_key_id: Option<Vec<u8>>,
+    authority_key_id: Option<Vec<u8>>,
+    public_key: Option<PKey<Public>>,
+    is_ca: Option<bool>,
+    cert_sign: Option<bool>,
+    dice_layer: Option<BigUint>,
+    body: Body,
+}
+
+enum Body {
+    X509(X509),
+    Cwt {
+        protected: Vec<u8>,
+        payload: Vec<u8>,
+        signature: Vec<u8>,
+    },
+}
+
+fn name_to_string(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|e| {
+            let key = e.object().nid().short_name().unwrap_or("?");
+            let value = e
+                .data()
+                .as_utf8()
+                .map(|s| s.to_string())
+                .unwrap_or_else(|_| hex::encode(e.data().as_slice()));
+            format!("{key}={value}")
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// KeyUsage ::= BIT STRING. Unlike `extension::parse_key_usage`, which only accepts the bits that
+// certificate templates can express, any other bit (e.g. cRLSign on a CA) is ignored.
+fn parse_key_cert_sign(ext: &X509ExtensionRef) -> Result<bool> {
+    let bits = asn1::parse_single::<asn1::BitString>(ext.data.as_slice())?;
+    Ok(bits.has_bit_set(KEY_USAGE_CERT_SIGN))
+}
+
+fn check_dice_tcb(tcb: &DiceTcbInfoExtension, issues: &mut Vec<CertIssue>) {
+    for fw_id in tcb.fw_ids.iter().flatten() {
+        let expected = match fw_id.hash_algorithm {
+            HashAlgorithm::Sha256 => 32,
+        };
+        if let Value::Literal(digest) = &fw_id.digest {
+            if digest.len() != expected {
+                issues.push(CertIssue::DiceTcbInfo(format!(
+                    "firmware ID digest has {} bytes, expected {expected}",
+                    digest.len()
+                )));
+            }
+        }
+    }
+}
+
+fn parse_x509(
+    der: &[u8],
+    ignore_critical: bool,
+    issues: &mut Vec<CertIssue>,
+) -> Result<ParsedCert> {
+    let x509 = X509::from_der(der).context("invalid DER")?;
+    let mut cert = ParsedCert {
+        subject: name_to_string(x509.subject_name()),
+        issuer: name_to_string(x509.issuer_name()),
+        dice_id: None,
+        subject_key_id: None,
+        authority_key_id: None,
+        public_key: Some(x509.public_key().context("invalid public key")?),
+        is_ca: Some(false),
+        cert_sign: None,
+        dice_layer: None,
+        body: Body::X509(x509.clone()),
+    };
+    let dice_oid = Asn1Object::from_str(Oid::DiceTcbInfo.oid())?;
+    for ext in extension::x509_get_extensions(&x509)? {
+        let result = match ext.object.nid() {
+            Nid::BASIC_CONSTRAINTS => extension::parse_basic_constraints(&ext).map(|bc| {
+                cert.is_ca = Some(matches!(bc.ca, Value::Literal(true)));
+            }),
+            Nid::KEY_USAGE => parse_key_cert_sign(&ext).map(|sign| {
+                cert.cert_sign = Some(sign);
+            }),
+            Nid::AUTHORITY_KEY_IDENTIFIER => extension::parse_authority_key_id(&ext).map(|aki| {
+                cert.authority_key_id = Some(aki);
+            }),
+            Nid::SUBJECT_KEY_IDENTIFIER => extension::parse_subject_key_id(&ext).map(|ski| {
+                cert.subject_key_id = Some(ski);
+            }),
+            Nid::SUBJECT_ALT_NAME | Nid::EXT_KEY_USAGE => Ok(()),
+            _ if ext.object.to_owned().as_slice() == dice_oid.as_slice() => {
+                match extension::parse_dice_tcb_info_extension(ext.data.as_slice()) {
+                    Ok(tcb) => {
+                        check_dice_tcb(&tcb, issues);
+                        cert.dice_layer = match tcb.layer {
+                            Some(Value::Literal(layer)) => Some(layer),
+                            _ => None,
+                        };
+                    }
+                    Err(e) => issues.push(CertIssue::DiceTcbInfo(format!("{e:#}"))),
+                }
+                Ok(())
+            }
+            _ if ext.critical && !ignore_critical => {
+                issues.push(CertIssue::UnhandledCriticalExtension(
+                    ext.object.to_string(),
+                ));
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        result.with_context(|| format!("invalid {} extension", ext.object))?;
+    }
+    cert.dice_id = cert.subject_key_id.as_ref().map(hex::encode);
+
+    let now = Asn1Time::days_from_now(0)?;
+    if x509.not_before().compare(&now)? == Ordering::Greater {
+        issues.push(CertIssue::NotYetValid(x509.not_before().to_string()));
+    }
+    if x509.not_after().compare(&now)? == Ordering::Less {
+        issues.push(CertIssue::Expired(x509.not_after().to_string()));
+    }
+    Ok(cert)
+}
+
+fn parse_cose_key(bytes: &[u8]) -> Result<PKey<Public>> {
+    let key = cbor::decode(bytes).context("invalid COSE key")?;
+    let int = |label| key.get(label).and_then(cbor::Value::as_int);
+    ensure!(
+        int(COSE_KEY_KTY) == Some(COSE_KEY_KTY_EC2) && int(COSE_KEY_CRV) == Some(COSE_KEY_CRV_P256),
+        "only P-256 COSE keys are supported"
+    );
+    let coord = |label| -> Result<BigNum> {
+        let bytes = key
+            .get(label)
+            .and_then(cbor::Value::as_bytes)
+            .context("missing COSE key coordinate")?;
+        Ok(BigNum::from_slice(bytes)?)
+    };
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let (x, y) = (coord(COSE_KEY_X)?, coord(COSE_KEY_Y)?);
+    let ec_key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)?;
+    ec_key.check_key()?;
+    Ok(PKey::from_ec_key(ec_key)?)
+}
+
+fn parse_cwt(bytes: &[u8], issues: &mut Vec<CertIssue>) -> Result<ParsedCert> {
+    // COSE_Sign1 = [protected: bstr, unprotected: map, payload: bstr, signature: bstr]
+    let cose = cbor::decode(bytes).context("invalid CBOR")?;
+    let [protected, _, payload, signature] = cose.as_array().context("not a COSE_Sign1")? else {
+        bail!("COSE_Sign1 does not have 4 elements");
+    };
+    let protected = protected.as_bytes().context("invalid protected header")?;
+    let alg = cbor::decode(protected)?
+        .get(COSE_ALG)
+        .and_then(cbor::Value::as_int);
+    ensure!(alg == Some(COSE_ALG_ES256), "unsupported algorithm {alg:?}");
+    let payload = payload.as_bytes().context("invalid payload")?;
+    let signature = signature.as_bytes().context("invalid signature")?;
+
+    let claims = cbor::decode(payload).context("invalid CWT claims")?;
+    let text = |label| -> Result<String> {
+        Ok(claims
+            .get(label)
+            .and_then(cbor::Value::as_text)
+            .with_context(|| format!("missing CWT claim {label}"))?
+            .to_string())
+    };
+    let bytes = |label| claims.get(label).and_then(cbor::Value::as_bytes);
+    let subject = text(CWT_SUBJECT)?;
+    let public_key = parse_cose_key(bytes(DICE_SUBJECT_PK).context("missing subject key")?)?;
+    let cert_sign = bytes(DICE_KEY_USAGE).map(|ku| {
+        ku.get(KEY_USAGE_CERT_SIGN / 8)
+            .map(|b| b & (1 << (KEY_USAGE_CERT_SIGN % 8)) != 0)
+            .unwrap_or(false)
+    });
+    match bytes(DICE_MODE) {
+        Some([0..=3]) | None => {}
+        Some(mode) => issues.push(CertIssue::DiceTcbInfo(format!(
+            "invalid mode {}",
+            hex::encode(mode)
+        ))),
+    }
+    Ok(ParsedCert {
+        issuer: text(CWT_ISSUER)?,
+        dice_id: Some(subject.clone()),
+        subject,
+        subject_key_id: None,
+        authority_key_id: None,
+        public_key: Some(public_key),
+        is_ca: None,
+        cert_sign,
+        dice_layer: None,
+        body: Body::Cwt {
+            protected: protected.to_vec(),
+            payload: payload.to_vec(),
+            signature: signature.to_vec(),
+        },
+    })
+}
+
+fn verify_signature(cert: &ParsedCert, key: &PKey<Public>) -> Result<bool> {
+    match &cert.body {
+        Body::X509(x509) => Ok(x509.verify(key)?),
+        Body::Cwt {
+            protected,
+            payload,
+            signature,
+        } => {
+            // Sig_structure = ["Signature1", protected, external_aad, payload]
+            let mut tbs = cbor::array_header(4);
+            tbs.extend(cbor::string_header(10));
+            tbs.extend(b"Signature1");
+            tbs.extend(cbor::byte_array_header(protected.len() as u64));
+            tbs.extend(protected);
+            tbs.extend(cbor::byte_array_header(0));
+            tbs.extend(cbor::byte_array_header(payload.len() as u64));
+            tbs.extend(payload);
+            ensure!(signature.len() == 64, "invalid ES256 signature length");
+            let sig = EcdsaSig::from_private_components(
+                BigNum::from_slice(&signature[..32])?,
+                BigNum::from_slice(&signature[32..])?,
+            )?;
+            let digest = sha256(&tbs).to_be_bytes();
+            Ok(sig.verify(&digest, &*key.ec_key()?)?)
+        }
+    }
+}
+
+fn check_issuer(cert: &ParsedCert, issuer: &ParsedCert, issues: &mut Vec<CertIssue>) -> Result<()> {
+    let expected = match (&cert.body, &issuer.dice_id) {
+        (Body::Cwt { .. }, Some(id)) => id,
+        _ => &issuer.subject,
+    };
+    if !cert.issuer.eq_ignore_ascii_case(expected) {
+        issues.push(CertIssue::IssuerMismatch {
+            issuer: cert.issuer.clone(),
+            expected: expected.clone(),
+        });
+    }
+    if let (Some(aki), Some(ski)) = (&cert.authority_key_id, &issuer.subject_key_id) {
+        if aki != ski {
+            issues.push(CertIssue::KeyIdMismatch {
+                aki: hex::encode(aki),
+                ski: hex::encode(ski),
+            });
+        }
+    }
+    if issuer.is_ca == Some(false) {
+        issues.push(CertIssue::IssuerNotCa);
+    }
+    if issuer.cert_sign == Some(false) {
+        issues.push(CertIssue::IssuerCannotSign);
+    }
+    if let (Some(layer), Some(issuer_layer)) = (&cert.dice_layer, &issuer.dice_layer) {
+        if layer <= issuer_layer {
+            issues.push(CertIssue::DiceTcbInfo(format!(
+                "layer {layer} does not follow the issuer's layer {issuer_layer}"
+            )));
+        }
+    }
+    let key = issuer
+        .public_key
+        .as_ref()
+        .context("issuer has no public key")?;
+    // An unsupported signature algorithm is reported as a bad signature too.
+    if !verify_signature(cert, key).unwrap_or(false) {
+        issues.push(CertIssue::BadSignature);
+    }
+    Ok(())
+}
+
+/// Validates a chain of certificates, ordered from root to leaf, against the `ca` certificate.
+/// Each certificate is checked against its predecessor, the first one against `ca`.
+///
+/// Every certificate of the chain is examined, and the problems found are reported for each
+/// certificate.  Once a certificate cannot be parsed, the rest of the chain is reported as
+/// unverifiable.
+pub fn verify_cert_chain(ca: &X509, cert_chain: &[EndorsedCert]) -> Result<ChainReport> {
+    let mut ca_issues = Vec::new();
+    let mut issuer = parse_x509(&ca.to_der()?, false, &mut ca_issues)
+        .context("cannot parse the CA certificate")?;
+    ensure!(
+        ca_issues.is_empty(),
+        "invalid CA certificate: {}",
+        ca_issues
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let mut report = ChainReport { certs: Vec::new() };
+    let mut chain = cert_chain.iter();
+    for endorsed in chain.by_ref() {
+        let mut issues = Vec::new();
+        let cert = match endorsed.format {
+            CertFormat::X509 => parse_x509(&endorsed.bytes, endorsed.ignore_critical, &mut issues),
+            CertFormat::Cwt => parse_cwt(&endorsed.bytes, &mut issues),
+        };
+        let cert = match cert {
+            Ok(cert) => cert,
+            Err(e) => {
+                issues.push(CertIssue::Parse(format!("{e:#}")));
+                report.certs.push(CertReport {
+                    name: endorsed.name.clone(),
+                    subject: String::new(),
+                    issues,
+                });
+                break;
+            }
+        };
+        check_issuer(&cert, &issuer, &mut issues)?;
+        report.certs.push(CertReport {
+            name: endorsed.name.clone(),
+            subject: cert.subject.clone(),
+            issues,
+        });
+        issuer = cert;
+    }
+    for endorsed in chain {
+        report.certs.push(CertReport {
+            name: endorsed.name.clone(),
+            subject: String::new(),
+            issues: vec![CertIssue::UnverifiedIssuer],
+        });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Integer;
+    use openssl::bn::BigNumContext;
+    use openssl::ec::PointConversionForm;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::Private;
+    use openssl::x509::extension::{
+        AuthorityKeyIdentifier, BasicConstraints, KeyUsage, SubjectKeyIdentifier,
+    };
+    use openssl::x509::{X509Builder, X509NameBuilder};
+
+    fn gen_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    struct CertParams<'a> {
+        cn: &'a str,
+        ca: bool,
+        not_before: u32,
+        not_after: u32,
+    }
+
+    impl Default for CertParams<'_> {
+        fn default() -> Self {
+            CertParams {
+                cn: "",
+                ca: true,
+                not_before: 0,
+                not_after: 365,
+            }
+        }
+    }
+
+    // Builds a certificate for `key`, signed by `issuer` or self-signed.
+    fn build_cert(
+        params: CertParams,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, params.cn)
+            .unwrap();
+        let name = name.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap();
+        builder
+            .set_serial_number(&Asn1Integer::from_bn(&serial).unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder
+            .set_issuer_name(issuer.map_or(&name, |(cert, _)| cert.subject_name()))
+            .unwrap();
+        builder.set_pubkey(key).unwrap();
+        // Asn1Time cannot be built in the past, use a date before the tests were written.
+        let not_before = match params.not_before {
+            0 => Asn1Time::from_str("20240101000000Z").unwrap(),
+            days => Asn1Time::days_from_now(days).unwrap(),
+        };
+        let not_after = match params.not_after {
+            0 => Asn1Time::from_str("20240201000000Z").unwrap(),
+            days => Asn1Time::days_from_now(days).unwrap(),
+        };
+        builder.set_not_before(&not_before).unwrap();
+        builder.set_not_after(&not_after).unwrap();
+        // CAs are set up like the ones in manuf/keys, with a path length and cRLSign.
+        let mut bc = BasicConstraints::new();
+        if params.ca {
+            bc.ca().pathlen(1);
+        }
+        builder
+            .append_extension(bc.critical().build().unwrap())
+            .unwrap();
+        let mut ku = KeyUsage::new();
+        ku.critical().digital_signature();
+        if params.ca {
+            ku.key_cert_sign().crl_sign();
+        }
+        builder.append_extension(ku.build().unwrap()).unwrap();
+        let ski = SubjectKeyIdentifier::new()
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(ski).unwrap();
+        if let Some((cert, _)) = issuer {
+            let aki = AuthorityKeyIdentifier::new()
+                .keyid(true)
+                .build(&builder.x509v3_context(Some(cert), None))
+                .unwrap();
+            builder.append_extension(aki).unwrap();
+        }
+        let signer = issuer.map_or(key, |(_, key)| key);
+        builder.sign(signer, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn endorsed(name: &str, cert: &X509) -> EndorsedCert {
+        EndorsedCert {
+            format: CertFormat::X509,
+            name: name.to_string(),
+            bytes: cert.to_der().unwrap(),
+            ignore_critical: false,
+        }
+    }
+
+    fn issues(report: &ChainReport) -> Vec<&[CertIssue]> {
+        report.certs.iter().map(|c| c.issues.as_slice()).collect()
+    }
+
+    fn cbor_text(s: &str) -> Vec<u8> {
+        let mut v = cbor::string_header(s.len() as u64);
+        v.extend(s.as_bytes());
+        v
+    }
+
+    fn cbor_bytes(b: &[u8]) -> Vec<u8> {
+        let mut v = cbor::byte_array_header(b.len() as u64);
+        v.extend(b);
+        v
+    }
+
+    // Builds a DICE CWT certificate for `key`, signed by `issuer_key`.
+    fn build_cwt(
+        issuer: &str,
+        subject: &str,
+        key: &PKey<Private>,
+        issuer_key: &PKey<Private>,
+    ) -> Vec<u8> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let point = key
+            .ec_key()
+            .unwrap()
+            .public_key()
+            .to_bytes(&group, PointConversionForm::UNCOMPRESSED, &mut ctx)
+            .unwrap();
+        let mut cose_key = cbor::map_header(5);
+        for (label, value) in [
+            (COSE_KEY_KTY, cbor::int(2)),
+            (COSE_ALG, cbor::int(-7)),
+            (COSE_KEY_CRV, cbor::int(1)),
+            (COSE_KEY_X, cbor_bytes(&point[1..33])),
+            (COSE_KEY_Y, cbor_bytes(&point[33..])),
+        ] {
+            cose_key.extend(cbor::int(label));
+            cose_key.extend(value);
+        }
+        let mut payload = cbor::map_header(5);
+        for (label, value) in [
+            (CWT_ISSUER, cbor_text(issuer)),
+            (CWT_SUBJECT, cbor_text(subject)),
+            (DICE_MODE, cbor_bytes(&[1])),
+            (DICE_SUBJECT_PK, cbor_bytes(&cose_key)),
+            (DICE_KEY_USAGE, cbor_bytes(&[1 << KEY_USAGE_CERT_SIGN])),
+        ] {
+            payload.extend(cbor::int(label));
+            payload.extend(value);
+        }
+        let mut protected = cbor::map_header(1);
+        protected.extend(cbor::int(COSE_ALG));
+        protected.extend(cbor::int(-7));
+
+        let mut tbs = cbor::array_header(4);
+        tbs.extend(cbor_text("Signature1"));
+        tbs.extend(cbor_bytes(&protected));
+        tbs.extend(cbor_bytes(&[]));
+        tbs.extend(cbor_bytes(&payload));
+        let sig =
+            EcdsaSig::sign(&sha256(&tbs).to_be_bytes(), &*issuer_key.ec_key().unwrap()).unwrap();
+        let mut signature = sig.r().to_vec_padded(32).unwrap();
+        signature.extend(sig.s().to_vec_padded(32).unwrap());
+
+        let mut cwt = cbor::array_header(4);
+        cwt.extend(cbor_bytes(&protected));
+        cwt.extend(cbor::map_header(0));
+        cwt.extend(cbor_bytes(&payload));
+        cwt.extend(cbor_bytes(&signature));
+        cwt
+    }
+
+    #[test]
+    fn test_verify_x509_chain() {
+        let ca_key = gen_key();
+        let ca = build_cert(
+            CertParams {
+                cn: "CA",
+                ..Default::default()
+            },
+            &ca_key,
+            None,
+        );
+        let int_key = gen_key();
+        let int = build_cert(
+            CertParams {
+                cn: "Int",
+                ..Default::default()
+            },
+            &int_key,
+            Some((&ca, &ca_key)),
+        );
+        let leaf_key = gen_key();
+        let leaf = build_cert(
+            CertParams {
+                cn: "Leaf",
+                ca: false,
+                ..Default::default()
+            },
+            &leaf_key,
+            Some((&int, &int_key)),
+        );
+        let report =
+            verify_cert_chain(&ca, &[endorsed("int", &int), endorsed("leaf", &leaf)]).unwrap();
+        assert!(report.is_valid(), "{report}");
+        assert_eq!(report.certs[1].subject, "CN=Leaf");
+
+        // The leaf is signed by the CA but names the intermediate as its issuer.
+        let forged = build_cert(
+            CertParams {
+                cn: "Leaf",
+                ca: false,
+                ..Default::default()
+            },
+            &leaf_key,
+            Some((&int, &ca_key)),
+        );
+        let report =
+            verify_cert_chain(&ca, &[endorsed("int", &int), endorsed("leaf", &forged)]).unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(issues(&report), [&[][..], &[CertIssue::BadSignature]]);
+
+        // A certificate issued by the leaf, which is not a CA.
+        let other = build_cert(
+            CertParams {
+                cn: "Other",
+                ca: false,
+                ..Default::default()
+            },
+            &gen_key(),
+            Some((&leaf, &leaf_key)),
+        );
+        let report = verify_cert_chain(
+            &ca,
+            &[
+                endorsed("int", &int),
+                endorsed("leaf", &leaf),
+                endorsed("other", &other),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            issues(&report)[2],
+            [CertIssue::IssuerNotCa, CertIssue::IssuerCannotSign]
+        );
+
+        // The leaf is checked against the wrong issuer.
+        let report = verify_cert_chain(&ca, &[endorsed("leaf", &leaf)]).unwrap();
+        assert!(matches!(
+            issues(&report)[0],
+            [
+                CertIssue::IssuerMismatch { .. },
+                CertIssue::KeyIdMismatch { .. },
+                CertIssue::BadSignature
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_verify_validity() {
+        let ca_key = gen_key();
+        let ca = build_cert(
+            CertParams {
+                cn: "CA",
+                ..Default::default()
+            },
+            &ca_key,
+            None,
+        );
+        let expired = build_cert(
+            CertParams {
+                cn: "Expired",
+                not_after: 0,
+                ..Default::default()
+            },
+            &gen_key(),
+            Some((&ca, &ca_key)),
+        );
+        let future = build_cert(
+            CertParams {
+                cn: "Future",
+                not_before: 30,
+                ..Default::default()
+            },
+            &gen_key(),
+            Some((&ca, &ca_key)),
+        );
+        let report = verify_cert_chain(&ca, &[endorsed("expired", &expired)]).unwrap();
+        assert!(matches!(issues(&report)[0], [CertIssue::Expired(_)]));
+        let report = verify_cert_chain(&ca, &[endorsed("future", &future)]).unwrap();
+        assert!(matches!(issues(&report)[0], [CertIssue::NotYetValid(_)]));
+    }
+
+    #[test]
+    fn test_verify_unparsable() {
+        let ca_key = gen_key();
+        let ca = build_cert(
+            CertParams {
+                cn: "CA",
+                ..Default::default()
+            },
+            &ca_key,
+            None,
+        );
+        let int = build_cert(
+            CertParams {
+                cn: "Int",
+                ..Default::default()
+            },
+            &gen_key(),
+            Some((&ca, &ca_key)),
+        );
+        let mut bad = endorsed("bad", &int);
+        bad.bytes.truncate(10);
+        let report = verify_cert_chain(&ca, &[bad, endorsed("int", &int)]).unwrap();
+        assert!(matches!(issues(&report)[0], [CertIssue::Parse(_)]));
+        assert_eq!(issues(&report)[1], [CertIssue::UnverifiedIssuer]);
+    }
+
+    #[test]
+    fn test_verify_cwt_chain() {
+        let ca_key = gen_key();
+        let ca = build_cert(
+            CertParams {
+                cn: "CA",
+                ..Default::default()
+            },
+            &ca_key,
+            None,
+        );
+        let int_key = gen_key();
+        let int = build_cert(
+            CertParams {
+                cn: "Int",
+                ..Default::default()
+            },
+            &int_key,
+            Some((&ca, &ca_key)),
+        );
+        let int_id = hex::encode(int.subject_key_id().unwrap().as_slice());
+        let cwt_key = gen_key();
+        let cwt = |bytes| EndorsedCert {
+            format: CertFormat::Cwt,
+            name: "cwt".to_string(),
+            bytes,
+            ignore_critical: false,
+        };
+        let good = build_cwt(&int_id, "0123abcd", &cwt_key, &int_key);
+        let report = verify_cert_chain(&ca, &[endorsed("int", &int), cwt(good.clone())]).unwrap();
+        assert!(report.is_valid(), "{report}");
+        assert_eq!(report.certs[1].subject, "0123abcd");
+
+        // A CWT issued by the previous CWT.
+        let next = build_cwt("0123abcd", "4567ef01", &gen_key(), &cwt_key);
+        let report =
+            verify_cert_chain(&ca, &[endorsed("int", &int), cwt(good), cwt(next)]).unwrap();
+        assert!(report.is_valid(), "{report}");
+
+        let forged = build_cwt(&int_id, "0123abcd", &cwt_key, &ca_key);
+        let report = verify_cert_chain(&ca, &[endorsed("int", &int), cwt(forged)]).unwrap();
+        assert_eq!(issues(&report)[1], [CertIssue::BadSignature]);
+
+        let wrong_issuer = build_cwt("ffff", "0123abcd", &cwt_key, &int_key);
+        let report = verify_cert_chain(&ca, &[endorsed("int", &int), cwt(wrong_issuer)]).unwrap();
+        assert!(matches!(
+            issues(&report)[1],
+            [CertIssue::IssuerMismatch { .. }]
+        ));
+    }
+}
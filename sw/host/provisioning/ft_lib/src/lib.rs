@@ -293,7 +293,7 @@ fn provision_certificates(
     // Extract certificate byte vectors, endorse TBS certs, and ensure they parse with OpenSSL.
     // During the process, both:
     //   1. prepare a UJSON payload of endorsed certs to send back to the device,
-    //   2. collect the certs that were endorsed to verify their endorsement signatures with OpenSSL, and
+    //   2. collect the certs to verify their endorsement signatures, and
     //   3. hash all certs to check the integrity of what gets written back to the device.
     let mut cert_hasher = Sha256::new();
     let mut start: usize = 0;
@@ -365,22 +365,22 @@ fn provision_certificates(
         };
 
         // Collect all DICE certs to validate the chain.
-        // TODO(lowRISC/opentitan:#24281): Add CWT verifier
-        if dice_cert_names.contains(cert.cert_name)
-            && header.obj_type == ObjType::UnendorsedX509Cert
-        {
+        if dice_cert_names.contains(cert.cert_name) {
             dice_cert_chain.push(EndorsedCert {
-                format: CertFormat::X509,
+                format: match header.obj_type {
+                    ObjType::EndorsedCwtCert => CertFormat::Cwt,
+                    _ => CertFormat::X509,
+                },
                 name: cert.cert_name.to_string(),
                 bytes: cert_bytes.clone(),
                 ignore_critical: true,
             });
         }
 
-        // Ensure all certs parse with OpenSSL (even those that where endorsed on device).
+        // Ensure all X.509 certs parse with OpenSSL (even those that where endorsed on device).
+        // CWT certs are parsed when the DICE chain is validated.
         log::info!("{} Cert: {}", cert.cert_name, hex::encode(&cert_bytes));
-        // TODO(lowRISC/opentitan:#24281): Add CWT parser
-        if header.obj_type != ObjType::DevSeed && header.obj_type != ObjType::EndorsedCwtCert {
+        if header.obj_type != ObjType::EndorsedCwtCert {
             let _ = parse_certificate(&cert_bytes)?;
         }
         // Push the cert into the hasher so we can ensure the certs written to the device's flash
@@ -423,12 +423,11 @@ fn provision_certificates(
         )
     }
 
-    // Validate the certificate endorsements with OpenSSL.
-    // TODO(lowRISC/opentitan:#24281): Add CWT verifier
-    log::info!("Validating DICE certificate chain with OpenSSL ...");
+    // Validate the certificate endorsements.
+    log::info!("Validating DICE certificate chain ...");
     validate_cert_chain(dice_ca_cert.to_str().unwrap(), &dice_cert_chain)?;
     log::info!("Success.");
-    log::info!("Validating SKU-specific certificates with OpenSSL ...");
+    log::info!("Validating SKU-specific certificates ...");
     if !sku_specific_certs.is_empty() {
         for sku_specific_cert in sku_specific_certs.iter() {
             validate_cert_chain(ext_ca_cert.to_str().unwrap(), &[sku_specific_cert.clone()])?;
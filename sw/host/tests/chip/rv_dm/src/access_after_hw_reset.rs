@@ -10,7 +10,7 @@ use clap::Parser;
 
 use opentitanlib::app::TransportWrapper;
 use opentitanlib::execute_test;
-use opentitanlib::io::jtag::JtagTap;
+use opentitanlib::io::jtag::{JtagTap, TargetState};
 use opentitanlib::test_utils;
 use opentitanlib::test_utils::init::InitializeTest;
 use opentitanlib::test_utils::mem::MemWriteReq;
@@ -50,10 +50,7 @@ fn test_access_after_hw_reset(
         .jtag_params
         .create(transport)?
         .connect(JtagTap::RiscvTap)?;
-    assert_eq!(
-        jtag.as_raw()?.execute("$_TARGETNAME.0 curstate")?,
-        "running"
-    );
+    assert_eq!(jtag.target_state()?, TargetState::Running);
     jtag.disconnect()?;
 
     MemWriteReq::execute(uart, reset_addr, &[1])?;
@@ -65,10 +62,7 @@ fn test_access_after_hw_reset(
         .jtag_params
         .create(transport)?
         .connect(JtagTap::RiscvTap)?;
-    assert_eq!(
-        jtag.as_raw()?.execute("$_TARGETNAME.0 curstate")?,
-        "running"
-    );
+    assert_eq!(jtag.target_state()?, TargetState::Running);
     jtag.disconnect()?;
 
     UartConsole::wait_for(uart, r"PASS!", opts.timeout)?;
@@ -6,8 +6,9 @@ use anyhow::Result;
 use clap::Parser;
 
 use opentitanlib::app::TransportWrapper;
-use opentitanlib::debug::dmi::{DmiDebugger, OpenOcdDmi};
+use opentitanlib::debug::dmi::{Dmi, DmiDebugger, OpenOcdDmi};
 use opentitanlib::execute_test;
+use opentitanlib::io::jtag::JtagTap;
 use opentitanlib::test_utils::init::InitializeTest;
 
 #[derive(Debug, Parser)]
@@ -16,15 +17,19 @@ struct Opts {
     init: InitializeTest,
 }
 
+#[allow(clippy::unusual_byte_groupings)]
 // Needs to match util/openocd/target
 const RISCV_IDCODE: u32 = 0x10001cdf;
 
-#[allow(clippy::unusual_byte_groupings)]
-fn test_dtm(opts: &Opts, transport: &TransportWrapper) -> Result<()> {
-    transport.pin_strapping("PINMUX_TAP_RISCV")?.apply()?;
-    transport.reset_target(opts.init.bootstrap.options.reset_delay, true)?;
+/// Connect to the DMI of the RISC-V TAP without OpenOCD setting up a debug target.
+fn connect_dmi(opts: &Opts, transport: &TransportWrapper) -> Result<Box<dyn Dmi>> {
+    let jtag = opts.init.jtag_params.create(transport)?;
+    if transport.proxy_ops().is_ok() {
+        // OpenOCD runs on the session server, which declares the TAP on our behalf.
+        return jtag.connect_dmi(JtagTap::RiscvTap);
+    }
 
-    let mut openocd = opts.init.jtag_params.create(transport)?.into_raw()?;
+    let mut openocd = jtag.into_raw()?;
 
     // Configure OpenOCD to expect RISC-V tap and initialize JTAG.
     assert_eq!(
@@ -35,7 +40,14 @@ fn test_dtm(opts: &Opts, transport: &TransportWrapper) -> Result<()> {
     );
     assert_eq!(openocd.execute("init")?, "");
 
-    let mut dmi = DmiDebugger::new(OpenOcdDmi::new(openocd, "riscv.tap")?);
+    Ok(Box::new(OpenOcdDmi::new(openocd, "riscv.tap")?))
+}
+
+fn test_dtm(opts: &Opts, transport: &TransportWrapper) -> Result<()> {
+    transport.pin_strapping("PINMUX_TAP_RISCV")?.apply()?;
+    transport.reset_target(opts.init.bootstrap.options.reset_delay, true)?;
+
+    let mut dmi = DmiDebugger::new(connect_dmi(opts, transport)?);
     let mut hart = dmi.select_hart(0)?;
 
     let hartinfo = hart.hartinfo()?;
@@ -101,31 +101,11 @@ fn test_jtag_tap_sel(opts: &Opts, transport: &TransportWrapper) -> Result<()> {
             .connect(JtagTap::LcTap)?
             .disconnect()?;
     } else {
-        // For DFT test we need to do raw OpenOCD command.
-        let mut openocd = opts.init.jtag_params.create(transport)?.into_raw()?;
-        // Perform JTAG initialisation and capture the result.
-        let init_result = openocd.execute("capture \"jtag init\"")?;
-
-        static ID_REGEX: Lazy<Regex> =
-            Lazy::new(|| Regex::new(r"tap/device found: 0x([0-9A-Fa-f]+) \(").unwrap());
-        let idcode = if init_result.contains("JTAG scan chain interrogation failed") {
-            None
-        } else {
-            Some(u32::from_str_radix(
-                ID_REGEX
-                    .captures(&init_result)
-                    .context("Failed to parse IDCODE from OpenOCD output")?
-                    .get(1)
-                    .unwrap()
-                    .as_str(),
-                16,
-            )?)
-        };
+        let idcode = scan_idcode(opts, transport)?;
 
         // For everything other than test_unlocked* and RMA states, the DFT TAP should not exist.
         let expected_idcode = if needs_reset { opts.dft_idcode } else { None };
         assert_eq!(idcode, expected_idcode);
-        openocd.shutdown()?;
     }
 
     transport.pin_strapping("PINMUX_TAP_DFT")?.remove()?;
@@ -134,13 +114,43 @@ fn test_jtag_tap_sel(opts: &Opts, transport: &TransportWrapper) -> Result<()> {
     }
 
     // Now check that there's no JTAG TAP present.
-    let mut openocd = opts.init.jtag_params.create(transport)?.into_raw()?;
+    assert_eq!(scan_idcode(opts, transport)?, None);
+
+    Ok(())
+}
+
+/// Initialize JTAG without declaring any TAP and return the IDCODE found on the chain, or `None`
+/// if no TAP responds.
+fn scan_idcode(opts: &Opts, transport: &TransportWrapper) -> Result<Option<u32>> {
+    let jtag = opts.init.jtag_params.create(transport)?;
+    if transport.proxy_ops().is_ok() {
+        // OpenOCD runs on the session server, which performs the same scan on our behalf.
+        return Ok(jtag.scan_idcodes()?.first().copied());
+    }
+
+    // For DFT test we need to do raw OpenOCD command.
+    let mut openocd = jtag.into_raw()?;
     // Perform JTAG initialisation and capture the result.
     let init_result = openocd.execute("capture \"jtag init\"")?;
-    assert!(init_result.contains("JTAG scan chain interrogation failed"));
-    openocd.shutdown()?;
 
-    Ok(())
+    static ID_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"tap/device found: 0x([0-9A-Fa-f]+) \(").unwrap());
+    let idcode = if init_result.contains("JTAG scan chain interrogation failed") {
+        None
+    } else {
+        Some(u32::from_str_radix(
+            ID_REGEX
+                .captures(&init_result)
+                .context("Failed to parse IDCODE from OpenOCD output")?
+                .get(1)
+                .unwrap()
+                .as_str(),
+            16,
+        )?)
+    };
+
+    openocd.shutdown()?;
+    Ok(idcode)
 }
 
 fn main() -> Result<()> {
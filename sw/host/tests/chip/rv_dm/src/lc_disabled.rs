@@ -6,6 +6,7 @@ use anyhow::Result;
 use clap::Parser;
 
 use opentitanlib::app::TransportWrapper;
+use opentitanlib::debug::dmi::Dmi;
 use opentitanlib::execute_test;
 use opentitanlib::io::jtag::JtagTap;
 use opentitanlib::test_utils::init::InitializeTest;
@@ -28,11 +29,13 @@ fn access_jtag(opts: &Opts, transport: &TransportWrapper) -> Result<()> {
     transport.pin_strapping("PINMUX_TAP_RISCV")?.apply()?;
     transport.reset_target(opts.init.bootstrap.options.reset_delay, true)?;
 
-    let jtag_result = opts
-        .init
-        .jtag_params
-        .create(transport)?
-        .connect(JtagTap::RiscvTap);
+    let jtag = opts.init.jtag_params.create(transport)?;
+    if transport.proxy_ops().is_ok() {
+        // OpenOCD runs on the session server, go through its DMI forwarding instead.
+        return access_dmi(opts, jtag.connect_dmi(JtagTap::RiscvTap));
+    }
+
+    let jtag_result = jtag.connect(JtagTap::RiscvTap);
 
     if opts.expect_fail {
         assert!(
@@ -61,6 +64,28 @@ fn access_jtag(opts: &Opts, transport: &TransportWrapper) -> Result<()> {
     Ok(())
 }
 
+/// Same as above, over a DMI connection established by the session server.
+fn access_dmi(opts: &Opts, dmi_result: Result<Box<dyn Dmi>>) -> Result<()> {
+    if opts.expect_fail {
+        assert!(
+            dmi_result.is_err(),
+            "JTAG access to RV_DM established, but access is not allowed"
+        );
+        return Ok(());
+    }
+
+    let mut dmi = dmi_result?;
+
+    // Test that we can write sbaddress0 (address 0x39) register over DMI
+    let random_value: u32 = rand::random();
+    log::info!("Writing {random_value:#x} to sbaddress0");
+    dmi.dmi_write(0x39, random_value)?;
+    let readback = dmi.dmi_read(0x39)?;
+    assert_eq!(random_value, readback);
+
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let opts = Opts::parse();
     opts.init.init_logging();
@@ -8,8 +8,9 @@ use anyhow::Result;
 use clap::Parser;
 
 use opentitanlib::app::TransportWrapper;
-use opentitanlib::debug::dmi::{consts, DmiDebugger, OpenOcdDmi};
+use opentitanlib::debug::dmi::{consts, Dmi, DmiDebugger, OpenOcdDmi};
 use opentitanlib::execute_test;
+use opentitanlib::io::jtag::JtagTap;
 use opentitanlib::test_utils::init::InitializeTest;
 use opentitanlib::uart::console::UartConsole;
 
@@ -26,6 +27,28 @@ struct Opts {
 // Needs to match util/openocd/target
 const RISCV_IDCODE: u32 = 0x10001cdf;
 
+/// Connect to the DMI of the RISC-V TAP without OpenOCD setting up a debug target.
+fn connect_dmi(opts: &Opts, transport: &TransportWrapper) -> Result<Box<dyn Dmi>> {
+    let jtag = opts.init.jtag_params.create(transport)?;
+    if transport.proxy_ops().is_ok() {
+        // OpenOCD runs on the session server, which declares the TAP on our behalf.
+        return jtag.connect_dmi(JtagTap::RiscvTap);
+    }
+
+    let mut openocd = jtag.into_raw()?;
+
+    // Configure OpenOCD to expect RISC-V tap and initialize JTAG.
+    assert_eq!(
+        openocd.execute(&format!(
+            "jtag newtap riscv tap -irlen 5 -expected-id {RISCV_IDCODE:#x}"
+        ))?,
+        ""
+    );
+    assert_eq!(openocd.execute("init")?, "");
+
+    Ok(Box::new(OpenOcdDmi::new(openocd, "riscv.tap")?))
+}
+
 fn test_ndm_reset_req(opts: &Opts, transport: &TransportWrapper) -> Result<()> {
     // This test requires RV_DM access so first strap and reset.
     transport.pin_strapping("PINMUX_TAP_RISCV")?.apply()?;
@@ -37,23 +60,7 @@ fn test_ndm_reset_req(opts: &Opts, transport: &TransportWrapper) -> Result<()> {
     let _ = UartConsole::wait_for(&*uart, r"Running [^\r\n]*", opts.timeout)?;
 
     // Connect via JTAG and trigger a NDM reset
-    let mut jtag = opts
-        .init
-        .jtag_params
-        .create(transport)?
-        // .connect(JtagTap::RiscvTap)?;
-        .into_raw()?;
-
-    // Configure OpenOCD to expect RISC-V tap and initialize JTAG.
-    assert_eq!(
-        jtag.execute(&format!(
-            "jtag newtap riscv tap -irlen 5 -expected-id {RISCV_IDCODE:#x}"
-        ))?,
-        ""
-    );
-    assert_eq!(jtag.execute("init")?, "");
-
-    let mut dmi = DmiDebugger::new(OpenOcdDmi::new(jtag, "riscv.tap")?);
+    let mut dmi = DmiDebugger::new(connect_dmi(opts, transport)?);
 
     // Check dmstatus indicates havereset for Ibex (power-on reset) and set ackhavereset to clear it.
     let mut hart = dmi.select_hart(0)?;
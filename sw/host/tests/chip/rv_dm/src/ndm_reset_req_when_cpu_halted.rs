@@ -9,7 +9,7 @@ use clap::Parser;
 
 use opentitanlib::app::TransportWrapper;
 use opentitanlib::execute_test;
-use opentitanlib::io::jtag::{JtagTap, RiscvCsr};
+use opentitanlib::io::jtag::{JtagTap, RiscvCsr, TargetState};
 use opentitanlib::test_utils::init::InitializeTest;
 use opentitanlib::test_utils::poll::poll_until;
 use opentitanlib::uart::console::UartConsole;
@@ -42,7 +42,6 @@ fn test_ndm_reset_req_when_halted(opts: &Opts, transport: &TransportWrapper) ->
     let _ = UartConsole::wait_for(&*uart, "Ready for CPU halt request", opts.timeout)?;
 
     // Connect via JTAG.
-    // We need to obtain raw OpenOCD instance for direct DMI manipulation.
     // Debug module will be activated by OpenOCD.
     let mut jtag = opts
         .init
@@ -51,14 +50,11 @@ fn test_ndm_reset_req_when_halted(opts: &Opts, transport: &TransportWrapper) ->
         .connect(JtagTap::RiscvTap)?;
 
     // Verify the CPU is running before asserting haltreq.
-    assert_eq!(
-        jtag.as_raw()?.execute("$_TARGETNAME.0 curstate")?,
-        "running"
-    );
+    assert_eq!(jtag.target_state()?, TargetState::Running);
 
     // Initiate a CPU halt request and wait CPU to be halted.
     jtag.halt()?;
-    assert_eq!(jtag.as_raw()?.execute("$_TARGETNAME.0 curstate")?, "halted");
+    assert_eq!(jtag.target_state()?, TargetState::Halted);
 
     // Read DCSR and verify the cause field.
     let dcsr = jtag.read_riscv_reg(&RiscvCsr::DCSR.into())?;
@@ -71,7 +67,7 @@ fn test_ndm_reset_req_when_halted(opts: &Opts, transport: &TransportWrapper) ->
     jtag.reset(true)?;
 
     poll_until(opts.timeout, Duration::from_millis(10), || {
-        Ok(jtag.as_raw()?.execute("$_TARGETNAME.0 curstate")? == "running")
+        Ok(jtag.target_state()? == TargetState::Running)
     })?;
 
     // Let the CPU SW run its course (second reset phase after NDM reset).
@@ -13,6 +13,7 @@ use std::time::Duration;
 use opentitanlib::app::TransportWrapper;
 use opentitanlib::chip::boot_svc::{BootSlot, UnlockMode};
 use opentitanlib::rescue::serial::RescueSerial;
+use opentitanlib::rescue::Rescue;
 use opentitanlib::test_utils::init::InitializeTest;
 
 #[derive(Debug, Parser)]
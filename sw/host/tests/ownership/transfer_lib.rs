@@ -16,7 +16,7 @@ use opentitanlib::ownership::{
     OwnerConfigItem, OwnerFlashConfig, OwnerFlashInfoConfig, OwnerFlashRegion, OwnerInfoPage,
     OwnerRescueConfig, OwnershipKeyAlg,
 };
-use opentitanlib::rescue::serial::RescueSerial;
+use opentitanlib::rescue::Rescue;
 
 use std::path::Path;
 
@@ -25,7 +25,7 @@ pub const TEST_OWNER_CONFIG_VERSION: u32 = 1;
 /// Gets the BootLog.
 pub fn get_device_info(
     transport: &TransportWrapper,
-    rescue: &RescueSerial,
+    rescue: &dyn Rescue,
 ) -> Result<(BootLog, DeviceId)> {
     rescue.enter(transport, /*reset=*/ true)?;
     Ok((rescue.get_boot_log()?, rescue.get_device_id()?))
@@ -34,7 +34,7 @@ pub fn get_device_info(
 /// Prepares an UnlockOwnership command, sends it to the chip and gets the response.
 pub fn ownership_unlock(
     transport: &TransportWrapper,
-    rescue: &RescueSerial,
+    rescue: &dyn Rescue,
     mode: UnlockMode,
     nonce: u64,
     din: u64,
@@ -64,7 +64,7 @@ pub fn ownership_unlock(
 /// Prepares an UnlockOwnership command (with UnlockMode::Any), sends it to the chip and gets the response.
 pub fn ownership_unlock_any(
     transport: &TransportWrapper,
-    rescue: &RescueSerial,
+    rescue: &dyn Rescue,
     nonce: u64,
     din: u64,
     unlock_key: &Path,
@@ -83,7 +83,7 @@ pub fn ownership_unlock_any(
 /// Prepares an OwnershipActivate command, sends it to the chip and gets the response.
 pub fn ownership_activate(
     transport: &TransportWrapper,
-    rescue: &RescueSerial,
+    rescue: &dyn Rescue,
     nonce: u64,
     din: u64,
     activate_key: &Path,
@@ -190,7 +190,7 @@ impl OwnerConfigKind {
 #[allow(clippy::too_many_arguments)]
 pub fn create_owner<F>(
     transport: &TransportWrapper,
-    rescue: &RescueSerial,
+    rescue: &dyn Rescue,
     owner_key: &Path,
     activate_key: &Path,
     unlock_key: &Path,
@@ -7,62 +7,48 @@ use std::net::Shutdown;
 
 use anyhow::{anyhow, Context, Result};
 use mio::net::TcpStream;
-use opentitanlib::tpm::Driver;
+use mio::{Events, Interest, Poll, Token};
+use opentitanlib::tpm::{Driver, TcpTpmCommands, TpmEndpointInfo, TCP_PROTOCOL_VERSION};
 
 pub(crate) const CMD_SIZE: usize = std::mem::size_of::<TcpTpmCommands>();
-const SERVER_VERSION: u32 = 1;
-
-#[derive(strum::FromRepr, Debug)]
-#[repr(u32)]
-pub(crate) enum TcpTpmCommands {
-    SignalPowerOn = 1,
-    SignalPowerOff = 2,
-    SignalPPOn = 3,
-    SignalPPOff = 4,
-    SignalHashStart = 5,
-    SignalHashData = 6,
-    SignalHashEnd = 7,
-    SendCommand = 8,
-    SignalCancelOn = 9,
-    SignalCancelOff = 10,
-    SignalNvOn = 11,
-    SignalNvOff = 12,
-    SignalKeyCacheOn = 13,
-    SignalKeyCacheOff = 14,
-    RemoteHandshake = 15,
-    //SetAlternativeResult = 16,    // Not used since 1.38h
-    SessionEnd = 20,
-    Stop = 21,
-    ActGetSignaled = 26,
-    TestFailureMode = 30,
-}
-
-/// Platform hierarchy is enabled, and hardware platform functionality (such
-/// as SignalHashStart/Data/End) is available.
-const PLATFORM_AVAILABLE: u32 = 0x01;
-
-/// The device is TPM Resource Manager (TRM), rather than a raw TPM.
-/// This means context management commands are unavailable, and the handle values
-/// returned to the client are virtualized.
-const _USES_TBS: u32 = 0x02;
-
-/// The TRM is in raw mode (i.e. no actual resourse virtualization is performed).
-const _IN_RAW_MODE: u32 = 0x04;
-
-/// Physical presence signals (SignalPPOn/Off) are supported.
-const _SUPPORTS_PP: u32 = 0x08;
-
-/// Valid only with PlatformAvailable set.
-/// System and TPM power control signals (SignalPowerOn/Off) are not supported.
-const NO_POWER_CTL: u32 = 0x10;
 
-/// Valid only with tpmPlatformAvailable set.
-/// TPM locality cannot be changed.
-const NO_LOCALITY_CTL: u32 = 0x20;
-
-/// Valid only with tpmPlatformAvailable set.
-/// NV control signals (SignalNvOn/Off) are not supported.
-const NO_NV_CTL: u32 = 0x40;
+const CMD_TOKEN: Token = Token(0);
+const PLATFORM_TOKEN: Token = Token(1);
+
+/// Serve the command and platform ports until the client ends the session.
+pub(crate) fn serve(
+    cmd_stream: &mut TcpStream,
+    platform_stream: &mut TcpStream,
+    tpm: &dyn Driver,
+) -> Result<()> {
+    let mut poll = Poll::new()?;
+    let mut events = Events::with_capacity(128);
+    poll.registry()
+        .register(platform_stream, PLATFORM_TOKEN, Interest::READABLE)?;
+
+    poll.registry()
+        .register(cmd_stream, CMD_TOKEN, Interest::READABLE)?;
+
+    loop {
+        poll.poll(&mut events, None)?;
+
+        for event in events.iter() {
+            match event.token() {
+                CMD_TOKEN => {
+                    if serve_command(cmd_stream, tpm)? {
+                        return Ok(());
+                    }
+                }
+                PLATFORM_TOKEN => {
+                    if serve_command(platform_stream, tpm)? {
+                        return Ok(());
+                    }
+                }
+                Token(_) => todo!(),
+            }
+        }
+    }
+}
 
 /// Serve the command port for the TPM, forwarding commands to the bus specified in `opts`.
 pub(crate) fn serve_command(stream: &mut TcpStream, tpm: &dyn Driver) -> Result<bool> {
@@ -86,16 +72,18 @@ pub(crate) fn serve_command(stream: &mut TcpStream, tpm: &dyn Driver) -> Result<
 /// Handle the requested command and send the reply on `stream`. If this it a TPM command, send it
 /// to `tpm`.
 fn handle_cmd(cmd: TcpTpmCommands, stream: &mut TcpStream, tpm: &dyn Driver) -> Result<()> {
-    const CFG: u32 = PLATFORM_AVAILABLE | NO_POWER_CTL | NO_LOCALITY_CTL | NO_NV_CTL;
+    const CFG: TpmEndpointInfo = TpmEndpointInfo::PLATFORM_AVAILABLE
+        .union(TpmEndpointInfo::NO_POWER_CTL)
+        .union(TpmEndpointInfo::NO_LOCALITY_CTL)
+        .union(TpmEndpointInfo::NO_NV_CTL);
     log::info!("CMD {:?}", cmd);
     match cmd {
         TcpTpmCommands::RemoteHandshake => {
             let mut ver = [0u8; 4];
             stream.read_exact(&mut ver)?;
             log::debug!("Client ver {}.", u32::from_be_bytes(ver));
-            stream.write_all(&SERVER_VERSION.to_be_bytes())?;
-            // TODO Make TpmEndpointInfo a bitfield and send
-            stream.write_all(&CFG.to_be_bytes())?;
+            stream.write_all(&TCP_PROTOCOL_VERSION.to_be_bytes())?;
+            stream.write_all(&CFG.bits().to_be_bytes())?;
             stream.write_all(&[0u8; 4])?;
             Ok(())
         }
@@ -138,3 +126,67 @@ fn handle_send(stream: &mut TcpStream, tpm: &dyn Driver) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use opentitanlib::tpm::{Register, TcpDriver, Tpm};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answers TPM2_GetRandom with a fixed pattern, and fails any other command.
+    struct FakeTpm;
+
+    impl Driver for FakeTpm {
+        fn read_register(&self, _register: Register, _data: &mut [u8]) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn write_register(&self, _register: Register, _data: &[u8]) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn execute_command(&self, cmd: &[u8]) -> Result<Vec<u8>> {
+            if cmd.get(6..10) != Some(&[0x00, 0x00, 0x01, 0x7b]) {
+                bail!("unexpected command {:02x?}", cmd);
+            }
+            Ok(vec![
+                0x80, 0x01, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0xde, 0xad,
+                0xbe, 0xef,
+            ])
+        }
+    }
+
+    #[test]
+    fn test_loopback() -> Result<()> {
+        let cmd_listener = TcpListener::bind("127.0.0.1:0")?;
+        let platform_listener = TcpListener::bind("127.0.0.1:0")?;
+        let cmd_addr = cmd_listener.local_addr()?;
+        let platform_addr = platform_listener.local_addr()?;
+        let server = thread::spawn(move || -> Result<()> {
+            let mut cmd_stream = TcpStream::from_std(cmd_listener.accept()?.0);
+            let mut platform_stream = TcpStream::from_std(platform_listener.accept()?.0);
+            serve(&mut cmd_stream, &mut platform_stream, &FakeTpm)
+        });
+
+        let client = TcpDriver::connect(cmd_addr, platform_addr)?;
+        assert_eq!(
+            client.endpoint_info(),
+            TpmEndpointInfo::PLATFORM_AVAILABLE
+                | TpmEndpointInfo::NO_POWER_CTL
+                | TpmEndpointInfo::NO_LOCALITY_CTL
+                | TpmEndpointInfo::NO_NV_CTL
+        );
+        client.power_on()?;
+        let tpm = Tpm::new(&client);
+        assert_eq!(tpm.get_random(4)?, [0xde, 0xad, 0xbe, 0xef]);
+        // A failure to reach the TPM is reported to the client, which can carry on.
+        assert!(tpm.self_test(false).is_err());
+        assert_eq!(tpm.get_random(4)?, [0xde, 0xad, 0xbe, 0xef]);
+
+        // Ending the session stops the server.
+        drop(client);
+        server.join().unwrap()
+    }
+}
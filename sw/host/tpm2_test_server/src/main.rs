@@ -2,10 +2,9 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::interface::serve_command;
+use crate::interface::serve;
 use clap::{Parser, Subcommand};
 use log::LevelFilter;
-use mio::{Events, Interest, Poll, Token};
 use opentitanlib::backend;
 use opentitanlib::io::i2c::I2cParams;
 use opentitanlib::io::spi::SpiParams;
@@ -54,9 +53,6 @@ struct Opts {
     tpm_port: u16,
 }
 
-const CMD_TOKEN: Token = Token(0);
-const PLATFORM_TOKEN: Token = Token(1);
-
 pub fn main() -> anyhow::Result<()> {
     let options = Opts::parse();
     env_logger::Builder::from_default_env()
@@ -72,14 +68,6 @@ pub fn main() -> anyhow::Result<()> {
     let mut cmd_stream = mio::net::TcpStream::from_std(cmd_listener.accept()?.0);
     let mut platform_stream = mio::net::TcpStream::from_std(platform_listener.accept()?.0);
 
-    let mut poll = Poll::new()?;
-    let mut events = Events::with_capacity(128);
-    poll.registry()
-        .register(&mut platform_stream, PLATFORM_TOKEN, Interest::READABLE)?;
-
-    poll.registry()
-        .register(&mut cmd_stream, CMD_TOKEN, Interest::READABLE)?;
-
     let transport = backend::create(&options.backend_opts)?;
     let bus: Box<dyn Driver> = match options.bus {
         TpmBus::Spi { params, gsc_ready } => {
@@ -99,23 +87,5 @@ pub fn main() -> anyhow::Result<()> {
     };
     bus.init()?;
 
-    loop {
-        poll.poll(&mut events, None)?;
-
-        for event in events.iter() {
-            match event.token() {
-                CMD_TOKEN => {
-                    if serve_command(&mut cmd_stream, &*bus)? {
-                        return Ok(());
-                    }
-                }
-                PLATFORM_TOKEN => {
-                    if serve_command(&mut platform_stream, &*bus)? {
-                        return Ok(());
-                    }
-                }
-                Token(_) => todo!(),
-            }
-        }
-    }
+    serve(&mut cmd_stream, &mut platform_stream, &*bus)
 }